@@ -0,0 +1,90 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! Expands `#include "..."` in the shaders under `shaders/` and writes each one out as a
+//! `vulkano_shaders::shader!` invocation for the shader modules to `include!`.  The macro
+//! only takes whole sources, so the shared GLSL library is spliced in here.
+
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+static SHADER_DIR: &str = "shaders";
+
+fn main() {
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let shader_dir = Path::new(SHADER_DIR);
+    println!("cargo:rerun-if-changed={}", SHADER_DIR);
+    for path in glsl_files(shader_dir) {
+        println!("cargo:rerun-if-changed={}", path.display());
+    }
+
+    for entry in fs::read_dir(shader_dir).unwrap() {
+        let path = entry.unwrap().path();
+        let ty = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("vert") => "vertex",
+            Some("frag") => "fragment",
+            Some("comp") => "compute",
+            _ => continue,
+        };
+        let mut included = HashSet::new();
+        let source = expand(&path, shader_dir, &mut included);
+        let name = path.file_name().unwrap().to_str().unwrap();
+        let module = format!(
+            "vulkano_shaders::shader! {{\n    ty: \"{}\",\n    src: r##\"{}\"##\n}}\n",
+            ty, source
+        );
+        fs::write(out_dir.join(format!("{}.rs", name)), module).unwrap();
+    }
+}
+
+/// Every file under `dir`, so edits to any of them rebuild the shaders
+fn glsl_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            files.extend(glsl_files(&path));
+        } else {
+            files.push(path);
+        }
+    }
+    files
+}
+
+/// The source of `path` with its includes spliced in, each file at most once.  Includes are
+/// relative to `root`.  `#line` keeps compiler errors pointing at the including file's lines.
+fn expand(path: &Path, root: &Path, included: &mut HashSet<PathBuf>) -> String {
+    let source = fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Could not read shader {}: {}", path.display(), e));
+    let mut expanded = String::new();
+    for (index, line) in source.lines().enumerate() {
+        let trimmed = line.trim_start();
+        if !trimmed.starts_with("#include") {
+            expanded.push_str(line);
+            expanded.push('\n');
+            continue;
+        }
+        let name = trimmed["#include".len()..].trim().trim_matches('"');
+        let include = root.join(name);
+        if included.insert(include.clone()) {
+            expanded.push_str("#line 1\n");
+            expanded.push_str(&expand(&include, root, included));
+        }
+        expanded.push_str(&format!("#line {}\n", index + 2));
+    }
+    expanded
+}
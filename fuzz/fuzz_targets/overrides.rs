@@ -0,0 +1,38 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! `--set` overrides, key combos and the names and values runtime parameters are set by
+
+#![no_main]
+
+use e_nguyen::config::ENguyenConfig;
+use e_nguyen::input::KeyCombo;
+use e_nguyen::params::{Palette, ParamChange, Scene};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text,
+        Err(_) => return,
+    };
+    let overrides: Vec<String> = text.lines().map(str::to_owned).collect();
+    let _ = ENguyenConfig::default().with_overrides(&overrides);
+    for line in text.lines() {
+        let _ = line.parse::<KeyCombo>();
+        let _ = line.parse::<ParamChange>();
+        let _ = line.parse::<Palette>();
+        let _ = line.parse::<Scene>();
+    }
+});
@@ -0,0 +1,33 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! Requests from other instances, read off the socket a line at a time as the server does
+
+#![no_main]
+
+use e_nguyen::ipc::Remote;
+use libfuzzer_sys::fuzz_target;
+use std::io::{BufRead, BufReader};
+
+fuzz_target!(|data: &[u8]| {
+    for line in BufReader::new(data).lines() {
+        match line {
+            Ok(line) => {
+                let _ = line.parse::<Remote>();
+            },
+            Err(_) => break,
+        }
+    }
+});
@@ -0,0 +1,81 @@
+// Combines both channels' spectra into a column of the history.  Each .comp that includes
+// this defines HISTORY_FORMAT and CHANNEL_FORMAT, the image formats it writes.
+
+#include "lib/complex.glsl"
+
+layout(local_size_x=16, local_size_y=1, local_size_z=1) in;
+layout(set = 0, binding = 0) buffer LeftData {Complex data[];} left_chan;
+layout(set = 0, binding = 1) buffer RightData {Complex data[];} right_chan;
+layout (set = 0, binding = 2, HISTORY_FORMAT) uniform image2D out_img;
+
+// the linear bins each row sums, as a run of taps.  Built on the CPU with the log scale.
+struct Span {
+    uint first;
+    uint count;
+};
+struct Tap {
+    uint bin;
+    float weight;
+};
+layout(set = 0, binding = 3) readonly buffer Spans {Span data[];} spans;
+layout(set = 0, binding = 4) readonly buffer Taps {Tap data[];} taps;
+// each channel's levels on their own, one texel per row, when split
+layout (set = 0, binding = 5, CHANNEL_FORMAT) uniform writeonly image2D left_img;
+layout (set = 0, binding = 6, CHANNEL_FORMAT) uniform writeonly image2D right_img;
+
+// per window.  A uniform rather than push constants, so recorded dispatches can be reused.
+layout(set = 0, binding = 7) uniform Constants {
+    uint lin_bins;
+    float red;
+    float green;
+    float blue;
+    // nonzero keeps the channels apart, left level in red and right in green
+    uint stereo;
+    // the history column written
+    uint column;
+    // nonzero also writes left_img and right_img
+    uint split;
+} fft;
+
+// one channel's summed magnitude, about 0.0 to 1.0 for music
+float level(float sum) {
+    return max(0.08 * (sum - 0.4), 0.0);
+}
+
+void main() {
+    uint widx = gl_WorkGroupID.x;
+    uint lidx = gl_LocalInvocationID.x;
+    uint num_groups = gl_NumWorkGroups.x;
+    uint woven = widx + lidx * num_groups;
+
+    float left_sum = 0.0;
+    float right_sum = 0.0;
+
+    Span span = spans.data[woven];
+    for (uint i = span.first; i < span.first + span.count; i++) {
+        Tap tap = taps.data[i];
+        uint conjugate_index = fft.lin_bins - 1 - tap.bin;
+
+        float mag_l = (mag(left_chan.data[tap.bin]) + mag(left_chan.data[conjugate_index])) * 0.5;
+        float mag_r = (mag(right_chan.data[tap.bin]) + mag(right_chan.data[conjugate_index])) * 0.5;
+
+        left_sum += mag_l * tap.weight;
+        right_sum += mag_r * tap.weight;
+    }
+
+    vec4 out_col;
+    if (fft.stereo != 0) {
+        out_col = vec4(level(left_sum), level(right_sum), 0.0, 1.0);
+    } else {
+        out_col = vec4(fft.red * 0.04 * (pow(left_sum * right_sum, 0.5) - 0.3),
+                       fft.green * 0.06 * (right_sum - 0.8),
+                       fft.blue * 0.08 * (left_sum - 0.4),
+                       1.0);
+    }
+
+    imageStore(out_img, ivec2(fft.column, woven), out_col);
+    if (fft.split != 0) {
+        imageStore(left_img, ivec2(0, woven), vec4(level(left_sum)));
+        imageStore(right_img, ivec2(0, woven), vec4(level(right_sum)));
+    }
+}
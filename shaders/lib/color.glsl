@@ -0,0 +1,35 @@
+// Color conversions and colormaps.  Shaders work in linear light and the swapchain's sRGB
+// format encodes what they write, so colors picked by eye go through `to_linear` first.
+
+// hue, saturation and value from 0.0 to 1.0
+vec3 hsv_to_rgb(vec3 hsv) {
+    vec3 pure = clamp(abs(mod(hsv.x * 6.0 + vec3(0.0, 4.0, 2.0), 6.0) - 3.0) - 1.0, 0.0, 1.0);
+    return hsv.z * mix(vec3(1.0), pure, hsv.y);
+}
+
+// black through red and yellow to white as `t` goes from 0.0 to 1.0
+vec3 heat(float t) {
+    t = clamp(t, 0.0, 1.0);
+    return clamp(vec3(t * 3.0, t * 3.0 - 1.0, t * 3.0 - 2.0), 0.0, 1.0);
+}
+
+// `color` weighted per channel, as `Palette::weights`
+vec3 tint(vec3 color, vec3 weights) {
+    return color * weights;
+}
+
+// an sRGB-encoded color, as picked in an editor, in linear light
+vec3 to_linear(vec3 srgb) {
+    vec3 low = srgb / 12.92;
+    vec3 high = pow((srgb + 0.055) / 1.055, vec3(2.4));
+    return mix(low, high, step(vec3(0.04045), srgb));
+}
+
+// levels above 1.0 brought back into range.  `curve` as `Tonemap::curve`: 0 clips and 1 is
+// Reinhard, reaching white at 4.0.
+vec3 tonemap(vec3 color, uint curve) {
+    if (curve == 1) {
+        color = color * (1.0 + color / 16.0) / (1.0 + color);
+    }
+    return clamp(color, 0.0, 1.0);
+}
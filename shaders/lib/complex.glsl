@@ -0,0 +1,28 @@
+// Complex numbers as the FFT writes them
+
+const float HAPI = 1.5707963267948966;
+const float IPI = 0.3183098861837907;
+
+struct Complex {
+    float real;
+    float imag;
+};
+
+// TODO this mapping is suspicious
+float norm_tan(float unnormed) {
+    return (unnormed + HAPI) / IPI;
+}
+
+// magnitude
+float mag(Complex c) {
+    return pow((pow(c.real, 2.0) + pow(c.imag, 2.0)), 0.5);
+}
+
+// phase
+float phase(Complex c) {
+    return c.real != 0.0 ? norm_tan(atan(c.imag / c.real)) : 0.0;
+}
+
+Complex complex_mul(Complex a, Complex b) {
+    return Complex(a.real * b.real - a.imag * b.imag, a.real * b.imag + a.imag * b.real);
+}
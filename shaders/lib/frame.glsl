@@ -0,0 +1,9 @@
+// What every scene shader can read about the frame being drawn.  Laid out as
+// `rendering::FrameUniforms`.  Members are only ever added at the end.
+layout(set = 0, binding = 0) uniform Frame {
+    vec4 bands;
+    vec2 resolution;
+    float time;
+    float delta;
+    float beat_phase;
+} frame;
@@ -0,0 +1,12 @@
+#version 450
+
+layout(location = 0) out vec4 f_color;
+#include "lib/frame.glsl"
+#include "lib/color.glsl"
+layout (push_constant) uniform PushConstant {
+    vec4 color;
+} solid;
+
+void main() {
+    f_color = vec4(to_linear(solid.color.rgb), solid.color.a);
+}
@@ -0,0 +1,27 @@
+#version 450
+
+layout(location = 0) in vec2 tex_coords;
+layout(location = 0) out vec4 f_color;
+#include "lib/frame.glsl"
+#include "lib/color.glsl"
+layout (push_constant) uniform PushConstant {
+    vec4 color;
+    float travel;
+    float radius;
+    float twist;
+} tunnel;
+
+// a wall of rings and ribs seen down its length.  Each pixel looks at the wall `depth`
+// ahead, further toward the middle of the window.
+void main() {
+    float aspect = frame.resolution.x / max(frame.resolution.y, 1.0);
+    vec2 p = (tex_coords * 2.0 - 1.0) * vec2(aspect, 1.0);
+    float r = max(length(p), 0.001);
+    float depth = tunnel.radius / r;
+    float along = depth + tunnel.travel;
+    float around = atan(p.y, p.x) / 3.14159265 + tunnel.twist * depth;
+    float rings = smoothstep(0.4, 0.5, abs(fract(along) - 0.5));
+    float ribs = smoothstep(0.42, 0.5, abs(fract(around * 8.0) - 0.5));
+    float fog = exp(-0.25 * depth);
+    f_color = vec4(to_linear(tunnel.color.rgb) * max(rings, ribs) * fog, 1.0);
+}
@@ -0,0 +1,34 @@
+#version 450
+
+layout(location = 0) in vec2 tex_coords;
+layout(location = 0) out vec4 f_color;
+#include "lib/frame.glsl"
+#include "lib/color.glsl"
+layout(set = 1, binding = 0) uniform sampler2D tex;
+layout (push_constant) uniform PushConstant {
+    float offset_fac;
+    // nonzero for a stereo texture, drawn with the lowest frequencies meeting in the middle
+    uint mirror;
+    float red;
+    float green;
+    float blue;
+    // share of the history's width drawn across the viewport
+    float span;
+    // `Tonemap::curve` for levels above 1.0
+    uint tonemap;
+} scroll;
+
+void main() {
+    if (scroll.mirror == 0) {
+        vec2 scrolled_coords = vec2(scroll.offset_fac - tex_coords.x * scroll.span, tex_coords.y);
+        vec4 levels = texture(tex, scrolled_coords);
+        f_color = vec4(tonemap(levels.rgb, scroll.tonemap), levels.a);
+        return;
+    }
+    // the right channel fills the half below the middle and the left the half above
+    float from_middle = abs(tex_coords.y - 0.5) * 2.0;
+    vec2 scrolled_coords = vec2(scroll.offset_fac - tex_coords.x * scroll.span, from_middle);
+    vec4 levels = texture(tex, scrolled_coords);
+    float level = tonemap(vec3(tex_coords.y < 0.5 ? levels.g : levels.r), scroll.tonemap).r;
+    f_color = vec4(tint(to_linear(heat(level)), vec3(scroll.red, scroll.green, scroll.blue)), 1.0);
+}
@@ -13,148 +13,944 @@
 
 // Copyright 2019 E-Nguyen Developers.
 
-use crate::config::ENguyenConfig;
+//! The application's main loop.  The main thread owns the one event loop every window is
+//! opened on, opens and closes the settings window and visualizer as messages ask, routes
+//! each window its events and draws their frames in turn.  Capture, analysis, IPC and the
+//! like run on threads of their own and reach it through messages.
+
+use crate::calibrate;
+use crate::compute::{AudioTexTap, WarmPipeline};
+use crate::config;
+use crate::config::{ENguyenConfig, SceneSettings};
+use crate::errors::ENguyenError;
+use crate::ewin;
 use crate::ewin::GpuPicker;
-use crate::mesmerize;
-use crate::settings;
+use crate::ipc;
+use crate::ipc::{Claim, Remote};
+use crate::kiosk::{Health, Kiosk, SceneRotation};
+use crate::mesmerize::MezWindow;
+use crate::pacing::PacingSummary;
+use crate::params::{ParamBus, ParamChange, ParamWatch, Scene};
+use crate::power::PowerMonitor;
+use crate::recording::Recorder;
+use crate::settings::{Onboarding, SettingsResources, SettingsWindow};
 
-use log::{error, info};
+use log::{error, info, warn};
+use std::collections::{BTreeMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc;
-use std::sync::mpsc::SyncSender;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
 use std::thread;
-use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use winit;
+
+/// How often a daemon with no visualizer open discards analysis nobody is drawing
+static DRAIN_INTERVAL: Duration = Duration::from_millis(100);
+/// A subsystem that fails this many times within `RESTART_WINDOW` is left down
+static RESTART_LIMIT: usize = 3;
+static RESTART_WINDOW: Duration = Duration::from_secs(60);
+/// How long the error overlay stays up after a successful restart
+static OVERLAY_LINGER: Duration = Duration::from_secs(5);
+/// How long a demo shows each scene
+static DEMO_SCENE_SECONDS: f32 = 20.0;
 
-#[derive(Debug)]
 enum Message {
     LaunchMez,
     LaunchSettings,
-    ClosedMez,
-    ClosedSettings,
+    Remote(Remote),
+    /// The config file was edited
+    Reload(ENguyenConfig),
+    /// First-run choices are in the shared config and should be saved
+    Onboarded,
+    /// A noise floor was measured and should be applied and saved
+    Calibrated([f32; 4]),
 }
 
 pub enum LaunchRequest {
     Mez,
     Settings,
+    /// Settings window asking for a GPU and audio source first
+    Onboard,
+    /// No window.  Audio and compute stay warm until a visualizer is requested over IPC.
+    Daemon,
 }
 
-pub struct MezLauncher {
-    sender: SyncSender<Message>,
-    pub picker: GpuPicker,
+impl LaunchRequest {
+    /// What a second invocation asks of the instance that's already running
+    fn as_remote(&self) -> Remote {
+        match self {
+            LaunchRequest::Mez => Remote::ShowMez,
+            LaunchRequest::Settings | LaunchRequest::Onboard => Remote::ShowSettings,
+            LaunchRequest::Daemon => Remote::Focus,
+        }
+    }
 }
 
-impl MezLauncher {
-    fn launch(self) {
-        let r = mesmerize::mezmerize(&self);
-        match r {
-            Err(e) => error!("{:?}", e),
-            _ => {}
+/// Requests forwarded into a running window
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WindowCommand {
+    Focus,
+    ToggleFullscreen,
+    Close,
+}
+
+/// State every window reads each frame.  Windows all live on the event loop's thread, so a
+/// change made by one is seen by the others on their next frame.  Parameters that other
+/// threads read travel on `params` instead.
+pub struct SharedState {
+    pub config: ENguyenConfig,
+    pub params: ParamBus,
+    /// Raw captured audio written to a WAV file while on
+    pub recorder: Recorder,
+    /// The visualizer keeps its last frame while analysis carries on
+    pub frozen: bool,
+    /// Capture and analysis are stopped, exports included
+    pub analysis_paused: bool,
+    revision: u64,
+    /// Scene tuning as last read, so only what changed while running is remembered
+    loaded_scenes: BTreeMap<String, SceneSettings>,
+    /// Parameter changes not yet recorded as the scene's tuning and the session
+    tuning: ParamWatch,
+    scene: Scene,
+}
+
+impl SharedState {
+    pub fn new(config: ENguyenConfig) -> SharedState {
+        let params = ParamBus::new(config.parameters());
+        let tuning = params.watch();
+        let scene = tuning.current().scene;
+        let recorder = Recorder::default();
+        let loaded_scenes = config.scenes.clone();
+        SharedState {
+            config,
+            params,
+            recorder,
+            frozen: false,
+            analysis_paused: false,
+            revision: 0,
+            loaded_scenes,
+            tuning,
+            scene,
         }
-        self.sender.send(Message::ClosedMez).unwrap();
     }
 
+    /// Record parameter changes as the current scene's tuning and the session to resume.
+    /// Switching scenes brings back the new scene's tuning.
+    pub fn sync_scene(&mut self) {
+        if let Some(params) = self.tuning.changed() {
+            if params.scene != self.scene {
+                self.scene = params.scene;
+                let config = &self.config;
+                self.params.update(|params| config.apply_scene(params.scene, params));
+            }
+            let params = self.params.get();
+            self.update(|config| config.record_session(&params));
+        }
+    }
+
+    /// Forget the current scene's tuning and go back to the global settings
+    pub fn reset_scene(&mut self) {
+        let scene = self.params.get().scene;
+        self.update(|config| {
+            config.scenes.remove(&scene.to_string());
+        });
+        let config = &self.config;
+        self.params.update(|params| config.apply_scene(scene, params));
+    }
+
+    /// Change the config and mark it so windows re-apply it
+    pub fn update<F: FnOnce(&mut ENguyenConfig)>(&mut self, change: F) {
+        change(&mut self.config);
+        self.revision += 1;
+    }
+
+    /// Increments on every update
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+}
+
+/// Watches worker threads and restarts the ones that die, unless they keep dying
+struct Supervisor {
+    restarts: VecDeque<Instant>,
+}
+
+impl Supervisor {
+    fn new() -> Supervisor {
+        Supervisor { restarts: VecDeque::new() }
+    }
+
+    /// Records a restart at `now` if few enough have happened recently
+    fn allow_restart(&mut self, now: Instant) -> bool {
+        while let Some(first) = self.restarts.front() {
+            if now.duration_since(*first) > RESTART_WINDOW {
+                self.restarts.pop_front();
+            } else {
+                break;
+            }
+        }
+        if self.restarts.len() >= RESTART_LIMIT {
+            false
+        } else {
+            self.restarts.push_back(now);
+            true
+        }
+    }
+
+    /// Restarts `tap` if its thread died.  Returns what to tell the user and whether the
+    /// analysis is running again.
+    fn check(&mut self, tap: &mut AudioTexTap) -> Option<(String, bool)> {
+        let failure = tap.failure()?;
+        error!("Audio analysis stopped: {}", failure);
+        if !self.allow_restart(Instant::now()) {
+            error!(
+                "Audio analysis failed {} times in {:?}.  Giving up.",
+                RESTART_LIMIT, RESTART_WINDOW
+            );
+            return Some((format!("Audio analysis stopped: {}", failure), false));
+        }
+        match tap.restart() {
+            Ok(_) => {
+                warn!("Audio analysis restarted");
+                Some((format!("Audio analysis restarted: {}", failure), true))
+            },
+            Err(e) => {
+                error!("Audio analysis could not restart: {}", e);
+                Some((format!("Audio analysis stopped: {}", failure), false))
+            },
+        }
+    }
+}
+
+pub struct MezLauncher {
+    sender: Sender<Message>,
+    pub picker: GpuPicker,
+    pub params: ParamBus,
+    pub recorder: Recorder,
+    /// The config as it was at launch.  Live changes arrive through `SharedState`.
+    pub config: Arc<ENguyenConfig>,
+}
+
+impl MezLauncher {
     pub fn launch_settings(&self) {
         self.sender.send(Message::LaunchSettings).unwrap();
     }
+
+    /// Close every window and exit, even from kiosk mode
+    pub fn quit(&self) {
+        self.sender.send(Message::Remote(Remote::Quit)).unwrap();
+    }
 }
 
 pub struct SettingsLauncher {
-    sender: SyncSender<Message>,
+    sender: Sender<Message>,
     pub picker: GpuPicker,
 }
 
 impl SettingsLauncher {
-    fn launch(self) {
-        settings::settings_ui(&self).unwrap();
-        info!("Finished setting");
-        self.sender.send(Message::ClosedSettings).unwrap();
-    }
-
     pub fn launch_mez(&self) {
         self.sender.send(Message::LaunchMez).unwrap();
     }
+
+    /// Save the first-run choices and open the visualizer
+    pub fn finish_onboarding(&self) {
+        self.sender.send(Message::Onboarded).unwrap();
+    }
+
+    /// Measures the noise floor of `config`'s source in the background, then applies it
+    pub fn calibrate(&self, config: ENguyenConfig) {
+        let (sender, picker) = (self.sender.clone(), self.picker.clone());
+        thread::spawn(move || match calibrate::measure(&picker, &config, calibrate::LISTEN) {
+            Ok(floor) => {
+                let _ = sender.send(Message::Calibrated(floor));
+            },
+            Err(e) => warn!("Calibration failed: {}", e),
+        });
+    }
 }
 
-pub struct App {
-    settings_handle: Option<JoinHandle<()>>,
-    mez_handle: Option<JoinHandle<()>>,
+/// Owns every window and the one event loop that drives them
+pub struct App<'r> {
+    mez: Option<MezWindow>,
+    settings: Option<SettingsWindow<'r>>,
+    settings_resources: &'r SettingsResources<'r>,
+    shared: SharedState,
+    picker: GpuPicker,
+    sender: Sender<Message>,
+    supervisor: Supervisor,
+    /// Relaunches the visualizer in kiosk mode
+    window_supervisor: Supervisor,
+    /// Analysis and visualizer restarts, for the kiosk's health log
+    restarts: u64,
+    kiosk: Option<Kiosk>,
+    /// Turns through every scene in demo mode
+    demo: Option<SceneRotation>,
+    power: PowerMonitor,
+    warm: Option<WarmPipeline>,
+    daemon: bool,
+    quitting: bool,
+    /// Where window geometry is saved
+    config_path: Option<PathBuf>,
+    /// Reapplied whenever the config is reloaded
+    profile: Option<String>,
+    /// `--set` overrides, reapplied over the profile on every reload
+    overrides: Vec<String>,
 }
 
-impl App {
-    pub fn new() -> App {
-        App { settings_handle: None, mez_handle: None }
+impl<'r> App<'r> {
+    fn new(
+        config: ENguyenConfig,
+        picker: GpuPicker,
+        settings_resources: &'r SettingsResources<'r>,
+        sender: Sender<Message>,
+        config_path: Option<PathBuf>,
+        profile: Option<String>,
+        overrides: Vec<String>,
+    ) -> App<'r> {
+        let kiosk = if config.kiosk.enabled { Some(Kiosk::new(&config.kiosk)) } else { None };
+        let demo = if config.launch.demo {
+            Some(SceneRotation::new(&[], DEMO_SCENE_SECONDS))
+        } else {
+            None
+        };
+        App {
+            mez: None,
+            settings: None,
+            settings_resources,
+            shared: SharedState::new(config),
+            picker,
+            sender,
+            supervisor: Supervisor::new(),
+            window_supervisor: Supervisor::new(),
+            restarts: 0,
+            kiosk,
+            demo,
+            power: PowerMonitor::new(),
+            warm: None,
+            daemon: false,
+            quitting: false,
+            config_path,
+            profile,
+            overrides,
+        }
+    }
+
+    /// A daemon or kiosk outlives its windows until asked to quit
+    fn should_exit(&self) -> bool {
+        let lasting = self.daemon || self.kiosk.is_some();
+        !self.mez_alive() && !self.settings_alive() && (!lasting || self.quitting)
     }
 
     fn settings_alive(&self) -> bool {
-        match self.settings_handle {
-            Some(_) => true,
-            None => false,
-        }
+        self.settings.is_some()
     }
 
     fn mez_alive(&self) -> bool {
-        match self.mez_handle {
-            Some(_) => true,
-            None => false,
-        }
+        self.mez.is_some()
     }
 
-    fn launch_settings(&mut self, tx: &SyncSender<Message>, picker: GpuPicker) {
+    fn launch_settings(&mut self, events_loop: &winit::EventsLoop) {
+        self.open_settings(events_loop, None);
+    }
+
+    fn open_settings(&mut self, events_loop: &winit::EventsLoop, onboarding: Option<Onboarding>) {
         if !self.settings_alive() {
-            let settings = SettingsLauncher { sender: tx.clone(), picker };
-            self.settings_handle = Some(thread::spawn(move || {
-                settings.launch();
-            }));
+            let launcher =
+                SettingsLauncher { sender: self.sender.clone(), picker: self.picker.clone() };
+            let resources = self.settings_resources;
+            match SettingsWindow::new(launcher, events_loop, resources, onboarding) {
+                Ok(window) => self.settings = Some(window),
+                Err(e) => error!("{:?}", e),
+            }
         }
     }
 
-    fn launch_mez(&mut self, tx: &SyncSender<Message>, picker: GpuPicker) {
+    fn launch_mez(&mut self, events_loop: &winit::EventsLoop) {
         if !self.mez_alive() {
-            let mez = MezLauncher { sender: tx.clone(), picker };
-            self.mez_handle = Some(thread::spawn(move || {
-                mez.launch();
-            }));
+            let launcher = MezLauncher {
+                sender: self.sender.clone(),
+                picker: self.picker.clone(),
+                params: self.shared.params.clone(),
+                recorder: self.shared.recorder.clone(),
+                config: Arc::new(self.shared.config.clone()),
+            };
+            let warm = self.warm.take();
+            if let Some(warm) = &warm {
+                warm.tap.park(false);
+            }
+            match MezWindow::new(launcher, events_loop, warm) {
+                Ok(window) => self.mez = Some(window),
+                Err(e) => error!("{:?}", e),
+            }
         }
     }
 
-    pub fn launch(request: LaunchRequest, _config: ENguyenConfig, picker: GpuPicker) {
-        let (tx, rx) = mpsc::sync_channel(5);
-        let mut app = App::new();
-        match request {
-            LaunchRequest::Settings => {
-                app.launch_settings(&tx, picker.clone());
+    fn command_mez(&mut self, command: WindowCommand) {
+        if let Some(mez) = &mut self.mez {
+            mez.command(command);
+        }
+    }
+
+    fn command_settings(&mut self, command: WindowCommand) {
+        if let Some(settings) = &mut self.settings {
+            settings.command(command);
+        }
+    }
+
+    fn handle_message(&mut self, message: Message, events_loop: &winit::EventsLoop) {
+        match message {
+            Message::LaunchMez => self.launch_mez(events_loop),
+            Message::LaunchSettings => self.launch_settings(events_loop),
+            Message::Remote(remote) => self.handle_remote(remote, events_loop),
+            Message::Reload(config) => self.reload(config),
+            Message::Onboarded => {
+                self.save_onboarding();
+                self.launch_mez(events_loop);
+            },
+            Message::Calibrated(floor) => self.save_noise_floor(floor),
+        }
+    }
+
+    fn save_noise_floor(&mut self, floor: [f32; 4]) {
+        info!("Noise floor measured: {:?}", floor);
+        self.shared.update(|config| config.analysis.noise_floor = floor);
+        self.shared.params.apply(ParamChange::NoiseFloor(floor));
+        if let Some(path) = &self.config_path {
+            let saved = config::edit_file(path, |on_disk| on_disk.analysis.noise_floor = floor);
+            if let Err(e) = saved {
+                warn!("The noise floor will be forgotten on exit: {}", e);
             }
-            LaunchRequest::Mez => {
-                app.launch_mez(&tx, picker.clone());
+        }
+    }
+
+    fn save_onboarding(&mut self) {
+        let config = &self.shared.config;
+        self.picker.prefer(config.physical_device_uuid.clone());
+        if let Some(path) = &self.config_path {
+            let saved = config::edit_file(path, |on_disk| {
+                on_disk.physical_device_uuid = config.physical_device_uuid.clone();
+                on_disk.physical_device_name = config.physical_device_name.clone();
+                on_disk.audio.source = config.audio.source.clone();
+            });
+            match saved {
+                Ok(_) => info!("Wrote {}", path.display()),
+                Err(e) => warn!("Setup will be asked for again next time: {}", e),
+            }
+        }
+    }
+
+    /// Apply a freshly read config file, with the current profile and `--set` over it
+    fn reload(&mut self, config: ENguyenConfig) {
+        let config = match &self.profile {
+            Some(name) => match config.clone().with_profile(name) {
+                Ok(profiled) => profiled,
+                Err(e) => {
+                    warn!("Profile {} no longer applies: {}", name, e);
+                    config
+                },
+            },
+            None => config,
+        };
+        let config = match config.clone().with_overrides(&self.overrides) {
+            Ok(overridden) => overridden,
+            Err(e) => {
+                warn!("--set no longer applies: {}", e);
+                config
+            },
+        };
+        let launch = self.shared.config.launch.clone();
+        self.shared.loaded_scenes = config.scenes.clone();
+        self.shared.update(move |current| *current = ENguyenConfig { launch, ..config });
+        let config = &self.shared.config;
+        self.shared.params.update(|params| config.apply_live(params));
+    }
+
+    fn switch_profile(&mut self, profile: Option<String>) {
+        let base = match &self.config_path {
+            Some(path) if path.is_file() => ENguyenConfig::parse(path),
+            _ => Ok(ENguyenConfig::default()),
+        };
+        let base = match base {
+            Ok(base) => base,
+            Err(e) => {
+                error!("Can't switch profiles while the config doesn't parse: {}", e);
+                return;
+            },
+        };
+        if let Some(name) = &profile {
+            if let Err(e) = base.clone().with_profile(name) {
+                error!("Not switching to profile {}: {}", name, e);
+                return;
             }
         }
+        self.profile = profile;
+        self.reload(base);
+    }
 
-        for recieved in rx.iter() {
-            match recieved {
-                Message::LaunchMez => {
-                    app.launch_mez(&tx, picker.clone());
+    fn handle_remote(&mut self, remote: Remote, events_loop: &winit::EventsLoop) {
+        info!("Remote request: {}", remote);
+        match remote {
+            Remote::Focus => {
+                self.command_mez(WindowCommand::Focus);
+                self.command_settings(WindowCommand::Focus);
+            },
+            Remote::ToggleFullscreen => {
+                self.launch_mez(events_loop);
+                self.command_mez(WindowCommand::ToggleFullscreen);
+            },
+            Remote::ShowMez => {
+                if self.mez_alive() {
+                    self.command_mez(WindowCommand::Focus);
+                } else {
+                    self.launch_mez(events_loop);
                 }
-                Message::LaunchSettings => {
-                    app.launch_settings(&tx, picker.clone());
+            },
+            Remote::ShowSettings => {
+                if self.settings_alive() {
+                    self.command_settings(WindowCommand::Focus);
+                } else {
+                    self.launch_settings(events_loop);
                 }
-                Message::ClosedSettings => {
-                    if let Some(handle) = app.settings_handle {
-                        handle.join().expect("Could not join Settings thread");
-                        app.settings_handle = None
-                    }
-                    if !app.mez_alive() {
-                        break;
-                    }
+            },
+            Remote::Quit => {
+                self.quitting = true;
+                self.command_mez(WindowCommand::Close);
+                self.command_settings(WindowCommand::Close);
+            },
+            Remote::Set(change) => {
+                self.shared.params.apply(change);
+            },
+            Remote::ResetScene => {
+                self.shared.reset_scene();
+            },
+            Remote::Profile(profile) => {
+                self.switch_profile(profile);
+            },
+            Remote::Record => {
+                self.shared.recorder.toggle();
+            },
+            Remote::Freeze => {
+                self.shared.frozen = !self.shared.frozen;
+            },
+            Remote::Pause => {
+                self.shared.analysis_paused = !self.shared.analysis_paused;
+                // the visualizer pauses its own on the next frame
+                if let Some(warm) = &self.warm {
+                    warm.tap.pause(self.shared.analysis_paused);
                 }
-                Message::ClosedMez => {
-                    if let Some(handle) = app.mez_handle {
-                        handle.join().expect("Could not join Mezmerizer thread");
-                        app.mez_handle = None
-                    }
-                    if !app.settings_alive() {
-                        break;
+            },
+        }
+    }
+
+    /// Window events go to the window they belong to.  Device events go to every window.
+    fn route(&mut self, event: &winit::Event) {
+        let target = match event {
+            winit::Event::WindowEvent { window_id, .. } => Some(*window_id),
+            _ => None,
+        };
+        if let Some(mez) = &mut self.mez {
+            if target.map_or(true, |id| id == mez.id()) {
+                mez.handle(event, &mut self.shared);
+            }
+        }
+        if let Some(settings) = &mut self.settings {
+            if target.map_or(true, |id| id == settings.id()) {
+                settings.handle(event, &mut self.shared);
+            }
+        }
+    }
+
+    /// A window that can't draw anymore is closed.  A daemon recreates the device for the
+    /// next visualizer instead of keeping the failed one warm.
+    fn render(&mut self) {
+        if let Some(mez) = &mut self.mez {
+            if let Err(e) = mez.render(&self.shared) {
+                error!("The visualizer can't draw and will close: {}", e);
+                self.close_mez(false);
+            }
+        }
+        if let Some(settings) = &mut self.settings {
+            if let Err(e) = settings.render(&self.shared) {
+                error!("The settings window can't draw and will close: {}", e);
+                settings.done = true;
+            }
+        }
+    }
+
+    /// Restart dead worker threads and tell the user about it.  A kiosk whose analysis can't
+    /// restart closes the visualizer, so that a new one starts it over.
+    fn supervise(&mut self) {
+        let checked = match &mut self.mez {
+            Some(mez) => self.supervisor.check(mez.audio_tap()),
+            None => None,
+        };
+        if let Some((message, recovered)) = checked {
+            if recovered {
+                self.restarts += 1;
+            }
+            if !recovered && self.kiosk.is_some() {
+                self.close_mez(false);
+            } else if let Some(mez) = &mut self.mez {
+                let linger = if recovered { Some(OVERLAY_LINGER) } else { None };
+                mez.show_error(message, linger);
+            }
+        }
+        if let Some(warm) = &mut self.warm {
+            self.supervisor.check(&mut warm.tap);
+        }
+    }
+
+    /// Keep the kiosk's visualizer open and its scenes turning, and log how it's doing.
+    /// Relaunches are rate limited like restarts, and tried again once old ones age out.
+    fn tend_kiosk(&mut self, events_loop: &winit::EventsLoop) {
+        if self.kiosk.is_none() || self.quitting {
+            return;
+        }
+        let now = Instant::now();
+        if !self.mez_alive() && self.window_supervisor.allow_restart(now) {
+            warn!("Kiosk is launching the visualizer");
+            self.launch_mez(events_loop);
+            self.restarts += 1;
+        }
+        if let Some(kiosk) = &mut self.kiosk {
+            let scene = self.shared.params.get().scene;
+            if let Some(next) = kiosk.rotate(scene, now) {
+                info!("Kiosk scene: {}", next);
+                self.shared.params.apply(ParamChange::Scene(next));
+            }
+            if kiosk.health_due(now) {
+                let health = Health {
+                    visualizer: self.mez.is_some(),
+                    scene,
+                    restarts: self.restarts,
+                    pacing: self.mez.as_ref().map(MezWindow::pacing),
+                };
+                kiosk.log_health(&health, now);
+            }
+        }
+    }
+
+    /// Show the demo's next scene when it's due
+    fn tend_demo(&mut self) {
+        if let Some(demo) = &mut self.demo {
+            if let Some(next) = demo.rotate(self.shared.params.get().scene, Instant::now()) {
+                info!("Demo scene: {}", next);
+                self.shared.params.apply(ParamChange::Scene(next));
+            }
+        }
+    }
+
+    /// Follow the power supply into and out of low power mode
+    fn check_power(&mut self) {
+        let config = &self.shared.config.power;
+        if let Some(low_power) = self.power.poll(config, Instant::now()) {
+            self.picker.prefer_integrated(low_power && config.prefer_integrated);
+            self.shared.params.apply(ParamChange::LowPower(low_power));
+        }
+    }
+
+    /// Close windows that finished this pass
+    fn reap(&mut self) {
+        if self.settings.as_ref().map_or(false, |s| s.done) {
+            if let Some(settings) = self.settings.take() {
+                settings.close();
+            }
+            info!("Finished setting");
+        }
+        if self.mez.as_ref().map_or(false, |m| m.done) {
+            self.close_mez(self.daemon && !self.quitting);
+        }
+    }
+
+    /// Saves where the visualizer was and closes it.  Its analysis keeps running if `warm`.
+    fn close_mez(&mut self, warm: bool) {
+        if let Some(mut mez) = self.mez.take() {
+            let geometry = mez.geometry();
+            self.shared.update(|config| config.video.window = geometry);
+            self.remember();
+            let returned = mez.close();
+            if warm {
+                // nothing draws it until the next visualizer opens
+                returned.tap.park(true);
+                self.warm = Some(returned);
+            } else {
+                returned.shutdown();
+            }
+        }
+    }
+
+    /// Save window geometry, scene tuning and the session for the next launch
+    fn remember(&self) {
+        if let Some(path) = &self.config_path {
+            let shared = &self.shared;
+            if let Err(e) = config::remember(path, &shared.config, &shared.loaded_scenes) {
+                warn!("Window position, scene and tuning won't be restored: {}", e);
+            }
+        }
+    }
+
+    /// Windows first, then analysis and capture, each waiting on its device so nothing is
+    /// destroyed while the GPU or sound server still uses it
+    fn shutdown(&mut self) {
+        if let Some(settings) = self.settings.take() {
+            settings.close();
+        }
+        if let Some(mut mez) = self.mez.take() {
+            let geometry = mez.geometry();
+            self.shared.update(|config| config.video.window = geometry);
+            mez.close().shutdown();
+        }
+        if let Some(warm) = self.warm.take() {
+            warm.shutdown();
+        }
+        self.shared.sync_scene();
+        self.remember();
+        info!("Shut down");
+    }
+
+    /// Runs until every window is closed.  Edits to `config_path` apply while running.
+    pub fn launch(
+        request: LaunchRequest,
+        config: ENguyenConfig,
+        config_path: Option<PathBuf>,
+        profile: Option<String>,
+        overrides: Vec<String>,
+        picker: GpuPicker,
+    ) {
+        let (tx, rx) = mpsc::channel();
+
+        // only one instance may capture audio and own the GPU.  Held until launch returns.
+        let _ipc_server = match ipc::claim() {
+            Ok(Claim::Secondary(client)) => {
+                // the running instance also switches to what this launch chose
+                let mut remotes = vec![request.as_remote()];
+                let launch = &config.launch;
+                remotes.extend(launch.scene.map(|scene| Remote::Set(ParamChange::Scene(scene))));
+                remotes.extend(launch.palette.map(|p| Remote::Set(ParamChange::Palette(p))));
+                for remote in remotes {
+                    match client.send(&remote) {
+                        Ok(_) => info!("E-Nguyen is already running.  Forwarded: {}", remote),
+                        Err(e) => {
+                            error!("E-Nguyen is already running but didn't answer: {}", e);
+                            break;
+                        },
                     }
                 }
-            };
+                return;
+            },
+            Ok(Claim::Primary(server)) => {
+                let remote_tx = tx.clone();
+                let served = server.serve(move |remote| {
+                    let _ = remote_tx.send(Message::Remote(remote));
+                });
+                if let Err(e) = served {
+                    warn!("Other instances won't be able to reach this one: {}", e);
+                }
+                Some(server)
+            },
+            Err(e) => {
+                warn!("Single-instance check failed, continuing anyway: {}", e);
+                None
+            },
+        };
+
+        // the first SIGINT or SIGTERM asks for a clean shutdown.  A second one doesn't wait.
+        let signal_tx = tx.clone();
+        let signalled = AtomicBool::new(false);
+        let handled = ctrlc::set_handler(move || {
+            if signalled.swap(true, Ordering::SeqCst) {
+                std::process::exit(130);
+            }
+            info!("Signal received.  Shutting down.");
+            let _ = signal_tx.send(Message::Remote(Remote::Quit));
+        });
+        if let Err(e) = handled {
+            warn!("Signals will not shut down cleanly: {}", e);
         }
+
+        let _config_watcher = config_path.clone().and_then(|path| {
+            let reload_tx = tx.clone();
+            let watched = config::watch(path, move |config| {
+                let _ = reload_tx.send(Message::Reload(config));
+            });
+            match watched {
+                Ok(watcher) => Some(watcher),
+                Err(e) => {
+                    warn!("Config edits won't apply until restart: {}", e);
+                    None
+                },
+            }
+        });
+
+        let settings_resources = match SettingsResources::new() {
+            Ok(resources) => resources,
+            Err(e) => {
+                error!("{:?}", e);
+                return;
+            },
+        };
+
+        let mut events_loop = match ewin::events_loop(&picker.instance) {
+            Ok((events_loop, _backend)) => events_loop,
+            Err(e) => {
+                error!("{:?}", e);
+                return;
+            },
+        };
+        let mut app =
+            App::new(config, picker, &settings_resources, tx, config_path, profile, overrides);
+        // before any device is picked
+        app.check_power();
+        match request {
+            LaunchRequest::Settings => {
+                app.launch_settings(&events_loop);
+            },
+            LaunchRequest::Onboard => {
+                let onboarding = Onboarding::detect(&app.picker);
+                app.open_settings(&events_loop, Some(onboarding));
+            },
+            LaunchRequest::Mez => {
+                app.launch_mez(&events_loop);
+            },
+            LaunchRequest::Daemon => {
+                let config = &app.shared.config;
+                let (params, recorder) = (&app.shared.params, &app.shared.recorder);
+                match WarmPipeline::start(&app.picker, 1024, params, recorder, config) {
+                    Ok(warm) => {
+                        info!("Daemon running.  Waiting for a visualizer request.");
+                        warm.tap.park(true);
+                        app.warm = Some(warm);
+                        app.daemon = true;
+                    },
+                    Err(e) => {
+                        error!("Could not start the analysis pipeline: {}", e);
+                        return;
+                    },
+                }
+            },
+        }
+
+        while !app.should_exit() {
+            if !app.mez_alive() && !app.settings_alive() {
+                // nothing to draw, so park on the channel instead of spinning
+                match rx.recv_timeout(DRAIN_INTERVAL) {
+                    Ok(message) => app.handle_message(message, &events_loop),
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        if let Some(warm) = &app.warm {
+                            warm.drain();
+                        }
+                        app.shared.sync_scene();
+                        app.supervise();
+                        app.tend_kiosk(&events_loop);
+                        app.check_power();
+                    },
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                }
+                continue;
+            }
+
+            for message in rx.try_iter() {
+                app.handle_message(message, &events_loop);
+            }
+
+            let mut events = Vec::new();
+            events_loop.poll_events(|ev| events.push(ev));
+            for event in &events {
+                app.route(event);
+            }
+
+            let frame_start = Instant::now();
+            app.shared.sync_scene();
+            app.supervise();
+            app.tend_kiosk(&events_loop);
+            app.tend_demo();
+            app.check_power();
+            app.render();
+            app.reap();
+
+            if let Some(interval) = app.shared.params.get().frame_interval() {
+                let spent = frame_start.elapsed();
+                if spent < interval {
+                    thread::sleep(interval - spent);
+                }
+            }
+        }
+        app.shutdown();
+    }
+}
+
+/// Opens the visualizer with default settings for `duration` and reports its frame pacing
+pub fn bench_render(picker: GpuPicker, duration: Duration) -> Result<PacingSummary, ENguyenError> {
+    let (tx, _rx) = mpsc::channel();
+    let (mut events_loop, _backend) = ewin::events_loop(&picker.instance)?;
+    let mut shared = SharedState::new(ENguyenConfig::default());
+    let config = Arc::new(shared.config.clone());
+    let (params, recorder) = (shared.params.clone(), shared.recorder.clone());
+    let launcher = MezLauncher { sender: tx, picker, params, recorder, config };
+    let mut mez = MezWindow::new(launcher, &events_loop, None)?;
+    let start = Instant::now();
+    let mut rendered = Ok(());
+    while start.elapsed() < duration && !mez.done && rendered.is_ok() {
+        let mut events = Vec::new();
+        events_loop.poll_events(|ev| events.push(ev));
+        for event in &events {
+            mez.handle(event, &mut shared);
+        }
+        rendered = mez.render(&shared);
+    }
+    let pacing = mez.pacing();
+    mez.close().shutdown();
+    rendered?;
+    Ok(pacing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restarts_are_rate_limited() {
+        let mut supervisor = Supervisor::new();
+        let start = Instant::now();
+        for _ in 0..RESTART_LIMIT {
+            assert!(supervisor.allow_restart(start));
+        }
+        assert!(!supervisor.allow_restart(start + Duration::from_secs(1)));
+        // old failures age out
+        assert!(supervisor.allow_restart(start + RESTART_WINDOW + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn scene_tuning_is_recorded_and_reset() {
+        let mut shared = SharedState::new(ENguyenConfig::default());
+        shared.params.apply(ParamChange::Gain(4.0));
+        shared.sync_scene();
+        assert_eq!(shared.config.scenes["scroll"].gain, Some(4.0));
+
+        shared.reset_scene();
+        shared.sync_scene();
+        assert!(shared.config.scenes.is_empty());
+        assert_eq!(shared.params.get().gain, 1.0);
+    }
+
+    #[test]
+    fn session_is_resumed() {
+        let mut shared = SharedState::new(ENguyenConfig::default());
+        shared.params.apply(ParamChange::FpsCap(30));
+        shared.params.apply(ParamChange::Gain(2.0));
+        shared.sync_scene();
+        let resumed = SharedState::new(shared.config.clone());
+        assert_eq!(resumed.params.get(), shared.params.get());
+    }
+
+    #[test]
+    fn shared_state_revisions() {
+        let mut shared = SharedState::new(ENguyenConfig::default());
+        let before = shared.revision();
+        shared.update(|config| config.start_in_fullscreen = true);
+        assert!(shared.config.start_in_fullscreen);
+        assert_eq!(shared.revision(), before + 1);
     }
 }
@@ -64,25 +64,28 @@
 /// Signed 16-bit littel-endian 2 channel, 44100/s, so 176.4kbps raw PCM
 /// ```
 ///
-use crate::errors::ENguyenError;
-use crate::ring::{RingBytes, RingReader};
+use crate::errors::{ENguyenError, Retry};
+use crate::ring::{FrameReader, OverflowPolicy, RingFrames, Sample};
 
+use lazy_static::lazy_static;
 use libpulse_binding as pulse;
 use log::{debug, error, info, warn};
 use pulse::callbacks::ListResult;
-use pulse::context::introspect::SourceInfo;
+use pulse::context::introspect::{ServerInfo, SinkInfo, SourceInfo};
+use pulse::context::subscribe::{subscription_masks, Facility, Operation as Change};
 use pulse::context::Context;
+use pulse::def::BufferAttr;
 use pulse::error::PAErr;
 #[allow(unused_imports)]
 use pulse::mainloop::api::Mainloop as MainloopTrait;
 use pulse::mainloop::threaded::Mainloop;
-use pulse::proplist::Proplist;
+use pulse::proplist::{properties, Proplist};
 use pulse::sample::{Format, Spec};
 use pulse::stream::flags;
-use pulse::stream::{PeekResult, Stream};
+use pulse::stream::{PeekResult, SeekMode, Stream};
 use std::borrow::Cow;
-use std::cell::RefCell;
-use std::error::Error;
+use std::cell::{Cell, RefCell};
+use std::fmt;
 use std::ops::Deref;
 use std::rc::Rc;
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -91,13 +94,68 @@ use std::thread;
 use std::thread::JoinHandle;
 use std::time;
 
+/// The capture thread.  Joining it tells why capture stopped, if it wasn't asked to.
+pub type CaptureHandle = JoinHandle<Result<(), ENguyenError>>;
+
+/// How many peeks or discards in a row can fail before capture gives up
+static CAPTURE_RETRIES: u32 = 20;
+/// How often suspended capture checks whether it's wanted again
+static SUSPENDED_POLL: time::Duration = time::Duration::from_millis(20);
+/// What sound settings such as pavucontrol call this program unless an embedder renames it
+static DEFAULT_CLIENT_NAME: &str = "E-Nguyen";
+/// Icon theme name shown beside the client and its stream
+static ICON_NAME: &str = env!("CARGO_PKG_NAME");
+/// What sound settings call the capture stream
+static STREAM_NAME: &str = "Visualizer input";
+/// What sound settings call the loop test's playback
+static TONE_NAME: &str = "Preview tone";
+/// Pitch of the preview tone, Hz
+static TONE_HZ: f32 = 1000.0;
+/// Peak of the preview tone, -20dBFS, quiet in headphones and under any limiter
+static TONE_LEVEL: f32 = 0.1;
+static TONE_RATE: u32 = 48000;
+static TONE_LENGTH: time::Duration = time::Duration::from_millis(600);
+/// Fade at each end of the tone, so it starts and stops without a click
+static TONE_FADE: time::Duration = time::Duration::from_millis(20);
+/// Listening continues this long after the tone, for the trip through the server
+static TONE_MARGIN: time::Duration = time::Duration::from_millis(500);
+/// Frames the tone is listened for at a time
+static TONE_WINDOW: usize = 2048;
+/// Heard at this share of the level played or more, the loop test passes
+pub static TONE_HEARD: f32 = 0.1;
+
+lazy_static! {
+    static ref CLIENT_NAME: Mutex<String> = Mutex::new(DEFAULT_CLIENT_NAME.to_owned());
+}
+
+/// Show the sound server `name` for this program instead of E-Nguyen.  Applies to
+/// connections made afterwards, so call it before starting capture.
+pub fn set_client_name(name: &str) {
+    *CLIENT_NAME.lock().unwrap() = name.to_owned();
+}
+
 /// Implement AudioStream and adapt the input / output in compute to support additional
 /// sound servers.
 pub trait AudioStream {
-    fn connect(&mut self) -> Result<RingState, Box<dyn Error>>;
-    fn heat(&mut self) -> Result<(RingReader, SimpleSource), Box<dyn Error>>;
-    fn chill(&mut self) -> Result<(RingState, JoinHandle<()>), Box<dyn Error>>;
+    fn connect(&mut self) -> Result<RingState, ENguyenError>;
+    fn heat(&mut self) -> Result<(FrameReader<i16>, SimpleSource), ENguyenError>;
+    fn chill(&mut self) -> Result<(RingState, CaptureHandle), ENguyenError>;
     fn state(&self) -> RingState;
+    /// Pause capture while nothing uses it, or resume it.  Streams that can't pause keep
+    /// writing and the ring drops what isn't read.
+    fn suspend(&mut self, _suspended: bool) {}
+    /// The output whose sound is captured, if any, and how far its speakers lag the capture
+    fn output_latency(&self) -> Option<OutputLatency> {
+        None
+    }
+}
+
+/// An output, by sink name, and how long it takes to play what it's given as the sound
+/// server reports it.  Bluetooth sinks lag their monitors by a few hundred milliseconds.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutputLatency {
+    pub sink: String,
+    pub latency: time::Duration,
 }
 
 #[derive(PartialEq, Copy, Clone, Debug)]
@@ -144,6 +202,24 @@ impl SimpleSource {
     fn sample_bytes(&self) -> u32 {
         self.channels as u32 * self.sample_format.size() as u32
     }
+
+    /// Bytes the server should deliver at a time to keep within `latency_ms`, in whole frames
+    fn fragment_bytes(&self, latency_ms: u32) -> u32 {
+        let frame = u64::from(self.sample_bytes());
+        let frames = (self.byte_rate() * u64::from(latency_ms) / 1000 / frame).max(1);
+        (frames * frame).min(u64::from(u32::MAX - 1)) as u32
+    }
+
+    /// 16 bit stereo called `name`, for streams that aren't from the sound server
+    pub fn stereo(name: &str, rate: u32) -> SimpleSource {
+        let name = Box::new(name.to_owned());
+        SimpleSource { name, index: 0, rate, sample_format: Format::S16le, channels: 2 }
+    }
+
+    /// Interleaved channel count.  Frames in the ring are this many samples wide.
+    pub fn channels(&self) -> usize {
+        self.channels as usize
+    }
 }
 
 impl Default for SimpleSource {
@@ -158,15 +234,16 @@ impl Default for SimpleSource {
     }
 }
 
-/// Test audio source
-struct Square4kHz {
+/// Synthetic square wave source for tests and benchmarks that need no sound server
+pub struct Square4kHz {
     volume: f32,
-    hot_handle: Option<JoinHandle<()>>,
+    hot_handle: Option<CaptureHandle>,
     frequency: u32,
     state: Mutex<RingState>,
     killed: Arc<AtomicBool>,
     source_info: SimpleSource,
     inverse_second_fraction: u32, // 100 -> 100ths of second between thread sleeps
+    overflow: OverflowPolicy,
 }
 
 impl Default for Square4kHz {
@@ -179,15 +256,24 @@ impl Default for Square4kHz {
             killed: Arc::new(AtomicBool::from(false)),
             source_info: SimpleSource::default(),
             inverse_second_fraction: 100, // emit 100ths of a second
+            overflow: OverflowPolicy::default(),
         }
     }
 }
 
+impl Square4kHz {
+    /// What the wave does when the reader falls behind
+    pub fn with_overflow(mut self, overflow: OverflowPolicy) -> Square4kHz {
+        self.overflow = overflow;
+        self
+    }
+}
+
 impl AudioStream for Square4kHz {
-    fn connect(&mut self) -> Result<RingState, Box<dyn Error>> {
+    fn connect(&mut self) -> Result<RingState, ENguyenError> {
         let mut state = self.state.lock().unwrap();
         if *state != RingState::BORN {
-            Err(Box::new(ENguyenError::from("Ring already connected.  Get your own")))
+            Err(ENguyenError::audio("Ring already connected.  Get your own"))
         } else {
             // connect to server, don't start sending data yet
             *state = RingState::CONNECTED;
@@ -195,12 +281,13 @@ impl AudioStream for Square4kHz {
         }
     }
 
-    fn heat(&mut self) -> Result<(RingReader, SimpleSource), Box<dyn Error>> {
+    fn heat(&mut self) -> Result<(FrameReader<i16>, SimpleSource), ENguyenError> {
         let mut state = self.state.lock().unwrap();
         if *state != RingState::CONNECTED {
-            Err(Box::new(ENguyenError::from("Can't heat a ring that isn't connected")))
+            Err(ENguyenError::audio("Can't heat a ring that isn't connected"))
         } else {
-            let (tx, rx) = RingBytes::new(16384);
+            let channels = self.source_info.channels();
+            let (tx, rx) = RingFrames::<i16>::with_policy(4096, channels, self.overflow);
 
             let killed: Weak<AtomicBool> = Arc::downgrade(&self.killed);
             let buffer_count = self.source_info.rate;
@@ -238,23 +325,14 @@ impl AudioStream for Square4kHz {
                                 }
                             };
                             let amplitude = if is_high { high_amplitude } else { low_amplitude };
-                            let sample = unsafe {
-                                // TODO use byteorder
-                                // TODO check alignment
-                                std::mem::transmute::<[i16; 2], [u8; 4]>([amplitude, amplitude])
-                            };
-                            let data: Vec<u8> = (0..remaining)
-                                .into_iter()
-                                .flat_map(|_i| sample.iter().cloned())
-                                .collect();
-
-                            // append bytes to available space and accumulate them
-                            // until you have enough to send.
-                            let space = tx.reserve(data.len());
-                            tx.write(&data);
-
-                            cycle_count += space / 4;
-                            step_count += space / 4;
+                            let data: Vec<i16> = vec![amplitude; remaining * tx.channels()];
+
+                            // the ring's overflow policy decides what happens if the
+                            // reader has fallen behind
+                            tx.write_frames(&data);
+
+                            cycle_count += remaining;
+                            step_count += remaining;
                             if cycle_count >= samples_per_cycle {
                                 is_high = !is_high;
                                 cycle_count = 0;
@@ -270,16 +348,17 @@ impl AudioStream for Square4kHz {
                         1000 / inverse_second_fraction as u64,
                     ));
                 }
+                Ok(())
             }));
             *state = RingState::HOT;
             Ok((rx, self.source_info.clone()))
         }
     }
 
-    fn chill(&mut self) -> Result<(RingState, JoinHandle<()>), Box<dyn Error>> {
+    fn chill(&mut self) -> Result<(RingState, CaptureHandle), ENguyenError> {
         let mut state = self.state.lock().unwrap();
         if *state != RingState::HOT {
-            Err(Box::new(ENguyenError::from("Can't chill a ring that isn't hot")))
+            Err(ENguyenError::audio("Can't chill a ring that isn't hot"))
         } else {
             self.killed.store(true, Ordering::Relaxed);
             *state = RingState::DEAD;
@@ -297,35 +376,100 @@ impl AudioStream for Square4kHz {
 
 /// Pulseaudio implementation
 pub struct PaStream {
-    hot_handle: Option<JoinHandle<()>>,
+    hot_handle: Option<CaptureHandle>,
     state: Mutex<RingState>,
     killed: Arc<AtomicBool>,
     source_info: SimpleSource,
     source: ServerStream,
+    /// Move to the default sink's monitor whenever the default sink changes
+    follow_default: bool,
+    /// Capture latency target.  None leaves it to the sound server.
+    latency_ms: Option<u32>,
+    /// The capture thread corks the stream while raised
+    suspended: Arc<AtomicBool>,
+    overflow: OverflowPolicy,
 }
 
-impl Default for PaStream {
-    fn default() -> Self {
-        let ac = connect_to_server().unwrap();
-        let server_streams = server_streams(&ac);
-        let (monitor, mon_info) = first_monitor(server_streams).unwrap();
-        debug!("Using monitor: {:?}", monitor.name);
+impl PaStream {
+    /// Capture from the first monitor the sound server offers
+    pub fn monitor() -> Result<PaStream, ENguyenError> {
+        PaStream::select(&SourceChoice::default())
+    }
+
+    /// Capture from the configured source, or the default sink's monitor if following it.  If
+    /// neither is there, the first monitor is used instead.
+    pub fn select(choice: &SourceChoice) -> Result<PaStream, ENguyenError> {
+        let ac = connect_to_server().map_err(ENguyenError::audio)?;
+        let mut server_streams = server_streams(&ac);
+        let default_monitor = if choice.follow_default { default_monitor(&ac) } else { None };
+        ac.mainloop.borrow_mut().stop();
+        let offered: Vec<ServerStream> = server_streams.iter().map(|(s, _)| s.clone()).collect();
+        let chosen = match default_monitor {
+            Some(monitor) => offered.iter().position(|s| s.name == monitor),
+            None => choose(&offered, choice),
+        };
+        let (source, source_info) = match chosen {
+            Some(chosen) => server_streams.swap_remove(chosen),
+            None => {
+                if !choice.is_default() {
+                    warn!("Audio source {} is gone.  Using the first monitor instead.", choice);
+                }
+                first_monitor(server_streams)
+                    .ok_or_else(|| ENguyenError::audio("The sound server has no monitor source"))?
+            },
+        };
+        debug!("Using source: {:?}", source.name);
+        Ok(PaStream {
+            hot_handle: None,
+            state: Mutex::new(RingState::BORN),
+            killed: Arc::new(AtomicBool::from(false)),
+            source_info,
+            source,
+            follow_default: choice.follow_default,
+            latency_ms: None,
+            suspended: Arc::new(AtomicBool::new(false)),
+            overflow: OverflowPolicy::default(),
+        })
+    }
+
+    /// Ask the sound server to deliver audio within `latency_ms`
+    pub fn with_latency(mut self, latency_ms: Option<u32>) -> PaStream {
+        self.latency_ms = latency_ms;
+        self
+    }
+
+    /// What capture does when the reader falls behind
+    pub fn with_overflow(mut self, overflow: OverflowPolicy) -> PaStream {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Capture from the source named `name` instead of the first monitor
+    pub fn with_source(name: &str) -> Result<PaStream, ENguyenError> {
+        let ac = connect_to_server().map_err(ENguyenError::audio)?;
+        let found = server_streams(&ac).into_iter().find(|(stream, _info)| stream.name == name);
         ac.mainloop.borrow_mut().stop();
-        PaStream {
+        let (source, source_info) =
+            found.ok_or_else(|| ENguyenError::audio(format!("No audio source named {}", name)))?;
+        Ok(PaStream {
             hot_handle: None,
             state: Mutex::new(RingState::BORN),
             killed: Arc::new(AtomicBool::from(false)),
-            source_info: mon_info,
-            source: monitor,
-        }
+            source_info,
+            source,
+            follow_default: false,
+            latency_ms: None,
+            suspended: Arc::new(AtomicBool::new(false)),
+            overflow: OverflowPolicy::default(),
+        })
     }
 }
 
 impl AudioStream for PaStream {
-    fn connect(&mut self) -> Result<RingState, Box<dyn Error>> {
+    fn connect(&mut self) -> Result<RingState, ENguyenError> {
         let mut state = self.state.lock().unwrap();
         if *state != RingState::BORN {
-            Err(Box::new(ENguyenError::from("Ring already connected.  Get your own")))
+            Err(ENguyenError::audio("Ring already connected.  Get your own"))
         } else {
             // assert_eq!(connect_stream(&self.pa_context, &mut self.pa_stream, &self.source)?, true);
             *state = RingState::CONNECTED;
@@ -333,28 +477,90 @@ impl AudioStream for PaStream {
         }
     }
 
-    fn heat(&mut self) -> Result<(RingReader, SimpleSource), Box<dyn Error>> {
+    fn heat(&mut self) -> Result<(FrameReader<i16>, SimpleSource), ENguyenError> {
         let mut state = self.state.lock().unwrap();
         if *state != RingState::CONNECTED {
-            Err(Box::new(ENguyenError::from("Can't heat a ring that isn't connected")))
+            Err(ENguyenError::audio("Can't heat a ring that isn't connected"))
         } else {
             let weak_killed: Weak<AtomicBool> = Arc::downgrade(&self.killed);
+            if self.source_info.sample_format.size() != i16::BYTES {
+                return Err(ENguyenError::audio("Only 16-bit sources are supported"));
+            }
             let min_count: usize = 128; // at least 512B at a time
-            let (tx, rx) = RingBytes::new(32768);
+            let channels = self.source_info.channels();
+            let (mut tx, rx) = RingFrames::<i16>::with_policy(8192, channels, self.overflow);
 
-            let monitor = self.source.clone();
-            self.hot_handle = Some(thread::spawn(move || {
-                let pa_context = connect_to_server().unwrap();
-                let mut stream = create_stream(&pa_context, &monitor).unwrap();
-                assert!(connect_stream(&pa_context, &mut stream, &monitor).unwrap());;
+            let mut monitor = self.source.clone();
+            let follow_default = self.follow_default;
+            let attr = self.latency_ms.map(|ms| buffer_attr(&self.source_info, ms));
+            let suspended = self.suspended.clone();
+            self.hot_handle = Some(thread::spawn(move || -> Result<(), ENguyenError> {
+                let pa_context = connect_to_server().map_err(ENguyenError::audio)?;
+                let mut stream =
+                    create_stream(&pa_context, &monitor).map_err(ENguyenError::audio)?;
+                let attr = attr.as_ref();
+                connect_stream(&pa_context, &mut stream, &monitor, attr)
+                    .map_err(ENguyenError::audio)?;
                 let mut pa_stream = stream.lock().unwrap();
                 pa_context.mainloop.borrow_mut().lock();
-                pa_stream.uncork(None); // TODO wait on unlock
+                let uncorked = complete(&pa_context, &mut pa_stream, StreamOp::Uncork);
                 pa_context.mainloop.borrow_mut().unlock();
+                uncorked.map_err(ENguyenError::audio)?;
+                let defaults_changed =
+                    if follow_default { Some(watch_defaults(&pa_context)) } else { None };
 
+                // a failed peek or discard skips this read.  Only a run of them stops capture.
+                let mut retry = Retry::new(CAPTURE_RETRIES);
+                let mut outcome = Ok(());
+                let mut corked = false;
                 loop {
                     let killed_up = weak_killed.upgrade();
                     if killed_up.is_some() && !killed_up.unwrap().load(Ordering::Relaxed) {
+                        let changed = defaults_changed.as_ref();
+                        if changed.map_or(false, |changed| changed.swap(false, Ordering::Relaxed)) {
+                            let moved_to = default_monitor(&pa_context)
+                                .filter(|default| *default != monitor.name);
+                            if let Some(default) = moved_to {
+                                info!("The default output changed.  Capturing {}", default);
+                                // the ring keeps the first source's sample format and channels,
+                                // and the server converts the new one's to them
+                                monitor.name = default;
+                                drop(pa_stream);
+                                let moved = move_stream(&pa_context, &mut stream, &monitor, attr);
+                                pa_stream = stream.lock().unwrap();
+                                if let Err(e) = moved {
+                                    outcome = Err(ENguyenError::audio(e));
+                                    break;
+                                }
+                                // the new stream starts uncorked
+                                corked = false;
+                            }
+                        }
+
+                        let suspend = suspended.load(Ordering::Relaxed);
+                        if suspend != corked {
+                            pa_context.mainloop.borrow_mut().lock();
+                            let done = if suspend {
+                                // what was captured before pausing is stale once resumed
+                                complete(&pa_context, &mut pa_stream, StreamOp::Cork).and_then(
+                                    |_| complete(&pa_context, &mut pa_stream, StreamOp::Flush),
+                                )
+                            } else {
+                                complete(&pa_context, &mut pa_stream, StreamOp::Uncork)
+                            };
+                            pa_context.mainloop.borrow_mut().unlock();
+                            if let Err(e) = done {
+                                outcome = Err(ENguyenError::audio(e));
+                                break;
+                            }
+                            corked = suspend;
+                            debug!("Capture {}", if corked { "suspended" } else { "resumed" });
+                        }
+                        if corked {
+                            thread::sleep(SUSPENDED_POLL);
+                            continue;
+                        }
+
                         pa_context.mainloop.borrow_mut().lock();
                         let avail = pa_stream.readable_size();
                         pa_context.mainloop.borrow_mut().unlock();
@@ -365,59 +571,60 @@ impl AudioStream for PaStream {
                             }
                         }
 
-                        let mut written = 0;
                         pa_context.mainloop.borrow_mut().lock();
-                        let peek = pa_stream.peek().expect("Could not peek PA streag");
-                        match peek {
-                            PeekResult::Empty => {
-                                pa_context.mainloop.borrow_mut().unlock();
-                                thread::sleep(time::Duration::from_micros(200));
-                                continue;
-                            }
-                            PeekResult::Hole(size) => {
+                        let read = match pa_stream.peek() {
+                            Ok(PeekResult::Empty) => Ok(false),
+                            Ok(PeekResult::Hole(size)) => {
                                 debug!("Skipping PA stream hole sized: {:?}", size);
-                                pa_stream.discard().unwrap();
-                                pa_context.mainloop.borrow_mut().unlock();
-                            }
-                            PeekResult::Data(data) => {
-                                let read = data.len();
-                                let mut sentinel: i32 = 100;
-                                while sentinel > 0 {
-                                    let wavail = tx.reserve(data.len());
-                                    if wavail > data.len() {
-                                        tx.write(data);
-                                        written = data.len();
-                                    }
-                                    sentinel -= 1;
-                                    if written >= read {
-                                        break;
-                                    }
-                                }
+                                pa_stream.discard().map(|_| true)
+                            },
+                            Ok(PeekResult::Data(data)) => {
+                                // a stalled reader is handled by the ring's overflow policy
+                                tx.write_bytes(data);
                                 // done with the data
-                                pa_stream.discard().expect("Could not discard PA stream");
-                                pa_context.mainloop.borrow_mut().unlock();
-                            }
+                                pa_stream.discard().map(|_| true)
+                            },
+                            Err(e) => Err(e),
+                        };
+                        pa_context.mainloop.borrow_mut().unlock();
+                        match read {
+                            Ok(read_data) => {
+                                retry.succeeded();
+                                if !read_data {
+                                    thread::sleep(time::Duration::from_micros(200));
+                                }
+                            },
+                            Err(e) => {
+                                let message = format!("PulseAudio stream failed: {:?}", e);
+                                if retry.failed() {
+                                    outcome = Err(ENguyenError::audio(message));
+                                    break;
+                                }
+                                warn!("{}.  Retrying.", message);
+                                thread::sleep(time::Duration::from_micros(200));
+                            },
                         }
                     } else {
-                        let disconn = disconnect_stream(&pa_context, &stream);
-                        match disconn {
-                            Ok(_) => {}
-                            Err(error) => warn!("Disconnect failed: {:?}", error),
-                        }
-                        pa_context.mainloop.borrow_mut().stop();
                         break;
                     }
                 }
+                // disconnect_stream takes the stream lock itself
+                drop(pa_stream);
+                if let Err(error) = disconnect_stream(&pa_context, &stream) {
+                    warn!("Disconnect failed: {:?}", error);
+                }
+                pa_context.mainloop.borrow_mut().stop();
+                outcome
             }));
             *state = RingState::HOT;
             Ok((rx, self.source_info.clone()))
         }
     }
 
-    fn chill(&mut self) -> Result<(RingState, JoinHandle<()>), Box<dyn Error>> {
+    fn chill(&mut self) -> Result<(RingState, CaptureHandle), ENguyenError> {
         let mut state = self.state.lock().unwrap();
         if *state != RingState::HOT {
-            Err(Box::new(ENguyenError::from("Can't chill a ring that isn't hot")))
+            Err(ENguyenError::audio("Can't chill a ring that isn't hot"))
         } else {
             self.killed.store(true, Ordering::Relaxed);
             *state = RingState::DEAD;
@@ -430,6 +637,58 @@ impl AudioStream for PaStream {
     fn state(&self) -> RingState {
         *self.state.lock().unwrap()
     }
+
+    fn suspend(&mut self, suspended: bool) {
+        self.suspended.store(suspended, Ordering::Relaxed);
+    }
+
+    /// Asks the server afresh, since the latency settles once the sink plays and following
+    /// the default can move capture to another sink's monitor
+    fn output_latency(&self) -> Option<OutputLatency> {
+        let ac = connect_to_server().ok()?;
+        let monitor = if self.follow_default { default_monitor(&ac) } else { None };
+        let latency = sink_latency(&ac, monitor.as_ref().unwrap_or(&self.source.name));
+        ac.mainloop.borrow_mut().stop();
+        latency
+    }
+}
+
+/// Which source to capture, from `audio.source` or else `audio_input_index`.  The default
+/// is the first monitor.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SourceChoice {
+    pub name: Option<String>,
+    /// The sound server's index, as printed by `list-devices`
+    pub index: Option<u32>,
+    /// Capture the default sink's monitor, moving along when the default sink changes.
+    /// Takes precedence over `name` and `index`.
+    pub follow_default: bool,
+}
+
+impl SourceChoice {
+    pub fn is_default(&self) -> bool {
+        self.name.is_none() && self.index.is_none() && !self.follow_default
+    }
+}
+
+impl fmt::Display for SourceChoice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.name, self.index) {
+            _ if self.follow_default => write!(f, "default output's monitor"),
+            (Some(name), _) => write!(f, "{}", name),
+            (None, Some(index)) => write!(f, "#{}", index),
+            (None, None) => write!(f, "first monitor"),
+        }
+    }
+}
+
+/// Position of the chosen source in `offered`.  A name is matched before an index.
+fn choose(offered: &[ServerStream], choice: &SourceChoice) -> Option<usize> {
+    match (&choice.name, choice.index) {
+        (Some(name), _) => offered.iter().position(|s| &s.name == name),
+        (None, Some(index)) => offered.iter().position(|s| s.index == index),
+        (None, None) => None,
+    }
 }
 
 /// TODO ServerStream and SimpleSource can likely be merged
@@ -441,6 +700,23 @@ pub struct ServerStream {
     spec: Spec,
 }
 
+impl ServerStream {
+    /// What `audio.source` in the config refers to
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl fmt::Display for ServerStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "#{} {} [{:?} {}ch {}Hz] {}",
+            self.index, self.name, self.spec.format, self.spec.channels, self.spec.rate, self.desc
+        )
+    }
+}
+
 enum ReadyState {
     Stream(pulse::stream::State),
     Context(pulse::context::State),
@@ -451,18 +727,27 @@ struct AudioContext {
     mainloop: Rc<RefCell<Mainloop>>,
 }
 
-fn connect_to_server() -> Result<AudioContext, String> {
-    let app_name: &str = env!("CARGO_PKG_NAME");
+/// Properties that tell sound settings who is listening and why
+fn client_proplist(entries: &[(&str, &str)]) -> Result<Proplist, String> {
+    let mut proplist = Proplist::new().ok_or("Failed to create proplist")?;
+    for (key, value) in entries {
+        proplist.sets(key, value).map_err(|_| format!("Failed to set {}", key))?;
+    }
+    Ok(proplist)
+}
 
-    let mut proplist = Proplist::new().unwrap();
-    proplist.sets(pulse::proplist::properties::APPLICATION_NAME, &app_name).unwrap();
-    // TODO icons supported for apps like Pavucontrol
-    // https://docs.rs/libpulse-binding/2.5.0/libpulse_binding/proplist/properties/constant.APPLICATION_ICON_NAME.html
+fn connect_to_server() -> Result<AudioContext, String> {
+    let app_name = CLIENT_NAME.lock().unwrap().clone();
+    let proplist = client_proplist(&[
+        (properties::APPLICATION_NAME, &app_name),
+        (properties::APPLICATION_ICON_NAME, ICON_NAME),
+        (properties::APPLICATION_VERSION, env!("CARGO_PKG_VERSION")),
+    ])?;
 
-    let mainloop = Rc::new(RefCell::new(Mainloop::new().expect("Failed to create mainloop")));
+    let mainloop = Rc::new(RefCell::new(Mainloop::new().ok_or("Failed to create mainloop")?));
     let context = Rc::new(RefCell::new(
         Context::new_with_proplist(mainloop.borrow().deref(), &app_name, &proplist)
-            .expect("Failed to create new context"),
+            .ok_or("Failed to create new context")?,
     ));
 
     let ac = AudioContext { context, mainloop };
@@ -486,9 +771,13 @@ fn connect_to_server() -> Result<AudioContext, String> {
     ac.context
         .borrow_mut()
         .connect(None, pulse::context::flags::NOFLAGS, None)
-        .expect("Failed to connect context");
+        .map_err(|e| format!("Failed to connect context: {:?}", e))?;
     ac.mainloop.borrow_mut().lock();
-    ac.mainloop.borrow_mut().start().expect("Failed to start mainloop");
+    let started = ac.mainloop.borrow_mut().start();
+    if let Err(e) = started {
+        ac.mainloop.borrow_mut().unlock();
+        return Err(format!("Failed to start mainloop: {:?}", e));
+    }
     let state_closure = || ReadyState::Context(ac.context.borrow().get_state());
     ready_wait(&state_closure, &ac)?;
     ac.mainloop.borrow_mut().unlock();
@@ -551,6 +840,234 @@ fn server_streams(ac: &AudioContext) -> Vec<(ServerStream, SimpleSource)> {
     unwrapped
 }
 
+/// Every source the sound server offers, for showing the user what can be configured
+pub fn list_sources() -> Result<Vec<ServerStream>, ENguyenError> {
+    let ac = connect_to_server().map_err(ENguyenError::audio)?;
+    let sources = server_streams(&ac).into_iter().map(|(stream, _info)| stream).collect();
+    ac.mainloop.borrow_mut().stop();
+    Ok(sources)
+}
+
+/// Loudest sample heard from `stream` while listening, from 0.0 for silence to 1.0 for full
+/// scale.  Used to tell the user which sources are playing something.
+pub fn peak_level<A: AudioStream>(
+    stream: &mut A,
+    listen: time::Duration,
+) -> Result<f32, ENguyenError> {
+    stream.connect()?;
+    let (rx, _source) = stream.heat()?;
+    let deadline = time::Instant::now() + listen;
+    let mut loudest: f32 = 0.0;
+    while time::Instant::now() < deadline {
+        if let Some(samples) = rx.read_frames_at_least(256, time::Duration::from_millis(50)) {
+            let peak = samples.iter().map(|s| (*s as f32).abs()).fold(0.0, f32::max);
+            loudest = loudest.max(peak / i16::max_value() as f32);
+        }
+    }
+    let (_state, handle) = stream.chill()?;
+    handle.join().map_err(|_| ENguyenError::audio("Audio capture thread panicked"))??;
+    Ok(loudest.min(1.0))
+}
+
+/// Plays a short quiet tone to the default output while capturing `stream` and returns how
+/// loud it was heard, as a share of how loud it was played.  About 1.0 when `stream` is the
+/// output's monitor at full volume, and under `TONE_HEARD` when the loop is broken.
+pub fn loop_test<A: AudioStream>(stream: &mut A) -> Result<f32, ENguyenError> {
+    stream.connect()?;
+    let (rx, source) = stream.heat()?;
+    let player = thread::spawn(|| play(&preview_tone(TONE_RATE), TONE_RATE));
+    let (channels, rate) = (source.channels().max(1), source.rate as f32);
+    let full_scale = channels as f32 * f32::from(i16::max_value());
+    let deadline = time::Instant::now() + TONE_LENGTH + TONE_MARGIN;
+    // both channels play the same tone, so their average keeps its level
+    let mut heard: Vec<f32> = Vec::new();
+    let mut loudest: f32 = 0.0;
+    while time::Instant::now() < deadline {
+        if let Some(samples) = rx.read_frames_at_least(256, time::Duration::from_millis(50)) {
+            let frames = samples.chunks_exact(channels);
+            heard.extend(frames.map(|frame| frame.iter().map(|s| f32::from(*s)).sum::<f32>()));
+            while heard.len() >= TONE_WINDOW {
+                let window: Vec<f32> =
+                    heard[..TONE_WINDOW].iter().map(|s| s / full_scale).collect();
+                loudest = loudest.max(tone_amplitude(&window, rate, TONE_HZ));
+                heard.drain(..TONE_WINDOW / 2);
+            }
+        }
+    }
+    let (_state, handle) = stream.chill()?;
+    handle.join().map_err(|_| ENguyenError::audio("Audio capture thread panicked"))??;
+    let played = player.join().map_err(|_| ENguyenError::audio("Preview tone thread panicked"))?;
+    played.map_err(|e| ENguyenError::audio("The preview tone didn't play").caused_by(e))?;
+    Ok(loudest / TONE_LEVEL)
+}
+
+/// Interleaved stereo frames of the preview tone at `rate`
+fn preview_tone(rate: u32) -> Vec<i16> {
+    let seconds = |duration: time::Duration| {
+        (duration.as_secs() as f32 + duration.subsec_nanos() as f32 * 1e-9) * rate as f32
+    };
+    let (length, fade) = (seconds(TONE_LENGTH) as usize, seconds(TONE_FADE));
+    let tau = 2.0 * std::f32::consts::PI;
+    let mut frames = Vec::with_capacity(length * 2);
+    for i in 0..length {
+        let envelope = (i as f32 / fade).min((length - i) as f32 / fade).min(1.0);
+        let level = TONE_LEVEL * envelope * (tau * TONE_HZ * i as f32 / rate as f32).sin();
+        let sample = (level * f32::from(i16::max_value())) as i16;
+        frames.push(sample);
+        frames.push(sample);
+    }
+    frames
+}
+
+/// Peak amplitude of the `hz` sine in `samples`, by the Goertzel algorithm
+fn tone_amplitude(samples: &[f32], rate: f32, hz: f32) -> f32 {
+    let omega = 2.0 * std::f32::consts::PI * hz / rate;
+    let coefficient = 2.0 * omega.cos();
+    let (mut previous, mut before) = (0.0, 0.0);
+    for sample in samples {
+        let current = sample + coefficient * previous - before;
+        before = previous;
+        previous = current;
+    }
+    let power = previous * previous + before * before - coefficient * previous * before;
+    2.0 * power.max(0.0).sqrt() / samples.len().max(1) as f32
+}
+
+/// Plays interleaved stereo `frames` on the default output and waits until they're heard
+fn play(frames: &[i16], rate: u32) -> Result<(), String> {
+    let ac = connect_to_server()?;
+    let spec = Spec { format: Format::S16le, rate, channels: 2 };
+    let stream = new_stream(&ac, &spec, TONE_NAME, "test")?;
+    ac.mainloop.borrow_mut().lock();
+    let connected = stream.lock().unwrap().connect_playback(None, None, flags::NOFLAGS, None, None);
+    if let Err(e) = connected {
+        ac.mainloop.borrow_mut().unlock();
+        ac.mainloop.borrow_mut().stop();
+        return Err(format!("Could not connect to the default output: {:?}", e));
+    }
+    let state_producer = || ReadyState::Stream(stream.clone().try_lock().unwrap().get_state());
+    ready_wait(&state_producer, &ac)?;
+    let bytes: Vec<u8> = frames.iter().flat_map(|sample| sample.to_le_bytes().to_vec()).collect();
+    let played = {
+        let mut s = stream.lock().unwrap();
+        s.write(&bytes, None, 0, SeekMode::Relative)
+            .map_err(|e| format!("Could not write the tone: {:?}", e))
+            .and_then(|_| complete(&ac, &mut s, StreamOp::Drain))
+    };
+    ac.mainloop.borrow_mut().unlock();
+    if let Err(e) = disconnect_stream(&ac, &stream) {
+        warn!("Disconnect failed: {:?}", e);
+    }
+    ac.mainloop.borrow_mut().stop();
+    played
+}
+
+/// The monitor of the sink the sound server plays to by default
+fn default_monitor(ac: &AudioContext) -> Option<String> {
+    let found: Rc<RefCell<Option<String>>> = Rc::new(RefCell::new(None));
+    ac.mainloop.borrow_mut().lock();
+    let op = {
+        let (insider, ml_ref) = (found.clone(), Rc::clone(&ac.mainloop));
+        ac.context.borrow_mut().introspect().get_server_info(move |info: &ServerInfo| {
+            *insider.borrow_mut() = info.default_sink_name.as_ref().map(|name| name.to_string());
+            unsafe {
+                (*ml_ref.as_ptr()).signal(false);
+            }
+        })
+    };
+    while op.get_state() == pulse::operation::State::Running {
+        ac.mainloop.borrow_mut().wait();
+    }
+    let sink = found.borrow_mut().take();
+    if let Some(sink) = sink {
+        let (insider, ml_ref) = (found.clone(), Rc::clone(&ac.mainloop));
+        let op = ac.context.borrow_mut().introspect().get_sink_info_by_name(
+            &sink,
+            move |result: ListResult<&SinkInfo>| match result {
+                ListResult::Item(info) => {
+                    *insider.borrow_mut() =
+                        info.monitor_source_name.as_ref().map(|name| name.to_string());
+                },
+                ListResult::End | ListResult::Error => unsafe {
+                    (*ml_ref.as_ptr()).signal(false);
+                },
+            },
+        );
+        while op.get_state() == pulse::operation::State::Running {
+            ac.mainloop.borrow_mut().wait();
+        }
+    }
+    ac.mainloop.borrow_mut().unlock();
+    let monitor = found.borrow_mut().take();
+    monitor
+}
+
+/// The sink `monitor` listens to and its latency.  None for sources that aren't monitors and
+/// for idle sinks, which report none.
+fn sink_latency(ac: &AudioContext, monitor: &str) -> Option<OutputLatency> {
+    let sink: Rc<Cell<Option<u32>>> = Rc::new(Cell::new(None));
+    let found: Rc<RefCell<Option<OutputLatency>>> = Rc::new(RefCell::new(None));
+    ac.mainloop.borrow_mut().lock();
+    let op = {
+        let (insider, ml_ref) = (sink.clone(), Rc::clone(&ac.mainloop));
+        ac.context.borrow_mut().introspect().get_source_info_by_name(
+            monitor,
+            move |result: ListResult<&SourceInfo>| match result {
+                ListResult::Item(info) => insider.set(info.monitor_of_sink),
+                ListResult::End | ListResult::Error => unsafe {
+                    (*ml_ref.as_ptr()).signal(false);
+                },
+            },
+        )
+    };
+    while op.get_state() == pulse::operation::State::Running {
+        ac.mainloop.borrow_mut().wait();
+    }
+    if let Some(index) = sink.get() {
+        let (insider, ml_ref) = (found.clone(), Rc::clone(&ac.mainloop));
+        let op = ac.context.borrow_mut().introspect().get_sink_info_by_index(
+            index,
+            move |result: ListResult<&SinkInfo>| match result {
+                ListResult::Item(info) => {
+                    let latency = time::Duration::from_micros(info.latency.0);
+                    if latency > time::Duration::default() {
+                        let sink = info.name.as_ref().map(|name| name.to_string());
+                        let sink = sink.unwrap_or_default();
+                        *insider.borrow_mut() = Some(OutputLatency { sink, latency });
+                    }
+                },
+                ListResult::End | ListResult::Error => unsafe {
+                    (*ml_ref.as_ptr()).signal(false);
+                },
+            },
+        );
+        while op.get_state() == pulse::operation::State::Running {
+            ac.mainloop.borrow_mut().wait();
+        }
+    }
+    ac.mainloop.borrow_mut().unlock();
+    let latency = found.borrow_mut().take();
+    latency
+}
+
+/// A flag raised whenever the sound server's defaults change
+fn watch_defaults(ac: &AudioContext) -> Arc<AtomicBool> {
+    let changed = Arc::new(AtomicBool::new(false));
+    let raised = changed.clone();
+    ac.mainloop.borrow_mut().lock();
+    {
+        let mut context = ac.context.borrow_mut();
+        context.set_subscribe_callback(Some(Box::new(move |facility, change, _index| {
+            if facility == Some(Facility::Server) && change == Some(Change::Changed) {
+                raised.store(true, Ordering::Relaxed);
+            }
+        })));
+        context.subscribe(subscription_masks::SERVER, |_subscribed| {});
+    }
+    ac.mainloop.borrow_mut().unlock();
+    changed
+}
+
 fn first_monitor(
     devices: Vec<(ServerStream, SimpleSource)>,
 ) -> Option<(ServerStream, SimpleSource)> {
@@ -566,11 +1083,24 @@ fn create_stream(
     ac: &AudioContext,
     server_stream: &ServerStream,
 ) -> Result<Arc<Mutex<Stream>>, String> {
-    // TODO check proplists again
-    let stream = Arc::new(Mutex::new(
-        Stream::new(&mut ac.context.borrow_mut(), "Music Monitor", &server_stream.spec, None)
-            .expect("Failed to create new stream"),
-    ));
+    new_stream(ac, &server_stream.spec, STREAM_NAME, "music")
+}
+
+/// A stream of `spec` called `name` in sound settings, playing or recording in `role`
+fn new_stream(
+    ac: &AudioContext,
+    spec: &Spec,
+    name: &str,
+    role: &str,
+) -> Result<Arc<Mutex<Stream>>, String> {
+    let mut proplist = client_proplist(&[
+        (properties::MEDIA_ROLE, role),
+        (properties::MEDIA_NAME, name),
+        (properties::MEDIA_ICON_NAME, ICON_NAME),
+    ])?;
+    let stream =
+        Stream::new_with_proplist(&mut ac.context.borrow_mut(), name, spec, None, &mut proplist);
+    let stream = Arc::new(Mutex::new(stream.ok_or("Failed to create new stream")?));
     ac.mainloop.borrow_mut().lock();
     let ml_ref = Rc::clone(&ac.mainloop);
     // Stream state change callback
@@ -605,39 +1135,123 @@ fn create_stream(
     Ok(stream)
 }
 
+/// Buffer attributes asking for fragments of `latency_ms`.  Playback fields and the maximum
+/// length are left to the server.
+fn buffer_attr(source: &SimpleSource, latency_ms: u32) -> BufferAttr {
+    BufferAttr {
+        maxlength: u32::MAX,
+        tlength: u32::MAX,
+        prebuf: u32::MAX,
+        minreq: u32::MAX,
+        fragsize: source.fragment_bytes(latency_ms),
+    }
+}
+
+/// Records from `stream_def`, with fragments sized by `attr` if given
 fn connect_stream(
     ac: &AudioContext,
     stream: &mut Arc<Mutex<Stream>>,
     stream_def: &ServerStream,
+    attr: Option<&BufferAttr>,
 ) -> Result<bool, String> {
+    // corked until capture is ready to read
+    let mut stream_flags = flags::START_UNMUTED | flags::START_CORKED;
+    if attr.is_some() {
+        // the source's latency is lowered to match, instead of only the stream's buffering
+        stream_flags |= flags::ADJUST_LATENCY;
+    }
     ac.mainloop.borrow_mut().lock();
-    {
-        stream
-            .lock()
-            .unwrap()
-            .connect_record(
-                Some(stream_def.name.as_str()),
-                None,
-                flags::START_UNMUTED & flags::START_CORKED,
-            )
-            .expect("Could not connect");
+    let connected =
+        stream.lock().unwrap().connect_record(Some(stream_def.name.as_str()), attr, stream_flags);
+    if let Err(e) = connected {
+        ac.mainloop.borrow_mut().unlock();
+        return Err(format!("Could not connect to {}: {:?}", stream_def.name, e));
     }
 
     // Wait for stream to be ready
     let state_producer = || ReadyState::Stream(stream.clone().try_lock().unwrap().get_state());
     ready_wait(&state_producer, ac)?;
+    let negotiated = stream.lock().unwrap().get_buffer_attr().map(|attr| attr.fragsize);
     ac.mainloop.borrow_mut().unlock();
+    if let Some(fragsize) = negotiated {
+        let spec = &stream_def.spec;
+        let byte_rate = spec.rate as f32 * spec.channels as f32 * spec.format.size() as f32;
+        let latency_ms = 1000.0 * fragsize as f32 / byte_rate;
+        info!("Capture latency is {:.1}ms ({} byte fragments)", latency_ms, fragsize);
+    }
     Ok(true)
 }
 
+/// Records from `monitor` on a new stream in place of `stream`
+fn move_stream(
+    ac: &AudioContext,
+    stream: &mut Arc<Mutex<Stream>>,
+    monitor: &ServerStream,
+    attr: Option<&BufferAttr>,
+) -> Result<(), String> {
+    if let Err(e) = disconnect_stream(ac, stream) {
+        warn!("Disconnect failed: {:?}", e);
+    }
+    let mut moved = create_stream(ac, monitor)?;
+    connect_stream(ac, &mut moved, monitor, attr)?;
+    *stream = moved;
+    ac.mainloop.borrow_mut().lock();
+    let uncorked = complete(ac, &mut stream.lock().unwrap(), StreamOp::Uncork);
+    ac.mainloop.borrow_mut().unlock();
+    uncorked
+}
+
+/// Stream operations the server acknowledges
+#[derive(Clone, Copy, Debug)]
+enum StreamOp {
+    Cork,
+    Uncork,
+    Flush,
+    Drain,
+}
+
+/// Starts `op` on `stream` and waits until the server has done it.  Call with the mainloop
+/// locked.
+fn complete(ac: &AudioContext, stream: &mut Stream, op: StreamOp) -> Result<(), String> {
+    let succeeded = Rc::new(Cell::new(false));
+    let (reported, ml_ref) = (succeeded.clone(), Rc::clone(&ac.mainloop));
+    let callback: Box<dyn FnMut(bool)> = Box::new(move |success| {
+        reported.set(success);
+        unsafe {
+            (*ml_ref.as_ptr()).signal(false);
+        }
+    });
+    let operation = match op {
+        StreamOp::Cork => stream.cork(Some(callback)),
+        StreamOp::Uncork => stream.uncork(Some(callback)),
+        StreamOp::Flush => stream.flush(Some(callback)),
+        StreamOp::Drain => stream.drain(Some(callback)),
+    };
+    while operation.get_state() == pulse::operation::State::Running {
+        ac.mainloop.borrow_mut().wait();
+    }
+    if succeeded.get() {
+        Ok(())
+    } else {
+        Err(format!("The sound server failed to {:?} the stream", op).to_lowercase())
+    }
+}
+
 fn disconnect_stream(ac: &AudioContext, stream: &Arc<Mutex<Stream>>) -> Result<bool, PAErr> {
     ac.mainloop.borrow_mut().lock();
     let mut s = stream.lock().unwrap();
-    s.cork(None);
-    s.flush(None);
+    // a stream that can't be paused or emptied is still disconnected
+    if s.get_state() == pulse::stream::State::Ready {
+        for op in [StreamOp::Cork, StreamOp::Flush].iter() {
+            if let Err(e) = complete(ac, &mut s, *op) {
+                warn!("{}", e);
+            }
+        }
+    }
     s.set_state_callback(None);
-    s.disconnect()?;
+    let disconnected = s.disconnect();
     ac.mainloop.borrow_mut().unlock();
+    disconnected?;
     Ok(true)
 }
 
@@ -690,8 +1304,82 @@ mod tests {
         let streams = server_streams(&ac);
         let (monitor, _monitor_info) = first_monitor(streams).unwrap();
         let mut stream = create_stream(&ac, &monitor).unwrap();
-        connect_stream(&ac, &mut stream, &monitor).unwrap();
+        connect_stream(&ac, &mut stream, &monitor, None).unwrap();
+        disconnect_stream(&ac, &stream).unwrap();
+    }
+
+    /// A sink playing nothing, so its monitor's stream is the test's alone
+    struct NullSink<'a> {
+        ac: &'a AudioContext,
+        module: u32,
+    }
+
+    impl<'a> NullSink<'a> {
+        fn load(ac: &'a AudioContext, name: &str) -> NullSink<'a> {
+            let loaded = Rc::new(Cell::new(u32::MAX));
+            let (reported, ml_ref) = (loaded.clone(), Rc::clone(&ac.mainloop));
+            ac.mainloop.borrow_mut().lock();
+            let argument = format!("sink_name={}", name);
+            let op = ac.context.borrow_mut().introspect().load_module(
+                "module-null-sink",
+                &argument,
+                move |module| {
+                    reported.set(module);
+                    unsafe {
+                        (*ml_ref.as_ptr()).signal(false);
+                    }
+                },
+            );
+            while op.get_state() == pulse::operation::State::Running {
+                ac.mainloop.borrow_mut().wait();
+            }
+            ac.mainloop.borrow_mut().unlock();
+            assert_ne!(loaded.get(), u32::MAX, "module-null-sink didn't load");
+            NullSink { ac, module: loaded.get() }
+        }
+    }
+
+    impl<'a> Drop for NullSink<'a> {
+        fn drop(&mut self) {
+            self.ac.mainloop.borrow_mut().lock();
+            let ml_ref = Rc::clone(&self.ac.mainloop);
+            let op = self.ac.context.borrow_mut().introspect().unload_module(
+                self.module,
+                move |_success| unsafe {
+                    (*ml_ref.as_ptr()).signal(false);
+                },
+            );
+            while op.get_state() == pulse::operation::State::Running {
+                self.ac.mainloop.borrow_mut().wait();
+            }
+            self.ac.mainloop.borrow_mut().unlock();
+        }
+    }
+
+    #[test]
+    fn corking_waits_for_the_server() {
+        let ac = connect_to_server().unwrap();
+        let sink = NullSink::load(&ac, "e_nguyen_cork_test");
+        let (monitor, _info) = server_streams(&ac)
+            .into_iter()
+            .find(|(stream, _info)| stream.name == "e_nguyen_cork_test.monitor")
+            .unwrap();
+        let mut stream = create_stream(&ac, &monitor).unwrap();
+        connect_stream(&ac, &mut stream, &monitor, None).unwrap();
+        ac.mainloop.borrow_mut().lock();
+        {
+            let mut s = stream.lock().unwrap();
+            assert!(s.is_corked().unwrap());
+            complete(&ac, &mut s, StreamOp::Uncork).unwrap();
+            assert!(!s.is_corked().unwrap());
+            complete(&ac, &mut s, StreamOp::Cork).unwrap();
+            complete(&ac, &mut s, StreamOp::Flush).unwrap();
+            assert!(s.is_corked().unwrap());
+        }
+        ac.mainloop.borrow_mut().unlock();
         disconnect_stream(&ac, &stream).unwrap();
+        drop(sink);
+        ac.mainloop.borrow_mut().stop();
     }
 
     #[test]
@@ -705,21 +1393,52 @@ mod tests {
 
         let handle = thread::spawn(move || {
             while recorded < 16334 {
-                let ravail = rx.available();
+                let ravail = rx.available_frames() * rx.frame_bytes();
                 if ravail > min_count {
-                    let _read = rx.read(ravail);
+                    let _read = rx.read_frames(ravail / rx.frame_bytes());
                     recorded += ravail;
                 }
             }
         });
         handle.join().unwrap();
-        stream.chill().unwrap().1.join().unwrap();
+        stream.chill().unwrap().1.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn square_wave_is_heard() {
+        let level = peak_level(&mut Square4kHz::default(), time::Duration::from_millis(100));
+        let level = level.unwrap();
+        assert!(level > 0.4 && level <= 1.0, "level {}", level);
+    }
+
+    #[test]
+    fn sources_are_chosen() {
+        let spec = Spec { format: Format::S16le, channels: 2, rate: 44100 };
+        let stream = |name: &str, index| ServerStream {
+            name: name.to_owned(),
+            index,
+            desc: String::new(),
+            spec,
+        };
+        let offered = vec![stream("alsa_input.mic", 3), stream("alsa_output.monitor", 7)];
+        let name = Some("alsa_output.monitor".to_owned());
+        let by_name = SourceChoice { name, index: Some(3), follow_default: false };
+        assert_eq!(choose(&offered, &by_name), Some(1));
+        let by_index = SourceChoice { index: Some(3), ..SourceChoice::default() };
+        assert_eq!(choose(&offered, &by_index), Some(0));
+        let gone = SourceChoice { index: Some(9), ..SourceChoice::default() };
+        assert_eq!(choose(&offered, &gone), None);
+        assert_eq!(choose(&offered, &SourceChoice::default()), None);
+
+        // 20ms of 44.1kHz stereo is 882 frames of 4 bytes
+        assert_eq!(buffer_attr(&SimpleSource::default(), 20).fragsize, 3528);
+        assert_eq!(SimpleSource::default().fragment_bytes(0), 4);
     }
 
     #[test]
     fn heat_and_chill_pa_ring() {
         let min_count = 1024;
-        let mut stream = PaStream::default();
+        let mut stream = PaStream::monitor().unwrap();
 
         let _connected = stream.connect().unwrap();
         let (rx, source) = stream.heat().unwrap();
@@ -728,14 +1447,34 @@ mod tests {
 
         let handle = thread::spawn(move || {
             while recorded < 16334 {
-                let ravail = rx.available();
+                let ravail = rx.available_frames() * rx.frame_bytes();
                 if ravail > min_count {
-                    let _read = rx.read(ravail);
+                    let _read = rx.read_frames(ravail / rx.frame_bytes());
                     recorded += ravail;
                 }
             }
         });
         handle.join().unwrap();
-        stream.chill().unwrap().1.join().unwrap();
+        stream.chill().unwrap().1.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn preview_tone_is_heard_at_its_level() {
+        let tone = preview_tone(TONE_RATE);
+        let peak = tone.iter().map(|s| f32::from(*s).abs()).fold(0.0, f32::max);
+        assert!(peak <= TONE_LEVEL * f32::from(i16::max_value()));
+        // faded in and out, so it doesn't click
+        assert!(tone[..4].iter().all(|s| s.abs() < 100));
+        assert!(tone[tone.len() - 4..].iter().all(|s| s.abs() < 100));
+        let middle: Vec<f32> = tone[tone.len() / 2..]
+            .iter()
+            .step_by(2)
+            .take(TONE_WINDOW)
+            .map(|s| f32::from(*s) / f32::from(i16::max_value()))
+            .collect();
+        let heard = tone_amplitude(&middle, TONE_RATE as f32, TONE_HZ) / TONE_LEVEL;
+        assert!((heard - 1.0).abs() < 0.05, "heard at {}", heard);
+        let elsewhere = tone_amplitude(&middle, TONE_RATE as f32, 3.0 * TONE_HZ) / TONE_LEVEL;
+        assert!(elsewhere < TONE_HEARD, "heard at {}", elsewhere);
     }
 }
@@ -0,0 +1,175 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! Starting with the desktop session, through an XDG autostart entry or a systemd user unit
+//! tied to the graphical session.  Either runs this binary with the flags it was installed
+//! with.
+
+use crate::errors::ENguyenError;
+
+use log::warn;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+static DEFAULT_CONFIG_HOME: &str = "~/.config";
+static DESKTOP_ENTRY: &str = "autostart/e-nguyen.desktop";
+static UNIT_NAME: &str = "e-nguyen.service";
+static UNIT_DIR: &str = "systemd/user";
+
+/// How the session starts the visualizer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Autostart {
+    /// An XDG autostart entry, which most desktops read
+    Desktop,
+    /// A systemd user unit, restarted if it fails
+    Systemd,
+}
+
+impl Autostart {
+    /// Where the file for this kind of autostart goes
+    pub fn path(self) -> PathBuf {
+        match self {
+            Autostart::Desktop => config_home().join(DESKTOP_ENTRY),
+            Autostart::Systemd => config_home().join(UNIT_DIR).join(UNIT_NAME),
+        }
+    }
+
+    /// The file's contents for running `exec` with `args`
+    fn contents(self, exec: &Path, args: &[String]) -> String {
+        let command = command_line(exec, args);
+        match self {
+            Autostart::Desktop => format!(
+                "[Desktop Entry]\nType=Application\nName=E-Nguyen\nComment=Music visualizer\n\
+                 Exec={}\nTerminal=false\nX-GNOME-Autostart-enabled=true\n",
+                command
+            ),
+            Autostart::Systemd => format!(
+                "[Unit]\nDescription=E-Nguyen music visualizer\n\
+                 PartOf=graphical-session.target\nAfter=graphical-session.target\n\n\
+                 [Service]\nExecStart={}\nRestart=on-failure\nRestartSec=5\n\n\
+                 [Install]\nWantedBy=graphical-session.target\n",
+                command
+            ),
+        }
+    }
+}
+
+/// `$XDG_CONFIG_HOME`, or `~/.config`
+fn config_home() -> PathBuf {
+    // TODO platform independence
+    if let Some(dir) = std::env::var_os("XDG_CONFIG_HOME") {
+        return PathBuf::from(dir);
+    }
+    match std::env::var_os("HOME") {
+        Some(home) => PathBuf::from(home).join(&DEFAULT_CONFIG_HOME[2..]),
+        None => PathBuf::from(DEFAULT_CONFIG_HOME),
+    }
+}
+
+/// Writes the autostart file to run this binary with `args`, and enables a systemd unit.
+/// Returns where the file was written.
+pub fn install(kind: Autostart, args: &[String]) -> Result<PathBuf, ENguyenError> {
+    let exec = std::env::current_exe()
+        .map_err(|e| ENguyenError::config("This binary's path is unknown").caused_by(e))?;
+    let path = kind.path();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, kind.contents(&exec, args))?;
+    if kind == Autostart::Systemd {
+        systemctl(&["daemon-reload"])?;
+        systemctl(&["enable", UNIT_NAME])?;
+    }
+    Ok(path)
+}
+
+/// Disables and removes both kinds of autostart.  Returns the files removed.
+pub fn uninstall() -> Result<Vec<PathBuf>, ENguyenError> {
+    let mut removed = Vec::new();
+    for kind in [Autostart::Desktop, Autostart::Systemd].iter() {
+        let path = kind.path();
+        if !path.exists() {
+            continue;
+        }
+        if *kind == Autostart::Systemd {
+            if let Err(e) = systemctl(&["disable", UNIT_NAME]) {
+                warn!("{}", e);
+            }
+        }
+        fs::remove_file(&path)?;
+        removed.push(path);
+    }
+    if removed.iter().any(|path| path.ends_with(UNIT_NAME)) {
+        let _ = systemctl(&["daemon-reload"]);
+    }
+    Ok(removed)
+}
+
+fn systemctl(args: &[&str]) -> Result<(), ENguyenError> {
+    let shown = format!("systemctl --user {}", args.join(" "));
+    let status = Command::new("systemctl").arg("--user").args(args).status();
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(ENguyenError::config(format!("`{}` failed with {}", shown, status))),
+        Err(e) => Err(ENguyenError::config(format!("`{}` didn't run", shown)).caused_by(e)),
+    }
+}
+
+/// `exec` and `args` quoted as both desktop entries and systemd read them.  Both take double
+/// quotes with backslash escapes and expand `%` sequences.
+fn command_line(exec: &Path, args: &[String]) -> String {
+    let exec = exec.to_string_lossy();
+    let words = std::iter::once(exec.as_ref()).chain(args.iter().map(String::as_str));
+    words.map(quote).collect::<Vec<_>>().join(" ")
+}
+
+fn quote(word: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@".contains(c);
+    let escaped = word.replace('%', "%%");
+    if !escaped.is_empty() && escaped.chars().all(plain) {
+        return escaped;
+    }
+    let mut quoted = String::from("\"");
+    for c in escaped.chars() {
+        if "\"`$\\".contains(c) {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_are_quoted() {
+        let args = vec!["--profile".to_owned(), "living room".to_owned(), "50%".to_owned()];
+        let line = command_line(Path::new("/opt/e-nguyen/bin/e-nguyen"), &args);
+        assert_eq!(line, "/opt/e-nguyen/bin/e-nguyen --profile \"living room\" \"50%%\"");
+        assert_eq!(quote("say \"$hi\""), "\"say \\\"\\$hi\\\"\"");
+        assert_eq!(quote(""), "\"\"");
+
+        let unit = Autostart::Systemd.contents(Path::new("/usr/bin/e-nguyen"), &[]);
+        assert!(unit.contains("\nExecStart=/usr/bin/e-nguyen\n"));
+        assert!(unit.contains("\nWantedBy=graphical-session.target\n"));
+        let entry = Autostart::Desktop.contents(Path::new("/usr/bin/e-nguyen"), &args[..1]);
+        assert!(entry.starts_with("[Desktop Entry]\n"));
+        assert!(entry.contains("\nExec=/usr/bin/e-nguyen --profile\n"));
+    }
+}
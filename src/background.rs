@@ -0,0 +1,219 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! What fills the window beneath the scene.  Every background the config names, globally or
+//! for a scene, is loaded when the window opens so switching scenes never waits on a file.
+
+use crate::bench::millis;
+use crate::config::{BackgroundConfig, BackgroundKind, ENguyenConfig};
+use crate::errors::ENguyenError;
+use crate::geometry::{pixel_rect, static_vertices};
+use crate::layers::ImageLayer;
+use crate::rendering::{gradient_pipeline, to_linear, PipelineRegistry, XyVertex};
+
+use image::RgbaImage;
+use log::warn;
+use std::fs;
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use vulkano::buffer::ImmutableBuffer;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DynamicState};
+use vulkano::device::Queue;
+use vulkano::pipeline::GraphicsPipelineAbstract;
+use vulkano::sync::GpuFuture;
+
+/// Frames of a video loop beyond this are left out to bound GPU memory
+static MAX_FRAMES: usize = 600;
+static BLACK: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+
+enum Fill {
+    /// Drawn by clearing the window
+    Solid([f32; 4]),
+    Gradient,
+    /// An image or the frames of a video loop
+    Frames {
+        layer: ImageLayer,
+        fps: f32,
+    },
+}
+
+/// The loaded backgrounds of every scene
+pub struct Backdrop {
+    fills: Vec<(BackgroundConfig, Fill)>,
+    gradient: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    rect: Arc<ImmutableBuffer<[XyVertex]>>,
+    started: Instant,
+}
+
+impl Backdrop {
+    /// Backgrounds that fail to load are solid black, with a warning.  Uploads run on `queue`.
+    pub fn new(
+        config: &ENguyenConfig,
+        queue: &Arc<Queue>,
+        pipelines: &PipelineRegistry,
+    ) -> Result<(Backdrop, Box<dyn GpuFuture>), ENguyenError> {
+        let gradient = gradient_pipeline(pipelines)?;
+        let corners = [[-1.0, -1.0], [1.0, -1.0], [-1.0, 1.0], [1.0, 1.0]];
+        let corners = corners.iter().map(|&position| XyVertex { position });
+        let (rect, mut uploads) = static_vertices(queue, corners)?;
+
+        let mut fills: Vec<(BackgroundConfig, Fill)> = Vec::new();
+        let scenes = config.scenes.values().filter_map(|tuning| tuning.background.as_ref());
+        for background in iter::once(&config.background).chain(scenes) {
+            if fills.iter().any(|(loaded, _)| loaded == background) {
+                continue;
+            }
+            let fill = match load(background, queue, pipelines) {
+                Ok((fill, Some(upload))) => {
+                    uploads = Box::new(uploads.join(upload));
+                    fill
+                },
+                Ok((fill, None)) => fill,
+                Err(e) => {
+                    warn!("Background {:?} failed to load, using black: {}", background.path, e);
+                    Fill::Solid(BLACK)
+                },
+            };
+            fills.push((background.clone(), fill));
+        }
+
+        let backdrop = Backdrop { fills, gradient, rect, started: Instant::now() };
+        Ok((backdrop, uploads))
+    }
+
+    /// What to clear the window to beneath `background`
+    pub fn clear_color(&self, background: &BackgroundConfig) -> [f32; 4] {
+        match self.fill(background) {
+            Some(Fill::Solid(color)) => to_linear(*color),
+            _ => BLACK,
+        }
+    }
+
+    /// Records `background` filling a window of `window` pixels
+    pub fn draw(
+        &self,
+        cbb: AutoCommandBufferBuilder,
+        state: &DynamicState,
+        background: &BackgroundConfig,
+        window: [f32; 2],
+    ) -> Result<AutoCommandBufferBuilder, ENguyenError> {
+        match self.fill(background) {
+            Some(Fill::Gradient) => cbb
+                .draw(self.gradient.clone(), state, vec![self.rect.clone()], (), ())
+                .map_err(|e| ENguyenError::render("Gradient draw failed").caused_by(e)),
+            Some(Fill::Frames { layer, fps }) => {
+                let seconds = millis(self.started.elapsed()) as f32 / 1000.0;
+                let frame = frame_at(seconds, *fps, layer.frames());
+                let rect = cover(layer.dimensions, window);
+                layer.draw_frame(cbb, state, rect, 1.0, frame)
+            },
+            Some(Fill::Solid(_)) | None => Ok(cbb),
+        }
+    }
+
+    fn fill(&self, background: &BackgroundConfig) -> Option<&Fill> {
+        self.fills.iter().find(|(loaded, _)| loaded == background).map(|(_, fill)| fill)
+    }
+}
+
+fn load(
+    background: &BackgroundConfig,
+    queue: &Arc<Queue>,
+    pipelines: &PipelineRegistry,
+) -> Result<(Fill, Option<Box<dyn GpuFuture>>), ENguyenError> {
+    let path = match (background.kind, &background.path) {
+        (BackgroundKind::Solid, _) => {
+            let [r, g, b] = background.color;
+            return Ok((Fill::Solid([r, g, b, 1.0]), None));
+        },
+        (BackgroundKind::Gradient, _) => return Ok((Fill::Gradient, None)),
+        (_, None) => return Err(ENguyenError::config("No path set")),
+        (_, Some(path)) => path,
+    };
+    let frames = match background.kind {
+        BackgroundKind::VideoLoop => frame_paths(path)?
+            .into_iter()
+            .map(|frame| read(&frame))
+            .collect::<Result<Vec<RgbaImage>, ENguyenError>>()?,
+        _ => vec![read(path)?],
+    };
+    let (layer, upload) = ImageLayer::sequence(queue, pipelines, frames)?;
+    Ok((Fill::Frames { layer, fps: background.fps }, Some(upload)))
+}
+
+fn read(path: &Path) -> Result<RgbaImage, ENguyenError> {
+    let image = image::open(path).map_err(|e| {
+        ENguyenError::config(format!("Could not read {}", path.display())).caused_by(e)
+    })?;
+    Ok(image.to_rgba())
+}
+
+/// The files in `directory` in name order, at most `MAX_FRAMES` of them
+fn frame_paths(directory: &Path) -> Result<Vec<PathBuf>, ENguyenError> {
+    let entries = fs::read_dir(directory).map_err(|e| {
+        ENguyenError::config(format!("Could not list {}", directory.display())).caused_by(e)
+    })?;
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .collect();
+    paths.sort();
+    if paths.len() > MAX_FRAMES {
+        warn!("Playing the first {} of {} frames", MAX_FRAMES, paths.len());
+        paths.truncate(MAX_FRAMES);
+    }
+    Ok(paths)
+}
+
+/// The frame of a loop of `frames` shown `seconds` after it started
+fn frame_at(seconds: f32, fps: f32, frames: usize) -> usize {
+    (seconds * fps.max(0.0)) as usize % frames.max(1)
+}
+
+/// Window coordinates of an image of `image` pixels scaled to cover the window, cropping the
+/// longer side evenly
+fn cover(image: [u32; 2], window: [f32; 2]) -> [f32; 4] {
+    let (image_width, image_height) = (image[0].max(1) as f32, image[1].max(1) as f32);
+    let [width, height] = window;
+    let scale = (width / image_width).max(height / image_height);
+    let (scaled_width, scaled_height) = (image_width * scale, image_height * scale);
+    let (left, top) = ((width - scaled_width) / 2.0, (height - scaled_height) / 2.0);
+    pixel_rect(window, left, top, scaled_width, scaled_height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn images_cover_the_window() {
+        // a square image on a wide window is cropped top and bottom
+        let [left, top, right, bottom] = cover([100, 100], [200.0, 100.0]);
+        assert_eq!([left, right], [-1.0, 1.0]);
+        assert_eq!([top, bottom], [-2.0, 2.0]);
+        // a matching image fits exactly
+        assert_eq!(cover([64, 36], [1280.0, 720.0]), [-1.0, -1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn loops_wrap() {
+        assert_eq!(frame_at(0.0, 24.0, 10), 0);
+        assert_eq!(frame_at(0.25, 24.0, 10), 6);
+        assert_eq!(frame_at(0.5, 24.0, 10), 2);
+        assert_eq!(frame_at(100.0, 0.0, 10), 0);
+    }
+}
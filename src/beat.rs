@@ -0,0 +1,156 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! Beat tracking from the rise of low band levels between analysis windows.  Onsets are
+//! rises well above the recent average, and the beat period follows the time between them.
+
+use crate::bench::millis;
+
+use std::collections::VecDeque;
+use std::time::Instant;
+
+/// Rises averaged for the onset threshold.  About 0.7s of windows.
+static HISTORY: usize = 43;
+/// How far above the average rise an onset must be
+static SENSITIVITY: f32 = 1.5;
+/// Rises smaller than this are never onsets
+static MIN_RISE: f32 = 0.02;
+/// Beat periods in seconds, 200 to 40bpm.  Onsets closer than the shortest are ignored.
+static MIN_PERIOD: f32 = 0.3;
+static MAX_PERIOD: f32 = 1.5;
+static DEFAULT_PERIOD: f32 = 0.5;
+/// Share of the gap between the period and a new interval closed at each onset
+static PERIOD_SMOOTHING: f32 = 0.25;
+
+/// Where the music is within its beat
+#[derive(Clone, Debug, PartialEq)]
+pub struct Beat {
+    /// A beat started in this window
+    pub onset: bool,
+    /// From 0.0 at the last beat toward 1.0 at the next
+    pub phase: f32,
+    pub bpm: f32,
+}
+
+impl Default for Beat {
+    fn default() -> Beat {
+        Beat { onset: false, phase: 0.0, bpm: 60.0 / DEFAULT_PERIOD }
+    }
+}
+
+pub struct BeatTracker {
+    previous: f32,
+    rises: VecDeque<f32>,
+    last_onset: Option<Instant>,
+    /// Seconds
+    period: f32,
+}
+
+impl BeatTracker {
+    pub fn new() -> BeatTracker {
+        BeatTracker {
+            previous: 0.0,
+            rises: VecDeque::with_capacity(HISTORY),
+            last_onset: None,
+            period: DEFAULT_PERIOD,
+        }
+    }
+
+    /// Follow a window whose low bands were at `level`, captured `at`
+    pub fn hear(&mut self, level: f32, at: Instant) -> Beat {
+        let rise = (level - self.previous).max(0.0);
+        self.previous = level;
+        let average = if self.rises.is_empty() {
+            0.0
+        } else {
+            self.rises.iter().sum::<f32>() / self.rises.len() as f32
+        };
+        if self.rises.len() == HISTORY {
+            self.rises.pop_front();
+        }
+        self.rises.push_back(rise);
+
+        let since = self.last_onset.map(|onset| seconds_between(onset, at));
+        let settled = match since {
+            Some(since) => since >= MIN_PERIOD,
+            None => true,
+        };
+        let onset = rise > MIN_RISE && rise > average * SENSITIVITY && settled;
+        if onset {
+            if let Some(interval) = since {
+                if interval <= MAX_PERIOD {
+                    self.period += (interval - self.period) * PERIOD_SMOOTHING;
+                }
+            }
+            self.last_onset = Some(at);
+        }
+
+        let phase = match self.last_onset {
+            Some(onset) => (seconds_between(onset, at) / self.period).fract(),
+            None => 0.0,
+        };
+        Beat { onset, phase, bpm: 60.0 / self.period }
+    }
+}
+
+impl Default for BeatTracker {
+    fn default() -> BeatTracker {
+        BeatTracker::new()
+    }
+}
+
+fn seconds_between(earlier: Instant, later: Instant) -> f32 {
+    millis(later.duration_since(earlier)) as f32 / 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn tempo_is_followed() {
+        // a kick every 36 windows of 1/60s is 100bpm
+        let start = Instant::now();
+        let mut tracker = BeatTracker::new();
+        let mut onsets = 0;
+        let mut beat = Beat::default();
+        for window in 0..36 * 20 {
+            let at = start + Duration::from_millis(window * 1000 / 60);
+            let level = if window % 36 == 0 { 0.8 } else { 0.1 };
+            beat = tracker.hear(level, at);
+            if beat.onset {
+                onsets += 1;
+            }
+            if window == 36 * 19 + 18 {
+                assert!((beat.phase - 0.5).abs() < 0.05, "{}", beat.phase);
+            }
+        }
+        assert_eq!(onsets, 20);
+        assert!((beat.bpm - 100.0).abs() < 1.0, "{}", beat.bpm);
+    }
+
+    #[test]
+    fn steady_levels_have_no_onsets() {
+        let start = Instant::now();
+        let mut tracker = BeatTracker::new();
+        tracker.hear(0.5, start);
+        for window in 1..120 {
+            let at = start + Duration::from_millis(window * 16);
+            assert!(!tracker.hear(0.5, at).onset);
+        }
+        assert_eq!(tracker.hear(0.5, start + Duration::from_secs(2)).bpm, 120.0);
+    }
+}
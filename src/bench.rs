@@ -0,0 +1,216 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+use crate::audio::{AudioStream, CaptureHandle, RingState, SimpleSource};
+use crate::compute::{AudioTexSource, AudioTexTap};
+use crate::errors::ENguyenError;
+use crate::ewin::GpuPicker;
+use crate::pacing::PacingSummary;
+use crate::ring::{FrameReader, OverflowPolicy, RingFrames};
+
+use log::info;
+use serde::Serialize;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use vulkano::sync::GpuFuture;
+
+static FLOOD_RATE: u32 = 44100;
+/// Pitch of the square wave the benchmark analyzes, as the synthetic source plays it
+static FLOOD_HZ: u32 = 200;
+/// Frames the ring holds before the flood waits on the analysis
+static FLOOD_FRAMES: usize = 4096;
+
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub mean: f64,
+    pub min: f64,
+    pub max: f64,
+    pub p95: f64,
+}
+
+impl Summary {
+    pub fn of(samples: &mut [f64]) -> Summary {
+        if samples.is_empty() {
+            return Summary { mean: 0.0, min: 0.0, max: 0.0, p95: 0.0 };
+        }
+        samples.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        let p95 = samples[((samples.len() - 1) as f64 * 0.95) as usize];
+        Summary { mean, min: samples[0], max: samples[samples.len() - 1], p95 }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BenchReport {
+    pub version: &'static str,
+    pub seconds: f64,
+    pub frames: u64,
+    /// Analysis textures produced per second
+    pub analysis_rate: f64,
+    /// From submitting each compute dispatch until the benchmark sees its fence signaled
+    pub gpu_dispatch_ms: Summary,
+    /// Ring backlog plus time from reading audio to the texture being ready
+    pub latency_ms: Summary,
+    /// Visualizer frame timings, if a window was rendered to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frame_pacing: Option<PacingSummary>,
+}
+
+impl BenchReport {
+    pub fn save(&self, path: &Path) -> Result<(), ENguyenError> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| ENguyenError::compute("Report could not be serialized").caused_by(e))?;
+        let mut f = File::create(path)?;
+        f.write_all(json.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Runs the analysis pipeline headless as fast as it will go and measures it.  Complements
+/// the criterion FFT bench by covering ring, compute and GPU together.
+pub fn run(picker: &GpuPicker, duration: Duration) -> Result<BenchReport, ENguyenError> {
+    let (device, queue) = picker.headless_compute()?;
+    let source = AudioTexSource::with_stream(1024, Box::new(Flood::new()))?;
+    let tap = AudioTexTap::turn_on(source, device.clone(), queue.clone())?;
+
+    let mut dispatch_ms: Vec<f64> = Vec::new();
+    let mut latency_ms: Vec<f64> = Vec::new();
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        let tex = match tap.tap.recv_timeout(Duration::from_secs(1)) {
+            Ok(tex) => tex,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(ENguyenError::compute("Analysis thread stopped during benchmark"));
+            },
+        };
+        tex.ready
+            .then_signal_fence_and_flush()
+            .map_err(|e| ENguyenError::compute("Could not flush compute dispatch").caused_by(e))?
+            .wait(None)
+            .map_err(|e| ENguyenError::compute("Compute dispatch never signaled").caused_by(e))?;
+        let done = Instant::now();
+        dispatch_ms.push(millis(done.duration_since(tex.submitted)));
+        latency_ms.push(millis(tex.backlog + done.duration_since(tex.captured)));
+    }
+    let seconds = millis(start.elapsed()) / 1000.0;
+    let frames = dispatch_ms.len() as u64;
+    info!("Benchmark processed {} frames in {:.2}s", frames, seconds);
+
+    Ok(BenchReport {
+        version: env!("CARGO_PKG_VERSION"),
+        seconds,
+        frames,
+        analysis_rate: frames as f64 / seconds,
+        gpu_dispatch_ms: Summary::of(&mut dispatch_ms),
+        latency_ms: Summary::of(&mut latency_ms),
+        frame_pacing: None,
+    })
+}
+
+/// A second of square wave, generated up front and written again and again as fast as the
+/// analysis reads it, so the analysis rather than real time sets the pace
+struct Flood {
+    samples: Arc<Vec<i16>>,
+    source: SimpleSource,
+    state: RingState,
+    killed: Arc<AtomicBool>,
+    capture: Option<CaptureHandle>,
+}
+
+impl Flood {
+    fn new() -> Flood {
+        let half_period = (FLOOD_RATE / FLOOD_HZ / 2) as usize;
+        let level = i16::max_value() / 2;
+        let samples = (0..FLOOD_RATE as usize)
+            .map(|frame| if frame / half_period % 2 == 0 { level } else { -level })
+            .flat_map(|sample| vec![sample, sample])
+            .collect();
+        Flood {
+            samples: Arc::new(samples),
+            source: SimpleSource::stereo("flood", FLOOD_RATE),
+            state: RingState::BORN,
+            killed: Arc::new(AtomicBool::new(false)),
+            capture: None,
+        }
+    }
+}
+
+impl AudioStream for Flood {
+    fn connect(&mut self) -> Result<RingState, ENguyenError> {
+        self.state = RingState::CONNECTED;
+        Ok(self.state)
+    }
+
+    fn heat(&mut self) -> Result<(FrameReader<i16>, SimpleSource), ENguyenError> {
+        let channels = self.source.channels();
+        let policy = OverflowPolicy::BlockWriter;
+        let (tx, rx) = RingFrames::<i16>::with_policy(FLOOD_FRAMES, channels, policy);
+        let samples = self.samples.clone();
+        let killed = self.killed.clone();
+        self.capture = Some(thread::spawn(move || {
+            // each write waits for the analysis to make room, and gives up once it's gone
+            while !killed.load(Ordering::Relaxed) {
+                tx.write_frames(&samples);
+            }
+            Ok(())
+        }));
+        self.state = RingState::HOT;
+        Ok((rx, self.source.clone()))
+    }
+
+    fn chill(&mut self) -> Result<(RingState, CaptureHandle), ENguyenError> {
+        self.killed.store(true, Ordering::Relaxed);
+        let capture = self.capture.take().ok_or_else(|| ENguyenError::audio("Never heated"))?;
+        self.state = RingState::DEAD;
+        Ok((self.state, capture))
+    }
+
+    fn state(&self) -> RingState {
+        self.state
+    }
+}
+
+pub fn millis(d: Duration) -> f64 {
+    d.as_secs() as f64 * 1000.0 + f64::from(d.subsec_nanos()) / 1_000_000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary_statistics() {
+        let mut samples: Vec<f64> = (1..=100).map(|i| i as f64).collect();
+        let summary = Summary::of(&mut samples);
+        assert_eq!(summary.min, 1.0);
+        assert_eq!(summary.max, 100.0);
+        assert_eq!(summary.mean, 50.5);
+        assert_eq!(summary.p95, 95.0);
+    }
+
+    #[test]
+    fn bench_synthetic_source() {
+        let picker = GpuPicker::new(false).unwrap();
+        let report = run(&picker, Duration::from_secs(1)).unwrap();
+        assert!(report.frames > 0);
+    }
+}
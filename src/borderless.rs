@@ -0,0 +1,209 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! Moving and resizing the visualizer when it has no decorations.  The strip along the top
+//! moves it, as does dragging anywhere with a modifier key held, and the edges resize it.
+//! Positions are logical pixels from the window's top left.  Wayland compositors ignore
+//! windows placing themselves, so there only resizing works.
+
+use crate::input::{MousePos, UserEvent};
+
+use winit::MouseCursor;
+
+/// Height of the strip along the top that moves the window
+static MOVE_STRIP: f64 = 24.0;
+/// Width of the edges that resize the window
+static EDGE: f64 = 6.0;
+/// The window isn't resized smaller than this
+static MIN_SIZE: [f64; 2] = [160.0, 90.0];
+
+/// What a drag from some point does to the window
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Grip {
+    Move,
+    /// Which edges follow the pointer
+    Resize {
+        left: bool,
+        right: bool,
+        top: bool,
+        bottom: bool,
+    },
+}
+
+impl Grip {
+    /// What `pos` grabs of a window `size` large, with a modifier key held if `modified`
+    pub fn at(pos: [f64; 2], size: [f64; 2], modified: bool) -> Option<Grip> {
+        if modified {
+            return Some(Grip::Move);
+        }
+        let [x, y] = pos;
+        let (left, right) = (x < EDGE, x >= size[0] - EDGE);
+        let (top, bottom) = (y < EDGE, y >= size[1] - EDGE);
+        if left || right || top || bottom {
+            Some(Grip::Resize { left, right, top, bottom })
+        } else if y < MOVE_STRIP {
+            Some(Grip::Move)
+        } else {
+            None
+        }
+    }
+
+    /// The pointer shown over the grip
+    pub fn cursor(self) -> MouseCursor {
+        match self {
+            Grip::Move => MouseCursor::Move,
+            Grip::Resize { left, right, top, bottom } => match (left || right, top || bottom) {
+                (true, false) => MouseCursor::EwResize,
+                (false, true) => MouseCursor::NsResize,
+                _ if (left && top) || (right && bottom) => MouseCursor::NwseResize,
+                _ => MouseCursor::NeswResize,
+            },
+        }
+    }
+}
+
+/// How far to move and resize the window, logical pixels
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Reshape {
+    pub moved: [f64; 2],
+    pub resized: [f64; 2],
+}
+
+/// Follows drags of an undecorated window
+pub struct WindowDrag {
+    /// What was grabbed and where, while dragging
+    held: Option<(Grip, [f64; 2])>,
+    /// The pointer shown, to only change it when it should look different
+    cursor: MouseCursor,
+}
+
+impl WindowDrag {
+    pub fn new() -> WindowDrag {
+        WindowDrag { held: None, cursor: MouseCursor::Default }
+    }
+
+    /// Follows `event` over a window `size` large.  True if the event was used, with how to
+    /// reshape the window.
+    pub fn handle(&mut self, event: &UserEvent, size: [f64; 2]) -> (bool, Option<Reshape>) {
+        match event {
+            UserEvent::MouseDown { pos: MousePos::Pos { x, y } } => {
+                self.held = Grip::at([*x, *y], size, false).map(|grip| (grip, [*x, *y]));
+                (self.held.is_some(), None)
+            },
+            UserEvent::ModifiedMouseDown { pos: MousePos::Pos { x, y } } => {
+                self.held = Some((Grip::Move, [*x, *y]));
+                (true, None)
+            },
+            UserEvent::MouseMove { pos: MousePos::Pos { x, y } } => match self.held {
+                Some((grip, from)) => (true, Some(self.drag(grip, from, [*x, *y], size))),
+                None => (false, None),
+            },
+            UserEvent::MouseUp { .. } => (self.held.take().is_some(), None),
+            _ => (false, None),
+        }
+    }
+
+    /// The pointer to show at `pos` if it changed since last asked
+    pub fn hover(&mut self, pos: [f64; 2], size: [f64; 2]) -> Option<MouseCursor> {
+        let grip = self.held.map(|(grip, _)| grip).or_else(|| Grip::at(pos, size, false));
+        let cursor = grip.map_or(MouseCursor::Default, Grip::cursor);
+        if cursor == self.cursor {
+            return None;
+        }
+        self.cursor = cursor;
+        Some(cursor)
+    }
+
+    fn drag(&mut self, grip: Grip, from: [f64; 2], to: [f64; 2], size: [f64; 2]) -> Reshape {
+        let mut reshape = Reshape::default();
+        let (left, right, top, bottom) = match grip {
+            Grip::Move => {
+                // the window follows, so what was grabbed stays under the pointer
+                reshape.moved = [to[0] - from[0], to[1] - from[1]];
+                return reshape;
+            },
+            Grip::Resize { left, right, top, bottom } => (left, right, top, bottom),
+        };
+        let mut grabbed = from;
+        for (axis, (start, end)) in [(left, right), (top, bottom)].iter().enumerate() {
+            let delta = to[axis] - from[axis];
+            let room = size[axis] - MIN_SIZE[axis];
+            if *start {
+                // the far edge stays put as the window moves
+                let delta = delta.min(room);
+                reshape.moved[axis] = delta;
+                reshape.resized[axis] = -delta;
+            } else if *end {
+                let delta = delta.max(-room);
+                reshape.resized[axis] = delta;
+                grabbed[axis] += delta;
+            }
+        }
+        self.held = Some((grip, grabbed));
+        reshape
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(x: f64, y: f64) -> MousePos {
+        MousePos::Pos { x, y }
+    }
+
+    #[test]
+    fn grips() {
+        let size = [800.0, 600.0];
+        assert_eq!(Grip::at([400.0, 10.0], size, false), Some(Grip::Move));
+        assert_eq!(Grip::at([400.0, 300.0], size, false), None);
+        assert_eq!(Grip::at([400.0, 300.0], size, true), Some(Grip::Move));
+        let corner = Grip::at([798.0, 599.0], size, false).unwrap();
+        let expected = Grip::Resize { left: false, right: true, top: false, bottom: true };
+        assert_eq!(corner, expected);
+        assert_eq!(corner.cursor(), MouseCursor::NwseResize);
+        assert_eq!(Grip::at([2.0, 300.0], size, false).unwrap().cursor(), MouseCursor::EwResize);
+    }
+
+    #[test]
+    fn drags_reshape() {
+        let size = [800.0, 600.0];
+        let mut drag = WindowDrag::new();
+        // a modifier drag in the middle moves the window along with the pointer
+        assert!(drag.handle(&UserEvent::ModifiedMouseDown { pos: at(400.0, 300.0) }, size).0);
+        let (used, reshape) = drag.handle(&UserEvent::MouseMove { pos: at(410.0, 295.0) }, size);
+        assert!(used);
+        assert_eq!(reshape.unwrap().moved, [10.0, -5.0]);
+        assert!(drag.handle(&UserEvent::MouseUp { pos: at(410.0, 295.0) }, size).0);
+        assert!(!drag.handle(&UserEvent::MouseMove { pos: at(420.0, 300.0) }, size).0);
+
+        // the right edge follows the pointer, but not below the smallest size
+        drag.handle(&UserEvent::MouseDown { pos: at(798.0, 300.0) }, size);
+        let (_, reshape) = drag.handle(&UserEvent::MouseMove { pos: at(818.0, 300.0) }, size);
+        assert_eq!(reshape.unwrap().resized, [20.0, 0.0]);
+        let size = [820.0, 600.0];
+        let (_, reshape) = drag.handle(&UserEvent::MouseMove { pos: at(0.0, 300.0) }, size);
+        assert_eq!(reshape.unwrap().resized, [-660.0, 0.0]);
+        drag.handle(&UserEvent::MouseUp { pos: MousePos::NoPos }, size);
+
+        // the left edge moves the window and keeps the right edge in place
+        drag.handle(&UserEvent::MouseDown { pos: at(2.0, 300.0) }, size);
+        let (_, reshape) = drag.handle(&UserEvent::MouseMove { pos: at(-18.0, 300.0) }, size);
+        assert_eq!(reshape.unwrap(), Reshape { moved: [-20.0, 0.0], resized: [20.0, 0.0] });
+        // clicks away from the edges and the strip are left to the scenes
+        drag.handle(&UserEvent::MouseUp { pos: MousePos::NoPos }, size);
+        assert!(!drag.handle(&UserEvent::MouseDown { pos: at(400.0, 300.0) }, size).0);
+    }
+}
@@ -0,0 +1,95 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! Noise floor calibration.  With nothing playing, the configured source is analyzed for a
+//! few seconds and the level each band rarely rises above is kept as its floor.
+
+use crate::compute::{AudioTexSource, AudioTexTap};
+use crate::config::ENguyenConfig;
+use crate::errors::ENguyenError;
+use crate::ewin::GpuPicker;
+use crate::params::ParamBus;
+
+use log::info;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, Instant};
+
+/// How long the settings window listens for
+pub static LISTEN: Duration = Duration::from_secs(3);
+/// Share of windows at or under the floor.  The rest are spikes of noise it lets through.
+static FLOOR_PERCENTILE: f32 = 0.9;
+
+/// Listens to `config`'s source for `duration`, with its gain, and returns each band's floor
+pub fn measure(
+    picker: &GpuPicker,
+    config: &ENguyenConfig,
+    duration: Duration,
+) -> Result<[f32; 4], ENguyenError> {
+    let (device, queue) = picker.headless_compute()?;
+    // measured from silence up, whatever floor is configured now
+    let mut params = config.parameters();
+    params.noise_floor = [0.0; 4];
+    let bus = ParamBus::new(params);
+    let mut source = AudioTexSource::new(1024)?;
+    source.watch_params(&bus);
+    source.configure(config);
+    let tap = AudioTexTap::turn_on(source, device, queue)?;
+
+    let mut windows = Vec::new();
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        match tap.tap.recv_timeout(Duration::from_secs(1)) {
+            Ok(tex) => windows.push(tex.features.bands),
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(ENguyenError::audio("Analysis stopped during calibration"));
+            },
+        }
+    }
+    info!("Calibrated from {} windows", windows.len());
+    floor_of(&windows).ok_or_else(|| ENguyenError::audio("Nothing was heard to calibrate from"))
+}
+
+/// Each band's `FLOOR_PERCENTILE` level across `windows`
+fn floor_of(windows: &[[f32; 4]]) -> Option<[f32; 4]> {
+    if windows.is_empty() {
+        return None;
+    }
+    let mut floor = [0.0; 4];
+    for (band, floor) in floor.iter_mut().enumerate() {
+        let mut levels: Vec<f32> = windows.iter().map(|bands| bands[band]).collect();
+        levels.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        *floor = levels[((levels.len() - 1) as f32 * FLOOR_PERCENTILE) as usize];
+    }
+    Some(floor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn floors_ignore_spikes() {
+        let rising = |i: u32| [0.1, 0.2, i as f32 / 100.0, 0.0];
+        let mut windows: Vec<[f32; 4]> = (0..20).map(rising).collect();
+        windows[3] = [1.0; 4];
+        let floor = floor_of(&windows).unwrap();
+        assert_eq!(floor[0], 0.1);
+        assert_eq!(floor[1], 0.2);
+        assert_eq!(floor[2], 0.18);
+        assert_eq!(floor[3], 0.0);
+        assert_eq!(floor_of(&[]), None);
+    }
+}
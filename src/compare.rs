@@ -0,0 +1,190 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! A/B comparison.  A second source is captured and analyzed alongside the first, and the
+//! scroll scene draws its spectrogram under or over the main one's, scrolling with it.
+
+use crate::compute::{AudioTex, AudioTexSource, AudioTexTap};
+use crate::config::{CompareView, ENguyenConfig};
+use crate::errors::ENguyenError;
+use crate::params::{Palette, ParamBus};
+use crate::rendering::{over_blending, PipelineRegistry, XyUvVertex};
+use crate::scenes::{spectrogram_pipeline, SceneFrame, Spectrogram};
+use crate::studio::viewed;
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Instant;
+use vulkano::buffer::ImmutableBuffer;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DynamicState};
+use vulkano::descriptor::descriptor_set::PersistentDescriptorSet;
+use vulkano::device::Queue;
+use vulkano::pipeline::blend::{AttachmentBlend, BlendFactor};
+use vulkano::sampler::Sampler;
+use vulkano::sync::GpuFuture;
+
+/// The compared source's analysis and spectrogram, with the main one's to draw beside it
+pub struct Comparison {
+    tap: AudioTexTap,
+    /// Windows analyzed but not yet due, held back for the output's latency
+    held: VecDeque<AudioTex>,
+    /// The history column written last
+    newest: u32,
+    main: Spectrogram,
+    compared: Spectrogram,
+    view: CompareView,
+    palette: Palette,
+}
+
+impl Comparison {
+    /// Starts analyzing the source `config.compare` names, drawn beside `main`.  None if it
+    /// names none.
+    pub fn start(
+        config: &ENguyenConfig,
+        params: &ParamBus,
+        queue: &Arc<Queue>,
+        pipelines: &PipelineRegistry,
+        sampler: Arc<Sampler>,
+        rect: Arc<ImmutableBuffer<[XyUvVertex]>>,
+        main: Spectrogram,
+    ) -> Result<Option<Comparison>, ENguyenError> {
+        if config.compare.source.is_none() {
+            return Ok(None);
+        }
+        let mut source = AudioTexSource::new(1024)?;
+        source.watch_params(params);
+        source.configure_compared(config);
+        let tap = AudioTexTap::turn_on(source, queue.device().clone(), queue.clone())?;
+
+        let view = config.compare.view;
+        let (name, blend) = match view {
+            CompareView::Split => ("compared spectrogram", over_blending()),
+            CompareView::Overlay => {
+                // both sources' colors show where they overlap
+                let additive = AttachmentBlend {
+                    color_destination: BlendFactor::One,
+                    alpha_destination: BlendFactor::One,
+                    ..over_blending()
+                };
+                ("overlaid spectrogram", additive)
+            },
+        };
+        let pipeline = spectrogram_pipeline(pipelines, name, blend)?;
+        let set = Arc::new(
+            PersistentDescriptorSet::start(pipeline.clone(), 1)
+                .add_sampled_image(tap.history.image(), sampler)
+                .map_err(|e| ENguyenError::render("Spectrogram descriptor failed").caused_by(e))?
+                .build()
+                .map_err(|e| ENguyenError::render("Spectrogram descriptor failed").caused_by(e))?,
+        );
+        let compared = Spectrogram::new(pipeline, set, rect, config.analysis.layout);
+        let palette = config.compare.palette;
+        let held = VecDeque::new();
+        Ok(Some(Comparison { tap, held, newest: 0, main, compared, view, palette }))
+    }
+
+    /// Whether the main spectrogram is drawn in the upper half of the window
+    pub fn splits(&self) -> bool {
+        self.view == CompareView::Split
+    }
+
+    /// Pause or resume the compared source's analysis with the main one's
+    pub fn pause(&self, paused: bool) {
+        self.tap.pause(paused);
+    }
+
+    /// Why the compared source's analysis stopped, once it has
+    pub fn failure(&mut self) -> Option<ENguyenError> {
+        self.tap.failure()
+    }
+
+    /// Takes the next window due by `now`.  The frame must wait on the future returned before
+    /// sampling its column.
+    pub fn due(&mut self, now: Instant) -> Option<Box<dyn GpuFuture + Send + Sync>> {
+        self.held.extend(self.tap.tap.try_iter());
+        if !self.held.front().map_or(false, |r| r.captured + r.delay <= now) {
+            return None;
+        }
+        let ready = self.held.pop_front()?;
+        self.newest = ready.column;
+        Some(ready.ready)
+    }
+
+    /// Records both spectrograms over `frame`'s viewport, the compared one `behind` its
+    /// newest column as the main one is
+    pub fn draw(
+        &self,
+        mut cbb: AutoCommandBufferBuilder,
+        frame: &SceneFrame,
+        behind: u32,
+    ) -> Result<AutoCommandBufferBuilder, ENguyenError> {
+        let history = &self.tap.history;
+        let compared = SceneFrame {
+            painter: frame.painter,
+            state: frame.state,
+            uniforms: frame.uniforms.clone(),
+            features: frame.features,
+            palette: self.palette,
+            tonemap: frame.tonemap,
+            scroll: history.scroll(history.back_from(self.newest, behind)),
+            span: history.span(),
+        };
+        match self.view {
+            CompareView::Split => {
+                let [above, below] = match halves(frame.state) {
+                    Some(halves) => halves,
+                    None => return Ok(cbb),
+                };
+                cbb = self.main.draw(cbb, &above, frame)?;
+                self.compared.draw(cbb, &below, &compared)
+            },
+            CompareView::Overlay => {
+                cbb = self.main.draw(cbb, frame.state, frame)?;
+                self.compared.draw(cbb, frame.state, &compared)
+            },
+        }
+    }
+}
+
+/// The upper and lower halves of the viewport in `full`.  None before the swapchain has a
+/// size.
+fn halves(full: &DynamicState) -> Option<[DynamicState; 2]> {
+    let viewport = full.viewports.as_ref()?.first()?;
+    let [x, y] = viewport.origin;
+    let [width, height] = viewport.dimensions;
+    let half = height / 2.0;
+    Some([viewed([x, y], [width, half]), viewed([x, y + half], [width, half])])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vulkano::pipeline::viewport::Viewport;
+
+    #[test]
+    fn split_stacks_the_sources() {
+        let viewport =
+            Viewport { origin: [0.0, 0.0], dimensions: [800.0, 600.0], depth_range: 0.0..1.0 };
+        let full =
+            DynamicState { line_width: None, viewports: Some(vec![viewport]), scissors: None };
+        let [above, below] = halves(&full).unwrap();
+        let above = above.viewports.unwrap().remove(0);
+        let below = below.viewports.unwrap().remove(0);
+        assert_eq!((above.origin, above.dimensions), ([0.0, 0.0], [800.0, 300.0]));
+        assert_eq!((below.origin, below.dimensions), ([0.0, 300.0], [800.0, 300.0]));
+        let no_viewport = DynamicState { line_width: None, viewports: None, scissors: None };
+        assert!(halves(&no_viewport).is_none());
+    }
+}
@@ -13,61 +13,316 @@
 
 // Copyright 2019 E-Nguyen Developers.
 
-use crate::audio::{AudioStream, PaStream};
+use crate::audio::{AudioStream, OutputLatency, PaStream, SourceChoice, Square4kHz};
+use crate::beat::BeatTracker;
+use crate::config::{
+    AnalysisConfig, AudioBackend, ENguyenConfig, ExportConfig, HistoryFormat, SpectrumLayout,
+    WindowFunction,
+};
+use crate::errors::{panic_message, ENguyenError, Retry};
+use crate::ewin;
+use crate::ewin::{GpuPicker, Queues};
+use crate::export::Exporter;
+use crate::features::{level, AudioFeatures};
+use crate::metadata::MetadataWatch;
+use crate::params::{Palette, ParamBus};
+use crate::recording::Recorder;
+use crate::replay::WavReplay;
+use crate::ring::OverflowPolicy;
+use crate::scrolling::ScrollingTexture;
+use crate::shader_reload;
+use crate::shader_reload::ShaderWatch;
+use crate::tracks::TrackDetector;
+use crate::upload;
 
-use byteorder::ByteOrder;
-use byteorder::LittleEndian as Le;
-use bytes::buf::BufMut;
-use bytes::BytesMut;
+use log::{debug, info, warn};
 use rustfft::num_complex::Complex;
 use rustfft::num_traits::Zero;
-use rustfft::FFTplanner;
+use rustfft::{FFTplanner, FFT};
 use std::boxed::Box;
-use std::error::Error;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc;
 use std::sync::Arc;
 use std::thread;
 use std::thread::JoinHandle;
-use std::time;
-use vulkano::buffer::{BufferUsage, CpuBufferPool};
-use vulkano::command_buffer::AutoCommandBufferBuilder;
-use vulkano::descriptor::descriptor_set::PersistentDescriptorSet;
+use std::time::{Duration, Instant};
+use vulkano::buffer::{
+    BufferAccess, BufferUsage, CpuAccessibleBuffer, CpuBufferPool, ImmutableBuffer,
+};
+use vulkano::command_buffer::{AutoCommandBuffer, AutoCommandBufferBuilder, CommandBuffer};
+use vulkano::descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet};
 use vulkano::device::{Device, Queue};
-use vulkano::format::Format;
+use vulkano::format::{ClearValue, Format};
 use vulkano::image::{Dimensions, ImageUsage, StorageImage};
-use vulkano::pipeline::ComputePipeline;
+use vulkano::pipeline::shader::ShaderStages;
+use vulkano::pipeline::{ComputePipeline, ComputePipelineAbstract};
 use vulkano::sync;
 use vulkano::sync::GpuFuture;
 
-/// The compute module provides processed audio in the form of a channel of textures
+/// How long the compute thread parks on the ring before re-checking for shutdown
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// How many frames in a row can fail to dispatch before the analysis thread gives up
+const DISPATCH_RETRIES: u32 = 30;
+
+/// How often parked analysis checks whether it's wanted again
+const PARK_POLL: Duration = Duration::from_millis(50);
+
+/// How often the captured output's latency is asked for again
+const OUTPUT_POLL: Duration = Duration::from_secs(5);
+
+/// Recorded dispatches reused in turn.  More windows than this in flight are recorded afresh.
+const DISPATCH_SLOTS: usize = 4;
+
+/// How the spectrum is computed, from the `[analysis]` and `[audio]` config sections
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnalysisOptions {
+    pub fft_size: usize,
+    /// Frames of audio in each FFT.  The rest of the FFT is zero padded.
+    pub sample_window: usize,
+    pub window: WindowFunction,
+    /// Lowest frequency drawn, Hz
+    pub min_freq: f64,
+    /// Highest frequency drawn, Hz
+    pub max_freq: f64,
+    /// Whether the channels are combined or kept apart in the texture
+    pub layout: SpectrumLayout,
+    /// Columns of the `ScrollingTexture` shown at once
+    pub history: u32,
+    /// Columns kept beyond those shown
+    pub review: u32,
+    /// Texel format of the history
+    pub history_format: HistoryFormat,
+    pub backend: AudioBackend,
+    /// Capture latency target, milliseconds
+    pub latency_ms: Option<u32>,
+    /// What the replay backend plays
+    pub replay: Option<PathBuf>,
+    /// What capture does when analysis falls behind
+    pub overflow: OverflowPolicy,
+    /// Hold windows back by the captured output's reported latency
+    pub detect_output_delay: bool,
+    /// Milliseconds to hold windows back for each output, by sink name
+    pub output_delays: BTreeMap<String, u32>,
+    /// Where each window's features are sent
+    pub export: ExportConfig,
+    /// Measure `AudioFeatures::readout`
+    pub readout: bool,
+    /// Colors in place of the published palette's
+    pub palette: Option<Palette>,
+}
+
+impl Default for AnalysisOptions {
+    fn default() -> AnalysisOptions {
+        AnalysisOptions::from_config(&ENguyenConfig::default())
+    }
+}
+
+impl AnalysisOptions {
+    /// Values that can't be analyzed are replaced with a warning
+    pub fn from_config(config: &ENguyenConfig) -> AnalysisOptions {
+        let (analysis, audio) = (&config.analysis, &config.audio);
+        let mut fft_size = analysis.fft_size;
+        if fft_size < MIN_FFT_SIZE {
+            warn!("analysis.fft_size {} is too small.  Using {}.", fft_size, MIN_FFT_SIZE);
+            fft_size = MIN_FFT_SIZE;
+        }
+        let sample_window = audio.sample_window.max(1).min(fft_size);
+        if sample_window != audio.sample_window {
+            let range = "audio.sample_window must be 1 to analysis.fft_size";
+            warn!("{}.  Using {}.", range, sample_window);
+        }
+        let (min_freq, max_freq) = frequency_range(analysis.min_freq, analysis.max_freq);
+        // windows held back for the output's latency are written ahead of the view
+        let most = MAX_HISTORY - HELD_COLUMNS;
+        let history = analysis.history.max(1).min(most);
+        if history != analysis.history {
+            warn!("analysis.history must be 1 to {}.  Using {}.", most, history);
+        }
+        let wanted = (analysis.review_seconds.max(0.0) * ANALYSIS_RATE as f32) as u32;
+        let review = wanted.min(most - history);
+        if review != wanted {
+            let seconds = review as f32 / ANALYSIS_RATE as f32;
+            warn!("analysis.review_seconds is more than the history fits.  Using {}.", seconds);
+        }
+        AnalysisOptions {
+            fft_size,
+            sample_window,
+            window: analysis.window,
+            min_freq,
+            max_freq,
+            layout: analysis.layout,
+            history,
+            review,
+            history_format: analysis.history_format,
+            backend: audio.backend,
+            latency_ms: audio.latency_ms,
+            replay: audio.replay.clone(),
+            overflow: audio.overflow,
+            detect_output_delay: audio.detect_output_delay,
+            output_delays: audio.output_delay_ms.clone(),
+            export: config.export.clone(),
+            readout: config.video.frequency_ruler,
+            palette: None,
+        }
+    }
+}
+
+/// The compute module provides processed audio as columns of the tap's `ScrollingTexture`
 /// and their futures.  Implement as an AudioTexTap that provides a stream of AudioTex.
 
-/// Consume these by memory barrier synchronization and copy (or use directly)
+/// One analysis window.  Wait on `ready` before sampling its column.
 pub struct AudioTex {
-    pub buffer: Arc<StorageImage<Format>>,
+    /// The column of the history this window was written to
+    pub column: u32,
     pub ready: Box<dyn GpuFuture + Send + Sync>,
+    /// When the newest audio in this texture left the ring
+    pub captured: Instant,
+    /// When the dispatch that writes it was submitted
+    pub submitted: Instant,
+    /// How stale the ring's oldest audio was when it was read
+    pub backlog: Duration,
+    /// Audio frames the ring has discarded since capture started
+    pub dropped_frames: usize,
+    /// How long after `captured` to draw this window, so it's seen as the output plays it
+    pub delay: Duration,
+    /// Frequencies of the history's lowest and highest rows, Hz
+    pub range: [f32; 2],
+    /// When the analysis first had a full window of audio, since starting or resuming.  Later
+    /// windows are real audio rather than the silence it began with.
+    pub warm_since: Instant,
+    /// Levels and waveform of the same window
+    pub features: AudioFeatures,
+    /// From sources that split channels
+    pub channels: Option<ChannelImages>,
 }
 
+/// Left and right levels, a 1×H image each
+pub type ChannelImages = [Arc<StorageImage<Format>>; 2];
+
 pub struct AudioTexSource {
     tex_height: usize,
     bins: usize,
+    stream: Option<Box<dyn AudioStream + Send>>,
+    choice: SourceChoice,
+    options: AnalysisOptions,
+    params: Option<ParamBus>,
+    split: bool,
+    recorder: Option<Recorder>,
+    metadata: Option<MetadataWatch>,
 }
 
 impl AudioTexSource {
-    pub fn new(height: usize) -> Result<AudioTexSource, Box<dyn Error>> {
+    pub fn new(height: usize) -> Result<AudioTexSource, ENguyenError> {
         let padded_bins = height * 2;
         let tex_height = height;
-        Ok(AudioTexSource { tex_height: height, bins: padded_bins })
+        Ok(AudioTexSource {
+            tex_height: height,
+            bins: padded_bins,
+            stream: None,
+            choice: SourceChoice::default(),
+            options: AnalysisOptions::default(),
+            params: None,
+            split: false,
+            recorder: None,
+            metadata: None,
+        })
+    }
+
+    /// Capture and analyze as `config` says
+    pub fn configure(&mut self, config: &ENguyenConfig) {
+        self.choice = config.source_choice();
+        self.options = AnalysisOptions::from_config(config);
+    }
+
+    /// Capture and analyze the source `config.compare` names instead, in the compared palette.
+    /// Nothing is exported from it.
+    pub fn configure_compared(&mut self, config: &ENguyenConfig) {
+        self.configure(config);
+        let name = config.compare.source.clone();
+        self.choice = SourceChoice { name, index: None, follow_default: false };
+        self.options.export = ExportConfig::default();
+        self.options.readout = false;
+        self.options.palette = Some(config.compare.palette);
+    }
+
+    /// Draw `min_freq` to `max_freq` Hz instead of the configured range
+    pub fn frequency_range(&mut self, min_freq: f64, max_freq: f64) {
+        let (min_freq, max_freq) = frequency_range(min_freq, max_freq);
+        self.options.min_freq = min_freq;
+        self.options.max_freq = max_freq;
+    }
+
+    /// Also write each window's left and right levels into images of their own, for scenes
+    /// that draw the channels apart
+    pub fn split_channels(&mut self) {
+        self.split = true;
+    }
+
+    /// Follow gain, smoothing and palette changes published on `bus`
+    pub fn watch_params(&mut self, bus: &ParamBus) {
+        self.params = Some(bus.clone());
+    }
+
+    /// Tee captured audio into whatever `recorder` is recording
+    pub fn record_to(&mut self, recorder: &Recorder) {
+        self.recorder = Some(recorder.clone());
+    }
+
+    /// Publish track changes from `metadata` instead of watching the players separately
+    pub fn follow_tracks(&mut self, metadata: &MetadataWatch) {
+        self.metadata = Some(metadata.clone());
+    }
+
+    /// Analyze `stream` instead of the default sound server monitor
+    pub fn with_stream(
+        height: usize,
+        stream: Box<dyn AudioStream + Send>,
+    ) -> Result<AudioTexSource, ENguyenError> {
+        let mut source = AudioTexSource::new(height)?;
+        source.stream = Some(stream);
+        Ok(source)
     }
 }
 
 /// This trait describes a source of audio textures that renderers
 /// can tap into for use in drawing things that are Nguyen
 pub struct AudioTexTap {
-    hot_handle: Option<JoinHandle<()>>,
+    hot_handle: Option<JoinHandle<Result<(), ENguyenError>>>,
     killed: Arc<AtomicBool>,
+    finished: Arc<AtomicBool>,
+    /// Raised while nothing draws the analysis
+    parked: Arc<AtomicBool>,
+    /// Raised while the user has paused analysis, exports and all
+    paused: Arc<AtomicBool>,
+    restart: Restart,
     pub tap: mpsc::Receiver<AudioTex>,
+    /// Every window's column, kept across restarts
+    pub history: Arc<ScrollingTexture>,
+}
+
+/// What it takes to start the analysis thread over
+struct Restart {
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    tex_height: usize,
+    choice: SourceChoice,
+    options: AnalysisOptions,
+    params: Option<ParamBus>,
+    split: bool,
+    recorder: Option<Recorder>,
+    metadata: Option<MetadataWatch>,
+}
+
+/// Raised when the thread holding it exits, whether it returned or panicked
+struct ExitFlag(Arc<AtomicBool>);
+
+impl Drop for ExitFlag {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
 }
 
 impl AudioTexTap {
@@ -75,155 +330,851 @@ impl AudioTexTap {
         source: AudioTexSource,
         device: Arc<Device>,
         compute_queue: Arc<Queue>,
-    ) -> Result<AudioTexTap, Box<dyn Error>> {
+    ) -> Result<AudioTexTap, ENguyenError> {
+        let shown = source.options.history;
+        let columns = shown + source.options.review + HELD_COLUMNS;
+        let rows = source.tex_height as u32;
+        let format = source.options.history_format;
+        let history = ScrollingTexture::with_format(&device, columns, shown, rows, format)?;
+        let history = Arc::new(history);
+        AudioTexTap::start(source, device, compute_queue, history)
+    }
+
+    /// Analyze into `history`
+    fn start(
+        source: AudioTexSource,
+        device: Arc<Device>,
+        compute_queue: Arc<Queue>,
+        history: Arc<ScrollingTexture>,
+    ) -> Result<AudioTexTap, ENguyenError> {
         let (tx, rx) = mpsc::channel();
         let killed = Arc::new(AtomicBool::new(false));
         let kill_watch = killed.clone();
+        let finished = Arc::new(AtomicBool::new(false));
+        let exit_flag = ExitFlag(finished.clone());
+        let parked = Arc::new(AtomicBool::new(false));
+        let park_watch = parked.clone();
+        let paused = Arc::new(AtomicBool::new(false));
+        let pause_watch = paused.clone();
+        let restart = Restart {
+            device: device.clone(),
+            queue: compute_queue.clone(),
+            tex_height: source.tex_height,
+            choice: source.choice.clone(),
+            options: source.options.clone(),
+            params: source.params.clone(),
+            split: source.split,
+            recorder: source.recorder.clone(),
+            metadata: source.metadata.clone(),
+        };
+        let written = history.clone();
 
-        let hot_handle = thread::spawn(move || {
-            let draw_log_scale = LogScale::new(source.tex_height, 220_f64, 22000_f64);
-            // let largest_bin = 64;
-            // let fft_log_scale = LogScale::new(source.tex_height / largest_bin, 80_f64, 22000_f64);
-            let lin_bins = 3000;
-
-            let mut left_input: Vec<Complex<f32>> = vec![Zero::zero(); lin_bins];
-            let mut right_input: Vec<Complex<f32>> = vec![Zero::zero(); lin_bins];
-            let mut output: Vec<Complex<f32>> = vec![Zero::zero(); lin_bins];
-
-            let mut planner = FFTplanner::new(false);
-            let fft = planner.plan_fft(lin_bins);
-            let fft_bufpool: CpuBufferPool<Complex<f32>> =
-                CpuBufferPool::new(device.clone(), BufferUsage::all());
-            let mut pastream = PaStream::default();
-            pastream.connect().unwrap();
-            let (rx, source_def) = pastream.heat().unwrap();
-            let byte_rate = source_def.byte_rate();
-            let target_bytes_per_frame = (byte_rate / 60) as usize;
-            let fft_byte_len: usize = lin_bins * 4; // Complex<f32>
-            let mut stream_buf =
-                BytesMut::with_capacity(target_bytes_per_frame * 6 + 32 * lin_bins);
-            let mut audio: Vec<i16> = vec![0; lin_bins * 2];
+        let hot_handle = thread::spawn(move || -> Result<(), ENguyenError> {
+            let _exit_flag = exit_flag;
+            let mut source = source;
+            let options = source.options.clone();
+            let lin_bins = options.fft_size;
+            let mut spectra = Spectra::new(&options);
 
-            let lin_fft_res = (source_def.rate / 2) as f64 / (lin_bins / 2) as f64; // Nyquist limit / nbins
+            let mut retry = Retry::new(DISPATCH_RETRIES);
 
-            let norm = 1.0 / (i16::max_value() as f32);
+            let mut stream: Box<dyn AudioStream + Send> = match source.stream.take() {
+                Some(stream) => stream,
+                None => match options.backend {
+                    AudioBackend::Pulse => {
+                        let stream = PaStream::select(&source.choice)?;
+                        let stream = stream.with_latency(options.latency_ms);
+                        Box::new(stream.with_overflow(options.overflow))
+                    },
+                    AudioBackend::Synthetic => {
+                        Box::new(Square4kHz::default().with_overflow(options.overflow))
+                    },
+                    AudioBackend::Replay => match &options.replay {
+                        Some(path) => {
+                            let replay = WavReplay::open(path, options.latency_ms)?;
+                            Box::new(replay.with_overflow(options.overflow))
+                        },
+                        None => {
+                            let missing = "audio.replay must name a WAV file to replay";
+                            return Err(ENguyenError::config(missing));
+                        },
+                    },
+                },
+            };
+            stream
+                .connect()
+                .map_err(|e| ENguyenError::audio("Audio connect failed").caused_by(e))?;
+            let (rx, source_def) = stream
+                .heat()
+                .map_err(|e| ENguyenError::audio("Audio capture failed to start").caused_by(e))?;
+            let target_frames_per_frame = (source_def.rate / ANALYSIS_RATE) as usize;
+            let window_len: usize = options.sample_window * 2; // interleaved stereo samples
+            let mut audio: Vec<i16> = vec![0; window_len];
+            let mut filled: usize = 0;
+            let mut warm_since: Option<Instant> = None;
+            let mut delay = Duration::default();
+            let mut output_polled: Option<Instant> = None;
+
+            let lin_fft_res = (source_def.rate / 2) as f64 / (lin_bins / 2) as f64; // Nyquist limit / nbins
+            let nyquist = source_def.rate as f64 / 2.0;
+            let (min_freq, max_freq) = below_nyquist(options.min_freq, options.max_freq, nyquist);
+            let draw_log_scale =
+                LogScale::new(source.tex_height, min_freq, max_freq, lin_bins, lin_fft_res);
 
             // compute an output texture and yield the AudioTex
-            let shader = channel_combine::Shader::load(device.clone()).unwrap();
-            let pipeline = Arc::new(
-                ComputePipeline::new(device.clone(), &shader.main_entry_point(), &()).unwrap(),
-            );
+            let mut dispatcher =
+                Dispatcher::new(device.clone(), compute_queue.clone(), &draw_log_scale, written)?;
+            dispatcher.split = source.split;
 
-            while !kill_watch.load(Ordering::Relaxed) {
-                let avail = rx.available();
+            let norm = 1.0 / (i16::max_value() as f32);
+            let mut param_watch = source.params.as_ref().map(|bus| bus.watch());
+            let mut params = match &param_watch {
+                Some(watch) => watch.current(),
+                None => Default::default(),
+            };
+            let mut beats = BeatTracker::new();
+            let mut tracks = TrackDetector::new();
+            let mut exporter = Exporter::from_config(&options.export, source.metadata.as_ref());
+            let spectrum_bins = options.export.spectrum_bins;
+            let spectrum = if exporter.is_empty() || spectrum_bins < 2 {
+                None
+            } else {
+                Some(LogScale::new(spectrum_bins, min_freq, max_freq, lin_bins, lin_fft_res))
+            };
+            let readout = if options.readout {
+                Some(LogScale::new(READOUT_BINS, min_freq, max_freq, lin_bins, lin_fft_res))
+            } else {
+                None
+            };
 
-                if avail < (target_bytes_per_frame * 2) {
-                    thread::sleep(time::Duration::from_micros(500));
+            while !kill_watch.load(Ordering::Relaxed) {
+                // exports still want analysis with nothing drawing it, unless paused
+                let exporting = !exporter.is_empty();
+                let held = || {
+                    pause_watch.load(Ordering::Relaxed)
+                        || park_watch.load(Ordering::Relaxed) && !exporting
+                };
+                if held() {
+                    let paused = pause_watch.load(Ordering::Relaxed);
+                    debug!("Analysis {}", if paused { "paused" } else { "parked" });
+                    stream.suspend(true);
+                    while held() && !kill_watch.load(Ordering::Relaxed) {
+                        thread::sleep(PARK_POLL);
+                    }
+                    stream.suspend(false);
+                    // whatever arrived while held is stale
+                    rx.read_frames(rx.available_frames());
+                    filled = 0;
+                    warm_since = None;
+                    debug!("Analysis resumed");
                     continue;
                 }
+                if let Some(changed) = param_watch.as_mut().and_then(|w| w.changed()) {
+                    params = changed;
+                }
+                let gain = norm * params.gain;
 
-                let mut to_consume = if avail > target_bytes_per_frame * 2 {
-                    target_bytes_per_frame
-                } else {
-                    continue;
+                // wakes as soon as a frame's worth of audio arrives, times out to check the kill.
+                // Low power waits for two frames' worth, analyzing half as often.
+                let backlog = rx.backlog();
+                let dropped_frames = rx.dropped_frames();
+                let wanted = target_frames_per_frame * if params.low_power { 2 } else { 1 };
+                let fresh = match rx.read_frames_at_least(wanted, READ_TIMEOUT) {
+                    Some(samples) => samples,
+                    None if rx.writer_alive() => continue,
+                    None => {
+                        // the capture thread dropped its writer.  Find out why.
+                        let stopped = ENguyenError::audio("Audio capture stopped");
+                        return Err(match stream.chill() {
+                            Ok((_, capture)) => match capture.join() {
+                                Ok(Ok(())) => stopped.caused_by("capture thread exited"),
+                                Ok(Err(e)) => stopped.caused_by(e),
+                                Err(payload) => stopped.caused_by(panic_message(&payload)),
+                            },
+                            Err(e) => stopped.caused_by(e),
+                        });
+                    },
                 };
-                to_consume -= to_consume % 4;
-
-                let fresh_bytes = rx.read(to_consume);
-                stream_buf.reserve(to_consume);
-                stream_buf.put(&fresh_bytes);
-                let fft_available = stream_buf.len();
-                if fft_available > fft_byte_len {
-                    stream_buf.advance(fft_available - fft_byte_len);
+                let captured = Instant::now();
+                if let Some(recorder) = &source.recorder {
+                    recorder.tee(&fresh, source_def.rate);
+                }
+                // a round trip to the sound server, rarely enough not to hold analysis up
+                if output_polled.map_or(true, |at| captured.duration_since(at) >= OUTPUT_POLL) {
+                    let output = stream.output_latency();
+                    let held = output_delay(output.as_ref(), &options);
+                    if held != delay {
+                        info!("Holding visuals back {}ms for {:?}", held.as_millis(), output);
+                        delay = held;
+                    }
+                    output_polled = Some(captured);
                 }
 
-                if stream_buf.len() < fft_byte_len {
+                slide(&mut audio, &fresh);
+                filled = (filled + fresh.len()).min(window_len);
+
+                if filled < window_len {
                     continue;
                 }
-
-                {
-                    Le::read_i16_into(&stream_buf.clone().split_to(fft_byte_len), &mut audio);
-                    let mut lc = left_input.iter_mut();
-                    let mut rc = right_input.iter_mut();
-                    for sample in audio.chunks_exact(2) {
-                        let normed = sample[1] as f32 * norm;
-                        *lc.next().unwrap() = Complex::new(normed, 0.0);
-                        let normed = sample[0] as f32 * norm;
-                        *rc.next().unwrap() = Complex::new(normed, 0.0);
-                    }
+                let warm = *warm_since.get_or_insert_with(|| {
+                    debug!("Analysis warm");
+                    captured
+                });
+                let mut features = AudioFeatures::measure(&audio, gain);
+                spectra.hear(&audio, gain, params.smoothing);
+                let (left, right, sine_scale) = (&spectra.left, &spectra.right, spectra.sine_scale);
+                let bin_hz = lin_fft_res as f32;
+                features.hear_pitches(left, right, bin_hz, sine_scale);
+                features.hear_bands(left, right, bin_hz, sine_scale);
+                features.above_floor(&params.noise_floor);
+                features.beat = beats.hear(features.bands[0].max(features.bands[1]), captured);
+                features.track_changed = tracks.hear(&features, captured);
+                if let Some(spectrum) = &spectrum {
+                    features.spectrum = spectrum.levels(left, right, sine_scale);
+                }
+                if let Some(readout) = &readout {
+                    features.readout = readout.levels(left, right, sine_scale);
                 }
+                let palette = options.palette.unwrap_or(params.palette);
+                exporter.record(&features, palette, captured);
 
-                fft.process(&mut left_input, &mut output);
-                let left_buffer = fft_bufpool.chunk(output.clone().into_iter()).unwrap();
-                fft.process(&mut right_input, &mut output);
-                let right_buffer = fft_bufpool.chunk(output.clone().into_iter()).unwrap();
-
-                let out_buf = StorageImage::with_usage(
-                    device.clone(),
-                    Dimensions::Dim2d { width: 1, height: source.tex_height as u32 },
-                    Format::R32G32B32A32Sfloat,
-                    ImageUsage { transfer_source: true, storage: true, ..ImageUsage::none() },
-                    vec![compute_queue.family()],
-                )
-                .unwrap();
-
-                let set = Arc::new(
-                    PersistentDescriptorSet::start(pipeline.clone(), 0)
-                        .add_buffer(left_buffer.clone())
-                        .unwrap()
-                        .add_buffer(right_buffer.clone())
-                        .unwrap()
-                        .add_image(out_buf.clone())
-                        .unwrap()
-                        .build()
-                        .unwrap(),
-                );
-
-                let push_constants = channel_combine::ty::PushConstant {
+                let [red, green, blue] = palette.weights();
+                let constants = channel_combine::ty::Constants {
                     lin_bins: lin_bins as u32,
-                    log_scale: draw_log_scale.log_bin_ratio as f32,
-                    lin_res: lin_fft_res as f32,
-                    min_freq: draw_log_scale.min_freq as f32,
-                    max_freq: draw_log_scale.max_freq as f32,
+                    red,
+                    green,
+                    blue,
+                    stereo: (options.layout == SpectrumLayout::Stereo) as u32,
+                    column: dispatcher.column,
+                    split: dispatcher.split as u32,
                 };
 
-                let cb = AutoCommandBufferBuilder::secondary_compute_simultaneous_use(
-                    device.clone(),
-                    compute_queue.family(),
-                )
-                .unwrap();
-
-                assert_eq!(source.tex_height as u32 % channel_combine::LOCAL_SIZE_X, 0);
-                let dispatch_x = source.tex_height as u32 / channel_combine::LOCAL_SIZE_X;
-                let cb = cb
-                    .dispatch([dispatch_x, 1, 1], pipeline.clone(), set.clone(), push_constants)
-                    .unwrap()
-                    .build()
-                    .unwrap();
-                let future =
-                    sync::now(device.clone()).then_execute(compute_queue.clone(), cb).unwrap();
-                let result = AudioTex { ready: Box::new(future), buffer: out_buf.clone() };
-                tx.send(result).unwrap();
+                dispatcher.reload();
+                let submitted = Instant::now();
+                let dispatched = dispatcher.dispatch(&spectra.left, &spectra.right, constants);
+                let (column, channels, future) = match dispatched {
+                    Ok(dispatched) => {
+                        retry.succeeded();
+                        dispatched
+                    },
+                    Err(e) => {
+                        if retry.failed() {
+                            let gave_up = ENguyenError::compute("Analysis dispatch keeps failing");
+                            return Err(gave_up.caused_by(e));
+                        }
+                        warn!("Analysis dispatch failed.  Skipping a frame: {}", e);
+                        continue;
+                    },
+                };
+                let result = AudioTex {
+                    column,
+                    ready: Box::new(future),
+                    captured,
+                    submitted,
+                    backlog,
+                    dropped_frames,
+                    delay,
+                    range: [min_freq as f32, max_freq as f32],
+                    warm_since: warm,
+                    features,
+                    channels,
+                };
+                if tx.send(result).is_err() {
+                    // nobody is listening anymore
+                    break;
+                }
+            }
+
+            // stop capture explicitly instead of leaving it to drop order.  A writer waiting on
+            // the ring for room gives up once the reader is gone.
+            drop(rx);
+            match stream.chill() {
+                Ok((_, capture)) => match capture.join() {
+                    Ok(Ok(())) => {},
+                    Ok(Err(e)) => warn!("Audio capture had stopped: {}", e),
+                    Err(payload) => warn!("Audio capture panicked: {}", panic_message(&payload)),
+                },
+                Err(e) => warn!("Audio capture was not running: {}", e),
             }
+            Ok(())
         });
 
-        Ok(AudioTexTap { killed, hot_handle: Some(hot_handle), tap: rx })
+        let hot_handle = Some(hot_handle);
+        let tap = rx;
+        Ok(AudioTexTap { killed, finished, parked, paused, restart, hot_handle, tap, history })
+    }
+
+    /// Why the analysis thread died, if it has.  Reported once.
+    pub fn failure(&mut self) -> Option<ENguyenError> {
+        if !self.finished.load(Ordering::Relaxed) || self.killed.load(Ordering::Relaxed) {
+            return None;
+        }
+        let handle = self.hot_handle.take()?;
+        match handle.join() {
+            Ok(Ok(())) => Some(ENguyenError::compute("Analysis thread exited")),
+            Ok(Err(reason)) => Some(reason),
+            Err(payload) => Some(ENguyenError::compute(panic_message(&payload))),
+        }
+    }
+
+    /// Pause analysis and capture while nothing draws them, or resume them.  Analysis that
+    /// feeds exports keeps running.
+    pub fn park(&self, parked: bool) {
+        self.parked.store(parked, Ordering::Relaxed);
+    }
+
+    /// Pause analysis and capture, exports included, until asked to resume
+    pub fn pause(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    /// Stops the analysis thread, which stops capture, and discards textures not yet drawn
+    pub fn shutdown(&mut self) {
+        self.killed.store(true, Ordering::Relaxed);
+        if let Some(hot) = self.hot_handle.take() {
+            match hot.join() {
+                Ok(Ok(())) => {},
+                Ok(Err(reason)) => warn!("Audio analysis had stopped: {}", reason),
+                Err(payload) => warn!("Audio analysis panicked: {}", panic_message(&payload)),
+            }
+        }
+        // dropping a texture waits on its dispatch
+        self.tap.try_iter().count();
+    }
+
+    /// Start a new analysis thread on the same device and history, capturing from the
+    /// configured source
+    pub fn restart(&mut self) -> Result<(), ENguyenError> {
+        let mut source = AudioTexSource::new(self.restart.tex_height)?;
+        source.choice = self.restart.choice.clone();
+        source.options = self.restart.options.clone();
+        source.params = self.restart.params.clone();
+        source.split = self.restart.split;
+        source.recorder = self.restart.recorder.clone();
+        source.metadata = self.restart.metadata.clone();
+        let device = self.restart.device.clone();
+        let queue = self.restart.queue.clone();
+        let parked = self.parked.load(Ordering::Relaxed);
+        let paused = self.paused.load(Ordering::Relaxed);
+        *self = AudioTexTap::start(source, device, queue, self.history.clone())?;
+        self.park(parked);
+        self.pause(paused);
+        Ok(())
+    }
+}
+
+/// An AudioTexTap kept running together with the device it dispatches on, so a window can
+/// start drawing without waiting on device creation or the sound server.
+pub struct WarmPipeline {
+    pub device: Arc<Device>,
+    pub queues: Queues,
+    pub tap: AudioTexTap,
+}
+
+impl WarmPipeline {
+    pub fn start(
+        picker: &GpuPicker,
+        height: usize,
+        params: &ParamBus,
+        recorder: &Recorder,
+        config: &ENguyenConfig,
+    ) -> Result<WarmPipeline, ENguyenError> {
+        let (device, queues) = picker.windowless_device()?;
+        let mut source = AudioTexSource::new(height)?;
+        source.watch_params(params);
+        source.record_to(recorder);
+        source.configure(config);
+        let tap = AudioTexTap::turn_on(source, device.clone(), queues.compute.clone())?;
+        Ok(WarmPipeline { device, queues, tap })
+    }
+
+    /// Discard textures nobody is drawing so they don't pile up between windows
+    pub fn drain(&self) -> usize {
+        self.tap.tap.try_iter().count()
+    }
+
+    /// Stop analysis and capture and wait for the device to finish
+    pub fn shutdown(mut self) {
+        self.tap.shutdown();
+        ewin::wait_idle(&self.device);
     }
 }
 
 impl Drop for AudioTexTap {
     fn drop(&mut self) {
-        self.killed.store(true, Ordering::Relaxed);
-        let mut handle: Option<JoinHandle<_>> = None;
-        std::mem::swap(&mut handle, &mut self.hot_handle);
-        if let Some(hot) = handle {
-            hot.join().unwrap();
+        self.shutdown();
+    }
+}
+
+/// Uploads both channels' spectra and runs the shader that combines them into the next column
+/// of the history
+struct Dispatcher {
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    pipeline: Arc<ComputePipelineAbstract + Send + Sync>,
+    bufpool: CpuBufferPool<Complex<f32>>,
+    constpool: CpuBufferPool<channel_combine::ty::Constants>,
+    /// Dispatches recorded with buffers of their own, reused while the GPU is done with them
+    slots: Vec<Slot>,
+    next_slot: usize,
+    tex_height: usize,
+    history: Arc<ScrollingTexture>,
+    /// Written by the next dispatch
+    column: u32,
+    /// Each window's channels are also written to images of their own
+    split: bool,
+    /// Bound in place of the channel images when they aren't written
+    unsplit: ChannelImages,
+    /// The log scale's bin table
+    spans: Arc<ImmutableBuffer<[Span]>>,
+    taps: Arc<ImmutableBuffer<[Tap]>>,
+    /// Edits to rebuild the pipeline from, with `--dev`
+    shaders: Option<ShaderWatch>,
+}
+
+impl Dispatcher {
+    /// Uploads `scale`'s bin table and waits for it.  `history` has a row per log bin.
+    fn new(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        scale: &LogScale,
+        history: Arc<ScrollingTexture>,
+    ) -> Result<Dispatcher, ENguyenError> {
+        let tex_height = scale.n_log_bins;
+        assert_eq!(tex_height as u32 % channel_combine::LOCAL_SIZE_X, 0);
+        assert_eq!(tex_height as u32, history.rows());
+        let pipeline = combine_pipeline(&device, history.format())?;
+        let usage = BufferUsage { storage_buffer: true, ..BufferUsage::none() };
+        let bufpool = CpuBufferPool::new(device.clone(), usage);
+        let constpool = CpuBufferPool::uniform_buffer(device.clone());
+        let (spans, spans_uploaded) = upload::buffer(&queue, usage, scale.spans.iter().cloned())?;
+        let (taps, taps_uploaded) = upload::buffer(&queue, usage, scale.taps.iter().cloned())?;
+        // columns not written yet would show whatever the image held
+        let family = queue.family();
+        let clear = AutoCommandBufferBuilder::primary_one_time_submit(device.clone(), family)
+            .map_err(|e| ENguyenError::compute("Command buffer allocation failed").caused_by(e))?
+            .clear_color_image(history.image(), ClearValue::Float([0.0; 4]))
+            .map_err(|e| ENguyenError::compute("History clear rejected").caused_by(e))?
+            .build()
+            .map_err(|e| ENguyenError::compute("Command buffer build failed").caused_by(e))?;
+        spans_uploaded
+            .join(taps_uploaded)
+            .then_execute(queue.clone(), clear)
+            .map_err(|e| ENguyenError::compute("History clear failed").caused_by(e))?
+            .then_signal_fence_and_flush()
+            .and_then(|uploaded| uploaded.wait(None))
+            .map_err(|e| ENguyenError::compute("Bin table upload failed").caused_by(e))?;
+        let shaders = if shader_reload::enabled() {
+            ShaderWatch::start()
+                .map_err(|e| warn!("Analysis shader edits won't be reloaded: {}", e))
+                .ok()
+        } else {
+            None
+        };
+        let format = history.format();
+        let unsplit = [channel_image(&device, 1, format)?, channel_image(&device, 1, format)?];
+        Ok(Dispatcher {
+            device,
+            queue,
+            pipeline,
+            bufpool,
+            constpool,
+            slots: Vec::new(),
+            next_slot: 0,
+            tex_height,
+            history,
+            column: 0,
+            split: false,
+            unsplit,
+            spans,
+            taps,
+            shaders,
+        })
+    }
+
+    /// Rebuilds the pipeline if the shader was edited.  Edits that don't build are logged and
+    /// the running pipeline kept.
+    fn reload(&mut self) {
+        if !self.shaders.as_ref().map_or(false, |shaders| shaders.edited()) {
+            return;
+        }
+        let stages = ShaderStages { compute: true, ..ShaderStages::none() };
+        let layout = channel_combine::Layout(stages);
+        let file = combine_shader_file(self.history.format());
+        match shader_reload::compute_pipeline(&self.device, file, layout) {
+            Ok(pipeline) => {
+                info!("Reloaded {}", file);
+                self.pipeline = pipeline;
+                self.slots.clear();
+            },
+            Err(e) => warn!("Keeping the running analysis shader: {}", e),
+        }
+    }
+
+    /// The history column `constants` name, the channel images if split and the future that
+    /// finishes writing them.  The next dispatch writes the column after.
+    fn dispatch(
+        &mut self,
+        left: &[f32],
+        right: &[f32],
+        constants: channel_combine::ty::Constants,
+    ) -> Result<Dispatched, ENguyenError> {
+        let channels = if self.split {
+            let (rows, format) = (self.tex_height as u32, self.history.format());
+            let left = channel_image(&self.device, rows, format)?;
+            Some([left, channel_image(&self.device, rows, format)?])
+        } else {
+            None
+        };
+        if let Some(dispatched) = self.reuse(left, right, constants, channels.clone())? {
+            return Ok(dispatched);
         }
+        let upload = |magnitudes: &[f32]| {
+            self.bufpool
+                .chunk(magnitudes.iter().map(|m| Complex::new(*m, 0.0)))
+                .map_err(|e| ENguyenError::compute("Spectrum upload failed").caused_by(e))
+        };
+        let left_buffer = upload(left)?;
+        let right_buffer = upload(right)?;
+        let constants_buffer = self
+            .constpool
+            .next(constants)
+            .map_err(|e| ENguyenError::compute("Constants upload failed").caused_by(e))?;
+        let images = channels.clone().unwrap_or_else(|| self.unsplit.clone());
+        let set = self.descriptor_set(left_buffer, right_buffer, constants_buffer, images)?;
+        let cb = self.record(set)?;
+        self.submit(cb, constants.column, channels)
+    }
+
+    /// Submits the next recorded dispatch after writing its buffers.  `None` while the GPU
+    /// still has them.  Split channel images get a dispatch of their own reading the slot.
+    fn reuse(
+        &mut self,
+        left: &[f32],
+        right: &[f32],
+        constants: channel_combine::ty::Constants,
+        channels: Option<ChannelImages>,
+    ) -> Result<Option<Dispatched>, ENguyenError> {
+        if self.slots.is_empty() {
+            let slots = (0..DISPATCH_SLOTS).map(|_| self.slot(left.len(), constants));
+            self.slots = slots.collect::<Result<_, _>>()?;
+        }
+        let slot = &self.slots[self.next_slot];
+        match (slot.left.write(), slot.right.write(), slot.constants.write()) {
+            (Ok(mut left_bins), Ok(mut right_bins), Ok(mut slot_constants)) => {
+                for (bin, m) in left_bins.iter_mut().zip(left) {
+                    *bin = Complex::new(*m, 0.0);
+                }
+                for (bin, m) in right_bins.iter_mut().zip(right) {
+                    *bin = Complex::new(*m, 0.0);
+                }
+                *slot_constants = constants;
+            },
+            _ => return Ok(None),
+        }
+        let commands = match &channels {
+            None => slot.commands.clone(),
+            // held back windows keep their channel images, so every split dispatch writes
+            // new ones and can't be recorded ahead or cached
+            Some(images) => {
+                let (left, right) = (slot.left.clone(), slot.right.clone());
+                let set =
+                    self.descriptor_set(left, right, slot.constants.clone(), images.clone())?;
+                Arc::new(self.record(set)?)
+            },
+        };
+        self.next_slot = (self.next_slot + 1) % self.slots.len();
+        self.submit(commands, constants.column, channels).map(Some)
+    }
+
+    /// Buffers of its own for `bins` linear bins and a dispatch recorded to read them
+    fn slot(
+        &self,
+        bins: usize,
+        constants: channel_combine::ty::Constants,
+    ) -> Result<Slot, ENguyenError> {
+        let usage = BufferUsage { storage_buffer: true, ..BufferUsage::none() };
+        let spectrum = || {
+            let zeros = (0..bins).map(|_| Complex::zero());
+            CpuAccessibleBuffer::from_iter(self.device.clone(), usage, zeros)
+                .map_err(|e| ENguyenError::compute("Spectrum buffer failed").caused_by(e))
+        };
+        let (left, right) = (spectrum()?, spectrum()?);
+        let constants = CpuAccessibleBuffer::from_data(
+            self.device.clone(),
+            BufferUsage::uniform_buffer(),
+            constants,
+        )
+        .map_err(|e| ENguyenError::compute("Constants buffer failed").caused_by(e))?;
+        let images = self.unsplit.clone();
+        let set = self.descriptor_set(left.clone(), right.clone(), constants.clone(), images)?;
+        let commands = Arc::new(self.record(set)?);
+        Ok(Slot { left, right, constants, commands })
+    }
+
+    /// Binds the spectra and constants in these buffers and the channel images
+    fn descriptor_set<L, R, C>(
+        &self,
+        left: L,
+        right: R,
+        constants: C,
+        [left_image, right_image]: ChannelImages,
+    ) -> Result<Arc<dyn DescriptorSet + Send + Sync>, ENguyenError>
+    where
+        L: BufferAccess + Send + Sync + 'static,
+        R: BufferAccess + Send + Sync + 'static,
+        C: BufferAccess + Send + Sync + 'static,
+    {
+        let set = PersistentDescriptorSet::start(self.pipeline.clone(), 0)
+            .add_buffer(left)
+            .and_then(|set| set.add_buffer(right))
+            .and_then(|set| set.add_image(self.history.image()))
+            .and_then(|set| set.add_buffer(self.spans.clone()))
+            .and_then(|set| set.add_buffer(self.taps.clone()))
+            .and_then(|set| set.add_image(left_image))
+            .and_then(|set| set.add_image(right_image))
+            .and_then(|set| set.add_buffer(constants))
+            .map_err(|e| ENguyenError::compute("Analysis descriptor rejected").caused_by(e))?
+            .build()
+            .map_err(|e| ENguyenError::compute("Analysis descriptor set failed").caused_by(e))?;
+        Ok(Arc::new(set))
+    }
+
+    /// A dispatch of the combine shader over every row
+    fn record(
+        &self,
+        set: Arc<dyn DescriptorSet + Send + Sync>,
+    ) -> Result<AutoCommandBuffer, ENguyenError> {
+        let dispatch_x = self.tex_height as u32 / channel_combine::LOCAL_SIZE_X;
+        AutoCommandBufferBuilder::secondary_compute_simultaneous_use(
+            self.device.clone(),
+            self.queue.family(),
+        )
+        .map_err(|e| ENguyenError::compute("Command buffer allocation failed").caused_by(e))?
+        .dispatch([dispatch_x, 1, 1], self.pipeline.clone(), set, ())
+        .map_err(|e| ENguyenError::compute("Analysis dispatch rejected").caused_by(e))?
+        .build()
+        .map_err(|e| ENguyenError::compute("Command buffer build failed").caused_by(e))
+    }
+
+    /// Runs `cb`, which writes `column`, and moves on to the column after
+    fn submit<Cb>(
+        &mut self,
+        cb: Cb,
+        column: u32,
+        channels: Option<ChannelImages>,
+    ) -> Result<Dispatched, ENguyenError>
+    where
+        Cb: CommandBuffer + Send + Sync + 'static,
+    {
+        // frames drawn on another queue wait on the semaphore before sampling the column
+        let future = sync::now(self.device.clone())
+            .then_execute(self.queue.clone(), cb)
+            .map_err(|e| ENguyenError::compute("Analysis submit failed").caused_by(e))?
+            .then_signal_semaphore_and_flush()
+            .map_err(|e| ENguyenError::compute("Analysis flush failed").caused_by(e))?;
+        self.column = self.history.next_column(column);
+        Ok((column, channels, Box::new(future)))
     }
 }
 
-static MAX_AUDIBLE: f64 = 20000_f64;
+/// A recorded dispatch and the buffers it reads.  Writing them locks out the GPU, so a slot
+/// still in flight can't be written.
+struct Slot {
+    left: Arc<CpuAccessibleBuffer<[Complex<f32>]>>,
+    right: Arc<CpuAccessibleBuffer<[Complex<f32>]>>,
+    constants: Arc<CpuAccessibleBuffer<channel_combine::ty::Constants>>,
+    commands: Arc<AutoCommandBuffer>,
+}
+
+/// A dispatch's history column, channel images and the future that writes them
+type Dispatched = (u32, Option<ChannelImages>, Box<dyn GpuFuture + Send + Sync>);
+
+/// One channel's levels, a texel per row.  Usable from every queue family, like the history.
+fn channel_image(
+    device: &Arc<Device>,
+    rows: u32,
+    format: HistoryFormat,
+) -> Result<Arc<StorageImage<Format>>, ENguyenError> {
+    StorageImage::with_usage(
+        device.clone(),
+        Dimensions::Dim2d { width: 1, height: rows },
+        format.channel_format(),
+        ImageUsage { storage: true, sampled: true, ..ImageUsage::none() },
+        device.active_queue_families(),
+    )
+    .map_err(|e| ENguyenError::compute("Channel texture creation failed").caused_by(e))
+}
+
+/// Both channels' spectra of the newest window.  Magnitudes are averaged over windows, and the
+/// shader only reads magnitudes, so they are passed as real numbers.
+struct Spectra {
+    fft: Arc<dyn FFT<f32>>,
+    weights: Vec<f32>,
+    /// Brings a full scale sine's magnitude to 1.0
+    sine_scale: f32,
+    left_input: Vec<Complex<f32>>,
+    right_input: Vec<Complex<f32>>,
+    output: Vec<Complex<f32>>,
+    /// Smoothed magnitude of each bin
+    left: Vec<f32>,
+    right: Vec<f32>,
+}
+
+impl Spectra {
+    fn new(options: &AnalysisOptions) -> Spectra {
+        let bins = options.fft_size;
+        let weights = window_weights(options.window, options.sample_window);
+        // a full scale sine's FFT magnitude is half the window's sum
+        let sine_scale = 2.0 / weights.iter().sum::<f32>();
+        Spectra {
+            fft: FFTplanner::new(false).plan_fft(bins),
+            weights,
+            sine_scale,
+            left_input: vec![Zero::zero(); bins],
+            right_input: vec![Zero::zero(); bins],
+            output: vec![Zero::zero(); bins],
+            left: vec![0.0; bins],
+            right: vec![0.0; bins],
+        }
+    }
+
+    /// Transforms interleaved stereo `audio`, each sample multiplied by `gain`, and averages
+    /// the magnitudes in keeping `smoothing` of the previous ones
+    fn hear(&mut self, audio: &[i16], gain: f32, smoothing: f32) {
+        unpack(audio, &self.weights, gain, &mut self.left_input, &mut self.right_input);
+        self.fft.process(&mut self.left_input, &mut self.output);
+        smooth(&self.output, &mut self.left, smoothing);
+        self.fft.process(&mut self.right_input, &mut self.output);
+        smooth(&self.output, &mut self.right, smoothing);
+    }
+}
+
+/// Slides the interleaved samples of `fresh` into the end of `window`, dropping the oldest
+fn slide(window: &mut [i16], fresh: &[i16]) {
+    let len = window.len();
+    if fresh.len() >= len {
+        window.copy_from_slice(&fresh[fresh.len() - len..]);
+    } else {
+        window.rotate_left(fresh.len());
+        window[len - fresh.len()..].copy_from_slice(fresh);
+    }
+}
+
+/// Each channel of interleaved stereo `audio` as FFT inputs, weighted and multiplied by
+/// `gain`.  Inputs past the weights, the FFT's zero padding, are left as they are.
+fn unpack(
+    audio: &[i16],
+    weights: &[f32],
+    gain: f32,
+    left: &mut [Complex<f32>],
+    right: &mut [Complex<f32>],
+) {
+    let inputs = left.iter_mut().zip(right.iter_mut());
+    for ((sample, weight), (left, right)) in audio.chunks_exact(2).zip(weights).zip(inputs) {
+        *left = Complex::new(f32::from(sample[1]) * gain * weight, 0.0);
+        *right = Complex::new(f32::from(sample[0]) * gain * weight, 0.0);
+    }
+}
+
+/// Exponential moving average of each bin's magnitude.  `factor` is how much of the
+/// previous value is kept.
+fn smooth(spectrum: &[Complex<f32>], magnitudes: &mut [f32], factor: f32) {
+    for (bin, mag) in spectrum.iter().zip(magnitudes.iter_mut()) {
+        *mag = *mag * factor + bin.norm() * (1.0 - factor);
+    }
+}
+
+/// Weight of each sample in a window of `len`
+fn window_weights(window: WindowFunction, len: usize) -> Vec<f32> {
+    let span = (len.max(2) - 1) as f32;
+    let tau = 2.0 * std::f32::consts::PI;
+    (0..len)
+        .map(|i| {
+            let x = i as f32 / span;
+            match window {
+                WindowFunction::Rectangular => 1.0,
+                WindowFunction::Hann => 0.5 - 0.5 * (tau * x).cos(),
+                WindowFunction::Blackman => {
+                    0.42 - 0.5 * (tau * x).cos() + 0.08 * (2.0 * tau * x).cos()
+                },
+            }
+        })
+        .collect()
+}
+
+/// Lowest frequency that can be drawn
 static MIN_AUDIBLE: f64 = 20_f64;
+/// Highest frequency the range can start at
+static MAX_AUDIBLE: f64 = 20000_f64;
+/// Smaller FFTs can't resolve the lowest drawn frequencies at all
+static MIN_FFT_SIZE: usize = 64;
+/// Every Vulkan device supports images this wide
+static MAX_HISTORY: u32 = 4096;
+/// Analysis windows a second, unless saving power
+pub static ANALYSIS_RATE: u32 = 60;
+/// Bands the frequency ruler reads levels from
+static READOUT_BINS: usize = 256;
+/// Longest windows are held back for an output's latency
+static MAX_OUTPUT_DELAY: Duration = Duration::from_millis(500);
+/// Columns written ahead of the view while windows are held back, `MAX_OUTPUT_DELAY` of them
+pub static HELD_COLUMNS: u32 = 30;
+
+/// How long to hold each window back so it's drawn as `output` plays it.  The configured
+/// delay for the output wins over the latency it reports.
+fn output_delay(output: Option<&OutputLatency>, options: &AnalysisOptions) -> Duration {
+    let output = match output {
+        Some(output) => output,
+        None => return Duration::default(),
+    };
+    let delay = match options.output_delays.get(&output.sink) {
+        Some(ms) => Duration::from_millis(u64::from(*ms)),
+        None if options.detect_output_delay => output.latency,
+        None => Duration::default(),
+    };
+    delay.min(MAX_OUTPUT_DELAY)
+}
+
+/// `min_freq` to `max_freq`, or the defaults with a warning if that isn't an audible range
+fn frequency_range(min_freq: f64, max_freq: f64) -> (f64, f64) {
+    if !(MIN_AUDIBLE..MAX_AUDIBLE).contains(&min_freq) || max_freq <= min_freq {
+        let defaults = AnalysisConfig::default();
+        warn!("analysis.min_freq must be audible and below max_freq.  Using the defaults.");
+        return (defaults.min_freq, defaults.max_freq);
+    }
+    (min_freq, max_freq)
+}
+
+/// The range lowered to end at the `nyquist` limit of the source, past which the top rows
+/// would only repeat the ones below them
+fn below_nyquist(min_freq: f64, max_freq: f64, nyquist: f64) -> (f64, f64) {
+    if max_freq <= nyquist {
+        return (min_freq, max_freq);
+    }
+    let limit = format!("the source's {}Hz Nyquist limit", nyquist);
+    warn!("analysis.max_freq is above {}.  Drawing up to the limit.", limit);
+    if min_freq >= nyquist {
+        let defaults = AnalysisConfig::default();
+        return (defaults.min_freq.min(nyquist / 2.0), nyquist);
+    }
+    (min_freq, nyquist)
+}
+
+/// The run of `LogScale::taps` a row of the analysis texture sums
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[repr(C)]
+struct Span {
+    first: u32,
+    count: u32,
+}
+
+/// A linear bin and the share of it inside a row's frequency range
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+struct Tap {
+    bin: u32,
+    weight: f32,
+}
 
 struct LogScale {
     n_log_bins: usize,
@@ -231,17 +1182,18 @@ struct LogScale {
     log_bin_ratio: f64,
     min_freq: f64,
     max_freq: f64,
+    /// Each log bin's taps, lowest bin first
+    spans: Vec<Span>,
+    taps: Vec<Tap>,
 }
 
 impl LogScale {
-    fn new(log_bins: usize, f_min: f64, f_max: f64) -> LogScale {
-        assert!(f_min < MAX_AUDIBLE);
-        assert!(f_max > MIN_AUDIBLE);
+    /// `log_bins` rows from `f_min` to `f_max`, read from an FFT of `lin_bins` bins
+    /// `lin_res` Hz apart
+    fn new(log_bins: usize, f_min: f64, f_max: f64, lin_bins: usize, lin_res: f64) -> LogScale {
+        assert!(f_min > 0.0);
         assert!(f_max > f_min);
 
-        let f_max = if f_max > MAX_AUDIBLE { MAX_AUDIBLE } else { f_max };
-        let f_min = if f_min < MIN_AUDIBLE { MIN_AUDIBLE } else { f_min };
-
         let f64_log_bins = log_bins as f64;
 
         // the (1 / n_log_bins) power takes the n_log_binsth root which will
@@ -251,145 +1203,522 @@ impl LogScale {
         // worst-case resolution
         let min_log_res = f_min * (a - 1.0_f64);
         let n_lin_bins = f_max / min_log_res;
+        let (spans, taps) = bin_taps(log_bins, f_min, a, lin_bins, lin_res);
         LogScale {
             n_log_bins: log_bins,
             n_lin_bins: n_lin_bins as usize,
             log_bin_ratio: a,
             min_freq: f_min,
             max_freq: f_max,
+            spans,
+            taps,
+        }
+    }
+
+    /// Each log bin's level from both channels' FFT magnitudes, as `features::level`.
+    /// `scale` brings a full scale sine's magnitude to 1.0.
+    fn levels(&self, left: &[f32], right: &[f32], scale: f32) -> Vec<f32> {
+        self.spans
+            .iter()
+            .map(|span| {
+                let taps = &self.taps[span.first as usize..(span.first + span.count) as usize];
+                let energy: f32 = taps
+                    .iter()
+                    .map(|tap| {
+                        let magnitude = (left[tap.bin as usize] + right[tap.bin as usize]) / 2.0;
+                        magnitude * magnitude * tap.weight
+                    })
+                    .sum();
+                level(energy.sqrt() * scale)
+            })
+            .collect()
+    }
+}
+
+/// Each log bin's share of the linear bins it overlaps.  Log bin `n` reaches from `f_min`
+/// times `ratio` to the `n - 0.5` up to the `n + 0.5`.  Linear bin `c` is taken to reach
+/// from bin `c - 1` to `c + 1`.  The first bin is never read, nor are those past the Nyquist
+/// bin, which mirror the ones below it.
+fn bin_taps(
+    log_bins: usize,
+    f_min: f64,
+    ratio: f64,
+    lin_bins: usize,
+    lin_res: f64,
+) -> (Vec<Span>, Vec<Tap>) {
+    let (lowest, highest) = (1, lin_bins as i64 / 2);
+    let mut spans = Vec::with_capacity(log_bins);
+    let mut taps = Vec::new();
+    for log_bin in 0..log_bins {
+        let start = f_min * ratio.powf(log_bin as f64 - 0.5);
+        let end = f_min * ratio.powf(log_bin as f64 + 0.5);
+        let first = taps.len();
+        let from = ((start / lin_res).floor() as i64 - 1).max(lowest);
+        let to = ((end / lin_res).floor() as i64 + 1).min(highest);
+        for bin in from..=to {
+            let (bin_start, bin_end) = ((bin - 1) as f64 * lin_res, (bin + 1) as f64 * lin_res);
+            let overlap = end.min(bin_end) - start.max(bin_start);
+            if overlap > 0.0 {
+                let weight = (overlap / (bin_end - bin_start)) as f32;
+                taps.push(Tap { bin: bin as u32, weight });
+            }
         }
+        spans.push(Span { first: first as u32, count: (taps.len() - first) as u32 });
+    }
+    (spans, taps)
+}
+
+/// The analysis shader's source in `shaders/`
+static CHANNEL_COMBINE: &str = "channel_combine.comp";
+static CHANNEL_COMBINE_F16: &str = "channel_combine_f16.comp";
+static CHANNEL_COMBINE_R11: &str = "channel_combine_r11.comp";
+
+/// The analysis shader that writes histories in `format`
+fn combine_shader_file(format: HistoryFormat) -> &'static str {
+    match format {
+        HistoryFormat::Rgba32f => CHANNEL_COMBINE,
+        HistoryFormat::Rgba16f => CHANNEL_COMBINE_F16,
+        HistoryFormat::R11g11b10f => CHANNEL_COMBINE_R11,
     }
 }
 
+/// Every variant shares `channel_combine`'s layout and push constants
+fn combine_pipeline(
+    device: &Arc<Device>,
+    format: HistoryFormat,
+) -> Result<Arc<dyn ComputePipelineAbstract + Send + Sync>, ENguyenError> {
+    let loaded = |e| ENguyenError::compute("Analysis shader failed to load").caused_by(e);
+    let created = |e| ENguyenError::compute("Analysis pipeline creation failed").caused_by(e);
+    let pipeline: Arc<dyn ComputePipelineAbstract + Send + Sync> = match format {
+        HistoryFormat::Rgba32f => {
+            let shader = channel_combine::Shader::load(device.clone()).map_err(loaded)?;
+            let entry = shader.main_entry_point();
+            Arc::new(ComputePipeline::new(device.clone(), &entry, &()).map_err(created)?)
+        },
+        HistoryFormat::Rgba16f => {
+            let shader = channel_combine_f16::Shader::load(device.clone()).map_err(loaded)?;
+            let entry = shader.main_entry_point();
+            Arc::new(ComputePipeline::new(device.clone(), &entry, &()).map_err(created)?)
+        },
+        HistoryFormat::R11g11b10f => {
+            let shader = channel_combine_r11::Shader::load(device.clone()).map_err(loaded)?;
+            let entry = shader.main_entry_point();
+            Arc::new(ComputePipeline::new(device.clone(), &entry, &()).map_err(created)?)
+        },
+    };
+    Ok(pipeline)
+}
+
 mod channel_combine {
     pub static LOCAL_SIZE_X: u32 = 16; // this must match local size
-    vulkano_shaders::shader! {
-        ty: "compute",
-        src: "
-#version 450
+    include!(concat!(env!("OUT_DIR"), "/channel_combine.comp.rs"));
+}
+
+mod channel_combine_f16 {
+    include!(concat!(env!("OUT_DIR"), "/channel_combine_f16.comp.rs"));
+}
 
-const float HAPI = 1.5707963267948966;
-const float IPI = 0.3183098861837907;
+mod channel_combine_r11 {
+    include!(concat!(env!("OUT_DIR"), "/channel_combine_r11.comp.rs"));
+}
 
-struct Complex {
-    float real;
-    float imag;
-};
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::{CaptureHandle, RingState, SimpleSource};
+    use crate::ring::{FrameReader, RingFrames};
 
-layout(local_size_x=16, local_size_y=1, local_size_z=1) in;
-layout(set = 0, binding = 0) buffer LeftData {Complex data[];} left_chan;
-layout(set = 0, binding = 1) buffer RightData {Complex data[];} right_chan;
-layout (set = 0, binding = 2, rgba32f)  uniform image2D out_img;
-layout (push_constant) uniform PushConstant {
-    uint lin_bins;
-    float log_scale;
-    float lin_res;
-    float min_freq;
-    float max_freq;
-} fft;
-
-float norm_tan(float unnormed);
-float mag(Complex c);
-float phase(Complex c);
-
-void main() {
-    uint gidx = gl_GlobalInvocationID.x;
-    uint widx = gl_WorkGroupID.x;
-    uint lidx = gl_LocalInvocationID.x;
-    uint num_groups = gl_NumWorkGroups.x;
-    uint woven = widx + lidx * num_groups;
-
-    float left_sum = 0.0;
-    float right_sum = 0.0;
+    #[test]
+    fn windows_slide_and_unpack() {
+        let mut window = vec![0, 0, 1, 2, 3, 4];
+        slide(&mut window, &[5, 6]);
+        assert_eq!(window, vec![1, 2, 3, 4, 5, 6]);
+        slide(&mut window, &[7, 8, 9, 10, 11, 12, 13, 14]);
+        assert_eq!(window, vec![9, 10, 11, 12, 13, 14]);
 
-    {
-        float freq_center = fft.min_freq * pow(fft.log_scale, float(woven));
-        float log_bin_start_f = fft.min_freq * pow(fft.log_scale, float(woven) - 0.5);
-        float log_bin_end_f = fft.min_freq * pow(fft.log_scale, float(woven) + 0.5);
-
-        uint start_cen_idx = clamp(uint(log_bin_start_f / fft.lin_res) - 1, 1, fft.lin_bins - 2);
-        uint end_cen_idx = clamp(uint(log_bin_end_f / fft.lin_res) + 1, 1, fft.lin_bins - 2);
-
-        uint lin_bin_cen_idx = start_cen_idx;
-        while (lin_bin_cen_idx >= start_cen_idx && lin_bin_cen_idx <= end_cen_idx) {
-
-            uint lin_bin_start_idx = lin_bin_cen_idx - 1;
-            uint lin_bin_end_idx = lin_bin_cen_idx + 1;
-            float lin_bin_start_f = float(lin_bin_start_idx) * fft.lin_res;
-            float lin_bin_end_f = float(lin_bin_end_idx) * fft.lin_res;
-            float lin_bin_size = lin_bin_end_f - lin_bin_start_f;
-
-            float lin_bin_frac;
-            if (log_bin_start_f < lin_bin_start_f) {
-                if (log_bin_end_f > lin_bin_end_f) {
-                    lin_bin_frac = 1.0;
-                } else {
-                    if (log_bin_end_f < lin_bin_start_f) {
-                        lin_bin_frac = 0.0;
-                    } else {
-                        lin_bin_frac = (log_bin_end_f - lin_bin_start_f) / lin_bin_size;
-                    }
-                }
-            } else {
-                if (log_bin_end_f > lin_bin_end_f) {
-                    if (log_bin_start_f > lin_bin_end_f) {
-                        lin_bin_frac = 0.0;
-                    } else {
-                        lin_bin_frac = (lin_bin_end_f - log_bin_start_f) / lin_bin_size;
-                    }
-                } else {
-                    lin_bin_frac = (log_bin_end_f - log_bin_start_f) / lin_bin_size;
-                }
+        let mut left = vec![Complex::new(9.0, 9.0); 4];
+        let mut right = left.clone();
+        unpack(&window, &[1.0, 0.5], 0.5, &mut left, &mut right);
+        assert_eq!(left[..2], [Complex::new(5.0, 0.0), Complex::new(3.0, 0.0)]);
+        assert_eq!(right[..2], [Complex::new(4.5, 0.0), Complex::new(2.75, 0.0)]);
+        // the zero padding isn't touched
+        assert_eq!(left[2], Complex::new(9.0, 9.0));
+    }
+
+    #[test]
+    fn smoothing_averages_magnitudes() {
+        let spectrum = vec![Complex::new(3.0, 4.0); 4];
+        let mut mags = vec![0.0; 4];
+        smooth(&spectrum, &mut mags, 0.0);
+        assert_eq!(mags, vec![5.0; 4]);
+        smooth(&vec![Complex::zero(); 4], &mut mags, 0.5);
+        assert_eq!(mags, vec![2.5; 4]);
+    }
+
+    #[test]
+    fn window_functions() {
+        assert_eq!(window_weights(WindowFunction::Rectangular, 4), vec![1.0; 4]);
+        let hann = window_weights(WindowFunction::Hann, 5);
+        assert!(hann[0].abs() < 1e-6 && hann[4].abs() < 1e-6);
+        assert!((hann[2] - 1.0).abs() < 1e-6);
+        let blackman = window_weights(WindowFunction::Blackman, 5);
+        assert!(blackman[0].abs() < 1e-6);
+        assert!((blackman[2] - 1.0).abs() < 1e-6);
+        assert_eq!(window_weights(WindowFunction::Hann, 1).len(), 1);
+    }
+
+    #[test]
+    fn log_bins_tap_what_they_overlap() {
+        // 48kHz into 2048 bins
+        let res = 24000.0 / 1024.0;
+        let scale = LogScale::new(1024, 20.0, 20000.0, 2048, res);
+        assert_eq!(scale.spans.len(), 1024);
+        let ratio = scale.log_bin_ratio;
+        for (n, span) in scale.spans.iter().enumerate() {
+            let taps = &scale.taps[span.first as usize..(span.first + span.count) as usize];
+            assert!(taps.iter().all(|tap| tap.bin >= 1 && tap.bin <= 2046));
+            assert!(taps.iter().all(|tap| tap.weight > 0.0 && tap.weight <= 1.0));
+            // every frequency above the first bin is in two linear bins, each two bins wide
+            let start = 20.0 * ratio.powf(n as f64 - 0.5);
+            let end = 20.0 * ratio.powf(n as f64 + 0.5);
+            if start >= res {
+                let total: f32 = taps.iter().map(|tap| tap.weight).sum();
+                let expected = (end - start) / res;
+                assert!((total as f64 - expected).abs() < expected * 1e-4, "{}", n);
+            }
+        }
+        let widest = scale.spans[1023];
+        assert!(scale.taps[widest.first as usize + 2].weight == 1.0);
+
+        // a full scale sine in one bin
+        let mut magnitudes = vec![0.0; 2048];
+        magnitudes[60] = 1.0;
+        let coarse = LogScale::new(16, 20.0, 20000.0, 2048, res);
+        let levels = coarse.levels(&magnitudes, &magnitudes, 1.0);
+        let heard = coarse.spans.iter().position(|span| {
+            let taps = &coarse.taps[span.first as usize..(span.first + span.count) as usize];
+            taps.iter().any(|tap| tap.bin == 60 && tap.weight == 1.0)
+        });
+        assert_eq!(levels[heard.unwrap()], 1.0);
+        assert_eq!(levels[0], 0.0);
+        assert_eq!(levels[15], 0.0);
+    }
+
+    #[test]
+    fn analysis_options_are_validated() {
+        let mut config = ENguyenConfig::default();
+        config.analysis.fft_size = 4096;
+        config.analysis.window = WindowFunction::Hann;
+        config.audio.sample_window = 2048;
+        let options = AnalysisOptions::from_config(&config);
+        assert_eq!((options.fft_size, options.sample_window), (4096, 2048));
+        assert_eq!(options.window, WindowFunction::Hann);
+        assert_eq!((options.history, options.review), (1024, 2700));
+
+        config.analysis.fft_size = 8;
+        config.analysis.min_freq = 5000.0;
+        config.analysis.max_freq = 100.0;
+        config.analysis.history = 100_000;
+        let options = AnalysisOptions::from_config(&config);
+        assert_eq!((options.fft_size, options.sample_window), (MIN_FFT_SIZE, MIN_FFT_SIZE));
+        assert_eq!((options.min_freq, options.max_freq), (220.0, 22000.0));
+        assert_eq!((options.history, options.review), (MAX_HISTORY - HELD_COLUMNS, 0));
+
+        let mut source = AudioTexSource::new(64).unwrap();
+        source.frequency_range(30.0, 16000.0);
+        assert_eq!((source.options.min_freq, source.options.max_freq), (30.0, 16000.0));
+        source.frequency_range(10.0, 16000.0);
+        assert_eq!(source.options.min_freq, 220.0);
+    }
+
+    #[test]
+    fn outputs_delay_by_their_latency() {
+        let mut options = AnalysisOptions::default();
+        let bluetooth = OutputLatency {
+            sink: "bluez_sink.headphones".to_owned(),
+            latency: Duration::from_millis(200),
+        };
+        assert_eq!(output_delay(None, &options), Duration::default());
+        assert_eq!(output_delay(Some(&bluetooth), &options), Duration::from_millis(200));
+        let slow = OutputLatency { latency: Duration::from_secs(2), ..bluetooth.clone() };
+        assert_eq!(output_delay(Some(&slow), &options), MAX_OUTPUT_DELAY);
+
+        options.output_delays.insert(bluetooth.sink.clone(), 150);
+        assert_eq!(output_delay(Some(&bluetooth), &options), Duration::from_millis(150));
+        options.output_delays.clear();
+        options.detect_output_delay = false;
+        assert_eq!(output_delay(Some(&bluetooth), &options), Duration::default());
+        // every held window fits in the columns kept for them
+        let held = MAX_OUTPUT_DELAY.as_millis() as u32 * ANALYSIS_RATE / 1000;
+        assert_eq!(held, HELD_COLUMNS);
+    }
+
+    #[test]
+    fn range_ends_at_nyquist() {
+        assert_eq!(below_nyquist(220.0, 22000.0, 24000.0), (220.0, 22000.0));
+        assert_eq!(below_nyquist(220.0, 22000.0, 11025.0), (220.0, 11025.0));
+        assert_eq!(below_nyquist(12000.0, 16000.0, 11025.0), (220.0, 11025.0));
+        // taps stop at the Nyquist bin even though the top row reaches past it
+        let res = 24000.0 / 1024.0;
+        let scale = LogScale::new(1024, 220.0, 24000.0, 2048, res);
+        assert!(scale.taps.iter().all(|tap| tap.bin <= 1024));
+        assert_eq!(scale.taps.last().unwrap().bin, 1024);
+    }
+
+    /// Plays `samples` into the ring all at once, then keeps the ring open until chilled.
+    /// Analysis can be fed known audio without a sound server.
+    struct MockStream {
+        samples: Vec<i16>,
+        source: SimpleSource,
+        state: RingState,
+        chilled: Arc<AtomicBool>,
+        capture: Option<CaptureHandle>,
+    }
+
+    impl MockStream {
+        /// A sine at `hz`, `amplitude` of full scale, in both channels
+        fn sine(hz: f32, amplitude: f32, rate: u32, frames: usize) -> MockStream {
+            let samples = (0..frames)
+                .map(|frame| {
+                    let phase = 2.0 * std::f32::consts::PI * hz * frame as f32 / rate as f32;
+                    (phase.sin() * amplitude * f32::from(i16::max_value())) as i16
+                })
+                .flat_map(|sample| vec![sample, sample])
+                .collect();
+            MockStream {
+                samples,
+                source: SimpleSource::stereo("mock", rate),
+                state: RingState::BORN,
+                chilled: Arc::new(AtomicBool::new(false)),
+                capture: None,
             }
+        }
+    }
 
-            uint conjugate_index = fft.lin_bins - 1 - lin_bin_cen_idx;
-            uint complex_index = lin_bin_cen_idx;
-            Complex conj_l = left_chan.data[conjugate_index];
-            Complex conj_r = right_chan.data[conjugate_index];
+    impl AudioStream for MockStream {
+        fn connect(&mut self) -> Result<RingState, ENguyenError> {
+            self.state = RingState::CONNECTED;
+            Ok(self.state)
+        }
 
-            Complex com_l = left_chan.data[complex_index];
-            Complex com_r = right_chan.data[complex_index];
-        
-            float mag_l = (mag(com_l) + mag(conj_l)) * 0.5;
-            float mag_r = (mag(com_r) + mag(conj_r)) * 0.5;
+        fn heat(&mut self) -> Result<(FrameReader<i16>, SimpleSource), ENguyenError> {
+            let frames = self.samples.len() / self.source.channels();
+            let (tx, rx) = RingFrames::<i16>::new(frames.max(1), self.source.channels());
+            let samples = self.samples.clone();
+            let chilled = self.chilled.clone();
+            self.capture = Some(thread::spawn(move || {
+                tx.write_frames(&samples);
+                while !chilled.load(Ordering::Relaxed) {
+                    thread::sleep(Duration::from_millis(1));
+                }
+                Ok(())
+            }));
+            self.state = RingState::HOT;
+            Ok((rx, self.source.clone()))
+        }
 
-            left_sum += mag_l * lin_bin_frac;
-            right_sum += mag_r * lin_bin_frac;
+        fn chill(&mut self) -> Result<(RingState, CaptureHandle), ENguyenError> {
+            self.chilled.store(true, Ordering::Relaxed);
+            let capture =
+                self.capture.take().ok_or_else(|| ENguyenError::audio("not capturing"))?;
+            self.state = RingState::DEAD;
+            Ok((self.state, capture))
+        }
 
-            lin_bin_cen_idx++;
+        fn state(&self) -> RingState {
+            self.state
         }
     }
 
-    vec4 out_col = vec4(0.04 * (pow(left_sum * right_sum, 0.5) - 0.3),
-                        0.06 * (right_sum - 0.8),
-                        0.08 * (left_sum - 0.4),
-                        1.0);
+    #[test]
+    fn mock_tones_are_heard_without_a_gpu() {
+        let options = AnalysisOptions::default();
+        let rate = 48000;
+        let bin_hz = rate as f32 / options.fft_size as f32;
+        // on a bin, so the window's sum gives the sine's whole magnitude
+        let hz = 64.0 * bin_hz;
+        let mut stream = MockStream::sine(hz, 0.5, rate, options.sample_window * 2);
+        stream.connect().unwrap();
+        let (rx, source) = stream.heat().unwrap();
+        assert_eq!(source.rate, rate);
 
-    imageStore(out_img, ivec2(0, woven), out_col);
-}
+        let mut audio = vec![0; options.sample_window * 2];
+        let mut filled = 0;
+        while filled < audio.len() {
+            let fresh = rx.read_frames_at_least(1, Duration::from_secs(1)).unwrap();
+            slide(&mut audio, &fresh);
+            filled += fresh.len();
+        }
+        let mut spectra = Spectra::new(&options);
+        spectra.hear(&audio, 1.0 / f32::from(i16::max_value()), 0.0);
+        let loudest = (0..spectra.left.len() / 2)
+            .max_by(|a, b| spectra.left[*a].partial_cmp(&spectra.left[*b]).unwrap())
+            .unwrap();
+        assert_eq!(loudest, 64);
+        let amplitude = spectra.left[loudest] * spectra.sine_scale;
+        assert!((amplitude - 0.5).abs() < 0.01, "{}", amplitude);
+        assert_eq!(spectra.left, spectra.right);
 
-// TODO this mapping is suspicious
-float norm_tan(float unnormed) {
-    return (unnormed + HAPI) / IPI;
-}
+        let scale = LogScale::new(256, 20.0, 20000.0, options.fft_size, f64::from(bin_hz));
+        let levels = scale.levels(&spectra.left, &spectra.right, spectra.sine_scale);
+        let row = (hz / 20.0).ln() / scale.log_bin_ratio.ln() as f32;
+        let loudest = (0..levels.len()).max_by(|a, b| levels[*a].partial_cmp(&levels[*b]).unwrap());
+        assert!((loudest.unwrap() as f32 - row).abs() <= 1.0, "{:?} {}", loudest, row);
 
-// magnitude
-float mag(Complex c) {
-    return pow((pow(c.real, 2.0) + pow(c.imag, 2.0)), 0.5);
-}
+        let (state, capture) = stream.chill().unwrap();
+        assert_eq!(state, RingState::DEAD);
+        capture.join().unwrap().unwrap();
+    }
 
-// phase
-float phase(Complex c) {
-    return c.real != 0.0 ? norm_tan(atan(c.imag / c.real)) : 0.0;
-}
-"
+    /// Injects a sound server failure when connecting or right after capture starts
+    struct FailingCapture {
+        fail_heat: bool,
+        capture: Option<CaptureHandle>,
+    }
+
+    impl AudioStream for FailingCapture {
+        fn connect(&mut self) -> Result<RingState, ENguyenError> {
+            Ok(RingState::CONNECTED)
+        }
+
+        fn heat(&mut self) -> Result<(FrameReader<i16>, SimpleSource), ENguyenError> {
+            if self.fail_heat {
+                return Err(ENguyenError::audio("injected: no such source"));
+            }
+            let source = SimpleSource::default();
+            let (tx, rx) = RingFrames::<i16>::new(4096, source.channels());
+            self.capture = Some(thread::spawn(move || {
+                drop(tx);
+                Err(ENguyenError::audio("injected: server went away"))
+            }));
+            Ok((rx, source))
+        }
+
+        fn chill(&mut self) -> Result<(RingState, CaptureHandle), ENguyenError> {
+            let capture =
+                self.capture.take().ok_or_else(|| ENguyenError::audio("not capturing"))?;
+            Ok((RingState::DEAD, capture))
+        }
 
+        fn state(&self) -> RingState {
+            RingState::HOT
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    fn reported_failure(fail_heat: bool) -> ENguyenError {
+        let picker = GpuPicker::new(false).unwrap();
+        let (device, queue) = picker.headless_compute().unwrap();
+        let stream = FailingCapture { fail_heat, capture: None };
+        let source = AudioTexSource::with_stream(1024, Box::new(stream)).unwrap();
+        let mut tap = AudioTexTap::turn_on(source, device, queue).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            if let Some(failure) = tap.failure() {
+                return failure;
+            }
+            assert!(Instant::now() < deadline, "the failure was never reported");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// A square wave that shows whether analysis suspended it
+    struct WatchedWave {
+        wave: Square4kHz,
+        suspended: Arc<AtomicBool>,
+    }
+
+    impl AudioStream for WatchedWave {
+        fn connect(&mut self) -> Result<RingState, ENguyenError> {
+            self.wave.connect()
+        }
+
+        fn heat(&mut self) -> Result<(FrameReader<i16>, SimpleSource), ENguyenError> {
+            self.wave.heat()
+        }
+
+        fn chill(&mut self) -> Result<(RingState, CaptureHandle), ENguyenError> {
+            self.wave.chill()
+        }
+
+        fn state(&self) -> RingState {
+            self.wave.state()
+        }
+
+        fn suspend(&mut self, suspended: bool) {
+            self.suspended.store(suspended, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn parked_analysis_suspends_capture() {
+        let picker = GpuPicker::new(false).unwrap();
+        let (device, queue) = picker.headless_compute().unwrap();
+        let suspended = Arc::new(AtomicBool::new(false));
+        let stream = WatchedWave { wave: Square4kHz::default(), suspended: suspended.clone() };
+        let source = AudioTexSource::with_stream(1024, Box::new(stream)).unwrap();
+        let tap = AudioTexTap::turn_on(source, device, queue).unwrap();
+        let tex = tap.tap.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(tex.channels.is_none());
 
-    // TODO re-implement tests with updated signature
+        tap.park(true);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !suspended.load(Ordering::Relaxed) {
+            assert!(Instant::now() < deadline, "capture was never suspended");
+            thread::sleep(Duration::from_millis(10));
+        }
+        tap.tap.try_iter().count();
+        assert!(tap.tap.recv_timeout(Duration::from_millis(300)).is_err());
+
+        tap.park(false);
+        tap.tap.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(!suspended.load(Ordering::Relaxed));
+
+        // a pause holds analysis that's still drawn
+        tap.pause(true);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !suspended.load(Ordering::Relaxed) {
+            assert!(Instant::now() < deadline, "paused capture was never suspended");
+            thread::sleep(Duration::from_millis(10));
+        }
+        tap.pause(false);
+        tap.tap.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn channels_can_be_split() {
+        let picker = GpuPicker::new(false).unwrap();
+        let (device, queue) = picker.headless_compute().unwrap();
+        let stream = Box::new(Square4kHz::default());
+        let mut source = AudioTexSource::with_stream(1024, stream).unwrap();
+        source.split_channels();
+        let tap = AudioTexTap::turn_on(source, device, queue).unwrap();
+        let tex = tap.tap.recv_timeout(Duration::from_secs(5)).unwrap();
+        let [left, right] = tex.channels.unwrap();
+        assert_eq!(left.dimensions().width_height(), [1, 1024]);
+        assert_eq!(right.dimensions().width_height(), [1, 1024]);
+    }
+
+    #[test]
+    fn held_dispatches_are_recorded_afresh() {
+        let picker = GpuPicker::new(false).unwrap();
+        let (device, queue) = picker.headless_compute().unwrap();
+        let stream = Box::new(Square4kHz::default());
+        let source = AudioTexSource::with_stream(1024, stream).unwrap();
+        let tap = AudioTexTap::turn_on(source, device, queue).unwrap();
+        // holding every texture keeps the recorded slots busy
+        let held: Vec<AudioTex> = (0..DISPATCH_SLOTS + 2)
+            .map(|_| tap.tap.recv_timeout(Duration::from_secs(5)).unwrap())
+            .collect();
+        for pair in held.windows(2) {
+            assert_ne!(pair[0].column, pair[1].column);
+        }
+    }
+
+    #[test]
+    fn injected_capture_failures_are_reported() {
+        let failure = reported_failure(true).to_string();
+        assert!(failure.contains("failed to start: Audio error: injected"), "{}", failure);
+        let failure = reported_failure(false).to_string();
+        assert!(failure.contains("stopped: Audio error: injected"), "{}", failure);
+    }
 }
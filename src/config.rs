@@ -13,46 +13,829 @@
 
 // Copyright 2019 E-Nguyen Developers.
 
+use crate::audio::SourceChoice;
+use crate::errors::ENguyenError;
 use crate::ewin;
+use crate::params::{Palette, ParamChange, Parameters, Scene};
+use crate::ring::OverflowPolicy;
 
 use lazy_static::lazy_static;
-use log::{error, warn};
+use log::{error, info, warn};
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
 use serde::{Deserialize, Serialize};
-use std::error::Error;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs::File;
 use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
 use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
 use toml;
 use vulkano::instance::PhysicalDevice;
+use vulkano::swapchain;
 
 static DEFAULT_CONF_DIR: &str = "~/.config/e-nguyen/";
 static DEFAULT_TOML_FILE: &str = "e-nguyen.toml";
+/// Editors write files in bursts.  Wait this long for the file to settle before reloading.
+static RELOAD_DEBOUNCE: Duration = Duration::from_millis(300);
 
 lazy_static! {
     static ref CONFIG_LOCK: Mutex<()> = Mutex::new(());
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// Missing keys and sections fall back to their defaults so older files keep parsing
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
 pub struct ENguyenConfig {
+    /// Superseded by `physical_device_uuid`.  Indices change when drivers or PCI slots do.
     pub physical_device_index: i32,
+    pub physical_device_uuid: Option<String>,
+    /// For display only.  The UUID picks the device.
+    pub physical_device_name: Option<String>,
     pub audio_input_index: i32,
     pub start_in_fullscreen: bool,
+    pub audio: AudioConfig,
+    pub analysis: AnalysisConfig,
+    pub video: VideoConfig,
+    pub piano: PianoConfig,
+    pub logo: LogoConfig,
+    pub background: BackgroundConfig,
+    pub power: PowerConfig,
+    pub export: ExportConfig,
+    pub kiosk: KioskConfig,
+    pub tracks: TracksConfig,
+    pub compare: CompareConfig,
+    /// Tuning each scene remembers, written as `[scene.<name>]` tables
+    #[serde(rename = "scene")]
+    pub scenes: BTreeMap<String, SceneSettings>,
+    pub session: Session,
+    /// Named overlays, written as `[profile.<name>]` tables of any of the keys above
+    #[serde(rename = "profile")]
+    pub profiles: BTreeMap<String, toml::value::Table>,
+    /// Chosen on the command line.  Never read from or written to a file.
+    #[serde(skip)]
+    pub launch: LaunchChoice,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioBackend {
+    Pulse,
+    /// Square wave generator.  No sound server needed.
+    Synthetic,
+    /// Plays `audio.replay` into the analysis as if it were being captured
+    Replay,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AudioConfig {
+    pub backend: AudioBackend,
+    /// Sound server source name.  Takes precedence over `audio_input_index` when set.
+    pub source: Option<String>,
+    /// Capture whatever output is the default, such as headphones or speakers, switching
+    /// when it does.  Takes precedence over `source`.
+    pub follow_default: bool,
+    /// Frames of audio in each analysis window
+    pub sample_window: usize,
+    /// How far behind the sound the capture may run, in milliseconds.  Unset leaves it to
+    /// the sound server.
+    pub latency_ms: Option<u32>,
+    /// 16 bit WAV file the replay backend plays, such as a recording
+    pub replay: Option<PathBuf>,
+    /// Hold the visuals back by the latency the sound server reports for the captured output,
+    /// so they aren't seen before they're heard
+    pub detect_output_delay: bool,
+    /// What capture does when analysis falls behind: `drop_oldest` audio to stay current, or
+    /// `block_writer` until analysis catches up
+    pub overflow: OverflowPolicy,
+    /// Milliseconds to hold the visuals back for each output, by sink name, in place of the
+    /// reported latency.  Written as an `[audio.output_delay_ms]` table.
+    pub output_delay_ms: BTreeMap<String, u32>,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        AudioConfig {
+            backend: AudioBackend::Pulse,
+            source: None,
+            follow_default: false,
+            sample_window: 3000,
+            latency_ms: None,
+            replay: None,
+            detect_output_delay: true,
+            overflow: OverflowPolicy::default(),
+            output_delay_ms: BTreeMap::new(),
+        }
+    }
+}
+
+/// Weighting applied to each sample window before the FFT
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WindowFunction {
+    Rectangular,
+    Hann,
+    Blackman,
+}
+
+/// What the analysis texture holds.  Combined colors each row by both channels together.
+/// Stereo stores the left level in red and the right in green, and scenes mirror them.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpectrumLayout {
+    Combined,
+    Stereo,
+}
+
+/// Texel format of the spectrogram history.  Smaller formats halve the bandwidth analysis and
+/// drawing use, for weak GPUs.  Formats the GPU can't store to fall back to the next larger.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryFormat {
+    Rgba32f,
+    Rgba16f,
+    /// Packed 11 and 10 bit floats, without alpha
+    R11g11b10f,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AnalysisConfig {
+    /// Input gain applied before the FFT
+    pub gain: f32,
+    pub fft_size: usize,
+    pub window: WindowFunction,
+    pub smoothing: f32,
+    /// Lowest frequency drawn, Hz
+    pub min_freq: f64,
+    /// Highest frequency drawn, Hz
+    pub max_freq: f64,
+    pub layout: SpectrumLayout,
+    /// Analysis windows the spectrogram shows, oldest scrolling off first
+    pub history: u32,
+    /// Seconds of spectrogram kept beyond those shown, to pause and scrub back through
+    pub review_seconds: f32,
+    pub history_format: HistoryFormat,
+    /// Level of each band with nothing playing, as `e-nguyen calibrate` measures it
+    pub noise_floor: [f32; 4],
+}
+
+impl Default for AnalysisConfig {
+    fn default() -> Self {
+        AnalysisConfig {
+            gain: 1.0,
+            fft_size: 3000,
+            window: WindowFunction::Rectangular,
+            smoothing: 0.0,
+            min_freq: 220.0,
+            max_freq: 22000.0,
+            layout: SpectrumLayout::Combined,
+            history: 1024,
+            review_seconds: 45.0,
+            history_format: HistoryFormat::Rgba32f,
+            noise_floor: [0.0; 4],
+        }
+    }
+}
+
+/// Swapchain presentation.  Fifo waits for vsync and is always supported.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PresentMode {
+    Fifo,
+    Relaxed,
+    Mailbox,
+    Immediate,
+}
+
+impl PresentMode {
+    pub fn vulkano(&self) -> swapchain::PresentMode {
+        match self {
+            PresentMode::Fifo => swapchain::PresentMode::Fifo,
+            PresentMode::Relaxed => swapchain::PresentMode::Relaxed,
+            PresentMode::Mailbox => swapchain::PresentMode::Mailbox,
+            PresentMode::Immediate => swapchain::PresentMode::Immediate,
+        }
+    }
+}
+
+/// How the spectrogram shows levels louder than full brightness
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Tonemap {
+    /// Clipped to white
+    None,
+    /// Compressed toward white, keeping detail in loud passages
+    Reinhard,
+}
+
+impl Tonemap {
+    /// The curve number `tonemap` in `shaders/lib/color.glsl` takes
+    pub fn curve(&self) -> u32 {
+        match self {
+            Tonemap::None => 0,
+            Tonemap::Reinhard => 1,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct VideoConfig {
+    pub present_mode: PresentMode,
+    /// 0 leaves the frame rate to the present mode
+    pub fps_cap: u32,
+    /// Monitor name to go fullscreen on.  The window's current monitor if unset.
+    pub monitor: Option<String>,
+    pub scene: Scene,
+    pub palette: Palette,
+    /// Render resolution as a share of the window's, from 0.25 to 2.0
+    pub resolution_scale: f32,
+    /// Lower the render resolution while frames run late
+    pub adaptive_quality: bool,
+    /// Samples per pixel, smoothing the edges of lines and particles.  1 turns it off.
+    /// Lowered to what the GPU supports when the window opens.
+    pub msaa: u32,
+    /// Mark frequencies along the spectrogram and read out the frequency and level under the
+    /// pointer
+    pub frequency_ruler: bool,
+    pub tonemap: Tonemap,
+    /// Clear to transparent so the compositor shows the desktop through the visualizer
+    pub transparent: bool,
+    /// Title bar and borders.  Without them the top strip or a drag with a modifier key held
+    /// moves the visualizer and its edges resize it.
+    pub decorations: bool,
+    /// Where the visualizer was when it last closed
+    pub window: WindowGeometry,
+}
+
+impl Default for VideoConfig {
+    fn default() -> Self {
+        VideoConfig {
+            present_mode: PresentMode::Fifo,
+            fps_cap: 0,
+            monitor: None,
+            scene: Scene::Scroll,
+            palette: Palette::Nguyen,
+            resolution_scale: 1.0,
+            adaptive_quality: true,
+            msaa: 1,
+            frequency_ruler: false,
+            tonemap: Tonemap::None,
+            transparent: false,
+            decorations: true,
+            window: WindowGeometry::default(),
+        }
+    }
+}
+
+/// Keys the piano scene draws, as MIDI note numbers.  Middle C is 60.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PianoConfig {
+    pub lowest_note: u8,
+    pub highest_note: u8,
+}
+
+impl Default for PianoConfig {
+    fn default() -> Self {
+        PianoConfig { lowest_note: 36, highest_note: 96 }
+    }
+}
+
+/// Which corner of the window, or its middle, an image is drawn against
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Anchor {
+    Center,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+/// An image drawn over every scene that moves with the music
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LogoConfig {
+    /// PNG or other image file.  No logo if unset.
+    pub path: Option<PathBuf>,
+    pub anchor: Anchor,
+    /// Height as a share of the window's
+    pub size: f32,
+    /// Pixels kept between the logo and the window's edges
+    pub margin: f32,
+    /// Share of the size added on each beat
+    pub pulse: f32,
+    /// Brightness added at full scale bass
+    pub glow: f32,
+    /// Pixels the logo shakes at full scale sub-bass
+    pub shake: f32,
+}
+
+impl Default for LogoConfig {
+    fn default() -> Self {
+        LogoConfig {
+            path: None,
+            anchor: Anchor::BottomRight,
+            size: 0.2,
+            margin: 32.0,
+            pulse: 0.1,
+            glow: 0.5,
+            shake: 0.0,
+        }
+    }
+}
+
+/// What fills the window beneath a scene
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BackgroundKind {
+    Solid,
+    Gradient,
+    Image,
+    VideoLoop,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BackgroundConfig {
+    pub kind: BackgroundKind,
+    /// Red, green and blue of a solid background
+    pub color: [f32; 3],
+    /// The image, or a directory of frames for a video loop played in file name order
+    pub path: Option<PathBuf>,
+    /// Frames per second of a video loop
+    pub fps: f32,
+}
+
+impl Default for BackgroundConfig {
+    fn default() -> Self {
+        BackgroundConfig { kind: BackgroundKind::Solid, color: [0.0; 3], path: None, fps: 24.0 }
+    }
+}
+
+/// When to save power
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PowerMode {
+    /// On battery at or below `battery_below` percent
+    Auto,
+    Always,
+    Never,
+}
+
+/// Low power mode halves the analysis rate, caps the frame rate and favors integrated GPUs
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PowerConfig {
+    pub mode: PowerMode,
+    /// Percent charge.  At 100 any time on battery saves power.
+    pub battery_below: f32,
+    /// Windows opened in low power mode use an integrated GPU over the configured one
+    pub prefer_integrated: bool,
+}
+
+impl Default for PowerConfig {
+    fn default() -> Self {
+        PowerConfig { mode: PowerMode::Auto, battery_below: 100.0, prefer_integrated: true }
+    }
+}
+
+/// How the export file is written
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// A header row, then a row per analysis window
+    Csv,
+    /// A JSON object per line
+    Json,
+}
+
+/// Where each analysis window's features are sent, for tools that only want the numbers
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ExportConfig {
+    /// Written over each time analysis starts
+    pub path: Option<PathBuf>,
+    pub format: ExportFormat,
+    /// `host:port` sent a JSON datagram per window
+    pub udp: Option<String>,
+    /// `host:port` to serve a WebSocket of JSON frames on
+    pub websocket: Option<String>,
+    /// Frames a second sent to WebSocket clients
+    pub websocket_rate: f32,
+    /// Log spaced levels in each JSON record's `spectrum`.  0 leaves it out.
+    pub spectrum_bins: usize,
+    /// An LED strip lit by the spectrum, in an `[export.led]` table
+    pub led: Option<LedConfig>,
+    /// Room lights following the music, in an `[export.lights]` table
+    pub lights: Option<LightsConfig>,
+    /// Beat, tempo, silence and track events, in an `[export.mqtt]` table
+    pub mqtt: Option<MqttConfig>,
+    /// LEDs around the screen matching the visualizer's edges, in an `[export.edges]` table
+    pub edges: Option<EdgesConfig>,
+}
+
+impl Default for ExportConfig {
+    fn default() -> Self {
+        ExportConfig {
+            path: None,
+            format: ExportFormat::Csv,
+            udp: None,
+            websocket: None,
+            websocket_rate: 30.0,
+            spectrum_bins: 64,
+            led: None,
+            lights: None,
+            mqtt: None,
+            edges: None,
+        }
+    }
+}
+
+/// How pixels are sent to an LED strip
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LedProtocol {
+    /// WLED's realtime UDP protocol, port 21324
+    Wled,
+    /// sACN, port 5568
+    E131,
+    /// ArtDmx packets, port 6454
+    ArtNet,
+}
+
+/// Where along the strip each part of the spectrum is drawn
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LedMapping {
+    /// Bass at the first pixel, highs at the last
+    Spectrum,
+    /// Bass in the middle, highs at both ends
+    Mirror,
+    /// Four equal segments lit by sub-bass, bass, mids and highs
+    Bands,
+}
+
+/// A strip of RGB pixels lit by each analysis window
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LedConfig {
+    /// `host` or `host:port` of the controller.  The protocol's port if none is given.
+    pub address: String,
+    pub protocol: LedProtocol,
+    /// Pixels on the strip
+    pub length: usize,
+    pub mapping: LedMapping,
+    /// First DMX universe for E1.31 and Art-Net.  170 pixels fit in each.
+    pub universe: u16,
+    /// 0.0 to 1.0
+    pub brightness: f32,
+}
+
+impl Default for LedConfig {
+    fn default() -> Self {
+        LedConfig {
+            address: "127.0.0.1".into(),
+            protocol: LedProtocol::Wled,
+            length: 60,
+            mapping: LedMapping::Spectrum,
+            universe: 1,
+            brightness: 1.0,
+        }
+    }
+}
+
+/// LEDs behind a screen, lit with the average colors along the edges of each frame drawn.
+/// They run clockwise from the bottom left corner: up the left edge, along the top, down the
+/// right edge and back along the bottom.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct EdgesConfig {
+    /// Where the LEDs are sent, in an `[export.edges.led]` table.  `length` and `mapping` are
+    /// taken from the edges.
+    pub led: LedConfig,
+    pub left: u32,
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32,
+    /// Share of the frame's width or height averaged in from each edge
+    pub depth: f32,
+}
+
+impl Default for EdgesConfig {
+    fn default() -> Self {
+        EdgesConfig {
+            led: LedConfig::default(),
+            left: 18,
+            top: 32,
+            right: 18,
+            bottom: 32,
+            depth: 0.1,
+        }
+    }
+}
+
+/// Unattended installations.  The visualizer opens fullscreen on `video.monitor` without the
+/// settings window, scenes take turns, whatever fails is restarted, and input is ignored
+/// except for the exit combo.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct KioskConfig {
+    pub enabled: bool,
+    /// Shown in turn.  Every scene if empty.
+    pub scenes: Vec<Scene>,
+    /// Seconds each scene is shown.  0 stays on one.
+    pub scene_seconds: f32,
+    /// Keys that quit, such as `ctrl+alt+q`
+    pub exit_combo: String,
+    /// Where health is logged.  `kiosk-health.log` in the data directory if unset.
+    pub health_log: Option<PathBuf>,
+    /// Seconds between health lines
+    pub health_seconds: f32,
+}
+
+impl Default for KioskConfig {
+    fn default() -> Self {
+        KioskConfig {
+            enabled: false,
+            scenes: Vec::new(),
+            scene_seconds: 300.0,
+            exit_combo: "ctrl+alt+q".to_owned(),
+            health_log: None,
+            health_seconds: 60.0,
+        }
+    }
+}
+
+/// What the visualizer does when a new track is heard
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrackAction {
+    Nothing,
+    /// Show the next scene, of `kiosk.scenes` if any are listed
+    Scene,
+    Palette,
+}
+
+/// Track changes, heard from silent gaps between tracks and changes in the sound, so they
+/// work without track metadata
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TracksConfig {
+    pub on_change: TrackAction,
+}
+
+impl Default for TracksConfig {
+    fn default() -> Self {
+        TracksConfig { on_change: TrackAction::Nothing }
+    }
+}
+
+/// How the compared source's spectrogram is drawn with the main one's
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompareView {
+    /// The main source above the compared one, sharing the time axis
+    Split,
+    /// The compared source added over the main one
+    Overlay,
+}
+
+/// A second source analyzed alongside the first, such as two masters or an EQ's input and
+/// output.  Drawn in the scroll scene.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CompareConfig {
+    /// Sound server source name to compare with.  Unset compares nothing.
+    pub source: Option<String>,
+    pub view: CompareView,
+    /// Colors of the compared source, told apart from the main source's
+    pub palette: Palette,
+}
+
+impl Default for CompareConfig {
+    fn default() -> Self {
+        CompareConfig { source: None, view: CompareView::Split, palette: Palette::Ice }
+    }
+}
+
+/// What room lights are set through
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LightsBackend {
+    /// A Philips Hue bridge's REST API, setting a group or entertainment area
+    Hue,
+    /// JSON light commands published to an MQTT broker, as Home Assistant and zigbee2mqtt take
+    Mqtt,
+}
+
+/// Smart bulbs slowly following the music's dominant color, pulsing on beats
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LightsConfig {
+    pub backend: LightsBackend,
+    /// The bridge or broker, `host` or `host:port`
+    pub address: String,
+    /// Hue application key, or MQTT user
+    pub username: Option<String>,
+    /// MQTT only
+    pub password: Option<String>,
+    /// Hue group or entertainment area id
+    pub group: u32,
+    /// MQTT topic light commands are published to
+    pub topic: String,
+    /// Commands sent a second.  Hue bridges take about one a second for a group.
+    pub rate: f32,
+    /// Seconds the color takes to follow the music
+    pub fade: f32,
+}
+
+impl Default for LightsConfig {
+    fn default() -> Self {
+        LightsConfig {
+            backend: LightsBackend::Hue,
+            address: "127.0.0.1".into(),
+            username: None,
+            password: None,
+            group: 1,
+            topic: "e-nguyen/light/set".into(),
+            rate: 1.0,
+            fade: 2.0,
+        }
+    }
+}
+
+/// Music events published to an MQTT broker.  An empty topic leaves its events out.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MqttConfig {
+    /// The broker, `host` or `host:port`
+    pub address: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// A message per beat with the time and tempo
+    pub beat_topic: String,
+    /// The tempo, retained, whenever it changes by a beat a minute
+    pub bpm_topic: String,
+    /// `active` or `silent`, retained
+    pub activity_topic: String,
+    /// The playing track's title and artists, retained.  Empty when nothing is playing.
+    pub track_topic: String,
+    /// Seconds of quiet before the music is silent
+    pub silence_seconds: f32,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        MqttConfig {
+            address: "127.0.0.1".into(),
+            username: None,
+            password: None,
+            beat_topic: "e-nguyen/beat".into(),
+            bpm_topic: "e-nguyen/bpm".into(),
+            activity_topic: "e-nguyen/activity".into(),
+            track_topic: "e-nguyen/track".into(),
+            silence_seconds: 3.0,
+        }
+    }
+}
+
+/// Unset values fall back to `[analysis]`, `[video]` and `[background]`
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SceneSettings {
+    pub gain: Option<f32>,
+    pub smoothing: Option<f32>,
+    pub palette: Option<Palette>,
+    /// Only ever set in the config file
+    pub background: Option<BackgroundConfig>,
+}
+
+impl SceneSettings {
+    fn is_empty(&self) -> bool {
+        self == &SceneSettings::default()
+    }
+
+    /// Take the keys that went from `was` to `now`, leaving the rest as they are
+    fn edit(&mut self, was: &SceneSettings, now: &SceneSettings) {
+        if now.gain != was.gain {
+            self.gain = now.gain;
+        }
+        if now.smoothing != was.smoothing {
+            self.smoothing = now.smoothing;
+        }
+        if now.palette != was.palette {
+            self.palette = now.palette;
+        }
+        if now.background != was.background {
+            self.background = now.background.clone();
+        }
+    }
+}
+
+/// What the visualizer was left showing, restored on the next launch.  Unset values follow
+/// `[video]`.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Session {
+    pub scene: Option<Scene>,
+    pub fps_cap: Option<u32>,
+}
+
+/// What `--scene` and `--palette` show this launch, over the session and scene tuning.
+/// Left out of what's remembered unless changed while running.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LaunchChoice {
+    pub scene: Option<Scene>,
+    pub palette: Option<Palette>,
+    /// `--demo` turns through every scene
+    pub demo: bool,
+}
+
+/// Size and position are logical pixels of the windowed, not fullscreen, window
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct WindowGeometry {
+    pub size: Option<(u32, u32)>,
+    pub position: Option<(i32, i32)>,
+    pub monitor: Option<String>,
+    pub fullscreen: bool,
 }
 
 impl ENguyenConfig {
-    pub fn parse(path: &PathBuf) -> Result<ENguyenConfig, Box<Error>> {
+    pub fn parse(path: &PathBuf) -> Result<ENguyenConfig, ENguyenError> {
         let mut config_toml = String::new();
         {
             let _held = CONFIG_LOCK.lock();
             let mut f = File::open(path)?;
             f.read_to_string(&mut config_toml)?;
         }
-        let parsed = toml::from_str(&config_toml)?;
-        Ok(parsed)
+        ENguyenConfig::from_toml(&config_toml).map_err(|e| {
+            ENguyenError::config(format!("Invalid config in {}", path.display())).caused_by(e)
+        })
+    }
+
+    /// The config written in `text`, as a config file would have it
+    pub fn from_toml(text: &str) -> Result<ENguyenConfig, toml::de::Error> {
+        toml::from_str(text)
+    }
+
+    /// Overlay `key=value` pairs such as `analysis.fft_size=4096`.  Values are read as TOML
+    /// and fall back to plain strings, so `video.present_mode=mailbox` needs no quotes.
+    pub fn with_overrides(self, overrides: &[String]) -> Result<ENguyenConfig, ENguyenError> {
+        let mut values = Vec::new();
+        for assignment in overrides {
+            let mut split = assignment.splitn(2, '=');
+            let (key, raw) = match (split.next(), split.next()) {
+                (Some(key), Some(raw)) if !key.trim().is_empty() => (key.trim(), raw.trim()),
+                _ => {
+                    let message = format!("Expected key=value, got: {}", assignment);
+                    return Err(ENguyenError::config(message));
+                },
+            };
+            let value = match toml::from_str::<toml::Value>(&format!("v = {}", raw)) {
+                Ok(toml::Value::Table(mut parsed)) => parsed.remove("v").unwrap(),
+                _ => toml::Value::String(raw.to_owned()),
+            };
+            values.push((key.to_owned(), value));
+        }
+        self.overlay(values)
     }
 
-    pub fn save(&self, path_override: Option<PathBuf>) -> Result<(), Box<Error>> {
+    /// Overlay the `[profile.<name>]` table onto the rest of the config
+    pub fn with_profile(self, name: &str) -> Result<ENguyenConfig, ENguyenError> {
+        let profile = match self.profiles.get(name) {
+            Some(profile) => profile.clone(),
+            None => return Err(ENguyenError::config(format!("No such profile: {}", name))),
+        };
+        let mut values = Vec::new();
+        flatten("", toml::Value::Table(profile), &mut values);
+        self.overlay(values)
+    }
+
+    /// Set each dotted key to its value, then check the result still reads as a config
+    fn overlay(self, values: Vec<(String, toml::Value)>) -> Result<ENguyenConfig, ENguyenError> {
+        let mut tree = toml::Value::try_from(&self)?;
+        for (key, value) in values.iter() {
+            set_key(&mut tree, key, value.clone())?;
+        }
+        let overridden: ENguyenConfig = tree.try_into()?;
+
+        // keys the config doesn't have are dropped when deserializing.  Catch typos.
+        let reserialized = toml::Value::try_from(&overridden)?;
+        for (key, _) in values.iter() {
+            if get_key(&reserialized, key).is_none() {
+                return Err(ENguyenError::config(format!("Unknown config key: {}", key)));
+            }
+        }
+        Ok(overridden)
+    }
+
+    pub fn save(&self, path_override: Option<PathBuf>) -> Result<(), ENguyenError> {
         let path = path_override.unwrap_or_else(|| default_config_path());
         let mut cloned = path.clone();
         cloned.pop();
@@ -66,9 +849,131 @@ impl ENguyenConfig {
         Ok(())
     }
 
+    /// Runtime parameters as configured
+    pub fn parameters(&self) -> Parameters {
+        let mut params = Parameters::default();
+        self.apply_live(&mut params);
+        params
+    }
+
+    /// Copy the settings that can change while running into `params`
+    pub fn apply_live(&self, params: &mut Parameters) {
+        let scene = self.launch.scene.or(self.session.scene).unwrap_or(self.video.scene);
+        params.apply(ParamChange::Scene(scene));
+        params.apply(ParamChange::FpsCap(self.session.fps_cap.unwrap_or(self.video.fps_cap)));
+        params.apply(ParamChange::ResolutionScale(self.video.resolution_scale));
+        params.apply(ParamChange::NoiseFloor(self.analysis.noise_floor));
+        self.apply_scene(scene, params);
+    }
+
+    /// Tune `params` for `scene`.  Its table takes precedence over the global settings.
+    pub fn apply_scene(&self, scene: Scene, params: &mut Parameters) {
+        self.apply_global_tuning(params);
+        if let Some(tuning) = self.scenes.get(&scene.to_string()) {
+            if let Some(gain) = tuning.gain {
+                params.apply(ParamChange::Gain(gain));
+            }
+            if let Some(smoothing) = tuning.smoothing {
+                params.apply(ParamChange::Smoothing(smoothing));
+            }
+            if let Some(palette) = tuning.palette {
+                params.apply(ParamChange::Palette(palette));
+            }
+        }
+        if let Some(palette) = self.launch.palette {
+            params.apply(ParamChange::Palette(palette));
+        }
+    }
+
+    /// The background beneath `scene`.  Its table takes precedence over `[background]`.
+    pub fn background(&self, scene: Scene) -> &BackgroundConfig {
+        let tuning = self.scenes.get(&scene.to_string());
+        tuning.and_then(|tuning| tuning.background.as_ref()).unwrap_or(&self.background)
+    }
+
+    /// Remember the tuning in `params` for the scene they're showing.  Only values that
+    /// differ from the global settings are kept.
+    pub fn record_scene(&mut self, params: &Parameters) {
+        let mut global = *params;
+        self.apply_global_tuning(&mut global);
+        let differs = |value, global| if value != global { Some(value) } else { None };
+        let name = params.scene.to_string();
+        let recorded = self.scenes.get(&name);
+        let palette = if self.launch.palette.is_some() {
+            // set aside for this launch, not forgotten
+            recorded.and_then(|tuning| tuning.palette)
+        } else if params.palette != global.palette {
+            Some(params.palette)
+        } else {
+            None
+        };
+        let tuning = SceneSettings {
+            gain: differs(params.gain, global.gain),
+            smoothing: differs(params.smoothing, global.smoothing),
+            palette,
+            background: recorded.and_then(|tuning| tuning.background.clone()),
+        };
+        if tuning.is_empty() {
+            self.scenes.remove(&name);
+        } else {
+            self.scenes.insert(name, tuning);
+        }
+    }
+
+    /// Remember the scene `params` show, its tuning and the fps cap for the next launch.  As
+    /// with scene tuning, only values that differ from `[video]` are kept.
+    pub fn record_session(&mut self, params: &Parameters) {
+        // choices made at launch last until changed, and only then are they remembered
+        if self.launch.scene.map_or(false, |scene| scene != params.scene) {
+            self.launch.scene = None;
+        }
+        if self.launch.palette.map_or(false, |palette| palette != params.palette) {
+            self.launch.palette = None;
+        }
+        let video = &self.video;
+        if self.launch.scene.is_none() {
+            self.session.scene =
+                if params.scene != video.scene { Some(params.scene) } else { None };
+        }
+        self.session.fps_cap =
+            if params.fps_cap != video.fps_cap { Some(params.fps_cap) } else { None };
+        self.record_scene(params);
+    }
+
+    fn apply_global_tuning(&self, params: &mut Parameters) {
+        params.apply(ParamChange::Gain(self.analysis.gain));
+        params.apply(ParamChange::Smoothing(self.analysis.smoothing));
+        params.apply(ParamChange::Palette(self.video.palette));
+    }
+
+    /// The configured GPU, by UUID or, for files that predate it, by index
+    pub fn physical_device<'p>(&self, picker: &'p ewin::GpuPicker) -> Option<PhysicalDevice<'p>> {
+        match &self.physical_device_uuid {
+            Some(uuid) => picker.find_by_uuid(uuid),
+            None => {
+                PhysicalDevice::from_index(&picker.instance, self.physical_device_index as usize)
+            },
+        }
+    }
+
+    /// Replace an index-only device setting with the UUID of the device it refers to today.
+    /// True if anything changed.
+    pub fn migrate_device(&mut self, picker: &ewin::GpuPicker) -> bool {
+        if self.physical_device_uuid.is_some() {
+            return false;
+        }
+        let (uuid, name) = match self.physical_device(picker) {
+            Some(pd) => (ewin::format_uuid(pd.uuid()), pd.name()),
+            None => return false,
+        };
+        info!("Configured GPU #{} is {} ({})", self.physical_device_index, name, uuid);
+        self.physical_device_uuid = Some(uuid);
+        self.physical_device_name = Some(name);
+        true
+    }
+
     pub fn ready(&self, picker: &ewin::GpuPicker) -> bool {
-        // TODO UUID is more robust for verifying that config is referring to same GPU as before
-        match PhysicalDevice::from_index(&picker.instance, self.physical_device_index as usize) {
+        match self.physical_device(picker) {
             None => {
                 warn!("The configured physical device doesn't exist.  Update your settings");
                 warn!("Proceeding with a default configuration.");
@@ -77,6 +982,14 @@ impl ENguyenConfig {
             Some(pd) => ewin::GpuPicker::has_graphics(&pd),
         }
     }
+
+    /// The audio source to capture.  `audio.source` wins over `audio_input_index`.
+    pub fn source_choice(&self) -> SourceChoice {
+        let index = self.audio_input_index;
+        let index = if index >= 0 { Some(index as u32) } else { None };
+        let follow_default = self.audio.follow_default;
+        SourceChoice { name: self.audio.source.clone(), index, follow_default }
+    }
 }
 
 impl Default for ENguyenConfig {
@@ -84,7 +997,24 @@ impl Default for ENguyenConfig {
         ENguyenConfig {
             start_in_fullscreen: false,
             physical_device_index: 0,
+            physical_device_uuid: None,
+            physical_device_name: None,
             audio_input_index: -1,
+            audio: AudioConfig::default(),
+            analysis: AnalysisConfig::default(),
+            video: VideoConfig::default(),
+            piano: PianoConfig::default(),
+            logo: LogoConfig::default(),
+            background: BackgroundConfig::default(),
+            power: PowerConfig::default(),
+            export: ExportConfig::default(),
+            kiosk: KioskConfig::default(),
+            tracks: TracksConfig::default(),
+            compare: CompareConfig::default(),
+            scenes: BTreeMap::new(),
+            session: Session::default(),
+            profiles: BTreeMap::new(),
+            launch: LaunchChoice::default(),
         }
     }
 }
@@ -99,9 +1029,137 @@ pub fn try_load_config(path: PathBuf) -> Option<ENguyenConfig> {
     }
 }
 
-fn default_config_path() -> PathBuf {
+/// Change only what `edit` touches in the file at `path`, so command line overrides don't end
+/// up in it.  A missing file is created.  A file that doesn't parse is left alone.
+pub fn edit_file<F>(path: &PathBuf, edit: F) -> Result<(), ENguyenError>
+where
+    F: FnOnce(&mut ENguyenConfig),
+{
+    let on_disk = if path.exists() { ENguyenConfig::parse(path)? } else { Default::default() };
+    let mut edited = on_disk.clone();
+    edit(&mut edited);
+    if edited == on_disk && path.exists() {
+        return Ok(());
+    }
+    edited.save(Some(path.clone()))
+}
+
+/// Write what the running app remembers, window geometry, scene tuning and the session, to
+/// `path`.  Only scene keys that changed since `loaded` are written, so tuning that came from a
+/// profile or `--set` stays out of the file.
+pub fn remember(
+    path: &PathBuf,
+    running: &ENguyenConfig,
+    loaded: &BTreeMap<String, SceneSettings>,
+) -> Result<(), ENguyenError> {
+    edit_file(path, |on_disk| {
+        on_disk.video.window = running.video.window.clone();
+        on_disk.session = running.session.clone();
+        let names: BTreeSet<&String> = running.scenes.keys().chain(loaded.keys()).collect();
+        for name in names {
+            let was = loaded.get(name).cloned().unwrap_or_default();
+            let now = running.scenes.get(name).cloned().unwrap_or_default();
+            if was == now {
+                continue;
+            }
+            let mut kept = on_disk.scenes.remove(name).unwrap_or_default();
+            kept.edit(&was, &now);
+            if !kept.is_empty() {
+                on_disk.scenes.insert(name.clone(), kept);
+            }
+        }
+    })
+}
+
+fn set_key(tree: &mut toml::Value, key: &str, value: toml::Value) -> Result<(), ENguyenError> {
+    let mut parts: Vec<&str> = key.split('.').collect();
+    let leaf = parts.pop().unwrap_or(key);
+    let mut table = tree;
+    for part in parts {
+        table = match table {
+            toml::Value::Table(t) => {
+                t.entry(part.to_owned()).or_insert_with(|| toml::Value::Table(Default::default()))
+            },
+            _ => return Err(ENguyenError::config(format!("{} is not a section", key))),
+        };
+    }
+    match table {
+        toml::Value::Table(t) => {
+            t.insert(leaf.to_owned(), value);
+            Ok(())
+        },
+        _ => Err(ENguyenError::config(format!("{} is not a section", key))),
+    }
+}
+
+/// Dotted keys of every value in `value` that isn't itself a table
+fn flatten(prefix: &str, value: toml::Value, into: &mut Vec<(String, toml::Value)>) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table {
+                let dotted = if prefix.is_empty() { key } else { format!("{}.{}", prefix, key) };
+                flatten(&dotted, value, into);
+            }
+        },
+        leaf => into.push((prefix.to_owned(), leaf)),
+    }
+}
+
+fn get_key<'t>(tree: &'t toml::Value, key: &str) -> Option<&'t toml::Value> {
+    key.split('.').try_fold(tree, |value, part| value.get(part))
+}
+
+/// Stops watching when dropped
+pub struct ConfigWatcher {
+    _watcher: RecommendedWatcher,
+}
+
+/// Calls `on_change` with every valid edit of the file at `path`.  Invalid edits are logged
+/// and skipped so the last good config stays in effect.
+pub fn watch<F>(path: PathBuf, mut on_change: F) -> Result<ConfigWatcher, ENguyenError>
+where
+    F: FnMut(ENguyenConfig) + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel();
+    let mut watcher: RecommendedWatcher = Watcher::new(tx, RELOAD_DEBOUNCE)?;
+    // editors often replace the file instead of writing it, so watch the directory
+    let dir = match path.parent() {
+        Some(dir) if dir != Path::new("") => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    std::fs::create_dir_all(&dir)?;
+    watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+    let file_name = path.file_name().map(|name| name.to_owned());
+
+    thread::spawn(move || {
+        // ends when the watcher is dropped along with its sender
+        for event in rx.iter() {
+            let touched = match &event {
+                DebouncedEvent::Create(p) | DebouncedEvent::Write(p) => p,
+                DebouncedEvent::Rename(_, p) => p,
+                _ => continue,
+            };
+            if touched.file_name() != file_name.as_ref().map(|name| name.as_os_str()) {
+                continue;
+            }
+            match ENguyenConfig::parse(&path) {
+                Ok(config) => {
+                    info!("Reloaded {}", path.display());
+                    on_change(config);
+                },
+                Err(e) => error!("Ignoring invalid edit of {}: {}", path.display(), e),
+            }
+        }
+    });
+    Ok(ConfigWatcher { _watcher: watcher })
+}
+
+pub fn default_config_path() -> PathBuf {
     // TODO platform independence
-    let mut p = PathBuf::from(DEFAULT_CONF_DIR);
+    let mut p = match (DEFAULT_CONF_DIR.starts_with("~/"), std::env::var_os("HOME")) {
+        (true, Some(home)) => PathBuf::from(home).join(&DEFAULT_CONF_DIR[2..]),
+        _ => PathBuf::from(DEFAULT_CONF_DIR),
+    };
     p.push(DEFAULT_TOML_FILE);
     p
 }
@@ -118,7 +1176,10 @@ mod tests {
 
     #[test]
     fn get_default_path() {
-        default_config_path();
+        let path = default_config_path();
+        if std::env::var_os("HOME").is_some() {
+            assert!(!path.starts_with("~"));
+        }
     }
 
     #[test]
@@ -140,6 +1201,323 @@ mod tests {
         assert_eq!(loaded.unwrap().start_in_fullscreen, true);
     }
 
+    #[test]
+    fn live_settings() {
+        let mut en_conf = ENguyenConfig::default();
+        en_conf.analysis.gain = 2.0;
+        en_conf.video.fps_cap = 30;
+        en_conf.video.palette = Palette::Ice;
+        en_conf.video.resolution_scale = 1.5;
+        let params = en_conf.parameters();
+        assert_eq!(params.gain, 2.0);
+        assert_eq!(params.fps_cap, 30);
+        assert_eq!(params.resolution_scale, 1.5);
+        assert_eq!(params.palette, Palette::Ice);
+    }
+
+    #[test]
+    fn watch_reloads_valid_edits() {
+        let mut dir = std::env::temp_dir();
+        dir.push(format!("e-nguyen-watch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut path = dir.clone();
+        path.push(DEFAULT_TOML_FILE);
+        ENguyenConfig::default().save(Some(path.clone())).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let _watcher = watch(path.clone(), move |config| tx.send(config).unwrap()).unwrap();
+        thread::sleep(Duration::from_millis(100));
+
+        // an invalid edit is skipped
+        std::fs::write(&path, "analysis = \"loud\"").unwrap();
+        assert!(rx.recv_timeout(RELOAD_DEBOUNCE * 4).is_err());
+
+        let mut en_conf = ENguyenConfig::default();
+        en_conf.video.fps_cap = 24;
+        en_conf.save(Some(path.clone())).unwrap();
+        let reloaded = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(reloaded.video.fps_cap, 24);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn scenes_remember_tuning() {
+        let mut en_conf = ENguyenConfig::default();
+        en_conf.analysis.gain = 2.0;
+        let mut params = en_conf.parameters();
+
+        // only what differs from the globals is kept
+        params.apply(ParamChange::Palette(Palette::Ember));
+        en_conf.record_scene(&params);
+        let tuning = &en_conf.scenes["scroll"];
+        assert_eq!(tuning.palette, Some(Palette::Ember));
+        assert_eq!(tuning.gain, None);
+
+        let as_toml = toml::to_string_pretty(&en_conf).unwrap();
+        assert!(as_toml.contains("[scene.scroll]"));
+        let parsed: ENguyenConfig = toml::from_str(&as_toml).unwrap();
+        assert_eq!(parsed.parameters().palette, Palette::Ember);
+        assert_eq!(parsed.parameters().gain, 2.0);
+
+        // back at the globals, the table goes away
+        params.apply(ParamChange::Palette(Palette::Nguyen));
+        en_conf.record_scene(&params);
+        assert!(en_conf.scenes.is_empty());
+
+        let overridden = ENguyenConfig::default()
+            .with_overrides(&["scene.scroll.smoothing=0.5".to_owned()])
+            .unwrap();
+        assert_eq!(overridden.parameters().smoothing, 0.5);
+    }
+
+    #[test]
+    fn sessions_resume() {
+        let mut en_conf = ENguyenConfig::default();
+        en_conf.video.fps_cap = 30;
+        let mut params = en_conf.parameters();
+        params.apply(ParamChange::FpsCap(0));
+        params.apply(ParamChange::Palette(Palette::Ice));
+        en_conf.record_session(&params);
+        assert_eq!(en_conf.session, Session { scene: None, fps_cap: Some(0) });
+
+        let as_toml = toml::to_string_pretty(&en_conf).unwrap();
+        let parsed: ENguyenConfig = toml::from_str(&as_toml).unwrap();
+        assert_eq!(parsed.parameters(), params);
+
+        // back at the [video] settings, nothing is kept
+        params.apply(ParamChange::FpsCap(30));
+        en_conf.record_session(&params);
+        assert_eq!(en_conf.session, Session::default());
+    }
+
+    #[test]
+    fn launch_choices_are_not_remembered() {
+        let mut en_conf = ENguyenConfig::default();
+        en_conf.session.scene = Some(Scene::Piano);
+        en_conf.scenes.insert(
+            "tunnel".to_owned(),
+            SceneSettings { palette: Some(Palette::Ice), ..Default::default() },
+        );
+        en_conf.launch.scene = Some(Scene::Tunnel);
+        en_conf.launch.palette = Some(Palette::Ember);
+        let mut params = en_conf.parameters();
+        assert_eq!((params.scene, params.palette), (Scene::Tunnel, Palette::Ember));
+
+        params.apply(ParamChange::Gain(2.0));
+        en_conf.record_session(&params);
+        assert_eq!(en_conf.session.scene, Some(Scene::Piano));
+        assert_eq!(en_conf.scenes["tunnel"].palette, Some(Palette::Ice));
+        assert_eq!(en_conf.scenes["tunnel"].gain, Some(2.0));
+
+        // switching away ends the launch's choice
+        params.apply(ParamChange::Scene(Scene::Scroll));
+        en_conf.record_session(&params);
+        assert_eq!(en_conf.launch.scene, None);
+        assert_eq!(en_conf.session.scene, None);
+    }
+
+    #[test]
+    fn overrides() {
+        let overrides = vec![
+            "analysis.fft_size=4096".to_owned(),
+            "video.present_mode=mailbox".to_owned(),
+            "audio.source = alsa_output.monitor".to_owned(),
+            "start_in_fullscreen=true".to_owned(),
+            "analysis.smoothing=0.5".to_owned(),
+            "audio.overflow=block_writer".to_owned(),
+        ];
+        let en_conf = ENguyenConfig::default().with_overrides(&overrides).unwrap();
+        assert_eq!(en_conf.analysis.fft_size, 4096);
+        assert_eq!(en_conf.video.present_mode, PresentMode::Mailbox);
+        assert_eq!(en_conf.audio.source, Some("alsa_output.monitor".to_owned()));
+        assert!(en_conf.start_in_fullscreen);
+        assert_eq!(en_conf.analysis.smoothing, 0.5);
+        assert_eq!(en_conf.audio.overflow, OverflowPolicy::BlockWriter);
+
+        let bad = |o: &str| ENguyenConfig::default().with_overrides(&[o.to_owned()]).is_err();
+        assert!(bad("analysis.fft_size=big"));
+        assert!(bad("video.present_mode=sometimes"));
+        assert!(bad("audio.overflow=sometimes"));
+        assert!(bad("video.colour=red"));
+        assert!(bad("analysis"));
+        assert!(bad("start_in_fullscreen.nested=1"));
+    }
+
+    #[test]
+    fn remembered_state_saved_alone() {
+        let mut test_path = std::env::temp_dir();
+        test_path.push(format!("test_remember_{}", DEFAULT_TOML_FILE));
+        let mut en_conf = ENguyenConfig::default();
+        en_conf.video.fps_cap = 30;
+        en_conf.save(Some(test_path.clone())).unwrap();
+
+        let mut running = ENguyenConfig::default();
+        running.video.fps_cap = 60;
+        running.video.window = WindowGeometry {
+            size: Some((1280, 720)),
+            position: Some((-1920, 40)),
+            monitor: Some("DP-1".to_owned()),
+            fullscreen: true,
+        };
+        let mut params = running.parameters();
+        params.apply(ParamChange::Gain(2.0));
+        running.record_scene(&params);
+        remember(&test_path, &running, &BTreeMap::new()).unwrap();
+        let loaded = ENguyenConfig::parse(&test_path).unwrap();
+        assert_eq!(loaded.video.window, running.video.window);
+        assert_eq!(loaded.scenes, running.scenes);
+        assert_eq!(loaded.video.fps_cap, 30);
+
+        // tuned while running, the fps cap resumes without changing [video]
+        params.apply(ParamChange::FpsCap(45));
+        running.record_session(&params);
+        remember(&test_path, &running, &BTreeMap::new()).unwrap();
+        let loaded = ENguyenConfig::parse(&test_path).unwrap();
+        assert_eq!(loaded.video.fps_cap, 30);
+        assert_eq!(loaded.parameters().fps_cap, 45);
+        assert_eq!(loaded.parameters().gain, 2.0);
+
+        // unparseable files are not overwritten
+        std::fs::write(&test_path, "video = 3").unwrap();
+        assert!(remember(&test_path, &running, &BTreeMap::new()).is_err());
+        assert_eq!(std::fs::read_to_string(&test_path).unwrap(), "video = 3");
+        std::fs::remove_file(&test_path).unwrap();
+    }
+
+    #[test]
+    fn overridden_scenes_not_remembered() {
+        let mut test_path = std::env::temp_dir();
+        test_path.push(format!("test_remember_overrides_{}", DEFAULT_TOML_FILE));
+        ENguyenConfig::default().save(Some(test_path.clone())).unwrap();
+
+        let overrides =
+            vec!["scene.scroll.gain=3.0".to_owned(), "scene.tunnel.smoothing=0.2".to_owned()];
+        let mut running = ENguyenConfig::default().with_overrides(&overrides).unwrap();
+        let loaded = running.scenes.clone();
+        let mut params = running.parameters();
+        params.apply(ParamChange::Scene(Scene::Scroll));
+        running.apply_scene(Scene::Scroll, &mut params);
+        params.apply(ParamChange::Palette(Palette::Ice));
+        running.record_scene(&params);
+        remember(&test_path, &running, &loaded).unwrap();
+
+        // only the palette was changed while running
+        let on_disk = ENguyenConfig::parse(&test_path).unwrap();
+        assert_eq!(on_disk.scenes.len(), 1);
+        assert_eq!(on_disk.scenes["scroll"].palette, Some(Palette::Ice));
+        assert_eq!(on_disk.scenes["scroll"].gain, None);
+        std::fs::remove_file(&test_path).unwrap();
+    }
+
+    #[test]
+    fn profiles_overlay() {
+        let with_profiles = "
+            [video]
+            fps_cap = 60
+            palette = \"ice\"
+
+            [profile.laptop]
+            video = { fps_cap = 30 }
+            analysis.fft_size = 1024
+
+            [profile.typo]
+            video.colour = \"red\"
+        ";
+        let parsed: ENguyenConfig = toml::from_str(with_profiles).unwrap();
+        assert_eq!(parsed.video.fps_cap, 60);
+
+        let laptop = parsed.clone().with_profile("laptop").unwrap();
+        assert_eq!(laptop.video.fps_cap, 30);
+        assert_eq!(laptop.video.palette, Palette::Ice);
+        assert_eq!(laptop.analysis.fft_size, 1024);
+        // profiles survive being applied so another can be chosen later
+        assert_eq!(laptop.profiles, parsed.profiles);
+
+        assert!(parsed.clone().with_profile("typo").is_err());
+        assert!(parsed.with_profile("party").is_err());
+    }
+
+    #[test]
+    fn old_files_parse() {
+        let old = "physical_device_index = 1\naudio_input_index = 2\nstart_in_fullscreen = true\n";
+        let parsed: ENguyenConfig = toml::from_str(old).unwrap();
+        assert_eq!(parsed.physical_device_index, 1);
+        assert_eq!(parsed.physical_device_uuid, None);
+        assert_eq!(parsed.analysis, AnalysisConfig::default());
+        assert_eq!(parsed.video, VideoConfig::default());
+        let choice = SourceChoice { index: Some(2), ..SourceChoice::default() };
+        assert_eq!(parsed.source_choice(), choice);
+        assert!(ENguyenConfig::default().source_choice().is_default());
+    }
+
+    #[test]
+    fn sections_round_trip() {
+        let mut en_conf = ENguyenConfig::default();
+        en_conf.audio.source = Some("alsa_output.monitor".to_owned());
+        en_conf.analysis.window = WindowFunction::Hann;
+        en_conf.analysis.layout = SpectrumLayout::Stereo;
+        en_conf.video.present_mode = PresentMode::Mailbox;
+        en_conf.video.palette = Palette::Ember;
+        let as_toml = toml::to_string_pretty(&en_conf).unwrap();
+        let parsed: ENguyenConfig = toml::from_str(&as_toml).unwrap();
+        assert_eq!(parsed.audio, en_conf.audio);
+        assert_eq!(parsed.analysis, en_conf.analysis);
+        assert_eq!(parsed.video, en_conf.video);
+
+        let partial = "[video]\nfps_cap = 30\n";
+        let parsed: ENguyenConfig = toml::from_str(partial).unwrap();
+        assert_eq!(parsed.video.fps_cap, 30);
+        assert_eq!(parsed.video.present_mode, PresentMode::Fifo);
+        assert_eq!(parsed.audio_input_index, -1);
+
+        let partial = "[piano]\nlowest_note = 48\n";
+        let parsed: ENguyenConfig = toml::from_str(partial).unwrap();
+        assert_eq!(parsed.piano.lowest_note, 48);
+        assert_eq!(parsed.piano.highest_note, PianoConfig::default().highest_note);
+
+        let partial = "[logo]\npath = \"/tmp/logo.png\"\nanchor = \"top-left\"\n";
+        let parsed: ENguyenConfig = toml::from_str(partial).unwrap();
+        assert_eq!(parsed.logo.path, Some(PathBuf::from("/tmp/logo.png")));
+        assert_eq!(parsed.logo.anchor, Anchor::TopLeft);
+        assert_eq!(parsed.logo.size, LogoConfig::default().size);
+
+        let partial = "[power]\nmode = \"never\"\n";
+        let parsed: ENguyenConfig = toml::from_str(partial).unwrap();
+        assert_eq!(parsed.power.mode, PowerMode::Never);
+        assert!(parsed.power.prefer_integrated);
+
+        let partial = "[export.led]\nprotocol = \"art-net\"\nlength = 300\n";
+        let parsed: ENguyenConfig = toml::from_str(partial).unwrap();
+        let led = parsed.export.led.unwrap();
+        assert_eq!((led.protocol, led.length), (LedProtocol::ArtNet, 300));
+        assert_eq!(led.mapping, LedMapping::Spectrum);
+
+        let partial = "[kiosk]\nenabled = true\nscenes = [\"tunnel\", \"piano\"]\n";
+        let parsed: ENguyenConfig = toml::from_str(partial).unwrap();
+        assert_eq!(parsed.kiosk.scenes, vec![Scene::Tunnel, Scene::Piano]);
+        assert_eq!(parsed.kiosk.exit_combo, "ctrl+alt+q");
+    }
+
+    #[test]
+    fn scenes_pick_backgrounds() {
+        let partial = "[background]\nkind = \"gradient\"\n\n\
+                       [scene.tunnel.background]\nkind = \"video-loop\"\npath = \"/tmp/loop\"\n";
+        let mut en_conf: ENguyenConfig = toml::from_str(partial).unwrap();
+        assert_eq!(en_conf.background(Scene::Scroll).kind, BackgroundKind::Gradient);
+        let tunnel = en_conf.background(Scene::Tunnel).clone();
+        assert_eq!(tunnel.kind, BackgroundKind::VideoLoop);
+        assert_eq!(tunnel.fps, BackgroundConfig::default().fps);
+
+        // recording tuning keeps the scene's background
+        let mut params = en_conf.parameters();
+        params.apply(ParamChange::Scene(Scene::Tunnel));
+        en_conf.record_scene(&params);
+        assert_eq!(en_conf.background(Scene::Tunnel), &tunnel);
+        let as_toml = toml::to_string_pretty(&en_conf).unwrap();
+        let parsed: ENguyenConfig = toml::from_str(&as_toml).unwrap();
+        assert_eq!(parsed.background(Scene::Tunnel), &tunnel);
+    }
+
     #[test]
     fn test_ready() {
         use crate::ewin::GpuPicker;
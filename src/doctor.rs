@@ -0,0 +1,316 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+use crate::audio;
+use crate::bench;
+use crate::errors::{step, ENguyenError};
+use crate::ewin::{Backend, GpuPicker, Session};
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer};
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::device::DeviceExtensions;
+use vulkano::format::Format;
+use vulkano::framebuffer::Framebuffer;
+use vulkano::image::{AttachmentImage, ImageUsage};
+use vulkano::instance::PhysicalDevice;
+use vulkano::sync;
+use vulkano::sync::GpuFuture;
+
+/// Where the Vulkan loader looks for ICD manifests on Linux, after `VK_ICD_FILENAMES`
+static ICD_DIRS: [&str; 3] =
+    ["/etc/vulkan/icd.d", "/usr/local/share/vulkan/icd.d", "/usr/share/vulkan/icd.d"];
+
+static LOADER_HELP: &str =
+    "https://vulkan.lunarg.com/doc/view/1.0.54.0/windows/LoaderAndLayerInterface.html#Overview";
+
+/// The color the offscreen render clears to, as RGBA bytes
+static CLEAR_BYTES: [u8; 4] = [255, 0, 255, 255];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// One line of the report.  `detail` may span lines.
+#[derive(Debug)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+}
+
+impl Check {
+    fn new<D: Into<String>>(name: &'static str, status: Status, detail: D) -> Check {
+        Check { name, status, detail: detail.into() }
+    }
+
+    /// The status tag, name and detail, with the tag colored for terminals if `color`
+    pub fn line(&self, color: bool) -> String {
+        let (tag, ansi) = match self.status {
+            Status::Pass => ("PASS", "32"),
+            Status::Warn => ("WARN", "33"),
+            Status::Fail => ("FAIL", "31"),
+        };
+        let tag = if color { format!("\x1b[{}m{}\x1b[0m", ansi, tag) } else { tag.to_owned() };
+        let detail = self.detail.replace('\n', "\n                         ");
+        format!("[{}] {:<17} {}", tag, self.name, detail)
+    }
+}
+
+/// Checks everything E-Nguyen needs, from the Vulkan loader to drawing and dispatching on
+/// the GPU and listening to the sound server.  Checks that need a GPU are skipped without
+/// one.
+pub fn run(load_layers: bool) -> Vec<Check> {
+    let mut checks = Vec::new();
+    checks.push(icds());
+
+    let picker = match GpuPicker::new(load_layers) {
+        Ok(picker) => {
+            checks.push(Check::new("Vulkan loader", Status::Pass, "Instance created"));
+            Some(picker)
+        },
+        Err(e) => {
+            let detail = format!("{}\nInstall your GPU's Vulkan driver.  See {}", e, LOADER_HELP);
+            checks.push(Check::new("Vulkan loader", Status::Fail, detail));
+            None
+        },
+    };
+
+    if let Some(picker) = &picker {
+        checks.push(devices(picker));
+        checks.push(window_system(picker));
+        checks.push(match offscreen_render(picker) {
+            Ok(detail) => Check::new("Offscreen render", Status::Pass, detail),
+            Err(e) => Check::new("Offscreen render", Status::Fail, e.to_string()),
+        });
+        checks.push(match bench::run(picker, Duration::from_millis(500)) {
+            Ok(ref report) if report.frames > 0 => {
+                let detail = format!("{} analysis frames in 0.5s", report.frames);
+                Check::new("Compute dispatch", Status::Pass, detail)
+            },
+            Ok(_) => Check::new("Compute dispatch", Status::Fail, "No analysis frames finished"),
+            Err(e) => Check::new("Compute dispatch", Status::Fail, e.to_string()),
+        });
+    }
+
+    checks.push(sound_server());
+    checks
+}
+
+/// Prints the report.  True if nothing failed.
+pub fn report(checks: &[Check], color: bool) -> bool {
+    for check in checks {
+        println!("{}", check.line(color));
+    }
+    let count = |status| checks.iter().filter(|c| c.status == status).count();
+    let failed = count(Status::Fail);
+    println!("{} passed, {} warnings, {} failed", count(Status::Pass), count(Status::Warn), failed);
+    failed == 0
+}
+
+fn icds() -> Check {
+    let manifests = match env::var("VK_ICD_FILENAMES") {
+        Ok(files) => files.split(':').filter(|f| !f.is_empty()).map(PathBuf::from).collect(),
+        Err(_) => icd_manifests(&ICD_DIRS.iter().map(PathBuf::from).collect::<Vec<_>>()),
+    };
+    if manifests.is_empty() {
+        Check::new("ICDs", Status::Warn, "No ICD manifests found.  The loader may find none.")
+    } else {
+        let listed: Vec<String> = manifests.iter().map(|m| m.display().to_string()).collect();
+        Check::new("ICDs", Status::Pass, listed.join("\n"))
+    }
+}
+
+/// JSON manifests in `dirs`, sorted.  Directories that don't exist are skipped.
+fn icd_manifests(dirs: &[PathBuf]) -> Vec<PathBuf> {
+    let mut manifests: Vec<PathBuf> = dirs
+        .iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flat_map(|entries| entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()))
+        .filter(|path| path.extension().and_then(|ext| ext.to_str()) == Some("json"))
+        .collect();
+    manifests.sort();
+    manifests
+}
+
+/// Each device needs a graphics queue and swapchain support to draw, and a compute queue to
+/// analyze
+fn devices(picker: &GpuPicker) -> Check {
+    let mut lines = Vec::new();
+    let mut can_draw = false;
+    let mut can_compute = false;
+    for pd in PhysicalDevice::enumerate(&picker.instance) {
+        let graphics = GpuPicker::has_graphics(&pd);
+        let compute = GpuPicker::has_compute(&pd);
+        let swapchain = DeviceExtensions::supported_by_device(pd).khr_swapchain;
+        can_draw |= graphics && swapchain;
+        can_compute |= compute;
+        lines.push(format!(
+            "{} [{:?}] graphics: {} compute: {} swapchain: {}",
+            pd.name(),
+            pd.ty(),
+            graphics,
+            compute,
+            swapchain
+        ));
+    }
+    let status = match (can_draw, can_compute) {
+        (true, true) => Status::Pass,
+        (false, false) => Status::Fail,
+        _ => Status::Warn,
+    };
+    if lines.is_empty() {
+        lines.push("No physical devices".to_owned());
+    }
+    Check::new("Devices", status, lines.join("\n"))
+}
+
+/// The window system windows would open on, without connecting to it
+fn window_system(picker: &GpuPicker) -> Check {
+    let session = Session::detect(&picker.instance);
+    match session.backend() {
+        Ok(Backend::XWayland) if session.forced.is_none() => {
+            let detail = "XWayland.  The Vulkan loader can't present to Wayland surfaces.";
+            Check::new("Window system", Status::Warn, detail)
+        },
+        Ok(backend) => Check::new("Window system", Status::Pass, backend.to_string()),
+        Err(e) => {
+            let detail = format!("{}\nOnly headless commands will work.", e);
+            Check::new("Window system", Status::Warn, detail)
+        },
+    }
+}
+
+/// Clears a small image in a render pass on the device windows would use and reads it back
+fn offscreen_render(picker: &GpuPicker) -> Result<String, ENguyenError> {
+    let (device, queues) = picker.windowless_device()?;
+    let queue = queues.graphics;
+    let usage = ImageUsage { color_attachment: true, transfer_source: true, ..ImageUsage::none() };
+    let image = step(
+        "Image creation failed",
+        AttachmentImage::with_usage(device.clone(), [16, 16], Format::R8G8B8A8Unorm, usage),
+    )?;
+    let render_pass = Arc::new(step(
+        "Render pass creation failed",
+        vulkano::single_pass_renderpass!(device.clone(),
+            attachments: {
+                color: {
+                    load: Clear,
+                    store: Store,
+                    format: Format::R8G8B8A8Unorm,
+                    samples: 1,
+                }
+            },
+            pass: {
+                color: [color],
+                depth_stencil: {}
+            }
+        ),
+    )?);
+    let attached = step("Image doesn't fit", Framebuffer::start(render_pass).add(image.clone()))?;
+    let framebuffer = Arc::new(step("Framebuffer creation failed", attached.build())?);
+    let zeroes = (0..16 * 16 * 4).map(|_| 0u8);
+    let readback = step(
+        "Readback buffer creation failed",
+        CpuAccessibleBuffer::from_iter(device.clone(), BufferUsage::transfer_destination(), zeroes),
+    )?;
+
+    let clear: Vec<f32> = CLEAR_BYTES.iter().map(|c| f32::from(*c) / 255.0).collect();
+    let clear_values = vec![[clear[0], clear[1], clear[2], clear[3]].into()];
+    let cb = step(
+        "Command buffer allocation failed",
+        AutoCommandBufferBuilder::primary_one_time_submit(device.clone(), queue.family()),
+    )?;
+    let cb = cb.begin_render_pass(framebuffer, false, clear_values);
+    let cb = step("Render pass begin failed", cb)?;
+    let cb = step("Render pass end failed", cb.end_render_pass())?;
+    let cb = step("Readback copy failed", cb.copy_image_to_buffer(image, readback.clone()))?;
+    let cb = step("Command buffer build failed", cb.build())?;
+    let submitted = step("Submit failed", sync::now(device.clone()).then_execute(queue, cb))?;
+    let fence = step("Flush failed", submitted.then_signal_fence_and_flush())?;
+    step("The GPU never finished", fence.wait(None))?;
+
+    let pixels = step("Readback failed", readback.read())?;
+    if pixels[..4] == CLEAR_BYTES {
+        Ok(format!("Cleared a 16x16 image on {}", device.physical_device().name()))
+    } else {
+        let message = format!("Read back {:?} instead of {:?}", &pixels[..4], CLEAR_BYTES);
+        Err(ENguyenError::render(message))
+    }
+}
+
+/// The visualizer captures the first monitor source, so one should exist
+fn sound_server() -> Check {
+    match audio::list_sources() {
+        Ok(ref sources) if sources.is_empty() => {
+            Check::new("Sound server", Status::Warn, "Connected, but it has no sources")
+        },
+        Ok(sources) => {
+            let names: Vec<&str> = sources.iter().map(|s| s.name()).collect();
+            match names.iter().find(|name| name.ends_with(".monitor")) {
+                Some(monitor) => {
+                    let detail = format!("{} sources.  Monitor: {}", names.len(), monitor);
+                    Check::new("Sound server", Status::Pass, detail)
+                },
+                None => {
+                    let detail = format!(
+                        "{} sources, but no monitor.  Set audio.source to one of: {}",
+                        names.len(),
+                        names.join(", ")
+                    );
+                    Check::new("Sound server", Status::Warn, detail)
+                },
+            }
+        },
+        Err(e) => Check::new("Sound server", Status::Fail, e.to_string()),
+    }
+}
+
+/// Terminals get colors unless `NO_COLOR` is set
+pub fn color_wanted() -> bool {
+    env::var_os("NO_COLOR").is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_are_tagged() {
+        let check = Check::new("Sound server", Status::Warn, "first\nsecond");
+        let plain = check.line(false);
+        assert!(plain.starts_with("[WARN] Sound server      first\n"), "{}", plain);
+        assert!(plain.ends_with("\n                         second"), "{}", plain);
+        assert!(check.line(true).starts_with("[\x1b[33mWARN\x1b[0m]"));
+    }
+
+    #[test]
+    fn manifests_are_found() {
+        let dir = env::temp_dir().join(format!("e-nguyen-icds-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("radeon_icd.x86_64.json"), "{}").unwrap();
+        fs::write(dir.join("README"), "").unwrap();
+        let found = icd_manifests(&[dir.clone(), dir.join("missing")]);
+        assert_eq!(found, vec![dir.join("radeon_icd.x86_64.json")]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,249 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! Backlight LEDs that match the visualizer.  After each frame is drawn a compute shader
+//! averages the pixels along its edges into a zone per LED, and the averages are read back a
+//! frame later, once the GPU is done with them, and sent out like any other LED strip.
+
+use crate::config::{EdgesConfig, LedConfig};
+use crate::errors::ENguyenError;
+use crate::ewin::is_srgb;
+use crate::led::LedSink;
+use crate::rendering::{resource_key, DescriptorCache};
+
+use log::warn;
+use std::sync::Arc;
+use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer};
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet};
+use vulkano::device::Device;
+use vulkano::format::Format;
+use vulkano::image::AttachmentImage;
+use vulkano::pipeline::{ComputePipeline, ComputePipelineAbstract};
+use vulkano::sampler::Sampler;
+
+/// A zone's corners in pixels, `max` excluded
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+struct Zone {
+    min: [u32; 2],
+    max: [u32; 2],
+}
+
+/// A zone per LED in a frame of `size` pixels, in the order of `EdgesConfig`
+fn zones(config: &EdgesConfig, size: [u32; 2]) -> Vec<Zone> {
+    let [width, height] = size;
+    let depth = |across: u32| ((across as f32 * config.depth).round() as u32).max(1).min(across);
+    let (deep_x, deep_y) = (depth(width), depth(height));
+    // the `index`th of `count` equal parts of `length`, at least a pixel long
+    let part = |length: u32, count: u32, index: u32| {
+        let start = (length * index / count).min(length - 1);
+        (start, (length * (index + 1) / count).max(start + 1))
+    };
+    let mut zones = Vec::new();
+    for led in 0..config.left {
+        let (top, bottom) = part(height, config.left, config.left - 1 - led);
+        zones.push(Zone { min: [0, top], max: [deep_x, bottom] });
+    }
+    for led in 0..config.top {
+        let (left, right) = part(width, config.top, led);
+        zones.push(Zone { min: [left, 0], max: [right, deep_y] });
+    }
+    for led in 0..config.right {
+        let (top, bottom) = part(height, config.right, led);
+        zones.push(Zone { min: [width - deep_x, top], max: [width, bottom] });
+    }
+    for led in 0..config.bottom {
+        let (left, right) = part(width, config.bottom, config.bottom - 1 - led);
+        zones.push(Zone { min: [left, height - deep_y], max: [right, height] });
+    }
+    zones
+}
+
+/// An average color read back from the GPU as an LED's pixel.  Frames in an sRGB format are
+/// sampled as linear light and encoded again, so the LEDs match what's on screen.
+fn pixel(color: [f32; 4], srgb: bool, brightness: f32) -> [u8; 3] {
+    let encode = |linear: f32| {
+        let linear = linear.clamp(0.0, 1.0);
+        if !srgb {
+            linear
+        } else if linear <= 0.003_130_8 {
+            linear * 12.92
+        } else {
+            1.055 * linear.powf(1.0 / 2.4) - 0.055
+        }
+    };
+    let scale = brightness.clamp(0.0, 1.0) * 255.0;
+    let byte = |channel: f32| (encode(channel) * scale).round() as u8;
+    [byte(color[0]), byte(color[1]), byte(color[2])]
+}
+
+/// Sets kept for each target image and colors buffer pairing.  Swapchains have at most a few
+/// images.
+static CACHED_SETS: usize = 8;
+
+/// Averages each frame's edges and sends them to the LEDs
+pub struct EdgeSampler {
+    device: Arc<Device>,
+    pipeline: Arc<dyn ComputePipelineAbstract + Send + Sync>,
+    sampler: Arc<Sampler>,
+    config: EdgesConfig,
+    /// Frames in this format are decoded from sRGB when sampled
+    srgb: bool,
+    /// Zones for frames of the size they were laid out for
+    zones: Option<([u32; 2], Arc<CpuAccessibleBuffer<[Zone]>>)>,
+    /// Written on alternate frames, so one can be read while the other is written
+    colors: [Arc<CpuAccessibleBuffer<[[f32; 4]]>>; 2],
+    /// The colors written next
+    next: usize,
+    sets: DescriptorCache,
+    /// The other colors were written and haven't been sent
+    written: bool,
+    /// None once sending failed
+    leds: Option<LedSink>,
+}
+
+impl EdgeSampler {
+    /// Samples frames drawn in `format`
+    pub fn new(
+        device: &Arc<Device>,
+        config: &EdgesConfig,
+        format: Format,
+    ) -> Result<EdgeSampler, ENguyenError> {
+        let shader = edge_colors::Shader::load(device.clone())
+            .map_err(|e| ENguyenError::render("Edge color shader failed to load").caused_by(e))?;
+        let pipeline = ComputePipeline::new(device.clone(), &shader.main_entry_point(), &())
+            .map_err(|e| ENguyenError::render("Edge color pipeline failed").caused_by(e))?;
+        let leds = config.left + config.top + config.right + config.bottom;
+        if leds == 0 {
+            return Err(ENguyenError::config("Edge colors need LEDs along at least one edge"));
+        }
+        let sampler = Sampler::simple_repeat_linear_no_mipmap(device.clone());
+        let usage = BufferUsage { storage_buffer: true, ..BufferUsage::none() };
+        let buffer = || {
+            let black = (0..leds).map(|_| [0.0; 4]);
+            CpuAccessibleBuffer::from_iter(device.clone(), usage, black)
+                .map_err(|e| ENguyenError::render("Edge color buffer failed").caused_by(e))
+        };
+        let colors = [buffer()?, buffer()?];
+        let led_config = LedConfig { length: leds as usize, ..config.led.clone() };
+        let srgb = is_srgb(format);
+        Ok(EdgeSampler {
+            device: device.clone(),
+            pipeline: Arc::new(pipeline),
+            sampler,
+            config: config.clone(),
+            srgb,
+            zones: None,
+            colors,
+            next: 0,
+            sets: DescriptorCache::new(CACHED_SETS),
+            written: false,
+            leds: Some(LedSink::connect(&led_config)?),
+        })
+    }
+
+    /// Sends the colors averaged a frame ago, if the GPU is done with them, and records
+    /// averaging `frame`.  Call outside the render pass.
+    pub fn sample(
+        &mut self,
+        cbb: AutoCommandBufferBuilder,
+        frame: Arc<AttachmentImage<Format>>,
+    ) -> Result<AutoCommandBufferBuilder, ENguyenError> {
+        if self.written {
+            self.send();
+        }
+        let size = frame.dimensions();
+        let zones = match &self.zones {
+            Some((made_for, zones)) if *made_for == size => zones.clone(),
+            _ => {
+                let usage = BufferUsage { storage_buffer: true, ..BufferUsage::none() };
+                let laid_out = zones(&self.config, size).into_iter();
+                let zones = CpuAccessibleBuffer::from_iter(self.device.clone(), usage, laid_out)
+                    .map_err(|e| ENguyenError::render("Edge zone buffer failed").caused_by(e))?;
+                self.zones = Some((size, zones.clone()));
+                self.sets.clear();
+                zones
+            },
+        };
+        let colors = &self.colors[self.next];
+        let key = vec![resource_key(&frame), resource_key(&zones), resource_key(colors)];
+        let set = self.sets.get(key, || {
+            let set = PersistentDescriptorSet::start(self.pipeline.clone(), 0)
+                .add_sampled_image(frame, self.sampler.clone())
+                .and_then(|set| set.add_buffer(zones.clone()))
+                .and_then(|set| set.add_buffer(colors.clone()))
+                .map_err(|e| ENguyenError::render("Edge color descriptor rejected").caused_by(e))?
+                .build()
+                .map_err(|e| {
+                    ENguyenError::render("Edge color descriptor set failed").caused_by(e)
+                })?;
+            Ok(Arc::new(set) as Arc<dyn DescriptorSet + Send + Sync>)
+        })?;
+        let cbb = cbb
+            .dispatch([zones.len() as u32, 1, 1], self.pipeline.clone(), set, ())
+            .map_err(|e| ENguyenError::render("Edge color dispatch rejected").caused_by(e))?;
+        self.next = 1 - self.next;
+        self.written = true;
+        Ok(cbb)
+    }
+
+    /// The colors not written next, unless the GPU still has them
+    fn send(&mut self) {
+        let pixels: Vec<[u8; 3]> = match self.colors[1 - self.next].read() {
+            Ok(colors) => {
+                let brightness = self.config.led.brightness;
+                colors.iter().map(|color| pixel(*color, self.srgb, brightness)).collect()
+            },
+            Err(_) => return,
+        };
+        self.written = false;
+        if let Some(leds) = &mut self.leds {
+            if let Err(e) = leds.send_pixels(&pixels) {
+                warn!("Stopped sending edge colors to {}: {}", self.config.led.address, e);
+                self.leds = None;
+            }
+        }
+    }
+}
+
+mod edge_colors {
+    include!(concat!(env!("OUT_DIR"), "/edge_colors.comp.rs"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edges_are_zoned_clockwise() {
+        let config = EdgesConfig { left: 2, top: 3, right: 2, bottom: 1, ..Default::default() };
+        let laid_out = zones(&config, [300, 100]);
+        assert_eq!(laid_out.len(), 8);
+        // up the left edge from the bottom
+        assert_eq!(laid_out[0], Zone { min: [0, 50], max: [30, 100] });
+        assert_eq!(laid_out[1], Zone { min: [0, 0], max: [30, 50] });
+        assert_eq!(laid_out[3], Zone { min: [100, 0], max: [200, 10] });
+        assert_eq!(laid_out[5], Zone { min: [270, 0], max: [300, 50] });
+        assert_eq!(laid_out[7], Zone { min: [0, 90], max: [300, 100] });
+
+        // more LEDs than pixels still get a pixel each
+        let crowded = EdgesConfig { top: 4, ..config };
+        assert!(zones(&crowded, [2, 2]).iter().all(|zone| zone.max[0] > zone.min[0]));
+
+        assert_eq!(pixel([1.0, 0.5, 0.0, 1.0], false, 1.0), [255, 128, 0]);
+        assert_eq!(pixel([0.0, 0.214, 1.0, 1.0], true, 1.0), [0, 127, 255]);
+    }
+}
@@ -13,123 +13,290 @@
 
 // Copyright 2019 E-Nguyen Developers.
 
-use failure::Fail;
-use log::error;
+use std::any::Any;
 use std::convert::From;
 use std::error::Error as OldError;
-use std::fmt;
-use vulkano::memory::DeviceMemoryAllocError;
-use vulkano::swapchain::{AcquireError, CapabilitiesError, SwapchainCreationError};
+use std::io;
+use thiserror::Error;
+use vulkano::swapchain::{AcquireError, SwapchainCreationError};
+use vulkano::sync::FlushError;
 
-/// Checked errors originating from Vulkan setup or Vulkano API
-#[derive(Debug, Fail)]
-pub enum VulkanoError {
-    #[fail(display = "No Vulkan implementation installed: {}", ice)]
-    NoVulkanInstalled { ice: vulkano::instance::InstanceCreationError },
-    #[fail(display = "Device creation error: {}", dce)]
-    DeviceCreation { dce: vulkano::device::DeviceCreationError },
-    #[fail(display = "Device capabilities error: {}", dce)]
-    DeviceCapabilities { dce: CapabilitiesError },
-    #[fail(display = "No device could draw to the window")]
-    CantDraw {},
-    #[fail(display = "Device out of memory: {}", doom)]
-    DeviceOom { doom: DeviceMemoryAllocError },
-    #[fail(display = "No display detected.  What do?")]
-    NoDisplay {},
-    #[fail(display = "Fatal: {}", msg)]
-    Fatal { msg: &'static str },
-    #[fail(display = "SwapchainCreationError {}", sce)]
-    SwapchainCreation { sce: SwapchainCreationError },
+/// Errors drawing a frame.  Recoverable ones go away once the swapchain matches the window
+/// again, so the frame is retried.  Fatal ones mean the window can't draw anymore.
+#[derive(Debug, Error)]
+pub enum FrameError {
+    #[error(transparent)]
+    Recoverable { err: ENguyenError },
+    #[error(transparent)]
+    Fatal { err: ENguyenError },
 }
 
-impl From<vulkano::device::DeviceCreationError> for VulkanoError {
-    fn from(dce: vulkano::device::DeviceCreationError) -> VulkanoError {
-        VulkanoError::DeviceCreation { dce }
+impl FrameError {
+    pub fn recoverable(err: ENguyenError) -> FrameError {
+        FrameError::Recoverable { err }
+    }
+
+    pub fn fatal(err: ENguyenError) -> FrameError {
+        FrameError::Fatal { err }
+    }
+
+    fn classified(recoverable: bool, err: ENguyenError) -> FrameError {
+        if recoverable {
+            FrameError::recoverable(err)
+        } else {
+            FrameError::fatal(err)
+        }
+    }
+
+    pub fn is_recoverable(&self) -> bool {
+        match self {
+            FrameError::Recoverable { .. } => true,
+            FrameError::Fatal { .. } => false,
+        }
     }
 }
 
-impl From<FrameError> for VulkanoError {
-    fn from(err: FrameError) -> VulkanoError {
-        VulkanoError::Fatal { msg: "Frame error in unrecoverable position" }
+impl From<&'static str> for FrameError {
+    fn from(msg: &'static str) -> FrameError {
+        FrameError::fatal(ENguyenError::render(msg))
     }
 }
 
-impl From<&'static str> for VulkanoError {
-    fn from(msg: &'static str) -> VulkanoError {
-        VulkanoError::Fatal { msg }
+impl From<ENguyenError> for FrameError {
+    fn from(err: ENguyenError) -> FrameError {
+        FrameError::fatal(err)
     }
 }
 
-impl From<CapabilitiesError> for VulkanoError {
-    fn from(dce: CapabilitiesError) -> VulkanoError {
-        VulkanoError::DeviceCapabilities { dce }
+impl From<FrameError> for ENguyenError {
+    fn from(fe: FrameError) -> ENguyenError {
+        match fe {
+            FrameError::Recoverable { err } | FrameError::Fatal { err } => err,
+        }
     }
 }
 
-impl From<SwapchainCreationError> for VulkanoError {
-    fn from(sce: SwapchainCreationError) -> VulkanoError {
-        VulkanoError::SwapchainCreation { sce }
+impl From<AcquireError> for FrameError {
+    fn from(ae: AcquireError) -> FrameError {
+        let recoverable = match ae {
+            AcquireError::OutOfDate | AcquireError::Timeout => true,
+            _ => false,
+        };
+        let err = ENguyenError::render("No swapchain image").caused_by(ae);
+        FrameError::classified(recoverable, err)
     }
 }
 
-impl From<DeviceMemoryAllocError> for VulkanoError {
-    fn from(doom: DeviceMemoryAllocError) -> VulkanoError {
-        VulkanoError::DeviceOom { doom }
+impl From<SwapchainCreationError> for FrameError {
+    fn from(sce: SwapchainCreationError) -> FrameError {
+        // happens while the user is resizing the window
+        let recoverable = match sce {
+            SwapchainCreationError::UnsupportedDimensions => true,
+            _ => false,
+        };
+        let err = ENguyenError::render("Swapchain recreation failed").caused_by(sce);
+        FrameError::classified(recoverable, err)
     }
 }
 
-impl From<vulkano::command_buffer::CommandBufferExecError> for VulkanoError {
-    fn from(_ugh: vulkano::command_buffer::CommandBufferExecError) -> VulkanoError {
-        VulkanoError::Fatal { msg: "Fixme" }
+impl From<FlushError> for FrameError {
+    fn from(fe: FlushError) -> FrameError {
+        let recoverable = match fe {
+            FlushError::OutOfDate | FlushError::Timeout => true,
+            _ => false,
+        };
+        let err = ENguyenError::render("Frame could not be presented").caused_by(fe);
+        FrameError::classified(recoverable, err)
     }
 }
 
-impl From<Box<OldError>> for VulkanoError {
-    fn from(err: Box<OldError>) -> VulkanoError {
-        error!("(This is a placeholder) Error: {}", err);
-        VulkanoError::Fatal { msg: "Placeholder error.  Check console error logs" }
+/// What caused an `ENguyenError`.  Sendable so errors can be returned from worker threads.
+pub type Cause = Box<dyn OldError + Send + Sync>;
+
+/// Errors returned across modules and threads.  The variant says which part of E-Nguyen
+/// failed, the message what it was doing, and the cause, if any, why.  Displayed as the
+/// message followed by the cause's.  The cause is only shown there and isn't returned as the
+/// `source`, so reports that walk sources don't print it twice.
+#[derive(Debug, Error)]
+pub enum ENguyenError {
+    #[error("Audio error: {message}{}", caused(.cause))]
+    Audio { message: String, cause: Option<Cause> },
+    #[error("Compute error: {message}{}", caused(.cause))]
+    Compute { message: String, cause: Option<Cause> },
+    #[error("Render error: {message}{}", caused(.cause))]
+    Render { message: String, cause: Option<Cause> },
+    #[error("Config error: {message}{}", caused(.cause))]
+    Config { message: String, cause: Option<Cause> },
+    #[error("I/O error: {message}{}", caused(.cause))]
+    Io { message: String, cause: Option<Cause> },
+}
+
+impl ENguyenError {
+    pub fn audio<M: Into<String>>(message: M) -> ENguyenError {
+        ENguyenError::Audio { message: message.into(), cause: None }
+    }
+
+    pub fn compute<M: Into<String>>(message: M) -> ENguyenError {
+        ENguyenError::Compute { message: message.into(), cause: None }
+    }
+
+    pub fn render<M: Into<String>>(message: M) -> ENguyenError {
+        ENguyenError::Render { message: message.into(), cause: None }
+    }
+
+    pub fn config<M: Into<String>>(message: M) -> ENguyenError {
+        ENguyenError::Config { message: message.into(), cause: None }
+    }
+
+    /// Attach the error that led to this one
+    pub fn caused_by<E: Into<Cause>>(mut self, error: E) -> ENguyenError {
+        *self.cause_mut() = Some(error.into());
+        self
+    }
+
+    fn cause_mut(&mut self) -> &mut Option<Cause> {
+        match self {
+            ENguyenError::Audio { cause, .. }
+            | ENguyenError::Compute { cause, .. }
+            | ENguyenError::Render { cause, .. }
+            | ENguyenError::Config { cause, .. }
+            | ENguyenError::Io { cause, .. } => cause,
+        }
     }
 }
 
-/// Recoverable frame errors
-#[derive(Debug, Fail)]
-pub enum FrameError {
-    #[fail(display = "Swapchain buffers require updating before next image acquisition: {}", ae)]
-    ImageAcquisition { ae: AcquireError },
-    #[fail(display = "Swapchain creation failed.  Recreate: {}", sce)]
-    SwapchainCreation { sce: SwapchainCreationError },
-    #[fail(display = "Generic error: {}", msg)]
-    Generic { msg: &'static str },
+/// Wraps a Vulkan error with what was being tried
+pub fn step<T, E: Into<Cause>>(
+    what: &'static str,
+    result: Result<T, E>,
+) -> Result<T, ENguyenError> {
+    result.map_err(|e| ENguyenError::render(what).caused_by(e))
 }
 
-impl From<&'static str> for FrameError {
-    fn from(msg: &'static str) -> FrameError {
-        FrameError::Generic { msg }
+/// `": cause"` when there is one
+fn caused(cause: &Option<Cause>) -> String {
+    cause.as_ref().map_or(String::new(), |cause| format!(": {}", cause))
+}
+
+impl From<io::Error> for ENguyenError {
+    fn from(error: io::Error) -> ENguyenError {
+        let message = "File or socket operation failed".to_owned();
+        ENguyenError::Io { message, cause: Some(Box::new(error)) }
     }
 }
 
-/// Custom error for switching known error behavior.
-#[derive(Debug, Clone)]
-pub struct ENguyenError {
-    message: String,
+impl From<toml::de::Error> for ENguyenError {
+    fn from(error: toml::de::Error) -> ENguyenError {
+        ENguyenError::config("Invalid config").caused_by(error)
+    }
 }
 
-impl fmt::Display for ENguyenError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "ENguyenError: {}", self.message)
+impl From<toml::ser::Error> for ENguyenError {
+    fn from(error: toml::ser::Error) -> ENguyenError {
+        ENguyenError::config("Config could not be written").caused_by(error)
+    }
+}
+
+impl From<notify::Error> for ENguyenError {
+    fn from(error: notify::Error) -> ENguyenError {
+        ENguyenError::config("Config file can't be watched").caused_by(error)
+    }
+}
+
+/// Counts consecutive failures of something retried every frame or read, so a few failures
+/// in a row can be skipped over while a persistent one is given up on.
+#[derive(Debug)]
+pub struct Retry {
+    limit: u32,
+    failures: u32,
+}
+
+impl Retry {
+    pub fn new(limit: u32) -> Retry {
+        Retry { limit, failures: 0 }
+    }
+
+    /// Forget earlier failures
+    pub fn succeeded(&mut self) {
+        self.failures = 0;
+    }
+
+    /// Count a failure.  True once there have been too many in a row to keep retrying.
+    pub fn failed(&mut self) -> bool {
+        self.failures += 1;
+        self.failures >= self.limit
+    }
+
+    /// Failures since the last success
+    pub fn failures(&self) -> u32 {
+        self.failures
     }
 }
 
-impl From<&'static str> for ENguyenError {
-    fn from(s: &'static str) -> ENguyenError {
-        ENguyenError { message: s.to_owned() }
+/// The message a thread panicked with, when it's a string
+pub fn panic_message(payload: &Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_owned()
     }
 }
 
-impl OldError for ENguyenError {
-    fn description(&self) -> &str {
-        // https://doc.rust-lang.org/std/error/trait.Error.html
-        // This method is soft-deprecated.
-        "ENguyenError!"
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn causes_are_chained() {
+        let io = io::Error::new(io::ErrorKind::NotFound, "no such file");
+        let err = ENguyenError::config("Could not load e-nguyen.toml").caused_by(io);
+        assert_eq!(err.to_string(), "Config error: Could not load e-nguyen.toml: no such file");
+        assert!(err.source().is_none());
+
+        let err = ENguyenError::compute("Analysis stopped").caused_by(err);
+        assert!(err.to_string().starts_with("Compute error: Analysis stopped: Config error"));
+        assert!(err.to_string().ends_with("e-nguyen.toml: no such file"));
+
+        let err: ENguyenError = io::Error::new(io::ErrorKind::Other, "disk on fire").into();
+        match &err {
+            ENguyenError::Io { .. } => {},
+            other => panic!("wrong variant: {:?}", other),
+        }
+        assert!(ENguyenError::audio("silence").source().is_none());
+    }
+
+    #[test]
+    fn frame_errors_are_classified() {
+        assert!(FrameError::from(AcquireError::OutOfDate).is_recoverable());
+        assert!(FrameError::from(FlushError::OutOfDate).is_recoverable());
+        assert!(FrameError::from(SwapchainCreationError::UnsupportedDimensions).is_recoverable());
+        assert!(!FrameError::from(AcquireError::SurfaceLost).is_recoverable());
+        assert!(!FrameError::from(FlushError::DeviceLost).is_recoverable());
+        assert!(!FrameError::from("Overlay draw failed").is_recoverable());
+    }
+
+    #[test]
+    fn retries_give_up_on_persistent_failure() {
+        let mut retry = Retry::new(3);
+        assert!(!retry.failed());
+        assert!(!retry.failed());
+        retry.succeeded();
+        assert_eq!(retry.failures(), 0);
+
+        assert!(!retry.failed());
+        assert!(!retry.failed());
+        assert!(retry.failed());
+        assert_eq!(retry.failures(), 3);
+    }
+
+    #[test]
+    fn panic_messages() {
+        let payload = thread::spawn(|| panic!("static")).join().unwrap_err();
+        assert_eq!(panic_message(&payload), "static");
+        let payload = thread::spawn(|| panic!("formatted {}", 1)).join().unwrap_err();
+        assert_eq!(panic_message(&payload), "formatted 1");
     }
 }
@@ -0,0 +1,186 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! Beats, tempo changes, the music starting and stopping, and track changes, published to an
+//! MQTT broker as they happen so home automation can follow along.  Only changes are sent,
+//! and the state topics are retained so scripts that start later still see them.
+
+use crate::config::MqttConfig;
+use crate::errors::ENguyenError;
+use crate::export::{FeatureRecord, FeatureSink};
+use crate::metadata::{MetadataWatch, NowPlaying};
+use crate::mqtt::{MqttClient, Publication};
+
+use log::debug;
+use serde_json::json;
+use std::sync::mpsc::{SyncSender, TrySendError};
+
+/// Below this level in every band is quiet
+static SILENT_LEVEL: f32 = 0.05;
+/// Messages waiting for the broker before new ones are dropped
+static QUEUE: usize = 32;
+
+/// What was last published, to only publish changes
+struct MusicEvents {
+    config: MqttConfig,
+    bpm: Option<f32>,
+    active: Option<bool>,
+    /// `FeatureRecord::time` the music went quiet at
+    quiet_since: Option<f64>,
+    track: Option<Option<NowPlaying>>,
+}
+
+impl MusicEvents {
+    fn new(config: &MqttConfig) -> MusicEvents {
+        let config = config.clone();
+        MusicEvents { config, bpm: None, active: None, quiet_since: None, track: None }
+    }
+
+    /// What to publish for a window measured while `track` was playing
+    fn hear(&mut self, record: &FeatureRecord, track: Option<NowPlaying>) -> Vec<Publication> {
+        let mut events = Vec::new();
+        let config = &self.config;
+        let mut publish = |topic: &String, payload: String, retain: bool| {
+            if !topic.is_empty() {
+                let (topic, payload) = (topic.clone(), payload.into_bytes());
+                events.push(Publication { topic, payload, retain });
+            }
+        };
+
+        if record.onset {
+            let beat = json!({ "time": record.time, "bpm": record.bpm });
+            publish(&config.beat_topic, beat.to_string(), false);
+        }
+        let bpm = record.bpm.round();
+        if self.bpm != Some(bpm) {
+            self.bpm = Some(bpm);
+            publish(&config.bpm_topic, bpm.to_string(), true);
+        }
+
+        let quiet = record.bands.iter().all(|band| *band < SILENT_LEVEL);
+        let active = if quiet {
+            let quiet_for = record.time - *self.quiet_since.get_or_insert(record.time);
+            // quiet isn't silence until it's lasted
+            if quiet_for < f64::from(config.silence_seconds) {
+                self.active
+            } else {
+                Some(false)
+            }
+        } else {
+            self.quiet_since = None;
+            Some(true)
+        };
+        if active != self.active {
+            self.active = active;
+            let activity = if active == Some(true) { "active" } else { "silent" };
+            publish(&config.activity_topic, activity.to_owned(), true);
+        }
+
+        if self.track.as_ref() != Some(&track) {
+            let payload = match &track {
+                Some(playing) => json!({
+                    "title": playing.title,
+                    "artist": playing.artist(),
+                    "artists": playing.artists,
+                })
+                .to_string(),
+                None => String::new(),
+            };
+            publish(&config.track_topic, payload, true);
+            self.track = Some(track);
+        }
+        events
+    }
+}
+
+/// Publishes music events from a thread of its own
+pub struct EventSink {
+    events: MusicEvents,
+    /// Only watched if track changes are published
+    metadata: Option<MetadataWatch>,
+    publications: SyncSender<Publication>,
+}
+
+impl EventSink {
+    /// Track changes are read from `metadata`, or from a watch started for them if there's
+    /// none to share
+    pub fn connect(config: &MqttConfig, metadata: Option<&MetadataWatch>) -> EventSink {
+        let (username, password) = (config.username.clone(), config.password.clone());
+        let publications = MqttClient::new(&config.address, username, password).spawn(QUEUE);
+        let metadata = if config.track_topic.is_empty() {
+            None
+        } else {
+            Some(metadata.cloned().unwrap_or_else(MetadataWatch::start))
+        };
+        EventSink { events: MusicEvents::new(config), metadata, publications }
+    }
+}
+
+impl FeatureSink for EventSink {
+    fn send(&mut self, record: &FeatureRecord) -> Result<(), ENguyenError> {
+        let track = self.metadata.as_ref().and_then(MetadataWatch::now_playing);
+        for publication in self.events.hear(record, track) {
+            match self.publications.try_send(publication) {
+                Ok(()) => {},
+                Err(TrySendError::Full(dropped)) => {
+                    debug!("The broker is behind.  Dropped a message to {}", dropped.topic)
+                },
+                Err(TrySendError::Disconnected(_)) => {
+                    return Err(ENguyenError::compute("Music events stopped being published"))
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::features::AudioFeatures;
+
+    #[test]
+    fn only_changes_are_published() {
+        let config = MqttConfig { bpm_topic: String::new(), ..MqttConfig::default() };
+        let mut events = MusicEvents::new(&config);
+        let window = |level: f32, onset: bool, time: f64| {
+            let features = AudioFeatures { bands: [level; 4], ..AudioFeatures::default() };
+            FeatureRecord { onset, ..FeatureRecord::new(&features, time) }
+        };
+        let topics = |published: Vec<Publication>| -> Vec<(String, String)> {
+            let text = |payload| String::from_utf8(payload).unwrap();
+            published.into_iter().map(|sent| (sent.topic, text(sent.payload))).collect()
+        };
+        let track = NowPlaying { title: "Spectra".into(), artists: vec!["Nguyen".into()] };
+
+        let first = topics(events.hear(&window(0.5, true, 0.0), Some(track.clone())));
+        assert_eq!(first.len(), 3);
+        assert_eq!(first[0].0, "e-nguyen/beat");
+        assert_eq!(first[1], ("e-nguyen/activity".into(), "active".into()));
+        assert!(first[2].1.contains("\"title\":\"Spectra\""));
+        assert!(events.hear(&window(0.5, false, 0.5), Some(track.clone())).is_empty());
+
+        // quiet only becomes silence once it's lasted
+        assert!(events.hear(&window(0.0, false, 1.0), Some(track.clone())).is_empty());
+        let silent = topics(events.hear(&window(0.0, false, 4.0), None));
+        let expected = vec![
+            ("e-nguyen/activity".to_owned(), "silent".to_owned()),
+            ("e-nguyen/track".to_owned(), String::new()),
+        ];
+        assert_eq!(silent, expected);
+        let active = topics(events.hear(&window(0.3, false, 4.5), None));
+        assert_eq!(active, vec![("e-nguyen/activity".to_owned(), "active".to_owned())]);
+    }
+}
@@ -13,93 +13,265 @@
 
 // Copyright 2019 E-Nguyen Developers.
 
-use crate::errors::{ENguyenError, FrameError, VulkanoError};
+use crate::errors::{ENguyenError, FrameError};
+use crate::params::Scene;
 
-use log::{info, warn};
-use std::error::Error;
+use log::{debug, info, log, warn, Level};
+use std::env;
+use std::fmt;
 use std::sync::Arc;
+use std::thread;
 use vulkano::command_buffer::DynamicState;
-use vulkano::device::{Device, DeviceExtensions, Queue};
-use vulkano::framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract};
-use vulkano::image::SwapchainImage;
+use vulkano::device::{Device, DeviceExtensions, Features, Queue};
+use vulkano::format::Format;
+use vulkano::framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract, RenderPassDesc};
+use vulkano::image::{AttachmentImage, SwapchainImage};
+use vulkano::instance::debug::{DebugCallback, Message, MessageTypes};
 use vulkano::instance::PhysicalDeviceType;
-use vulkano::instance::{Instance, PhysicalDevice, QueueFamily};
+use vulkano::instance::{Instance, InstanceExtensions, PhysicalDevice, QueueFamily};
 use vulkano::pipeline::viewport::Viewport;
 use vulkano::swapchain;
 use vulkano::swapchain::Surface;
 use vulkano::swapchain::{
-    AcquireError, PresentMode, SurfaceTransform, Swapchain, SwapchainCreationError,
+    ColorSpace, CompositeAlpha, PresentMode, SupportedCompositeAlpha, SurfaceTransform, Swapchain,
 };
 use vulkano::sync::GpuFuture;
 use winit;
-use winit::Window;
+use winit::os::unix::EventsLoopExt;
+use winit::{EventsLoop, Window};
+
+/// Blocks until `device` has finished all submitted work.  Used on shutdown so nothing is
+/// destroyed while the GPU still uses it.
+pub fn wait_idle(device: &Device) {
+    // only unsafe if another thread keeps submitting, which shutdown has already stopped
+    if let Err(e) = unsafe { device.wait() } {
+        warn!("Waiting for the device to idle failed: {}", e);
+    }
+}
+
+/// A single pass drawing color in `format`.  With more than one sample it draws into a
+/// multisampled attachment and resolves into the second, which is the one presented.
+pub fn color_render_pass(
+    device: &Arc<Device>,
+    format: Format,
+    samples: u32,
+) -> Result<Arc<dyn RenderPassAbstract + Send + Sync>, FrameError> {
+    if samples <= 1 {
+        let render_pass = vulkano::single_pass_renderpass!(device.clone(),
+            attachments: {
+                color: { load: Clear, store: Store, format: format, samples: 1, }
+            },
+            pass: { color: [color], depth_stencil: {} }
+        )
+        .map_err(|_| "Render pass creation failed")?;
+        return Ok(Arc::new(render_pass));
+    }
+    let render_pass = vulkano::single_pass_renderpass!(device.clone(),
+        attachments: {
+            multisampled: { load: Clear, store: DontCare, format: format, samples: samples, },
+            color: { load: DontCare, store: Store, format: format, samples: 1, }
+        },
+        pass: { color: [multisampled], depth_stencil: {}, resolve: [color] }
+    )
+    .map_err(|_| "Multisampled render pass creation failed")?;
+    Ok(Arc::new(render_pass))
+}
+
+/// The most samples up to `wanted` that `physical` can draw color with.  1 if it can't
+/// multisample.
+pub fn supported_samples(physical: &PhysicalDevice, wanted: u32) -> u32 {
+    fit_samples(wanted, physical.limits().framebuffer_color_sample_counts())
+}
+
+/// The highest power of two up to `wanted` set in the `counts` bit mask, or 1
+fn fit_samples(wanted: u32, counts: u32) -> u32 {
+    (0..7)
+        .map(|bit| 1 << bit)
+        .filter(|&samples| samples <= wanted && counts & samples != 0)
+        .max()
+        .unwrap_or(1)
+}
+
+/// The device features to enable out of those `supported`.  Everything E-Nguyen draws and
+/// computes needs only core Vulkan, so this is never more than the optional features
+/// `Capabilities` reports.
+fn device_features(supported: &Features) -> Features {
+    Features {
+        large_points: supported.large_points,
+        shader_storage_image_extended_formats: supported.shader_storage_image_extended_formats,
+        ..Features::none()
+    }
+}
+
+/// Optional features a device was created with, checked before relying on them
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Capabilities {
+    /// Points wider than a pixel, which the goniometer draws its trace with
+    pub large_points: bool,
+    /// Storing 16 bit and packed floats, which the compact history formats need
+    pub extended_formats: bool,
+}
+
+impl Capabilities {
+    pub fn of(device: &Device) -> Capabilities {
+        let enabled = device.enabled_features();
+        Capabilities {
+            large_points: enabled.large_points,
+            extended_formats: enabled.shader_storage_image_extended_formats,
+        }
+    }
+
+    /// What `scene` needs that the device lacks, if anything
+    pub fn missing(&self, scene: Scene) -> Option<&'static str> {
+        match scene {
+            Scene::Goniometer if !self.large_points => Some("large points"),
+            _ => None,
+        }
+    }
+
+    pub fn supports(&self, scene: Scene) -> bool {
+        self.missing(scene).is_none()
+    }
+
+    /// `scene`, or the spectrogram if the device can't draw it
+    pub fn drawable(&self, scene: Scene) -> Scene {
+        if self.supports(scene) {
+            scene
+        } else {
+            Scene::Scroll
+        }
+    }
+
+    /// The scene after `scene` that the device can draw
+    pub fn next_scene(&self, scene: Scene) -> Scene {
+        let mut next = scene.next();
+        while !self.supports(next) {
+            next = next.next();
+        }
+        next
+    }
+}
+
+/// The image a multisampled `render_pass` from `color_render_pass` draws into before it
+/// resolves, sized `dimensions`.  None for passes that don't multisample.
+pub fn multisampled_image(
+    device: &Arc<Device>,
+    render_pass: &Arc<dyn RenderPassAbstract + Send + Sync>,
+    dimensions: [u32; 2],
+) -> Result<Option<Arc<AttachmentImage>>, FrameError> {
+    let first = render_pass.attachment_desc(0).ok_or("Render pass has no attachments")?;
+    if render_pass.num_attachments() < 2 || first.samples <= 1 {
+        return Ok(None);
+    }
+    let (samples, format) = (first.samples, first.format);
+    let image =
+        AttachmentImage::transient_multisampled(device.clone(), dimensions, samples, format)
+            .map_err(|_| "Multisampled image allocation failed")?;
+    Ok(Some(image))
+}
 
 /// A GPU chosen to draw to a surface, which owns a window, has a logical
 /// device configured and at least one graphics queue.
 pub struct SwapWindow {
     pub device: Arc<Device>,
     pub window_queue: Arc<Queue>,
+    /// Analysis dispatches go here, as `QueueAllocator` assigns them
+    pub compute_queue: Arc<Queue>,
+    /// Uploads go here.  A dedicated transfer queue when the GPU has one, otherwise the window
+    /// queue.
+    pub transfer_queue: Arc<Queue>,
     pub surface: Arc<Surface<Window>>,
     pub swapchain: Arc<Swapchain<Window>>,
     pub swap_images: Vec<Arc<SwapchainImage<Window>>>,
     pub dynamic_state: DynamicState,
+    /// Wanted transparent, and the compositor blends the window with what's behind it
+    pub transparent: bool,
 }
 
 impl<'a> SwapWindow {
     pub fn new(
         picker: &'a GpuPicker,
         surface: &Arc<Surface<Window>>,
-    ) -> Result<SwapWindow, VulkanoError> {
+        present_mode: PresentMode,
+        transparent: bool,
+    ) -> Result<SwapWindow, ENguyenError> {
         let physical = picker.discrete_or_first_device(&surface)?;
         info!("Using device: {} (type: {:?})", physical.name(), physical.ty());
 
-        let queue_family = GpuPicker::graphics_queue_fam(&physical, &surface)
-            .ok_or("Physical device has no graphics queue")?;
+        let allocator = QueueAllocator::new(&physical, Some(surface), true)
+            .ok_or_else(|| ENguyenError::render("Physical device has no graphics queue"))?;
         let device_ext = DeviceExtensions { khr_swapchain: true, ..DeviceExtensions::none() };
-        let (device, mut queues) = Device::new(
-            physical,
-            physical.supported_features(), // requests all supported features
-            &device_ext,
-            [(queue_family, 0.5)].iter().cloned(),
-        )?;
-
-        let window_queue =
-            queues.next().ok_or("Logical device creation returned no supported graphics queue")?;
-
-        let (swapchain, swap_images) = {
-            let caps = surface.capabilities(device.physical_device())?;
-            let alpha = caps
-                .supported_composite_alpha
-                .iter()
-                .next()
-                .ok_or("No supported alpha composite")?;
-            let initial_dimensions =
-                _dimensions(&surface.window()).ok_or("No window dimensions")?;
-
-            Swapchain::new(
+        let (device, queues) = allocator.create(physical, &device_ext)?;
+        SwapWindow::with_device(device, queues, surface, present_mode, transparent)
+    }
+
+    /// Attach a surface to a device that already exists, such as one kept warm in daemon mode.
+    /// Fifo is used if the device can't present with `present_mode`, and an opaque window if
+    /// it can't composite a `transparent` one.
+    pub fn with_device(
+        device: Arc<Device>,
+        queues: Queues,
+        surface: &Arc<Surface<Window>>,
+        present_mode: PresentMode,
+        transparent: bool,
+    ) -> Result<SwapWindow, ENguyenError> {
+        let window_queue = queues.graphics;
+        if !surface.is_supported(window_queue.family()).unwrap_or(false) {
+            return Err(ENguyenError::render(CANT_DRAW));
+        }
+
+        let (swapchain, swap_images, alpha) = {
+            let caps = surface.capabilities(device.physical_device()).map_err(|e| {
+                ENguyenError::render("Surface capabilities unavailable").caused_by(e)
+            })?;
+            let alpha = composite_alpha(caps.supported_composite_alpha, transparent)
+                .ok_or_else(|| ENguyenError::render("No supported alpha composite"))?;
+            if transparent && alpha == CompositeAlpha::Opaque {
+                warn!("The window can't be composited with alpha.  It will be opaque.");
+            }
+            let format = surface_format(&caps.supported_formats)
+                .ok_or_else(|| ENguyenError::render("No surface formats"))?;
+            if !is_srgb(format) {
+                warn!("No sRGB surface format.  Presenting {:?} will look too dark.", format);
+            }
+            let initial_dimensions = _dimensions(&surface.window())
+                .ok_or_else(|| ENguyenError::render("No window dimensions"))?;
+            let present_mode = if caps.present_modes.supports(present_mode) {
+                present_mode
+            } else {
+                warn!("Present mode {:?} isn't supported.  Using Fifo.", present_mode);
+                PresentMode::Fifo
+            };
+
+            let (swapchain, swap_images) = Swapchain::new(
                 device.clone(),
                 surface.clone(),
                 caps.min_image_count,
-                caps.supported_formats[0].0,
+                format,
                 initial_dimensions,
                 1,
                 caps.supported_usage_flags,
                 &window_queue.clone(),
                 SurfaceTransform::Identity,
                 alpha,
-                PresentMode::Fifo,
+                present_mode,
                 true,
                 None,
-            )?
+            )
+            .map_err(|e| ENguyenError::render("Swapchain creation failed").caused_by(e))?;
+            (swapchain, swap_images, alpha)
         };
 
         Ok(SwapWindow {
             device,
+            compute_queue: queues.compute,
+            transfer_queue: queues.transfer,
             window_queue,
             surface: surface.clone(),
             dynamic_state: DynamicState { line_width: None, viewports: None, scissors: None },
             swapchain,
             swap_images,
+            transparent: transparent && alpha != CompositeAlpha::Opaque,
         })
     }
 
@@ -114,37 +286,45 @@ impl<'a> SwapWindow {
             depth_range: 0.0..1.0,
         };
         self.dynamic_state.viewports = Some(vec![viewport]);
+        // every image shares one multisampled image.  Frames using it are drawn in order.
+        let multisampled = multisampled_image(&self.device, &render_pass, dimensions)?;
         // TODO duplicates code up above
-        let frames = self
-            .swap_images
+        self.swap_images
             .iter()
-            .map(|image| {
-                Arc::new(
-                    Framebuffer::start(render_pass.clone())
-                        .add(image.clone())
-                        .unwrap()
-                        .build()
-                        .unwrap(),
-                ) as Arc<FramebufferAbstract + Send + Sync>
+            .map(|image| -> Result<Arc<FramebufferAbstract + Send + Sync>, FrameError> {
+                let start = Framebuffer::start(render_pass.clone());
+                let framebuffer: Arc<FramebufferAbstract + Send + Sync> = match &multisampled {
+                    Some(multisampled) => Arc::new(
+                        start
+                            .add(multisampled.clone())
+                            .map_err(|_| "Multisampled image doesn't fit the render pass")?
+                            .add(image.clone())
+                            .map_err(|_| "Swapchain image doesn't fit the render pass")?
+                            .build()
+                            .map_err(|_| "Framebuffer creation failed")?,
+                    ),
+                    None => Arc::new(
+                        start
+                            .add(image.clone())
+                            .map_err(|_| "Swapchain image doesn't fit the render pass")?
+                            .build()
+                            .map_err(|_| "Framebuffer creation failed")?,
+                    ),
+                };
+                Ok(framebuffer)
             })
-            .collect::<Vec<Arc<FramebufferAbstract + Send + Sync>>>();
-        Ok(frames)
+            .collect()
     }
 
     pub fn recreate_swapchain(
         &mut self,
         render_pass: Arc<RenderPassAbstract + Send + Sync>,
     ) -> Result<Vec<Arc<FramebufferAbstract + Send + Sync>>, FrameError> {
-        let dimensions = self.dimensions().ok_or("No window dimensions")?;
-        let (new_swapchain, new_images) = match self.swapchain.recreate_with_dimension(dimensions) {
-            Ok(r) => r,
-            // This error tends to happen when the user is manually resizing the window.
-            // Simply restarting the loop is the easiest way to fix this issue.
-            Err(sce @ SwapchainCreationError::UnsupportedDimensions) => {
-                return Err(FrameError::SwapchainCreation { sce });
-            }
-            Err(err) => panic!("{:?}", err),
-        };
+        // a minimized window has no size to match
+        let dimensions = self
+            .dimensions()
+            .ok_or_else(|| FrameError::recoverable(ENguyenError::render("No window dimensions")))?;
+        let (new_swapchain, new_images) = self.swapchain.recreate_with_dimension(dimensions)?;
 
         self.swap_images = new_images;
         self.swapchain = new_swapchain;
@@ -153,13 +333,8 @@ impl<'a> SwapWindow {
     }
 
     pub fn future_image(&self) -> Result<(usize, Box<GpuFuture>), FrameError> {
-        match swapchain::acquire_next_image(self.swapchain.clone(), None) {
-            Ok((image_index, future)) => Ok((image_index, Box::new(future))),
-            Err(ae @ AcquireError::OutOfDate) | Err(ae @ AcquireError::SurfaceLost) => {
-                return Err(FrameError::ImageAcquisition { ae });
-            }
-            Err(err) => panic!("{:?}", err),
-        }
+        let (image_index, future) = swapchain::acquire_next_image(self.swapchain.clone(), None)?;
+        Ok((image_index, Box::new(future)))
     }
 
     pub fn dimensions(&self) -> Option<[u32; 2]> {
@@ -167,15 +342,51 @@ impl<'a> SwapWindow {
     }
 
     pub fn f_dimensions(&self) -> Option<[f32; 2]> {
-        match self.surface.window().get_inner_size() {
-            Some(dimensions) => {
-                // convert to physical pixels
-                let dimensions = dimensions.to_physical(self.surface.window().get_hidpi_factor());
-                return Some([dimensions.width as f32, dimensions.height as f32]);
-            }
-            None => None,
-        }
+        // the swapchain's whole pixels, even at fractional scale factors
+        self.dimensions().map(|[width, height]| [width as f32, height as f32])
+    }
+
+    /// Physical pixels per logical pixel
+    pub fn hidpi_factor(&self) -> f32 {
+        self.surface.window().get_hidpi_factor() as f32
     }
+
+    /// The window's size in logical pixels, which text and layouts are placed in so they
+    /// keep their size across monitors of different DPI
+    pub fn logical_dimensions(&self) -> Option<[f32; 2]> {
+        let hidpi = self.hidpi_factor();
+        self.f_dimensions().map(|[width, height]| [width / hidpi, height / hidpi])
+    }
+}
+
+/// The format to present in.  sRGB formats encode the linear colors shaders write for the
+/// display, and are taken over whatever the surface lists first.
+fn surface_format(supported: &[(Format, ColorSpace)]) -> Option<Format> {
+    supported
+        .iter()
+        .find(|(format, space)| is_srgb(*format) && *space == ColorSpace::SrgbNonLinear)
+        .or_else(|| supported.first())
+        .map(|(format, _)| *format)
+}
+
+/// Whether writes to `format` are encoded from linear to sRGB
+pub fn is_srgb(format: Format) -> bool {
+    format == Format::B8G8R8A8Srgb || format == Format::R8G8B8A8Srgb
+}
+
+/// Opaque unless `transparent` is wanted and the compositor can blend the window with
+/// what's behind it.  Scenes write premultiplied alpha.
+fn composite_alpha(
+    supported: SupportedCompositeAlpha,
+    transparent: bool,
+) -> Option<CompositeAlpha> {
+    let wanted: &[CompositeAlpha] = if transparent {
+        &[CompositeAlpha::PreMultiplied, CompositeAlpha::Inherit, CompositeAlpha::Opaque]
+    } else {
+        &[CompositeAlpha::Opaque]
+    };
+    let supports = |alpha: &CompositeAlpha| supported.iter().any(|s| s == *alpha);
+    wanted.iter().cloned().find(supports).or_else(|| supported.iter().next())
 }
 
 #[inline]
@@ -190,19 +401,54 @@ fn _dimensions(window: &Window) -> Option<[u32; 2]> {
     }
 }
 
+/// Logs validation layer messages with the `vulkan` target
+fn route_validation(instance: &Arc<Instance>) {
+    let everything = MessageTypes {
+        error: true,
+        warning: true,
+        performance_warning: true,
+        information: true,
+        debug: true,
+    };
+    match DebugCallback::new(instance, everything, |msg: &Message| {
+        let level = if msg.ty.error {
+            Level::Error
+        } else if msg.ty.warning || msg.ty.performance_warning {
+            Level::Warn
+        } else if msg.ty.information {
+            Level::Info
+        } else {
+            Level::Debug
+        };
+        log!(target: "vulkan", level, "{}: {}", msg.layer_prefix, msg.description);
+    }) {
+        // dropping the callback stops the messages.  It keeps the instance alive until exit.
+        Ok(callback) => std::mem::forget(callback),
+        Err(e) => warn!("Validation messages won't be logged: {}", e),
+    }
+}
+
 /// The Vulkan installation, the ICD's for devices, and the Vulkano Instance mainly provide
 /// the entry point to getting and evaluating the capability of physical devices.
 #[derive(Clone)]
 pub struct GpuPicker {
     pub instance: Arc<vulkano::instance::Instance>,
+    /// UUID of the device to use when it's capable, as written by `format_uuid`
+    preferred: Option<String>,
+    /// Favor integrated devices over discrete and preferred ones, to save power
+    integrated: bool,
 }
 
 static STANDARD_VALIDATION: &str = "VK_LAYER_LUNARG_standard_validation";
+static CANT_DRAW: &str = "No device could draw to the window";
 
 impl GpuPicker {
-    pub fn new(load_layers: bool) -> Result<GpuPicker, VulkanoError> {
+    pub fn new(load_layers: bool) -> Result<GpuPicker, ENguyenError> {
         let app_info = vulkano::app_info_from_cargo_toml!();
-        let extensions = vulkano_win::required_extensions();
+        let mut extensions = vulkano_win::required_extensions();
+        let debug_report = load_layers
+            && InstanceExtensions::supported_by_core().map(|e| e.ext_debug_report).unwrap_or(false);
+        extensions.ext_debug_report = debug_report;
         let instance = if load_layers {
             let found_standard =
                 vulkano::instance::layers_list().unwrap().find(|l| l.name() == STANDARD_VALIDATION);
@@ -215,56 +461,151 @@ impl GpuPicker {
             Instance::new(Some(&app_info), &extensions, None)
         };
         return match instance {
-            Ok(instance) => Ok(GpuPicker { instance }),
-            Err(no_vulkan) => Err(VulkanoError::NoVulkanInstalled { ice: no_vulkan }),
+            Ok(instance) => {
+                if debug_report {
+                    route_validation(&instance);
+                }
+                Ok(GpuPicker { instance, preferred: None, integrated: false })
+            },
+            Err(no_vulkan) => {
+                Err(ENguyenError::render("No Vulkan implementation installed").caused_by(no_vulkan))
+            },
         };
     }
 
+    /// Use the device with this UUID over discrete and first devices
+    pub fn prefer(&mut self, uuid: Option<String>) {
+        self.preferred = uuid;
+    }
+
+    /// Use integrated devices over the preferred one and discrete devices.  Applies to
+    /// devices created afterwards.
+    pub fn prefer_integrated(&mut self, integrated: bool) {
+        self.integrated = integrated;
+    }
+
+    /// The type of device picked when there's a choice
+    fn favored_type(&self) -> PhysicalDeviceType {
+        if self.integrated {
+            PhysicalDeviceType::IntegratedGpu
+        } else {
+            PhysicalDeviceType::DiscreteGpu
+        }
+    }
+
+    pub fn find_by_uuid(&self, uuid: &str) -> Option<PhysicalDevice> {
+        PhysicalDevice::enumerate(&self.instance)
+            .find(|pd| format_uuid(pd.uuid()).eq_ignore_ascii_case(uuid.trim()))
+    }
+
+    fn preferred_device(&self) -> Option<PhysicalDevice> {
+        let preferred = self.preferred.as_ref()?;
+        let found = self.find_by_uuid(preferred);
+        if found.is_none() {
+            warn!("The configured GPU {} is missing.  Picking another.", preferred);
+        }
+        found.filter(|pd| !self.integrated || pd.ty() == PhysicalDeviceType::IntegratedGpu)
+    }
+
     pub fn discrete_or_first_device(
         &self,
         surface: &Arc<Surface<Window>>,
-    ) -> Result<PhysicalDevice, VulkanoError> {
+    ) -> Result<PhysicalDevice, ENguyenError> {
+        if let Some(pd) = self.preferred_device() {
+            if GpuPicker::graphics_queue_fam(&pd, &surface).is_some() {
+                return Ok(pd);
+            }
+            warn!("The configured GPU {} can't draw to this window.", pd.name());
+        }
         let all_devs = PhysicalDevice::enumerate(&self.instance);
         let mut can_draw =
             all_devs.filter(|&pd| GpuPicker::graphics_queue_fam(&pd, &surface).is_some());
-        let mut discrete =
-            can_draw.clone().filter(|&pd| -> bool { pd.ty() == PhysicalDeviceType::DiscreteGpu });
-        if let Some(first_dev) = discrete.next() {
+        let mut favored = can_draw.clone().filter(|&pd| -> bool { pd.ty() == self.favored_type() });
+        if let Some(first_dev) = favored.next() {
             Ok(first_dev)
         } else {
-            warn!("No discrete device was found.");
+            warn!("No {:?} device was found.", self.favored_type());
             let first_dev = can_draw.next();
             match first_dev {
                 Some(dev) => Ok(dev),
                 None => {
-                    return Err(VulkanoError::CantDraw {});
-                }
+                    return Err(ENguyenError::render(CANT_DRAW));
+                },
             }
         }
     }
 
-    pub fn compute_device(&self) -> Result<PhysicalDevice, Box<dyn Error>> {
+    pub fn compute_device(&self) -> Result<PhysicalDevice, ENguyenError> {
+        if let Some(pd) = self.preferred_device() {
+            if GpuPicker::compute_queue_fam(&pd).is_some() {
+                return Ok(pd);
+            }
+            warn!("The configured GPU {} can't compute.", pd.name());
+        }
         let all_devs = PhysicalDevice::enumerate(&self.instance);
         let mut can_compute = all_devs.filter(|&pd| GpuPicker::compute_queue_fam(&pd).is_some());
-        let mut discrete = can_compute
-            .clone()
-            .filter(|&pd| -> bool { pd.ty() == PhysicalDeviceType::DiscreteGpu });
-        if let Some(first_dev) = discrete.next() {
+        let mut favored =
+            can_compute.clone().filter(|&pd| -> bool { pd.ty() == self.favored_type() });
+        if let Some(first_dev) = favored.next() {
             Ok(first_dev)
         } else {
-            warn!("No discrete device was found.");
+            warn!("No {:?} device was found.", self.favored_type());
             let first_dev = can_compute.next();
             match first_dev {
                 Some(dev) => Ok(dev),
-                None => {
-                    return Err(Box::new(ENguyenError::from(
-                        "No physical devices have compute capability",
-                    )));
-                }
+                None => Err(ENguyenError::compute("No physical devices have compute capability")),
             }
         }
     }
 
+    /// One line per physical device with the index and UUID the config can refer to
+    pub fn describe_devices(&self) -> Vec<String> {
+        PhysicalDevice::enumerate(&self.instance)
+            .map(|pd| {
+                format!(
+                    "#{} {} [{:?}] uuid: {} graphics: {} compute: {}",
+                    pd.index(),
+                    pd.name(),
+                    pd.ty(),
+                    format_uuid(pd.uuid()),
+                    GpuPicker::has_graphics(&pd),
+                    GpuPicker::has_compute(&pd),
+                )
+            })
+            .collect()
+    }
+
+    /// A device that can draw to windows created later, with queues for each role.  Used to
+    /// keep analysis running before any window exists.
+    pub fn windowless_device(&self) -> Result<(Arc<Device>, Queues), ENguyenError> {
+        let can_draw = |pd: &PhysicalDevice| GpuPicker::graphics_compute_queue_fam(pd).is_some();
+        let physical = self
+            .preferred_device()
+            .filter(|pd| can_draw(pd))
+            .or_else(|| {
+                PhysicalDevice::enumerate(&self.instance)
+                    .filter(|pd| can_draw(pd))
+                    .find(|pd| pd.ty() == self.favored_type())
+            })
+            .or_else(|| PhysicalDevice::enumerate(&self.instance).find(|pd| can_draw(pd)))
+            .ok_or_else(|| ENguyenError::render(CANT_DRAW))?;
+        info!("Using device: {} (type: {:?})", physical.name(), physical.ty());
+        let allocator = QueueAllocator::new(&physical, None, true)
+            .ok_or_else(|| ENguyenError::render("Physical device has no graphics queue"))?;
+        let device_ext = DeviceExtensions { khr_swapchain: true, ..DeviceExtensions::none() };
+        allocator.create(physical, &device_ext)
+    }
+
+    /// Logical device and compute queue with no window attached, for headless analysis
+    pub fn headless_compute(&self) -> Result<(Arc<Device>, Arc<Queue>), ENguyenError> {
+        let physical = self.compute_device()?;
+        info!("Using compute device: {} (type: {:?})", physical.name(), physical.ty());
+        let allocator = QueueAllocator::new(&physical, None, false)
+            .ok_or_else(|| ENguyenError::compute("Physical device has no compute queue"))?;
+        let (device, queues) = allocator.create(physical, &DeviceExtensions::none())?;
+        Ok((device, queues.compute))
+    }
+
     pub fn has_graphics(device: &PhysicalDevice) -> bool {
         device.queue_families().find(|fam| fam.supports_graphics()).is_some()
     }
@@ -282,6 +623,10 @@ impl GpuPicker {
             .find(|fam| fam.supports_graphics() && surface.is_supported(*fam).unwrap_or(false))
     }
 
+    pub fn graphics_compute_queue_fam<'a>(pd: &'a PhysicalDevice) -> Option<QueueFamily<'a>> {
+        pd.queue_families().find(|fam| fam.supports_graphics() && fam.supports_compute())
+    }
+
     pub fn compute_queue_fam<'a>(pd: &'a PhysicalDevice) -> Option<QueueFamily<'a>> {
         let mut has_compute = pd.queue_families().filter(|fam| fam.supports_compute());
         let mut compute_only =
@@ -295,6 +640,264 @@ impl GpuPicker {
     }
 }
 
+/// Frames are what's seen, so drawing goes first.  Analysis has to keep up with the audio
+/// and comes next, and uploads can wait.
+static GRAPHICS_PRIORITY: f32 = 1.0;
+static COMPUTE_PRIORITY: f32 = 0.75;
+static TRANSFER_PRIORITY: f32 = 0.25;
+
+/// The queues each kind of work is submitted to.  Roles the device has no queue of their own
+/// for share another's.
+#[derive(Clone)]
+pub struct Queues {
+    /// Draws and presents.  Compute-only on headless devices.
+    pub graphics: Arc<Queue>,
+    /// Analysis dispatches
+    pub compute: Arc<Queue>,
+    /// Uploads
+    pub transfer: Arc<Queue>,
+}
+
+/// What planning needs to know of a queue family
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Family {
+    id: u32,
+    graphics: bool,
+    compute: bool,
+    /// Copies only, which GPUs with one run on their DMA engines
+    transfer_only: bool,
+    /// Can present to the window's surface, or true with no surface
+    presents: bool,
+    queues: usize,
+}
+
+impl Family {
+    fn of(family: QueueFamily, surface: Option<&Arc<Surface<Window>>>) -> Family {
+        Family {
+            id: family.id(),
+            graphics: family.supports_graphics(),
+            compute: family.supports_compute(),
+            transfer_only: family.explicitly_supports_transfers()
+                && !family.supports_graphics()
+                && !family.supports_compute(),
+            presents: surface.map_or(true, |surface| surface.is_supported(family).unwrap_or(false)),
+            queues: family.queues_count(),
+        }
+    }
+}
+
+/// Decides which queues a device is created with and which work goes to each.  Compute gets
+/// a dedicated family when there is one, else a second queue of the graphics family, and
+/// transfers get a copy-only family when there is one.  Otherwise roles share a queue.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueueAllocator {
+    /// Family and priority of each queue, in the order requested
+    requests: Vec<(u32, f32)>,
+    /// Index into `requests` of each role's queue
+    graphics: usize,
+    compute: usize,
+    transfer: usize,
+}
+
+impl QueueAllocator {
+    /// Queues for drawing to `surface` as well as analysis, if `graphics`, or for analysis
+    /// alone.  None if `physical` can't do the work.
+    pub fn new(
+        physical: &PhysicalDevice,
+        surface: Option<&Arc<Surface<Window>>>,
+        graphics: bool,
+    ) -> Option<QueueAllocator> {
+        let families: Vec<Family> =
+            physical.queue_families().map(|family| Family::of(family, surface)).collect();
+        QueueAllocator::plan(&families, graphics)
+    }
+
+    fn plan(families: &[Family], graphics: bool) -> Option<QueueAllocator> {
+        let find = |wanted: &dyn Fn(&Family) -> bool| families.iter().find(|f| wanted(f));
+        let dedicated_compute = find(&|f| f.compute && !f.graphics);
+        let main = if graphics {
+            find(&|f| f.graphics && f.compute && f.presents)
+                .or_else(|| find(&|f| f.graphics && f.presents))?
+        } else {
+            dedicated_compute.or_else(|| find(&|f| f.compute))?
+        };
+        let first = if graphics { GRAPHICS_PRIORITY } else { COMPUTE_PRIORITY };
+        let mut requests = vec![(main.id, first)];
+        let mut request = |family: &Family, priority: f32| {
+            requests.push((family.id, priority));
+            requests.len() - 1
+        };
+
+        let compute = if !graphics {
+            0
+        } else if let Some(family) = dedicated_compute {
+            request(family, COMPUTE_PRIORITY)
+        } else if main.compute && main.queues > 1 {
+            request(main, COMPUTE_PRIORITY)
+        } else if main.compute {
+            0
+        } else {
+            return None;
+        };
+        // headless analysis uploads on its one queue
+        let transfer = match find(&|f| f.transfer_only) {
+            Some(family) if graphics => request(family, TRANSFER_PRIORITY),
+            _ => 0,
+        };
+        Some(QueueAllocator { requests, graphics: 0, compute, transfer })
+    }
+
+    /// Creates the device with the planned queues and hands them out by role
+    pub fn create(
+        &self,
+        physical: PhysicalDevice,
+        extensions: &DeviceExtensions,
+    ) -> Result<(Arc<Device>, Queues), ENguyenError> {
+        let mut families = Vec::new();
+        for &(id, priority) in &self.requests {
+            let family = physical
+                .queue_family_by_id(id)
+                .ok_or_else(|| ENguyenError::render("No such queue family"))?;
+            families.push((family, priority));
+        }
+        let features = device_features(physical.supported_features());
+        let (device, created) = Device::new(physical, &features, extensions, families)
+            .map_err(|e| ENguyenError::render("Device creation failed").caused_by(e))?;
+        let created: Vec<Arc<Queue>> = created.collect();
+        // queues come back grouped by family, so they're matched by family and index in it
+        let queue = |role: usize| -> Result<Arc<Queue>, ENguyenError> {
+            let (id, _) = self.requests[role];
+            let index = self.requests[..role].iter().filter(|(other, _)| *other == id).count();
+            let found = created.iter().find(|queue| {
+                queue.family().id() == id && queue.id_within_family() as usize == index
+            });
+            let missing = "Logical device creation returned too few queues";
+            Ok(found.ok_or_else(|| ENguyenError::render(missing))?.clone())
+        };
+        let queues = Queues {
+            graphics: queue(self.graphics)?,
+            compute: queue(self.compute)?,
+            transfer: queue(self.transfer)?,
+        };
+        debug!(
+            "Queue families: graphics {}, compute {}, transfer {}",
+            queues.graphics.family().id(),
+            queues.compute.family().id(),
+            queues.transfer.family().id()
+        );
+        Ok((device, queues))
+    }
+}
+
+/// Canonical 8-4-4-4-12 hex form of a device UUID
+pub fn format_uuid(uuid: &[u8; 16]) -> String {
+    let hex: Vec<String> = uuid.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        hex[0..4].concat(),
+        hex[4..6].concat(),
+        hex[6..8].concat(),
+        hex[8..10].concat(),
+        hex[10..16].concat()
+    )
+}
+
+/// Forces a window system, as winit reads it
+static BACKEND_VAR: &str = "WINIT_UNIX_BACKEND";
+
+/// The window system windows are opened on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backend {
+    Wayland,
+    /// X11 through a Wayland compositor's X server
+    XWayland,
+    X11,
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Backend::Wayland => "Wayland",
+            Backend::XWayland => "XWayland",
+            Backend::X11 => "X11",
+        };
+        f.write_str(name)
+    }
+}
+
+/// What the environment offers to open windows on
+#[derive(Clone, Debug, Default)]
+pub struct Session {
+    /// `WINIT_UNIX_BACKEND`, which forces a backend
+    pub forced: Option<String>,
+    /// A Wayland compositor is running
+    pub wayland: bool,
+    /// An X server, maybe XWayland, is running
+    pub x11: bool,
+    /// The Vulkan loader can present to Wayland surfaces
+    pub vulkan_wayland: bool,
+}
+
+impl Session {
+    pub fn detect(instance: &Instance) -> Session {
+        Session {
+            forced: env::var(BACKEND_VAR).ok(),
+            wayland: env::var_os("WAYLAND_DISPLAY").is_some(),
+            x11: env::var_os("DISPLAY").is_some(),
+            vulkan_wayland: instance.loaded_extensions().khr_wayland_surface,
+        }
+    }
+
+    /// Native Wayland when both the session and the Vulkan loader support it, falling back
+    /// to XWayland or X11
+    pub fn backend(&self) -> Result<Backend, ENguyenError> {
+        let x11 = if self.wayland { Backend::XWayland } else { Backend::X11 };
+        match self.forced.as_ref().map(String::as_str) {
+            Some("wayland") if self.vulkan_wayland => Ok(Backend::Wayland),
+            Some("wayland") => Err(ENguyenError::render(format!(
+                "{} asks for Wayland, which the Vulkan loader can't present to",
+                BACKEND_VAR
+            ))),
+            Some("x11") => Ok(x11),
+            Some(other) => Err(ENguyenError::config(format!(
+                "Unknown {} {:?}, try x11 or wayland",
+                BACKEND_VAR, other
+            ))),
+            None if self.wayland && self.vulkan_wayland => Ok(Backend::Wayland),
+            None if self.x11 => Ok(x11),
+            None if self.wayland => Err(ENguyenError::render(
+                "The Vulkan loader can't present to Wayland and no X server is running",
+            )),
+            None => Err(ENguyenError::render("No display server found for windows")),
+        }
+    }
+}
+
+/// The event loop windows open on, with the backend it uses
+pub fn events_loop(instance: &Instance) -> Result<(EventsLoop, Backend), ENguyenError> {
+    if thread::current().name() != Some("main") {
+        // macOS refuses, and Wayland and X11 misbehave in ways that are hard to trace
+        warn!("Windows should be opened from the main thread");
+    }
+    let session = Session::detect(instance);
+    let backend = session.backend()?;
+    let events_loop = match backend {
+        // winit prefers Wayland, falling back to X11 if it can't connect
+        Backend::Wayland => EventsLoop::new(),
+        _ => EventsLoop::new_x11()
+            .map_err(|e| ENguyenError::render("Could not connect to the X server").caused_by(e))?,
+    };
+    let backend = match (backend, events_loop.is_wayland()) {
+        (Backend::Wayland, false) => {
+            warn!("Could not connect to the Wayland compositor, using XWayland");
+            Backend::XWayland
+        },
+        (backend, _) => backend,
+    };
+    info!("Opening windows on {}", backend);
+    Ok((events_loop, backend))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -314,14 +917,14 @@ mod tests {
     fn find_device_for_surface() {
         let picker = GpuPicker::new(false).unwrap();
         let surface = test_surface(&picker.instance);
-        SwapWindow::new(&picker, &surface).unwrap();
+        SwapWindow::new(&picker, &surface, PresentMode::Fifo, false).unwrap();
     }
 
     #[test]
     fn get_dimensions() {
         let picker = GpuPicker::new(false).unwrap();
         let surface = test_surface(&picker.instance);
-        let gpu_win = SwapWindow::new(&picker, &surface).unwrap();
+        let gpu_win = SwapWindow::new(&picker, &surface, PresentMode::Fifo, false).unwrap();
         gpu_win.dimensions();
     }
 
@@ -333,6 +936,137 @@ mod tests {
             .unwrap()
     }
 
+    #[test]
+    fn samples_fit_the_device() {
+        // 1, 2, 4 and 8 samples
+        let counts = 0b1111;
+        assert_eq!(fit_samples(4, counts), 4);
+        assert_eq!(fit_samples(6, counts), 4);
+        assert_eq!(fit_samples(16, counts), 8);
+        assert_eq!(fit_samples(0, counts), 1);
+        assert_eq!(fit_samples(8, 0b101), 4);
+    }
+
+    #[test]
+    fn transparency_falls_back_to_opaque() {
+        let mut supported = SupportedCompositeAlpha {
+            opaque: true,
+            pre_multiplied: true,
+            post_multiplied: true,
+            inherit: false,
+        };
+        assert_eq!(composite_alpha(supported, false), Some(CompositeAlpha::Opaque));
+        assert_eq!(composite_alpha(supported, true), Some(CompositeAlpha::PreMultiplied));
+        supported.pre_multiplied = false;
+        assert_eq!(composite_alpha(supported, true), Some(CompositeAlpha::Opaque));
+        supported.opaque = false;
+        assert_eq!(composite_alpha(supported, false), Some(CompositeAlpha::PostMultiplied));
+    }
+
+    #[test]
+    fn queues_are_allocated_by_role() {
+        let family = |id, graphics, compute, transfer_only, queues| Family {
+            id,
+            graphics,
+            compute,
+            transfer_only,
+            presents: true,
+            queues,
+        };
+        // one queue does everything
+        let lone = [family(0, true, true, false, 1)];
+        let shared = QueueAllocator::plan(&lone, true).unwrap();
+        assert_eq!(shared.requests, vec![(0, GRAPHICS_PRIORITY)]);
+        assert_eq!((shared.compute, shared.transfer), (0, 0));
+        // a second queue of the family goes to analysis
+        let two = QueueAllocator::plan(&[family(0, true, true, false, 2)], true).unwrap();
+        assert_eq!(two.requests, vec![(0, GRAPHICS_PRIORITY), (0, COMPUTE_PRIORITY)]);
+        // families of their own are preferred
+        let discrete = [
+            family(0, true, true, false, 16),
+            family(1, false, true, false, 8),
+            family(2, false, false, true, 2),
+        ];
+        let split = QueueAllocator::plan(&discrete, true).unwrap();
+        let requests = vec![(0, GRAPHICS_PRIORITY), (1, COMPUTE_PRIORITY), (2, TRANSFER_PRIORITY)];
+        assert_eq!(split.requests, requests);
+        assert_eq!((split.graphics, split.compute, split.transfer), (0, 1, 2));
+        let headless = QueueAllocator::plan(&discrete, false).unwrap();
+        assert_eq!(headless.requests, vec![(1, COMPUTE_PRIORITY)]);
+        // windows can't be drawn from families that can't present to them
+        let offscreen = [Family { presents: false, ..lone[0] }];
+        assert_eq!(QueueAllocator::plan(&offscreen, true), None);
+    }
+
+    #[test]
+    fn scenes_are_gated_on_capabilities() {
+        let supported = Features { large_points: true, wide_lines: true, ..Features::none() };
+        let enabled = device_features(&supported);
+        assert!(enabled.large_points && !enabled.wide_lines);
+        assert!(!enabled.shader_storage_image_extended_formats);
+
+        let limited = Capabilities::default();
+        assert_eq!(limited.missing(Scene::Goniometer), Some("large points"));
+        assert_eq!(limited.drawable(Scene::Goniometer), Scene::Scroll);
+        assert_eq!(limited.next_scene(Scene::Meters), Scene::Piano);
+        let full = Capabilities { large_points: true, extended_formats: true };
+        assert_eq!(full.next_scene(Scene::Meters), Scene::Goniometer);
+        assert!(full.supports(Scene::Goniometer));
+    }
+
+    #[test]
+    fn srgb_surfaces_are_preferred() {
+        let unorm = (Format::B8G8R8A8Unorm, ColorSpace::SrgbNonLinear);
+        let srgb = (Format::B8G8R8A8Srgb, ColorSpace::SrgbNonLinear);
+        assert_eq!(surface_format(&[unorm, srgb]), Some(Format::B8G8R8A8Srgb));
+        assert_eq!(surface_format(&[unorm]), Some(Format::B8G8R8A8Unorm));
+        assert_eq!(surface_format(&[]), None);
+    }
+
+    #[test]
+    fn wayland_is_preferred() {
+        let mut session = Session { forced: None, wayland: true, x11: true, vulkan_wayland: true };
+        assert_eq!(session.backend().unwrap(), Backend::Wayland);
+        session.vulkan_wayland = false;
+        assert_eq!(session.backend().unwrap(), Backend::XWayland);
+        session.x11 = false;
+        assert!(session.backend().is_err());
+        session.forced = Some("x11".to_owned());
+        assert_eq!(session.backend().unwrap(), Backend::XWayland);
+        session.forced = Some("wayland".to_owned());
+        assert!(session.backend().is_err());
+
+        let session = Session { x11: true, ..Session::default() };
+        assert_eq!(session.backend().unwrap(), Backend::X11);
+        let session = Session { forced: Some("mir".to_owned()), ..session };
+        assert!(session.backend().is_err());
+        assert!(Session::default().backend().is_err());
+    }
+
+    #[test]
+    fn uuid_formatting() {
+        let uuid = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 255];
+        assert_eq!(format_uuid(&uuid), "00010203-0405-0607-0809-0a0b0c0d0eff");
+    }
+
+    #[test]
+    fn preferred_device_by_uuid() {
+        let mut picker = GpuPicker::new(false).unwrap();
+        let instance = picker.instance.clone();
+        let last = PhysicalDevice::enumerate(&instance).last().unwrap();
+        let uuid = format_uuid(last.uuid());
+        assert_eq!(picker.find_by_uuid(&uuid.to_uppercase()).unwrap().index(), last.index());
+
+        let index = last.index();
+        picker.prefer(Some(uuid));
+        if GpuPicker::compute_queue_fam(&last).is_some() {
+            assert_eq!(picker.compute_device().unwrap().index(), index);
+        }
+        // a missing device falls back to the usual choice
+        picker.prefer(Some(format_uuid(&[0; 16])));
+        picker.compute_device().unwrap();
+    }
+
     #[test]
     fn compute_device_and_queue() {
         let picker = GpuPicker::new(false).unwrap();
@@ -0,0 +1,335 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! Each analysis window's features, written out as they're measured so lighting controllers
+//! and scripts can follow the music without drawing anything.  Sinks run on the analysis
+//! thread and must not block it.
+
+use crate::config::{ExportConfig, ExportFormat};
+use crate::errors::ENguyenError;
+use crate::events::EventSink;
+use crate::features::AudioFeatures;
+use crate::led::LedSink;
+use crate::lights::LightSink;
+use crate::metadata::MetadataWatch;
+use crate::params::Palette;
+use crate::websocket::WebSocketSink;
+
+use log::warn;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::net::UdpSocket;
+use std::path::Path;
+use std::time::Instant;
+
+/// What is exported of one analysis window
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FeatureRecord {
+    /// Seconds from the first window exported to when this one was captured
+    pub time: f64,
+    pub rms: [f32; 2],
+    pub peak: [f32; 2],
+    /// Sub-bass, bass, mids and highs, as `AudioFeatures::bands`
+    pub bands: [f32; 4],
+    /// A beat started in this window
+    pub onset: bool,
+    pub bpm: f32,
+    pub beat_phase: f32,
+    /// As `AudioFeatures::spectrum`.  Left out of CSV.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub spectrum: Vec<f32>,
+    /// What the visualizer was coloring with, for lights that match it.  Not exported.
+    #[serde(skip)]
+    pub palette: Palette,
+}
+
+static CSV_HEADER: &str =
+    "time,rms_left,rms_right,peak_left,peak_right,sub_bass,bass,mids,highs,onset,bpm,beat_phase";
+
+impl FeatureRecord {
+    /// In the default palette
+    pub fn new(features: &AudioFeatures, time: f64) -> FeatureRecord {
+        FeatureRecord {
+            time,
+            rms: features.rms,
+            peak: features.peak,
+            bands: features.bands,
+            onset: features.beat.onset,
+            bpm: features.beat.bpm,
+            beat_phase: features.beat.phase,
+            spectrum: features.spectrum.clone(),
+            palette: Palette::Nguyen,
+        }
+    }
+
+    /// Fields in the order of `CSV_HEADER`
+    fn csv_row(&self) -> String {
+        let [rms_left, rms_right] = self.rms;
+        let [peak_left, peak_right] = self.peak;
+        let [sub_bass, bass, mids, highs] = self.bands;
+        format!(
+            "{:.4},{},{},{},{},{},{},{},{},{},{},{}",
+            self.time,
+            rms_left,
+            rms_right,
+            peak_left,
+            peak_right,
+            sub_bass,
+            bass,
+            mids,
+            highs,
+            self.onset as u8,
+            self.bpm,
+            self.beat_phase
+        )
+    }
+
+    pub fn json(&self) -> Result<String, ENguyenError> {
+        serde_json::to_string(self)
+            .map_err(|e| ENguyenError::compute("Features could not be serialized").caused_by(e))
+    }
+}
+
+/// Somewhere features are sent
+pub trait FeatureSink: Send {
+    fn send(&mut self, record: &FeatureRecord) -> Result<(), ENguyenError>;
+}
+
+/// Rows flushed as they're written, so the file can be followed while it grows
+struct FileSink {
+    file: BufWriter<File>,
+    format: ExportFormat,
+}
+
+impl FileSink {
+    fn create(path: &Path, format: ExportFormat) -> Result<FileSink, ENguyenError> {
+        let file = File::create(path).map_err(|e| {
+            ENguyenError::config(format!("Could not create {}", path.display())).caused_by(e)
+        })?;
+        let mut file = BufWriter::new(file);
+        if format == ExportFormat::Csv {
+            writeln!(file, "{}", CSV_HEADER)?;
+        }
+        Ok(FileSink { file, format })
+    }
+}
+
+impl FeatureSink for FileSink {
+    fn send(&mut self, record: &FeatureRecord) -> Result<(), ENguyenError> {
+        let line = match self.format {
+            ExportFormat::Csv => record.csv_row(),
+            ExportFormat::Json => record.json()?,
+        };
+        writeln!(self.file, "{}", line)?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+/// A JSON datagram per window.  Nobody listening is not an error.
+struct UdpSink {
+    socket: UdpSocket,
+}
+
+impl UdpSink {
+    fn connect(address: &str) -> Result<UdpSink, ENguyenError> {
+        let socket = UdpSocket::bind("0.0.0.0:0")
+            .and_then(|socket| socket.connect(address).map(|_| socket))
+            .map_err(|e| {
+                ENguyenError::config(format!("Could not reach {}", address)).caused_by(e)
+            })?;
+        socket.set_nonblocking(true)?;
+        Ok(UdpSink { socket })
+    }
+}
+
+impl FeatureSink for UdpSink {
+    fn send(&mut self, record: &FeatureRecord) -> Result<(), ENguyenError> {
+        match self.socket.send(record.json()?.as_bytes()) {
+            Ok(_) => Ok(()),
+            // refused and full buffers only drop this window
+            Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// `address`, with `port` unless it names one.  IPv6 addresses name a port as `[addr]:port`
+/// and are bracketed when given bare.
+pub fn with_port(address: &str, port: u16) -> String {
+    let bracketed = address.starts_with('[');
+    match address.matches(':').count() {
+        0 => format!("{}:{}", address, port),
+        1 if !bracketed => address.to_owned(),
+        _ if bracketed && address.contains("]:") => address.to_owned(),
+        _ if bracketed => format!("{}:{}", address, port),
+        _ => format!("[{}]:{}", address, port),
+    }
+}
+
+/// Sends every window to each configured sink
+pub struct Exporter {
+    sinks: Vec<(String, Box<dyn FeatureSink>)>,
+    started: Option<Instant>,
+}
+
+impl Exporter {
+    pub fn new() -> Exporter {
+        Exporter { sinks: Vec::new(), started: None }
+    }
+
+    /// Sinks that fail to open are left out with a warning.  Track events follow `metadata`,
+    /// or a watch of their own without one.
+    pub fn from_config(config: &ExportConfig, metadata: Option<&MetadataWatch>) -> Exporter {
+        let mut exporter = Exporter::new();
+        if let Some(path) = &config.path {
+            match FileSink::create(path, config.format) {
+                Ok(sink) => exporter.add(path.display().to_string(), Box::new(sink)),
+                Err(e) => warn!("Features won't be exported: {}", e),
+            }
+        }
+        if let Some(address) = &config.udp {
+            match UdpSink::connect(address) {
+                Ok(sink) => exporter.add(address.clone(), Box::new(sink)),
+                Err(e) => warn!("Features won't be sent: {}", e),
+            }
+        }
+        if let Some(address) = &config.websocket {
+            match WebSocketSink::listen(address, config.websocket_rate) {
+                Ok(sink) => exporter.add(address.clone(), Box::new(sink)),
+                Err(e) => warn!("Features won't be served: {}", e),
+            }
+        }
+        if let Some(led) = &config.led {
+            match LedSink::connect(led) {
+                Ok(sink) => exporter.add(led.address.clone(), Box::new(sink)),
+                Err(e) => warn!("The LED strip won't be lit: {}", e),
+            }
+        }
+        if let Some(lights) = &config.lights {
+            match LightSink::connect(lights) {
+                Ok(sink) => exporter.add(lights.address.clone(), Box::new(sink)),
+                Err(e) => warn!("The lights won't follow the music: {}", e),
+            }
+        }
+        if let Some(mqtt) = &config.mqtt {
+            exporter.add(mqtt.address.clone(), Box::new(EventSink::connect(mqtt, metadata)));
+        }
+        exporter
+    }
+
+    /// Send to `sink`, called `name` in warnings
+    pub fn add(&mut self, name: String, sink: Box<dyn FeatureSink>) {
+        self.sinks.push((name, sink));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+
+    /// Send the features of a window captured at `captured` and drawn in `palette`.  Sinks
+    /// that fail are dropped with a warning.
+    pub fn record(&mut self, features: &AudioFeatures, palette: Palette, captured: Instant) {
+        if self.sinks.is_empty() {
+            return;
+        }
+        let started = *self.started.get_or_insert(captured);
+        let elapsed = captured.duration_since(started);
+        let time = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+        let record = FeatureRecord { palette, ..FeatureRecord::new(features, time) };
+        let mut failed = Vec::new();
+        for (index, (name, sink)) in self.sinks.iter_mut().enumerate() {
+            if let Err(e) = sink.send(&record) {
+                warn!("Stopped exporting features to {}: {}", name, e);
+                failed.push(index);
+            }
+        }
+        for index in failed.into_iter().rev() {
+            self.sinks.remove(index);
+        }
+    }
+}
+
+impl Default for Exporter {
+    fn default() -> Exporter {
+        Exporter::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::Duration;
+
+    #[test]
+    fn features_are_exported() {
+        let dir = std::env::temp_dir().join(format!("e-nguyen-export-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (csv, json) = (dir.join("features.csv"), dir.join("features.json"));
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        listener.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+
+        let udp = Some(listener.local_addr().unwrap().to_string());
+        let path = Some(csv.clone());
+        let config = ExportConfig { path, udp, ..ExportConfig::default() };
+        let mut exporter = Exporter::from_config(&config, None);
+        let (path, format) = (Some(json.clone()), ExportFormat::Json);
+        let config = ExportConfig { path, format, ..ExportConfig::default() };
+        let mut json_exporter = Exporter::from_config(&config, None);
+
+        let mut features = AudioFeatures { bands: [0.5, 0.25, 0.0, 1.0], ..Default::default() };
+        features.beat.onset = true;
+        let start = Instant::now();
+        for at in &[start, start + Duration::from_millis(500)] {
+            exporter.record(&features, Palette::Ember, *at);
+            json_exporter.record(&features, Palette::Ember, *at);
+        }
+
+        let written = fs::read_to_string(&csv).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], CSV_HEADER);
+        assert!(lines[2].starts_with("0.5000,0,0,0,0,0.5,0.25,0,1,1,120,0"));
+
+        let written = fs::read_to_string(&json).unwrap();
+        let second = written.lines().nth(1).unwrap();
+        let record: serde_json::Value = serde_json::from_str(second).unwrap();
+        assert_eq!(record["time"], 0.5);
+        assert_eq!(record["onset"], true);
+
+        let mut datagram = [0; 512];
+        let len = listener.recv(&mut datagram).unwrap();
+        let record: serde_json::Value = serde_json::from_slice(&datagram[..len]).unwrap();
+        assert_eq!(record["bands"][1], 0.25);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn bad_sinks_are_left_out() {
+        let path = Some(Path::new("/nonexistent/features.csv").to_path_buf());
+        let udp = Some("nowhere".into());
+        let config = ExportConfig { path, udp, ..ExportConfig::default() };
+        assert!(Exporter::from_config(&config, None).is_empty());
+        assert_eq!(with_port("bridge", 80), "bridge:80");
+        assert_eq!(with_port("bridge:8080", 80), "bridge:8080");
+        assert_eq!(with_port("::1", 80), "[::1]:80");
+        assert_eq!(with_port("fe80::1", 80), "[fe80::1]:80");
+        assert_eq!(with_port("[::1]", 80), "[::1]:80");
+        assert_eq!(with_port("[::1]:8080", 80), "[::1]:8080");
+    }
+}
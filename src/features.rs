@@ -0,0 +1,270 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! Measurements of each analysis window that scenes draw from directly, alongside the
+//! spectrum texture.  Computed on the analysis thread and sent with each `AudioTex`.
+
+use crate::beat::Beat;
+
+/// Points per channel in `AudioFeatures::waveform`
+pub static WAVEFORM_POINTS: usize = 512;
+/// Frames the waveform spans at most.  About 21ms at 48kHz.
+static WAVEFORM_SPAN: usize = 1024;
+/// MIDI note numbers of the lowest and highest piano keys, A0 and C8
+pub static LOWEST_NOTE: u8 = 21;
+pub static HIGHEST_NOTE: u8 = 108;
+/// Level at the bottom of `AudioFeatures::notes` and `AudioFeatures::bands`
+static NOTE_FLOOR: f32 = -60.0;
+/// Edges of sub-bass, bass, mids and highs, Hz
+static BAND_EDGES: [f32; 5] = [20.0, 60.0, 250.0, 2000.0, 16000.0];
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AudioFeatures {
+    /// Samples of both channels from -1.0 to 1.0.  Starts at a rising zero crossing of the
+    /// first channel when there is one, so consecutive windows line up.
+    pub waveform: Vec<[f32; 2]>,
+    /// Root mean square of each channel over the window
+    pub rms: [f32; 2],
+    /// Largest magnitude of each channel over the window
+    pub peak: [f32; 2],
+    /// Level of each piano key from `LOWEST_NOTE` up, from 0.0 at -60dBFS to 1.0 at full
+    /// scale.  The loudest spectrum bin within a quarter tone of the key's pitch.
+    pub notes: Vec<f32>,
+    /// Energy of each pitch class from C up, relative to the loudest
+    pub chroma: [f32; 12],
+    /// Level of sub-bass, bass, mids and highs, from 0.0 at -60dBFS to 1.0 at full scale
+    pub bands: [f32; 4],
+    pub beat: Beat,
+    /// A new track seems to have started in this window
+    pub track_changed: bool,
+    /// Levels of log spaced bands across the analyzed range, as `bands`.  Only measured for
+    /// export.
+    pub spectrum: Vec<f32>,
+    /// Finer levels across the drawn range, as `spectrum`, for the frequency ruler's readout.
+    /// Only measured with the ruler shown.
+    pub readout: Vec<f32>,
+}
+
+impl AudioFeatures {
+    /// Measure interleaved stereo `audio`, with each sample multiplied by `scale`
+    pub fn measure(audio: &[i16], scale: f32) -> AudioFeatures {
+        let frames: Vec<[f32; 2]> = audio
+            .chunks_exact(2)
+            .map(|frame| [f32::from(frame[0]) * scale, f32::from(frame[1]) * scale])
+            .collect();
+        if frames.is_empty() {
+            return AudioFeatures::default();
+        }
+
+        let mut squares = [0.0; 2];
+        let mut peak = [0.0_f32; 2];
+        for frame in &frames {
+            for (channel, sample) in frame.iter().enumerate() {
+                squares[channel] += sample * sample;
+                peak[channel] = peak[channel].max(sample.abs());
+            }
+        }
+        let count = frames.len() as f32;
+        let rms = [(squares[0] / count).sqrt(), (squares[1] / count).sqrt()];
+
+        AudioFeatures { waveform: waveform(&frames), rms, peak, ..AudioFeatures::default() }
+    }
+
+    /// Fill `notes` and `chroma` from the FFT magnitudes of each channel, spaced `bin_hz`
+    /// apart.  `scale` brings a full scale sine's magnitude to 1.0.
+    pub fn hear_pitches(&mut self, left: &[f32], right: &[f32], bin_hz: f32, scale: f32) {
+        let nyquist_bins = left.len().min(right.len()) / 2;
+        let mut amplitudes = Vec::with_capacity(usize::from(HIGHEST_NOTE - LOWEST_NOTE) + 1);
+        for note in LOWEST_NOTE..=HIGHEST_NOTE {
+            let (low, high) = semitone_bins(note, bin_hz);
+            let loudest = (low..=high.min(nyquist_bins.saturating_sub(1)))
+                .map(|bin| (left[bin] + right[bin]) / 2.0)
+                .fold(0.0, f32::max);
+            amplitudes.push(loudest * scale);
+        }
+
+        let mut chroma = [0.0; 12];
+        for (note, amplitude) in (LOWEST_NOTE..=HIGHEST_NOTE).zip(amplitudes.iter()) {
+            chroma[usize::from(note % 12)] += amplitude;
+        }
+        let loudest = chroma.iter().copied().fold(0.0, f32::max);
+        if loudest > 10_f32.powf(NOTE_FLOOR / 20.0) {
+            for class in chroma.iter_mut() {
+                *class /= loudest;
+            }
+        } else {
+            chroma = [0.0; 12];
+        }
+
+        self.notes = amplitudes.iter().map(|amplitude| level(*amplitude)).collect();
+        self.chroma = chroma;
+    }
+
+    /// Fill `bands` from the FFT magnitudes of each channel, as `hear_pitches`
+    pub fn hear_bands(&mut self, left: &[f32], right: &[f32], bin_hz: f32, scale: f32) {
+        let nyquist_bins = left.len().min(right.len()) / 2;
+        for (band, edges) in self.bands.iter_mut().zip(BAND_EDGES.windows(2)) {
+            let low = (edges[0] / bin_hz).ceil() as usize;
+            let high = ((edges[1] / bin_hz).ceil() as usize).min(nyquist_bins);
+            let energy: f32 = (low..high.max(low))
+                .map(|bin| {
+                    let magnitude = (left[bin] + right[bin]) / 2.0;
+                    magnitude * magnitude
+                })
+                .sum();
+            let amplitude = energy.sqrt() * scale;
+            *band = level(amplitude);
+        }
+    }
+
+    /// Rescale `bands` so each starts at its level in `floor` instead of at silence
+    pub fn above_floor(&mut self, floor: &[f32; 4]) {
+        for (band, floor) in self.bands.iter_mut().zip(floor.iter()) {
+            *band = ((*band - floor) / (1.0 - floor)).max(0.0);
+        }
+    }
+}
+
+/// Equal tempered pitch of a MIDI note, A4 at 440Hz
+pub fn note_hz(note: u8) -> f32 {
+    440.0 * 2_f32.powf((f32::from(note) - 69.0) / 12.0)
+}
+
+/// First and last bins within a quarter tone of `note`.  Low notes narrower than a bin get
+/// the nearest one.
+fn semitone_bins(note: u8, bin_hz: f32) -> (usize, usize) {
+    let hz = note_hz(note);
+    let quarter_tone = 2_f32.powf(1.0 / 24.0);
+    let low = (hz / quarter_tone / bin_hz).ceil() as usize;
+    let high = (hz * quarter_tone / bin_hz).floor() as usize;
+    if low > high {
+        let nearest = (hz / bin_hz).round() as usize;
+        (nearest, nearest)
+    } else {
+        (low, high)
+    }
+}
+
+/// The newest trigger point that leaves a whole span after it, decimated to
+/// `WAVEFORM_POINTS`
+fn waveform(frames: &[[f32; 2]]) -> Vec<[f32; 2]> {
+    let span = (frames.len() / 2).max(1).min(WAVEFORM_SPAN);
+    let latest = frames.len() - span;
+    let start = (1..=latest)
+        .rev()
+        .find(|&i| frames[i - 1][0] <= 0.0 && frames[i][0] > 0.0)
+        .unwrap_or(latest);
+    let points = span.min(WAVEFORM_POINTS);
+    (0..points)
+        .map(|p| {
+            let [a, b] = frames[start + p * span / points];
+            [a.clamp(-1.0, 1.0), b.clamp(-1.0, 1.0)]
+        })
+        .collect()
+}
+
+/// `amplitude` from 0.0 at -60dBFS to 1.0 at full scale
+pub fn level(amplitude: f32) -> f32 {
+    (dbfs(amplitude, NOTE_FLOOR) - NOTE_FLOOR) / -NOTE_FLOOR
+}
+
+/// Level in decibels relative to full scale.  Silence is `floor`.
+pub fn dbfs(level: f32, floor: f32) -> f32 {
+    if level <= 0.0 {
+        floor
+    } else {
+        (20.0 * level.log10()).max(floor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_are_measured() {
+        let mut audio = Vec::new();
+        for i in 0..4000 {
+            let square = if i % 40 < 20 { 16384 } else { -16384 };
+            audio.push(square);
+            audio.push(-square / 2);
+        }
+        let features = AudioFeatures::measure(&audio, 1.0 / 32768.0);
+        assert_eq!(features.peak, [0.5, 0.25]);
+        assert!((features.rms[0] - 0.5).abs() < 1e-6);
+        assert!((features.rms[1] - 0.25).abs() < 1e-6);
+        assert_eq!(features.waveform.len(), WAVEFORM_POINTS);
+        // triggered on the rising edge
+        assert_eq!(features.waveform[0][0], 0.5);
+
+        assert_eq!(AudioFeatures::measure(&[], 1.0), AudioFeatures::default());
+        assert_eq!(AudioFeatures::measure(&[i16::max_value(), 0], 1.0).waveform.len(), 1);
+    }
+
+    #[test]
+    fn decibels() {
+        assert_eq!(dbfs(1.0, -60.0), 0.0);
+        assert!((dbfs(0.5, -60.0) + 6.0206).abs() < 1e-3);
+        assert_eq!(dbfs(0.0, -60.0), -60.0);
+        assert_eq!(dbfs(1e-6, -60.0), -60.0);
+        assert_eq!((level(1.0), level(1e-4), level(0.0)), (1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn pitches_are_heard() {
+        assert!((note_hz(69) - 440.0).abs() < 1e-3);
+        assert!((note_hz(60) - 261.626).abs() < 1e-2);
+        assert_eq!(semitone_bins(69, 10.0), (43, 45));
+        assert_eq!(semitone_bins(21, 10.0), (3, 3));
+
+        // a full scale A4 in the bin nearest 440Hz, a quieter E5 at 660Hz
+        let mut magnitudes = vec![0.0; 400];
+        magnitudes[44] = 100.0;
+        magnitudes[66] = 10.0;
+        let mut features = AudioFeatures::default();
+        features.hear_pitches(&magnitudes, &magnitudes, 10.0, 0.01);
+        assert_eq!(features.notes.len(), 88);
+        assert_eq!(features.notes[usize::from(69 - LOWEST_NOTE)], 1.0);
+        assert!((features.notes[usize::from(76 - LOWEST_NOTE)] - 2.0 / 3.0).abs() < 1e-3);
+        assert_eq!(features.notes[usize::from(70 - LOWEST_NOTE)], 0.0);
+        assert_eq!(features.chroma[9], 1.0);
+        assert!((features.chroma[4] - 0.1).abs() < 1e-3);
+        assert_eq!(features.chroma[0], 0.0);
+
+        features.hear_pitches(&[0.0; 400], &[0.0; 400], 10.0, 0.01);
+        assert_eq!(features.chroma, [0.0; 12]);
+    }
+
+    #[test]
+    fn bands_are_heard() {
+        // full scale at 40Hz, -20dB at 100Hz and 110Hz together, nothing above
+        let mut magnitudes = vec![0.0; 400];
+        magnitudes[4] = 100.0;
+        magnitudes[10] = 10.0;
+        magnitudes[11] = 10.0;
+        let mut features = AudioFeatures::default();
+        features.hear_bands(&magnitudes, &magnitudes, 10.0, 0.01);
+        assert_eq!(features.bands[0], 1.0);
+        let bass = (20.0 * (0.02_f32).sqrt().log10() + 60.0) / 60.0;
+        assert!((features.bands[1] - bass).abs() < 1e-4, "{:?}", features.bands);
+        assert_eq!(features.bands[2], 0.0);
+        // 2000Hz and up is past the 200 bins below Nyquist
+        assert_eq!(features.bands[3], 0.0);
+
+        features.bands = [1.0, 0.5, 0.2, 0.0];
+        features.above_floor(&[0.5, 0.5, 0.5, 0.0]);
+        assert_eq!(features.bands, [1.0, 0.0, 0.0, 0.0]);
+    }
+}
@@ -0,0 +1,207 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! Vertices of the shapes scenes draw and buffers to draw them from.  Positions are window
+//! coordinates, -1.0 to 1.0 with y down.  Shapes are triangle lists unless they say otherwise.
+
+use crate::errors::ENguyenError;
+use crate::rendering::{XyUvVertex, XyVertex};
+use crate::upload;
+
+use std::f32::consts::PI;
+use std::sync::Arc;
+use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer, ImmutableBuffer};
+use vulkano::device::{Device, Queue};
+use vulkano::sync::GpuFuture;
+
+/// Two triangles covering a rectangle
+pub fn quad(left: f32, top: f32, right: f32, bottom: f32) -> [XyVertex; 6] {
+    let corner = |x, y| XyVertex { position: [x, y] };
+    [
+        corner(left, top),
+        corner(right, top),
+        corner(left, bottom),
+        corner(left, bottom),
+        corner(right, top),
+        corner(right, bottom),
+    ]
+}
+
+/// A triangle strip over `rect`, left, top, right and bottom, textured top left to bottom
+/// right
+pub fn textured_strip(rect: [f32; 4]) -> [XyUvVertex; 4] {
+    let [left, top, right, bottom] = rect;
+    let corner = |x, y, u, v| XyUvVertex { position: [x, y], uv: [u, v] };
+    [
+        corner(left, top, 0.0, 0.0),
+        corner(right, top, 1.0, 0.0),
+        corner(left, bottom, 0.0, 1.0),
+        corner(right, bottom, 1.0, 1.0),
+    ]
+}
+
+/// A triangle strip over the whole window, textured top left to bottom right
+pub fn unit_quad() -> [XyUvVertex; 4] {
+    textured_strip([-1.0, -1.0, 1.0, 1.0])
+}
+
+/// Window coordinates of a rectangle given in pixels from the window's top left
+pub fn pixel_rect(window: [f32; 2], left: f32, top: f32, width: f32, height: f32) -> [f32; 4] {
+    let [window_width, window_height] = window;
+    let x = |pixels: f32| pixels / window_width * 2.0 - 1.0;
+    let y = |pixels: f32| pixels / window_height * 2.0 - 1.0;
+    [x(left), y(top), x(left + width), y(top + height)]
+}
+
+/// A fan of `segments` triangles around `center`.  Radii are given per axis so circles can
+/// stay round on windows that aren't square.
+pub fn circle(center: [f32; 2], radii: [f32; 2], segments: usize) -> Vec<XyVertex> {
+    let segments = segments.max(3);
+    let rim = |segment: usize| {
+        let angle = segment as f32 / segments as f32 * 2.0 * PI;
+        let [x, y] = center;
+        XyVertex { position: [x + radii[0] * angle.cos(), y + radii[1] * angle.sin()] }
+    };
+    let mut vertices = Vec::with_capacity(segments * 3);
+    let middle = XyVertex { position: center };
+    for segment in 0..segments {
+        vertices.extend_from_slice(&[middle.clone(), rim(segment), rim(segment + 1)]);
+    }
+    vertices
+}
+
+/// A line through `points`, `width` pixels thick in a window of `window` pixels.  Each
+/// segment is its own quad, so sharp turns show a notch on the outside.
+pub fn polyline(points: &[[f32; 2]], width: f32, window: [f32; 2]) -> Vec<XyVertex> {
+    let [half_width, half_height] = [window[0] / 2.0, window[1] / 2.0];
+    let mut vertices = Vec::with_capacity(points.len().saturating_sub(1) * 6);
+    for pair in points.windows(2) {
+        let (from, to) = (pair[0], pair[1]);
+        // the normal is found in pixels so the line is as thick across as it is along
+        let (dx, dy) = ((to[0] - from[0]) * half_width, (to[1] - from[1]) * half_height);
+        let length = (dx * dx + dy * dy).sqrt();
+        if length == 0.0 {
+            continue;
+        }
+        let reach = width / 2.0 / length;
+        let normal = [-dy * reach / half_width, dx * reach / half_height];
+        let offset = |point: [f32; 2], side: f32| XyVertex {
+            position: [point[0] + normal[0] * side, point[1] + normal[1] * side],
+        };
+        vertices.extend_from_slice(&[
+            offset(from, 1.0),
+            offset(from, -1.0),
+            offset(to, 1.0),
+            offset(to, 1.0),
+            offset(from, -1.0),
+            offset(to, -1.0),
+        ]);
+    }
+    vertices
+}
+
+/// A bar for each of `heights` side by side from `left` to `right`, rising from `bottom`.
+/// `gap` is the share of each bar's slot left empty.
+pub fn bars(heights: &[f32], left: f32, right: f32, bottom: f32, gap: f32) -> Vec<XyVertex> {
+    let slot = (right - left) / heights.len().max(1) as f32;
+    let inset = slot * gap.max(0.0).min(1.0) / 2.0;
+    let mut vertices = Vec::with_capacity(heights.len() * 6);
+    for (index, height) in heights.iter().enumerate() {
+        let slot_left = left + slot * index as f32;
+        let bar = quad(slot_left + inset, bottom - height, slot_left + slot - inset, bottom);
+        vertices.extend_from_slice(&bar);
+    }
+    vertices
+}
+
+/// Vertices the CPU writes, such as shapes built for one frame
+pub fn vertex_buffer<V, I>(
+    device: &Arc<Device>,
+    vertices: I,
+) -> Result<Arc<CpuAccessibleBuffer<[V]>>, ENguyenError>
+where
+    V: Send + Sync + 'static,
+    I: ExactSizeIterator<Item = V>,
+{
+    CpuAccessibleBuffer::from_iter(device.clone(), BufferUsage::vertex_buffer(), vertices)
+        .map_err(|e| ENguyenError::render("Vertex upload failed").caused_by(e))
+}
+
+/// Vertices that never change, copied by `queue` to memory only the GPU reads.  Draws once
+/// the returned future completes.
+pub fn static_vertices<V, I>(
+    queue: &Arc<Queue>,
+    vertices: I,
+) -> Result<(Arc<ImmutableBuffer<[V]>>, Box<dyn GpuFuture>), ENguyenError>
+where
+    V: Send + Sync + 'static,
+    I: ExactSizeIterator<Item = V>,
+{
+    upload::buffer(queue, BufferUsage::vertex_buffer(), vertices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(actual: [f32; 2], expected: [f32; 2]) {
+        for (actual, expected) in actual.iter().zip(expected.iter()) {
+            assert!((actual - expected).abs() < 1e-5, "{} is not {}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn circles_reach_their_radii() {
+        let vertices = circle([0.5, 0.0], [0.25, 0.5], 16);
+        assert_eq!(vertices.len(), 48);
+        assert_near(vertices[0].position, [0.5, 0.0]);
+        assert_near(vertices[1].position, [0.75, 0.0]);
+        // a quarter of the way around is straight down
+        assert_near(vertices[3 * 4 + 1].position, [0.5, 0.5]);
+        assert_eq!(circle([0.0; 2], [1.0; 2], 0).len(), 9);
+    }
+
+    #[test]
+    fn polylines_are_thick_in_pixels() {
+        // a horizontal line 10 pixels thick on a 200 by 100 window spans 0.2 vertically
+        let vertices = polyline(&[[-0.5, 0.0], [0.5, 0.0], [0.5, 0.0]], 10.0, [200.0, 100.0]);
+        assert_eq!(vertices.len(), 6);
+        assert_near(vertices[0].position, [-0.5, 0.1]);
+        assert_near(vertices[1].position, [-0.5, -0.1]);
+        // vertically, the same thickness spans 0.1 across
+        let vertices = polyline(&[[0.0, -0.5], [0.0, 0.5]], 10.0, [200.0, 100.0]);
+        assert_near(vertices[0].position, [-0.05, -0.5]);
+        assert!(polyline(&[[0.0, 0.0]], 10.0, [200.0, 100.0]).is_empty());
+    }
+
+    #[test]
+    fn bars_share_the_width() {
+        let vertices = bars(&[0.5, 1.0], -1.0, 1.0, 1.0, 0.5);
+        assert_eq!(vertices.len(), 12);
+        // each bar has a 1.0 slot with a quarter of it empty on either side
+        assert_near(vertices[0].position, [-0.75, 0.5]);
+        assert_near(vertices[11].position, [0.75, 1.0]);
+        assert_near(vertices[6].position, [0.25, 0.0]);
+    }
+
+    #[test]
+    fn strips_cover_the_window() {
+        let strip = unit_quad();
+        assert_eq!(strip[0].position, [-1.0, -1.0]);
+        assert_eq!(strip[0].uv, [0.0, 0.0]);
+        assert_eq!(strip[3].position, [1.0, 1.0]);
+        assert_eq!(strip[3].uv, [1.0, 1.0]);
+    }
+}
@@ -0,0 +1,459 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! Golden images of every scene.  Each scene is drawn offscreen from the same synthetic
+//! analysis, a test tone with seeded noise over a fixed history, and compared with the image
+//! stored for it.  Shader and scene refactors that change what's drawn show up as differences.
+
+use crate::config::{ENguyenConfig, Tonemap};
+use crate::errors::{step, ENguyenError};
+use crate::ewin::{color_render_pass, GpuPicker};
+use crate::features::AudioFeatures;
+use crate::geometry::{static_vertices, unit_quad};
+use crate::goniometer::Goniometer;
+use crate::meters::MetersScene;
+use crate::params::{Palette, Scene};
+use crate::piano::{KeyRange, PianoScene};
+use crate::rendering::{
+    over_blending, FrameBlock, FrameUniforms, PipelineRegistry, SolidPainter, XyUvVertex,
+};
+use crate::scenes::{spectrogram_pipeline, SceneFrame, SceneManager, Spectrogram};
+use crate::scrolling::ScrollingTexture;
+use crate::studio::StudioScene;
+use crate::tunnel::Tunnel;
+
+use image::RgbaImage;
+use rustfft::num_complex::Complex;
+use rustfft::num_traits::Zero;
+use rustfft::FFTplanner;
+use std::env;
+use std::f32::consts::PI;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DynamicState};
+use vulkano::descriptor::descriptor_set::PersistentDescriptorSet;
+use vulkano::device::Queue;
+use vulkano::format::Format;
+use vulkano::framebuffer::{Framebuffer, FramebufferAbstract};
+use vulkano::image::{AttachmentImage, ImageUsage};
+use vulkano::pipeline::viewport::Viewport;
+use vulkano::sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode};
+use vulkano::sync;
+use vulkano::sync::GpuFuture;
+
+/// Where goldens are kept, one PNG per scene
+pub static GOLDEN_DIR: &str = "golden";
+/// Set to record the goldens `cargo test` draws instead of comparing with them
+pub static UPDATE_VAR: &str = "E_NGUYEN_UPDATE_GOLDENS";
+
+/// Scenes drawn the same for the same analysis.  Now playing shows whatever track metadata
+/// the desktop has.
+static SCENES: [Scene; 6] =
+    [Scene::Scroll, Scene::Studio, Scene::Meters, Scene::Goniometer, Scene::Piano, Scene::Tunnel];
+
+/// Pixels of each image
+static SIZE: [u32; 2] = [320, 180];
+/// Channel difference allowed for rounding between GPUs and drivers
+static TOLERANCE: u8 = 8;
+/// Share of pixels allowed past `TOLERANCE`, for edges rasterized a pixel over
+static MAX_DIFFERING: f32 = 0.005;
+
+static TONE_RATE: f32 = 48000.0;
+/// Frames of the test tone, one analysis window
+static TONE_FRAMES: usize = 4096;
+/// Seeds the noise under the test tone
+static SEED: u32 = 0x3e9a_11c5;
+
+/// `FrameUniforms::time` every image is drawn at
+static TIME: f32 = 2.5;
+/// Columns and rows of the synthetic history
+static HISTORY_COLUMNS: u32 = 64;
+static HISTORY_ROWS: u32 = 256;
+/// Windows scenes follow before being drawn, so smoothed levels settle
+static WARM_UPDATES: usize = 60;
+
+/// How a scene's image compared with its golden
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Matched,
+    /// Goldens were being updated, so the image became the golden
+    Recorded,
+    /// There is no golden to compare with.  The image drawn was saved to `drawn`.
+    Missing {
+        drawn: PathBuf,
+    },
+    /// `share` of the pixels differ.  The image drawn was saved to `drawn`.
+    Differs {
+        share: f32,
+        drawn: PathBuf,
+    },
+}
+
+/// Draws every scene and compares each with its golden in `dir`, or records them all if
+/// `update`
+pub fn check(
+    picker: &GpuPicker,
+    dir: &Path,
+    update: bool,
+) -> Result<Vec<(Scene, Outcome)>, ENguyenError> {
+    let mut renderer = GoldenRenderer::new(picker)?;
+    fs::create_dir_all(dir)?;
+    let mut outcomes = Vec::new();
+    for scene in SCENES.iter() {
+        let image = renderer.render(*scene)?;
+        let golden_path = dir.join(format!("{}.png", scene));
+        let drawn = env::temp_dir().join(format!("e-nguyen-{}.png", scene));
+        let outcome = if update {
+            save(&image, &golden_path)?;
+            Outcome::Recorded
+        } else if !golden_path.exists() {
+            save(&image, &drawn)?;
+            Outcome::Missing { drawn }
+        } else {
+            let golden = image::open(&golden_path).map_err(|e| {
+                let message = format!("Could not read {}", golden_path.display());
+                ENguyenError::render(message).caused_by(e)
+            })?;
+            match difference(&golden.to_rgba(), &image) {
+                share if share <= MAX_DIFFERING => Outcome::Matched,
+                share => {
+                    save(&image, &drawn)?;
+                    Outcome::Differs { share, drawn }
+                },
+            }
+        };
+        outcomes.push((*scene, outcome));
+    }
+    Ok(outcomes)
+}
+
+fn save(image: &RgbaImage, path: &Path) -> Result<(), ENguyenError> {
+    image.save(path).map_err(|e| {
+        ENguyenError::render(format!("Could not write {}", path.display())).caused_by(e)
+    })
+}
+
+/// Share of pixels with a channel more than `TOLERANCE` apart.  Images of different sizes
+/// differ everywhere.
+fn difference(golden: &RgbaImage, drawn: &RgbaImage) -> f32 {
+    if golden.dimensions() != drawn.dimensions() {
+        return 1.0;
+    }
+    let differing = golden
+        .pixels()
+        .zip(drawn.pixels())
+        .filter(|(a, b)| {
+            a.data.iter().zip(b.data.iter()).any(|(a, b)| a.max(b) - a.min(b) > TOLERANCE)
+        })
+        .count();
+    differing as f32 / (golden.width() * golden.height()).max(1) as f32
+}
+
+/// Noise from -1.0 to 1.0, the same for the same seed everywhere
+struct Noise(u32);
+
+impl Noise {
+    fn next(&mut self) -> f32 {
+        // Numerical Recipes' LCG
+        self.0 = self.0.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+        (self.0 >> 8) as f32 / (1 << 23) as f32 - 1.0
+    }
+}
+
+/// An A440 and its fifth below on the left, E5 on the right, with quiet noise under both
+fn test_tone() -> Vec<i16> {
+    let mut noise = Noise(SEED);
+    let mut audio = Vec::with_capacity(TONE_FRAMES * 2);
+    for frame in 0..TONE_FRAMES {
+        let t = frame as f32 / TONE_RATE;
+        let left = 0.5 * (2.0 * PI * 440.0 * t).sin() + 0.25 * (2.0 * PI * 293.66 * t).sin();
+        let right = 0.5 * (2.0 * PI * 659.26 * t).sin();
+        for sample in [left, right].iter() {
+            let noisy = sample + 0.02 * noise.next();
+            audio.push((noisy * f32::from(i16::max_value())) as i16);
+        }
+    }
+    audio
+}
+
+/// What the analysis measures of `test_tone`
+fn tone_features() -> AudioFeatures {
+    let audio = test_tone();
+    let mut features = AudioFeatures::measure(&audio, 1.0 / 32768.0);
+    let mut planner = FFTplanner::new(false);
+    let fft = planner.plan_fft(TONE_FRAMES);
+    let magnitudes = |channel: usize| {
+        let mut input: Vec<Complex<f32>> = audio
+            .chunks_exact(2)
+            .map(|frame| Complex::new(f32::from(frame[channel]) / 32768.0, 0.0))
+            .collect();
+        let mut output: Vec<Complex<f32>> = vec![Zero::zero(); TONE_FRAMES];
+        fft.process(&mut input, &mut output);
+        output.iter().map(|c| c.norm()).collect::<Vec<f32>>()
+    };
+    let (left, right) = (magnitudes(0), magnitudes(1));
+    let bin_hz = TONE_RATE / TONE_FRAMES as f32;
+    let sine_scale = 2.0 / TONE_FRAMES as f32;
+    features.hear_pitches(&left, &right, bin_hz, sine_scale);
+    features.hear_bands(&left, &right, bin_hz, sine_scale);
+    features
+}
+
+/// Ripples drifting up through the history, in each channel's levels
+fn history_texels() -> Vec<[f32; 4]> {
+    let mut texels = Vec::with_capacity((HISTORY_COLUMNS * HISTORY_ROWS) as usize);
+    for row in 0..HISTORY_ROWS {
+        for column in 0..HISTORY_COLUMNS {
+            let (x, y) = (column as f32 / 8.0, row as f32 / 16.0);
+            let left = 0.5 + 0.5 * (x + y).sin();
+            let right = 0.5 + 0.5 * (x - 2.0 * y).cos();
+            texels.push([left, right, (left + right) / 2.0, 1.0]);
+        }
+    }
+    texels
+}
+
+/// Draws scenes into an image on the device windows would use, without a window
+struct GoldenRenderer {
+    queue: Arc<Queue>,
+    image: Arc<AttachmentImage>,
+    framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    state: DynamicState,
+    frame_block: FrameBlock,
+    painter: SolidPainter,
+    scenes: SceneManager,
+    features: AudioFeatures,
+    history: ScrollingTexture,
+    /// Uploads the scenes draw after
+    uploaded: Option<Box<dyn GpuFuture>>,
+}
+
+impl GoldenRenderer {
+    fn new(picker: &GpuPicker) -> Result<GoldenRenderer, ENguyenError> {
+        let (device, queues) = picker.windowless_device()?;
+        // one queue for uploads and drawing keeps them in order
+        let queue = queues.graphics;
+        let format = Format::R8G8B8A8Srgb;
+        let usage =
+            ImageUsage { color_attachment: true, transfer_source: true, ..ImageUsage::none() };
+        let image = step(
+            "Image creation failed",
+            AttachmentImage::with_usage(device.clone(), SIZE, format, usage),
+        )?;
+        let render_pass = color_render_pass(&device, format, 1)?;
+        let attached =
+            step("Image doesn't fit", Framebuffer::start(render_pass.clone()).add(image.clone()))?;
+        let framebuffer = Arc::new(step("Framebuffer creation failed", attached.build())?);
+        let viewport = Viewport {
+            origin: [0.0, 0.0],
+            dimensions: [SIZE[0] as f32, SIZE[1] as f32],
+            depth_range: 0.0..1.0,
+        };
+        let state =
+            DynamicState { line_width: None, viewports: Some(vec![viewport]), scissors: None };
+
+        let history =
+            ScrollingTexture::new(&device, HISTORY_COLUMNS, HISTORY_COLUMNS, HISTORY_ROWS)?;
+        let texels = step(
+            "History buffer creation failed",
+            CpuAccessibleBuffer::from_iter(
+                device.clone(),
+                BufferUsage::transfer_source(),
+                history_texels().into_iter(),
+            ),
+        )?;
+        let cb = step(
+            "Command buffer allocation failed",
+            AutoCommandBufferBuilder::primary_one_time_submit(device.clone(), queue.family()),
+        )?;
+        let cb = step("History copy failed", cb.copy_buffer_to_image(texels, history.image()))?;
+        let cb = step("Command buffer build failed", cb.build())?;
+        let filled =
+            step("Submit failed", sync::now(device.clone()).then_execute(queue.clone(), cb))?;
+
+        // the spectrogram is drawn turned half around, as in the visualizer
+        let (rect, rect_upload) = static_vertices(
+            &queue,
+            unit_quad().iter().map(|corner| XyUvVertex {
+                position: [-corner.position[0], -corner.position[1]],
+                uv: corner.uv,
+            }),
+        )?;
+        // as the visualizer samples its history
+        let sampler = step(
+            "Sampler creation failed",
+            Sampler::new(
+                device.clone(),
+                Filter::Linear,
+                Filter::Linear,
+                MipmapMode::Nearest,
+                SamplerAddressMode::Repeat,
+                SamplerAddressMode::ClampToEdge,
+                SamplerAddressMode::ClampToEdge,
+                0.0,
+                1.0,
+                0.0,
+                0.0,
+            ),
+        )?;
+        let pipelines = PipelineRegistry::new(device.clone(), render_pass);
+        let pipeline = spectrogram_pipeline(&pipelines, "spectrogram", over_blending())?;
+        let set = Arc::new(
+            PersistentDescriptorSet::start(pipeline.clone(), 1)
+                .add_sampled_image(history.image(), sampler)
+                .map_err(|e| ENguyenError::render("Spectrogram descriptor failed").caused_by(e))?
+                .build()
+                .map_err(|e| ENguyenError::render("Spectrogram descriptor failed").caused_by(e))?,
+        );
+
+        let config = ENguyenConfig::default();
+        let layout = config.analysis.layout;
+        let spectrogram = Spectrogram::new(pipeline.clone(), set, rect.clone(), layout);
+        let mut scenes = SceneManager::new();
+        scenes.add(Scene::Scroll, Box::new(spectrogram.clone()));
+        scenes.add(Scene::Studio, Box::new(StudioScene::new(spectrogram)));
+        scenes.add(Scene::Meters, Box::new(MetersScene::default()));
+        scenes.add(Scene::Goniometer, Box::new(Goniometer::new()));
+        scenes.add(Scene::Piano, Box::new(PianoScene::new(KeyRange::from_config(&config))));
+        scenes.add(Scene::Tunnel, Box::new(Tunnel::new(&pipelines, rect)?));
+        scenes.resize([SIZE[0] as f32, SIZE[1] as f32]);
+        let features = tone_features();
+        for _ in 0..WARM_UPDATES {
+            scenes.update(&features, Duration::from_millis(16));
+        }
+
+        Ok(GoldenRenderer {
+            queue,
+            image,
+            framebuffer,
+            state,
+            frame_block: FrameBlock::new(&device, pipeline),
+            painter: SolidPainter::new(&pipelines)?,
+            scenes,
+            features,
+            history,
+            uploaded: Some(Box::new(filled.join(rect_upload))),
+        })
+    }
+
+    /// `scene` drawn over black
+    fn render(&mut self, scene: Scene) -> Result<RgbaImage, ENguyenError> {
+        let device = self.queue.device().clone();
+        let frame = self.frame_block.set(FrameUniforms {
+            bands: self.features.bands,
+            resolution: [SIZE[0] as f32, SIZE[1] as f32],
+            time: TIME,
+            delta: 1.0 / 60.0,
+            beat_phase: self.features.beat.phase,
+        })?;
+        self.painter.begin_frame(frame.clone());
+        let zeroes = (0..SIZE[0] * SIZE[1] * 4).map(|_| 0u8);
+        let readback = step(
+            "Readback buffer creation failed",
+            CpuAccessibleBuffer::from_iter(
+                device.clone(),
+                BufferUsage::transfer_destination(),
+                zeroes,
+            ),
+        )?;
+
+        let cb = step(
+            "Command buffer allocation failed",
+            AutoCommandBufferBuilder::primary_one_time_submit(device.clone(), self.queue.family()),
+        )?;
+        let clear_values = vec![[0.0, 0.0, 0.0, 1.0].into()];
+        let cb = step(
+            "Render pass begin failed",
+            cb.begin_render_pass(self.framebuffer.clone(), false, clear_values),
+        )?;
+        let scene_frame = SceneFrame {
+            painter: &self.painter,
+            state: &self.state,
+            uniforms: frame,
+            features: &self.features,
+            palette: Palette::Nguyen,
+            tonemap: Tonemap::None,
+            scroll: self.history.scroll(HISTORY_COLUMNS - 1),
+            span: self.history.span(),
+        };
+        let drawn = self
+            .scenes
+            .get(scene)
+            .ok_or_else(|| ENguyenError::render(format!("No {} scene", scene)))?;
+        let cb = drawn.render(cb, &scene_frame)?;
+        let cb = step("Render pass end failed", cb.end_render_pass())?;
+        let cb = step(
+            "Readback copy failed",
+            cb.copy_image_to_buffer(self.image.clone(), readback.clone()),
+        )?;
+        let cb = step("Command buffer build failed", cb.build())?;
+
+        let before: Box<dyn GpuFuture> = match self.uploaded.take() {
+            Some(uploaded) => uploaded,
+            None => Box::new(sync::now(device.clone())),
+        };
+        let submitted = step("Submit failed", before.then_execute(self.queue.clone(), cb))?;
+        let fence = step("Flush failed", submitted.then_signal_fence_and_flush())?;
+        step("The GPU never finished", fence.wait(None))?;
+
+        let pixels = step("Readback failed", readback.read())?;
+        RgbaImage::from_raw(SIZE[0], SIZE[1], pixels.to_vec())
+            .ok_or_else(|| ENguyenError::render("Read back the wrong number of pixels"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn small_differences_are_tolerated() {
+        let golden = RgbaImage::from_pixel(10, 10, image::Rgba([100, 100, 100, 255]));
+        let mut drawn = golden.clone();
+        drawn.put_pixel(0, 0, image::Rgba([100 + TOLERANCE, 100, 100, 255]));
+        assert_eq!(difference(&golden, &drawn), 0.0);
+        drawn.put_pixel(1, 0, image::Rgba([100, 90 - TOLERANCE, 100, 255]));
+        assert_eq!(difference(&golden, &drawn), 0.01);
+        assert_eq!(difference(&golden, &RgbaImage::new(10, 5)), 1.0);
+        assert_eq!(tone_features(), tone_features());
+    }
+
+    #[test]
+    fn scenes_match_their_goldens() {
+        let picker = GpuPicker::new(false).unwrap();
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join(GOLDEN_DIR);
+        let update = env::var_os(UPDATE_VAR).is_some();
+        for (scene, outcome) in check(&picker, &dir, update).unwrap() {
+            match outcome {
+                Outcome::Matched | Outcome::Recorded => {},
+                Outcome::Missing { drawn } => panic!(
+                    "{} has no golden.  See {} and set {} to record it",
+                    scene,
+                    drawn.display(),
+                    UPDATE_VAR
+                ),
+                Outcome::Differs { share, drawn } => panic!(
+                    "{} differs in {:.1}% of pixels.  See {}",
+                    scene,
+                    share * 100.0,
+                    drawn.display()
+                ),
+            }
+        }
+    }
+}
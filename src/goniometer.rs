@@ -0,0 +1,161 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! The goniometer scene.  Left samples against right, turned 45 degrees so mono content is
+//! a vertical line and out of phase content a horizontal one.  Recent windows fade out
+//! behind the newest.
+
+use crate::errors::ENguyenError;
+use crate::features::AudioFeatures;
+use crate::rendering::{SolidPainter, XyVertex};
+use crate::scenes::{Scene, SceneFrame};
+
+use std::collections::VecDeque;
+use std::time::Duration;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DynamicState};
+use vulkano::pipeline::viewport::Viewport;
+
+/// Windows kept on screen
+static PERSISTENCE: usize = 12;
+static CLOUD_COLOR: [f32; 4] = [0.0, 0.906, 0.702, 0.6];
+static AXIS_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 0.2];
+/// Mid, side, left and right
+static AXES: [[[f32; 2]; 2]; 4] = [
+    [[0.0, -1.0], [0.0, 1.0]],
+    [[-1.0, 0.0], [1.0, 0.0]],
+    [[-1.0, -1.0], [1.0, 1.0]],
+    [[1.0, -1.0], [-1.0, 1.0]],
+];
+
+/// The plotted windows, oldest first
+pub struct Goniometer {
+    recent: VecDeque<Vec<XyVertex>>,
+}
+
+impl Goniometer {
+    pub fn new() -> Goniometer {
+        Goniometer { recent: VecDeque::with_capacity(PERSISTENCE) }
+    }
+
+    /// Plot the newest window, forgetting the oldest
+    pub fn push(&mut self, features: &AudioFeatures) {
+        if self.recent.len() == PERSISTENCE {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(plot(&features.waveform));
+    }
+
+    /// Records the axes and the cloud in a square in the middle of `full`'s viewport
+    pub fn draw(
+        &self,
+        painter: &SolidPainter,
+        mut cbb: AutoCommandBufferBuilder,
+        full: &DynamicState,
+    ) -> Result<AutoCommandBufferBuilder, ENguyenError> {
+        let state = match square(full) {
+            Some(state) => state,
+            None => return Ok(cbb),
+        };
+        for [from, to] in AXES.iter() {
+            let axis = vec![XyVertex { position: *from }, XyVertex { position: *to }];
+            cbb = painter.lines(cbb, &state, axis, AXIS_COLOR)?;
+        }
+        for (age, points) in self.recent.iter().rev().enumerate() {
+            let [r, g, b, a] = CLOUD_COLOR;
+            cbb = painter.glow_points(cbb, &state, points.clone(), [r, g, b, a * fade(age)])?;
+        }
+        Ok(cbb)
+    }
+}
+
+impl Scene for Goniometer {
+    fn update(&mut self, features: &AudioFeatures, _elapsed: Duration) {
+        self.push(features);
+    }
+
+    fn render(
+        &mut self,
+        cbb: AutoCommandBufferBuilder,
+        frame: &SceneFrame,
+    ) -> Result<AutoCommandBufferBuilder, ENguyenError> {
+        self.draw(frame.painter, cbb, frame.state)
+    }
+}
+
+impl Default for Goniometer {
+    fn default() -> Goniometer {
+        Goniometer::new()
+    }
+}
+
+/// Brightness of a window drawn `age` windows ago
+fn fade(age: usize) -> f32 {
+    1.0 - age as f32 / PERSISTENCE as f32
+}
+
+/// Full scale on both channels reaches the edges
+fn plot(waveform: &[[f32; 2]]) -> Vec<XyVertex> {
+    waveform
+        .iter()
+        .map(|[left, right]| XyVertex { position: [(right - left) / 2.0, -(left + right) / 2.0] })
+        .collect()
+}
+
+/// The largest square centered in `full`'s viewport
+fn square(full: &DynamicState) -> Option<DynamicState> {
+    let viewport = full.viewports.as_ref()?.first()?;
+    let [x, y] = viewport.origin;
+    let [width, height] = viewport.dimensions;
+    let side = width.min(height);
+    let origin = [x + (width - side) / 2.0, y + (height - side) / 2.0];
+    let square = Viewport { origin, dimensions: [side, side], depth_range: 0.0..1.0 };
+    Some(DynamicState { line_width: None, viewports: Some(vec![square]), scissors: None })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stereo_is_plotted() {
+        let points = plot(&[[0.5, 0.5], [0.5, -0.5], [1.0, 0.0]]);
+        // mono goes straight up, out of phase sideways, left alone up and to the left
+        assert_eq!(points[0].position, [0.0, -0.5]);
+        assert_eq!(points[1].position, [-0.5, 0.0]);
+        assert_eq!(points[2].position, [-0.5, -0.5]);
+    }
+
+    #[test]
+    fn old_windows_fade() {
+        let mut goniometer = Goniometer::new();
+        for _ in 0..PERSISTENCE + 3 {
+            goniometer.push(&AudioFeatures::default());
+        }
+        assert_eq!(goniometer.recent.len(), PERSISTENCE);
+        assert_eq!(fade(0), 1.0);
+        assert!(fade(PERSISTENCE - 1) > 0.0);
+    }
+
+    #[test]
+    fn cloud_is_square() {
+        let viewport =
+            Viewport { origin: [0.0, 0.0], dimensions: [800.0, 600.0], depth_range: 0.0..1.0 };
+        let full =
+            DynamicState { line_width: None, viewports: Some(vec![viewport]), scissors: None };
+        let square = square(&full).unwrap().viewports.unwrap().remove(0);
+        assert_eq!(square.origin, [100.0, 0.0]);
+        assert_eq!(square.dimensions, [600.0, 600.0]);
+    }
+}
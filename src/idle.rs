@@ -0,0 +1,203 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! Keeps the session from locking or blanking the display while the visualizer is fullscreen
+//! and music is playing.  Inhibitions go through the freedesktop ScreenSaver service, or GNOME's
+//! session manager where that's missing, from a thread of their own so a slow bus never holds
+//! up a frame.  Wayland's idle-inhibit protocol isn't used.  winit doesn't expose the bindings,
+//! and the desktops that implement it also provide one of the D-Bus services.
+
+use crate::features::AudioFeatures;
+
+use dbus::{BusType, Connection, Message};
+use log::{debug, info, warn};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+static APP_NAME: &str = "e-nguyen";
+static REASON: &str = "Visualizing music fullscreen";
+static SCREENSAVER: &str = "org.freedesktop.ScreenSaver";
+static SCREENSAVER_PATH: &str = "/org/freedesktop/ScreenSaver";
+static SESSION_MANAGER: &str = "org.gnome.SessionManager";
+static SESSION_MANAGER_PATH: &str = "/org/gnome/SessionManager";
+/// GNOME's flag for inhibiting the session going idle
+static INHIBIT_IDLE: u32 = 8;
+/// D-Bus calls are abandoned after this many milliseconds
+static CALL_TIMEOUT: i32 = 500;
+/// Peaks below this are silence
+static QUIET_PEAK: f32 = 0.01;
+/// Silence lasting this long lets the session go idle again.  Gaps between tracks don't.
+static QUIET_HOLD: Duration = Duration::from_secs(30);
+
+/// Whichever service took the inhibition, with the cookie that releases it
+enum Inhibition {
+    ScreenSaver(u32),
+    SessionManager(u32),
+}
+
+/// Inhibits idling while wanted.  Released when no longer wanted and on drop.
+#[derive(Default)]
+pub struct IdleInhibitor {
+    /// When the music was last heard
+    heard_at: Option<Instant>,
+    inhibiting: bool,
+    /// Started the first time idling is inhibited
+    requests: Option<(Sender<bool>, JoinHandle<()>)>,
+}
+
+impl IdleInhibitor {
+    /// Follow the newest analysis window
+    pub fn hear(&mut self, features: &AudioFeatures) {
+        if features.peak.iter().any(|peak| *peak >= QUIET_PEAK) {
+            self.heard_at = Some(Instant::now());
+        }
+    }
+
+    /// Inhibit idling while `fullscreen` and music was heard recently, and release otherwise
+    pub fn update(&mut self, fullscreen: bool) {
+        let wanted = wanted(fullscreen, self.heard_at, Instant::now());
+        if wanted == self.inhibiting {
+            return;
+        }
+        self.inhibiting = wanted;
+        if self.requests.is_none() {
+            let (sender, receiver) = channel();
+            let worker = thread::spawn(move || serve(&receiver));
+            self.requests = Some((sender, worker));
+        }
+        if let Some((sender, _)) = &self.requests {
+            let _ = sender.send(wanted);
+        }
+    }
+}
+
+impl Drop for IdleInhibitor {
+    fn drop(&mut self) {
+        // closing the channel releases any inhibition and ends the thread
+        if let Some((sender, worker)) = self.requests.take() {
+            drop(sender);
+            let _ = worker.join();
+        }
+    }
+}
+
+fn wanted(fullscreen: bool, heard_at: Option<Instant>, now: Instant) -> bool {
+    fullscreen && heard_at.map_or(false, |heard_at| now.duration_since(heard_at) < QUIET_HOLD)
+}
+
+/// Applies each request until the channel closes.  The connection is kept while inhibiting,
+/// since services drop inhibitions of peers that disconnect.
+fn serve(requests: &Receiver<bool>) {
+    let mut connection = None;
+    let mut inhibition = None;
+    let mut warned = false;
+    for wanted in requests.iter() {
+        if !wanted {
+            if let (Some(connected), Some(held)) = (&connection, inhibition.take()) {
+                release(connected, held);
+            }
+            continue;
+        }
+        if inhibition.is_some() {
+            continue;
+        }
+        if connection.is_none() {
+            match Connection::get_private(BusType::Session) {
+                Ok(connected) => connection = Some(connected),
+                Err(e) if !warned => {
+                    warn!("No session bus to keep the display awake with: {}", e);
+                    warned = true;
+                },
+                Err(e) => debug!("Session bus still unreachable: {}", e),
+            }
+        }
+        if let Some(connected) = &connection {
+            match inhibit(connected) {
+                Ok(held) => {
+                    info!("Keeping the display awake while fullscreen");
+                    inhibition = Some(held);
+                },
+                Err(e) if !warned => {
+                    warn!("The display may blank while fullscreen: {}", e);
+                    warned = true;
+                },
+                Err(e) => debug!("Idle inhibit failed again: {}", e),
+            }
+        }
+    }
+    if let (Some(connected), Some(held)) = (&connection, inhibition) {
+        release(connected, held);
+    }
+}
+
+fn inhibit(connection: &Connection) -> Result<Inhibition, dbus::Error> {
+    let screensaver = call(SCREENSAVER, SCREENSAVER_PATH, "Inhibit")?.append2(APP_NAME, REASON);
+    match connection.send_with_reply_and_block(screensaver, CALL_TIMEOUT) {
+        Ok(reply) => return Ok(Inhibition::ScreenSaver(cookie(&reply)?)),
+        Err(e) => debug!("No ScreenSaver service, trying GNOME's session manager: {}", e),
+    }
+    // no toplevel window id, which Wayland windows don't have
+    let session = call(SESSION_MANAGER, SESSION_MANAGER_PATH, "Inhibit")?
+        .append2(APP_NAME, 0_u32)
+        .append2(REASON, INHIBIT_IDLE);
+    let reply = connection.send_with_reply_and_block(session, CALL_TIMEOUT)?;
+    Ok(Inhibition::SessionManager(cookie(&reply)?))
+}
+
+fn release(connection: &Connection, inhibition: Inhibition) {
+    let released = match inhibition {
+        Inhibition::ScreenSaver(cookie) => {
+            call(SCREENSAVER, SCREENSAVER_PATH, "UnInhibit").map(|m| m.append1(cookie))
+        },
+        Inhibition::SessionManager(cookie) => {
+            call(SESSION_MANAGER, SESSION_MANAGER_PATH, "Uninhibit").map(|m| m.append1(cookie))
+        },
+    }
+    .and_then(|message| connection.send_with_reply_and_block(message, CALL_TIMEOUT));
+    match released {
+        Ok(_) => info!("The display may blank again"),
+        Err(e) => debug!("Releasing the idle inhibit failed: {}", e),
+    }
+}
+
+/// A call to `method` of the service named `service`, whose interface shares its name
+fn call(service: &str, path: &str, method: &str) -> Result<Message, dbus::Error> {
+    Message::new_method_call(service, path, service, method)
+        .map_err(|e| dbus::Error::new_custom("org.freedesktop.DBus.Error.Failed", &e))
+}
+
+fn cookie(reply: &Message) -> Result<u32, dbus::Error> {
+    reply.get1().ok_or_else(|| {
+        dbus::Error::new_custom("org.freedesktop.DBus.Error.InvalidArgs", "No inhibit cookie")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inhibited_while_fullscreen_and_playing() {
+        let now = Instant::now();
+        let heard = Some(now - Duration::from_secs(5));
+        assert!(wanted(true, heard, now));
+        assert!(!wanted(false, heard, now));
+        assert!(!wanted(true, None, now));
+        // long silences let the session idle again
+        assert!(!wanted(true, Some(now - QUIET_HOLD), now));
+    }
+}
@@ -13,8 +13,77 @@
 
 // Copyright 2019 E-Nguyen Developers.
 
+use crate::errors::ENguyenError;
+
+use std::str::FromStr;
 use winit::ElementState::{Pressed, Released};
-use winit::{DeviceEvent, Event, KeyboardInput, ModifiersState, WindowEvent};
+use winit::{DeviceEvent, Event, KeyboardInput, ModifiersState, VirtualKeyCode, WindowEvent};
+
+/// Key names accepted in combos, besides letters and digits
+static NAMED_KEYS: [(&str, VirtualKeyCode); 22] = [
+    ("escape", VirtualKeyCode::Escape),
+    ("space", VirtualKeyCode::Space),
+    ("enter", VirtualKeyCode::Return),
+    ("tab", VirtualKeyCode::Tab),
+    ("backspace", VirtualKeyCode::Back),
+    ("delete", VirtualKeyCode::Delete),
+    ("insert", VirtualKeyCode::Insert),
+    ("home", VirtualKeyCode::Home),
+    ("end", VirtualKeyCode::End),
+    ("pause", VirtualKeyCode::Pause),
+    ("f1", VirtualKeyCode::F1),
+    ("f2", VirtualKeyCode::F2),
+    ("f3", VirtualKeyCode::F3),
+    ("f4", VirtualKeyCode::F4),
+    ("f5", VirtualKeyCode::F5),
+    ("f6", VirtualKeyCode::F6),
+    ("f7", VirtualKeyCode::F7),
+    ("f8", VirtualKeyCode::F8),
+    ("f9", VirtualKeyCode::F9),
+    ("f10", VirtualKeyCode::F10),
+    ("f11", VirtualKeyCode::F11),
+    ("f12", VirtualKeyCode::F12),
+];
+static LETTERS: [VirtualKeyCode; 26] = [
+    VirtualKeyCode::A,
+    VirtualKeyCode::B,
+    VirtualKeyCode::C,
+    VirtualKeyCode::D,
+    VirtualKeyCode::E,
+    VirtualKeyCode::F,
+    VirtualKeyCode::G,
+    VirtualKeyCode::H,
+    VirtualKeyCode::I,
+    VirtualKeyCode::J,
+    VirtualKeyCode::K,
+    VirtualKeyCode::L,
+    VirtualKeyCode::M,
+    VirtualKeyCode::N,
+    VirtualKeyCode::O,
+    VirtualKeyCode::P,
+    VirtualKeyCode::Q,
+    VirtualKeyCode::R,
+    VirtualKeyCode::S,
+    VirtualKeyCode::T,
+    VirtualKeyCode::U,
+    VirtualKeyCode::V,
+    VirtualKeyCode::W,
+    VirtualKeyCode::X,
+    VirtualKeyCode::Y,
+    VirtualKeyCode::Z,
+];
+static DIGITS: [VirtualKeyCode; 10] = [
+    VirtualKeyCode::Key0,
+    VirtualKeyCode::Key1,
+    VirtualKeyCode::Key2,
+    VirtualKeyCode::Key3,
+    VirtualKeyCode::Key4,
+    VirtualKeyCode::Key5,
+    VirtualKeyCode::Key6,
+    VirtualKeyCode::Key7,
+    VirtualKeyCode::Key8,
+    VirtualKeyCode::Key9,
+];
 
 #[derive(Clone, Debug)]
 pub enum MousePos {
@@ -25,10 +94,34 @@ pub enum MousePos {
 #[derive(Debug)]
 pub enum UserEvent {
     // Abstract, useful events
-    MouseMove { pos: MousePos },
-    MouseDown { pos: MousePos },
-    MouseUp { pos: MousePos },
-    KeyPress { character: char },
+    MouseMove {
+        pos: MousePos,
+    },
+    MouseDown {
+        pos: MousePos,
+    },
+    /// Pressed with a modifier key held, for gestures the window handles itself
+    ModifiedMouseDown {
+        pos: MousePos,
+    },
+    MouseUp {
+        pos: MousePos,
+    },
+    KeyPress {
+        character: char,
+    },
+    Arrow {
+        arrow: Arrow,
+    },
+}
+
+/// Arrow keys are sent on press and on every repeat while held
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Arrow {
+    Left,
+    Right,
+    Up,
+    Down,
 }
 
 pub enum ProcessedEvent {
@@ -41,6 +134,69 @@ pub enum ProcessedEvent {
     KeyDown { scancode: u32, no_mods: bool },
     KeyUp { scancode: u32, no_mods: bool },
     KeyChar { character: char },
+    ArrowDown { arrow: Arrow, no_mods: bool },
+}
+
+/// A key pressed with exactly these modifiers, written like `ctrl+alt+q`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyCombo {
+    key: VirtualKeyCode,
+    modifiers: ModifiersState,
+}
+
+impl KeyCombo {
+    pub fn pressed(&self, input: &KeyboardInput) -> bool {
+        input.state == Pressed
+            && input.virtual_keycode == Some(self.key)
+            && input.modifiers == self.modifiers
+    }
+
+    /// Whether `ev` presses the combo
+    pub fn pressed_by(&self, ev: &Event) -> bool {
+        match ev {
+            Event::WindowEvent { event: WindowEvent::KeyboardInput { input, .. }, .. } => {
+                self.pressed(input)
+            },
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for KeyCombo {
+    type Err = ENguyenError;
+
+    fn from_str(combo: &str) -> Result<KeyCombo, ENguyenError> {
+        let mut modifiers = ModifiersState::default();
+        let mut key = None;
+        for part in combo.split('+').map(|part| part.trim().to_lowercase()) {
+            match part.as_str() {
+                "ctrl" | "control" => modifiers.ctrl = true,
+                "alt" => modifiers.alt = true,
+                "shift" => modifiers.shift = true,
+                "logo" | "super" | "meta" => modifiers.logo = true,
+                name => {
+                    let unknown = || ENguyenError::config(format!("Unknown key \"{}\"", name));
+                    if key.replace(key_named(name).ok_or_else(unknown)?).is_some() {
+                        let message = format!("\"{}\" has more than one key to press", combo);
+                        return Err(ENguyenError::config(message));
+                    }
+                },
+            }
+        }
+        match key {
+            Some(key) => Ok(KeyCombo { key, modifiers }),
+            None => Err(ENguyenError::config(format!("\"{}\" has no key to press", combo))),
+        }
+    }
+}
+
+fn key_named(name: &str) -> Option<VirtualKeyCode> {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(letter @ 'a'..='z'), None) => Some(LETTERS[letter as usize - 'a' as usize]),
+        (Some(digit @ '0'..='9'), None) => Some(DIGITS[digit as usize - '0' as usize]),
+        _ => NAMED_KEYS.iter().find(|(named, _)| *named == name).map(|(_, key)| *key),
+    }
 }
 
 pub struct MouseTracker {
@@ -57,12 +213,13 @@ impl MouseTracker {
     pub fn update(&mut self, event: &ProcessedEvent) -> Option<UserEvent> {
         match event {
             ProcessedEvent::LeftButtonDown { no_mods, .. } => {
-                if *no_mods {
-                    if let MousePos::Pos { x, y } = self.last_pos {
-                        self.mouse_down = MousePos::Pos { x, y };
-                        Some(UserEvent::MouseDown { pos: self.last_pos.clone() })
+                if let MousePos::Pos { x, y } = self.last_pos {
+                    self.mouse_down = MousePos::Pos { x, y };
+                    let pos = self.last_pos.clone();
+                    if *no_mods {
+                        Some(UserEvent::MouseDown { pos })
                     } else {
-                        None
+                        Some(UserEvent::ModifiedMouseDown { pos })
                     }
                 } else {
                     None
@@ -144,7 +301,15 @@ impl KeyTracker {
                     self.key_down = LastKey::NoKey;
                 }
                 None
-            }
+            },
+            ProcessedEvent::ArrowDown { arrow, no_mods } => {
+                self.key_down = LastKey::NoKey;
+                if *no_mods {
+                    Some(UserEvent::Arrow { arrow: *arrow })
+                } else {
+                    None
+                }
+            },
             _ => None,
         }
     }
@@ -154,6 +319,56 @@ impl KeyTracker {
     }
 }
 
+/// Plays scripted input through the trackers a window uses, so hotkeys and gestures can be
+/// tested without a display server
+pub struct Simulation {
+    kt: KeyTracker,
+    mt: MouseTracker,
+}
+
+impl Simulation {
+    pub fn new() -> Simulation {
+        Simulation { kt: KeyTracker::new(), mt: MouseTracker::new() }
+    }
+
+    /// What one event yields, keys first as the windows handle them
+    pub fn feed(&mut self, event: &ProcessedEvent) -> Vec<UserEvent> {
+        vec![self.kt.update(event), self.mt.update(event)].into_iter().flatten().collect()
+    }
+
+    /// Everything a script yields, in order
+    pub fn run(&mut self, script: &[ProcessedEvent]) -> Vec<UserEvent> {
+        script.iter().flat_map(|event| self.feed(event)).collect()
+    }
+
+    /// Raw window events go through the same processing as a window's
+    pub fn feed_raw(&mut self, event: &Event) -> Vec<UserEvent> {
+        process(event).map_or_else(Vec::new, |event| self.feed(&event))
+    }
+}
+
+/// Each character of `text` pressed and released without modifiers
+pub fn typed(text: &str) -> Vec<ProcessedEvent> {
+    let mut script = Vec::new();
+    for (scancode, character) in text.chars().enumerate() {
+        let scancode = scancode as u32;
+        script.push(ProcessedEvent::KeyDown { scancode, no_mods: true });
+        script.push(ProcessedEvent::KeyChar { character });
+        script.push(ProcessedEvent::KeyUp { scancode, no_mods: true });
+    }
+    script
+}
+
+/// The left button pressed at `from`, moved to `to` and released there
+pub fn drag(from: (f64, f64), to: (f64, f64)) -> Vec<ProcessedEvent> {
+    vec![
+        ProcessedEvent::CursorMove { x: from.0, y: from.1 },
+        ProcessedEvent::LeftButtonDown { no_mods: true },
+        ProcessedEvent::CursorMove { x: to.0, y: to.1 },
+        ProcessedEvent::LeftButtonUp,
+    ]
+}
+
 pub fn process(ev: &winit::Event) -> Option<ProcessedEvent> {
     match &ev {
         Event::DeviceEvent { event, .. } => match event {
@@ -171,6 +386,13 @@ pub fn process(ev: &winit::Event) -> Option<ProcessedEvent> {
                 input: KeyboardInput { state: Released, scancode, .. },
                 ..
             } => Some(ProcessedEvent::KeyUp { scancode: *scancode, no_mods: mod_keys_off(&ev) }),
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput { state: Pressed, virtual_keycode: Some(key), .. },
+                ..
+            } if arrow(*key).is_some() => {
+                let no_mods = mod_keys_off(&ev);
+                arrow(*key).map(|arrow| ProcessedEvent::ArrowDown { arrow, no_mods })
+            },
             WindowEvent::KeyboardInput {
                 input: KeyboardInput { state: Pressed, scancode, .. },
                 ..
@@ -187,6 +409,16 @@ pub fn process(ev: &winit::Event) -> Option<ProcessedEvent> {
     }
 }
 
+fn arrow(key: VirtualKeyCode) -> Option<Arrow> {
+    match key {
+        VirtualKeyCode::Left => Some(Arrow::Left),
+        VirtualKeyCode::Right => Some(Arrow::Right),
+        VirtualKeyCode::Up => Some(Arrow::Up),
+        VirtualKeyCode::Down => Some(Arrow::Down),
+        _ => None,
+    }
+}
+
 fn mod_keys_off(ev: &Event) -> bool {
     match ev {
         Event::WindowEvent {
@@ -218,6 +450,7 @@ fn mod_keys_off(ev: &Event) -> bool {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use winit::{DeviceId, WindowId};
 
     #[test]
     fn test_key_tracking() {
@@ -258,6 +491,34 @@ mod tests {
             UserEvent::KeyPress { character } => assert_eq!('c', character),
             _ => panic!("mismatched"),
         }
+
+        // arrows are sent on press, without modifiers
+        let left = ProcessedEvent::ArrowDown { arrow: Arrow::Left, no_mods: true };
+        match kt.update(&left) {
+            Some(UserEvent::Arrow { arrow }) => assert_eq!(arrow, Arrow::Left),
+            _ => panic!("mismatched"),
+        }
+        let held = ProcessedEvent::ArrowDown { arrow: Arrow::Up, no_mods: false };
+        assert!(kt.update(&held).is_none());
+    }
+
+    #[test]
+    fn key_combos() {
+        let combo: KeyCombo = "Ctrl+Alt+Q".parse().unwrap();
+        let mut input = KeyboardInput {
+            scancode: 24,
+            state: Pressed,
+            virtual_keycode: Some(VirtualKeyCode::Q),
+            modifiers: ModifiersState { ctrl: true, alt: true, ..ModifiersState::default() },
+        };
+        assert!(combo.pressed(&input));
+        input.modifiers.shift = true;
+        assert!(!combo.pressed(&input));
+        assert_eq!("shift + f4".parse::<KeyCombo>().unwrap().key, VirtualKeyCode::F4);
+        assert_eq!("7".parse::<KeyCombo>().unwrap().key, VirtualKeyCode::Key7);
+        assert!("ctrl+alt".parse::<KeyCombo>().is_err());
+        assert!("ctrl+q+w".parse::<KeyCombo>().is_err());
+        assert!("hyper+q".parse::<KeyCombo>().is_err());
     }
 
     #[test]
@@ -271,6 +532,43 @@ mod tests {
         assert!(no_key);
     }
 
+    #[test]
+    fn simulated_scripts() {
+        let mut sim = Simulation::new();
+        let mut script = typed("ab");
+        script.extend(drag((5., 5.), (50., 8.)));
+        let keys: Vec<char> = sim
+            .run(&script)
+            .into_iter()
+            .filter_map(|ue| match ue {
+                UserEvent::KeyPress { character } => Some(character),
+                UserEvent::MouseUp { pos: MousePos::Pos { x, .. } } => {
+                    assert_eq!(x, 50.);
+                    Some('^')
+                },
+                _ => None,
+            })
+            .collect();
+        assert_eq!(keys, vec!['a', 'b', '^']);
+
+        // synthesized window events take the same path, modifiers included
+        let key = |state, modifiers| {
+            let input = KeyboardInput { scancode: 9, state, virtual_keycode: None, modifiers };
+            WindowEvent::KeyboardInput { device_id: unsafe { DeviceId::dummy() }, input }
+        };
+        let ctrl = ModifiersState { ctrl: true, ..ModifiersState::default() };
+        for mods in &[ModifiersState::default(), ctrl] {
+            let window_id = unsafe { WindowId::dummy() };
+            let events = vec![
+                Event::WindowEvent { window_id, event: key(Pressed, *mods) },
+                Event::WindowEvent { window_id, event: WindowEvent::ReceivedCharacter('x') },
+                Event::WindowEvent { window_id, event: key(Released, *mods) },
+            ];
+            let pressed = events.iter().flat_map(|ev| sim.feed_raw(ev)).count();
+            assert_eq!(pressed, if mods.ctrl { 0 } else { 1 });
+        }
+    }
+
     #[test]
     fn test_mouse_tracking() {
         let mut mt = MouseTracker::new();
@@ -286,58 +584,65 @@ mod tests {
             ProcessedEvent::LeftButtonUp,
             // does not cause MouseDown as it must be outside window
             ProcessedEvent::LeftButtonDown { no_mods: true },
-            // does not cause MousDown due to modifier keys
+            // does not cause MousDown due to modifier keys, but ModifiedMouseDown
             ProcessedEvent::CursorMove { x: 30., y: 40. },
             ProcessedEvent::LeftButtonDown { no_mods: false },
         ];
         let mut outputs: Vec<UserEvent> = inputs.iter().filter_map(|m| mt.update(&m)).collect();
-        assert_eq!(7, outputs.len());
+        assert_eq!(8, outputs.len());
         outputs.reverse();
         let mut m = outputs.pop().unwrap();
         match m {
             UserEvent::MouseMove { pos: MousePos::Pos { x, y } } => {
                 assert_eq!((10., 20.), (x, y));
-            }
+            },
             _ => panic!("mismatched"),
         }
         m = outputs.pop().unwrap();
         match m {
             UserEvent::MouseDown { pos: MousePos::Pos { x, y } } => {
                 assert_eq!((10., 20.), (x, y));
-            }
+            },
             _ => panic!("mismatched"),
         }
         m = outputs.pop().unwrap();
         match m {
             UserEvent::MouseMove { pos: MousePos::Pos { x, y } } => {
                 assert_eq!((20., 30.), (x, y));
-            }
+            },
             _ => panic!("mismatched"),
         }
         m = outputs.pop().unwrap();
         match m {
             UserEvent::MouseUp { pos: MousePos::Pos { x, y } } => {
                 assert_eq!((20., 30.), (x, y));
-            }
+            },
             _ => panic!("mismatched"),
         }
         m = outputs.pop().unwrap();
         match m {
             UserEvent::MouseDown { pos: MousePos::Pos { x, y } } => {
                 assert_eq!((20., 30.), (x, y));
-            }
+            },
             _ => panic!("mismatched"),
         }
         m = outputs.pop().unwrap();
         match m {
-            UserEvent::MouseUp { pos: MousePos::NoPos } => { /* dont panic */ }
+            UserEvent::MouseUp { pos: MousePos::NoPos } => { /* dont panic */ },
             _ => panic!("mismatched"),
         }
         m = outputs.pop().unwrap();
         match m {
             UserEvent::MouseMove { pos: MousePos::Pos { x, y } } => {
                 assert_eq!((30., 40.), (x, y));
-            }
+            },
+            _ => panic!("mismatched"),
+        }
+        m = outputs.pop().unwrap();
+        match m {
+            UserEvent::ModifiedMouseDown { pos: MousePos::Pos { x, y } } => {
+                assert_eq!((30., 40.), (x, y));
+            },
             _ => panic!("mismatched"),
         }
     }
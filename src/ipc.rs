@@ -0,0 +1,311 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! A per-user unix socket doubles as the single-instance lock.  Whoever binds it first is
+//! the running instance and later invocations forward their request over it and exit.
+//!
+//! The protocol is one lowercase command per line, so `echo focus | socat - UNIX:...` works.
+
+use crate::params::ParamChange;
+
+use log::{debug, info, warn};
+use std::fmt;
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+static SOCKET_NAME: &str = "e-nguyen";
+/// How long a connection may go without sending a line before it's dropped
+static READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Requests another invocation can make of the running instance
+#[derive(Clone, Debug, PartialEq)]
+pub enum Remote {
+    Focus,
+    ToggleFullscreen,
+    ShowMez,
+    ShowSettings,
+    Quit,
+    /// Forget the current scene's tuning
+    ResetScene,
+    /// Change a runtime parameter, such as `set gain 1.5`
+    Set(ParamChange),
+    /// Switch to a `[profile.<name>]`, such as `profile laptop`.  No name goes back to none.
+    Profile(Option<String>),
+    /// Start or stop recording the captured audio to a WAV file
+    Record,
+    /// Stop or resume presenting new frames.  Analysis carries on.
+    Freeze,
+    /// Stop or resume capture and analysis
+    Pause,
+}
+
+impl fmt::Display for Remote {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let command = match self {
+            Remote::Focus => "focus",
+            Remote::ToggleFullscreen => "fullscreen",
+            Remote::ShowMez => "mez",
+            Remote::ShowSettings => "settings",
+            Remote::Quit => "quit",
+            Remote::ResetScene => "reset-scene",
+            Remote::Set(change) => return write!(f, "set {}", change),
+            Remote::Profile(Some(name)) => return write!(f, "profile {}", name),
+            Remote::Profile(None) => "profile",
+            Remote::Record => "record",
+            Remote::Freeze => "freeze",
+            Remote::Pause => "pause",
+        };
+        write!(f, "{}", command)
+    }
+}
+
+impl FromStr for Remote {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Remote, String> {
+        let s = s.trim();
+        if s.starts_with("set ") {
+            return s["set ".len()..].parse().map(Remote::Set);
+        }
+        if s.starts_with("profile ") {
+            let name = s["profile ".len()..].trim();
+            if name.contains(char::is_whitespace) {
+                return Err(format!("Profile names are one word, got: {}", name));
+            }
+            return Ok(Remote::Profile(Some(name.to_owned())));
+        }
+        match s {
+            "focus" => Ok(Remote::Focus),
+            "fullscreen" => Ok(Remote::ToggleFullscreen),
+            "mez" => Ok(Remote::ShowMez),
+            "settings" => Ok(Remote::ShowSettings),
+            "quit" => Ok(Remote::Quit),
+            "reset-scene" => Ok(Remote::ResetScene),
+            "profile" => Ok(Remote::Profile(None)),
+            "record" => Ok(Remote::Record),
+            "freeze" => Ok(Remote::Freeze),
+            "pause" => Ok(Remote::Pause),
+            other => Err(format!("Unknown remote command: {}", other)),
+        }
+    }
+}
+
+pub enum Claim {
+    /// No other instance is running.  Keep the server alive to hold the claim.
+    Primary(IpcServer),
+    /// Another instance owns the socket
+    Secondary(IpcClient),
+}
+
+/// Claim the default per-user socket
+pub fn claim() -> io::Result<Claim> {
+    claim_at(socket_path())
+}
+
+/// The running instance, if there is one
+pub fn running() -> Option<IpcClient> {
+    let path = socket_path();
+    match UnixStream::connect(&path) {
+        Ok(_) => Some(IpcClient { path }),
+        Err(_) => None,
+    }
+}
+
+pub fn claim_at(path: PathBuf) -> io::Result<Claim> {
+    if let Some(server) = listen(&path)? {
+        return Ok(Claim::Primary(server));
+    }
+    if UnixStream::connect(&path).is_ok() {
+        return Ok(Claim::Secondary(IpcClient { path }));
+    }
+    // nobody answered so the last owner died without cleaning up
+    debug!("Removing stale socket {}", path.display());
+    if let Err(e) = std::fs::remove_file(&path) {
+        // fine if another instance starting at the same time removed it first
+        if e.kind() != io::ErrorKind::NotFound {
+            return Err(e);
+        }
+    }
+    match listen(&path)? {
+        Some(server) => Ok(Claim::Primary(server)),
+        // another instance starting at the same time got there first
+        None => Ok(Claim::Secondary(IpcClient { path })),
+    }
+}
+
+/// The server for `path`, or None if the socket is already there
+fn listen(path: &PathBuf) -> io::Result<Option<IpcServer>> {
+    match UnixListener::bind(path) {
+        Ok(listener) => {
+            info!("Listening for other instances on {}", path.display());
+            Ok(Some(IpcServer { listener, path: path.clone() }))
+        },
+        Err(ref e) if e.kind() == io::ErrorKind::AddrInUse => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+pub struct IpcServer {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl IpcServer {
+    /// Accepts connections on a background thread, calling `handler` for each request
+    pub fn serve<H>(&self, mut handler: H) -> io::Result<JoinHandle<()>>
+    where
+        H: FnMut(Remote) + Send + 'static,
+    {
+        let listener = self.listener.try_clone()?;
+        Ok(thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(s) => s,
+                    Err(e) => {
+                        warn!("IPC connection failed: {}", e);
+                        continue;
+                    },
+                };
+                // a client that never finishes a line would hold up everyone after it
+                if let Err(e) = stream.set_read_timeout(Some(READ_TIMEOUT)) {
+                    warn!("IPC connection can't time out: {}", e);
+                    continue;
+                }
+                for line in BufReader::new(stream).lines() {
+                    match line.map(|l| l.parse::<Remote>()) {
+                        Ok(Ok(remote)) => handler(remote),
+                        Ok(Err(unknown)) => warn!("{}", unknown),
+                        Err(e) => {
+                            warn!("IPC read failed: {}", e);
+                            break;
+                        },
+                    }
+                }
+            }
+        }))
+    }
+}
+
+impl Drop for IpcServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+pub struct IpcClient {
+    path: PathBuf,
+}
+
+impl IpcClient {
+    pub fn send(&self, remote: &Remote) -> io::Result<()> {
+        let mut stream = UnixStream::connect(&self.path)?;
+        writeln!(stream, "{}", remote)
+    }
+}
+
+fn socket_path() -> PathBuf {
+    let mut p = match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => std::env::temp_dir(),
+    };
+    let user = std::env::var("USER").unwrap_or_else(|_| "default".to_owned());
+    p.push(format!("{}-{}.sock", SOCKET_NAME, user));
+    p
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn remote_round_trip() {
+        let all = [
+            Remote::Focus,
+            Remote::ToggleFullscreen,
+            Remote::ShowMez,
+            Remote::ShowSettings,
+            Remote::Quit,
+            Remote::ResetScene,
+            Remote::Set(ParamChange::Gain(2.5)),
+            Remote::Profile(Some("laptop".to_owned())),
+            Remote::Profile(None),
+            Remote::Record,
+            Remote::Freeze,
+            Remote::Pause,
+        ];
+        for remote in all.iter() {
+            assert_eq!(remote.to_string().parse::<Remote>().unwrap(), *remote);
+        }
+        assert!("explode".parse::<Remote>().is_err());
+        assert!("profile two words".parse::<Remote>().is_err());
+    }
+
+    #[test]
+    fn second_claim_forwards() {
+        let mut path = std::env::temp_dir();
+        path.push(format!("e-nguyen-test-{}.sock", std::process::id()));
+        let server = match claim_at(path.clone()).unwrap() {
+            Claim::Primary(server) => server,
+            Claim::Secondary(_) => panic!("socket should be free"),
+        };
+        let (tx, rx) = mpsc::channel();
+        server.serve(move |remote| tx.send(remote).unwrap()).unwrap();
+        match claim_at(path.clone()).unwrap() {
+            Claim::Secondary(client) => client.send(&Remote::ToggleFullscreen).unwrap(),
+            Claim::Primary(_) => panic!("first claim should still be held"),
+        }
+        let received = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(received, Remote::ToggleFullscreen);
+        drop(server);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn stale_sockets_are_reclaimed() {
+        let mut path = std::env::temp_dir();
+        path.push(format!("e-nguyen-stale-{}.sock", std::process::id()));
+        // a listener that goes away leaves its socket behind, as a crash would
+        drop(UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+        match claim_at(path.clone()).unwrap() {
+            Claim::Primary(server) => drop(server),
+            Claim::Secondary(_) => panic!("nobody is listening on a stale socket"),
+        }
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn silent_clients_time_out() {
+        let mut path = std::env::temp_dir();
+        path.push(format!("e-nguyen-silent-{}.sock", std::process::id()));
+        let server = match claim_at(path.clone()).unwrap() {
+            Claim::Primary(server) => server,
+            Claim::Secondary(_) => panic!("socket should be free"),
+        };
+        let (tx, rx) = mpsc::channel();
+        server.serve(move |remote| tx.send(remote).unwrap()).unwrap();
+        let _silent = UnixStream::connect(&path).unwrap();
+        IpcClient { path: path.clone() }.send(&Remote::Focus).unwrap();
+        let received = rx.recv_timeout(READ_TIMEOUT * 5).unwrap();
+        assert_eq!(received, Remote::Focus);
+    }
+}
@@ -0,0 +1,185 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! Attract mode for permanent installations.  The App asks the kiosk which scene is due and
+//! when to write a health line, and relaunches whatever fails for as long as it runs.  Demos
+//! turn through the scenes the same way.
+
+use crate::config::KioskConfig;
+use crate::logging::{data_dir, rfc3339, LogFile};
+use crate::pacing::PacingSummary;
+use crate::params::Scene;
+
+use log::warn;
+use serde::Serialize;
+use serde_json::json;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+static HEALTH_LOG: &str = "kiosk-health.log";
+
+/// What a health line reports
+#[derive(Debug, Serialize)]
+pub struct Health {
+    /// Whether the visualizer window is open
+    pub visualizer: bool,
+    pub scene: Scene,
+    /// Audio analysis and visualizer restarts since launch
+    pub restarts: u64,
+    /// Frame pacing of the open window
+    pub pacing: Option<PacingSummary>,
+}
+
+pub struct Kiosk {
+    config: KioskConfig,
+    started: Instant,
+    rotation: SceneRotation,
+    last_health: Option<Instant>,
+    health_log: Option<LogFile>,
+}
+
+impl Kiosk {
+    pub fn new(config: &KioskConfig) -> Kiosk {
+        let path = config.health_log.clone().unwrap_or_else(|| data_dir().join(HEALTH_LOG));
+        let health_log = match LogFile::open(&path) {
+            Ok(file) => Some(file),
+            Err(e) => {
+                warn!("Kiosk health won't be logged to {}: {}", path.display(), e);
+                None
+            },
+        };
+        Kiosk {
+            config: config.clone(),
+            started: Instant::now(),
+            rotation: SceneRotation::new(&config.scenes, config.scene_seconds),
+            last_health: None,
+            health_log,
+        }
+    }
+
+    /// The scene to switch to at `now`, if any
+    pub fn rotate(&mut self, current: Scene, now: Instant) -> Option<Scene> {
+        self.rotation.rotate(current, now)
+    }
+
+    /// Whether a health line is due at `now`
+    pub fn health_due(&self, now: Instant) -> bool {
+        let interval = Duration::from_millis((self.config.health_seconds * 1000.0) as u64);
+        self.last_health.map_or(true, |last| now.duration_since(last) >= interval)
+    }
+
+    /// Appends a health line, one JSON object, to the health log
+    pub fn log_health(&mut self, health: &Health, now: Instant) {
+        self.last_health = Some(now);
+        let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let line = json!({
+            "time": rfc3339(since_epoch),
+            "uptime": now.duration_since(self.started).as_secs(),
+            "health": health,
+        });
+        if let Some(log) = &mut self.health_log {
+            if let Err(e) = log.write_line(&line.to_string()) {
+                warn!("Stopped logging kiosk health: {}", e);
+                self.health_log = None;
+            }
+        }
+    }
+}
+
+/// Shows scenes in turn, for kiosks and demos
+pub struct SceneRotation {
+    /// Every scene if empty
+    scenes: Vec<Scene>,
+    /// Zero stays on one
+    turn: Duration,
+    /// The scene shown and since when
+    shown: Option<(Scene, Instant)>,
+}
+
+impl SceneRotation {
+    pub fn new(scenes: &[Scene], seconds: f32) -> SceneRotation {
+        let turn = Duration::from_millis((seconds.max(0.0) * 1000.0) as u64);
+        SceneRotation { scenes: scenes.to_vec(), turn, shown: None }
+    }
+
+    /// The scene to switch to at `now` if `current` has been shown long enough.  Scenes
+    /// picked by other means, such as a remote, get their full turn too.
+    pub fn rotate(&mut self, current: Scene, now: Instant) -> Option<Scene> {
+        match self.shown {
+            Some((shown, since)) if shown == current => {
+                if self.turn == Duration::from_secs(0) || now.duration_since(since) < self.turn {
+                    return None;
+                }
+                let next = next_scene(&self.scenes, current);
+                self.shown = Some((next, now));
+                Some(next).filter(|next| *next != current)
+            },
+            _ => {
+                self.shown = Some((current, now));
+                None
+            },
+        }
+    }
+}
+
+/// The scene after `current` in `scenes`, or in every scene if there are none
+pub fn next_scene(scenes: &[Scene], current: Scene) -> Scene {
+    if scenes.is_empty() {
+        return current.next();
+    }
+    match scenes.iter().position(|scene| *scene == current) {
+        Some(shown) => scenes[(shown + 1) % scenes.len()],
+        None => scenes[0],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scenes_take_turns() {
+        let turns = [Scene::Tunnel, Scene::Piano];
+        assert_eq!(next_scene(&turns, Scene::Tunnel), Scene::Piano);
+        assert_eq!(next_scene(&turns, Scene::Piano), Scene::Tunnel);
+        assert_eq!(next_scene(&turns, Scene::Scroll), Scene::Tunnel);
+        assert_eq!(next_scene(&[], Scene::Scroll), Scene::Studio);
+
+        let dir = std::env::temp_dir().join(format!("e-nguyen-kiosk-{}", std::process::id()));
+        let config = KioskConfig {
+            scenes: turns.to_vec(),
+            scene_seconds: 10.0,
+            health_log: Some(dir.join(HEALTH_LOG)),
+            ..KioskConfig::default()
+        };
+        let mut kiosk = Kiosk::new(&config);
+        let start = Instant::now();
+        assert_eq!(kiosk.rotate(Scene::Tunnel, start), None);
+        assert_eq!(kiosk.rotate(Scene::Tunnel, start + Duration::from_secs(9)), None);
+        let later = start + Duration::from_secs(10);
+        assert_eq!(kiosk.rotate(Scene::Tunnel, later), Some(Scene::Piano));
+        // a scene picked by hand starts its turn over
+        assert_eq!(kiosk.rotate(Scene::Meters, later + Duration::from_secs(5)), None);
+        assert_eq!(kiosk.rotate(Scene::Meters, later + Duration::from_secs(14)), None);
+
+        assert!(kiosk.health_due(start));
+        let health = Health { visualizer: true, scene: Scene::Piano, restarts: 1, pacing: None };
+        kiosk.log_health(&health, start);
+        assert!(!kiosk.health_due(start + Duration::from_secs(59)));
+        let logged = std::fs::read_to_string(dir.join(HEALTH_LOG)).unwrap();
+        let line: serde_json::Value = serde_json::from_str(logged.trim()).unwrap();
+        assert_eq!(line["health"]["scene"], "piano");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
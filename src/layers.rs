@@ -0,0 +1,316 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! Images composited over the scenes.  An `ImageLayer` draws a texture into any rectangle
+//! of a window, and the `Logo` is the user's image placed by the `[logo]` config section,
+//! moving with the music.
+
+use crate::bench::millis;
+use crate::config::{Anchor, LogoConfig};
+use crate::errors::ENguyenError;
+use crate::features::AudioFeatures;
+use crate::geometry::{pixel_rect, textured_strip};
+use crate::rendering::{layer_fsm, over_blending, uv_image_vsm, PipelineRegistry, XyUvVertex};
+use crate::upload;
+
+use image::RgbaImage;
+use log::warn;
+use std::sync::Arc;
+use std::time::Duration;
+use vulkano::buffer::{BufferAccess, CpuBufferPool};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DynamicState};
+use vulkano::descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet};
+use vulkano::device::Queue;
+use vulkano::pipeline::{GraphicsPipeline, GraphicsPipelineAbstract};
+use vulkano::sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode};
+use vulkano::sync::GpuFuture;
+
+/// Shake cycles per second on each axis.  Unrelated rates so it doesn't trace a pattern.
+static SHAKE_RATES: [f32; 2] = [7.3, 11.9];
+
+/// Textures drawn into a rectangle, blended over what is already drawn.  Holds one image or
+/// the frames of an animation.
+pub struct ImageLayer {
+    pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    sets: Vec<Arc<dyn DescriptorSet + Send + Sync>>,
+    vertices: CpuBufferPool<XyUvVertex>,
+    /// Pixels of the first frame
+    pub dimensions: [u32; 2],
+}
+
+impl ImageLayer {
+    /// Uploads `image`.  Draws once the returned future completes.
+    pub fn new(
+        queue: &Arc<Queue>,
+        pipelines: &PipelineRegistry,
+        image: RgbaImage,
+    ) -> Result<(ImageLayer, Box<dyn GpuFuture>), ENguyenError> {
+        ImageLayer::sequence(queue, pipelines, vec![image])
+    }
+
+    /// Uploads each of `frames`, which must not be empty
+    pub fn sequence(
+        queue: &Arc<Queue>,
+        pipelines: &PipelineRegistry,
+        frames: Vec<RgbaImage>,
+    ) -> Result<(ImageLayer, Box<dyn GpuFuture>), ENguyenError> {
+        let device = queue.device().clone();
+        let dimensions = match frames.first() {
+            Some(first) => [first.width(), first.height()],
+            None => return Err(ENguyenError::render("No frames to draw")),
+        };
+
+        let pipeline = pipelines.get("layer", |device, subpass| {
+            let vs = uv_image_vsm::Shader::load(device.clone())
+                .map_err(|e| ENguyenError::render("Layer shader failed to load").caused_by(e))?;
+            let fs = layer_fsm::Shader::load(device.clone())
+                .map_err(|e| ENguyenError::render("Layer shader failed to load").caused_by(e))?;
+            let pipeline = GraphicsPipeline::start()
+                .triangle_strip()
+                .vertex_input_single_buffer::<XyUvVertex>()
+                .vertex_shader(vs.main_entry_point(), ())
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(fs.main_entry_point(), ())
+                .blend_collective(over_blending())
+                .render_pass(subpass)
+                .build(device.clone())
+                .map_err(|e| ENguyenError::render("Layer pipeline failed").caused_by(e))?;
+            Ok(Arc::new(pipeline))
+        })?;
+
+        let sampler = Sampler::new(
+            device.clone(),
+            Filter::Linear,
+            Filter::Linear,
+            MipmapMode::Nearest,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            0.0,
+            1.0,
+            0.0,
+            0.0,
+        )
+        .map_err(|e| ENguyenError::render("Layer sampler failed").caused_by(e))?;
+
+        let mut sets: Vec<Arc<dyn DescriptorSet + Send + Sync>> = Vec::new();
+        let mut uploads: Box<dyn GpuFuture> = Box::new(vulkano::sync::now(device.clone()));
+        for frame in frames {
+            let (texture, uploaded) = upload::texture(queue, frame)?;
+            uploads = Box::new(uploads.join(uploaded));
+            sets.push(Arc::new(
+                PersistentDescriptorSet::start(pipeline.clone(), 0)
+                    .add_sampled_image(texture, sampler.clone())
+                    .map_err(|e| ENguyenError::render("Layer descriptor failed").caused_by(e))?
+                    .build()
+                    .map_err(|e| ENguyenError::render("Layer descriptor failed").caused_by(e))?,
+            ));
+        }
+
+        let vertices = CpuBufferPool::vertex_buffer(device);
+        let layer = ImageLayer { pipeline, sets, vertices, dimensions };
+        Ok((layer, uploads))
+    }
+
+    pub fn frames(&self) -> usize {
+        self.sets.len()
+    }
+
+    /// Records the first frame stretched over `rect`, left, top, right and bottom in window
+    /// coordinates, with its colors multiplied by `brightness`
+    pub fn draw(
+        &self,
+        cbb: AutoCommandBufferBuilder,
+        state: &DynamicState,
+        rect: [f32; 4],
+        brightness: f32,
+    ) -> Result<AutoCommandBufferBuilder, ENguyenError> {
+        self.draw_frame(cbb, state, rect, brightness, 0)
+    }
+
+    /// As `draw`, for `frame` wrapped to the frames there are
+    pub fn draw_frame(
+        &self,
+        cbb: AutoCommandBufferBuilder,
+        state: &DynamicState,
+        rect: [f32; 4],
+        brightness: f32,
+        frame: usize,
+    ) -> Result<AutoCommandBufferBuilder, ENguyenError> {
+        let chunk = self
+            .vertices
+            .chunk(textured_strip(rect).iter().cloned())
+            .map_err(|e| ENguyenError::render("Vertex upload failed").caused_by(e))?;
+        let buffers: Vec<Arc<dyn BufferAccess + Send + Sync>> = vec![Arc::new(chunk)];
+        let push_constants = layer_fsm::ty::PushConstant { brightness };
+        let set = self.sets[frame % self.sets.len()].clone();
+        cbb.draw(self.pipeline.clone(), state, buffers, set, push_constants)
+            .map_err(|e| ENguyenError::render("Layer draw failed").caused_by(e))
+    }
+}
+
+/// How the music has moved the logo
+#[derive(Clone, Debug, PartialEq)]
+struct Motion {
+    seconds: f32,
+    /// Multiplies the configured size
+    scale: f32,
+    brightness: f32,
+    /// Pixels
+    shake: [f32; 2],
+}
+
+impl Default for Motion {
+    fn default() -> Motion {
+        Motion { seconds: 0.0, scale: 1.0, brightness: 1.0, shake: [0.0; 2] }
+    }
+}
+
+impl Motion {
+    fn update(&mut self, config: &LogoConfig, features: &AudioFeatures, elapsed: Duration) {
+        // wrapped where the shake repeats, keeping the sines precise
+        self.seconds = (self.seconds + millis(elapsed) as f32 / 1000.0) % 10.0;
+        let kick = (1.0 - features.beat.phase).powi(4);
+        self.scale = 1.0 + config.pulse * kick;
+        self.brightness = 1.0 + config.glow * features.bands[1];
+        let reach = config.shake * features.bands[0];
+        for (shake, rate) in self.shake.iter_mut().zip(SHAKE_RATES.iter()) {
+            *shake = reach * (self.seconds * rate * 2.0 * std::f32::consts::PI).sin();
+        }
+    }
+}
+
+/// The user's image, drawn over every scene
+pub struct Logo {
+    layer: ImageLayer,
+    config: LogoConfig,
+    motion: Motion,
+}
+
+impl Logo {
+    /// The configured logo.  None if there isn't one or, with a warning, if it can't be read.
+    pub fn load(
+        config: &LogoConfig,
+        queue: &Arc<Queue>,
+        pipelines: &PipelineRegistry,
+    ) -> Option<(Logo, Box<dyn GpuFuture>)> {
+        let path = config.path.as_ref()?;
+        let image = match image::open(path) {
+            Ok(image) => image.to_rgba(),
+            Err(e) => {
+                warn!("Logo {} could not be read: {}", path.display(), e);
+                return None;
+            },
+        };
+        match ImageLayer::new(queue, pipelines, image) {
+            Ok((layer, uploaded)) => {
+                let logo = Logo { layer, config: config.clone(), motion: Motion::default() };
+                Some((logo, uploaded))
+            },
+            Err(e) => {
+                warn!("Logo {} could not be drawn: {}", path.display(), e);
+                None
+            },
+        }
+    }
+
+    /// Move with `features`, measured `elapsed` after the previous ones
+    pub fn update(&mut self, features: &AudioFeatures, elapsed: Duration) {
+        self.motion.update(&self.config, features, elapsed);
+    }
+
+    /// Records the logo in a window of `window` pixels
+    pub fn draw(
+        &self,
+        cbb: AutoCommandBufferBuilder,
+        state: &DynamicState,
+        window: [f32; 2],
+    ) -> Result<AutoCommandBufferBuilder, ENguyenError> {
+        let rect = place(&self.config, self.layer.dimensions, window, &self.motion);
+        self.layer.draw(cbb, state, rect, self.motion.brightness)
+    }
+}
+
+/// The logo's rectangle.  Pulses grow it around its middle, so the margin from the edges
+/// it is anchored to holds at rest.
+fn place(config: &LogoConfig, image: [u32; 2], window: [f32; 2], motion: &Motion) -> [f32; 4] {
+    let [width, height] = window;
+    let rest_height = height * config.size;
+    let rest_width = rest_height * image[0] as f32 / image[1].max(1) as f32;
+    let margin = config.margin;
+    let (left, right) = (margin + rest_width / 2.0, width - margin - rest_width / 2.0);
+    let (top, bottom) = (margin + rest_height / 2.0, height - margin - rest_height / 2.0);
+    let [x, y] = match config.anchor {
+        Anchor::Center => [width / 2.0, height / 2.0],
+        Anchor::TopLeft => [left, top],
+        Anchor::TopRight => [right, top],
+        Anchor::BottomLeft => [left, bottom],
+        Anchor::BottomRight => [right, bottom],
+    };
+    let [shake_x, shake_y] = motion.shake;
+    let (drawn_width, drawn_height) = (rest_width * motion.scale, rest_height * motion.scale);
+    pixel_rect(
+        window,
+        x + shake_x - drawn_width / 2.0,
+        y + shake_y - drawn_height / 2.0,
+        drawn_width,
+        drawn_height,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(rect: [f32; 4], expected: [f32; 4]) {
+        for (placed, expected) in rect.iter().zip(expected.iter()) {
+            assert!((placed - expected).abs() < 1e-6, "{:?} is not {:?}", rect, expected);
+        }
+    }
+
+    #[test]
+    fn logo_is_anchored() {
+        let window = [1000.0, 500.0];
+        let config = LogoConfig { anchor: Anchor::BottomRight, ..LogoConfig::default() };
+        // a 2:1 image 20% of a 1000 by 500 window is 200 by 100 pixels
+        let rect = place(&config, [400, 200], window, &Motion::default());
+        let (left, top) = (1000.0 - 32.0 - 200.0, 500.0 - 32.0 - 100.0);
+        assert_near(rect, pixel_rect(window, left, top, 200.0, 100.0));
+
+        let config = LogoConfig { anchor: Anchor::Center, ..LogoConfig::default() };
+        let rect = place(&config, [400, 200], window, &Motion::default());
+        assert_near(rect, [-0.2, -0.2, 0.2, 0.2]);
+        assert_near(pixel_rect([100.0, 100.0], 0.0, 0.0, 100.0, 50.0), [-1.0, -1.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn logo_moves_with_the_music() {
+        let config = LogoConfig { shake: 10.0, ..LogoConfig::default() };
+        let mut features = AudioFeatures::default();
+        features.bands = [1.0, 0.5, 0.0, 0.0];
+        let mut motion = Motion::default();
+        motion.update(&config, &features, Duration::from_millis(30));
+        // on the beat, the logo is at its largest
+        assert!((motion.scale - (1.0 + config.pulse)).abs() < 1e-6);
+        assert!((motion.brightness - (1.0 + config.glow * 0.5)).abs() < 1e-6);
+        assert!(motion.shake.iter().all(|shake| shake.abs() <= 10.0));
+        assert!(motion.shake.iter().any(|shake| shake.abs() > 0.0));
+
+        features.beat.phase = 1.0;
+        features.bands = [0.0; 4];
+        motion.update(&config, &features, Duration::from_millis(30));
+        assert_eq!(motion, Motion { seconds: motion.seconds, ..Motion::default() });
+    }
+}
@@ -0,0 +1,265 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! LED strips lit by the spectrum.  Each analysis window's levels are spread along the strip,
+//! bass red through highs violet, and sent to a WLED controller or any E1.31 or Art-Net node
+//! as one frame of pixels.
+
+use crate::config::{LedConfig, LedMapping, LedProtocol};
+use crate::errors::ENguyenError;
+use crate::export::{with_port, FeatureRecord, FeatureSink};
+use crate::rendering::hue;
+
+use std::io::ErrorKind;
+use std::net::UdpSocket;
+
+/// Seconds WLED holds the last frame before returning to its own effects
+static WLED_TIMEOUT: u8 = 2;
+/// Pixels in one WLED DRGB packet
+static WLED_DRGB_PIXELS: usize = 490;
+/// Pixels in one WLED DNRGB packet, which also carries a start index
+static WLED_DNRGB_PIXELS: usize = 489;
+/// RGB pixels in one DMX universe of 512 slots
+static UNIVERSE_PIXELS: usize = 170;
+/// Hue of the highest frequencies, as a share of the color wheel
+static HIGHEST_HUE: f32 = 0.75;
+static SOURCE_NAME: &[u8] = b"E-Nguyen";
+/// E1.31 component identifier, the same for every run
+static CID: [u8; 16] = *b"e-nguyen-led-out";
+
+impl LedProtocol {
+    fn port(self) -> u16 {
+        match self {
+            LedProtocol::Wled => 21324,
+            LedProtocol::E131 => 5568,
+            LedProtocol::ArtNet => 6454,
+        }
+    }
+}
+
+/// Sends a frame of pixels per analysis window
+pub struct LedSink {
+    socket: UdpSocket,
+    config: LedConfig,
+    sequence: u8,
+}
+
+impl LedSink {
+    pub fn connect(config: &LedConfig) -> Result<LedSink, ENguyenError> {
+        let address = with_port(&config.address, config.protocol.port());
+        let socket = UdpSocket::bind("0.0.0.0:0")
+            .and_then(|socket| socket.connect(&address).map(|_| socket))
+            .map_err(|e| {
+                ENguyenError::config(format!("Could not reach {}", address)).caused_by(e)
+            })?;
+        socket.set_nonblocking(true)?;
+        Ok(LedSink { socket, config: config.clone(), sequence: 0 })
+    }
+
+    /// Every packet of one frame
+    fn packets(&mut self, pixels: &[[u8; 3]]) -> Vec<Vec<u8>> {
+        self.sequence = self.sequence.wrapping_add(1);
+        match self.config.protocol {
+            LedProtocol::Wled => wled_packets(pixels),
+            protocol => pixels
+                .chunks(UNIVERSE_PIXELS)
+                .enumerate()
+                .map(|(index, pixels)| {
+                    let universe = self.config.universe.wrapping_add(index as u16);
+                    let slots: Vec<u8> = pixels.iter().flatten().cloned().collect();
+                    if protocol == LedProtocol::E131 {
+                        e131_packet(universe, self.sequence, &slots)
+                    } else {
+                        art_net_packet(universe, self.sequence, &slots)
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// Light the strip with `pixels`, from its first pixel on
+    pub fn send_pixels(&mut self, pixels: &[[u8; 3]]) -> Result<(), ENguyenError> {
+        for packet in self.packets(pixels) {
+            match self.socket.send(&packet) {
+                Ok(_) => {},
+                // a controller that's off or busy only misses this frame
+                Err(e) if e.kind() == ErrorKind::ConnectionRefused => return Ok(()),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FeatureSink for LedSink {
+    fn send(&mut self, record: &FeatureRecord) -> Result<(), ENguyenError> {
+        let pixels = strip(&self.config, record);
+        self.send_pixels(&pixels)
+    }
+}
+
+/// The strip's pixels for one window.  Without a spectrum the four bands are spread instead.
+fn strip(config: &LedConfig, record: &FeatureRecord) -> Vec<[u8; 3]> {
+    let levels: &[f32] = if record.spectrum.is_empty() { &record.bands } else { &record.spectrum };
+    let length = config.length;
+    (0..length)
+        .map(|pixel| {
+            let along = (pixel as f32 + 0.5) / length as f32;
+            let (place, level) = match config.mapping {
+                LedMapping::Spectrum => (along, sample(levels, along)),
+                LedMapping::Mirror => {
+                    let place = (along * 2.0 - 1.0).abs();
+                    (place, sample(levels, place))
+                },
+                LedMapping::Bands => {
+                    let band = (pixel * 4 / length.max(1)).min(3);
+                    ((band as f32 + 0.5) / 4.0, record.bands[band])
+                },
+            };
+            let scale = level.clamp(0.0, 1.0) * config.brightness.clamp(0.0, 1.0) * 255.0;
+            let [red, green, blue] = hue(place * HIGHEST_HUE);
+            [(red * scale) as u8, (green * scale) as u8, (blue * scale) as u8]
+        })
+        .collect()
+}
+
+/// The level `place` of the way from the first to the last of `levels`
+fn sample(levels: &[f32], place: f32) -> f32 {
+    let index = (place * levels.len() as f32) as usize;
+    levels[index.min(levels.len() - 1)]
+}
+
+/// DRGB packets if the strip fits in one, otherwise DNRGB packets that each say where they
+/// start
+fn wled_packets(pixels: &[[u8; 3]]) -> Vec<Vec<u8>> {
+    if pixels.len() <= WLED_DRGB_PIXELS {
+        let mut packet = vec![2, WLED_TIMEOUT];
+        packet.extend(pixels.iter().flatten());
+        return vec![packet];
+    }
+    pixels
+        .chunks(WLED_DNRGB_PIXELS)
+        .enumerate()
+        .map(|(index, chunk)| {
+            let start = (index * WLED_DNRGB_PIXELS) as u16;
+            let mut packet = vec![4, WLED_TIMEOUT, (start >> 8) as u8, start as u8];
+            packet.extend(chunk.iter().flatten());
+            packet
+        })
+        .collect()
+}
+
+/// An E1.31 data packet of `slots` DMX values
+fn e131_packet(universe: u16, sequence: u8, slots: &[u8]) -> Vec<u8> {
+    let length = 126 + slots.len();
+    // each layer starts with 0x7 flags and the length from there to the end of the packet
+    let flags_and_length = |from: usize| (0x7000 | (length - from) as u16).to_be_bytes();
+    let mut packet = Vec::with_capacity(length);
+    // root layer
+    packet.extend(&[0x00, 0x10, 0x00, 0x00]);
+    packet.extend(b"ASC-E1.17\0\0\0");
+    packet.extend(&flags_and_length(16));
+    packet.extend(&4u32.to_be_bytes());
+    packet.extend(&CID);
+    // framing layer
+    packet.extend(&flags_and_length(38));
+    packet.extend(&2u32.to_be_bytes());
+    let mut source_name = [0; 64];
+    source_name[..SOURCE_NAME.len()].copy_from_slice(SOURCE_NAME);
+    packet.extend(&source_name[..]);
+    packet.push(100); // priority
+    packet.extend(&[0, 0]); // no synchronization universe
+    packet.push(sequence);
+    packet.push(0); // options
+    packet.extend(&universe.to_be_bytes());
+    // DMP layer
+    packet.extend(&flags_and_length(115));
+    packet.extend(&[0x02, 0xa1, 0x00, 0x00, 0x00, 0x01]);
+    packet.extend(&(slots.len() as u16 + 1).to_be_bytes());
+    packet.push(0); // DMX start code
+    packet.extend(slots);
+    packet
+}
+
+/// An ArtDmx packet of `slots` DMX values, padded to an even count
+fn art_net_packet(universe: u16, sequence: u8, slots: &[u8]) -> Vec<u8> {
+    let count = slots.len() + slots.len() % 2;
+    let mut packet = Vec::with_capacity(18 + count);
+    packet.extend(b"Art-Net\0");
+    packet.extend(&0x5000u16.to_le_bytes());
+    packet.extend(&14u16.to_be_bytes()); // protocol version
+    packet.push(sequence);
+    packet.push(0); // physical port
+    packet.extend(&universe.to_le_bytes());
+    packet.extend(&(count as u16).to_be_bytes());
+    packet.extend(slots);
+    packet.resize(18 + count, 0);
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::features::AudioFeatures;
+    use std::time::Duration;
+
+    #[test]
+    fn strips_follow_the_spectrum() {
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        listener.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let config = LedConfig { address, length: 4, ..LedConfig::default() };
+        let mut sink = LedSink::connect(&config).unwrap();
+
+        let spectrum = vec![1.0, 0.0, 0.0, 0.5];
+        let mut features = AudioFeatures { spectrum, ..AudioFeatures::default() };
+        sink.send(&FeatureRecord::new(&features, 0.0)).unwrap();
+        let mut packet = [0; 64];
+        let len = listener.recv(&mut packet).unwrap();
+        // bass is fully bright orange, the highs half bright blue and the rest dark
+        assert_eq!(&packet[..len], &[2, 2, 255, 143, 0, 0, 0, 0, 0, 0, 0, 0, 7, 127]);
+
+        let mirrored = LedConfig { mapping: LedMapping::Mirror, ..config.clone() };
+        let pixels = strip(&mirrored, &FeatureRecord::new(&features, 0.0));
+        assert_eq!(pixels[0], pixels[3]);
+        let bands = LedConfig { mapping: LedMapping::Bands, length: 8, ..config };
+        features.bands = [0.0, 0.0, 1.0, 0.0];
+        let pixels = strip(&bands, &FeatureRecord::new(&features, 0.0));
+        assert_eq!(pixels.iter().filter(|pixel| **pixel != [0, 0, 0]).count(), 2);
+    }
+
+    #[test]
+    fn packets_are_laid_out() {
+        let pixels = vec![[1, 2, 3]; 200];
+        let config = LedConfig { length: 200, protocol: LedProtocol::E131, ..Default::default() };
+        let mut sink = LedSink::connect(&config).unwrap();
+        let packets = sink.packets(&pixels);
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].len(), 126 + 510);
+        assert_eq!(&packets[0][16..18], &[0x72, 0x6c]);
+        assert_eq!((packets[1][111], &packets[1][113..115]), (1, &[0, 2][..]));
+        assert_eq!(&packets[1][123..126], &[0, 91, 0]);
+
+        sink.config.protocol = LedProtocol::ArtNet;
+        let packets = sink.packets(&pixels);
+        assert_eq!(&packets[1][..12], b"Art-Net\0\x00\x50\x00\x0e");
+        assert_eq!((packets[1][12], &packets[1][14..18]), (2, &[2, 0, 0, 90][..]));
+
+        let long = wled_packets(&vec![[0; 3]; 600]);
+        assert_eq!(long.iter().map(|packet| packet.len()).collect::<Vec<_>>(), vec![1471, 337]);
+        assert_eq!(&long[1][..4], &[4, 2, 1, 233]);
+    }
+}
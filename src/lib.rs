@@ -0,0 +1,77 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! E-Nguyen builds music visualizations from whatever your sound server is playing.
+//!
+//! The pieces are usable on their own.  `audio` captures from a sound server into a `ring`,
+//! `compute` turns the captured audio into a `scrolling` GPU texture and the `features`
+//! scenes draw directly, `ewin` picks a GPU and owns a swapchain, `rendering` holds shared
+//! shaders, `scenes` the `Scene` trait every visualization implements, `params` carries live
+//! tuning between threads, and `application` launches the visualizer and settings windows.
+
+pub mod application;
+pub mod audio;
+pub mod autostart;
+mod background;
+pub mod beat;
+pub mod bench;
+mod borderless;
+pub mod calibrate;
+mod compare;
+pub mod compute;
+pub mod config;
+pub mod doctor;
+pub mod edges;
+pub mod errors;
+pub mod events;
+pub mod ewin;
+pub mod export;
+pub mod features;
+pub mod geometry;
+pub mod golden;
+mod goniometer;
+pub mod idle;
+pub mod input;
+pub mod ipc;
+mod kiosk;
+mod layers;
+pub mod led;
+pub mod lights;
+pub mod logging;
+mod mesmerize;
+pub mod metadata;
+mod meters;
+pub mod mqtt;
+mod now_playing;
+pub mod pacing;
+pub mod params;
+mod piano;
+pub mod power;
+pub mod recording;
+pub mod rendering;
+pub mod replay;
+mod review;
+pub mod ring;
+mod ruler;
+mod scenes;
+pub mod scrolling;
+mod settings;
+pub mod shader_reload;
+pub mod status;
+mod studio;
+pub mod tracks;
+mod tunnel;
+pub mod upload;
+pub mod websocket;
@@ -0,0 +1,312 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! Room lights that follow the music.  Bulbs take a command or two a second at most, so
+//! instead of the spectrum they're sent its dominant color, tinted by the palette on screen
+//! and faded over seconds, with beats pulsing the brightness.  Commands go out from their own
+//! thread, since bridges and brokers can take a while to answer.
+//!
+//! Hue entertainment areas are set as a group through the bridge's REST API, not its DTLS
+//! streaming.
+
+use crate::config::{LightsBackend, LightsConfig};
+use crate::errors::ENguyenError;
+use crate::export::{with_port, FeatureRecord, FeatureSink};
+use crate::mqtt::{open, MqttClient, Publication};
+use crate::rendering::hue;
+
+use log::warn;
+use serde_json::json;
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::thread;
+
+/// Where along the color wheel sub-bass, bass, mids and highs pull the color
+static BAND_HUES: [f32; 4] = [0.0, 0.07, 0.3, 0.62];
+/// Seconds a beat's pulse takes to fall to a third
+static PULSE_DECAY: f32 = 0.4;
+/// Brightness with nothing playing
+static RESTING_BRIGHTNESS: f32 = 0.2;
+static HUE_PORT: u16 = 80;
+
+/// One command to the lights
+#[derive(Clone, Debug, PartialEq)]
+struct LightState {
+    color: [f32; 3],
+    /// 0.0 to 1.0
+    brightness: f32,
+    /// Seconds the lights take to change
+    transition: f32,
+}
+
+/// The music's dominant color and beat, slowed down for bulbs
+struct Ambience {
+    /// Seconds the color takes to follow the music
+    fade: f32,
+    color: [f32; 3],
+    loudness: f32,
+    pulse: f32,
+    /// `FeatureRecord::time` of the last window heard
+    last: Option<f64>,
+}
+
+impl Ambience {
+    fn new(fade: f32) -> Ambience {
+        Ambience { fade: fade.max(0.01), color: [1.0; 3], loudness: 0.0, pulse: 0.0, last: None }
+    }
+
+    fn hear(&mut self, record: &FeatureRecord) {
+        let elapsed = self.last.map_or(0.0, |last| (record.time - last).max(0.0)) as f32;
+        let follow = match self.last {
+            Some(_) => 1.0 - (-elapsed / self.fade).exp(),
+            None => 1.0,
+        };
+        self.last = Some(record.time);
+
+        let target = dominant(record);
+        for (channel, target) in self.color.iter_mut().zip(&target) {
+            *channel += (target - *channel) * follow;
+        }
+        let loudest = record.bands.iter().cloned().fold(0.0, f32::max);
+        self.loudness += (loudest - self.loudness) * follow;
+        self.pulse *= (-elapsed / PULSE_DECAY).exp();
+        if record.onset {
+            self.pulse = 1.0;
+        }
+    }
+
+    fn state(&self, transition: f32) -> LightState {
+        let lit = RESTING_BRIGHTNESS + 0.5 * self.loudness + 0.3 * self.pulse;
+        LightState { color: self.color, brightness: lit.clamp(0.0, 1.0), transition }
+    }
+}
+
+/// The bands' hues mixed by their levels and tinted by the palette, brightest channel at 1.0
+fn dominant(record: &FeatureRecord) -> [f32; 3] {
+    let total: f32 = record.bands.iter().sum();
+    let mut mixed = [1.0; 3];
+    if total > 0.0 {
+        mixed = [0.0; 3];
+        for (band, hue_at) in record.bands.iter().zip(&BAND_HUES) {
+            for (channel, pure) in mixed.iter_mut().zip(&hue(*hue_at)) {
+                *channel += pure * band / total;
+            }
+        }
+    }
+    let [red, green, blue] = record.palette.weights();
+    let tinted = [mixed[0] * red, mixed[1] * green, mixed[2] * blue];
+    let brightest = tinted.iter().cloned().fold(0.0, f32::max).max(1e-6);
+    [tinted[0] / brightest, tinted[1] / brightest, tinted[2] / brightest]
+}
+
+/// Sends the lights a command `rate` times a second, and on beats in between
+pub struct LightSink {
+    commands: SyncSender<LightState>,
+    ambience: Ambience,
+    /// Seconds between commands
+    interval: f64,
+    last_sent: Option<f64>,
+}
+
+impl LightSink {
+    pub fn connect(config: &LightsConfig) -> Result<LightSink, ENguyenError> {
+        if config.backend == LightsBackend::Hue && config.username.is_none() {
+            return Err(ENguyenError::config("Hue needs a username, the bridge's application key"));
+        }
+        let mut client = LightClient::new(config);
+        // one command waits while another goes out, newer ones are skipped rather than queued
+        let (commands, pending) = sync_channel(1);
+        thread::spawn(move || client.run(pending));
+        let interval = 1.0 / f64::from(config.rate.max(0.01));
+        Ok(LightSink { commands, ambience: Ambience::new(config.fade), interval, last_sent: None })
+    }
+}
+
+impl FeatureSink for LightSink {
+    fn send(&mut self, record: &FeatureRecord) -> Result<(), ENguyenError> {
+        self.ambience.hear(record);
+        let since = self.last_sent.map_or(f64::INFINITY, |last| record.time - last);
+        // beats go out early, as long as the bulbs had half a command's time to settle
+        let due = since >= self.interval || (record.onset && since >= self.interval / 2.0);
+        if !due {
+            return Ok(());
+        }
+        self.last_sent = Some(record.time);
+        match self.commands.try_send(self.ambience.state(self.interval as f32)) {
+            Ok(()) | Err(TrySendError::Full(_)) => Ok(()),
+            Err(TrySendError::Disconnected(_)) => {
+                Err(ENguyenError::compute("Light commands stopped going out"))
+            },
+        }
+    }
+}
+
+/// Talks to the bridge or broker
+struct LightClient {
+    config: LightsConfig,
+    /// The bridge, with its port
+    address: String,
+    broker: Option<MqttClient>,
+}
+
+impl LightClient {
+    fn new(config: &LightsConfig) -> LightClient {
+        let address = with_port(&config.address, HUE_PORT);
+        let broker = match config.backend {
+            LightsBackend::Hue => None,
+            LightsBackend::Mqtt => {
+                let (username, password) = (config.username.clone(), config.password.clone());
+                Some(MqttClient::new(&config.address, username, password))
+            },
+        };
+        LightClient { config: config.clone(), address, broker }
+    }
+
+    /// Sends commands until the sink is dropped.  Failures are logged once until one works.
+    fn run(&mut self, commands: Receiver<LightState>) {
+        let mut failing = false;
+        for state in commands.iter() {
+            match self.deliver(&state) {
+                Ok(()) => failing = false,
+                Err(e) => {
+                    if !failing {
+                        warn!("Lights at {} could not be set: {}", self.config.address, e);
+                    }
+                    failing = true;
+                },
+            }
+        }
+    }
+
+    fn deliver(&mut self, state: &LightState) -> io::Result<()> {
+        match &mut self.broker {
+            None => self.put_hue(state),
+            Some(broker) => {
+                let payload = mqtt_payload(state).to_string().into_bytes();
+                let topic = self.config.topic.clone();
+                broker.publish(&Publication { topic, payload, retain: false })
+            },
+        }
+    }
+
+    fn put_hue(&mut self, state: &LightState) -> io::Result<()> {
+        let body = hue_body(state).to_string();
+        let username = self.config.username.as_deref().unwrap_or_default();
+        let request = format!(
+            "PUT /api/{}/groups/{}/action HTTP/1.1\r\nHost: {}\r\n\
+             Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            username,
+            self.config.group,
+            self.address,
+            body.len(),
+            body
+        );
+        let mut bridge = open(&self.address)?;
+        bridge.write_all(request.as_bytes())?;
+        let mut response = String::new();
+        bridge.read_to_string(&mut response)?;
+        // the bridge answers errors with 200 and an error object
+        let status = response.lines().next().unwrap_or_default();
+        if !status.contains(" 200 ") || response.contains("\"error\"") {
+            let answer = response.split("\r\n\r\n").last().unwrap_or(status);
+            return Err(io::Error::new(io::ErrorKind::Other, answer.to_owned()));
+        }
+        Ok(())
+    }
+}
+
+/// A group action in CIE xy, as Hue bulbs take color
+fn hue_body(state: &LightState) -> serde_json::Value {
+    let linear: Vec<f32> = state
+        .color
+        .iter()
+        .map(|c| if *c > 0.04045 { ((c + 0.055) / 1.055).powf(2.4) } else { c / 12.92 })
+        .collect();
+    let (r, g, b) = (linear[0], linear[1], linear[2]);
+    let x = r * 0.664_511 + g * 0.154_324 + b * 0.162_028;
+    let y = r * 0.283_881 + g * 0.668_433 + b * 0.047_685;
+    let z = r * 0.000_088 + g * 0.072_310 + b * 0.986_039;
+    let sum = x + y + z;
+    // black has no chromaticity, so it's the white point
+    let xy = if sum > 0.0 { [x / sum, y / sum] } else { [0.3127, 0.3290] };
+    json!({
+        "on": true,
+        "xy": xy,
+        "bri": (1.0 + state.brightness * 253.0).round() as u8,
+        "transitiontime": (state.transition * 10.0).round() as u32,
+    })
+}
+
+/// A Home Assistant JSON schema light command
+fn mqtt_payload(state: &LightState) -> serde_json::Value {
+    let [r, g, b] = state.color;
+    let byte = |level: f32| (level.clamp(0.0, 1.0) * 255.0).round() as u8;
+    json!({
+        "state": "ON",
+        "brightness": byte(state.brightness),
+        "color": { "r": byte(r), "g": byte(g), "b": byte(b) },
+        "transition": state.transition,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::features::AudioFeatures;
+    use crate::params::Palette;
+
+    fn record(bands: [f32; 4], onset: bool, palette: Palette, time: f64) -> FeatureRecord {
+        let features = AudioFeatures { bands, ..AudioFeatures::default() };
+        FeatureRecord { onset, palette, ..FeatureRecord::new(&features, time) }
+    }
+
+    #[test]
+    fn ambience_follows_the_music() {
+        let mut ambience = Ambience::new(1.0);
+        ambience.hear(&record([0.0, 0.0, 0.0, 0.5], false, Palette::Nguyen, 0.0));
+        let highs = ambience.state(1.0);
+        assert!(highs.color[2] > highs.color[0]);
+
+        // bass takes seconds to turn the lights red
+        let bass = [0.0, 0.8, 0.0, 0.0];
+        ambience.hear(&record(bass, false, Palette::Nguyen, 0.1));
+        assert!(ambience.state(1.0).color[2] > 0.5);
+        for step in 2..300 {
+            ambience.hear(&record(bass, false, Palette::Nguyen, step as f64 / 60.0));
+        }
+        let red = ambience.state(1.0);
+        assert!(red.color[0] > 0.99 && red.color[2] < 0.1);
+
+        ambience.hear(&record(bass, true, Palette::Nguyen, 5.0));
+        assert!(ambience.state(1.0).brightness > red.brightness + 0.2);
+
+        let silence = record([0.0; 4], false, Palette::Ice, 0.0);
+        assert_eq!(dominant(&silence), [0.25, 0.625, 1.0]);
+    }
+
+    #[test]
+    fn light_commands_are_encoded() {
+        let red = LightState { color: [1.0, 0.0, 0.0], brightness: 1.0, transition: 0.5 };
+        let body = hue_body(&red);
+        assert!((body["xy"][0].as_f64().unwrap() - 0.7006).abs() < 1e-3);
+        assert!((body["xy"][1].as_f64().unwrap() - 0.2993).abs() < 1e-3);
+        assert_eq!((body["bri"].as_u64(), body["transitiontime"].as_u64()), (Some(254), Some(5)));
+
+        let command = mqtt_payload(&red);
+        assert_eq!(command["brightness"], 255);
+        assert_eq!(command["transition"], 0.5);
+        assert_eq!(command["color"], json!({ "r": 255, "g": 0, "b": 0 }));
+    }
+}
@@ -0,0 +1,311 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+use crate::errors::ENguyenError;
+
+use env_logger::filter::{Builder, Filter};
+use log::{warn, LevelFilter, Log, Metadata, Record};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+static DEFAULT_DATA_DIR: &str = "~/.local/share/e-nguyen";
+static LOG_FILE: &str = "e-nguyen.log";
+/// A log file is rotated once it grows past this many bytes
+static LOG_FILE_LIMIT: u64 = 1024 * 1024;
+/// The current log file plus rotated ones
+static LOG_FILES_KEPT: usize = 3;
+
+/// Subsystem names accepted by `--log` and the modules they cover.  Vulkan validation
+/// messages are logged with the `vulkan` target.
+static SUBSYSTEMS: [(&str, &[&str]); 7] = [
+    ("audio", &["e_nguyen::audio", "e_nguyen::ring"]),
+    ("compute", &["e_nguyen::compute", "e_nguyen::bench"]),
+    (
+        "render",
+        &["e_nguyen::rendering", "e_nguyen::mesmerize", "e_nguyen::settings", "e_nguyen::ewin"],
+    ),
+    ("config", &["e_nguyen::config"]),
+    ("ipc", &["e_nguyen::ipc"]),
+    ("metadata", &["e_nguyen::metadata"]),
+    ("vulkan", &["vulkan"]),
+];
+
+/// Where logs and other data that isn't configuration are written
+pub fn data_dir() -> PathBuf {
+    // TODO platform independence
+    if let Some(dir) = std::env::var_os("XDG_DATA_HOME") {
+        return PathBuf::from(dir).join("e-nguyen");
+    }
+    match std::env::var_os("HOME") {
+        Some(home) => PathBuf::from(home).join(&DEFAULT_DATA_DIR[2..]),
+        None => PathBuf::from(DEFAULT_DATA_DIR),
+    }
+}
+
+pub fn log_file_path() -> PathBuf {
+    data_dir().join(LOG_FILE)
+}
+
+/// Parses `audio=debug,render=warn` style filters.  A bare level sets the default, subsystem
+/// names expand to their modules, and any other name is taken as a module path.
+pub fn parse_filters(spec: &str) -> Result<Vec<(Option<String>, LevelFilter)>, ENguyenError> {
+    let mut filters = Vec::new();
+    for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+        let (name, level) = match directive.find('=') {
+            Some(split) => (Some(&directive[..split]), &directive[split + 1..]),
+            None => (None, directive),
+        };
+        let level = LevelFilter::from_str(level.trim()).map_err(|_| {
+            ENguyenError::config(format!("Unknown log level \"{}\" in \"{}\"", level, directive))
+        })?;
+        match name.map(str::trim) {
+            None => filters.push((None, level)),
+            Some("") => {
+                return Err(ENguyenError::config(format!("Missing module in \"{}\"", directive)));
+            },
+            Some(name) => match SUBSYSTEMS.iter().find(|(subsystem, _)| *subsystem == name) {
+                Some((_, modules)) => {
+                    filters.extend(modules.iter().map(|m| (Some((*m).to_owned()), level)));
+                },
+                None => filters.push((Some(name.to_owned()), level)),
+            },
+        }
+    }
+    Ok(filters)
+}
+
+/// Installs the logger.  `base` applies to everything `spec` doesn't mention.  Lines are
+/// JSON objects if `json`, and are also appended to `log_file_path()` if `to_file`.
+pub fn init(base: LevelFilter, spec: &str, json: bool, to_file: bool) -> Result<(), ENguyenError> {
+    let mut builder = Builder::new();
+    builder.filter_level(base);
+    for (module, level) in parse_filters(spec)? {
+        builder.filter(module.as_deref(), level);
+    }
+    let filter = builder.build();
+
+    let path = log_file_path();
+    let opened = if to_file { Some(LogFile::open(&path)) } else { None };
+    let (file, file_error) = match opened {
+        Some(Ok(file)) => (Some(Mutex::new(file)), None),
+        Some(Err(e)) => (None, Some(e)),
+        None => (None, None),
+    };
+
+    log::set_max_level(filter.filter());
+    let logger = Logger { filter, json, file };
+    log::set_boxed_logger(Box::new(logger))
+        .map_err(|e| ENguyenError::config("A logger was already installed").caused_by(e))?;
+    if let Some(e) = file_error {
+        warn!("Could not open log file {}: {}", path.display(), e);
+    }
+    Ok(())
+}
+
+/// Writes to stdout and optionally a rotating file
+struct Logger {
+    filter: Filter,
+    json: bool,
+    file: Option<Mutex<LogFile>>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.filter.matches(record) {
+            return;
+        }
+        let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let line = format_record(record, self.json, since_epoch);
+        let _ = writeln!(io::stdout(), "{}", line);
+        if let Some(file) = &self.file {
+            if let Ok(mut file) = file.lock() {
+                let _ = file.write_line(&line);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        let _ = io::stdout().flush();
+        if let Some(file) = &self.file {
+            if let Ok(mut file) = file.lock() {
+                let _ = file.file.flush();
+            }
+        }
+    }
+}
+
+fn format_record(record: &Record, json: bool, since_epoch: Duration) -> String {
+    let time = rfc3339(since_epoch);
+    if json {
+        serde_json::json!({
+            "time": time,
+            "level": record.level().to_string(),
+            "target": record.target(),
+            "message": record.args().to_string(),
+        })
+        .to_string()
+    } else {
+        format!("[{} {:<5} {}] {}", time, record.level(), record.target(), record.args())
+    }
+}
+
+/// UTC timestamp with milliseconds, such as `2019-04-18T02:45:55.000Z`
+pub fn rfc3339(since_epoch: Duration) -> String {
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days(secs / 86400);
+    let time = secs % 86400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Year, month and day of days since 1970-01-01, after Howard Hinnant's `civil_from_days`
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Lines appended to a file that rotates past `LOG_FILE_LIMIT`
+pub struct LogFile {
+    path: PathBuf,
+    file: File,
+    written: u64,
+}
+
+impl LogFile {
+    pub fn open(path: &Path) -> io::Result<LogFile> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        if fs::metadata(path).map(|m| m.len() >= LOG_FILE_LIMIT).unwrap_or(false) {
+            rotate(path, LOG_FILES_KEPT)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let written = file.metadata()?.len();
+        Ok(LogFile { path: path.to_owned(), file, written })
+    }
+
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.written > 0 && self.written + len > LOG_FILE_LIMIT {
+            rotate(&self.path, LOG_FILES_KEPT)?;
+            *self = LogFile::open(&self.path)?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.written += len;
+        Ok(())
+    }
+}
+
+/// `e-nguyen.log` becomes `e-nguyen.log.1` and so on, dropping the oldest of `kept` files
+fn rotate(path: &Path, kept: usize) -> io::Result<()> {
+    for n in (1..kept).rev() {
+        let from = numbered(path, n - 1);
+        if from.exists() {
+            fs::rename(&from, numbered(path, n))?;
+        }
+    }
+    Ok(())
+}
+
+fn numbered(path: &Path, n: usize) -> PathBuf {
+    if n == 0 {
+        return path.to_owned();
+    }
+    let mut numbered = path.as_os_str().to_owned();
+    numbered.push(format!(".{}", n));
+    PathBuf::from(numbered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    #[test]
+    fn subsystems_expand() {
+        let filters = parse_filters("info, audio=debug,e_nguyen::ipc=trace").unwrap();
+        assert_eq!(filters[0], (None, LevelFilter::Info));
+        assert_eq!(filters[1], (Some("e_nguyen::audio".to_owned()), LevelFilter::Debug));
+        assert_eq!(filters[2], (Some("e_nguyen::ring".to_owned()), LevelFilter::Debug));
+        assert_eq!(filters[3], (Some("e_nguyen::ipc".to_owned()), LevelFilter::Trace));
+        assert!(parse_filters("render=loud").is_err());
+        assert!(parse_filters("=warn").is_err());
+        assert!(parse_filters("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(rfc3339(Duration::from_millis(0)), "1970-01-01T00:00:00.000Z");
+        assert_eq!(rfc3339(Duration::from_millis(951_782_400_250)), "2000-02-29T00:00:00.250Z");
+        assert_eq!(rfc3339(Duration::from_secs(1_555_555_555)), "2019-04-18T02:45:55.000Z");
+    }
+
+    #[test]
+    fn json_lines() {
+        let line = format_record(
+            &Record::builder()
+                .args(format_args!("said \"{}\"", "hi"))
+                .level(Level::Warn)
+                .target("vulkan")
+                .build(),
+            true,
+            Duration::from_secs(0),
+        );
+        let parsed: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(parsed["level"], "WARN");
+        assert_eq!(parsed["target"], "vulkan");
+        assert_eq!(parsed["message"], "said \"hi\"");
+    }
+
+    #[test]
+    fn files_rotate() {
+        let dir = std::env::temp_dir().join(format!("e-nguyen-logs-{}", std::process::id()));
+        let path = dir.join(LOG_FILE);
+        let _ = fs::remove_dir_all(&dir);
+        let line = "x".repeat(LOG_FILE_LIMIT as usize / 2);
+        let mut file = LogFile::open(&path).unwrap();
+        for _ in 0..5 {
+            file.write_line(&line).unwrap();
+        }
+        assert!(numbered(&path, 2).exists());
+        assert!(!numbered(&path, 3).exists());
+        assert!(fs::metadata(&path).unwrap().len() <= LOG_FILE_LIMIT);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
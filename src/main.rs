@@ -13,32 +13,32 @@
 
 // Copyright 2019 E-Nguyen Developers.
 
-mod application;
-mod audio;
-mod compute;
-mod config;
-mod errors;
-mod ewin;
-mod input;
-mod mesmerize;
-mod rendering;
-mod ring;
-mod settings;
-
-use crate::application::{App, LaunchRequest};
+use e_nguyen::application::{self, App, LaunchRequest};
+use e_nguyen::autostart::{self, Autostart};
+use e_nguyen::{
+    audio, bench, calibrate, config, doctor, ewin, golden, ipc, logging, shader_reload,
+};
 
 use docopt::Docopt;
-use env_logger::{Builder, Target};
 use log::{error, info, warn, LevelFilter};
 use serde::Deserialize;
 use std::path::PathBuf;
+use std::time::Duration;
 
 const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 const USAGE: &'static str = "
 E-Nguyen
 
 Usage:
-  e-nguyen [options]
+  e-nguyen [options] [--set KEY=VALUE]...
+  e-nguyen list-devices
+  e-nguyen doctor [--layers]
+  e-nguyen bench [--seconds SECONDS] [--report PATH] [--render]
+  e-nguyen golden [--update]
+  e-nguyen calibrate [options] [--set KEY=VALUE]...
+  e-nguyen remote <command>...
+  e-nguyen install-autostart [--systemd] [options] [--set KEY=VALUE]...
+  e-nguyen uninstall-autostart
   e-nguyen (-h | --help)
   e-nguyen --version
 
@@ -46,11 +46,30 @@ Options:
   -h --help           Show this screen
   -v --version        Show version
   -c --config PATH    Custom configuration path
+  -p --profile NAME   Apply the config's [profile.NAME] table
   -f --fullscreen     Start in fullscreen
+  --scene NAME        Show this scene, such as tunnel, over the config and last session
+  --palette NAME      Show this palette over the config and the scene's tuning
+  -s --set KEY=VALUE  Override a config key, such as analysis.fft_size=4096
   -l --layers         Enable Vulkan debug layers
   -b --buffers        Enable robust buffer access
+  -d --daemon         Keep audio analysis warm without a window.  Open one with `remote mez`
+  -k --kiosk          Run unattended as the config's [kiosk] table sets up
+  --demo              Turn through every scene to a synthetic signal.  Needs no sound server
+  --dev               Rebuild the analysis shader from shaders/ whenever it's edited
   --verbose           RUST_LOG=debug
+  --log FILTERS       Per-subsystem levels, such as audio=debug,render=warn,vulkan=info [default: ]
+  --log-json          Log one JSON object per line
+  --log-file          Also log to e-nguyen.log in the data directory
+  --seconds SECONDS   Benchmark or calibration duration [default: 10]
+  --report PATH       Benchmark JSON report path [default: e-nguyen-bench.json]
+  --render            Also benchmark frame pacing in the visualizer window
+  --update            Record every scene's golden image again
+  --systemd           Start with a systemd user unit rather than an autostart entry
 ";
+const REMOTE_COMMANDS: &'static str = "focus, fullscreen, mez, settings, quit, reset-scene, \
+                                       record, freeze, pause, profile [name], \
+                                       set <parameter> <value>";
 const VERSION_BANNER_TEMPLATE: &'static str = r"
  ___   __  _  __ _  ___   _____ __  _   
 | __|_|  \| |/ _] || \ `v' / __|  \| |  
@@ -64,23 +83,45 @@ Made available under the GNU LGPL-3.0 License
 
 #[derive(Debug, Deserialize)]
 struct Args {
+    cmd_list_devices: bool,
+    cmd_doctor: bool,
+    cmd_bench: bool,
+    cmd_golden: bool,
+    cmd_calibrate: bool,
+    cmd_remote: bool,
+    cmd_install_autostart: bool,
+    cmd_uninstall_autostart: bool,
+    arg_command: Vec<String>,
     flag_config: String,
+    flag_profile: String,
     flag_fullscreen: bool,
+    flag_scene: String,
+    flag_palette: String,
+    flag_set: Vec<String>,
+    flag_daemon: bool,
+    flag_kiosk: bool,
+    flag_demo: bool,
+    flag_dev: bool,
     flag_layers: bool,
     flag_version: bool,
     flag_verbose: bool,
+    flag_log: String,
+    flag_log_json: bool,
+    flag_log_file: bool,
+    flag_seconds: u64,
+    flag_report: String,
+    flag_render: bool,
+    flag_update: bool,
+    flag_systemd: bool,
 }
 
 fn main() {
     let args: Args = Docopt::new(USAGE).and_then(|d| d.deserialize()).unwrap_or_else(|e| e.exit());
-    let mut builder = Builder::default();
-    builder.target(Target::Stdout);
-    if args.flag_verbose {
-        builder.filter_level(LevelFilter::Trace);
-    } else {
-        builder.filter_level(LevelFilter::Warn);
+    let base = if args.flag_verbose { LevelFilter::Trace } else { LevelFilter::Warn };
+    if let Err(e) = logging::init(base, &args.flag_log, args.flag_log_json, args.flag_log_file) {
+        eprintln!("Bad --log: {}", e);
+        std::process::exit(64);
     }
-    builder.init();
 
     if args.flag_version {
         let parts: Vec<&str> = VERSION_BANNER_TEMPLATE.split("☃").collect();
@@ -89,6 +130,40 @@ fn main() {
         std::process::exit(0)
     }
 
+    if args.cmd_list_devices {
+        list_devices();
+        std::process::exit(0)
+    }
+
+    if args.cmd_doctor {
+        let checks = doctor::run(args.flag_layers);
+        let healthy = doctor::report(&checks, doctor::color_wanted());
+        std::process::exit(if healthy { 0 } else { 1 })
+    }
+
+    if args.flag_dev {
+        shader_reload::enable();
+    }
+
+    if args.cmd_bench {
+        bench(args.flag_seconds, PathBuf::from(&args.flag_report), args.flag_render);
+        std::process::exit(0)
+    }
+
+    if args.cmd_golden {
+        golden(args.flag_update);
+    }
+
+    if args.cmd_remote {
+        remote(&args.arg_command.join(" "));
+    }
+
+    if args.cmd_install_autostart || args.cmd_uninstall_autostart {
+        manage_autostart(&args);
+    }
+
+    let first_run = args.flag_config.is_empty() && !config::default_config_path().exists();
+    let mut config_path = None;
     let config = {
         let mut parsed = None;
         if !args.flag_config.is_empty() {
@@ -96,32 +171,322 @@ fn main() {
             info!("Loading custom configuration from {}", args_path);
             let path = PathBuf::from(&args_path);
             if path.exists() && path.is_file() {
-                parsed = config::try_load_config(path);
+                parsed = config::try_load_config(path.clone());
+                config_path = Some(path);
             } else {
                 warn!("Invalid config path! {}", args_path);
             }
+        } else {
+            // the default file is created on first close to remember the window
+            let path = config::default_config_path();
+            if path.is_file() {
+                parsed = config::try_load_config(path.clone());
+            }
+            config_path = Some(path);
         }
         match parsed {
             Some(c) => c,
             None => config::ENguyenConfig::default(),
         }
     };
+    let profile = if args.flag_profile.is_empty() { None } else { Some(args.flag_profile) };
+    let config = match &profile {
+        Some(name) => match config.with_profile(name) {
+            Ok(c) => c,
+            Err(e) => {
+                error!("Bad --profile: {}", e);
+                std::process::exit(64);
+            },
+        },
+        None => config,
+    };
+    let overrides = args.flag_set;
+    let mut config = match config.with_overrides(&overrides) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Bad --set: {}", e);
+            std::process::exit(64);
+        },
+    };
+    config.kiosk.enabled |= args.flag_kiosk;
+    if !args.flag_scene.is_empty() {
+        match args.flag_scene.parse() {
+            Ok(scene) => config.launch.scene = Some(scene),
+            Err(e) => {
+                error!("Bad --scene: {}", e);
+                std::process::exit(64);
+            },
+        }
+    }
+    if !args.flag_palette.is_empty() {
+        match args.flag_palette.parse() {
+            Ok(palette) => config.launch.palette = Some(palette),
+            Err(e) => {
+                error!("Bad --palette: {}", e);
+                std::process::exit(64);
+            },
+        }
+    }
+    if args.flag_demo {
+        // no sound server needed, and nothing the demo shows is remembered
+        config.audio.backend = config::AudioBackend::Synthetic;
+        config.launch.demo = true;
+        config_path = None;
+    }
 
     let load_layers = args.flag_layers;
-    let picker = match ewin::GpuPicker::new(load_layers) {
+    let mut picker = match ewin::GpuPicker::new(load_layers) {
         Ok(i) => i,
-        Err(_) => {
-            error!("Missing Vulkan loader, ICD, or Vulkan capable device");
-            error!("https://vulkan.lunarg.com/doc/view/1.0.54.0/windows/LoaderAndLayerInterface.html#Overview");
-            error!("Consult your operating system and graphics card documentation for ICD & Vulkan loader installation instructions");
+        Err(e) => {
+            error!("Vulkan is unavailable: {}.  Run `e-nguyen doctor` to find out why", e);
             std::process::exit(66);
-        }
+        },
     };
 
+    if config.migrate_device(&picker) {
+        if let Some(path) = config_path.as_ref().filter(|path| path.is_file()) {
+            let migrated = config::edit_file(path, |on_disk| {
+                on_disk.physical_device_uuid = config.physical_device_uuid.clone();
+                on_disk.physical_device_name = config.physical_device_name.clone();
+            });
+            if let Err(e) = migrated {
+                warn!("Could not record the GPU's UUID: {}", e);
+            }
+        }
+    }
+    let preferred = config.physical_device(&picker).map(|pd| ewin::format_uuid(pd.uuid()));
+    picker.prefer(preferred);
+
+    if args.cmd_calibrate {
+        let path = config_path.unwrap_or_else(config::default_config_path);
+        calibrate(&picker, &config, &path, args.flag_seconds);
+        std::process::exit(0)
+    }
+
     let skip_settings = args.flag_fullscreen || config.start_in_fullscreen;
-    if skip_settings && config.ready(&picker) {
-        App::launch(LaunchRequest::Mez, config, picker);
+    if args.flag_daemon {
+        App::launch(LaunchRequest::Daemon, config, config_path, profile, overrides, picker);
+    } else if config.kiosk.enabled || args.flag_demo {
+        // an unattended screen or demo never waits on the settings window
+        App::launch(LaunchRequest::Mez, config, config_path, profile, overrides, picker);
+    } else if first_run && !args.flag_fullscreen {
+        App::launch(LaunchRequest::Onboard, config, config_path, profile, overrides, picker);
+    } else if skip_settings && config.ready(&picker) {
+        App::launch(LaunchRequest::Mez, config, config_path, profile, overrides, picker);
     } else {
-        App::launch(LaunchRequest::Settings, config, picker);
+        App::launch(LaunchRequest::Settings, config, config_path, profile, overrides, picker);
+    }
+}
+
+fn list_devices() {
+    println!("Audio sources (audio_input_index):");
+    match audio::list_sources() {
+        Ok(sources) => {
+            for source in sources.iter() {
+                println!("  {}", source);
+            }
+        },
+        Err(e) => error!("Could not list audio sources: {}", e),
+    }
+    println!("GPUs (physical_device_uuid):");
+    match ewin::GpuPicker::new(false) {
+        Ok(picker) => {
+            for device in picker.describe_devices().iter() {
+                println!("  {}", device);
+            }
+        },
+        Err(e) => error!("Could not list GPUs: {}", e),
+    }
+}
+
+fn bench(seconds: u64, report_path: PathBuf, render: bool) {
+    let picker = match ewin::GpuPicker::new(false) {
+        Ok(picker) => picker,
+        Err(e) => {
+            error!("Benchmark needs a Vulkan capable device: {}", e);
+            std::process::exit(66);
+        },
+    };
+    println!("Benchmarking analysis for {}s with the synthetic source...", seconds);
+    match bench::run(&picker, Duration::from_secs(seconds)) {
+        Ok(mut report) => {
+            println!("Analysis rate: {:.1} frames/s", report.analysis_rate);
+            let (dispatch, latency) = (&report.gpu_dispatch_ms, &report.latency_ms);
+            println!("GPU dispatch: {:.3}ms mean, {:.3}ms p95", dispatch.mean, dispatch.p95);
+            println!("Latency: {:.3}ms mean, {:.3}ms p95", latency.mean, latency.p95);
+            if render {
+                println!("Rendering the visualizer for {}s...", seconds);
+                match application::bench_render(picker, Duration::from_secs(seconds)) {
+                    Ok(pacing) => {
+                        println!("Frame pacing: {}", pacing);
+                        report.frame_pacing = Some(pacing);
+                    },
+                    Err(e) => error!("Render benchmark failed: {}", e),
+                }
+            }
+            match report.save(&report_path) {
+                Ok(_) => println!("Report written to {}", report_path.display()),
+                Err(e) => error!("Could not write report: {}", e),
+            }
+        },
+        Err(e) => {
+            error!("Benchmark failed: {}", e);
+            std::process::exit(1);
+        },
+    }
+}
+
+/// Compares every scene with its golden image in the current directory's `golden`
+fn golden(update: bool) -> ! {
+    let picker = match ewin::GpuPicker::new(false) {
+        Ok(picker) => picker,
+        Err(e) => {
+            error!("Golden images need a Vulkan capable device: {}", e);
+            std::process::exit(66);
+        },
+    };
+    let dir = PathBuf::from(golden::GOLDEN_DIR);
+    let outcomes = match golden::check(&picker, &dir, update) {
+        Ok(outcomes) => outcomes,
+        Err(e) => {
+            error!("Scenes could not be drawn: {}", e);
+            std::process::exit(1);
+        },
+    };
+    let mut differing = 0;
+    for (scene, outcome) in outcomes {
+        match outcome {
+            golden::Outcome::Matched => println!("{}: matches", scene),
+            golden::Outcome::Recorded => println!("{}: recorded", scene),
+            golden::Outcome::Missing { drawn } => {
+                differing += 1;
+                let drawn = drawn.display();
+                println!("{}: no golden.  See {} and record it with --update", scene, drawn);
+            },
+            golden::Outcome::Differs { share, drawn } => {
+                differing += 1;
+                let percent = share * 100.0;
+                println!("{}: {:.1}% of pixels differ.  See {}", scene, percent, drawn.display());
+            },
+        }
+    }
+    std::process::exit(if differing == 0 { 0 } else { 1 })
+}
+
+/// Measures the noise floor of the configured source and saves it to the file at `path`
+fn calibrate(
+    picker: &ewin::GpuPicker,
+    config: &config::ENguyenConfig,
+    path: &PathBuf,
+    seconds: u64,
+) {
+    println!("Measuring the noise floor for {}s.  Keep the source quiet...", seconds);
+    let floor = match calibrate::measure(picker, config, Duration::from_secs(seconds)) {
+        Ok(floor) => floor,
+        Err(e) => {
+            error!("Calibration failed: {}", e);
+            std::process::exit(1);
+        },
+    };
+    let [sub, bass, mids, highs] = floor;
+    println!("Sub-bass {:.2}, bass {:.2}, mids {:.2}, highs {:.2}", sub, bass, mids, highs);
+    match config::edit_file(path, |on_disk| on_disk.analysis.noise_floor = floor) {
+        Ok(_) => println!("Noise floor written to {}", path.display()),
+        Err(e) => {
+            error!("Could not save the noise floor: {}", e);
+            std::process::exit(1);
+        },
+    }
+}
+
+/// Installs or removes autostart.  Installing keeps the flags that choose what to run.
+fn manage_autostart(args: &Args) -> ! {
+    if args.cmd_uninstall_autostart {
+        match autostart::uninstall() {
+            Ok(removed) if removed.is_empty() => println!("E-Nguyen wasn't set to autostart"),
+            Ok(removed) => {
+                for path in removed.iter() {
+                    println!("Removed {}", path.display());
+                }
+            },
+            Err(e) => {
+                error!("Could not remove autostart: {}", e);
+                std::process::exit(1);
+            },
+        }
+        std::process::exit(0);
+    }
+
+    let mut forwarded = Vec::new();
+    let mut forward = |flag: &str, value: Option<&str>| {
+        forwarded.push(flag.to_owned());
+        forwarded.extend(value.map(str::to_owned));
+    };
+    if !args.flag_config.is_empty() {
+        // the session doesn't start in this directory
+        let path = PathBuf::from(&args.flag_config);
+        let path = path.canonicalize().unwrap_or(path);
+        forward("--config", Some(&path.to_string_lossy()));
+    }
+    if !args.flag_profile.is_empty() {
+        forward("--profile", Some(&args.flag_profile));
+    }
+    if !args.flag_scene.is_empty() {
+        forward("--scene", Some(&args.flag_scene));
+    }
+    if !args.flag_palette.is_empty() {
+        forward("--palette", Some(&args.flag_palette));
+    }
+    let switches = [
+        ("--fullscreen", args.flag_fullscreen),
+        ("--kiosk", args.flag_kiosk),
+        ("--demo", args.flag_demo),
+        ("--daemon", args.flag_daemon),
+        ("--log-json", args.flag_log_json),
+        ("--log-file", args.flag_log_file),
+    ];
+    for (flag, _) in switches.iter().filter(|(_, on)| *on) {
+        forward(flag, None);
+    }
+    if !args.flag_log.is_empty() {
+        forward("--log", Some(&args.flag_log));
+    }
+    for assignment in args.flag_set.iter() {
+        forward("--set", Some(assignment));
+    }
+
+    let kind = if args.flag_systemd { Autostart::Systemd } else { Autostart::Desktop };
+    match autostart::install(kind, &forwarded) {
+        Ok(path) => {
+            println!("Wrote {}.  E-Nguyen will start with your session.", path.display());
+            std::process::exit(0);
+        },
+        Err(e) => {
+            error!("Could not set up autostart: {}", e);
+            std::process::exit(1);
+        },
+    }
+}
+
+fn remote(command: &str) -> ! {
+    let parsed: ipc::Remote = match command.parse() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("{}.  Try one of: {}", e, REMOTE_COMMANDS);
+            std::process::exit(64);
+        },
+    };
+    match ipc::running() {
+        Some(client) => match client.send(&parsed) {
+            Ok(_) => std::process::exit(0),
+            Err(e) => {
+                error!("Could not reach the running instance: {}", e);
+                std::process::exit(1);
+            },
+        },
+        None => {
+            error!("E-Nguyen isn't running");
+            std::process::exit(1);
+        },
     }
 }
@@ -13,182 +13,677 @@
 
 // Copyright 2019 E-Nguyen Developers.
 
-use crate::application::MezLauncher;
-use crate::compute::{AudioTex, AudioTexSource, AudioTexTap};
-use crate::errors::VulkanoError;
-use crate::ewin::{GpuPicker, SwapWindow};
+use crate::application::{MezLauncher, SharedState, WindowCommand};
+use crate::background::Backdrop;
+use crate::bench::millis;
+use crate::borderless::{Reshape, WindowDrag};
+use crate::compare::Comparison;
+use crate::compute::{AudioTex, AudioTexSource, AudioTexTap, WarmPipeline, HELD_COLUMNS};
+use crate::config::{ENguyenConfig, KioskConfig, WindowGeometry};
+use crate::edges::EdgeSampler;
+use crate::errors::{ENguyenError, FrameError};
+use crate::ewin;
+use crate::ewin::{color_render_pass, supported_samples, Capabilities, Queues, SwapWindow};
+use crate::features::AudioFeatures;
+use crate::geometry::{static_vertices, unit_quad};
+use crate::goniometer::Goniometer;
+use crate::idle::IdleInhibitor;
 use crate::input;
-use crate::input::{KeyTracker, MouseTracker, UserEvent};
-use crate::rendering::{uv_image_vsm, uv_scroll_fsm, FrameState, Framer, XyUvVertex};
+use crate::input::{KeyCombo, KeyTracker, MousePos, MouseTracker, UserEvent};
+use crate::layers::Logo;
+use crate::metadata::MetadataWatch;
+use crate::meters::MetersScene;
+use crate::now_playing::NowPlayingScene;
+use crate::pacing::{FramePacing, PacingSummary, QualityGovernor, ASSUMED_REFRESH};
+use crate::params::{ParamBus, ParamChange, Scene};
+use crate::piano::{KeyRange, PianoScene};
+use crate::recording::Recorder;
+use crate::rendering::{
+    over_blending, ui_font, uv_image_vsm, uv_scroll_fsm, FrameBlock, FrameUniforms,
+    PipelineRegistry, ScaledTarget, SolidPainter, TextBlock, TextRenderer, XyUvVertex,
+};
+use crate::review::Review;
+use crate::ruler::FrequencyRuler;
+use crate::scenes::{SceneFrame, SceneManager, Spectrogram};
+use crate::status::{StatusDisplay, WindowStatus};
+use crate::studio::StudioScene;
+use crate::tracks;
+use crate::tunnel::Tunnel;
 
-use log::error;
-use std::error::Error;
+use log::{debug, info, warn};
+use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::sync::Arc;
-use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer};
+use std::thread;
+use std::time::{Duration, Instant};
 use vulkano::command_buffer::AutoCommandBufferBuilder;
-use vulkano::descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet};
-use vulkano::format::Format;
-use vulkano::framebuffer::{FramebufferAbstract, RenderPassAbstract, Subpass};
-use vulkano::image::{Dimensions, StorageImage};
+use vulkano::descriptor::descriptor_set::PersistentDescriptorSet;
+use vulkano::device::Device;
+use vulkano::format::ClearValue;
+use vulkano::framebuffer::{FramebufferAbstract, RenderPassAbstract, RenderPassDesc, Subpass};
 use vulkano::pipeline::{GraphicsPipeline, GraphicsPipelineAbstract};
 use vulkano::sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode};
-use vulkano::sync::FlushError;
 use vulkano::sync::GpuFuture;
 use vulkano_win::VkSurfaceBuild;
 use winit;
+use winit::dpi::{LogicalPosition, LogicalSize};
 use winit::Icon;
 
-pub fn mezmerize(launcher: &MezLauncher) -> Result<(), VulkanoError> {
-    let picker = launcher.picker.clone();
+/// The visualizer window.  Owned by the App's event loop, which routes this window's events
+/// here and calls `render` once per pass.
+pub struct MezWindow {
+    launcher: MezLauncher,
+    swap_window: SwapWindow,
+    framer: MezFramer,
+    frame_state: MezState,
+    resources: MezResources,
+    mt: MouseTracker,
+    kt: KeyTracker,
+    fullscreen: bool,
+    /// Windowed geometry, kept while fullscreen so it can be saved on close
+    geometry: WindowGeometry,
+    /// Where fullscreen goes.  The current monitor if unset.
+    monitor: Option<String>,
+    revision: Option<u64>,
+    start_in_fullscreen: Option<bool>,
+    overlay_until: Option<Instant>,
+    pacing: FramePacing,
+    quality: QualityGovernor,
+    /// When the stats HUD was last refreshed, if it's shown
+    hud_updated: Option<Instant>,
+    /// In kiosk mode, the only input that isn't ignored
+    kiosk_exit: Option<KeyCombo>,
+    /// Moves and resizes the window when it has no decorations
+    drag: Option<WindowDrag>,
+    /// Title and taskbar state, for when the window isn't focused
+    status: StatusDisplay,
+    recording_since: Option<Instant>,
+    /// Since analysis last started or resumed, for telling whether audio was lost
+    listening_since: Instant,
+    /// Whether the frozen frame, caption and all, has been presented
+    frozen_shown: bool,
+    pub done: bool,
+}
 
-    let icon_data = include_bytes!("../logo/icon.png");
+impl MezWindow {
+    /// Opens the visualizer where it was last closed.  A warm pipeline's device and tap are
+    /// reused if given.
+    pub fn new(
+        launcher: MezLauncher,
+        events_loop: &winit::EventsLoop,
+        warm: Option<WarmPipeline>,
+    ) -> Result<MezWindow, ENguyenError> {
+        let picker = launcher.picker.clone();
+        let config = launcher.config.clone();
+        let geometry = &config.video.window;
 
-    let mut events_loop = winit::EventsLoop::new();
-    let surface = winit::WindowBuilder::new()
-        .with_window_icon(Icon::from_bytes(icon_data).ok())
-        .with_title("E-Nguyen")
-        .build_vk_surface(&events_loop, picker.instance.clone())
-        .unwrap();
+        let icon_data = include_bytes!("../logo/icon.png");
 
-    let mut swap_window = SwapWindow::new(&picker, &surface)?;
-    let mut _r = MezResources {};
-    let (mut framer, mut frame_state): (MezFramer, MezState) =
-        MezFramer::new(&mut swap_window, &_r)?;
+        let mut builder = winit::WindowBuilder::new()
+            .with_window_icon(Icon::from_bytes(icon_data).ok())
+            .with_title("E-Nguyen")
+            .with_transparency(config.video.transparent)
+            .with_decorations(config.video.decorations);
+        if let Some(size) = geometry.size {
+            builder = builder.with_dimensions(LogicalSize::from(size));
+        }
+        let surface = builder.build_vk_surface(events_loop, picker.instance.clone()).unwrap();
+        if let Some((x, y)) = geometry.position {
+            surface.window().set_position(LogicalPosition::new(x as f64, y as f64));
+        }
+        let kiosk = config.kiosk.enabled;
+        if geometry.fullscreen || kiosk {
+            // the configured monitor wins over the one the window was closed on
+            let window = surface.window();
+            let name = config.video.monitor.as_ref().or_else(|| geometry.monitor.as_ref());
+            window.set_fullscreen(Some(fullscreen_monitor(window, name)));
+        }
+        let kiosk_exit = if kiosk {
+            surface.window().hide_cursor(true);
+            let combo = config.kiosk.exit_combo.parse().or_else(|e| {
+                let fallback = KioskConfig::default().exit_combo;
+                warn!("Bad kiosk exit combo, {} quits instead: {}", fallback, e);
+                fallback.parse()
+            });
+            combo.ok()
+        } else {
+            None
+        };
 
-    let mut mt = MouseTracker::new();
-    let mut kt = KeyTracker::new();
-    let mut done = false;
+        let present_mode = config.video.present_mode.vulkano();
+        let transparent = config.video.transparent;
+        let (mut swap_window, warm_tap) = match warm {
+            Some(warm) => {
+                let (device, queues) = (warm.device, warm.queues);
+                let swap_window =
+                    SwapWindow::with_device(device, queues, &surface, present_mode, transparent)?;
+                (swap_window, Some(warm.tap))
+            },
+            None => (SwapWindow::new(&picker, &surface, present_mode, transparent)?, None),
+        };
+        let resources = MezResources {
+            warm_tap: RefCell::new(warm_tap),
+            params: launcher.params.clone(),
+            recorder: launcher.recorder.clone(),
+            metadata: MetadataWatch::start(),
+            config: config.clone(),
+        };
+        let (framer, frame_state): (MezFramer, MezState) =
+            MezFramer::new(&mut swap_window, &resources)?;
 
-    loop {
-        let result = framer.render_one(&mut swap_window, frame_state, &_r);
-        match result {
-            Ok(new_state) => {
-                frame_state = new_state;
+        Ok(MezWindow {
+            launcher,
+            swap_window,
+            framer,
+            frame_state,
+            resources,
+            mt: MouseTracker::new(),
+            kt: KeyTracker::new(),
+            fullscreen: geometry.fullscreen || kiosk,
+            geometry: geometry.clone(),
+            monitor: config.video.monitor.clone(),
+            revision: None,
+            start_in_fullscreen: None,
+            overlay_until: None,
+            pacing: FramePacing::new(),
+            quality: QualityGovernor::new(),
+            hud_updated: None,
+            kiosk_exit,
+            drag: if config.video.decorations || kiosk { None } else { Some(WindowDrag::new()) },
+            status: StatusDisplay::new(),
+            recording_since: None,
+            listening_since: Instant::now(),
+            frozen_shown: false,
+            done: false,
+        })
+    }
+
+    pub fn id(&self) -> winit::WindowId {
+        self.swap_window.surface.window().id()
+    }
+
+    pub fn handle(&mut self, ev: &winit::Event, shared: &mut SharedState) {
+        if let Some(exit) = self.kiosk_exit {
+            // nothing but the exit combo gets through, not even the window manager closing
+            match &ev {
+                winit::Event::WindowEvent { event: winit::WindowEvent::Resized(_), .. }
+                | winit::Event::WindowEvent {
+                    event: winit::WindowEvent::HiDpiFactorChanged(_),
+                    ..
+                } => {
+                    self.frame_state.recreate_swapchain = true;
+                },
+                _ if exit.pressed_by(ev) => {
+                    info!("Kiosk exit combo pressed");
+                    self.launcher.quit();
+                },
+                _ => {},
             }
-            Err(_e) => {
-                frame_state = MezState {
-                    recreate_swapchain: true,
-                    previous_frame: Box::new(vulkano::sync::now(swap_window.device.clone())),
+            return;
+        }
+        match &ev {
+            winit::Event::WindowEvent { event: winit::WindowEvent::CloseRequested, .. } => {
+                self.done = true
+            },
+            winit::Event::WindowEvent { event: winit::WindowEvent::Resized(_), .. } => {
+                self.frame_state.recreate_swapchain = true;
+            },
+            winit::Event::WindowEvent {
+                event: winit::WindowEvent::HiDpiFactorChanged(factor),
+                ..
+            } => {
+                // entered an output with another scale.  Text follows on the next frame.
+                debug!("Scale factor changed to {}", factor);
+                self.frame_state.recreate_swapchain = true;
+            },
+            winit::Event::WindowEvent { event: winit::WindowEvent::CursorLeft { .. }, .. } => {
+                if let Some(ruler) = &mut self.framer.ruler {
+                    ruler.leave();
+                }
+            },
+            _ => {},
+        }
+
+        if let Some(pe) = input::process(&ev) {
+            // the scene shown has first look at input
+            let scene = shared.params.get().scene;
+            let events = vec![self.kt.update(&pe), self.mt.update(&pe)];
+            for ue in events.into_iter().flatten() {
+                if self.reshape(&ue) {
+                    continue;
+                }
+                if let Some(ruler) = &mut self.framer.ruler {
+                    ruler.handle(&ue);
+                }
+                if self.framer.scenes.get(scene).map_or(false, |shown| shown.handle_event(&ue)) {
+                    continue;
+                }
+                // then the spectrogram's history, where it's drawn
+                let window = self.swap_window.surface.window();
+                let width = window.get_inner_size().map_or(1.0, |size| size.width);
+                if shows_history(scene) && self.framer.review.handle(&ue, width) {
+                    continue;
+                }
+                let key = match &ue {
+                    UserEvent::KeyPress { character } => hotkey(*character),
+                    _ => None,
                 };
+                match key {
+                    Some(Hotkey::Maximize) => {
+                        // TODO querying window or state tracking
+                        let window = self.swap_window.surface.window();
+                        window.set_maximized(true);
+                    },
+                    Some(Hotkey::Settings) => self.launcher.launch_settings(),
+                    Some(Hotkey::Hud) => self.toggle_hud(),
+                    Some(Hotkey::NextScene) => {
+                        let scene = self.framer.capabilities.next_scene(shared.params.get().scene);
+                        shared.params.apply(ParamChange::Scene(scene));
+                    },
+                    Some(Hotkey::Record) => shared.recorder.toggle(),
+                    Some(Hotkey::Freeze) => shared.frozen = !shared.frozen,
+                    Some(Hotkey::Pause) => shared.analysis_paused = !shared.analysis_paused,
+                    Some(Hotkey::Close) => self.done = true,
+                    None => {},
+                }
             }
         }
-        events_loop.poll_events(|ev| {
-            match &ev {
-                winit::Event::WindowEvent { event: winit::WindowEvent::CloseRequested, .. } => {
-                    done = true
+    }
+
+    pub fn command(&mut self, command: WindowCommand) {
+        match command {
+            WindowCommand::Focus => self.swap_window.surface.window().show(),
+            WindowCommand::Close => self.done = true,
+            WindowCommand::ToggleFullscreen => {
+                let fullscreen = !self.fullscreen;
+                self.set_fullscreen(fullscreen);
+            },
+        }
+    }
+
+    /// The analysis feeding this window, for supervision
+    pub fn audio_tap(&mut self) -> &mut AudioTexTap {
+        &mut self.framer.audio_tex_tap
+    }
+
+    /// Draw `message` over the visualization, for `linger` or until replaced if `None`
+    pub fn show_error(&mut self, message: String, linger: Option<Duration>) {
+        self.framer.overlay_text = Some(message);
+        self.overlay_until = linger.map(|linger| Instant::now() + linger);
+    }
+
+    /// Frame timings since the window opened
+    pub fn pacing(&self) -> PacingSummary {
+        self.pacing.summary()
+    }
+
+    /// Moves or resizes an undecorated window being dragged.  True if `event` was used.
+    fn reshape(&mut self, event: &UserEvent) -> bool {
+        let drag = match &mut self.drag {
+            Some(drag) if !self.fullscreen => drag,
+            _ => return false,
+        };
+        let window = self.swap_window.surface.window();
+        let size = match window.get_inner_size() {
+            Some(size) => [size.width, size.height],
+            None => return false,
+        };
+        if let UserEvent::MouseMove { pos: MousePos::Pos { x, y } } = event {
+            if let Some(cursor) = drag.hover([*x, *y], size) {
+                window.set_cursor(cursor);
+            }
+        }
+        let (used, reshape) = drag.handle(event, size);
+        if let Some(Reshape { moved, resized }) = reshape {
+            if moved != [0.0; 2] {
+                if let Some(at) = window.get_position() {
+                    window.set_position(LogicalPosition::new(at.x + moved[0], at.y + moved[1]));
                 }
-                winit::Event::WindowEvent { event: winit::WindowEvent::Resized(_), .. } => {
-                    frame_state.recreate_swapchain = true;
+            }
+            if resized != [0.0; 2] {
+                let [width, height] = [size[0] + resized[0], size[1] + resized[1]];
+                window.set_inner_size(LogicalSize::new(width, height));
+            }
+        }
+        used
+    }
+
+    fn toggle_hud(&mut self) {
+        if self.hud_updated.take().is_some() {
+            self.framer.hud_text = None;
+        } else {
+            self.framer.hud_text = Some(self.hud_text());
+            self.hud_updated = Some(Instant::now());
+        }
+    }
+
+    /// Frame pacing and the audio the capture ring dropped
+    fn hud_text(&self) -> String {
+        format!("{}  audio dropped {}", self.pacing.summary(), self.framer.dropped_frames)
+    }
+
+    /// Draws one frame.  Recoverable errors retry on the next one, fatal ones are returned.
+    pub fn render(&mut self, shared: &SharedState) -> Result<(), FrameError> {
+        let start = Instant::now();
+        if self.hud_updated.map_or(false, |updated| start - updated >= HUD_REFRESH) {
+            self.framer.hud_text = Some(self.hud_text());
+            self.hud_updated = Some(start);
+        }
+        if self.overlay_until.map_or(false, |until| Instant::now() >= until) {
+            self.overlay_until = None;
+            self.framer.overlay_text = None;
+        }
+
+        // settings changed since the last frame, or this is the first one.  Unless the
+        // setting asks for fullscreen, the first frame keeps the restored geometry.
+        if self.revision != Some(shared.revision()) {
+            self.revision = Some(shared.revision());
+            self.monitor = shared.config.video.monitor.clone();
+            let decorations = shared.config.video.decorations;
+            if self.kiosk_exit.is_none() && self.drag.is_some() == decorations {
+                self.swap_window.surface.window().set_decorations(decorations);
+                self.drag = if decorations { None } else { Some(WindowDrag::new()) };
+            }
+            let wanted = shared.config.start_in_fullscreen || shared.config.kiosk.enabled;
+            if self.start_in_fullscreen != Some(wanted) {
+                if self.start_in_fullscreen.is_some() || wanted {
+                    self.set_fullscreen(wanted);
                 }
-                _ => {}
+                self.start_in_fullscreen = Some(wanted);
+            }
+        }
+
+        if self.framer.analysis_paused != shared.analysis_paused {
+            self.framer.analysis_paused = shared.analysis_paused;
+            self.framer.audio_tex_tap.pause(shared.analysis_paused);
+            if let Some(comparison) = &self.framer.comparison {
+                comparison.pause(shared.analysis_paused);
+            }
+            self.listening_since = Instant::now();
+        }
+        if self.framer.frozen != shared.frozen {
+            self.framer.frozen = shared.frozen;
+            self.frozen_shown = false;
+        }
+        // frozen, one frame is presented to say so.  Another only if the window is resized.
+        if self.frozen_shown && !self.frame_state.recreate_swapchain {
+            self.framer.skip_frame(&self.resources);
+            self.framer.idle.update(self.fullscreen);
+            self.show_status(shared);
+            if let Some(left) = ASSUMED_REFRESH.checked_sub(start.elapsed()) {
+                thread::sleep(left);
             }
+            return Ok(());
+        }
+        self.frozen_shown = self.framer.frozen;
 
-            if let Some(pe) = input::process(&ev) {
-                if let Some(ue) = kt.update(&pe) {
-                    println!("key in mez");
-                    dbg!(&ev);
-                    match &ue {
-                        UserEvent::KeyPress { character: c } => {
-                            match &c {
-                                'f' => {
-                                    // TODO querying window or state tracking
-                                    let window = surface.window();
-                                    window.set_maximized(true);
-                                }
-                                's' => {
-                                    launcher.launch_settings();
-                                }
-                                '\u{1b}' => {
-                                    // escape key
-                                    done = true;
-                                }
-                                _ => {}
-                            }
-                        }
-                        _ => {}
-                    }
+        let device = self.swap_window.device.clone();
+        let frame_state = std::mem::replace(&mut self.frame_state, MezState::fresh(&device, false));
+        let video = &shared.config.video;
+        let adaptive = if video.adaptive_quality { self.quality.scale() } else { 1.0 };
+        self.framer.render_scale = shared.params.get().resolution_scale * adaptive;
+        let result = self.framer.render_one(&mut self.swap_window, frame_state, &self.resources);
+        self.framer.idle.update(self.fullscreen);
+        self.show_status(shared);
+        let deadline = shared.params.get().frame_interval().unwrap_or(ASSUMED_REFRESH);
+        self.pacing.record(start, start.elapsed(), self.framer.acquire_wait, deadline);
+        if let Some(scale) = self.quality.record(start, deadline) {
+            debug!("Frames are drawn at {}x the window's resolution", scale);
+        }
+        match result {
+            Ok(new_state) => {
+                self.frame_state = new_state;
+                Ok(())
+            },
+            Err(fe) => {
+                self.frame_state = MezState::fresh(&device, true);
+                if fe.is_recoverable() {
+                    debug!("Frame skipped.  Recreating the swapchain: {}", fe);
+                    Ok(())
+                } else {
+                    Err(fe)
                 }
-                if let Some(_ue) = mt.update(&pe) {}
+            },
+        }
+    }
+
+    fn show_status(&mut self, shared: &SharedState) {
+        let now = Instant::now();
+        self.recording_since = match shared.recorder.recording() {
+            Some(_) => Some(self.recording_since.unwrap_or(now)),
+            None => None,
+        };
+        // nothing heard lately, whether the tap failed or the source went away
+        let since = self.listening_since;
+        let heard_at = self.framer.features_at.map_or(since, |at| at.max(since));
+        let paused = shared.analysis_paused;
+        let status = WindowStatus {
+            scene: self.framer.capabilities.drawable(shared.params.get().scene),
+            track: self.resources.metadata.now_playing(),
+            recording: self.recording_since.map(|since| (now - since).as_secs() / 60),
+            frozen: shared.frozen,
+            paused,
+            audio_lost: !paused && now.duration_since(heard_at) >= AUDIO_LOST,
+        };
+        self.status.show(self.swap_window.surface.window(), status);
+    }
+
+    /// Where the window is now, for restoring it next launch
+    pub fn geometry(&mut self) -> WindowGeometry {
+        let window = self.swap_window.surface.window();
+        if !self.fullscreen {
+            if let Some(size) = window.get_inner_size() {
+                self.geometry.size = Some((size.width.round() as u32, size.height.round() as u32));
+            }
+            if let Some(position) = window.get_position() {
+                self.geometry.position =
+                    Some((position.x.round() as i32, position.y.round() as i32));
             }
-        });
-        if done {
-            break;
         }
+        self.geometry.monitor = window.get_current_monitor().get_name();
+        self.geometry.fullscreen = self.fullscreen;
+        self.geometry.clone()
     }
-    Ok(())
+
+    /// Closes the window once its last frame is done.  The analysis pipeline is handed back
+    /// so that daemon mode can keep it warm for the next window.
+    pub fn close(mut self) -> WarmPipeline {
+        info!("Frame pacing: {}", self.pacing.summary());
+        self.frame_state.previous_frame.cleanup_finished();
+        ewin::wait_idle(&self.swap_window.device);
+        let swap_window = &self.swap_window;
+        let queues = Queues {
+            graphics: swap_window.window_queue.clone(),
+            compute: swap_window.compute_queue.clone(),
+            transfer: swap_window.transfer_queue.clone(),
+        };
+        WarmPipeline { device: swap_window.device.clone(), queues, tap: self.framer.audio_tex_tap }
+    }
+
+    fn set_fullscreen(&mut self, fullscreen: bool) {
+        if fullscreen == self.fullscreen {
+            return;
+        }
+        if fullscreen {
+            // remember the windowed geometry before it's replaced by the monitor's
+            self.geometry();
+        }
+        self.fullscreen = fullscreen;
+        let window = self.swap_window.surface.window();
+        if fullscreen {
+            window.set_fullscreen(Some(fullscreen_monitor(window, self.monitor.as_ref())));
+        } else {
+            window.set_fullscreen(None);
+        }
+    }
+}
+
+/// The monitor called `name`.  One that has since been unplugged falls back to the window's
+/// current monitor.
+fn fullscreen_monitor(window: &winit::Window, name: Option<&String>) -> winit::MonitorId {
+    window
+        .get_available_monitors()
+        .find(|m| m.get_name().is_some() && m.get_name().as_ref() == name)
+        .unwrap_or_else(|| window.get_current_monitor())
 }
 
-struct MezResources {}
+static OVERLAY_COLOR: [f32; 4] = [1.0, 0.4, 0.4, 1.0];
+static HUD_COLOR: [f32; 4] = [0.9, 0.9, 0.9, 1.0];
+static RECORDING_COLOR: [f32; 4] = [1.0, 0.3, 0.3, 1.0];
+/// What a transparent window clears to, leaving the desktop
+static TRANSPARENT: [f32; 4] = [0.0; 4];
+/// Stats change too fast to read if redrawn every frame
+static HUD_REFRESH: Duration = Duration::from_millis(500);
+/// No analysis for this long and the title and taskbar say audio is lost
+static AUDIO_LOST: Duration = Duration::from_secs(3);
+
+/// Scenes that draw the spectrogram, which can be paused and scrubbed
+fn shows_history(scene: Scene) -> bool {
+    match scene {
+        Scene::Scroll | Scene::Studio => true,
+        _ => false,
+    }
+}
+
+/// What a key pressed over the visualizer does, once scenes and review have passed on it
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Hotkey {
+    Maximize,
+    Settings,
+    Hud,
+    NextScene,
+    Record,
+    Freeze,
+    Pause,
+    Close,
+}
+
+fn hotkey(character: char) -> Option<Hotkey> {
+    match character {
+        'f' => Some(Hotkey::Maximize),
+        's' => Some(Hotkey::Settings),
+        'i' => Some(Hotkey::Hud),
+        'n' => Some(Hotkey::NextScene),
+        'r' => Some(Hotkey::Record),
+        'v' => Some(Hotkey::Freeze),
+        'p' => Some(Hotkey::Pause),
+        '\u{1b}' => Some(Hotkey::Close),
+        _ => None,
+    }
+}
+
+/// Where a scene showing history draws the spectrogram, as shares of the window: left, top,
+/// width and height.  A `split` comparison takes the lower half of the scroll scene.
+fn spectrogram_area(scene: Scene, split: bool) -> [f32; 4] {
+    match scene {
+        Scene::Studio => [0.0, 0.5, 1.0, 0.5],
+        Scene::Scroll if split => [0.0, 0.0, 1.0, 0.5],
+        _ => [0.0, 0.0, 1.0, 1.0],
+    }
+}
+
+struct MezResources {
+    warm_tap: RefCell<Option<AudioTexTap>>,
+    params: ParamBus,
+    recorder: Recorder,
+    /// The window's one watch on the media players, for its title, the now playing scene and
+    /// track events
+    metadata: MetadataWatch,
+    config: Arc<ENguyenConfig>,
+}
 
 struct MezFramer {
     framebuffers: Vec<Arc<FramebufferAbstract + Send + Sync>>,
     render_pass: Arc<RenderPassAbstract + Send + Sync>,
-    pipeline: Arc<GraphicsPipelineAbstract + Send + Sync>,
-    fft_texture: Arc<StorageImage<Format>>,
-    background_rect: Arc<CpuAccessibleBuffer<[XyUvVertex]>>,
-    set: Arc<dyn DescriptorSet + Send + Sync>,
-    fft_tex_index: i32,
+    /// Uniforms every scene pipeline reads
+    frame_block: FrameBlock,
+    /// When the window opened, as `FrameUniforms::time` counts
+    opened: Instant,
+    /// When the previous frame was drawn
+    drawn_at: Option<Instant>,
+    /// The history column written last
+    newest: u32,
+    /// Where in the history the spectrogram is looking
+    review: Review,
     audio_tex_tap: AudioTexTap,
-    audio_tex: Option<AudioTex>,
+    /// Windows analyzed but not yet due, held back for the output's latency
+    held: VecDeque<AudioTex>,
+    /// Levels and waveform of the newest analysis window drawn
+    features: AudioFeatures,
+    /// When `features` was captured
+    features_at: Option<Instant>,
+    /// Audio frames the capture ring has discarded, for the HUD
+    dropped_frames: usize,
+    /// Every scene, whichever is shown
+    scenes: SceneManager,
+    /// The user's image, over every scene
+    logo: Option<Logo>,
+    /// What each scene is drawn over
+    backdrop: Backdrop,
+    /// Frequency marks and the pointer's readout, over the spectrogram
+    ruler: Option<FrequencyRuler>,
+    /// A second source drawn with the first in the scroll scene
+    comparison: Option<Comparison>,
+    painter: SolidPainter,
+    text: TextRenderer<'static>,
+    /// Error text drawn over the visualization
+    overlay_text: Option<String>,
+    /// Frame statistics drawn below the error text
+    hud_text: Option<String>,
+    /// How long the last frame waited for a swapchain image
+    acquire_wait: Duration,
+    /// Share of the window's resolution scenes draw at
+    render_scale: f32,
+    /// Drawn into when `render_scale` isn't 1 or edge colors are sampled
+    scaled: Option<ScaledTarget>,
+    /// Lights LEDs around the screen with the frame's edges
+    edges: Option<EdgeSampler>,
+    /// Keeps the display awake while fullscreen with music playing
+    idle: IdleInhibitor,
+    /// Scenes the device can't draw are skipped
+    capabilities: Capabilities,
+    /// Captions for the user's pauses
+    frozen: bool,
+    analysis_paused: bool,
 }
 
-// TODO this trait bounds repeats the declaration and proceeds to use concrete
-// types to build the return value.  Can it be declared parameterized on types?
-impl<'a, 'f: 'a> Framer<'a, 'f, MezFramer, MezState, MezResources> for MezFramer {
+impl MezFramer {
     fn new(
         swap_win: &mut SwapWindow,
         _r: &MezResources,
-    ) -> Result<(MezFramer, MezState), VulkanoError> {
-        // creates a stream of image-futures we can use to copy to our fft_texture
-        let source = AudioTexSource::new(1024).unwrap();
-        let tap =
-            AudioTexTap::turn_on(source, swap_win.device.clone(), swap_win.window_queue.clone())
-                .unwrap();
+    ) -> Result<(MezFramer, MezState), ENguyenError> {
+        // writes each analysis window into its history and sends a future to wait on
+        let tap = match _r.warm_tap.borrow_mut().take() {
+            Some(tap) => tap,
+            None => {
+                let mut source = AudioTexSource::new(1024)?;
+                source.watch_params(&_r.params);
+                source.record_to(&_r.recorder);
+                source.follow_tracks(&_r.metadata);
+                source.configure(&_r.config);
+                let (device, queue) = (swap_win.device.clone(), swap_win.compute_queue.clone());
+                AudioTexTap::turn_on(source, device, queue)?
+            },
+        };
 
         let vs = uv_image_vsm::Shader::load(swap_win.device.clone()).unwrap();
         let fs = uv_scroll_fsm::Shader::load(swap_win.device.clone()).unwrap();
 
-        let render_pass = Arc::new(
-            vulkano::single_pass_renderpass!(swap_win.device.clone(),
-                                             attachments: {
-                                                 color: {
-                                                     load: Clear,
-                                                     store: Store,
-                                                     format: swap_win.swapchain.format(),
-                                                     samples: 1,
-                                                 }
-                                             },
-
-                                             pass: {
-                                                 color: [color],
-                                                 depth_stencil: {}
-                                             }
-            )
-            .unwrap(),
-        );
+        let format = swap_win.swapchain.format();
+        let samples = supported_samples(&swap_win.device.physical_device(), _r.config.video.msaa);
+        if samples != _r.config.video.msaa.max(1) {
+            warn!("{}x multisampling isn't supported.  Using {}x.", _r.config.video.msaa, samples);
+        }
+        let render_pass = color_render_pass(&swap_win.device, format, samples)?;
 
-        let fft_texture = StorageImage::new(
-            swap_win.device.clone(),
-            Dimensions::Dim2d { width: 1024, height: 1024 },
-            Format::R32G32B32A32Sfloat,
-            Some(swap_win.window_queue.family()),
-        )
-        .unwrap();
-
-        let background_rect = {
-            CpuAccessibleBuffer::from_iter(
-                swap_win.device.clone(),
-                BufferUsage::all(),
-                [
-                    XyUvVertex { position: [1.0, 1.0], uv: [0.0, 0.0] },
-                    XyUvVertex { position: [-1.0, 1.0], uv: [1.0, 0.0] },
-                    XyUvVertex { position: [1.0, -1.0], uv: [0.0, 1.0] },
-                    XyUvVertex { position: [-1.0, -1.0], uv: [1.0, 1.0] },
-                ]
-                .iter()
-                .cloned(),
-            )?
-        };
+        // the spectrogram is drawn turned half around
+        let (background_rect, rect_upload) = static_vertices(
+            &swap_win.transfer_queue,
+            unit_quad().iter().map(|corner| XyUvVertex {
+                position: [-corner.position[0], -corner.position[1]],
+                uv: corner.uv,
+            }),
+        )?;
 
         // lives in descriptor set
         let sampler = Sampler::new(
@@ -213,43 +708,161 @@ impl<'a, 'f: 'a> Framer<'a, 'f, MezFramer, MezState, MezResources> for MezFramer
                 .vertex_shader(vs.main_entry_point(), ())
                 .viewports_dynamic_scissors_irrelevant(1)
                 .fragment_shader(fs.main_entry_point(), ())
-                .blend_alpha_blending()
+                .blend_collective(over_blending())
                 .render_pass(Subpass::from(render_pass.clone(), 0).ok_or("No subpass").unwrap())
                 .build(swap_win.device.clone())
                 .unwrap(),
         );
 
         let set = Arc::new(
-            PersistentDescriptorSet::start(pipeline.clone(), 0)
-                .add_sampled_image(fft_texture.clone(), sampler.clone())
+            PersistentDescriptorSet::start(pipeline.clone(), 1)
+                .add_sampled_image(tap.history.image(), sampler.clone())
                 .unwrap()
                 .build()
                 .unwrap(),
         );
 
         let framebuffers = swap_win.size_dependent_setup(render_pass.clone())?;
+
+        let subpass =
+            Subpass::from(render_pass.clone() as Arc<RenderPassAbstract + Send + Sync>, 0)
+                .ok_or_else(|| ENguyenError::render("Subpass is None"))?;
+        let text = TextRenderer::new(&swap_win.device, subpass, ui_font()?)?;
+        let pipelines = PipelineRegistry::new(swap_win.device.clone(), render_pass.clone());
+        let painter = SolidPainter::new(&pipelines)?;
+        let layout = _r.config.analysis.layout;
+        let spectrogram = Spectrogram::new(pipeline.clone(), set, background_rect.clone(), layout);
+        let capabilities = Capabilities::of(&swap_win.device);
+        let scene = _r.params.get().scene;
+        if let Some(missing) = capabilities.missing(scene) {
+            warn!("The {} scene needs {}, which the GPU lacks.  Showing scroll.", scene, missing);
+        }
+        let mut scenes = SceneManager::new();
+        scenes.add(Scene::Scroll, Box::new(spectrogram.clone()));
+        let comparison = Comparison::start(
+            &_r.config,
+            &_r.params,
+            &swap_win.compute_queue,
+            &pipelines,
+            sampler.clone(),
+            background_rect.clone(),
+            spectrogram.clone(),
+        )
+        .unwrap_or_else(|e| {
+            warn!("Nothing to compare with: {}", e);
+            None
+        });
+        scenes.add(Scene::Studio, Box::new(StudioScene::new(spectrogram)));
+        scenes.add(Scene::Meters, Box::new(MetersScene::default()));
+        scenes.add(Scene::Goniometer, Box::new(Goniometer::new()));
+        let keys = KeyRange::from_config(&_r.config);
+        scenes.add(Scene::Piano, Box::new(PianoScene::new(keys)));
+        scenes.add(Scene::Tunnel, Box::new(Tunnel::new(&pipelines, background_rect)?));
+        scenes.add(Scene::NowPlaying, Box::new(NowPlayingScene::new(_r.metadata.clone())));
+        let queue = &swap_win.transfer_queue;
+        let (logo, logo_upload) = match Logo::load(&_r.config.logo, queue, &pipelines) {
+            Some((logo, upload)) => (Some(logo), Some(upload)),
+            None => (None, None),
+        };
+        let (backdrop, backdrop_upload) = Backdrop::new(&_r.config, queue, &pipelines)?;
+
+        let edges = _r.config.export.edges.as_ref().and_then(|config| {
+            EdgeSampler::new(&swap_win.device, config, format)
+                .map_err(|e| warn!("Edge colors are off: {}", e))
+                .ok()
+        });
+
+        let ruler = if _r.config.video.frequency_ruler {
+            Some(FrequencyRuler::new(_r.config.analysis.layout))
+        } else {
+            None
+        };
+
+        let frame_block = FrameBlock::new(&swap_win.device, pipeline.clone());
+        // held windows' columns are written ahead of the view, so it can't go back over them
+        let depth = tap.history.depth().saturating_sub(HELD_COLUMNS);
+        let review = Review::new(depth, tap.history.shown());
         let framer = MezFramer {
-            pipeline,
             render_pass,
-            fft_texture,
-            background_rect,
             framebuffers,
-            set,
+            frame_block,
+            opened: Instant::now(),
+            drawn_at: None,
             audio_tex_tap: tap,
-            audio_tex: None,
-            fft_tex_index: 0,
+            held: VecDeque::new(),
+            features: AudioFeatures::default(),
+            features_at: None,
+            dropped_frames: 0,
+            scenes,
+            logo,
+            backdrop,
+            ruler,
+            comparison,
+            painter,
+            newest: 0,
+            review,
+            text,
+            overlay_text: None,
+            hud_text: None,
+            acquire_wait: Duration::from_secs(0),
+            render_scale: 1.0,
+            scaled: None,
+            edges,
+            idle: IdleInhibitor::default(),
+            capabilities,
+            frozen: false,
+            analysis_paused: false,
         };
-        let previous_frame = Box::new(vulkano::sync::now(swap_win.device.clone()));
+        let mut previous_frame: Box<dyn GpuFuture> = Box::new(rect_upload.join(backdrop_upload));
+        if let Some(upload) = logo_upload {
+            previous_frame = Box::new(previous_frame.join(upload));
+        }
         let frame_state = MezState { previous_frame, recreate_swapchain: false };
         Ok((framer, frame_state))
     }
 
+    /// Takes in a window of analysis, drawn or not.  Returns how long since the last one.
+    fn take_in(&mut self, r: &AudioTex, _r: &MezResources) -> Option<Duration> {
+        self.newest = r.column;
+        self.review.written();
+        let elapsed = self.features_at.map(|at| r.captured.duration_since(at));
+        self.features_at = Some(r.captured);
+        self.dropped_frames = r.dropped_frames;
+        if r.features.track_changed {
+            if let Some(change) = tracks::on_change(&_r.config, &_r.params.get()) {
+                info!("New track.  {}", change);
+                _r.params.apply(change);
+            }
+        }
+        self.idle.hear(&r.features);
+        elapsed
+    }
+
+    /// Takes in the analysis due without drawing it, so frozen visuals resume in step
+    fn skip_frame(&mut self, _r: &MezResources) {
+        self.held.extend(self.audio_tex_tap.tap.try_iter());
+        let now = Instant::now();
+        while let Some(r) = self.held.pop_front() {
+            if r.captured + r.delay > now {
+                self.held.push_front(r);
+                break;
+            }
+            self.take_in(&r, _r);
+            // dropping the window waits on its dispatch
+            self.features = r.features;
+        }
+        if let Some(comparison) = &mut self.comparison {
+            while comparison.due(now).is_some() {}
+        }
+    }
+
     fn render_one(
         &mut self,
         swap_win: &mut SwapWindow,
         mut frame_state: MezState,
         _r: &MezResources,
-    ) -> Result<MezState, VulkanoError> {
+    ) -> Result<MezState, FrameError> {
+        self.acquire_wait = Duration::from_secs(0);
         let mut previous_frame =
             Box::new(vulkano::sync::now(swap_win.device.clone())) as Box<dyn GpuFuture>;
 
@@ -261,96 +874,190 @@ impl<'a, 'f: 'a> Framer<'a, 'f, MezFramer, MezState, MezResources> for MezFramer
             self.framebuffers = swap_win.recreate_swapchain(self.render_pass.clone())?;
         }
 
-        let ready: Option<AudioTex> = {
-            if let Some(_) = self.audio_tex {
-                let mut ready = None;
-                std::mem::swap(&mut ready, &mut self.audio_tex);
-                ready
-            } else if let Ok(fresh_tex) = self.audio_tex_tap.tap.try_recv() {
-                Some(fresh_tex)
-            } else {
-                None
+        let params = _r.params.get();
+        let scene = self.capabilities.drawable(params.scene);
+        let dimensions = swap_win.f_dimensions();
+        // text and layers are placed in logical pixels and scaled to the window's DPI
+        let hidpi = swap_win.hidpi_factor();
+        let logical = swap_win.logical_dimensions();
+        let mut text = Vec::new();
+        if let Some(error) = &self.overlay_text {
+            text.push(TextBlock::new(error.clone(), [24.0, 48.0], 28.0, OVERLAY_COLOR));
+        }
+        if let Some(hud) = &self.hud_text {
+            text.push(TextBlock::new(hud.clone(), [24.0, 88.0], 20.0, HUD_COLOR));
+        }
+        if !self.review.is_live() {
+            let behind = self.review.seconds_behind();
+            let caption = format!("Paused {:.1}s back.  Space for live.", behind);
+            text.push(TextBlock::new(caption, [24.0, 128.0], 20.0, HUD_COLOR));
+        }
+        if _r.recorder.recording().is_some() {
+            let caption = "Recording.  r stops.".to_owned();
+            text.push(TextBlock::new(caption, [24.0, 168.0], 20.0, RECORDING_COLOR));
+        }
+        let pauses = [
+            (self.frozen, "Frozen.  v for live visuals."),
+            (self.analysis_paused, "Analysis paused.  p resumes."),
+        ];
+        let captions = pauses.iter().filter(|(shown, _)| *shown).map(|(_, caption)| caption);
+        for (at, caption) in captions.enumerate() {
+            let y = 208.0 + 40.0 * at as f32;
+            text.push(TextBlock::new(caption.to_owned(), [24.0, y], 20.0, HUD_COLOR));
+        }
+        if let Some(window) = logical {
+            self.scenes.resize(window);
+        }
+        if let Some(shown) = self.scenes.get(scene) {
+            text.extend(shown.text());
+        }
+        let split = self.comparison.as_ref().map_or(false, Comparison::splits);
+        if let (Some(ruler), Some(window)) = (&self.ruler, logical) {
+            if shows_history(scene) {
+                text.extend(ruler.text(window, spectrogram_area(scene, split)));
             }
-        };
+        }
+        if let Some(upload) = self.text.show(&swap_win.window_queue, &text, hidpi)? {
+            previous_frame = Box::new(previous_frame.join(upload));
+        }
 
-        let (image_num, acquire_future) = swap_win.future_image().unwrap();
-        let clear_values = vec![[0.0, 0.0, 0.0, 1.0].into()];
+        self.held.extend(self.audio_tex_tap.tap.try_iter());
+        let arrived = Instant::now();
+        let due = self.held.front().map_or(false, |r| r.captured + r.delay <= arrived);
+        let ready: Option<AudioTex> = if due { self.held.pop_front() } else { None };
+
+        let acquiring = Instant::now();
+        let (image_num, acquire_future) = swap_win.future_image()?;
+        self.acquire_wait = acquiring.elapsed();
+
+        // away from full scale, scenes draw into an image of their own filtered onto the window's
+        let window = swap_win.swap_images[image_num].dimensions();
+        let scale = self.render_scale;
+        // the swapchain's images can't be sampled, so edge colors need an image of their own
+        if (scale - 1.0).abs() < std::f32::EPSILON && self.edges.is_none() {
+            self.scaled = None;
+        } else if !self.scaled.as_ref().map_or(false, |target| target.fits(window, scale)) {
+            let (device, format) = (&swap_win.device, swap_win.swapchain.format());
+            let render_pass = self.render_pass.clone();
+            self.scaled = Some(ScaledTarget::new(device, render_pass, format, window, scale)?);
+        }
+        let (framebuffer, state) = match &self.scaled {
+            Some(target) => (target.framebuffer.clone(), target.dynamic_state.clone()),
+            None => (self.framebuffers[image_num].clone(), swap_win.dynamic_state.clone()),
+        };
+        let background = _r.config.background(scene);
+        let clear_color =
+            if swap_win.transparent { TRANSPARENT } else { self.backdrop.clear_color(background) };
+        let mut clear_values = vec![clear_color.into()];
+        // resolved attachments are written whole and need no clearing
+        clear_values.resize(self.render_pass.num_attachments(), ClearValue::None);
 
         let mut cbb: AutoCommandBufferBuilder = AutoCommandBufferBuilder::primary_one_time_submit(
             swap_win.device.clone(),
             swap_win.window_queue.family(),
         )
-        .unwrap();
+        .map_err(|e| ENguyenError::render("Command buffer allocation failed").caused_by(e))?;
 
         if let Some(r) = ready {
-            let mut x: i32 = self.fft_tex_index;
-            let ux: i32 = x as i32;
-            cbb = cbb
-                .copy_image(
-                    r.buffer.clone(),
-                    [0, 0, 0],
-                    0,
-                    0,
-                    self.fft_texture.clone(),
-                    [ux, 0, 0],
-                    0,
-                    0,
-                    [1 as u32, 1024, 1],
-                    1,
-                )
-                .unwrap();
-            x += 1;
-            if x + 1 > 1024 {
-                x = 0;
+            let elapsed = self.take_in(&r, _r);
+            // the frame samples the column being written
+            previous_frame = Box::new(previous_frame.join(r.ready));
+            self.scenes.warmed(r.warm_since);
+            self.scenes.update(&r.features, elapsed.unwrap_or_default());
+            if let Some(ruler) = &mut self.ruler {
+                ruler.update(r.range, &r.features.readout);
             }
-            self.fft_tex_index = x;
-            self.audio_tex = None;
+            if let Some(logo) = &mut self.logo {
+                logo.update(&r.features, elapsed.unwrap_or_default());
+            }
+            self.features = r.features;
+        }
+        if let Some(failure) = self.comparison.as_mut().and_then(Comparison::failure) {
+            warn!("Comparison stopped: {}", failure);
+            self.comparison = None;
+        }
+        if let Some(ready) = self.comparison.as_mut().and_then(|c| c.due(arrived)) {
+            previous_frame = Box::new(previous_frame.join(ready));
         }
 
-        let push_constants =
-            uv_scroll_fsm::ty::PushConstant { offset_fac: self.fft_tex_index as f32 / 1024_f32 };
+        let now = Instant::now();
+        let seconds = |elapsed: Duration| millis(elapsed) as f32 / 1000.0;
+        let viewport = state.viewports.as_ref().and_then(|viewports| viewports.first());
+        let frame = self.frame_block.set(FrameUniforms {
+            bands: self.features.bands,
+            resolution: viewport.map_or([0.0; 2], |viewport| viewport.dimensions),
+            time: seconds(now.duration_since(self.opened)),
+            delta: self.drawn_at.map_or(0.0, |at| seconds(now.duration_since(at))),
+            beat_phase: self.features.beat.phase,
+        })?;
+        self.drawn_at = Some(now);
+        self.painter.begin_frame(frame.clone());
 
-        if self.audio_tex.is_none() {
-            self.audio_tex = self.audio_tex_tap.tap.try_recv().ok();
+        cbb = cbb
+            .begin_render_pass(framebuffer, false, clear_values)
+            .map_err(|e| ENguyenError::render("Render pass could not begin").caused_by(e))?;
+        if let Some(window) = dimensions {
+            cbb = self.backdrop.draw(cbb, &state, background, window)?;
+        }
+        let history = &self.audio_tex_tap.history;
+        let scene_frame = SceneFrame {
+            painter: &self.painter,
+            state: &state,
+            uniforms: frame,
+            features: &self.features,
+            palette: params.palette,
+            tonemap: _r.config.video.tonemap,
+            scroll: history.scroll(history.back_from(self.newest, self.review.behind())),
+            span: history.span(),
+        };
+        match (&self.comparison, scene) {
+            (Some(comparison), Scene::Scroll) => {
+                cbb = comparison.draw(cbb, &scene_frame, self.review.behind())?;
+            },
+            _ => {
+                if let Some(shown) = self.scenes.get(scene) {
+                    cbb = shown.render(cbb, &scene_frame)?;
+                }
+            },
+        }
+        if let Some(ruler) = &self.ruler {
+            if shows_history(scene) {
+                cbb = ruler.draw(&self.painter, cbb, &state, spectrogram_area(scene, split))?;
+            }
+        }
+        cbb = self.scenes.veil(cbb, &self.painter, &state, clear_color, now)?;
+        // layers go over every scene and under the text
+        if let (Some(logo), Some(window)) = (&self.logo, logical) {
+            cbb = logo.draw(cbb, &state, window)?;
+        }
+        if let Some(window) = dimensions {
+            cbb = self.text.draw(cbb, &state, window)?;
         }
-
         cbb = cbb
-            .begin_render_pass(self.framebuffers[image_num].clone(), false, clear_values)
-            .unwrap()
-            .draw(
-                self.pipeline.clone(),
-                &swap_win.dynamic_state,
-                vec![self.background_rect.clone()],
-                self.set.clone(),
-                push_constants,
-            )
-            .unwrap()
             .end_render_pass()
-            .unwrap();
-        let cb = cbb.build().unwrap();
+            .map_err(|e| ENguyenError::render("Render pass could not end").caused_by(e))?;
+        if let (Some(edges), Some(target)) = (&mut self.edges, &self.scaled) {
+            cbb = edges.sample(cbb, target.image())?;
+        }
+        if let Some(target) = &self.scaled {
+            cbb = target.blit(cbb, swap_win.swap_images[image_num].clone())?;
+        }
+        let cb = cbb
+            .build()
+            .map_err(|e| ENguyenError::render("Command buffer build failed").caused_by(e))?;
 
         let new_frame = acquire_future
             .join(previous_frame)
-            .then_execute(swap_win.window_queue.clone(), cb)?
+            .then_execute(swap_win.window_queue.clone(), cb)
+            .map_err(|e| ENguyenError::render("Frame submit failed").caused_by(e))?
             .then_swapchain_present(
                 swap_win.window_queue.clone(),
                 swap_win.swapchain.clone(),
                 image_num,
             )
-            .then_signal_fence_and_flush();
-
-        match new_frame {
-            Ok(frame) => {
-                frame_state.previous_frame = Box::new(frame);
-                Ok(frame_state)
-            }
-            // TODO research which of these are recoverable
-            Err(e @ FlushError::OutOfDate) => Err((Box::new(e) as Box<Error>).into()),
-            Err(e) => {
-                error!("{:?}", e);
-                Err((Box::new(e) as Box<Error>).into())
-            }
-        }
+            .then_signal_fence_and_flush()?;
+        frame_state.previous_frame = Box::new(new_frame);
+        Ok(frame_state)
     }
 }
 
@@ -359,4 +1066,38 @@ struct MezState {
     pub recreate_swapchain: bool,
 }
 
-impl FrameState for MezState {}
+impl MezState {
+    fn fresh(device: &Arc<Device>, recreate_swapchain: bool) -> MezState {
+        let previous_frame = Box::new(vulkano::sync::now(device.clone()));
+        MezState { previous_frame, recreate_swapchain }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::{typed, Simulation};
+
+    #[test]
+    fn typed_hotkeys() {
+        let pressed = Simulation::new().run(&typed("fxsin rvp\u{1b}"));
+        let keys: Vec<Hotkey> = pressed
+            .iter()
+            .filter_map(|ue| match ue {
+                UserEvent::KeyPress { character } => hotkey(*character),
+                _ => None,
+            })
+            .collect();
+        let expected = vec![
+            Hotkey::Maximize,
+            Hotkey::Settings,
+            Hotkey::Hud,
+            Hotkey::NextScene,
+            Hotkey::Record,
+            Hotkey::Freeze,
+            Hotkey::Pause,
+            Hotkey::Close,
+        ];
+        assert_eq!(keys, expected);
+    }
+}
@@ -0,0 +1,209 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! Track metadata from media players.  Players publishing MPRIS on the D-Bus session bus
+//! are polled from a thread of their own.
+
+use dbus::arg::{RefArg, Variant};
+use dbus::stdintf::org_freedesktop_dbus::Properties;
+use dbus::{BusType, Connection, Message};
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+static MPRIS_PREFIX: &str = "org.mpris.MediaPlayer2.";
+static MPRIS_PATH: &str = "/org/mpris/MediaPlayer2";
+static PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+/// D-Bus calls are abandoned after this many milliseconds
+static CALL_TIMEOUT: i32 = 500;
+static POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Polls between attempts to reach the session bus
+static RECONNECT_POLLS: u32 = 10;
+/// How often the poll thread checks for shutdown while waiting
+static STOP_CHECK: Duration = Duration::from_millis(100);
+
+/// The track a player reports
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NowPlaying {
+    pub title: String,
+    pub artists: Vec<String>,
+}
+
+impl NowPlaying {
+    /// Artists joined for display.  Empty if there are none.
+    pub fn artist(&self) -> String {
+        self.artists.join(", ")
+    }
+}
+
+/// Keeps the newest track of whichever player is playing.  Clones share one poll thread,
+/// which stops when the last is dropped.
+#[derive(Clone)]
+pub struct MetadataWatch {
+    now_playing: Arc<Mutex<Option<NowPlaying>>>,
+    _poller: Arc<Poller>,
+}
+
+/// Stops polling on drop
+struct Poller {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MetadataWatch {
+    pub fn start() -> MetadataWatch {
+        let now_playing = Arc::new(Mutex::new(None));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (shared, stopped) = (now_playing.clone(), stop.clone());
+        let thread = thread::spawn(move || poll(&shared, &stopped));
+        MetadataWatch { now_playing, _poller: Arc::new(Poller { stop, thread: Some(thread) }) }
+    }
+
+    /// None while no player has a track
+    pub fn now_playing(&self) -> Option<NowPlaying> {
+        self.now_playing.lock().unwrap().clone()
+    }
+}
+
+impl Drop for Poller {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn poll(shared: &Mutex<Option<NowPlaying>>, stop: &AtomicBool) {
+    let mut connection = None;
+    let mut polls_since_attempt = RECONNECT_POLLS;
+    let mut warned = false;
+    while !stop.load(Ordering::Relaxed) {
+        if connection.is_none() && polls_since_attempt >= RECONNECT_POLLS {
+            polls_since_attempt = 0;
+            match Connection::get_private(BusType::Session) {
+                Ok(connected) => connection = Some(connected),
+                Err(e) if !warned => {
+                    warn!("No session bus for track metadata: {}", e);
+                    warned = true;
+                },
+                Err(e) => debug!("Session bus still unreachable: {}", e),
+            }
+        }
+        polls_since_attempt += 1;
+
+        if let Some(connected) = &connection {
+            match current_track(connected) {
+                Ok(track) => *shared.lock().unwrap() = track,
+                Err(e) => {
+                    debug!("Track metadata poll failed: {}", e);
+                    *shared.lock().unwrap() = None;
+                    connection = None;
+                },
+            }
+        }
+
+        let mut waited = Duration::from_secs(0);
+        while waited < POLL_INTERVAL && !stop.load(Ordering::Relaxed) {
+            thread::sleep(STOP_CHECK);
+            waited += STOP_CHECK;
+        }
+    }
+}
+
+/// The track of the first playing player, or else the first paused one
+fn current_track(connection: &Connection) -> Result<Option<NowPlaying>, dbus::Error> {
+    let list = Message::new_method_call(
+        "org.freedesktop.DBus",
+        "/org/freedesktop/DBus",
+        "org.freedesktop.DBus",
+        "ListNames",
+    )
+    .map_err(|e| dbus::Error::new_custom("org.freedesktop.DBus.Error.Failed", &e))?;
+    let names: Vec<String> =
+        connection.send_with_reply_and_block(list, CALL_TIMEOUT)?.get1().unwrap_or_default();
+
+    let mut players = Vec::new();
+    for name in names.iter().filter(|name| name.starts_with(MPRIS_PREFIX)) {
+        let player = connection.with_path(name.as_str(), MPRIS_PATH, CALL_TIMEOUT);
+        // players come and go between listing and asking
+        let status: String = match player.get(PLAYER_INTERFACE, "PlaybackStatus") {
+            Ok(status) => status,
+            Err(_) => continue,
+        };
+        players.push((name.clone(), status));
+    }
+
+    let name = match pick_player(&players) {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+    let player = connection.with_path(name, MPRIS_PATH, CALL_TIMEOUT);
+    let metadata: HashMap<String, Variant<Box<dyn RefArg>>> =
+        player.get(PLAYER_INTERFACE, "Metadata")?;
+    let title = metadata.get("xesam:title").and_then(|title| title.0.as_str());
+    let artists = metadata
+        .get("xesam:artist")
+        .and_then(|artists| artists.0.as_iter())
+        .map(|artists| artists.filter_map(|artist| artist.as_str().map(String::from)).collect())
+        .unwrap_or_default();
+    Ok(track(title, artists))
+}
+
+/// Playing players win over paused ones.  Stopped players have no track worth showing.
+fn pick_player(players: &[(String, String)]) -> Option<&str> {
+    let with_status = |wanted: &str| {
+        players.iter().find(|(_, status)| status == wanted).map(|(name, _)| name.as_str())
+    };
+    with_status("Playing").or_else(|| with_status("Paused"))
+}
+
+/// Players without a title have nothing to show
+fn track(title: Option<&str>, artists: Vec<String>) -> Option<NowPlaying> {
+    let title = title.map(str::trim).filter(|title| !title.is_empty())?;
+    Some(NowPlaying { title: title.to_owned(), artists })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn playing_players_are_picked() {
+        let player = |name: &str, status: &str| (name.to_owned(), status.to_owned());
+        let players = vec![
+            player("org.mpris.MediaPlayer2.a", "Stopped"),
+            player("org.mpris.MediaPlayer2.b", "Paused"),
+            player("org.mpris.MediaPlayer2.c", "Playing"),
+        ];
+        assert_eq!(pick_player(&players), Some("org.mpris.MediaPlayer2.c"));
+        assert_eq!(pick_player(&players[..2]), Some("org.mpris.MediaPlayer2.b"));
+        assert_eq!(pick_player(&players[..1]), None);
+    }
+
+    #[test]
+    fn tracks_need_titles() {
+        let artists = vec!["Nguyen".to_owned(), "Knapp".to_owned()];
+        let playing = track(Some(" Spectra "), artists.clone()).unwrap();
+        assert_eq!(playing.title, "Spectra");
+        assert_eq!(playing.artist(), "Nguyen, Knapp");
+        assert_eq!(track(Some(""), artists.clone()), None);
+        assert_eq!(track(None, artists), None);
+    }
+}
@@ -0,0 +1,222 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! The meters scene.  A VU and a peak programme meter per channel on a dBFS scale.
+
+use crate::bench::millis;
+use crate::errors::ENguyenError;
+use crate::features::{dbfs, AudioFeatures};
+use crate::geometry::quad;
+use crate::rendering::{SolidPainter, TextBlock, XyVertex};
+use crate::scenes::{labelled, Scene, SceneFrame};
+
+use std::time::Duration;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DynamicState};
+
+/// VU meters reach 99% of a steady tone's level in 300ms
+static VU_RISE: f32 = 0.3;
+/// Peak programme meters integrate over 5ms and fall 20dB in 1.7s, as IEC 60268-10 type I
+static PPM_INTEGRATION: f32 = 0.005;
+static PPM_FALL: f32 = 20.0 / 1.7;
+/// Level at the bottom of the scale
+static FLOOR: f32 = -60.0;
+/// Scale markings, dBFS
+static MARKS: [f32; 8] = [0.0, -3.0, -6.0, -10.0, -20.0, -30.0, -40.0, -60.0];
+/// Where full scale and the floor are drawn, in window coordinates
+static SCALE_TOP: f32 = -0.8;
+static SCALE_BOTTOM: f32 = 0.8;
+/// Left edge of the markings.  Labels go to the left of it.
+static MARKS_LEFT: f32 = -0.6;
+static MARKS_RIGHT: f32 = 0.9;
+static MARK_HEIGHT: f32 = 0.004;
+/// Left edge of each channel's VU bar.  Its PPM bar follows.
+static CHANNEL_LEFT: [f32; 2] = [-0.5, 0.25];
+static VU_WIDTH: f32 = 0.4;
+static PPM_WIDTH: f32 = 0.15;
+static BAR_GAP: f32 = 0.05;
+static VU_COLOR: [f32; 4] = [0.0, 0.906, 0.702, 1.0];
+static PPM_COLOR: [f32; 4] = [1.0, 0.75, 0.2, 1.0];
+static MARK_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 0.3];
+/// Label glyphs are about this tall, in pixels
+pub static LABEL_SIZE: f32 = 20.0;
+
+/// Meter readings of each channel, as linear levels.  Both fall to silence from wherever
+/// they were, so the default starts at rest.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Ballistics {
+    pub vu: [f32; 2],
+    pub ppm: [f32; 2],
+}
+
+impl Ballistics {
+    /// Move toward `features`, measured `elapsed` after the previous ones
+    pub fn update(&mut self, features: &AudioFeatures, elapsed: Duration) {
+        let seconds = millis(elapsed) as f32 / 1000.0;
+        let vu_step = 1.0 - (-seconds * 100_f32.ln() / VU_RISE).exp();
+        let ppm_step = 1.0 - (-seconds / PPM_INTEGRATION).exp();
+        for (vu, rms) in self.vu.iter_mut().zip(features.rms.iter()) {
+            *vu += (rms - *vu) * vu_step;
+        }
+        for (ppm, peak) in self.ppm.iter_mut().zip(features.peak.iter()) {
+            *ppm = if *peak >= *ppm {
+                *ppm + (peak - *ppm) * ppm_step
+            } else {
+                let fallen = dbfs(*ppm, FLOOR) - PPM_FALL * seconds;
+                if fallen <= FLOOR {
+                    *peak
+                } else {
+                    10_f32.powf(fallen / 20.0).max(*peak)
+                }
+            };
+        }
+    }
+}
+
+/// The meters with their labels
+#[derive(Default)]
+pub struct MetersScene {
+    ballistics: Ballistics,
+    window: Option<[f32; 2]>,
+}
+
+impl Scene for MetersScene {
+    fn update(&mut self, features: &AudioFeatures, elapsed: Duration) {
+        self.ballistics.update(features, elapsed);
+    }
+
+    fn resize(&mut self, window: [f32; 2]) {
+        self.window = Some(window);
+    }
+
+    fn text(&mut self) -> Vec<TextBlock> {
+        self.window.map_or_else(Vec::new, |window| labelled(labels(window)))
+    }
+
+    fn render(
+        &mut self,
+        cbb: AutoCommandBufferBuilder,
+        frame: &SceneFrame,
+    ) -> Result<AutoCommandBufferBuilder, ENguyenError> {
+        draw(frame.painter, cbb, frame.state, &self.ballistics)
+    }
+}
+
+/// Records the meters and scale over the whole of `state`'s viewport
+pub fn draw(
+    painter: &SolidPainter,
+    mut cbb: AutoCommandBufferBuilder,
+    state: &DynamicState,
+    ballistics: &Ballistics,
+) -> Result<AutoCommandBufferBuilder, ENguyenError> {
+    let mut vu = Vec::new();
+    let mut ppm = Vec::new();
+    for (channel, left) in CHANNEL_LEFT.iter().enumerate() {
+        let vu_top = level_y(dbfs(ballistics.vu[channel], FLOOR));
+        vu.extend_from_slice(&quad(*left, vu_top, left + VU_WIDTH, SCALE_BOTTOM));
+        let ppm_left = left + VU_WIDTH + BAR_GAP;
+        let ppm_top = level_y(dbfs(ballistics.ppm[channel], FLOOR));
+        ppm.extend_from_slice(&quad(ppm_left, ppm_top, ppm_left + PPM_WIDTH, SCALE_BOTTOM));
+    }
+    cbb = painter.triangles(cbb, state, vu, VU_COLOR)?;
+    cbb = painter.triangles(cbb, state, ppm, PPM_COLOR)?;
+    painter.triangles(cbb, state, mark_lines(), MARK_COLOR)
+}
+
+/// Where `db` is drawn on the scale
+fn level_y(db: f32) -> f32 {
+    let fraction = (db.clamp(FLOOR, 0.0) - FLOOR) / -FLOOR;
+    SCALE_BOTTOM - (SCALE_BOTTOM - SCALE_TOP) * fraction
+}
+
+fn mark_lines() -> Vec<XyVertex> {
+    let mut vertices = Vec::new();
+    for mark in MARKS.iter() {
+        let y = level_y(*mark);
+        vertices.extend_from_slice(&quad(MARKS_LEFT, y, MARKS_RIGHT, y + MARK_HEIGHT));
+    }
+    vertices
+}
+
+/// Scale numbers and meter names, with where their baselines start in a window of
+/// `dimensions` pixels
+pub fn labels(dimensions: [f32; 2]) -> Vec<(String, [f32; 2])> {
+    let [width, height] = dimensions;
+    let pixels = |x: f32, y: f32| [(x + 1.0) / 2.0 * width, (y + 1.0) / 2.0 * height];
+    let mut labels: Vec<(String, [f32; 2])> = MARKS
+        .iter()
+        .map(|mark| {
+            let [x, y] = pixels(-0.9, level_y(*mark));
+            (format!("{}", mark), [x, y + LABEL_SIZE / 2.0])
+        })
+        .collect();
+    for (channel, left) in CHANNEL_LEFT.iter().enumerate() {
+        let name = ["l", "r"][channel];
+        let [x, y] = pixels(*left, SCALE_BOTTOM);
+        labels.push((format!("{} vu", name), [x, y + LABEL_SIZE * 1.5]));
+        let [x, y] = pixels(left + VU_WIDTH + BAR_GAP, SCALE_BOTTOM);
+        labels.push(("ppm".to_owned(), [x, y + LABEL_SIZE * 1.5]));
+    }
+    labels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn steady(level: f32) -> AudioFeatures {
+        AudioFeatures { rms: [level; 2], peak: [level; 2], ..AudioFeatures::default() }
+    }
+
+    #[test]
+    fn vu_integrates_over_300ms() {
+        let mut meters = Ballistics::default();
+        let tone = steady(0.5);
+        for _ in 0..15 {
+            meters.update(&tone, Duration::from_millis(10));
+        }
+        assert!(meters.vu[0] < 0.5 * 0.99);
+        for _ in 0..15 {
+            meters.update(&tone, Duration::from_millis(10));
+        }
+        assert!((meters.vu[0] - 0.5 * 0.99).abs() < 1e-3, "{}", meters.vu[0]);
+    }
+
+    #[test]
+    fn ppm_rises_fast_and_falls_20db_in_1_7s() {
+        let mut meters = Ballistics::default();
+        meters.update(&steady(1.0), Duration::from_millis(30));
+        assert!(dbfs(meters.ppm[1], FLOOR) > -0.1);
+        meters.ppm = [1.0; 2];
+        for _ in 0..17 {
+            meters.update(&steady(0.0), Duration::from_millis(100));
+        }
+        assert!((dbfs(meters.ppm[0], FLOOR) + 20.0).abs() < 0.01, "{}", meters.ppm[0]);
+        for _ in 0..100 {
+            meters.update(&steady(0.0), Duration::from_millis(100));
+        }
+        assert_eq!(meters.ppm, [0.0; 2]);
+    }
+
+    #[test]
+    fn scale_is_labelled() {
+        assert_eq!(level_y(0.0), SCALE_TOP);
+        assert_eq!(level_y(-120.0), SCALE_BOTTOM);
+        let labels = labels([1000.0, 500.0]);
+        assert_eq!(labels.len(), MARKS.len() + 4);
+        assert_eq!(labels[0].0, "0");
+        assert!((labels[0].1[1] - (50.0 + LABEL_SIZE / 2.0)).abs() < 1e-3);
+        assert_eq!(labels[MARKS.len()].0, "l vu");
+    }
+}
@@ -0,0 +1,206 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! Just enough MQTT 3.1.1 to publish to a broker: a clean session, then QoS 0 messages.
+//! Publishing blocks on the network, so it's done from threads other than analysis.
+
+use crate::export::with_port;
+
+use log::warn;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::thread;
+use std::time::Duration;
+
+static IO_TIMEOUT: Duration = Duration::from_secs(2);
+static KEEP_ALIVE: u16 = 60;
+static DEFAULT_PORT: u16 = 1883;
+
+/// A TCP connection to `address` that gives up on a silent peer
+pub fn open(address: &str) -> io::Result<TcpStream> {
+    let resolved = address.to_socket_addrs()?.next();
+    let resolved = resolved
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} not found", address)))?;
+    let stream = TcpStream::connect_timeout(&resolved, IO_TIMEOUT)?;
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    Ok(stream)
+}
+
+/// One message for the broker
+#[derive(Clone, Debug, PartialEq)]
+pub struct Publication {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    /// The broker keeps the last retained message of a topic for clients that join later
+    pub retain: bool,
+}
+
+/// Connects on the first publish and again after a failure
+pub struct MqttClient {
+    address: String,
+    username: Option<String>,
+    password: Option<String>,
+    broker: Option<TcpStream>,
+}
+
+impl MqttClient {
+    /// `address` is `host` or `host:port`, on port 1883 unless given
+    pub fn new(address: &str, username: Option<String>, password: Option<String>) -> MqttClient {
+        let address = with_port(address, DEFAULT_PORT);
+        MqttClient { address, username, password, broker: None }
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    pub fn publish(&mut self, publication: &Publication) -> io::Result<()> {
+        let published = self.try_publish(publication);
+        if published.is_err() {
+            self.broker = None;
+        }
+        published
+    }
+
+    fn try_publish(&mut self, publication: &Publication) -> io::Result<()> {
+        if self.broker.is_none() {
+            let mut broker = open(&self.address)?;
+            broker.write_all(&connect(self.username.as_deref(), self.password.as_deref()))?;
+            let mut connack = [0; 4];
+            broker.read_exact(&mut connack)?;
+            if connack[0] != 0x20 || connack[3] != 0 {
+                let refused = format!("the broker refused the connection, code {}", connack[3]);
+                return Err(io::Error::new(io::ErrorKind::ConnectionRefused, refused));
+            }
+            self.broker = Some(broker);
+        }
+        let packet = publish(publication);
+        self.broker.as_mut().unwrap().write_all(&packet)
+    }
+
+    /// Publishes what's sent to the returned channel, `queue` messages at most waiting, from a
+    /// thread that ends when the channel is dropped.  Failures are logged once until a
+    /// publish works.
+    pub fn spawn(mut self, queue: usize) -> SyncSender<Publication> {
+        let (publications, pending) = sync_channel::<Publication>(queue);
+        thread::spawn(move || {
+            let mut failing = false;
+            for publication in pending.iter() {
+                match self.publish(&publication) {
+                    Ok(()) => failing = false,
+                    Err(e) => {
+                        if !failing {
+                            warn!("Could not publish to {}: {}", self.address, e);
+                        }
+                        failing = true;
+                    },
+                }
+            }
+        });
+        publications
+    }
+}
+
+/// A control packet of `kind` with `body` after the remaining length
+fn packet(kind: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![kind];
+    let mut remaining = body.len();
+    loop {
+        let mut byte = (remaining % 128) as u8;
+        remaining /= 128;
+        if remaining > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if remaining == 0 {
+            break;
+        }
+    }
+    packet.extend(body);
+    packet
+}
+
+/// A length prefixed UTF-8 string
+fn string(body: &mut Vec<u8>, text: &[u8]) {
+    body.extend(&(text.len() as u16).to_be_bytes());
+    body.extend(text);
+}
+
+/// CONNECT with a clean session
+fn connect(username: Option<&str>, password: Option<&str>) -> Vec<u8> {
+    let mut body = Vec::new();
+    string(&mut body, b"MQTT");
+    body.push(4); // protocol level
+    let mut flags = 0x02;
+    if username.is_some() {
+        flags |= 0x80;
+    }
+    if password.is_some() {
+        flags |= 0x40;
+    }
+    body.push(flags);
+    body.extend(&KEEP_ALIVE.to_be_bytes());
+    string(&mut body, format!("e-nguyen-{}", std::process::id()).as_bytes());
+    for field in username.iter().chain(password.iter()) {
+        string(&mut body, field.as_bytes());
+    }
+    packet(0x10, &body)
+}
+
+/// PUBLISH at most once
+fn publish(publication: &Publication) -> Vec<u8> {
+    let mut body = Vec::new();
+    string(&mut body, publication.topic.as_bytes());
+    body.extend(&publication.payload);
+    packet(0x30 | publication.retain as u8, &body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn messages_reach_the_broker() {
+        let message = |retain| Publication { topic: "a".into(), payload: b"{}".to_vec(), retain };
+        assert_eq!(publish(&message(false)), vec![0x30, 5, 0, 1, b'a', b'{', b'}']);
+        assert_eq!(publish(&message(true))[0], 0x31);
+        assert_eq!(&packet(0x30, &[0; 200])[..3], &[0x30, 0xc8, 0x01]);
+
+        let broker = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = broker.local_addr().unwrap().to_string();
+        let mut client = MqttClient::new(&address, Some("nguyen".into()), None);
+        let broker = thread::spawn(move || {
+            let (mut connection, _) = broker.accept().unwrap();
+            // both packets are short enough for a single byte of remaining length
+            let packet = |connection: &mut TcpStream| {
+                let mut header = [0; 2];
+                connection.read_exact(&mut header).unwrap();
+                let mut body = vec![0; usize::from(header[1])];
+                connection.read_exact(&mut body).unwrap();
+                (header[0], body)
+            };
+            let (kind, connect) = packet(&mut connection);
+            assert_eq!((kind, &connect[..8]), (0x10, &b"\x00\x04MQTT\x04\x82"[..]));
+            connection.write_all(&[0x20, 2, 0, 0]).unwrap();
+            packet(&mut connection)
+        });
+        client.publish(&message(true)).unwrap();
+        assert_eq!(broker.join().unwrap(), (0x31, b"\x00\x01a{}".to_vec()));
+        assert_eq!(MqttClient::new("broker", None, None).address(), "broker:1883");
+    }
+}
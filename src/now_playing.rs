@@ -0,0 +1,197 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! The now playing scene.  Only the track's title and artist, drifting slowly around the
+//! window and shifting hue with the music.
+
+use crate::bench::millis;
+use crate::errors::ENguyenError;
+use crate::features::AudioFeatures;
+use crate::metadata::{MetadataWatch, NowPlaying};
+use crate::rendering::{hue, TextBlock};
+use crate::scenes::{Scene, SceneFrame};
+
+use std::f32::consts::PI;
+use std::time::Duration;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+
+static TITLE_SIZE: f32 = 72.0;
+static ARTIST_SIZE: f32 = 40.0;
+/// Glyph advance as a share of the glyph size.  The overlay font is monospaced.
+static ADVANCE: f32 = 0.6;
+/// Pixels kept clear at the window's edges
+static MARGIN: f32 = 48.0;
+/// Drift cycles per second in silence and added at full scale
+static DRIFT_SPEED: f32 = 0.01;
+static LOUD_DRIFT: f32 = 0.03;
+/// Hue turns per second in silence, added at full scale mids and on each beat
+static HUE_SPEED: f32 = 0.005;
+static MID_HUE: f32 = 0.03;
+static BEAT_HUE: f32 = 0.02;
+static SATURATION: f32 = 0.6;
+static NOTHING_PLAYING: &str = "nothing playing";
+
+/// Where the text has drifted to and what color it has become
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Drift {
+    /// Cycles of drift, sped up by the music
+    time: f32,
+    /// Turns around the color wheel
+    hue: f32,
+}
+
+impl Drift {
+    fn update(&mut self, features: &AudioFeatures, elapsed: Duration) {
+        let seconds = millis(elapsed) as f32 / 1000.0;
+        let level = (features.rms[0] + features.rms[1]) / 2.0;
+        self.time += (DRIFT_SPEED + LOUD_DRIFT * level) * seconds;
+        self.hue += (HUE_SPEED + MID_HUE * features.bands[2]) * seconds;
+        if features.beat.onset {
+            self.hue += BEAT_HUE;
+        }
+        self.hue = self.hue.fract();
+    }
+
+    /// How far across the space left beside the text, from 0.0 to 1.0
+    fn position(&self) -> [f32; 2] {
+        // incommensurate rates so the path doesn't repeat
+        let x = (self.time * 2.0 * PI).sin();
+        let y = (self.time * 2.0 * PI * 0.731 + 1.0).sin();
+        [(x + 1.0) / 2.0, (y + 1.0) / 2.0]
+    }
+}
+
+/// Shows the track the window's `MetadataWatch` has
+pub struct NowPlayingScene {
+    watch: MetadataWatch,
+    drift: Drift,
+    window: Option<[f32; 2]>,
+}
+
+impl NowPlayingScene {
+    pub fn new(watch: MetadataWatch) -> NowPlayingScene {
+        NowPlayingScene { watch, drift: Drift::default(), window: None }
+    }
+}
+
+impl Scene for NowPlayingScene {
+    fn update(&mut self, features: &AudioFeatures, elapsed: Duration) {
+        self.drift.update(features, elapsed);
+    }
+
+    fn resize(&mut self, window: [f32; 2]) {
+        self.window = Some(window);
+    }
+
+    fn text(&mut self) -> Vec<TextBlock> {
+        let window = match self.window {
+            Some(window) => window,
+            None => return Vec::new(),
+        };
+        captions(self.watch.now_playing().as_ref(), &self.drift, window)
+    }
+
+    /// The captions are all there is
+    fn render(
+        &mut self,
+        cbb: AutoCommandBufferBuilder,
+        _frame: &SceneFrame,
+    ) -> Result<AutoCommandBufferBuilder, ENguyenError> {
+        Ok(cbb)
+    }
+}
+
+fn captions(track: Option<&NowPlaying>, drift: &Drift, dimensions: [f32; 2]) -> Vec<TextBlock> {
+    let mut lines = match track {
+        Some(track) => vec![(track.title.clone(), TITLE_SIZE), (track.artist(), ARTIST_SIZE)],
+        None => vec![(NOTHING_PLAYING.to_owned(), ARTIST_SIZE)],
+    };
+    lines.retain(|(text, _)| !text.is_empty());
+
+    // the block of lines drifts as one
+    let [width, height] = dimensions;
+    let block_width = lines
+        .iter()
+        .map(|(text, size)| text.chars().count() as f32 * size * ADVANCE)
+        .fold(0.0, f32::max);
+    let block_height: f32 = lines.iter().map(|(_, size)| size * 1.25).sum();
+    let [across, down] = drift.position();
+    let left = MARGIN + (width - block_width - 2.0 * MARGIN).max(0.0) * across;
+    let top = MARGIN + (height - block_height - 2.0 * MARGIN).max(0.0) * down;
+
+    let color = hue_color(drift.hue);
+    let mut baseline = top;
+    lines
+        .into_iter()
+        .map(|(text, size)| {
+            baseline += size;
+            let caption = TextBlock::new(text, [left, baseline], size, color);
+            baseline += size * 0.25;
+            caption
+        })
+        .collect()
+}
+
+/// A fully bright color at `turns` around the color wheel, softened to `SATURATION`
+fn hue_color(turns: f32) -> [f32; 4] {
+    let soften = |pure: f32| 1.0 - SATURATION + SATURATION * pure;
+    let [red, green, blue] = hue(turns);
+    [soften(red), soften(green), soften(blue), 1.0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn captions_stay_in_the_window() {
+        let track = NowPlaying { title: "Spectra".to_owned(), artists: vec!["Nguyen".to_owned()] };
+        let mut drift = Drift::default();
+        for step in 0..200 {
+            let features = AudioFeatures { rms: [0.5; 2], ..AudioFeatures::default() };
+            drift.update(&features, Duration::from_millis(step * 37));
+            let captions = captions(Some(&track), &drift, [1280.0, 720.0]);
+            assert_eq!(captions.len(), 2);
+            assert_eq!(captions[0].size, TITLE_SIZE);
+            for caption in &captions {
+                let [x, y] = caption.at;
+                let right = x + caption.text.len() as f32 * caption.size * ADVANCE;
+                assert!(x >= MARGIN && right <= 1280.0 - MARGIN, "{:?}", caption);
+                assert!(y - caption.size >= MARGIN && y <= 720.0 - MARGIN, "{:?}", caption);
+            }
+        }
+
+        let idle = captions(None, &drift, [1280.0, 720.0]);
+        assert_eq!(idle[0].text, NOTHING_PLAYING);
+        let untitled = NowPlaying { title: "Spectra".to_owned(), artists: Vec::new() };
+        assert_eq!(captions(Some(&untitled), &drift, [1280.0, 720.0]).len(), 1);
+    }
+
+    #[test]
+    fn hue_follows_the_music() {
+        let red = hue_color(0.0);
+        assert_eq!(red, [1.0, 1.0 - SATURATION, 1.0 - SATURATION, 1.0]);
+        let green = hue_color(1.0 / 3.0);
+        assert!((green[1] - 1.0).abs() < 1e-5 && green[0] < 0.5, "{:?}", green);
+
+        let mut drift = Drift::default();
+        let mut features = AudioFeatures::default();
+        drift.update(&features, Duration::from_secs(1));
+        let quiet = drift.hue;
+        features.beat.onset = true;
+        drift.update(&features, Duration::from_secs(1));
+        assert!((drift.hue - 2.0 * quiet - BEAT_HUE).abs() < 1e-6);
+    }
+}
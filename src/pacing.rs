@@ -0,0 +1,284 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+use crate::bench::{millis, Summary};
+
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Frames kept for the statistics.  About ten seconds at 60Hz.
+static PACING_WINDOW: usize = 600;
+/// Deadline when the fps isn't capped.  Winit doesn't report the monitor's refresh rate.
+pub static ASSUMED_REFRESH: Duration = Duration::from_micros(16_667);
+/// Render scales stepped down through while frames run late
+static QUALITY_STEPS: [f32; 4] = [1.0, 0.85, 0.7, 0.5];
+/// Frames judged together.  One second at 60Hz.
+static QUALITY_WINDOW: usize = 60;
+/// Late frames in a window that lower the quality
+static LATE_LIMIT: usize = 6;
+/// How long frames must stay on time before quality is raised again.  Doubles, up to the
+/// maximum, each time raising it makes frames late.
+static RAISE_AFTER: Duration = Duration::from_secs(3);
+static MAX_RAISE_AFTER: Duration = Duration::from_secs(48);
+
+struct FrameTiming {
+    cpu: Duration,
+    gpu_wait: Duration,
+    /// Since the previous frame started.  None for the first frame.
+    interval: Option<Duration>,
+}
+
+/// Per-frame timings of a render loop, and how many vsync deadlines it missed
+pub struct FramePacing {
+    recent: VecDeque<FrameTiming>,
+    frames: u64,
+    missed: u64,
+    last_start: Option<Instant>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PacingSummary {
+    pub frames: u64,
+    /// Deadlines that passed with no new frame, over all frames
+    pub missed_vsyncs: u64,
+    /// Building and submitting a frame, less `gpu_wait_ms`
+    pub cpu_ms: Summary,
+    /// Blocked waiting for a swapchain image, which the GPU and presentation release
+    pub gpu_wait_ms: Summary,
+    /// From one frame's start to the next
+    pub interval_ms: Summary,
+}
+
+impl FramePacing {
+    pub fn new() -> FramePacing {
+        FramePacing {
+            recent: VecDeque::with_capacity(PACING_WINDOW),
+            frames: 0,
+            missed: 0,
+            last_start: None,
+        }
+    }
+
+    /// Record a frame that started at `start`, took `total` including `gpu_wait`, and was due
+    /// `deadline` after the last one
+    pub fn record(
+        &mut self,
+        start: Instant,
+        total: Duration,
+        gpu_wait: Duration,
+        deadline: Duration,
+    ) {
+        let interval = self.last_start.map(|last| start.duration_since(last));
+        self.last_start = Some(start);
+        self.frames += 1;
+        self.missed += interval.map_or(0, |interval| missed_deadlines(interval, deadline));
+        if self.recent.len() == PACING_WINDOW {
+            self.recent.pop_front();
+        }
+        let cpu = if total > gpu_wait { total - gpu_wait } else { Duration::from_secs(0) };
+        self.recent.push_back(FrameTiming { cpu, gpu_wait, interval });
+    }
+
+    /// Totals since the first frame, statistics over the recent ones
+    pub fn summary(&self) -> PacingSummary {
+        let mut cpu: Vec<f64> = self.recent.iter().map(|t| millis(t.cpu)).collect();
+        let mut gpu_wait: Vec<f64> = self.recent.iter().map(|t| millis(t.gpu_wait)).collect();
+        let mut interval: Vec<f64> =
+            self.recent.iter().filter_map(|t| t.interval).map(millis).collect();
+        PacingSummary {
+            frames: self.frames,
+            missed_vsyncs: self.missed,
+            cpu_ms: Summary::of(&mut cpu),
+            gpu_wait_ms: Summary::of(&mut gpu_wait),
+            interval_ms: Summary::of(&mut interval),
+        }
+    }
+}
+
+impl Default for FramePacing {
+    fn default() -> FramePacing {
+        FramePacing::new()
+    }
+}
+
+/// Lowers the render scale while frames miss their deadlines and raises it again once they
+/// have been on time for a while
+pub struct QualityGovernor {
+    step: usize,
+    /// Whether each recent frame was late
+    late: VecDeque<bool>,
+    last_start: Option<Instant>,
+    changed: Option<Instant>,
+    /// The last change raised the quality
+    raised: bool,
+    raise_after: Duration,
+}
+
+impl QualityGovernor {
+    pub fn new() -> QualityGovernor {
+        QualityGovernor {
+            step: 0,
+            late: VecDeque::with_capacity(QUALITY_WINDOW),
+            last_start: None,
+            changed: None,
+            raised: false,
+            raise_after: RAISE_AFTER,
+        }
+    }
+
+    /// Share of the full resolution to render at
+    pub fn scale(&self) -> f32 {
+        QUALITY_STEPS[self.step]
+    }
+
+    /// Judge a frame that started at `start` and was due `deadline` after the last one.
+    /// The new scale if it changed.
+    pub fn record(&mut self, start: Instant, deadline: Duration) -> Option<f32> {
+        let interval = self.last_start.map(|last| start.duration_since(last));
+        self.last_start = Some(start);
+        if self.late.len() == QUALITY_WINDOW {
+            self.late.pop_front();
+        }
+        self.late.push_back(missed_deadlines(interval?, deadline) > 0);
+
+        let late = self.late.iter().filter(|&&late| late).count();
+        let settled = match self.changed {
+            Some(changed) => start.duration_since(changed) >= self.raise_after,
+            None => true,
+        };
+        if late >= LATE_LIMIT && self.step + 1 < QUALITY_STEPS.len() {
+            // a raise that didn't hold is tried again later
+            self.raise_after = if self.raised && !settled {
+                (self.raise_after * 2).min(MAX_RAISE_AFTER)
+            } else {
+                RAISE_AFTER
+            };
+            Some(self.change(self.step + 1, start, false))
+        } else if late == 0 && self.late.len() == QUALITY_WINDOW && self.step > 0 && settled {
+            Some(self.change(self.step - 1, start, true))
+        } else {
+            None
+        }
+    }
+
+    fn change(&mut self, step: usize, at: Instant, raised: bool) -> f32 {
+        self.step = step;
+        self.changed = Some(at);
+        self.raised = raised;
+        self.late.clear();
+        self.scale()
+    }
+}
+
+impl Default for QualityGovernor {
+    fn default() -> QualityGovernor {
+        QualityGovernor::new()
+    }
+}
+
+/// One line, short enough for the HUD
+impl fmt::Display for PacingSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "cpu {:.1}ms  gpu wait {:.1}ms  frame {:.1}ms p95 {:.1}ms  missed {}/{}",
+            self.cpu_ms.mean,
+            self.gpu_wait_ms.mean,
+            self.interval_ms.mean,
+            self.interval_ms.p95,
+            self.missed_vsyncs,
+            self.frames
+        )
+    }
+}
+
+/// Vsyncs that went by without a frame.  Up to half a deadline late still counts as on time.
+fn missed_deadlines(interval: Duration, deadline: Duration) -> u64 {
+    if deadline == Duration::from_secs(0) {
+        return 0;
+    }
+    let periods = (millis(interval) / millis(deadline)).round() as u64;
+    periods.saturating_sub(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missed_vsyncs_are_counted() {
+        let deadline = Duration::from_millis(16);
+        assert_eq!(missed_deadlines(Duration::from_millis(2), deadline), 0);
+        assert_eq!(missed_deadlines(Duration::from_millis(20), deadline), 0);
+        assert_eq!(missed_deadlines(Duration::from_millis(30), deadline), 1);
+        assert_eq!(missed_deadlines(Duration::from_millis(64), deadline), 3);
+        assert_eq!(missed_deadlines(Duration::from_millis(64), Duration::from_secs(0)), 0);
+    }
+
+    #[test]
+    fn frames_are_summarized() {
+        let mut pacing = FramePacing::new();
+        let start = Instant::now();
+        let deadline = Duration::from_millis(10);
+        let wait = Duration::from_millis(2);
+        for frame in 0..PACING_WINDOW as u32 + 10 {
+            // every tenth frame is a deadline late
+            let at = start + deadline * frame + deadline * (frame / 10);
+            pacing.record(at, Duration::from_millis(5), wait, deadline);
+        }
+        let summary = pacing.summary();
+        assert_eq!(summary.frames, PACING_WINDOW as u64 + 10);
+        assert_eq!(summary.missed_vsyncs, 60);
+        assert_eq!(summary.cpu_ms.max, 3.0);
+        assert_eq!(summary.gpu_wait_ms.mean, 2.0);
+        assert_eq!(summary.interval_ms.max, 20.0);
+        assert!(summary.to_string().contains("missed 60/610"));
+    }
+
+    #[test]
+    fn quality_follows_the_deadline() {
+        let deadline = Duration::from_millis(10);
+        let mut governor = QualityGovernor::new();
+        let mut at = Instant::now();
+        let mut frame = |governor: &mut QualityGovernor, interval: Duration| {
+            at += interval;
+            governor.record(at, deadline)
+        };
+        frame(&mut governor, deadline);
+        for _ in 0..LATE_LIMIT - 1 {
+            assert_eq!(frame(&mut governor, deadline * 3), None);
+        }
+        assert_eq!(frame(&mut governor, deadline * 3), Some(QUALITY_STEPS[1]));
+
+        // on time for a window isn't enough until the wait is over
+        for _ in 0..QUALITY_WINDOW {
+            assert_eq!(frame(&mut governor, deadline), None);
+        }
+        let mut raised = None;
+        for _ in 0..RAISE_AFTER.as_millis() / 10 {
+            raised = raised.or(frame(&mut governor, deadline));
+        }
+        assert_eq!(raised, Some(1.0));
+
+        // late again right after raising waits twice as long to raise next time
+        for _ in 0..LATE_LIMIT {
+            frame(&mut governor, deadline * 3);
+        }
+        assert_eq!(governor.scale(), QUALITY_STEPS[1]);
+        assert_eq!(governor.raise_after, RAISE_AFTER * 2);
+    }
+}
@@ -0,0 +1,504 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! Parameters that can change while running.  The settings window, IPC and any other
+//! controller write them through a `ParamBus`.  Compute and render threads hold a
+//! `ParamWatch` and pick up changes between frames.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+static MAX_SMOOTHING: f32 = 0.99;
+static MAX_GAIN: f32 = 64.0;
+/// Highest noise floor, leaving bands some range above it
+static MAX_NOISE_FLOOR: f32 = 0.9;
+/// Frames per second drawn at most in low power mode
+pub static LOW_POWER_FPS: u32 = 30;
+/// Render resolution as a share of the window's
+static MIN_RESOLUTION_SCALE: f32 = 0.25;
+static MAX_RESOLUTION_SCALE: f32 = 2.0;
+
+/// Color weights applied to the analysis texture's channels
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Palette {
+    Nguyen,
+    Ember,
+    Ice,
+}
+
+impl Palette {
+    /// Red, green and blue multipliers
+    pub fn weights(&self) -> [f32; 3] {
+        match self {
+            Palette::Nguyen => [1.0, 1.0, 1.0],
+            Palette::Ember => [1.8, 0.8, 0.3],
+            Palette::Ice => [0.4, 1.0, 1.6],
+        }
+    }
+
+    pub fn next(&self) -> Palette {
+        match self {
+            Palette::Nguyen => Palette::Ember,
+            Palette::Ember => Palette::Ice,
+            Palette::Ice => Palette::Nguyen,
+        }
+    }
+
+    /// Every palette, in the order `next` cycles through them
+    pub fn all() -> Vec<Palette> {
+        let mut all = vec![Palette::Nguyen];
+        let mut palette = Palette::Nguyen.next();
+        while palette != Palette::Nguyen {
+            all.push(palette);
+            palette = palette.next();
+        }
+        all
+    }
+}
+
+impl fmt::Display for Palette {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Palette::Nguyen => "nguyen",
+            Palette::Ember => "ember",
+            Palette::Ice => "ice",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Palette {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Palette, String> {
+        match s.trim() {
+            "nguyen" => Ok(Palette::Nguyen),
+            "ember" => Ok(Palette::Ember),
+            "ice" => Ok(Palette::Ice),
+            other => {
+                let choices = names(&Palette::all());
+                Err(format!("Unknown palette: {}.  Choose from {}", other, choices))
+            },
+        }
+    }
+}
+
+/// `all` as a list for messages
+fn names<T: fmt::Display>(all: &[T]) -> String {
+    all.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")
+}
+
+/// Which visualization the mez window draws
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scene {
+    Scroll,
+    /// Spectrogram below an oscilloscope and level meters
+    Studio,
+    /// VU and peak programme meters
+    Meters,
+    /// Left against right samples, as a stereo goniometer
+    Goniometer,
+    /// Piano keys and pitch classes lit by what is playing
+    Piano,
+    /// A tunnel pulsing with the sub-bass and beat
+    Tunnel,
+    /// Only the playing track's title and artist
+    NowPlaying,
+}
+
+impl Scene {
+    pub fn next(&self) -> Scene {
+        match self {
+            Scene::Scroll => Scene::Studio,
+            Scene::Studio => Scene::Meters,
+            Scene::Meters => Scene::Goniometer,
+            Scene::Goniometer => Scene::Piano,
+            Scene::Piano => Scene::Tunnel,
+            Scene::Tunnel => Scene::NowPlaying,
+            Scene::NowPlaying => Scene::Scroll,
+        }
+    }
+
+    /// Every scene, in the order `next` cycles through them
+    pub fn all() -> Vec<Scene> {
+        let mut all = vec![Scene::Scroll];
+        let mut scene = Scene::Scroll.next();
+        while scene != Scene::Scroll {
+            all.push(scene);
+            scene = scene.next();
+        }
+        all
+    }
+}
+
+impl fmt::Display for Scene {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Scene::Scroll => write!(f, "scroll"),
+            Scene::Studio => write!(f, "studio"),
+            Scene::Meters => write!(f, "meters"),
+            Scene::Goniometer => write!(f, "goniometer"),
+            Scene::Piano => write!(f, "piano"),
+            Scene::Tunnel => write!(f, "tunnel"),
+            Scene::NowPlaying => write!(f, "nowplaying"),
+        }
+    }
+}
+
+impl FromStr for Scene {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Scene, String> {
+        match s.trim() {
+            "scroll" => Ok(Scene::Scroll),
+            "studio" => Ok(Scene::Studio),
+            "meters" => Ok(Scene::Meters),
+            "goniometer" => Ok(Scene::Goniometer),
+            "piano" => Ok(Scene::Piano),
+            "tunnel" => Ok(Scene::Tunnel),
+            "nowplaying" => Ok(Scene::NowPlaying),
+            other => {
+                let choices = names(&Scene::all());
+                Err(format!("Unknown scene: {}.  Choose from {}", other, choices))
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Parameters {
+    /// Input gain applied before the FFT
+    pub gain: f32,
+    /// 0.0 shows every frame as-is, values toward 1.0 average spectra over time
+    pub smoothing: f32,
+    pub palette: Palette,
+    pub scene: Scene,
+    /// Frames per second the windows draw at most.  0 leaves it to vsync.
+    pub fps_cap: u32,
+    /// Saving power.  Analysis runs at half rate and frames are capped at `LOW_POWER_FPS`.
+    pub low_power: bool,
+    /// Scenes render at this share of the window's resolution.  Above 1.0 supersamples.
+    pub resolution_scale: f32,
+    /// Level of each of `AudioFeatures::bands` with nothing playing.  Bands are rescaled to
+    /// start above it.
+    #[serde(default)]
+    pub noise_floor: [f32; 4],
+}
+
+impl Default for Parameters {
+    fn default() -> Self {
+        Parameters {
+            gain: 1.0,
+            smoothing: 0.0,
+            palette: Palette::Nguyen,
+            scene: Scene::Scroll,
+            fps_cap: 0,
+            low_power: false,
+            resolution_scale: 1.0,
+            noise_floor: [0.0; 4],
+        }
+    }
+}
+
+impl Parameters {
+    /// Apply one change, clamping it into a usable range
+    pub fn apply(&mut self, change: ParamChange) {
+        match change {
+            ParamChange::Gain(gain) => self.gain = gain.max(0.0).min(MAX_GAIN),
+            ParamChange::Smoothing(s) => self.smoothing = s.max(0.0).min(MAX_SMOOTHING),
+            ParamChange::Palette(palette) => self.palette = palette,
+            ParamChange::Scene(scene) => self.scene = scene,
+            ParamChange::FpsCap(cap) => self.fps_cap = cap,
+            ParamChange::LowPower(low_power) => self.low_power = low_power,
+            ParamChange::ResolutionScale(scale) => {
+                self.resolution_scale = scale.max(MIN_RESOLUTION_SCALE).min(MAX_RESOLUTION_SCALE)
+            },
+            ParamChange::NoiseFloor(floor) => {
+                for (band, level) in self.noise_floor.iter_mut().zip(floor.iter()) {
+                    *band = level.max(0.0).min(MAX_NOISE_FLOOR);
+                }
+            },
+        }
+    }
+
+    /// How long a frame should take at most, if capped
+    pub fn frame_interval(&self) -> Option<Duration> {
+        let cap = match (self.fps_cap, self.low_power) {
+            (0, true) => LOW_POWER_FPS,
+            (cap, true) => cap.min(LOW_POWER_FPS),
+            (cap, false) => cap,
+        };
+        match cap {
+            0 => None,
+            cap => Some(Duration::from_secs(1) / cap),
+        }
+    }
+}
+
+/// A single edit, as sent by controllers.  Written as `<name> <value>`, such as `gain 1.5`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParamChange {
+    Gain(f32),
+    Smoothing(f32),
+    Palette(Palette),
+    Scene(Scene),
+    FpsCap(u32),
+    LowPower(bool),
+    ResolutionScale(f32),
+    /// Written as the four levels separated by commas
+    NoiseFloor([f32; 4]),
+}
+
+impl fmt::Display for ParamChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParamChange::Gain(gain) => write!(f, "gain {}", gain),
+            ParamChange::Smoothing(s) => write!(f, "smoothing {}", s),
+            ParamChange::Palette(palette) => write!(f, "palette {}", palette),
+            ParamChange::Scene(scene) => write!(f, "scene {}", scene),
+            ParamChange::FpsCap(cap) => write!(f, "fps_cap {}", cap),
+            ParamChange::LowPower(low_power) => write!(f, "low_power {}", low_power),
+            ParamChange::ResolutionScale(scale) => write!(f, "resolution_scale {}", scale),
+            ParamChange::NoiseFloor([a, b, c, d]) => {
+                write!(f, "noise_floor {},{},{},{}", a, b, c, d)
+            },
+        }
+    }
+}
+
+impl FromStr for ParamChange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ParamChange, String> {
+        let mut parts = s.split_whitespace();
+        let (name, value) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(name), Some(value), None) => (name, value),
+            _ => return Err(format!("Expected <parameter> <value>, got: {}", s.trim())),
+        };
+        let bad_value = || format!("Bad value for {}: {}", name, value);
+        match name {
+            "gain" => value.parse().map(ParamChange::Gain).map_err(|_| bad_value()),
+            "smoothing" => value.parse().map(ParamChange::Smoothing).map_err(|_| bad_value()),
+            "palette" => value.parse().map(ParamChange::Palette),
+            "scene" => value.parse().map(ParamChange::Scene),
+            "fps_cap" => value.parse().map(ParamChange::FpsCap).map_err(|_| bad_value()),
+            "low_power" => value.parse().map(ParamChange::LowPower).map_err(|_| bad_value()),
+            "resolution_scale" => {
+                value.parse().map(ParamChange::ResolutionScale).map_err(|_| bad_value())
+            },
+            "noise_floor" => {
+                let levels: Result<Vec<f32>, _> = value.split(',').map(str::parse).collect();
+                match levels.as_ref().map(Vec::as_slice) {
+                    Ok(&[a, b, c, d]) => Ok(ParamChange::NoiseFloor([a, b, c, d])),
+                    _ => Err(bad_value()),
+                }
+            },
+            other => Err(format!("Unknown parameter: {}", other)),
+        }
+    }
+}
+
+struct Published {
+    params: Parameters,
+    revision: u64,
+}
+
+/// Cloneable handle to the one set of live parameters
+#[derive(Clone)]
+pub struct ParamBus {
+    inner: Arc<(Mutex<Published>, Condvar)>,
+}
+
+impl Default for ParamBus {
+    fn default() -> Self {
+        ParamBus::new(Parameters::default())
+    }
+}
+
+impl ParamBus {
+    pub fn new(params: Parameters) -> ParamBus {
+        let published = Published { params, revision: 0 };
+        ParamBus { inner: Arc::new((Mutex::new(published), Condvar::new())) }
+    }
+
+    pub fn get(&self) -> Parameters {
+        self.inner.0.lock().unwrap().params
+    }
+
+    pub fn apply(&self, change: ParamChange) {
+        self.update(|params| params.apply(change));
+    }
+
+    /// Edit the parameters and wake every watcher
+    pub fn update<F: FnOnce(&mut Parameters)>(&self, edit: F) {
+        let (lock, changed) = &*self.inner;
+        let mut published = lock.lock().unwrap();
+        edit(&mut published.params);
+        published.revision += 1;
+        changed.notify_all();
+    }
+
+    /// A watcher that has already seen the current parameters
+    pub fn watch(&self) -> ParamWatch {
+        let seen = self.inner.0.lock().unwrap().revision;
+        ParamWatch { bus: self.clone(), seen }
+    }
+}
+
+/// Reader side of the bus.  Each watcher tracks which revision it last saw.
+pub struct ParamWatch {
+    bus: ParamBus,
+    seen: u64,
+}
+
+impl ParamWatch {
+    pub fn current(&self) -> Parameters {
+        self.bus.get()
+    }
+
+    /// The new parameters if anything changed since the last call.  Never blocks.
+    pub fn changed(&mut self) -> Option<Parameters> {
+        let published = self.bus.inner.0.lock().unwrap();
+        if published.revision == self.seen {
+            return None;
+        }
+        self.seen = published.revision;
+        Some(published.params)
+    }
+
+    /// Like `changed` but waits up to `timeout` for a change
+    pub fn wait(&mut self, timeout: Duration) -> Option<Parameters> {
+        let deadline = Instant::now() + timeout;
+        let (lock, changed) = &*self.bus.inner;
+        let mut published = lock.lock().unwrap();
+        while published.revision == self.seen {
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            published = changed.wait_timeout(published, deadline - now).unwrap().0;
+        }
+        self.seen = published.revision;
+        Some(published.params)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn watchers_see_changes_once() {
+        let bus = ParamBus::default();
+        let mut watch = bus.watch();
+        assert_eq!(watch.changed(), None);
+
+        bus.apply(ParamChange::Gain(2.0));
+        bus.apply(ParamChange::Palette(Palette::Ice));
+        let seen = watch.changed().unwrap();
+        assert_eq!(seen.gain, 2.0);
+        assert_eq!(seen.palette, Palette::Ice);
+        assert_eq!(watch.changed(), None);
+
+        // late watchers start from the current revision
+        let mut late = bus.watch();
+        assert_eq!(late.changed(), None);
+        assert_eq!(late.current().gain, 2.0);
+    }
+
+    #[test]
+    fn wait_wakes_on_update() {
+        let bus = ParamBus::default();
+        let mut watch = bus.watch();
+        assert_eq!(watch.wait(Duration::from_millis(10)), None);
+
+        let writer = bus.clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            writer.apply(ParamChange::FpsCap(30));
+        });
+        let seen = watch.wait(Duration::from_secs(5)).unwrap();
+        assert_eq!(seen.fps_cap, 30);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn changes_are_clamped() {
+        let mut params = Parameters::default();
+        params.apply(ParamChange::Smoothing(3.0));
+        assert_eq!(params.smoothing, MAX_SMOOTHING);
+        params.apply(ParamChange::Gain(-1.0));
+        assert_eq!(params.gain, 0.0);
+        assert_eq!(params.frame_interval(), None);
+        params.apply(ParamChange::FpsCap(50));
+        assert_eq!(params.frame_interval(), Some(Duration::from_millis(20)));
+        params.apply(ParamChange::LowPower(true));
+        assert_eq!(params.frame_interval(), Some(Duration::from_secs(1) / LOW_POWER_FPS));
+        params.apply(ParamChange::FpsCap(0));
+        assert_eq!(params.frame_interval(), Some(Duration::from_secs(1) / LOW_POWER_FPS));
+        params.apply(ParamChange::ResolutionScale(8.0));
+        assert_eq!(params.resolution_scale, MAX_RESOLUTION_SCALE);
+        params.apply(ParamChange::ResolutionScale(0.0));
+        assert_eq!(params.resolution_scale, MIN_RESOLUTION_SCALE);
+        params.apply(ParamChange::NoiseFloor([-1.0, 0.5, 1.0, 0.0]));
+        assert_eq!(params.noise_floor, [0.0, 0.5, MAX_NOISE_FLOOR, 0.0]);
+    }
+
+    #[test]
+    fn change_round_trip() {
+        let all = [
+            ParamChange::Gain(1.5),
+            ParamChange::Smoothing(0.25),
+            ParamChange::Palette(Palette::Ember),
+            ParamChange::Scene(Scene::Scroll),
+            ParamChange::Scene(Scene::Studio),
+            ParamChange::Scene(Scene::Meters),
+            ParamChange::Scene(Scene::Goniometer),
+            ParamChange::Scene(Scene::Piano),
+            ParamChange::Scene(Scene::Tunnel),
+            ParamChange::Scene(Scene::NowPlaying),
+            ParamChange::FpsCap(144),
+            ParamChange::LowPower(true),
+            ParamChange::ResolutionScale(0.5),
+            ParamChange::NoiseFloor([0.25, 0.125, 0.0, 0.5]),
+        ];
+        for change in all.iter() {
+            let parsed: ParamChange = change.to_string().parse().unwrap();
+            assert_eq!(&parsed, change);
+        }
+        assert!("gain loud".parse::<ParamChange>().is_err());
+        assert!("volume 11".parse::<ParamChange>().is_err());
+        assert!("gain".parse::<ParamChange>().is_err());
+        assert!("noise_floor 0.1,0.2".parse::<ParamChange>().is_err());
+    }
+
+    #[test]
+    fn unknown_names_list_the_choices() {
+        for scene in Scene::all() {
+            assert_eq!(scene.to_string().parse::<Scene>(), Ok(scene));
+        }
+        assert_eq!(Scene::all().len(), 7);
+        let unknown = "party".parse::<Scene>().unwrap_err();
+        assert!(unknown.ends_with("scroll, studio, meters, goniometer, piano, tunnel, nowplaying"));
+        let unknown = "neon".parse::<Palette>().unwrap_err();
+        assert!(unknown.ends_with("nguyen, ember, ice"));
+    }
+}
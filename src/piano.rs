@@ -0,0 +1,263 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! The piano scene.  Keys light up with the level of their pitch, and a strip of the twelve
+//! pitch classes above them shows the harmony regardless of octave.
+
+use crate::config::{ENguyenConfig, PianoConfig};
+use crate::errors::ENguyenError;
+use crate::features::{AudioFeatures, HIGHEST_NOTE, LOWEST_NOTE};
+use crate::geometry::quad;
+use crate::meters::LABEL_SIZE;
+use crate::rendering::{SolidPainter, TextBlock, XyVertex};
+use crate::scenes::{labelled, Scene, SceneFrame};
+
+use log::warn;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DynamicState};
+
+static PITCH_CLASSES: [&str; 12] =
+    ["c", "c#", "d", "d#", "e", "f", "f#", "g", "g#", "a", "a#", "b"];
+/// Keyboard and chroma strip edges, in window coordinates
+static LEFT: f32 = -0.95;
+static RIGHT: f32 = 0.95;
+static KEYBOARD_TOP: f32 = 0.2;
+static KEYBOARD_BOTTOM: f32 = 0.8;
+static BLACK_BOTTOM: f32 = 0.55;
+static CHROMA_TOP: f32 = -0.8;
+static CHROMA_BOTTOM: f32 = -0.3;
+/// Shares of a white key's width
+static BLACK_WIDTH: f32 = 0.6;
+static KEY_GAP: f32 = 0.1;
+/// Share of a chroma cell's width left between cells
+static CELL_GAP: f32 = 0.1;
+/// Brightness steps.  Keys in the same step share a draw.
+static LEVELS: usize = 8;
+static WHITE_COLOR: [f32; 4] = [0.8, 0.8, 0.8, 1.0];
+static BLACK_COLOR: [f32; 4] = [0.12, 0.12, 0.12, 1.0];
+static CELL_COLOR: [f32; 4] = [0.15, 0.15, 0.15, 1.0];
+static LIT_COLOR: [f32; 4] = [0.0, 0.906, 0.702, 1.0];
+
+/// Keys drawn, from the `[piano]` config section
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyRange {
+    pub lowest: u8,
+    pub highest: u8,
+}
+
+impl KeyRange {
+    /// Ranges off the piano are replaced with a warning
+    pub fn from_config(config: &ENguyenConfig) -> KeyRange {
+        let (lowest, highest) = (config.piano.lowest_note, config.piano.highest_note);
+        if lowest < LOWEST_NOTE || highest > HIGHEST_NOTE || lowest >= highest {
+            let range = format!("piano notes must be {} to {}", LOWEST_NOTE, HIGHEST_NOTE);
+            warn!("{}, lowest first.  Using the defaults.", range);
+            let defaults = PianoConfig::default();
+            return KeyRange { lowest: defaults.lowest_note, highest: defaults.highest_note };
+        }
+        KeyRange { lowest, highest }
+    }
+}
+
+/// The keyboard and chroma strip with their labels
+pub struct PianoScene {
+    keys: KeyRange,
+    window: Option<[f32; 2]>,
+}
+
+impl PianoScene {
+    pub fn new(keys: KeyRange) -> PianoScene {
+        PianoScene { keys, window: None }
+    }
+}
+
+impl Scene for PianoScene {
+    fn resize(&mut self, window: [f32; 2]) {
+        self.window = Some(window);
+    }
+
+    fn text(&mut self) -> Vec<TextBlock> {
+        let keys = self.keys;
+        self.window.map_or_else(Vec::new, |window| labelled(labels(window, keys)))
+    }
+
+    fn render(
+        &mut self,
+        cbb: AutoCommandBufferBuilder,
+        frame: &SceneFrame,
+    ) -> Result<AutoCommandBufferBuilder, ENguyenError> {
+        draw(frame.painter, cbb, frame.state, frame.features, self.keys)
+    }
+}
+
+/// Where a key is drawn
+#[derive(Debug, PartialEq)]
+struct Key {
+    note: u8,
+    black: bool,
+    left: f32,
+    right: f32,
+}
+
+/// Records the keyboard and chroma strip over the whole of `state`'s viewport
+pub fn draw(
+    painter: &SolidPainter,
+    mut cbb: AutoCommandBufferBuilder,
+    state: &DynamicState,
+    features: &AudioFeatures,
+    range: KeyRange,
+) -> Result<AutoCommandBufferBuilder, ENguyenError> {
+    let keys = layout(range);
+    // black keys are drawn over the white ones
+    for (black, base, bottom) in
+        [(false, WHITE_COLOR, KEYBOARD_BOTTOM), (true, BLACK_COLOR, BLACK_BOTTOM)].iter()
+    {
+        let mut steps = vec![Vec::new(); LEVELS];
+        for key in keys.iter().filter(|key| key.black == *black) {
+            let level = features.notes.get(usize::from(key.note - LOWEST_NOTE));
+            let quad = quad(key.left, KEYBOARD_TOP, key.right, *bottom);
+            steps[step(level.copied().unwrap_or(0.0))].extend_from_slice(&quad);
+        }
+        cbb = draw_steps(painter, cbb, state, steps, *base)?;
+    }
+
+    let mut steps = vec![Vec::new(); LEVELS];
+    for (class, level) in features.chroma.iter().enumerate() {
+        let (left, right) = cell(class);
+        steps[step(*level)].extend_from_slice(&quad(left, CHROMA_TOP, right, CHROMA_BOTTOM));
+    }
+    draw_steps(painter, cbb, state, steps, CELL_COLOR)
+}
+
+fn draw_steps(
+    painter: &SolidPainter,
+    mut cbb: AutoCommandBufferBuilder,
+    state: &DynamicState,
+    steps: Vec<Vec<XyVertex>>,
+    base: [f32; 4],
+) -> Result<AutoCommandBufferBuilder, ENguyenError> {
+    for (step, vertices) in steps.into_iter().enumerate() {
+        let lit = step as f32 / (LEVELS - 1) as f32;
+        let mut color = base;
+        for (channel, target) in color.iter_mut().zip(LIT_COLOR.iter()) {
+            *channel += (target - *channel) * lit;
+        }
+        cbb = painter.triangles(cbb, state, vertices, color)?;
+    }
+    Ok(cbb)
+}
+
+/// Brightness step of a level from 0.0 to 1.0
+fn step(level: f32) -> usize {
+    (level.clamp(0.0, 1.0) * (LEVELS - 1) as f32).round() as usize
+}
+
+fn is_black(note: u8) -> bool {
+    [1, 3, 6, 8, 10].contains(&(note % 12))
+}
+
+/// White keys share the width evenly.  Black keys straddle the edge of the white key below.
+fn layout(range: KeyRange) -> Vec<Key> {
+    let whites = (range.lowest..=range.highest).filter(|note| !is_black(*note)).count();
+    let white_width = (RIGHT - LEFT) / whites as f32;
+    let mut x = LEFT;
+    let mut keys = Vec::new();
+    for note in range.lowest..=range.highest {
+        if is_black(note) {
+            let half = white_width * BLACK_WIDTH / 2.0;
+            keys.push(Key { note, black: true, left: x - half, right: x + half });
+        } else {
+            let gap = white_width * KEY_GAP / 2.0;
+            keys.push(Key { note, black: false, left: x + gap, right: x + white_width - gap });
+            x += white_width;
+        }
+    }
+    keys
+}
+
+/// Left and right edges of a pitch class's cell in the chroma strip
+fn cell(class: usize) -> (f32, f32) {
+    let width = (RIGHT - LEFT) / 12.0;
+    let left = LEFT + width * class as f32;
+    (left + width * CELL_GAP / 2.0, left + width * (1.0 - CELL_GAP / 2.0))
+}
+
+/// Pitch class names under the chroma strip and octave names under each C, with where their
+/// baselines start in a window of `dimensions` pixels
+pub fn labels(dimensions: [f32; 2], range: KeyRange) -> Vec<(String, [f32; 2])> {
+    let [width, height] = dimensions;
+    let pixels = |x: f32, y: f32| [(x + 1.0) / 2.0 * width, (y + 1.0) / 2.0 * height];
+    let mut labels: Vec<(String, [f32; 2])> = PITCH_CLASSES
+        .iter()
+        .enumerate()
+        .map(|(class, name)| {
+            let [x, y] = pixels(cell(class).0, CHROMA_BOTTOM);
+            (name.to_string(), [x, y + LABEL_SIZE * 1.5])
+        })
+        .collect();
+    for key in layout(range).iter().filter(|key| key.note % 12 == 0) {
+        let [x, y] = pixels(key.left, KEYBOARD_BOTTOM);
+        let octave = i32::from(key.note) / 12 - 1;
+        labels.push((format!("c{}", octave), [x, y + LABEL_SIZE * 1.5]));
+    }
+    labels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(lowest: u8, highest: u8) -> KeyRange {
+        let mut config = ENguyenConfig::default();
+        config.piano = PianoConfig { lowest_note: lowest, highest_note: highest };
+        KeyRange::from_config(&config)
+    }
+
+    #[test]
+    fn key_range_is_validated() {
+        assert_eq!(range(48, 72), KeyRange { lowest: 48, highest: 72 });
+        let defaults = range(36, 96);
+        assert_eq!(range(0, 72), defaults);
+        assert_eq!(range(60, 120), defaults);
+        assert_eq!(range(72, 48), defaults);
+    }
+
+    #[test]
+    fn keys_are_laid_out() {
+        // C4 to C5 is eight white keys and five black ones
+        let keys = layout(range(60, 72));
+        assert_eq!(keys.len(), 13);
+        assert_eq!(keys.iter().filter(|key| key.black).count(), 5);
+        let white_width = (RIGHT - LEFT) / 8.0;
+        assert!((keys[0].left - (LEFT + white_width * KEY_GAP / 2.0)).abs() < 1e-6);
+        // C#4 straddles the edge between C4 and D4
+        let c_sharp = &keys[1];
+        assert!(c_sharp.black);
+        assert!(((c_sharp.left + c_sharp.right) / 2.0 - (LEFT + white_width)).abs() < 1e-6);
+        assert!((keys[12].right - (RIGHT - white_width * KEY_GAP / 2.0)).abs() < 1e-6);
+
+        assert_eq!(step(0.0), 0);
+        assert_eq!(step(1.0), LEVELS - 1);
+        assert_eq!(step(2.0), LEVELS - 1);
+    }
+
+    #[test]
+    fn notes_are_labelled() {
+        let labels = labels([1000.0, 500.0], range(36, 96));
+        assert_eq!(labels.len(), 12 + 6);
+        assert_eq!(labels[9].0, "a");
+        assert_eq!(labels[12].0, "c2");
+        assert_eq!(labels[17].0, "c7");
+    }
+}
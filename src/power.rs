@@ -0,0 +1,189 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! Battery awareness.  Reads the kernel's power supply class, the same files UPower reads,
+//! and decides when to save power.
+
+use crate::config::{PowerConfig, PowerMode};
+
+use log::info;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+static SYSFS_POWER: &str = "/sys/class/power_supply";
+/// How often the power supply is checked
+static POWER_POLL: Duration = Duration::from_secs(10);
+
+/// What the machine is running on
+#[derive(Clone, Debug, PartialEq)]
+pub struct PowerState {
+    pub on_battery: bool,
+    /// Percent of the batteries' charge, if there are any
+    pub charge: Option<f32>,
+}
+
+impl PowerState {
+    /// None on machines without a power supply class, such as most desktops in containers
+    pub fn read(root: &Path) -> Option<PowerState> {
+        let mut mains_online = None;
+        let mut batteries = 0;
+        let mut discharging = false;
+        let mut charges = Vec::new();
+        for entry in fs::read_dir(root).ok()?.filter_map(Result::ok) {
+            let supply = entry.path();
+            match attribute(&supply, "type").as_ref().map(String::as_str) {
+                Some("Mains") | Some("USB") => {
+                    let online = attribute(&supply, "online").map_or(false, |value| value == "1");
+                    mains_online = Some(mains_online.unwrap_or(false) || online);
+                },
+                Some("Battery") => {
+                    batteries += 1;
+                    let status = attribute(&supply, "status");
+                    discharging |= status.map_or(false, |status| status == "Discharging");
+                    if let Some(capacity) = attribute(&supply, "capacity") {
+                        charges.extend(capacity.parse::<f32>().ok());
+                    }
+                },
+                _ => {},
+            }
+        }
+        let charge = if charges.is_empty() {
+            None
+        } else {
+            Some(charges.iter().sum::<f32>() / charges.len() as f32)
+        };
+        // mains adapters know best.  Without one, trust the battery's own status.
+        let on_battery = batteries > 0 && mains_online.map_or(discharging, |online| !online);
+        Some(PowerState { on_battery, charge })
+    }
+
+    /// Whether `config` asks to save power in this state
+    pub fn low_power(&self, config: &PowerConfig) -> bool {
+        match config.mode {
+            PowerMode::Always => true,
+            PowerMode::Never => false,
+            PowerMode::Auto => {
+                self.on_battery && self.charge.map_or(true, |charge| charge <= config.battery_below)
+            },
+        }
+    }
+}
+
+fn attribute(supply: &Path, name: &str) -> Option<String> {
+    fs::read_to_string(supply.join(name)).ok().map(|value| value.trim().to_owned())
+}
+
+/// Checks the power supply every so often and reports when low power mode should change
+pub struct PowerMonitor {
+    root: PathBuf,
+    checked: Option<Instant>,
+    low_power: bool,
+}
+
+impl PowerMonitor {
+    pub fn new() -> PowerMonitor {
+        PowerMonitor::with_root(PathBuf::from(SYSFS_POWER))
+    }
+
+    /// Reads power supplies from `root` instead of sysfs
+    pub fn with_root(root: PathBuf) -> PowerMonitor {
+        PowerMonitor { root, checked: None, low_power: false }
+    }
+
+    /// The new low power setting if it changed.  Checks at most every `POWER_POLL`.
+    pub fn poll(&mut self, config: &PowerConfig, now: Instant) -> Option<bool> {
+        if let Some(checked) = self.checked {
+            if now.duration_since(checked) < POWER_POLL {
+                return None;
+            }
+        }
+        self.checked = Some(now);
+        let state = PowerState::read(&self.root);
+        let low_power = match &state {
+            Some(state) => state.low_power(config),
+            None => config.mode == PowerMode::Always,
+        };
+        if low_power == self.low_power {
+            return None;
+        }
+        self.low_power = low_power;
+        info!("Low power mode {} ({:?})", if low_power { "on" } else { "off" }, state);
+        Some(low_power)
+    }
+}
+
+impl Default for PowerMonitor {
+    fn default() -> PowerMonitor {
+        PowerMonitor::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    fn supply(root: &Path, name: &str, attributes: &[(&str, &str)]) {
+        let dir = root.join(name);
+        fs::create_dir_all(&dir).unwrap();
+        for (attribute, value) in attributes {
+            fs::write(dir.join(attribute), format!("{}\n", value)).unwrap();
+        }
+    }
+
+    #[test]
+    fn batteries_are_read() {
+        let root = env::temp_dir().join(format!("e-nguyen-power-{}", process::id()));
+        let _ = fs::remove_dir_all(&root);
+        supply(&root, "AC", &[("type", "Mains"), ("online", "0")]);
+        supply(&root, "BAT0", &[("type", "Battery"), ("status", "Unknown"), ("capacity", "40")]);
+        let state = PowerState::read(&root).unwrap();
+        assert_eq!(state, PowerState { on_battery: true, charge: Some(40.0) });
+
+        let mut config = PowerConfig::default();
+        assert!(state.low_power(&config));
+        config.battery_below = 30.0;
+        assert!(!state.low_power(&config));
+        config.mode = PowerMode::Always;
+        let plugged_in = PowerState { on_battery: false, charge: Some(40.0) };
+        assert!(plugged_in.low_power(&config));
+
+        supply(&root, "AC", &[("online", "1")]);
+        assert!(!PowerState::read(&root).unwrap().on_battery);
+        fs::remove_dir_all(&root).unwrap();
+
+        let desktop = PowerState::read(&root.join("missing"));
+        assert_eq!(desktop, None);
+    }
+
+    #[test]
+    fn changes_are_reported_once() {
+        let root = env::temp_dir().join(format!("e-nguyen-monitor-{}", process::id()));
+        let _ = fs::remove_dir_all(&root);
+        supply(&root, "BAT1", &[("type", "Battery"), ("status", "Discharging")]);
+        let config = PowerConfig::default();
+        let mut monitor = PowerMonitor::with_root(root.clone());
+        let start = Instant::now();
+        assert_eq!(monitor.poll(&config, start), Some(true));
+        assert_eq!(monitor.poll(&config, start + POWER_POLL), None);
+
+        supply(&root, "BAT1", &[("status", "Charging")]);
+        assert_eq!(monitor.poll(&config, start + POWER_POLL / 2 * 3), None);
+        assert_eq!(monitor.poll(&config, start + POWER_POLL * 2), Some(false));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
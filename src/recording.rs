@@ -0,0 +1,257 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! Raw captured audio teed into WAV files while the visualizer keeps drawing it, to keep the
+//! sound behind an interesting visual and to replay analysis problems.
+
+use crate::errors::ENguyenError;
+use crate::logging::data_dir;
+
+use byteorder::{LittleEndian as Le, WriteBytesExt};
+use log::{info, warn};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static RECORDINGS_DIR: &str = "recordings";
+/// Interleaved stereo, as the ring holds it
+static CHANNELS: u16 = 2;
+static SAMPLE_BYTES: u16 = 2;
+static FRAME_BYTES: u64 = (CHANNELS * SAMPLE_BYTES) as u64;
+/// Bytes before the samples
+static HEADER_LEN: u32 = 44;
+/// The most sample bytes the 32 bit sizes in the header can count, in whole frames.  About
+/// six hours at 48kHz.
+static MAX_DATA_LEN: u64 = (u32::MAX - (HEADER_LEN - 8)) as u64 / FRAME_BYTES * FRAME_BYTES;
+
+/// Starts and stops recording.  Clones share one recording, so a hotkey or remote command can
+/// start what the analysis thread writes.
+#[derive(Clone, Default)]
+pub struct Recorder {
+    state: Arc<Mutex<RecorderState>>,
+}
+
+#[derive(Default)]
+struct RecorderState {
+    /// Where to record, while recording
+    path: Option<PathBuf>,
+    /// Opened once the capture's rate is known
+    writer: Option<WavWriter>,
+}
+
+impl Recorder {
+    /// Starts recording into a new file under the data directory, or stops.  The file
+    /// started, if any.
+    pub fn toggle(&self) -> Option<PathBuf> {
+        if self.recording().is_some() {
+            self.stop();
+            None
+        } else {
+            let stamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+            let name = format!("e-nguyen-{}.wav", stamp.as_secs());
+            let path = data_dir().join(RECORDINGS_DIR).join(name);
+            self.start(path.clone());
+            Some(path)
+        }
+    }
+
+    /// Record into `path`, from the next audio captured
+    pub fn start(&self, path: PathBuf) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(writer) = state.writer.take() {
+            finish(writer);
+        }
+        info!("Recording to {}", path.display());
+        state.path = Some(path);
+    }
+
+    /// Finishes the file being recorded
+    pub fn stop(&self) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(writer) = state.writer.take() {
+            finish(writer);
+        }
+        if let Some(path) = state.path.take() {
+            info!("Recorded {}", path.display());
+        }
+    }
+
+    /// Where audio is being recorded
+    pub fn recording(&self) -> Option<PathBuf> {
+        self.state.lock().unwrap().path.clone()
+    }
+
+    /// Appends interleaved stereo `samples` captured at `rate` frames a second, if recording.
+    /// A file that can't be written stops the recording.
+    pub fn tee(&self, samples: &[i16], rate: u32) {
+        let mut state = self.state.lock().unwrap();
+        let path = match &state.path {
+            Some(path) => path.clone(),
+            None => return,
+        };
+        if state.writer.is_none() {
+            match WavWriter::create(&path, rate) {
+                Ok(writer) => state.writer = Some(writer),
+                Err(e) => {
+                    warn!("Not recording: {}", e);
+                    state.path = None;
+                    return;
+                },
+            }
+        }
+        let written = state.writer.as_mut().map(|writer| writer.write(samples));
+        let stop = match written {
+            Some(Ok(true)) | None => false,
+            Some(Ok(false)) => {
+                warn!("Recording stopped: WAV files can't hold more than 4GiB");
+                true
+            },
+            Some(Err(e)) => {
+                let e = ENguyenError::audio("WAV write failed").caused_by(e);
+                warn!("Recording stopped: {}", e);
+                true
+            },
+        };
+        if stop {
+            state.path = None;
+            if let Some(writer) = state.writer.take() {
+                finish(writer);
+            }
+        }
+    }
+}
+
+fn finish(writer: WavWriter) {
+    if let Err(e) = writer.finish() {
+        warn!("Recording not finished: {}", e);
+    }
+}
+
+/// 16 bit stereo PCM.  The sizes in the header are written once the samples are.
+struct WavWriter {
+    out: BufWriter<File>,
+    data_len: u64,
+    /// Sample bytes the file stops taking at
+    limit: u64,
+}
+
+impl WavWriter {
+    fn create(path: &Path, rate: u32) -> Result<WavWriter, ENguyenError> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| {
+                ENguyenError::audio(format!("Can't create {}", dir.display())).caused_by(e)
+            })?;
+        }
+        let file = File::create(path).map_err(|e| {
+            ENguyenError::audio(format!("Can't create {}", path.display())).caused_by(e)
+        })?;
+        let mut writer = WavWriter { out: BufWriter::new(file), data_len: 0, limit: MAX_DATA_LEN };
+        writer
+            .header(rate)
+            .map_err(|e| ENguyenError::audio("WAV header write failed").caused_by(e))?;
+        Ok(writer)
+    }
+
+    fn header(&mut self, rate: u32) -> io::Result<()> {
+        let block = CHANNELS * SAMPLE_BYTES;
+        self.out.write_all(b"RIFF")?;
+        self.out.write_u32::<Le>(HEADER_LEN - 8)?;
+        self.out.write_all(b"WAVEfmt ")?;
+        self.out.write_u32::<Le>(16)?;
+        self.out.write_u16::<Le>(1)?; // PCM
+        self.out.write_u16::<Le>(CHANNELS)?;
+        self.out.write_u32::<Le>(rate)?;
+        self.out.write_u32::<Le>(rate * u32::from(block))?;
+        self.out.write_u16::<Le>(block)?;
+        self.out.write_u16::<Le>(SAMPLE_BYTES * 8)?;
+        self.out.write_all(b"data")?;
+        self.out.write_u32::<Le>(0)
+    }
+
+    /// Whether all of `samples` fit.  Those past the limit are left out.
+    fn write(&mut self, samples: &[i16]) -> io::Result<bool> {
+        let room = ((self.limit - self.data_len) / u64::from(SAMPLE_BYTES)) as usize;
+        let fits = samples.len().min(room);
+        for sample in &samples[..fits] {
+            self.out.write_i16::<Le>(*sample)?;
+        }
+        self.data_len += (fits * SAMPLE_BYTES as usize) as u64;
+        Ok(fits == samples.len())
+    }
+
+    /// Fills in the sizes
+    fn finish(mut self) -> Result<(), ENguyenError> {
+        let sizes = |out: &mut BufWriter<File>, data_len: u32| -> io::Result<()> {
+            out.seek(SeekFrom::Start(4))?;
+            out.write_u32::<Le>(HEADER_LEN - 8 + data_len)?;
+            out.seek(SeekFrom::Start(u64::from(HEADER_LEN) - 4))?;
+            out.write_u32::<Le>(data_len)?;
+            out.flush()
+        };
+        sizes(&mut self.out, self.data_len as u32)
+            .map_err(|e| ENguyenError::audio("WAV sizes write failed").caused_by(e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recording_writes_a_playable_wav() {
+        let path = std::env::temp_dir().join(format!("e-nguyen-{}.wav", std::process::id()));
+        let recorder = Recorder::default();
+        recorder.tee(&[1, 2], 48000);
+        assert!(recorder.recording().is_none());
+
+        recorder.start(path.clone());
+        recorder.tee(&[1, -1, 2, -2], 48000);
+        recorder.tee(&[3, -3], 48000);
+        recorder.stop();
+        recorder.tee(&[4, -4], 48000);
+
+        let wav = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(wav.len(), 44 + 12);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(&wav[4..8], &(36u32 + 12).to_le_bytes());
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(&wav[22..24], &2u16.to_le_bytes());
+        assert_eq!(&wav[24..28], &48000u32.to_le_bytes());
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(&wav[40..44], &12u32.to_le_bytes());
+        assert_eq!(&wav[44..48], &[1, 0, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn recordings_stop_at_the_size_limit() {
+        let path = std::env::temp_dir().join(format!("e-nguyen-full-{}.wav", std::process::id()));
+        let mut writer = WavWriter::create(&path, 48000).unwrap();
+        writer.limit = 8;
+        assert!(writer.write(&[1, -1]).unwrap());
+        assert!(!writer.write(&[2, -2, 3, -3]).unwrap());
+        assert!(!writer.write(&[4, -4]).unwrap());
+        writer.finish().unwrap();
+
+        let wav = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(wav.len(), 44 + 8);
+        assert_eq!(&wav[4..8], &(36u32 + 8).to_le_bytes());
+        assert_eq!(&wav[40..44], &8u32.to_le_bytes());
+        assert_eq!(&wav[48..52], &[2, 0, 0xfe, 0xff]);
+    }
+}
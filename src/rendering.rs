@@ -13,8 +13,31 @@
 
 // Copyright 2019 E-Nguyen Developers.
 
-use crate::errors::{FrameError, VulkanoError};
-use crate::ewin::SwapWindow;
+//! Drawing shared by the windows.  Shaders blend and sum in linear light, and windows present
+//! to sRGB swapchain formats so only presenting encodes for the display.  Analysis levels are
+//! already linear.  Colors picked by eye, in config, palettes and shader constants, are sRGB
+//! and go through `to_linear` or its GLSL twin in `shaders/lib/color.glsl` before drawing.
+
+use crate::errors::ENguyenError;
+use crate::ewin::multisampled_image;
+
+use rusttype::{point, Font, Scale};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::Arc;
+use vulkano::buffer::{BufferAccess, CpuBufferPool};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DynamicState};
+use vulkano::descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet};
+use vulkano::device::{Device, Queue};
+use vulkano::format::Format;
+use vulkano::framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract, Subpass};
+use vulkano::image::{AttachmentImage, ImageAccess, ImageUsage};
+use vulkano::pipeline::blend::{AttachmentBlend, BlendFactor};
+use vulkano::pipeline::viewport::Viewport;
+use vulkano::pipeline::{GraphicsPipeline, GraphicsPipelineAbstract};
+use vulkano::sampler::Filter;
+use vulkano::sync::GpuFuture;
+use vulkano_glyph::{GlyphBrush, Section};
 
 pub mod placeholder_vsm {
     vulkano_shaders::shader! {
@@ -40,7 +63,8 @@ pub mod placeholder_fsm {
 layout(location = 0) out vec4 f_color;
 
 void main() {
-    f_color = vec4(0.1, 0.2, 0.3, 1.0);
+    // 0.1, 0.2, 0.3 in sRGB
+    f_color = vec4(0.01, 0.0331, 0.0732, 1.0);
 }
 "
     }
@@ -80,7 +104,7 @@ void main() {
     }
 }
 
-pub mod uv_scroll_fsm {
+pub mod layer_fsm {
     vulkano_shaders::shader! {
     ty: "fragment",
         src: "
@@ -88,18 +112,60 @@ pub mod uv_scroll_fsm {
 
 layout(location = 0) in vec2 tex_coords;
 layout(location = 0) out vec4 f_color;
+
 layout(set = 0, binding = 0) uniform sampler2D tex;
 layout (push_constant) uniform PushConstant {
-    float offset_fac;
-} scroll;
+    float brightness;
+} layer;
 
 void main() {
-    vec2 scrolled_coords = vec2(scroll.offset_fac - float(tex_coords.x), tex_coords.y);
-    f_color = texture(tex, scrolled_coords);
+    vec4 color = texture(tex, tex_coords);
+    f_color = vec4(color.rgb * layer.brightness, color.a);
 }"
     }
 }
 
+pub mod uv_scroll_fsm {
+    include!(concat!(env!("OUT_DIR"), "/uv_scroll.frag.rs"));
+}
+
+pub mod tunnel_fsm {
+    include!(concat!(env!("OUT_DIR"), "/tunnel.frag.rs"));
+}
+
+pub mod xy_vsm {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "
+#version 450
+
+layout(location = 0) in vec2 position;
+
+void main() {
+    gl_Position = vec4(position, 0.0, 1.0);
+}"
+    }
+}
+
+pub mod xy_point_vsm {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "
+#version 450
+
+layout(location = 0) in vec2 position;
+
+void main() {
+    gl_Position = vec4(position, 0.0, 1.0);
+    gl_PointSize = 2.0;
+}"
+    }
+}
+
+pub mod solid_fsm {
+    include!(concat!(env!("OUT_DIR"), "/solid.frag.rs"));
+}
+
 pub mod diag_grad_vsm {
     vulkano_shaders::shader! {
         ty: "vertex",
@@ -131,8 +197,9 @@ pub mod diag_grad_fsm {
 layout(location = 0) in vec2 pos;
 layout(location = 0) out vec4 f_color;
 
-const vec4 blue = vec4(0.002, 0.241, 0.5, 1.0);
-const vec4 green = vec4(0, 0.906, 0.702, 1.0);
+// linear light.  0.002, 0.241, 0.5 and 0.0, 0.906, 0.702 in sRGB.
+const vec4 blue = vec4(0.0002, 0.0473, 0.214, 1.0);
+const vec4 green = vec4(0.0, 0.7993, 0.4508, 1.0);
 
 void main() {
     // distance from top left == 2.83 -> full mixture
@@ -166,28 +233,680 @@ pub struct XyVertex {
 }
 vulkano::impl_vertex!(XyVertex, position);
 
-pub trait Frame {
-    fn size_dependent_setup(&mut self) -> Result<(), VulkanoError>;
-    fn recreate_swapchain(&mut self, context: &SwapWindow) -> Result<(), VulkanoError>;
-    fn render_one(&mut self, context: &SwapWindow, recreate: &bool) -> Result<(), VulkanoError>;
+/// What every scene shader can read about the frame being drawn, at set 0, binding 0.
+/// Shaders under `shaders/` declare it with `#include "lib/frame.glsl"`.  Members are only
+/// ever added at the end so shaders written against it keep working.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[repr(C)]
+pub struct FrameUniforms {
+    /// Sub-bass, bass, mids and highs as in `AudioFeatures::bands`
+    pub bands: [f32; 4],
+    /// Pixels of the image being drawn
+    pub resolution: [f32; 2],
+    /// Seconds since the window opened
+    pub time: f32,
+    /// Seconds since the previous frame
+    pub delta: f32,
+    /// From 0.0 on a beat to 1.0 just before the next
+    pub beat_phase: f32,
 }
 
-/// Less dynamic data such as the products of pipeline setup and command buffer pool
-/// creation.  More tightly associated with the GPU.
-pub trait Framer<'f, 'r: 'f, F, S, R> {
-    fn render_one(
-        &mut self,
-        swap_win: &mut SwapWindow,
-        state: S,
-        resources: &R,
-    ) -> Result<S, VulkanoError>;
-    fn new(swap_win: &mut SwapWindow, resources: &'r R) -> Result<(F, S), VulkanoError>
+/// Uploads `FrameUniforms` each frame into a set bound by every scene pipeline
+pub struct FrameBlock {
+    /// Any scene pipeline.  They all lay out set 0 the same way.
+    layout: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    uniforms: CpuBufferPool<FrameUniforms>,
+}
+
+impl FrameBlock {
+    pub fn new(
+        device: &Arc<Device>,
+        layout: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    ) -> FrameBlock {
+        FrameBlock { layout, uniforms: CpuBufferPool::uniform_buffer(device.clone()) }
+    }
+
+    /// The set to bind at index 0 while drawing a frame with `uniforms`
+    pub fn set(
+        &self,
+        uniforms: FrameUniforms,
+    ) -> Result<Arc<dyn DescriptorSet + Send + Sync>, ENguyenError> {
+        let buffer = self
+            .uniforms
+            .next(uniforms)
+            .map_err(|e| ENguyenError::render("Frame uniform upload failed").caused_by(e))?;
+        let set = PersistentDescriptorSet::start(self.layout.clone(), 0)
+            .add_buffer(buffer)
+            .map_err(|e| ENguyenError::render("Frame uniforms don't fit").caused_by(e))?
+            .build()
+            .map_err(|e| ENguyenError::render("Frame descriptor set failed").caused_by(e))?;
+        Ok(Arc::new(set))
+    }
+}
+
+/// Pipelines drawing into one render pass, built the first time they're asked for and
+/// shared by everything that draws with them afterwards
+pub struct PipelineRegistry {
+    device: Arc<Device>,
+    render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    pipelines: RefCell<HashMap<&'static str, Arc<dyn GraphicsPipelineAbstract + Send + Sync>>>,
+}
+
+impl PipelineRegistry {
+    pub fn new(
+        device: Arc<Device>,
+        render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    ) -> PipelineRegistry {
+        PipelineRegistry { device, render_pass, pipelines: RefCell::new(HashMap::new()) }
+    }
+
+    pub fn device(&self) -> &Arc<Device> {
+        &self.device
+    }
+
+    pub fn render_pass(&self) -> Arc<dyn RenderPassAbstract + Send + Sync> {
+        self.render_pass.clone()
+    }
+
+    /// The pipeline called `name`.  Only the first request for a name builds it, so every
+    /// `build` given the same name must build the same pipeline.
+    pub fn get<F>(
+        &self,
+        name: &'static str,
+        build: F,
+    ) -> Result<Arc<dyn GraphicsPipelineAbstract + Send + Sync>, ENguyenError>
+    where
+        F: FnOnce(
+            &Arc<Device>,
+            Subpass<Arc<dyn RenderPassAbstract + Send + Sync>>,
+        ) -> Result<Arc<dyn GraphicsPipelineAbstract + Send + Sync>, ENguyenError>,
+    {
+        if let Some(pipeline) = self.pipelines.borrow().get(name) {
+            return Ok(pipeline.clone());
+        }
+        let subpass = Subpass::from(self.render_pass.clone(), 0)
+            .ok_or_else(|| ENguyenError::render("No subpass"))?;
+        let pipeline = build(&self.device, subpass)?;
+        self.pipelines.borrow_mut().insert(name, pipeline.clone());
+        Ok(pipeline)
+    }
+}
+
+/// Descriptor sets built before, keyed on the identity of the pipeline and resources they
+/// bind.  Cached sets keep their resources alive, so a key can't be reused by another resource.
+pub struct DescriptorCache<S = Arc<dyn DescriptorSet + Send + Sync>> {
+    sets: RefCell<HashMap<Vec<usize>, S>>,
+    /// Sets kept before the cache is emptied
+    capacity: usize,
+}
+
+impl<S: Clone> DescriptorCache<S> {
+    pub fn new(capacity: usize) -> DescriptorCache<S> {
+        DescriptorCache { sets: RefCell::new(HashMap::new()), capacity: capacity.max(1) }
+    }
+
+    /// The set built for `key`, built by `build` the first time
+    pub fn get<F>(&self, key: Vec<usize>, build: F) -> Result<S, ENguyenError>
+    where
+        F: FnOnce() -> Result<S, ENguyenError>,
+    {
+        if let Some(set) = self.sets.borrow().get(&key) {
+            return Ok(set.clone());
+        }
+        let set = build()?;
+        let mut sets = self.sets.borrow_mut();
+        if sets.len() >= self.capacity {
+            sets.clear();
+        }
+        sets.insert(key, set.clone());
+        Ok(set)
+    }
+
+    pub fn clear(&self) {
+        self.sets.borrow_mut().clear();
+    }
+}
+
+/// Part of a `DescriptorCache` key, the same for every clone of `resource`
+pub fn resource_key<T: ?Sized>(resource: &Arc<T>) -> usize {
+    &**resource as *const T as *const () as usize
+}
+
+/// The diagonal gradient over a triangle strip of `XyVertex`
+pub fn gradient_pipeline(
+    pipelines: &PipelineRegistry,
+) -> Result<Arc<dyn GraphicsPipelineAbstract + Send + Sync>, ENguyenError> {
+    pipelines.get("gradient", |device, subpass| {
+        let vs = diag_grad_vsm::Shader::load(device.clone())
+            .map_err(|e| ENguyenError::render("Gradient shader failed to load").caused_by(e))?;
+        let fs = diag_grad_fsm::Shader::load(device.clone())
+            .map_err(|e| ENguyenError::render("Gradient shader failed to load").caused_by(e))?;
+        let pipeline = GraphicsPipeline::start()
+            .triangle_strip()
+            .vertex_input_single_buffer::<XyVertex>()
+            .vertex_shader(vs.main_entry_point(), ())
+            .viewports_dynamic_scissors_irrelevant(1)
+            .fragment_shader(fs.main_entry_point(), ())
+            .render_pass(subpass)
+            .build(device.clone())
+            .map_err(|e| ENguyenError::render("Gradient pipeline failed").caused_by(e))?;
+        Ok(Arc::new(pipeline))
+    })
+}
+
+/// Alpha blending that also blends alpha, leaving premultiplied colors for a compositor to
+/// show a transparent window with.  Opaque windows look the same.
+pub fn over_blending() -> AttachmentBlend {
+    AttachmentBlend { alpha_source: BlendFactor::One, ..AttachmentBlend::alpha_blending() }
+}
+
+/// An sRGB color in linear light, for colors that don't pass through a shader converting them.
+/// Alpha is coverage and stays as it is.
+pub fn to_linear(color: [f32; 4]) -> [f32; 4] {
+    let channel = |c: f32| {
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    [channel(color[0]), channel(color[1]), channel(color[2]), color[3]]
+}
+
+/// A fully saturated color `turns` around the color wheel from red
+pub fn hue(turns: f32) -> [f32; 3] {
+    let channel = |offset: f32| {
+        let distance = ((turns + offset).fract() * 6.0 - 3.0).abs();
+        (distance - 1.0).clamp(0.0, 1.0)
+    };
+    [channel(0.0), channel(2.0 / 3.0), channel(1.0 / 3.0)]
+}
+
+/// Draws flat colored shapes from vertices uploaded every frame
+pub struct SolidPainter {
+    lines: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    triangles: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    /// Triangles that fade what's under them toward transparent
+    fade: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    /// Points that add their color to what's under them
+    glow: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    vertices: CpuBufferPool<XyVertex>,
+    /// The uniforms of the frame being drawn
+    frame: Option<Arc<dyn DescriptorSet + Send + Sync>>,
+}
+
+impl SolidPainter {
+    pub fn new(pipelines: &PipelineRegistry) -> Result<SolidPainter, ENguyenError> {
+        let device = pipelines.device();
+        let vs = xy_vsm::Shader::load(device.clone())
+            .map_err(|e| ENguyenError::render("Solid shader failed to load").caused_by(e))?;
+        let fs = solid_fsm::Shader::load(device.clone())
+            .map_err(|e| ENguyenError::render("Solid shader failed to load").caused_by(e))?;
+
+        let lines = pipelines.get("solid lines", |device, subpass| {
+            let pipeline = GraphicsPipeline::start()
+                .line_strip()
+                .vertex_input_single_buffer::<XyVertex>()
+                .vertex_shader(vs.main_entry_point(), ())
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(fs.main_entry_point(), ())
+                .blend_collective(over_blending())
+                .render_pass(subpass)
+                .build(device.clone())
+                .map_err(|e| ENguyenError::render("Line pipeline failed").caused_by(e))?;
+            Ok(Arc::new(pipeline))
+        })?;
+        let triangles = pipelines.get("solid triangles", |device, subpass| {
+            let pipeline = GraphicsPipeline::start()
+                .triangle_list()
+                .vertex_input_single_buffer::<XyVertex>()
+                .vertex_shader(vs.main_entry_point(), ())
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(fs.main_entry_point(), ())
+                .blend_collective(over_blending())
+                .render_pass(subpass)
+                .build(device.clone())
+                .map_err(|e| ENguyenError::render("Triangle pipeline failed").caused_by(e))?;
+            Ok(Arc::new(pipeline))
+        })?;
+        let fade = pipelines.get("solid fade", |device, subpass| {
+            // keeps 1 - alpha of what's under, color and alpha alike
+            let fading = AttachmentBlend {
+                color_source: BlendFactor::Zero,
+                alpha_source: BlendFactor::Zero,
+                ..AttachmentBlend::alpha_blending()
+            };
+            let pipeline = GraphicsPipeline::start()
+                .triangle_list()
+                .vertex_input_single_buffer::<XyVertex>()
+                .vertex_shader(vs.main_entry_point(), ())
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(fs.main_entry_point(), ())
+                .blend_collective(fading)
+                .render_pass(subpass)
+                .build(device.clone())
+                .map_err(|e| ENguyenError::render("Fade pipeline failed").caused_by(e))?;
+            Ok(Arc::new(pipeline))
+        })?;
+        let point_vs = xy_point_vsm::Shader::load(device.clone())
+            .map_err(|e| ENguyenError::render("Solid shader failed to load").caused_by(e))?;
+        let additive = AttachmentBlend {
+            color_destination: BlendFactor::One,
+            alpha_destination: BlendFactor::One,
+            ..over_blending()
+        };
+        let glow = pipelines.get("solid glow", |device, subpass| {
+            let pipeline = GraphicsPipeline::start()
+                .point_list()
+                .vertex_input_single_buffer::<XyVertex>()
+                .vertex_shader(point_vs.main_entry_point(), ())
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(fs.main_entry_point(), ())
+                .blend_collective(additive)
+                .render_pass(subpass)
+                .build(device.clone())
+                .map_err(|e| ENguyenError::render("Point pipeline failed").caused_by(e))?;
+            Ok(Arc::new(pipeline))
+        })?;
+
+        let vertices = CpuBufferPool::vertex_buffer(device.clone());
+        Ok(SolidPainter { lines, triangles, fade, glow, vertices, frame: None })
+    }
+
+    /// Draw with the uniforms in `frame` until the next frame begins
+    pub fn begin_frame(&mut self, frame: Arc<dyn DescriptorSet + Send + Sync>) {
+        self.frame = Some(frame);
+    }
+
+    /// A line strip through `vertices`
+    pub fn lines(
+        &self,
+        cbb: AutoCommandBufferBuilder,
+        state: &DynamicState,
+        vertices: Vec<XyVertex>,
+        color: [f32; 4],
+    ) -> Result<AutoCommandBufferBuilder, ENguyenError> {
+        self.draw(cbb, &self.lines, state, vertices, color)
+    }
+
+    /// Every three of `vertices` are a triangle
+    pub fn triangles(
+        &self,
+        cbb: AutoCommandBufferBuilder,
+        state: &DynamicState,
+        vertices: Vec<XyVertex>,
+        color: [f32; 4],
+    ) -> Result<AutoCommandBufferBuilder, ENguyenError> {
+        self.draw(cbb, &self.triangles, state, vertices, color)
+    }
+
+    /// Every three of `vertices` are a triangle fading what's under it toward transparent by
+    /// `amount`, for transparent windows
+    pub fn fade(
+        &self,
+        cbb: AutoCommandBufferBuilder,
+        state: &DynamicState,
+        vertices: Vec<XyVertex>,
+        amount: f32,
+    ) -> Result<AutoCommandBufferBuilder, ENguyenError> {
+        self.draw(cbb, &self.fade, state, vertices, [0.0, 0.0, 0.0, amount])
+    }
+
+    /// A point at each of `vertices`, added to the colors already drawn
+    pub fn glow_points(
+        &self,
+        cbb: AutoCommandBufferBuilder,
+        state: &DynamicState,
+        vertices: Vec<XyVertex>,
+        color: [f32; 4],
+    ) -> Result<AutoCommandBufferBuilder, ENguyenError> {
+        self.draw(cbb, &self.glow, state, vertices, color)
+    }
+
+    fn draw(
+        &self,
+        cbb: AutoCommandBufferBuilder,
+        pipeline: &Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+        state: &DynamicState,
+        vertices: Vec<XyVertex>,
+        color: [f32; 4],
+    ) -> Result<AutoCommandBufferBuilder, ENguyenError> {
+        if vertices.is_empty() {
+            return Ok(cbb);
+        }
+        let chunk = self
+            .vertices
+            .chunk(vertices)
+            .map_err(|e| ENguyenError::render("Vertex upload failed").caused_by(e))?;
+        let buffers: Vec<Arc<dyn BufferAccess + Send + Sync>> = vec![Arc::new(chunk)];
+        let frame = self.frame.clone().ok_or_else(|| ENguyenError::render("No frame uniforms"))?;
+        let push_constants = solid_fsm::ty::PushConstant { color };
+        cbb.draw(pipeline.clone(), state, buffers, frame, push_constants)
+            .map_err(|e| ENguyenError::render("Solid draw failed").caused_by(e))
+    }
+}
+
+/// An image drawn into instead of the window's when rendering at a scale of the window's
+/// size.  Blitted over the window's image, filtered, once the frame is drawn.
+pub struct ScaledTarget {
+    image: Arc<AttachmentImage<Format>>,
+    pub framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    /// Viewport covering the whole image
+    pub dynamic_state: DynamicState,
+    /// Window pixels and scale this was made for
+    made_for: ([u32; 2], f32),
+}
+
+impl ScaledTarget {
+    /// A target for a window of `window` pixels at `scale`, in the window's `format`
+    pub fn new(
+        device: &Arc<Device>,
+        render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+        format: Format,
+        window: [u32; 2],
+        scale: f32,
+    ) -> Result<ScaledTarget, ENguyenError> {
+        let dimensions = scaled_dimensions(window, scale);
+        // sampled by the edge colors
+        let usage = ImageUsage {
+            color_attachment: true,
+            transfer_source: true,
+            sampled: true,
+            ..ImageUsage::none()
+        };
+        let image = AttachmentImage::with_usage(device.clone(), dimensions, format, usage)
+            .map_err(|e| ENguyenError::render("Scaled target allocation failed").caused_by(e))?;
+        let multisampled = multisampled_image(device, &render_pass, dimensions)?;
+        let start = Framebuffer::start(render_pass);
+        let framebuffer: Arc<dyn FramebufferAbstract + Send + Sync> = match multisampled {
+            Some(multisampled) => Arc::new(
+                start
+                    .add(multisampled)
+                    .and_then(|start| start.add(image.clone()))
+                    .map_err(|e| ENguyenError::render("Scaled target doesn't fit").caused_by(e))?
+                    .build()
+                    .map_err(|e| ENguyenError::render("Scaled framebuffer failed").caused_by(e))?,
+            ),
+            None => Arc::new(
+                start
+                    .add(image.clone())
+                    .map_err(|e| ENguyenError::render("Scaled target doesn't fit").caused_by(e))?
+                    .build()
+                    .map_err(|e| ENguyenError::render("Scaled framebuffer failed").caused_by(e))?,
+            ),
+        };
+        let viewport = Viewport {
+            origin: [0.0, 0.0],
+            dimensions: [dimensions[0] as f32, dimensions[1] as f32],
+            depth_range: 0.0..1.0,
+        };
+        let dynamic_state =
+            DynamicState { line_width: None, viewports: Some(vec![viewport]), scissors: None };
+        Ok(ScaledTarget { image, framebuffer, dynamic_state, made_for: (window, scale) })
+    }
+
+    /// The drawn image, resolved if multisampled
+    pub fn image(&self) -> Arc<AttachmentImage<Format>> {
+        self.image.clone()
+    }
+
+    /// Whether this was made for a window of `window` pixels at `scale`
+    pub fn fits(&self, window: [u32; 2], scale: f32) -> bool {
+        self.made_for == (window, scale)
+    }
+
+    /// Records the drawn image stretched over all of `destination`.  Call outside the
+    /// render pass.
+    pub fn blit<I>(
+        &self,
+        cbb: AutoCommandBufferBuilder,
+        destination: I,
+    ) -> Result<AutoCommandBufferBuilder, ENguyenError>
     where
-        F: Framer<'f, 'r, F, S, R>,
-        S: FrameState,
-        R: Send; // generous bound
+        I: ImageAccess + Send + Sync + 'static,
+    {
+        let [width, height] = self.image.dimensions();
+        let [window_width, window_height] = destination.dimensions().width_height();
+        cbb.blit_image(
+            self.image.clone(),
+            [0, 0, 0],
+            [width as i32, height as i32, 1],
+            0,
+            0,
+            destination,
+            [0, 0, 0],
+            [window_width as i32, window_height as i32, 1],
+            0,
+            0,
+            1,
+            Filter::Linear,
+        )
+        .map_err(|e| ENguyenError::render("Scaled target blit failed").caused_by(e))
+    }
+}
+
+/// Pixels of an image at `scale` of `window`, at least one on each side
+pub fn scaled_dimensions(window: [u32; 2], scale: f32) -> [u32; 2] {
+    let scaled = |pixels: u32| ((pixels as f32 * scale).round() as u32).max(1);
+    [scaled(window[0]), scaled(window[1])]
+}
+
+/// The font every window draws its text in
+pub fn ui_font() -> Result<Font<'static>, ENguyenError> {
+    Font::from_bytes(include_bytes!("../font/MajorMonoDisplay-Regular.ttf") as &[u8])
+        .map_err(|e| ENguyenError::render("Font loading failed").caused_by(e))
 }
 
-/// Mutable datastructure that allows rich interaction with application flow control
-/// between frames.  More tightly associated with the window and swapchain.
-pub trait FrameState {}
+/// Which side of a line of text is at the point it is drawn from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+impl Align {
+    /// How far left of the point a line `width` wide starts
+    fn offset(self, width: f32) -> f32 {
+        match self {
+            Align::Left => 0.0,
+            Align::Center => width / 2.0,
+            Align::Right => width,
+        }
+    }
+}
+
+/// Text to draw.  Positions and sizes are logical pixels, scaled by the window's DPI factor.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextBlock {
+    pub text: String,
+    /// Baseline of the first line, on the side `align` names
+    pub at: [f32; 2],
+    pub size: f32,
+    pub color: [f32; 4],
+    pub align: Align,
+    /// Lines break between words to fit this width.  Newlines always break.
+    pub wrap: Option<f32>,
+}
+
+impl TextBlock {
+    /// Left aligned, without wrapping
+    pub fn new<S: Into<String>>(text: S, at: [f32; 2], size: f32, color: [f32; 4]) -> TextBlock {
+        TextBlock { text: text.into(), at, size, color, align: Align::Left, wrap: None }
+    }
+}
+
+static IDENTITY: [[f32; 4]; 4] =
+    [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0]];
+
+/// Lays out and draws a window's text.  Glyphs are cached on the GPU, and the text is only laid
+/// out again when it or the DPI factor changes.
+pub struct TextRenderer<'f> {
+    font: Font<'f>,
+    brush: GlyphBrush<'f>,
+    sections: Vec<Section>,
+    /// What `sections` were laid out from
+    shown: Vec<TextBlock>,
+    shown_hidpi: f32,
+}
+
+impl<'f> TextRenderer<'f> {
+    pub fn new(
+        device: &Arc<Device>,
+        subpass: Subpass<Arc<dyn RenderPassAbstract + Send + Sync>>,
+        font: Font<'f>,
+    ) -> Result<TextRenderer<'f>, ENguyenError> {
+        let brush = GlyphBrush::new(device, subpass)
+            .map_err(|_| ENguyenError::render("Glyph brush creation failed"))?;
+        let sections = Vec::new();
+        Ok(TextRenderer { font, brush, sections, shown: Vec::new(), shown_hidpi: 0.0 })
+    }
+
+    /// Draws `blocks` from now on, at `hidpi` physical pixels per logical pixel.  Glyphs not
+    /// yet cached are uploaded on `queue`, and drawing must wait for the returned future.
+    pub fn show(
+        &mut self,
+        queue: &Arc<Queue>,
+        blocks: &[TextBlock],
+        hidpi: f32,
+    ) -> Result<Option<Box<dyn GpuFuture>>, ENguyenError> {
+        if blocks == &self.shown[..] && hidpi == self.shown_hidpi {
+            return Ok(None);
+        }
+        self.sections.clear();
+        for block in blocks {
+            let scale = Scale::uniform(block.size * hidpi);
+            let [x, y] = [block.at[0] * hidpi, block.at[1] * hidpi];
+            let lines = wrap_lines(&self.font, &block.text, scale, block.wrap.map(|w| w * hidpi));
+            for (index, line) in lines.iter().enumerate() {
+                let left = x - block.align.offset(line_width(&self.font, line, scale));
+                let baseline = y + line_height(&self.font, scale) * index as f32;
+                let glyphs = self.font.layout(line, scale, point(left, baseline));
+                self.sections.push(self.brush.queue_glyphs(glyphs, 0, to_linear(block.color)));
+            }
+        }
+        self.shown = blocks.to_vec();
+        self.shown_hidpi = hidpi;
+        if self.sections.is_empty() {
+            return Ok(None);
+        }
+        // everything shown is cached again so new glyphs can't evict glyphs still in use
+        let upload = self
+            .brush
+            .cache_sections(queue, self.sections.iter())
+            .map_err(|_| ENguyenError::render("Glyph upload failed"))?;
+        Ok(upload.map(|upload| Box::new(upload) as Box<dyn GpuFuture>))
+    }
+
+    /// Records the text on a window of `window` physical pixels
+    pub fn draw(
+        &self,
+        cbb: AutoCommandBufferBuilder,
+        state: &DynamicState,
+        window: [f32; 2],
+    ) -> Result<AutoCommandBufferBuilder, ENguyenError> {
+        if self.sections.is_empty() {
+            return Ok(cbb);
+        }
+        self.brush
+            .draw(cbb, &self.sections, state, IDENTITY, window)
+            .map_err(|_| ENguyenError::render("Text draw failed"))
+    }
+}
+
+/// Pixels from the start of `line` to the end of its last glyph at `scale`
+pub fn line_width(font: &Font, line: &str, scale: Scale) -> f32 {
+    font.layout(line, scale, point(0.0, 0.0))
+        .last()
+        .map_or(0.0, |glyph| glyph.position().x + glyph.unpositioned().h_metrics().advance_width)
+}
+
+/// Pixels from one baseline to the next at `scale`
+pub fn line_height(font: &Font, scale: Scale) -> f32 {
+    let metrics = font.v_metrics(scale);
+    metrics.ascent - metrics.descent + metrics.line_gap
+}
+
+/// The lines of `text`, broken at newlines and, given a `width`, between words to fit it.  A
+/// word wider than `width` gets a line to itself.
+pub fn wrap_lines(font: &Font, text: &str, scale: Scale, width: Option<f32>) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let width = match width {
+            Some(width) => width,
+            None => {
+                lines.push(paragraph.to_owned());
+                continue;
+            },
+        };
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            if line.is_empty() {
+                line.push_str(word);
+                continue;
+            }
+            let longer = format!("{} {}", line, word);
+            if line_width(font, &longer, scale) > width {
+                lines.push(std::mem::replace(&mut line, word.to_owned()));
+            } else {
+                line = longer;
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_wraps_between_words() {
+        let font = ui_font().unwrap();
+        let scale = Scale::uniform(20.0);
+        let two_words = line_width(&font, "abc abc", scale);
+        assert!(two_words > line_width(&font, "abc", scale));
+        let lines = wrap_lines(&font, "abc abc abc\nx", scale, Some(two_words + 1.0));
+        assert_eq!(lines, vec!["abc abc", "abc", "x"]);
+        // words too wide for any line aren't split
+        assert_eq!(wrap_lines(&font, "abcdef g", scale, Some(1.0)), vec!["abcdef", "g"]);
+        assert_eq!(wrap_lines(&font, "a  b", scale, None), vec!["a  b"]);
+        assert!(line_height(&font, scale) > 0.0);
+    }
+
+    #[test]
+    fn srgb_decodes_to_linear() {
+        assert_eq!(to_linear([0.0, 1.0, 0.0, 0.5]), [0.0, 1.0, 0.0, 0.5]);
+        let [grey, dark, _, alpha] = to_linear([0.5, 0.02, 0.0, 0.3]);
+        assert!((grey - 0.214).abs() < 0.001);
+        assert!((dark - 0.02 / 12.92).abs() < 1e-6);
+        assert_eq!(alpha, 0.3);
+    }
+
+    #[test]
+    fn descriptor_sets_are_cached_by_resource() {
+        let (first, second, third) = (Arc::new(1), Arc::new(1), Arc::new(1));
+        let cache = DescriptorCache::new(2);
+        let mut builds = 0;
+        let mut get = |resource: &Arc<u32>| {
+            cache.get(vec![resource_key(resource)], || {
+                builds += 1;
+                Ok(builds)
+            })
+        };
+        assert_eq!(get(&first).unwrap(), 1);
+        assert_eq!(get(&first.clone()).unwrap(), 1);
+        assert_eq!(get(&second).unwrap(), 2);
+        // full, so the next new key starts over
+        assert_eq!(get(&third).unwrap(), 3);
+        assert_eq!(get(&first).unwrap(), 4);
+    }
+
+    #[test]
+    fn lines_align_to_their_point() {
+        assert_eq!(Align::Left.offset(10.0), 0.0);
+        assert_eq!(Align::Center.offset(10.0), 5.0);
+        assert_eq!(Align::Right.offset(10.0), 10.0);
+    }
+}
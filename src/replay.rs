@@ -0,0 +1,252 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! Replays a WAV file, such as a recording, through the ring at the pace and in the chunk
+//! sizes live capture writes it.  What a user saw can be reproduced frame for frame.
+
+use crate::audio::{AudioStream, CaptureHandle, RingState, SimpleSource};
+use crate::errors::ENguyenError;
+use crate::ring::{FrameReader, OverflowPolicy, RingFrames};
+
+use byteorder::{ByteOrder, LittleEndian as Le};
+use log::info;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Audio written at a time when no capture latency is set
+static CHUNK_MS: u32 = 10;
+/// Chunks the ring holds before the overflow policy applies
+static RING_CHUNKS: usize = 8;
+static PCM: u16 = 1;
+static EXTENSIBLE: u16 = 0xfffe;
+
+/// A WAV file played into the ring in real time.  Silence follows its end, so the analysis
+/// settles rather than restarting.
+pub struct WavReplay {
+    hot_handle: Option<CaptureHandle>,
+    state: Mutex<RingState>,
+    killed: Arc<AtomicBool>,
+    source_info: SimpleSource,
+    /// Interleaved stereo
+    samples: Arc<Vec<i16>>,
+    /// Frames written at a time
+    chunk: usize,
+    overflow: OverflowPolicy,
+}
+
+impl WavReplay {
+    /// Reads `path`, to be written `latency_ms` of audio at a time as capture with that
+    /// latency would be
+    pub fn open(path: &Path, latency_ms: Option<u32>) -> Result<WavReplay, ENguyenError> {
+        let bytes = fs::read(path).map_err(|e| {
+            ENguyenError::audio(format!("Can't read {}", path.display())).caused_by(e)
+        })?;
+        let (rate, samples) = parse_wav(&bytes).map_err(|e| {
+            ENguyenError::audio(format!("Can't replay {}", path.display())).caused_by(e)
+        })?;
+        let name = path.display().to_string();
+        let chunk = (rate * latency_ms.unwrap_or(CHUNK_MS).max(1) / 1000).max(1) as usize;
+        info!("Replaying {}s of {}", samples.len() / 2 / rate as usize, name);
+        Ok(WavReplay {
+            hot_handle: None,
+            state: Mutex::new(RingState::BORN),
+            killed: Arc::new(AtomicBool::new(false)),
+            source_info: SimpleSource::stereo(&name, rate),
+            samples: Arc::new(samples),
+            chunk,
+            overflow: OverflowPolicy::default(),
+        })
+    }
+
+    /// What the replay does when the reader falls behind
+    pub fn with_overflow(mut self, overflow: OverflowPolicy) -> WavReplay {
+        self.overflow = overflow;
+        self
+    }
+}
+
+impl AudioStream for WavReplay {
+    fn connect(&mut self) -> Result<RingState, ENguyenError> {
+        let mut state = self.state.lock().unwrap();
+        if *state != RingState::BORN {
+            return Err(ENguyenError::audio("Ring already connected.  Get your own"));
+        }
+        *state = RingState::CONNECTED;
+        Ok(RingState::CONNECTED)
+    }
+
+    fn heat(&mut self) -> Result<(FrameReader<i16>, SimpleSource), ENguyenError> {
+        let mut state = self.state.lock().unwrap();
+        if *state != RingState::CONNECTED {
+            return Err(ENguyenError::audio("Can't heat a ring that isn't connected"));
+        }
+        let channels = self.source_info.channels();
+        let frames = self.chunk * RING_CHUNKS;
+        let (tx, rx) = RingFrames::<i16>::with_policy(frames, channels, self.overflow);
+        let killed = self.killed.clone();
+        let samples = self.samples.clone();
+        let chunk = self.chunk;
+        let period =
+            Duration::from_nanos(chunk as u64 * 1_000_000_000 / self.source_info.rate as u64);
+        self.hot_handle = Some(thread::spawn(move || {
+            let silence = vec![0; chunk * channels];
+            let start = Instant::now();
+            // each chunk is due a whole period after the one before, however late it was
+            for written in 0.. {
+                if killed.load(Ordering::Relaxed) {
+                    break;
+                }
+                let from = (written * chunk * channels).min(samples.len());
+                let to = (from + chunk * channels).min(samples.len());
+                if from == to {
+                    tx.write_frames(&silence);
+                } else {
+                    tx.write_frames(&samples[from..to]);
+                }
+                let due = start + period * (written as u32 + 1);
+                let now = Instant::now();
+                if due > now {
+                    thread::sleep(due - now);
+                }
+            }
+            Ok(())
+        }));
+        *state = RingState::HOT;
+        Ok((rx, self.source_info.clone()))
+    }
+
+    fn chill(&mut self) -> Result<(RingState, CaptureHandle), ENguyenError> {
+        let mut state = self.state.lock().unwrap();
+        if *state != RingState::HOT {
+            return Err(ENguyenError::audio("Can't chill a ring that isn't hot"));
+        }
+        self.killed.store(true, Ordering::Relaxed);
+        *state = RingState::DEAD;
+        match self.hot_handle.take() {
+            Some(handle) => Ok((RingState::DEAD, handle)),
+            None => Err(ENguyenError::audio("Replay was never started")),
+        }
+    }
+
+    fn state(&self) -> RingState {
+        *self.state.lock().unwrap()
+    }
+}
+
+/// The rate and interleaved stereo samples of a 16 bit PCM WAV.  Mono is played in both
+/// channels.  A data chunk whose size was never written, as in a recording cut short, runs
+/// to the end of the file.
+fn parse_wav(bytes: &[u8]) -> Result<(u32, Vec<i16>), String> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err("not a WAV file".to_owned());
+    }
+    let mut format: Option<(u16, u32)> = None;
+    let mut at = 12;
+    while at + 8 <= bytes.len() {
+        let id = &bytes[at..at + 4];
+        let size = Le::read_u32(&bytes[at + 4..at + 8]) as usize;
+        let body = at + 8;
+        match id {
+            b"fmt " if size >= 16 && body + 16 <= bytes.len() => {
+                let tag = Le::read_u16(&bytes[body..]);
+                let channels = Le::read_u16(&bytes[body + 2..]);
+                let rate = Le::read_u32(&bytes[body + 4..]);
+                let bits = Le::read_u16(&bytes[body + 14..]);
+                if (tag != PCM && tag != EXTENSIBLE) || bits != 16 {
+                    return Err(format!("{} bit format {} isn't 16 bit PCM", bits, tag));
+                }
+                if channels != 1 && channels != 2 {
+                    return Err(format!("{} channels can't be replayed", channels));
+                }
+                if rate == 0 {
+                    return Err("no sample rate".to_owned());
+                }
+                format = Some((channels, rate));
+            },
+            b"data" => {
+                let (channels, rate) = format.ok_or("data before the format")?;
+                let end = match size {
+                    0 => bytes.len(),
+                    _ => (body + size).min(bytes.len()),
+                };
+                let data = &bytes[body..end];
+                let mut samples = Vec::with_capacity(data.len() / channels as usize);
+                for sample in data.chunks_exact(2).map(Le::read_i16) {
+                    samples.push(sample);
+                    if channels == 1 {
+                        samples.push(sample);
+                    }
+                }
+                // whole frames only
+                samples.truncate(samples.len() - samples.len() % 2);
+                return Ok((rate, samples));
+            },
+            _ => {},
+        }
+        at = body + size + size % 2;
+    }
+    Err("no audio data".to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recording::Recorder;
+
+    #[test]
+    fn recordings_replay_in_real_time() {
+        let path = std::env::temp_dir().join(format!("e-nguyen-replay-{}.wav", std::process::id()));
+        let recorder = Recorder::default();
+        recorder.start(path.clone());
+        let recorded: Vec<i16> = (0..9600).map(|i| (i % 300) as i16 - 150).collect();
+        recorder.tee(&recorded, 48000);
+        recorder.stop();
+
+        let mut replay = WavReplay::open(&path, Some(20)).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(replay.chunk, 960);
+        replay.connect().unwrap();
+        let started = Instant::now();
+        let (rx, source) = replay.heat().unwrap();
+        assert_eq!(source.rate, 48000);
+        let mut replayed = Vec::new();
+        while replayed.len() < recorded.len() {
+            let timeout = Duration::from_secs(1);
+            replayed.extend(rx.read_frames_at_least(960, timeout).unwrap());
+        }
+        // the 100ms recorded takes as long to replay, and silence follows
+        let took = started.elapsed();
+        assert!(took >= Duration::from_millis(80), "{:?}", took);
+        assert_eq!(&replayed[..recorded.len()], &recorded[..]);
+        let after = rx.read_frames_at_least(960, Duration::from_secs(1)).unwrap();
+        assert!(after.iter().all(|sample| *sample == 0));
+        let (_, capture) = replay.chill().unwrap();
+        capture.join().unwrap().unwrap();
+
+        let mono = [b"RIFF".as_ref(), &[0; 4], b"WAVEfmt ", &[16, 0, 0, 0, 1, 0, 1, 0]]
+            .concat()
+            .into_iter()
+            .chain(vec![0x44, 0xac, 0, 0, 0, 0, 0, 0, 2, 0, 16, 0])
+            .chain(b"data".iter().cloned())
+            .chain(vec![4, 0, 0, 0, 1, 0, 2, 0])
+            .collect::<Vec<u8>>();
+        assert_eq!(parse_wav(&mono).unwrap(), (44100, vec![1, 1, 2, 2]));
+        assert!(parse_wav(b"RIFF\0\0\0\0WAVE").is_err());
+    }
+}
@@ -0,0 +1,171 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! Pausing the spectrogram and scrubbing through its history.  Analysis keeps writing while
+//! paused, so the view holds still by falling further behind the newest column, until it
+//! reaches the oldest column kept and is pushed along.
+
+use crate::compute::ANALYSIS_RATE;
+use crate::input::{Arrow, MousePos, UserEvent};
+
+pub struct Review {
+    /// How far behind the newest column the view can fall
+    depth: u32,
+    /// Columns shown across the window
+    shown: u32,
+    /// Columns the view's leading edge is behind the newest.  None when live.
+    behind: Option<u32>,
+    /// Where a drag scrubbing the history last was, in logical pixels
+    dragging: Option<f64>,
+}
+
+impl Review {
+    pub fn new(depth: u32, shown: u32) -> Review {
+        Review { depth, shown, behind: None, dragging: None }
+    }
+
+    /// Columns the view is behind the newest, 0 when live
+    pub fn behind(&self) -> u32 {
+        self.behind.unwrap_or(0)
+    }
+
+    /// How long ago the view's leading edge was analyzed
+    pub fn seconds_behind(&self) -> f32 {
+        self.behind() as f32 / ANALYSIS_RATE as f32
+    }
+
+    pub fn is_live(&self) -> bool {
+        self.behind.is_none()
+    }
+
+    /// A new column was written.  Live views follow it, paused ones hold still.
+    pub fn written(&mut self) {
+        if let Some(behind) = &mut self.behind {
+            *behind = (*behind + 1).min(self.depth);
+        }
+    }
+
+    /// Pause, then move the view `columns` back in time, or forward if negative
+    pub fn scrub(&mut self, columns: i64) {
+        let behind = (i64::from(self.behind()) + columns).max(0).min(i64::from(self.depth));
+        self.behind = Some(behind as u32);
+    }
+
+    /// Follow the newest column again
+    pub fn go_live(&mut self) {
+        self.behind = None;
+        self.dragging = None;
+    }
+
+    /// Space pauses and resumes, arrows scrub a second, up returns to live and dragging
+    /// across a window `width` logical pixels wide scrubs with the pointer.  True if the
+    /// event was used.
+    pub fn handle(&mut self, event: &UserEvent, width: f64) -> bool {
+        match event {
+            UserEvent::KeyPress { character: ' ' } => {
+                if self.is_live() {
+                    self.scrub(0);
+                } else {
+                    self.go_live();
+                }
+            },
+            UserEvent::Arrow { arrow: Arrow::Left } => self.scrub(i64::from(ANALYSIS_RATE)),
+            UserEvent::Arrow { arrow: Arrow::Right } => self.scrub(-i64::from(ANALYSIS_RATE)),
+            UserEvent::Arrow { arrow: Arrow::Up } => self.go_live(),
+            UserEvent::MouseDown { pos: MousePos::Pos { x, .. } } => self.dragging = Some(*x),
+            UserEvent::MouseMove { pos: MousePos::Pos { x, .. } } => {
+                let from = match self.dragging {
+                    Some(from) => from,
+                    None => return false,
+                };
+                // dragging right pulls older columns into view
+                let columns = (x - from) / width.max(1.0) * f64::from(self.shown);
+                if columns.abs() < 1.0 {
+                    return true;
+                }
+                self.scrub(columns.trunc() as i64);
+                self.dragging = Some(from + columns.trunc() / f64::from(self.shown) * width);
+            },
+            UserEvent::MouseUp { .. } => return self.dragging.take().is_some(),
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::{drag, ProcessedEvent, Simulation};
+
+    #[test]
+    fn paused_views_hold_still() {
+        let mut review = Review::new(100, 50);
+        review.written();
+        assert!(review.is_live());
+
+        let pause = UserEvent::KeyPress { character: ' ' };
+        assert!(review.handle(&pause, 500.0));
+        review.written();
+        review.written();
+        assert_eq!(review.behind(), 2);
+        assert!(review.handle(&UserEvent::Arrow { arrow: Arrow::Left }, 500.0));
+        assert_eq!(review.behind(), 62);
+        assert!((review.seconds_behind() - 62.0 / 60.0).abs() < 1e-6);
+        // the oldest column kept pushes the view along
+        review.scrub(1000);
+        review.written();
+        assert_eq!(review.behind(), 100);
+        review.scrub(-1000);
+        assert_eq!(review.behind(), 0);
+        assert!(!review.is_live());
+        assert!(review.handle(&pause, 500.0));
+        assert!(review.is_live());
+    }
+
+    #[test]
+    fn dragging_scrubs() {
+        let mut review = Review::new(100, 50);
+        let at = |x| MousePos::Pos { x, y: 0.0 };
+        assert!(!review.handle(&UserEvent::MouseMove { pos: at(10.0) }, 500.0));
+        review.handle(&UserEvent::MouseDown { pos: at(10.0) }, 500.0);
+        // 100 pixels of 500 is a fifth of the 50 columns shown
+        review.handle(&UserEvent::MouseMove { pos: at(110.0) }, 500.0);
+        assert_eq!(review.behind(), 10);
+        review.handle(&UserEvent::MouseMove { pos: at(60.0) }, 500.0);
+        assert_eq!(review.behind(), 5);
+        assert!(review.handle(&UserEvent::MouseUp { pos: at(60.0) }, 500.0));
+        assert!(!review.handle(&UserEvent::MouseMove { pos: at(400.0) }, 500.0));
+        assert_eq!(review.behind(), 5);
+    }
+
+    #[test]
+    fn scripted_drags_scrub() {
+        let mut review = Review::new(100, 50);
+        let mut sim = Simulation::new();
+        for ue in sim.run(&drag((10.0, 0.0), (110.0, 0.0))) {
+            review.handle(&ue, 500.0);
+        }
+        assert_eq!(review.behind(), 10);
+        // a click with modifiers held isn't a drag
+        let script = vec![
+            ProcessedEvent::LeftButtonDown { no_mods: false },
+            ProcessedEvent::CursorMove { x: 300.0, y: 0.0 },
+        ];
+        let used = sim.run(&script).iter().filter(|ue| review.handle(ue, 500.0)).count();
+        assert_eq!(used, 0);
+        assert_eq!(review.behind(), 10);
+    }
+}
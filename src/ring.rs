@@ -13,24 +13,158 @@
 
 // Copyright 2019 E-Nguyen Developers.
 
+use byteorder::{ByteOrder, LittleEndian as Le};
 use bytes::buf::BufMut;
 use bytes::{Bytes, BytesMut};
-use std::sync::{Arc, Mutex};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
 
 pub type Guarantee = usize; // guardrail requesting more than available
 
+static MAX_WRITE_MARKS: usize = 4096; // bounds bookkeeping if the reader never shows up
+
+/// What the writer does when the reader falls behind and the ring is full.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Discard the oldest unread bytes.  Keeps latency bounded when the renderer stalls.
+    DropOldest,
+    /// Park the writer until the reader makes room.
+    BlockWriter,
+}
+
+impl Default for OverflowPolicy {
+    fn default() -> Self {
+        OverflowPolicy::DropOldest
+    }
+}
+
+/// Snapshot of ring throughput and latency for the debug HUD and A/V sync
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RingStats {
+    /// Running count of bytes that have entered the ring
+    pub written: u64,
+    /// Stream offset of the next byte to be read.  Read and dropped bytes both advance it.
+    pub position: u64,
+    pub dropped: u64,
+    /// How long ago the oldest unread byte entered the ring
+    pub backlog: Duration,
+    /// Average bytes per second entering the ring since the first write
+    pub write_rate: f64,
+}
+
+/// Monotonic timestamps attached to writes so the reader can tell how stale its data is
+struct Timeline {
+    first_write: Option<Instant>,
+    written: u64,
+    position: u64,
+    marks: VecDeque<(u64, Instant)>, // (stream offset at end of write, when it was written)
+}
+
+impl Timeline {
+    fn new() -> Timeline {
+        Timeline { first_write: None, written: 0, position: 0, marks: VecDeque::new() }
+    }
+
+    fn entered(&mut self, amount: usize) {
+        let now = Instant::now();
+        self.first_write.get_or_insert(now);
+        self.written += amount as u64;
+        if self.marks.len() >= MAX_WRITE_MARKS {
+            self.marks.pop_front();
+        }
+        self.marks.push_back((self.written, now));
+    }
+
+    fn consumed(&mut self, amount: usize) {
+        self.position += amount as u64;
+        while let Some((end, _)) = self.marks.front() {
+            if *end > self.position {
+                break;
+            }
+            self.marks.pop_front();
+        }
+    }
+
+    fn backlog(&self) -> Duration {
+        match self.marks.front() {
+            Some((_, written_at)) if self.written > self.position => written_at.elapsed(),
+            _ => Duration::from_secs(0),
+        }
+    }
+
+    fn write_rate(&self) -> f64 {
+        match self.first_write {
+            Some(first) => {
+                let elapsed = first.elapsed();
+                let secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) * 1e-9;
+                if secs > 0.0 {
+                    self.written as f64 / secs
+                } else {
+                    0.0
+                }
+            },
+            None => 0.0,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct RingBytes {
     buf: Arc<Mutex<BytesMut>>,
+    timeline: Arc<Mutex<Timeline>>,
+    drained: Arc<Condvar>,
+    filled: Arc<Condvar>,
+    dropped: Arc<AtomicUsize>,
+    reader_gone: Arc<AtomicBool>,
+    writer_gone: Arc<AtomicBool>,
+    capacity: usize,
+    policy: OverflowPolicy,
 }
 
 /// 2.4GB/s is enough for 44.1KB/s but monotonic lock-free would be better
 impl RingBytes {
     pub fn new(size: usize) -> (RingWriter, RingReader) {
+        RingBytes::with_policy(size, OverflowPolicy::default())
+    }
+
+    /// `size` is the most unread bytes the ring will hold before applying `policy`
+    pub fn with_policy(size: usize, policy: OverflowPolicy) -> (RingWriter, RingReader) {
         let buf = BytesMut::with_capacity(size);
-        let ring = RingBytes { buf: Arc::new(Mutex::new(buf)) };
+        let ring = RingBytes {
+            buf: Arc::new(Mutex::new(buf)),
+            timeline: Arc::new(Mutex::new(Timeline::new())),
+            drained: Arc::new(Condvar::new()),
+            filled: Arc::new(Condvar::new()),
+            dropped: Arc::new(AtomicUsize::new(0)),
+            reader_gone: Arc::new(AtomicBool::new(false)),
+            writer_gone: Arc::new(AtomicBool::new(false)),
+            capacity: size,
+            policy,
+        };
         (RingWriter { ring: ring.clone() }, RingReader { ring: ring.clone() })
     }
+
+    /// Total bytes discarded since creation, by `OverflowPolicy::DropOldest` or for want of
+    /// a reader
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn stats(&self) -> RingStats {
+        let timeline = self.timeline.lock().unwrap();
+        RingStats {
+            written: timeline.written,
+            position: timeline.position,
+            dropped: self.dropped() as u64,
+            backlog: timeline.backlog(),
+            write_rate: timeline.write_rate(),
+        }
+    }
 }
 
 pub struct RingReader {
@@ -43,7 +177,74 @@ impl RingReader {
     }
 
     pub fn read(&self, amount: usize) -> Bytes {
-        self.ring.buf.lock().unwrap().split_to(amount).freeze()
+        let mut buf = self.ring.buf.lock().unwrap();
+        let read = buf.split_to(amount).freeze();
+        self.ring.timeline.lock().unwrap().consumed(read.len());
+        drop(buf);
+        self.ring.drained.notify_all();
+        read
+    }
+
+    /// Parks until at least `amount` bytes are available and then reads all of them.
+    /// Returns `None` if `timeout` elapses first so callers can check for shutdown.
+    pub fn read_at_least(&self, amount: usize, timeout: Duration) -> Option<Bytes> {
+        self.read_aligned(amount, 1, timeout)
+    }
+
+    /// Like `read_at_least` but only reads a multiple of `align` bytes
+    fn read_aligned(&self, amount: usize, align: usize, timeout: Duration) -> Option<Bytes> {
+        let deadline = Instant::now() + timeout;
+        let mut buf = self.ring.buf.lock().unwrap();
+        while buf.len() < amount {
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            buf = self.ring.filled.wait_timeout(buf, deadline - now).unwrap().0;
+        }
+        let len = buf.len() - buf.len() % align;
+        let read = buf.split_to(len).freeze();
+        self.ring.timeline.lock().unwrap().consumed(len);
+        drop(buf);
+        self.ring.drained.notify_all();
+        Some(read)
+    }
+
+    pub fn dropped(&self) -> usize {
+        self.ring.dropped()
+    }
+
+    pub fn policy(&self) -> OverflowPolicy {
+        self.ring.policy
+    }
+
+    /// Stream offset of the next byte `read` will return
+    pub fn position(&self) -> u64 {
+        self.ring.timeline.lock().unwrap().position
+    }
+
+    /// Age of the oldest unread byte
+    pub fn backlog(&self) -> Duration {
+        self.ring.timeline.lock().unwrap().backlog()
+    }
+
+    pub fn stats(&self) -> RingStats {
+        self.ring.stats()
+    }
+
+    /// False once the writer has been dropped, including by a panicking capture thread
+    pub fn writer_alive(&self) -> bool {
+        !self.ring.writer_gone.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for RingReader {
+    fn drop(&mut self) {
+        // a blocked writer would otherwise wait forever on a reader that's gone.  Under the
+        // lock, so it can't check for the reader and then miss the wakeup.
+        let _buf = self.ring.buf.lock().unwrap();
+        self.ring.reader_gone.store(true, Ordering::Relaxed);
+        self.ring.drained.notify_all();
     }
 }
 
@@ -51,26 +252,404 @@ pub struct RingWriter {
     ring: RingBytes,
 }
 
+impl Drop for RingWriter {
+    fn drop(&mut self) {
+        // wake a parked reader so it notices without waiting out its timeout
+        let _buf = self.ring.buf.lock().unwrap();
+        self.ring.writer_gone.store(true, Ordering::Relaxed);
+        self.ring.filled.notify_all();
+    }
+}
+
 impl RingWriter {
+    /// Space that can be written without triggering the overflow policy
     pub fn reserve(&self, size: usize) -> Guarantee {
         let mut buf = self.ring.buf.lock().unwrap();
         buf.reserve(size);
-        buf.remaining_mut()
+        self.ring.capacity.saturating_sub(buf.len())
     }
 
+    /// Writes all of `bytes`, applying the overflow policy if the reader has fallen behind
     pub fn write(&self, bytes: &[u8]) {
+        match self.ring.policy {
+            OverflowPolicy::DropOldest => self.write_dropping(bytes),
+            OverflowPolicy::BlockWriter => self.write_blocking(bytes),
+        }
+    }
+
+    pub fn dropped(&self) -> usize {
+        self.ring.dropped()
+    }
+
+    fn write_dropping(&self, bytes: &[u8]) {
+        let capacity = self.ring.capacity;
         let mut buf = self.ring.buf.lock().unwrap();
+        let mut timeline = self.ring.timeline.lock().unwrap();
+        timeline.entered(bytes.len());
+        // only the newest bytes that fit can survive
+        let bytes = if bytes.len() > capacity {
+            let skipped = bytes.len() - capacity;
+            let unread = buf.len();
+            buf.clear();
+            timeline.consumed(unread + skipped);
+            self.ring.dropped.fetch_add(unread + skipped, Ordering::Relaxed);
+            &bytes[skipped..]
+        } else {
+            bytes
+        };
+        let overflow = (buf.len() + bytes.len()).saturating_sub(capacity);
+        if overflow > 0 {
+            buf.advance(overflow);
+            timeline.consumed(overflow);
+            self.ring.dropped.fetch_add(overflow, Ordering::Relaxed);
+        }
+        buf.reserve(bytes.len());
         buf.put(bytes);
+        drop(timeline);
+        drop(buf);
+        self.ring.filled.notify_all();
+    }
+
+    fn write_blocking(&self, bytes: &[u8]) {
+        let capacity = self.ring.capacity;
+        let mut remaining = bytes;
+        let mut buf = self.ring.buf.lock().unwrap();
+        while !remaining.is_empty() {
+            if self.ring.reader_gone.load(Ordering::Relaxed) {
+                self.ring.dropped.fetch_add(remaining.len(), Ordering::Relaxed);
+                break;
+            }
+            let space = capacity.saturating_sub(buf.len());
+            if space == 0 {
+                buf = self.ring.drained.wait(buf).unwrap();
+                continue;
+            }
+            let chunk = if remaining.len() > space { space } else { remaining.len() };
+            buf.reserve(chunk);
+            buf.put(&remaining[..chunk]);
+            self.ring.timeline.lock().unwrap().entered(chunk);
+            remaining = &remaining[chunk..];
+            self.ring.filled.notify_all();
+        }
+    }
+}
+
+/// Samples that can be packed little-endian into the ring
+pub trait Sample: Copy + Default + Send {
+    const BYTES: usize;
+    fn read(bytes: &[u8]) -> Self;
+    fn write(self, bytes: &mut [u8]);
+}
+
+impl Sample for i16 {
+    const BYTES: usize = 2;
+    fn read(bytes: &[u8]) -> Self {
+        Le::read_i16(bytes)
+    }
+    fn write(self, bytes: &mut [u8]) {
+        Le::write_i16(bytes, self)
+    }
+}
+
+impl Sample for i32 {
+    const BYTES: usize = 4;
+    fn read(bytes: &[u8]) -> Self {
+        Le::read_i32(bytes)
+    }
+    fn write(self, bytes: &mut [u8]) {
+        Le::write_i32(bytes, self)
+    }
+}
+
+impl Sample for f32 {
+    const BYTES: usize = 4;
+    fn read(bytes: &[u8]) -> Self {
+        Le::read_f32(bytes)
+    }
+    fn write(self, bytes: &mut [u8]) {
+        Le::write_f32(bytes, self)
+    }
+}
+
+/// Typed layer over RingBytes that only moves whole interleaved frames of `channels`
+/// samples each, so no caller has to fix up partial frames by hand.
+pub struct RingFrames<S: Sample> {
+    _sample: PhantomData<S>,
+}
+
+impl<S: Sample> RingFrames<S> {
+    pub fn new(frames: usize, channels: usize) -> (FrameWriter<S>, FrameReader<S>) {
+        RingFrames::with_policy(frames, channels, OverflowPolicy::default())
+    }
+
+    pub fn with_policy(
+        frames: usize,
+        channels: usize,
+        policy: OverflowPolicy,
+    ) -> (FrameWriter<S>, FrameReader<S>) {
+        let (tx, rx) = RingBytes::with_policy(frames * channels * S::BYTES, policy);
+        (FrameWriter::new(tx, channels), FrameReader::new(rx, channels))
+    }
+}
+
+pub struct FrameReader<S: Sample> {
+    bytes: RingReader,
+    channels: usize,
+    _sample: PhantomData<S>,
+}
+
+impl<S: Sample> FrameReader<S> {
+    /// Wrap a byte reader whose writer only ever writes whole frames
+    pub fn new(bytes: RingReader, channels: usize) -> FrameReader<S> {
+        assert!(channels > 0);
+        FrameReader { bytes, channels, _sample: PhantomData }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn frame_bytes(&self) -> usize {
+        self.channels * S::BYTES
+    }
+
+    pub fn available_frames(&self) -> usize {
+        self.bytes.available() / self.frame_bytes()
+    }
+
+    /// Reads up to `frames` whole frames as interleaved samples
+    pub fn read_frames(&self, frames: usize) -> Vec<S> {
+        let frames = frames.min(self.available_frames());
+        self.decode(&self.bytes.read(frames * self.frame_bytes()))
+    }
+
+    /// Parks until `frames` whole frames are available and reads every whole frame
+    pub fn read_frames_at_least(&self, frames: usize, timeout: Duration) -> Option<Vec<S>> {
+        let frame_bytes = self.frame_bytes();
+        self.bytes
+            .read_aligned(frames * frame_bytes, frame_bytes, timeout)
+            .map(|bytes| self.decode(&bytes))
+    }
+
+    pub fn dropped_frames(&self) -> usize {
+        self.bytes.dropped() / self.frame_bytes()
+    }
+
+    /// Running sample-frame counter of the next frame to be read
+    pub fn position_frames(&self) -> u64 {
+        self.bytes.position() / self.frame_bytes() as u64
+    }
+
+    pub fn backlog(&self) -> Duration {
+        self.bytes.backlog()
+    }
+
+    pub fn stats(&self) -> RingStats {
+        self.bytes.stats()
+    }
+
+    pub fn writer_alive(&self) -> bool {
+        self.bytes.writer_alive()
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Vec<S> {
+        bytes.chunks_exact(S::BYTES).map(S::read).collect()
+    }
+}
+
+pub struct FrameWriter<S: Sample> {
+    bytes: RingWriter,
+    channels: usize,
+    partial: Vec<u8>, // bytes of an incomplete frame carried between raw writes
+    _sample: PhantomData<S>,
+}
+
+impl<S: Sample> FrameWriter<S> {
+    pub fn new(bytes: RingWriter, channels: usize) -> FrameWriter<S> {
+        assert!(channels > 0);
+        FrameWriter { bytes, channels, partial: Vec::new(), _sample: PhantomData }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn frame_bytes(&self) -> usize {
+        self.channels * S::BYTES
+    }
+
+    /// Whole frames that can be written without triggering the overflow policy
+    pub fn reserve_frames(&self, frames: usize) -> usize {
+        self.bytes.reserve(frames * self.frame_bytes()) / self.frame_bytes()
+    }
+
+    /// Writes interleaved samples.  Trailing samples of an incomplete frame are ignored.
+    pub fn write_frames(&self, samples: &[S]) {
+        let whole = samples.len() - samples.len() % self.channels;
+        let mut packed = vec![0u8; whole * S::BYTES];
+        for (sample, bytes) in samples[..whole].iter().zip(packed.chunks_exact_mut(S::BYTES)) {
+            sample.write(bytes);
+        }
+        self.bytes.write(&packed);
+    }
+
+    /// Writes raw little-endian bytes from a backend that may split frames across
+    /// callbacks.  Any incomplete trailing frame is held until the next write.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        let frame_bytes = self.frame_bytes();
+        if self.partial.is_empty() {
+            let whole = bytes.len() - bytes.len() % frame_bytes;
+            self.bytes.write(&bytes[..whole]);
+            self.partial.extend_from_slice(&bytes[whole..]);
+        } else {
+            self.partial.extend_from_slice(bytes);
+            let whole = self.partial.len() - self.partial.len() % frame_bytes;
+            self.bytes.write(&self.partial[..whole]);
+            self.partial.drain(..whole);
+        }
+    }
+
+    pub fn dropped_frames(&self) -> usize {
+        self.bytes.dropped() / self.frame_bytes()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
     use std::sync::mpsc;
     use std::thread;
     use std::time::Instant;
 
+    #[derive(Clone, Debug)]
+    enum Op {
+        Write(Vec<u8>),
+        Read(usize),
+        Reserve(usize),
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            vec(any::<u8>(), 0..24).prop_map(Op::Write),
+            (0..24_usize).prop_map(Op::Read),
+            (0..24_usize).prop_map(Op::Reserve),
+        ]
+    }
+
+    /// Reads until the writer is gone and nothing is left, `amounts` bytes at a time in turn
+    fn drain(rx: &RingReader, amounts: &[usize], capacity: usize) -> Vec<u8> {
+        let mut received = Vec::new();
+        for amount in amounts.iter().cycle() {
+            let available = rx.available();
+            assert!(available <= capacity, "{} bytes in a ring of {}", available, capacity);
+            // the writer may have written more just before it went
+            if available == 0 && !rx.writer_alive() && rx.available() == 0 {
+                break;
+            }
+            received.extend_from_slice(&rx.read((*amount).min(available)));
+            thread::yield_now();
+        }
+        received
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        /// Dropping the oldest bytes behaves like a bounded queue
+        #[test]
+        fn ops_follow_a_bounded_queue(capacity in 1..32_usize, ops in vec(op(), 0..64)) {
+            let (tx, rx) = RingBytes::with_policy(capacity, OverflowPolicy::DropOldest);
+            let mut model: VecDeque<u8> = VecDeque::new();
+            let mut dropped = 0;
+            let mut written = 0;
+            for op in ops {
+                match op {
+                    Op::Write(bytes) => {
+                        tx.write(&bytes);
+                        written += bytes.len();
+                        model.extend(bytes);
+                        while model.len() > capacity {
+                            model.pop_front();
+                            dropped += 1;
+                        }
+                    }
+                    Op::Read(amount) => {
+                        let amount = amount.min(rx.available());
+                        let expected: Vec<u8> = model.drain(..amount).collect();
+                        prop_assert_eq!(&rx.read(amount)[..], &expected[..]);
+                    }
+                    Op::Reserve(amount) => {
+                        prop_assert_eq!(tx.reserve(amount), capacity - model.len());
+                    }
+                }
+                prop_assert_eq!(rx.available(), model.len());
+                prop_assert_eq!(rx.dropped(), dropped);
+                let stats = rx.stats();
+                prop_assert_eq!(stats.written, written as u64);
+                prop_assert_eq!(stats.position + model.len() as u64, stats.written);
+            }
+        }
+
+        /// A blocked writer on another thread loses and reorders nothing
+        #[test]
+        fn blocked_writes_arrive_in_order(
+            capacity in 1..32_usize,
+            writes in vec(vec(any::<u8>(), 0..48), 0..24),
+            reads in vec(1..48_usize, 1..8),
+        ) {
+            let (tx, rx) = RingBytes::with_policy(capacity, OverflowPolicy::BlockWriter);
+            let sent: Vec<u8> = writes.iter().flatten().cloned().collect();
+            let handle = thread::spawn(move || {
+                for bytes in writes {
+                    tx.write(&bytes);
+                }
+            });
+            let received = drain(&rx, &reads, capacity);
+            handle.join().unwrap();
+            prop_assert_eq!(received, sent);
+            prop_assert_eq!(rx.dropped(), 0);
+        }
+
+        /// Frames dropped while the reader lags are the oldest, and the rest stay whole and in
+        /// order
+        #[test]
+        fn dropped_frames_leave_the_rest_in_order(
+            frames in 1..16_usize,
+            channels in 1..3_usize,
+            writes in vec(1..24_usize, 1..24),
+        ) {
+            let (tx, rx) = RingFrames::<i32>::new(frames, channels);
+            let total: usize = writes.iter().sum();
+            let handle = thread::spawn(move || {
+                let mut counter = 0;
+                for count in writes {
+                    let samples: Vec<i32> = (counter..counter + count as i32)
+                        .flat_map(|frame| vec![frame; channels])
+                        .collect();
+                    tx.write_frames(&samples);
+                    counter += count as i32;
+                }
+            });
+            let mut received = Vec::new();
+            while rx.writer_alive() || rx.available_frames() > 0 {
+                prop_assert!(rx.available_frames() <= frames);
+                if let Some(samples) = rx.read_frames_at_least(1, Duration::from_millis(1)) {
+                    for frame in samples.chunks_exact(channels) {
+                        prop_assert!(frame.iter().all(|sample| *sample == frame[0]));
+                        received.push(frame[0]);
+                    }
+                }
+            }
+            handle.join().unwrap();
+            prop_assert!(received.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", received);
+            prop_assert_eq!(received.len() + rx.dropped_frames(), total);
+            // the newest frame always survives
+            prop_assert_eq!(received.last().cloned(), Some(total as i32 - 1));
+        }
+    }
+
     #[test]
     pub fn send_a_megabyte() {
         let one_meg: usize = 1048576; // 1mb
@@ -120,4 +699,121 @@ mod tests {
         assert!(written == received);
         handle.join().unwrap();
     }
+
+    #[test]
+    pub fn drop_oldest_bounds_backlog() {
+        let (tx, rx) = RingBytes::with_policy(8, OverflowPolicy::DropOldest);
+        tx.write(&[0, 1, 2, 3, 4, 5]);
+        tx.write(&[6, 7, 8, 9]);
+        assert_eq!(rx.available(), 8);
+        assert_eq!(rx.dropped(), 2);
+        assert_eq!(&rx.read(8)[..], &[2, 3, 4, 5, 6, 7, 8, 9]);
+        tx.write(&[10, 11, 12, 13, 14, 15, 16, 17, 18, 19]);
+        assert_eq!(tx.dropped(), 4);
+        assert_eq!(&rx.read(8)[..], &[12, 13, 14, 15, 16, 17, 18, 19]);
+    }
+
+    #[test]
+    pub fn block_writer_waits_for_reader() {
+        let (tx, rx) = RingBytes::with_policy(4, OverflowPolicy::BlockWriter);
+        let handle = thread::spawn(move || {
+            tx.write(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+            tx
+        });
+        let mut received = Vec::new();
+        while received.len() < 10 {
+            let avail = rx.available();
+            assert!(avail <= 4);
+            received.extend_from_slice(&rx.read(avail));
+        }
+        let tx = handle.join().unwrap();
+        assert_eq!(received, vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        assert_eq!(tx.dropped(), 0);
+    }
+
+    #[test]
+    pub fn blocked_writer_outlives_reader() {
+        let (tx, rx) = RingBytes::with_policy(4, OverflowPolicy::BlockWriter);
+        let handle = thread::spawn(move || {
+            tx.write(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+            tx
+        });
+        while rx.available() < 4 {
+            thread::yield_now();
+        }
+        drop(rx);
+        let tx = handle.join().unwrap();
+        assert_eq!(tx.dropped(), 6);
+    }
+
+    #[test]
+    pub fn read_at_least_wakes_on_write() {
+        let (tx, rx) = RingBytes::new(64);
+        assert!(rx.read_at_least(4, Duration::from_millis(10)).is_none());
+        let handle = thread::spawn(move || {
+            for i in 0..4 {
+                thread::sleep(Duration::from_millis(5));
+                tx.write(&[i, i]);
+            }
+            tx
+        });
+        let read = rx.read_at_least(6, Duration::from_secs(5)).unwrap();
+        assert!(read.len() >= 6);
+        assert_eq!(&read[..6], &[0, 0, 1, 1, 2, 2]);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    pub fn frames_stay_aligned() {
+        let (mut tx, rx) = RingFrames::<i16>::new(4, 2);
+        assert_eq!(tx.frame_bytes(), 4);
+        tx.write_frames(&[1, -1, 2, -2, 3]);
+        assert_eq!(rx.available_frames(), 2);
+        // split a frame across raw writes
+        tx.write_bytes(&[3, 0, 253]);
+        assert_eq!(rx.available_frames(), 2);
+        tx.write_bytes(&[255]);
+        assert_eq!(rx.read_frames(8), vec![1, -1, 2, -2, 3, -3]);
+        // overflow drops whole frames
+        tx.write_frames(&[4, -4, 5, -5, 6, -6, 7, -7, 8, -8]);
+        assert_eq!(rx.dropped_frames(), 1);
+        let read = rx.read_frames_at_least(4, Duration::from_millis(10)).unwrap();
+        assert_eq!(read, vec![5, -5, 6, -6, 7, -7, 8, -8]);
+    }
+
+    #[test]
+    pub fn stats_track_position_and_backlog() {
+        let (tx, rx) = RingBytes::new(8);
+        assert_eq!(rx.backlog(), Duration::from_secs(0));
+        tx.write(&[0, 1, 2, 3]);
+        thread::sleep(Duration::from_millis(20));
+        tx.write(&[4, 5, 6, 7]);
+        assert!(rx.backlog() >= Duration::from_millis(20));
+        rx.read(4);
+        assert_eq!(rx.position(), 4);
+        assert!(rx.backlog() < Duration::from_millis(20));
+        tx.write(&[8, 9, 10, 11, 12, 13]);
+        let stats = rx.stats();
+        assert_eq!(stats.written, 14);
+        assert_eq!(stats.dropped, 2);
+        assert_eq!(stats.position, 6);
+        assert!(stats.write_rate > 0.0);
+        rx.read(8);
+        assert_eq!(rx.position(), 14);
+        assert_eq!(rx.backlog(), Duration::from_secs(0));
+    }
+
+    #[test]
+    pub fn panicking_writer_is_noticed() {
+        let (tx, rx) = RingBytes::new(8);
+        assert!(rx.writer_alive());
+        let handle = thread::spawn(move || {
+            tx.write(&[1, 2]);
+            panic!("capture failed");
+        });
+        assert!(handle.join().is_err());
+        assert!(!rx.writer_alive());
+        // what was written before the panic can still be read
+        assert_eq!(&rx.read_at_least(2, Duration::from_millis(10)).unwrap()[..], &[1, 2]);
+    }
 }
@@ -0,0 +1,248 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! A frequency ruler along the left edge of the spectrogram, and the frequency and level
+//! under the pointer, for a quick look at what's playing.  The history's rows are log spaced
+//! from the lowest frequency drawn at the bottom to the highest at the top.  Stereo
+//! spectrograms mirror them, lowest in the middle.
+
+use crate::config::SpectrumLayout;
+use crate::errors::ENguyenError;
+use crate::geometry::quad;
+use crate::input::{MousePos, UserEvent};
+use crate::meters::LABEL_SIZE;
+use crate::rendering::{SolidPainter, TextBlock, XyVertex};
+use crate::scenes::labelled;
+
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DynamicState};
+
+/// Frequencies marked, Hz
+static MARKS: [f32; 10] =
+    [20.0, 50.0, 100.0, 200.0, 500.0, 1000.0, 2000.0, 5000.0, 10000.0, 20000.0];
+/// Size of each mark, as a share of the spectrogram
+static MARK_WIDTH: f32 = 0.02;
+static MARK_HEIGHT: f32 = 0.003;
+static MARK_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 0.6];
+static READOUT_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+/// Pixels from the pointer to the readout's baseline
+static READOUT_OFFSET: [f32; 2] = [16.0, -8.0];
+/// Level at the bottom of the readout's scale, as `features::level`
+static FLOOR: f32 = -60.0;
+
+/// The ruler and pointer readout over a spectrogram
+pub struct FrequencyRuler {
+    /// Frequencies of the lowest and highest rows, Hz
+    range: [f32; 2],
+    mirror: bool,
+    /// Logical pixels.  None while the pointer is outside the window.
+    pointer: Option<[f32; 2]>,
+    /// Levels of log spaced bands across `range`, lowest first
+    levels: Vec<f32>,
+}
+
+impl FrequencyRuler {
+    pub fn new(layout: SpectrumLayout) -> FrequencyRuler {
+        let mirror = layout == SpectrumLayout::Stereo;
+        FrequencyRuler { range: [0.0; 2], mirror, pointer: None, levels: Vec::new() }
+    }
+
+    /// Follow a window analyzed across `range` into `levels`
+    pub fn update(&mut self, range: [f32; 2], levels: &[f32]) {
+        self.range = range;
+        self.levels.clear();
+        self.levels.extend_from_slice(levels);
+    }
+
+    /// Follows the pointer.  Never uses the event, so it still scrubs the history.
+    pub fn handle(&mut self, event: &UserEvent) {
+        if let UserEvent::MouseMove { pos } = event {
+            self.pointer = match pos {
+                MousePos::Pos { x, y } => Some([*x as f32, *y as f32]),
+                MousePos::NoPos => None,
+            };
+        }
+    }
+
+    /// The pointer left the window
+    pub fn leave(&mut self) {
+        self.pointer = None;
+    }
+
+    /// Records the marks over `area` of `state`'s viewport, as shares of it: left, top, width
+    /// and height
+    pub fn draw(
+        &self,
+        painter: &SolidPainter,
+        cbb: AutoCommandBufferBuilder,
+        state: &DynamicState,
+        area: [f32; 4],
+    ) -> Result<AutoCommandBufferBuilder, ENguyenError> {
+        let [left, top, width, height] = area;
+        let x = |share: f32| (left + share * width) * 2.0 - 1.0;
+        let y = |share: f32| (top + share * height) * 2.0 - 1.0;
+        let mut vertices = Vec::new();
+        for down in self.marked().iter().flat_map(|(_hz, downs)| downs) {
+            let mark = quad(x(0.0), y(*down), x(MARK_WIDTH), y(down + MARK_HEIGHT));
+            vertices.extend_from_slice(&mark);
+        }
+        if vertices.is_empty() {
+            return Ok(cbb);
+        }
+        painter.triangles(cbb, state, vertices, MARK_COLOR)
+    }
+
+    /// Mark labels and the pointer readout, for a spectrogram covering `area` of a window
+    /// `window` logical pixels across, as `draw`
+    pub fn text(&self, window: [f32; 2], area: [f32; 4]) -> Vec<TextBlock> {
+        let [left, top, width, height] =
+            [area[0] * window[0], area[1] * window[1], area[2] * window[0], area[3] * window[1]];
+        let mut labels = Vec::new();
+        for (hz, downs) in self.marked() {
+            for down in downs {
+                let at = [left + width * MARK_WIDTH * 1.5, top + height * down + LABEL_SIZE / 3.0];
+                labels.push((short_hz(hz), at));
+            }
+        }
+        let mut text = labelled(labels);
+        let pointer = self
+            .pointer
+            .filter(|[x, y]| (left..left + width).contains(x) && (top..top + height).contains(y));
+        if let Some([x, y]) = pointer {
+            if let Some(readout) = self.readout((y - top) / height) {
+                let at = [x + READOUT_OFFSET[0], y + READOUT_OFFSET[1]];
+                text.push(TextBlock::new(readout, at, LABEL_SIZE, READOUT_COLOR));
+            }
+        }
+        text
+    }
+
+    /// The frequency and level `down` the spectrogram, a share of its height
+    fn readout(&self, down: f32) -> Option<String> {
+        let share = self.share_up(down);
+        let hz = self.frequency(share)?;
+        let bins = self.levels.len();
+        let level = match bins {
+            0 => return Some(precise_hz(hz)),
+            _ => self.levels[((share * (bins - 1) as f32).round() as usize).min(bins - 1)],
+        };
+        let db = if level <= 0.0 {
+            format!("<{}dB", FLOOR)
+        } else {
+            format!("{:.0}dB", FLOOR - FLOOR * level)
+        };
+        Some(format!("{}  {}", precise_hz(hz), db))
+    }
+
+    /// Each frequency marked with how far down the spectrogram its marks are
+    fn marked(&self) -> Vec<(f32, Vec<f32>)> {
+        let [lowest, highest] = self.range;
+        if lowest <= 0.0 || highest <= lowest {
+            return Vec::new();
+        }
+        let span = (highest / lowest).ln();
+        MARKS
+            .iter()
+            .filter(|hz| (lowest..=highest).contains(*hz))
+            .map(|hz| {
+                let up = (hz / lowest).ln() / span;
+                // the lowest row is marked once, where the halves meet
+                let downs = if self.mirror && up > 0.0 {
+                    vec![0.5 - up / 2.0, 0.5 + up / 2.0]
+                } else if self.mirror {
+                    vec![0.5]
+                } else {
+                    vec![1.0 - up]
+                };
+                (*hz, downs)
+            })
+            .collect()
+    }
+
+    /// Share of the way from the lowest row to the highest, `down` the spectrogram
+    fn share_up(&self, down: f32) -> f32 {
+        if self.mirror {
+            ((0.5 - down).abs() * 2.0).min(1.0)
+        } else {
+            1.0 - down
+        }
+    }
+
+    /// Frequency of the row `share` of the way from the lowest to the highest
+    fn frequency(&self, share: f32) -> Option<f32> {
+        let [lowest, highest] = self.range;
+        if lowest <= 0.0 || highest <= lowest {
+            return None;
+        }
+        Some(lowest * (highest / lowest).powf(share))
+    }
+}
+
+/// Mark labels, such as 50 and 2k
+fn short_hz(hz: f32) -> String {
+    if hz >= 1000.0 {
+        format!("{}k", hz / 1000.0)
+    } else {
+        format!("{}", hz)
+    }
+}
+
+/// The readout's frequency, to about a percent
+fn precise_hz(hz: f32) -> String {
+    if hz >= 1000.0 {
+        format!("{:.2}kHz", hz / 1000.0)
+    } else {
+        format!("{:.0}Hz", hz)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marks_and_readout_follow_the_rows() {
+        let mut ruler = FrequencyRuler::new(SpectrumLayout::Combined);
+        assert!(ruler.text([1000.0, 500.0], [0.0, 0.0, 1.0, 1.0]).is_empty());
+        // a decade, 100Hz at the bottom to 1kHz at the top, with a -30dB middle
+        ruler.update([100.0, 1000.0], &[0.0, 0.5, 1.0]);
+        let marked = ruler.marked();
+        let hz: Vec<f32> = marked.iter().map(|(hz, _)| *hz).collect();
+        assert_eq!(hz, vec![100.0, 200.0, 500.0, 1000.0]);
+        assert_eq!(marked[0].1, vec![1.0]);
+        assert_eq!(marked[3].1, vec![0.0]);
+        assert_eq!(ruler.readout(0.5), Some("316Hz  -30dB".to_owned()));
+        assert_eq!(ruler.readout(1.0), Some("100Hz  <-60dB".to_owned()));
+
+        // the readout shows only over the spectrogram, the bottom half in the studio
+        let studio = [0.0, 0.5, 1.0, 0.5];
+        let at = |x: f64, y: f64| UserEvent::MouseMove { pos: MousePos::Pos { x, y } };
+        ruler.handle(&at(500.0, 100.0));
+        let labels = ruler.text([1000.0, 500.0], studio);
+        assert_eq!(labels.len(), 4);
+        assert_eq!(labels[1].text, "200");
+        ruler.handle(&at(500.0, 375.0));
+        let labels = ruler.text([1000.0, 500.0], studio);
+        assert_eq!(labels.last().unwrap().text, "316Hz  -30dB");
+        ruler.leave();
+        assert_eq!(ruler.text([1000.0, 500.0], studio).len(), 4);
+
+        // stereo mirrors the rows, lowest in the middle
+        let mut stereo = FrequencyRuler::new(SpectrumLayout::Stereo);
+        stereo.update([100.0, 1000.0], &[]);
+        assert_eq!(stereo.marked()[0].1, vec![0.5]);
+        assert_eq!(stereo.marked()[3].1, vec![0.0, 1.0]);
+        assert_eq!(stereo.readout(0.0), stereo.readout(1.0));
+    }
+}
@@ -0,0 +1,329 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! What the visualizer draws.  Every scene is a `Scene` trait object, so the visualizer keeps
+//! them in one list and draws whichever the parameters select.
+
+use crate::config::{SpectrumLayout, Tonemap};
+use crate::errors::ENguyenError;
+use crate::features::AudioFeatures;
+use crate::input::UserEvent;
+use crate::meters::LABEL_SIZE;
+use crate::params;
+use crate::params::Palette;
+use crate::rendering::{
+    uv_image_vsm, uv_scroll_fsm, PipelineRegistry, SolidPainter, TextBlock, XyUvVertex, XyVertex,
+};
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use vulkano::buffer::ImmutableBuffer;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DynamicState};
+use vulkano::descriptor::descriptor_set::DescriptorSet;
+use vulkano::pipeline::blend::AttachmentBlend;
+use vulkano::pipeline::{GraphicsPipeline, GraphicsPipelineAbstract};
+
+static LABEL_COLOR: [f32; 4] = [0.7, 0.7, 0.7, 1.0];
+
+/// How long scenes take to fade in once the analysis is warm
+static FADE_IN: Duration = Duration::from_millis(500);
+
+/// What a scene draws with this frame
+pub struct SceneFrame<'a> {
+    pub painter: &'a SolidPainter,
+    /// Viewport the scene covers
+    pub state: &'a DynamicState,
+    /// `FrameUniforms` for set 0 of scene pipelines
+    pub uniforms: Arc<dyn DescriptorSet + Send + Sync>,
+    /// The newest analysis window
+    pub features: &'a AudioFeatures,
+    pub palette: Palette,
+    pub tonemap: Tonemap,
+    /// Where the spectrogram's leading edge is in its history, 0.0 to 1.0
+    pub scroll: f32,
+    /// Share of the history shown across the spectrogram
+    pub span: f32,
+}
+
+/// One visualization.  Only `render` is required.
+pub trait Scene {
+    /// Follow an analysis window measured `elapsed` after the one before
+    fn update(&mut self, _features: &AudioFeatures, _elapsed: Duration) {}
+
+    /// The window is now `window` logical pixels
+    fn resize(&mut self, _window: [f32; 2]) {}
+
+    /// Input while the scene is shown.  True if the scene used it.
+    fn handle_event(&mut self, _event: &UserEvent) -> bool {
+        false
+    }
+
+    /// Text drawn over the scene, in logical pixels
+    fn text(&mut self) -> Vec<TextBlock> {
+        Vec::new()
+    }
+
+    /// Records the scene.  The render pass has begun.
+    fn render(
+        &mut self,
+        cbb: AutoCommandBufferBuilder,
+        frame: &SceneFrame,
+    ) -> Result<AutoCommandBufferBuilder, ENguyenError>;
+}
+
+/// Every scene, with the `params::Scene` that selects it
+pub struct SceneManager {
+    scenes: Vec<(params::Scene, Box<dyn Scene>)>,
+    /// Logical size the scenes were last told about
+    window: Option<[f32; 2]>,
+    /// When the analysis had its first full window of audio.  Scenes are veiled until then.
+    warm_since: Option<Instant>,
+}
+
+impl SceneManager {
+    pub fn new() -> SceneManager {
+        SceneManager { scenes: Vec::new(), window: None, warm_since: None }
+    }
+
+    /// The analysis has been warm since `since`.  A later time, after a restart, fades the
+    /// scenes in again.
+    pub fn warmed(&mut self, since: Instant) {
+        self.warm_since = Some(since);
+    }
+
+    /// How much of the scenes shows at `now`, from 0.0 until the analysis is warm to 1.0 once
+    /// they've faded in
+    pub fn shown(&self, now: Instant) -> f32 {
+        let since = match self.warm_since {
+            Some(since) => since,
+            None => return 0.0,
+        };
+        let faded = now.saturating_duration_since(since).as_secs_f32() / FADE_IN.as_secs_f32();
+        let faded = faded.min(1.0);
+        faded * faded * (3.0 - 2.0 * faded)
+    }
+
+    /// Covers the scenes in `color` as much as they haven't faded in, or fades them toward
+    /// transparent if `color` is
+    pub fn veil(
+        &self,
+        cbb: AutoCommandBufferBuilder,
+        painter: &SolidPainter,
+        state: &DynamicState,
+        color: [f32; 4],
+        now: Instant,
+    ) -> Result<AutoCommandBufferBuilder, ENguyenError> {
+        let shown = self.shown(now);
+        if shown >= 1.0 {
+            return Ok(cbb);
+        }
+        let corners = [[-1.0, -1.0], [1.0, -1.0], [-1.0, 1.0], [1.0, 1.0]];
+        let vertices = [0, 1, 2, 2, 1, 3].iter().map(|&i| XyVertex { position: corners[i] });
+        let [red, green, blue, alpha] = color;
+        if alpha < 1.0 {
+            // a transparent window fades to the desktop behind it
+            return painter.fade(cbb, state, vertices.collect(), 1.0 - shown);
+        }
+        painter.triangles(cbb, state, vertices.collect(), [red, green, blue, 1.0 - shown])
+    }
+
+    /// Draw `scene` when `kind` is selected, replacing any scene added for it before
+    pub fn add(&mut self, kind: params::Scene, scene: Box<dyn Scene>) {
+        self.scenes.retain(|(added, _)| *added != kind);
+        self.scenes.push((kind, scene));
+    }
+
+    pub fn get(&mut self, kind: params::Scene) -> Option<&mut (dyn Scene + 'static)> {
+        self.scenes.iter_mut().find(|(added, _)| *added == kind).map(|(_, scene)| scene.as_mut())
+    }
+
+    /// Every scene follows the music, shown or not, so none jumps when switched to
+    pub fn update(&mut self, features: &AudioFeatures, elapsed: Duration) {
+        for (_, scene) in &mut self.scenes {
+            scene.update(features, elapsed);
+        }
+    }
+
+    /// Tells every scene about `window` if the size changed
+    pub fn resize(&mut self, window: [f32; 2]) {
+        if self.window == Some(window) {
+            return;
+        }
+        self.window = Some(window);
+        for (_, scene) in &mut self.scenes {
+            scene.resize(window);
+        }
+    }
+}
+
+impl Default for SceneManager {
+    fn default() -> SceneManager {
+        SceneManager::new()
+    }
+}
+
+/// Scale and axis labels in the label size and color
+pub fn labelled(labels: Vec<(String, [f32; 2])>) -> Vec<TextBlock> {
+    labels.into_iter().map(|(text, at)| TextBlock::new(text, at, LABEL_SIZE, LABEL_COLOR)).collect()
+}
+
+/// A pipeline drawing a `Spectrogram`, blended over what's drawn with `blend`.  Different
+/// blends need different names.
+pub fn spectrogram_pipeline(
+    pipelines: &PipelineRegistry,
+    name: &'static str,
+    blend: AttachmentBlend,
+) -> Result<Arc<dyn GraphicsPipelineAbstract + Send + Sync>, ENguyenError> {
+    pipelines.get(name, |device, subpass| {
+        let vs = uv_image_vsm::Shader::load(device.clone())
+            .map_err(|e| ENguyenError::render("Spectrogram shader failed").caused_by(e))?;
+        let fs = uv_scroll_fsm::Shader::load(device.clone())
+            .map_err(|e| ENguyenError::render("Spectrogram shader failed").caused_by(e))?;
+        let pipeline = GraphicsPipeline::start()
+            .triangle_strip()
+            .vertex_input_single_buffer::<XyUvVertex>()
+            .vertex_shader(vs.main_entry_point(), ())
+            .viewports_dynamic_scissors_irrelevant(1)
+            .fragment_shader(fs.main_entry_point(), ())
+            .blend_collective(blend)
+            .render_pass(subpass)
+            .build(device.clone())
+            .map_err(|e| ENguyenError::render("Spectrogram pipeline failed").caused_by(e))?;
+        Ok(Arc::new(pipeline))
+    })
+}
+
+/// The analysis texture's history scrolling across the viewport
+#[derive(Clone)]
+pub struct Spectrogram {
+    pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    /// The analysis texture, at set 1
+    texture: Arc<dyn DescriptorSet + Send + Sync>,
+    rect: Arc<ImmutableBuffer<[XyUvVertex]>>,
+    /// A stereo texture is drawn mirrored, left above right
+    layout: SpectrumLayout,
+}
+
+impl Spectrogram {
+    pub fn new(
+        pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+        texture: Arc<dyn DescriptorSet + Send + Sync>,
+        rect: Arc<ImmutableBuffer<[XyUvVertex]>>,
+        layout: SpectrumLayout,
+    ) -> Spectrogram {
+        Spectrogram { pipeline, texture, rect, layout }
+    }
+
+    /// Records the spectrogram over `state`'s viewport
+    pub fn draw(
+        &self,
+        cbb: AutoCommandBufferBuilder,
+        state: &DynamicState,
+        frame: &SceneFrame,
+    ) -> Result<AutoCommandBufferBuilder, ENguyenError> {
+        let [red, green, blue] = frame.palette.weights();
+        let push_constants = uv_scroll_fsm::ty::PushConstant {
+            offset_fac: frame.scroll,
+            mirror: (self.layout == SpectrumLayout::Stereo) as u32,
+            red,
+            green,
+            blue,
+            span: frame.span,
+            tonemap: frame.tonemap.curve(),
+        };
+        let sets = (frame.uniforms.clone(), self.texture.clone());
+        cbb.draw(self.pipeline.clone(), state, vec![self.rect.clone()], sets, push_constants)
+            .map_err(|e| ENguyenError::render("Spectrogram draw failed").caused_by(e))
+    }
+}
+
+impl Scene for Spectrogram {
+    fn render(
+        &mut self,
+        cbb: AutoCommandBufferBuilder,
+        frame: &SceneFrame,
+    ) -> Result<AutoCommandBufferBuilder, ENguyenError> {
+        self.draw(cbb, frame.state, frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /// Counts what it's told
+    struct Counting {
+        updates: Rc<Cell<usize>>,
+        resizes: Rc<Cell<usize>>,
+    }
+
+    impl Scene for Counting {
+        fn update(&mut self, _features: &AudioFeatures, _elapsed: Duration) {
+            self.updates.set(self.updates.get() + 1);
+        }
+
+        fn resize(&mut self, _window: [f32; 2]) {
+            self.resizes.set(self.resizes.get() + 1);
+        }
+
+        fn render(
+            &mut self,
+            cbb: AutoCommandBufferBuilder,
+            _frame: &SceneFrame,
+        ) -> Result<AutoCommandBufferBuilder, ENguyenError> {
+            Ok(cbb)
+        }
+    }
+
+    #[test]
+    fn scenes_are_kept_together() {
+        let (updates, resizes) = (Rc::new(Cell::new(0)), Rc::new(Cell::new(0)));
+        let counting = || Counting { updates: updates.clone(), resizes: resizes.clone() };
+        let mut scenes = SceneManager::new();
+        scenes.add(params::Scene::Meters, Box::new(counting()));
+        scenes.add(params::Scene::Piano, Box::new(counting()));
+        scenes.add(params::Scene::Piano, Box::new(counting()));
+
+        scenes.update(&AudioFeatures::default(), Duration::from_millis(16));
+        assert_eq!(updates.get(), 2);
+        scenes.resize([640.0, 480.0]);
+        scenes.resize([640.0, 480.0]);
+        assert_eq!(resizes.get(), 2);
+        scenes.resize([800.0, 600.0]);
+        assert_eq!(resizes.get(), 4);
+
+        assert!(scenes.get(params::Scene::Meters).is_some());
+        assert!(scenes.get(params::Scene::Tunnel).is_none());
+        let key = UserEvent::KeyPress { character: 'x' };
+        assert!(!scenes.get(params::Scene::Piano).unwrap().handle_event(&key));
+    }
+
+    #[test]
+    fn scenes_fade_in_once_warm() {
+        let mut scenes = SceneManager::new();
+        let start = Instant::now();
+        assert_eq!(scenes.shown(start), 0.0);
+        scenes.warmed(start);
+        assert_eq!(scenes.shown(start), 0.0);
+        let halfway = scenes.shown(start + FADE_IN / 2);
+        assert!(halfway > 0.0 && halfway < 1.0, "{}", halfway);
+        assert_eq!(scenes.shown(start + FADE_IN), 1.0);
+        // a restart fades in again
+        scenes.warmed(start + FADE_IN * 4);
+        assert_eq!(scenes.shown(start + FADE_IN * 4), 0.0);
+    }
+}
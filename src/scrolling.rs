@@ -0,0 +1,194 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! History that scrolls without moving.  The analysis shader writes each column into the
+//! storage image after the one before, wrapping around, and draws shift their UVs by where the
+//! newest column is.  Nothing is copied as the history scrolls.  Columns kept past those
+//! shown at once can be scrubbed back to.
+
+use crate::config::HistoryFormat;
+use crate::errors::ENguyenError;
+
+use log::warn;
+use std::sync::Arc;
+use vulkano::device::Device;
+use vulkano::format::Format;
+use vulkano::image::{Dimensions, ImageUsage, StorageImage};
+
+impl HistoryFormat {
+    pub fn format(self) -> Format {
+        match self {
+            HistoryFormat::Rgba32f => Format::R32G32B32A32Sfloat,
+            HistoryFormat::Rgba16f => Format::R16G16B16A16Sfloat,
+            HistoryFormat::R11g11b10f => Format::B10G11R11UfloatPack32,
+        }
+    }
+
+    /// Format of each channel's own levels, as precise as the history
+    pub fn channel_format(self) -> Format {
+        match self {
+            HistoryFormat::Rgba32f => Format::R32Sfloat,
+            _ => Format::R16Sfloat,
+        }
+    }
+
+    /// Whether storing the history or its channel images needs a device created with
+    /// extended storage formats
+    fn extended(self) -> bool {
+        self != HistoryFormat::Rgba32f
+    }
+
+    /// The next larger format, which more GPUs can store to.  Every Vulkan GPU stores both
+    /// RGBA float formats.
+    fn fallback(self) -> Option<HistoryFormat> {
+        match self {
+            HistoryFormat::Rgba32f => None,
+            HistoryFormat::Rgba16f => Some(HistoryFormat::Rgba32f),
+            HistoryFormat::R11g11b10f => Some(HistoryFormat::Rgba16f),
+        }
+    }
+}
+
+/// A ring of `columns` analysis columns of `rows` texels each, `shown` at a time
+pub struct ScrollingTexture {
+    image: Arc<StorageImage<Format>>,
+    format: HistoryFormat,
+    columns: u32,
+    shown: u32,
+    rows: u32,
+}
+
+impl ScrollingTexture {
+    /// Usable from every queue family of `device`, so analysis and drawing can be on different
+    /// queues
+    pub fn new(
+        device: &Arc<Device>,
+        columns: u32,
+        shown: u32,
+        rows: u32,
+    ) -> Result<ScrollingTexture, ENguyenError> {
+        ScrollingTexture::with_format(device, columns, shown, rows, HistoryFormat::Rgba32f)
+    }
+
+    /// In `format`, or the next larger format the GPU can store to
+    pub fn with_format(
+        device: &Arc<Device>,
+        columns: u32,
+        shown: u32,
+        rows: u32,
+        format: HistoryFormat,
+    ) -> Result<ScrollingTexture, ENguyenError> {
+        assert!(shown <= columns);
+        if format.extended() && !device.enabled_features().shader_storage_image_extended_formats {
+            if let Some(fallback) = format.fallback() {
+                warn!("{:?} history needs extended formats.  Using {:?}.", format, fallback);
+                return ScrollingTexture::with_format(device, columns, shown, rows, fallback);
+            }
+        }
+        let created = StorageImage::with_usage(
+            device.clone(),
+            Dimensions::Dim2d { width: columns, height: rows },
+            format.format(),
+            ImageUsage {
+                storage: true,
+                sampled: true,
+                transfer_destination: true,
+                ..ImageUsage::none()
+            },
+            device.active_queue_families(),
+        );
+        match (created, format.fallback()) {
+            (Ok(image), _) => Ok(ScrollingTexture { image, format, columns, shown, rows }),
+            (Err(e), Some(fallback)) => {
+                warn!("{:?} history isn't supported: {}.  Using {:?}.", format, e, fallback);
+                ScrollingTexture::with_format(device, columns, shown, rows, fallback)
+            },
+            (Err(e), None) => {
+                Err(ENguyenError::compute("History texture creation failed").caused_by(e))
+            },
+        }
+    }
+
+    /// The format the history was created in, after any fallback
+    pub fn format(&self) -> HistoryFormat {
+        self.format
+    }
+
+    /// The storage image, for the analysis shader to write and scenes to sample
+    pub fn image(&self) -> Arc<StorageImage<Format>> {
+        self.image.clone()
+    }
+
+    pub fn columns(&self) -> u32 {
+        self.columns
+    }
+
+    pub fn shown(&self) -> u32 {
+        self.shown
+    }
+
+    /// Columns kept beyond those shown, how far back the history can be scrubbed
+    pub fn depth(&self) -> u32 {
+        self.columns - self.shown
+    }
+
+    pub fn rows(&self) -> u32 {
+        self.rows
+    }
+
+    /// The column written after `column`
+    pub fn next_column(&self, column: u32) -> u32 {
+        (column + 1) % self.columns
+    }
+
+    /// The column `behind` columns before `newest`
+    pub fn back_from(&self, newest: u32, behind: u32) -> u32 {
+        (newest + self.columns - behind % self.columns) % self.columns
+    }
+
+    /// UV offset that puts `newest` at the leading edge of the history
+    pub fn scroll(&self, newest: u32) -> f32 {
+        self.next_column(newest) as f32 / self.columns as f32
+    }
+
+    /// Share of the texture's width shown at once
+    pub fn span(&self) -> f32 {
+        self.shown as f32 / self.columns as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ewin::GpuPicker;
+
+    #[test]
+    fn columns_wrap() {
+        let picker = GpuPicker::new(false).unwrap();
+        let (device, _) = picker.windowless_device().unwrap();
+        let history = ScrollingTexture::new(&device, 4, 2, 16).unwrap();
+        assert_eq!((history.columns(), history.rows(), history.depth()), (4, 16, 2));
+        assert_eq!(history.next_column(2), 3);
+        assert_eq!(history.next_column(3), 0);
+        assert_eq!(history.back_from(1, 2), 3);
+        assert_eq!(history.scroll(1), 0.5);
+        assert_eq!(history.scroll(3), 0.0);
+        assert_eq!((history.shown(), history.span()), (2, 0.5));
+
+        let packed = ScrollingTexture::with_format(&device, 4, 2, 16, HistoryFormat::R11g11b10f);
+        let extended = device.enabled_features().shader_storage_image_extended_formats;
+        assert_eq!(packed.unwrap().format() != HistoryFormat::Rgba32f, extended);
+    }
+}
@@ -13,34 +13,40 @@
 
 // Copyright 2019 E-Nguyen Developers.
 
-use crate::application::SettingsLauncher;
-use crate::errors::{FrameError, VulkanoError};
-use crate::ewin::{GpuPicker, SwapWindow};
+use crate::application::{SettingsLauncher, SharedState, WindowCommand};
+use crate::audio;
+use crate::audio::PaStream;
+use crate::config::ENguyenConfig;
+use crate::errors::{ENguyenError, FrameError};
+use crate::ewin;
+use crate::ewin::{Capabilities, GpuPicker, SwapWindow};
+use crate::geometry::{pixel_rect, static_vertices};
 use crate::input;
 use crate::input::{KeyTracker, MouseTracker, UserEvent};
+use crate::layers::ImageLayer;
+use crate::params::{ParamChange, Parameters};
 use crate::rendering::{
-    diag_grad_fsm, diag_grad_vsm, uv_image_fsm, uv_image_vsm, FrameState, Framer, XyUvVertex,
-    XyVertex,
+    gradient_pipeline, ui_font, PipelineRegistry, TextBlock, TextRenderer, XyVertex,
 };
 
 use image;
 use image::ImageFormat;
-use log::error;
-use rusttype::{point, Font, Scale};
-use std::error::Error;
+use log::{debug, warn};
+use rusttype::Font;
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
 use std::sync::Arc;
-use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer};
+use std::thread;
+use std::time::Duration;
+use vulkano::buffer::ImmutableBuffer;
 use vulkano::command_buffer::AutoCommandBufferBuilder;
-use vulkano::descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet};
-use vulkano::format::Format;
+use vulkano::device::Device;
 use vulkano::framebuffer::{FramebufferAbstract, RenderPassAbstract, Subpass};
-use vulkano::image::{Dimensions, ImmutableImage};
-use vulkano::pipeline::blend::AttachmentBlend;
-use vulkano::pipeline::{GraphicsPipeline, GraphicsPipelineAbstract};
-use vulkano::sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode};
+use vulkano::instance::PhysicalDevice;
+use vulkano::pipeline::GraphicsPipelineAbstract;
+use vulkano::swapchain::PresentMode;
 use vulkano::sync;
-use vulkano::sync::{FlushError, GpuFuture};
-use vulkano_glyph::{GlyphBrush, Section};
+use vulkano::sync::GpuFuture;
 use vulkano_win::VkSurfaceBuild;
 use winit;
 use winit::dpi::LogicalSize;
@@ -48,85 +54,506 @@ use winit::Icon;
 
 pub static HEIGHT: u32 = 600;
 pub static WIDTH: u32 = 370;
-pub static LOGO_WIDTH: u32 = 201;
-pub static LOGO_HEIGHT: u32 = 121;
-
-pub fn settings_ui(launcher: &SettingsLauncher) -> Result<(), VulkanoError> {
-    let picker = launcher.picker.clone();
-
-    let icon_data = include_bytes!("../logo/icon.png");
-
-    let mut events_loop = winit::EventsLoop::new();
-    let ldim = LogicalSize::from((WIDTH, HEIGHT));
-    let surface = winit::WindowBuilder::new()
-        .with_dimensions(ldim)
-        .with_resizable(false)
-        .with_window_icon(Icon::from_bytes(icon_data).ok())
-        .with_title("E-Nguyen Settings")
-        .build_vk_surface(&events_loop, picker.instance.clone())
-        .unwrap();
-
-    let mut swap_win = SwapWindow::new(&picker, &surface)?;
-    let resources = SettingsResources::new()?;
-    let (mut framer, mut frame_state): (SettingsFramer, SettingsState) =
-        SettingsFramer::new(&mut swap_win, &resources)?;
-
-    let mut mt = MouseTracker::new();
-    let mut kt = KeyTracker::new();
-    let mut done = false;
-    loop {
-        let result = framer.render_one(&mut swap_win, frame_state, &resources);
-        match result {
-            Ok(new_state) => {
-                frame_state = new_state;
+/// Pixels between the top of the window and the logo
+static LOGO_TOP: f32 = 24.0;
+/// Gain changes by this factor per key press
+static GAIN_STEP: f32 = 1.25;
+/// How long onboarding listens to each audio source
+static LISTEN: Duration = Duration::from_millis(750);
+/// Sources peaking above this are playing something
+static HEARD_LEVEL: f32 = 0.01;
+/// Longest device name that fits across the window
+static NAME_CHARS: usize = 26;
+static TITLE_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+static BODY_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+/// Settings the GPU can't do, greyed out
+static UNSUPPORTED_COLOR: [f32; 4] = [0.5, 0.5, 0.5, 1.0];
+static UNSUPPORTED: &str = " (unsupported)";
+
+/// The settings window.  Borrows the font it renders with from the App, which outlives it.
+pub struct SettingsWindow<'r> {
+    launcher: SettingsLauncher,
+    swap_win: SwapWindow,
+    framer: SettingsFramer<'r>,
+    frame_state: SettingsState,
+    mt: MouseTracker,
+    kt: KeyTracker,
+    onboarding: Option<Onboarding>,
+    loop_test: LoopTest,
+    tested: Option<Receiver<LoopTest>>,
+    /// What the GPU can draw, for greying out settings it can't
+    capabilities: Capabilities,
+    pub done: bool,
+}
+
+impl<'r> SettingsWindow<'r> {
+    /// Walks the user through `onboarding` first, if given
+    pub fn new(
+        launcher: SettingsLauncher,
+        events_loop: &winit::EventsLoop,
+        resources: &'r SettingsResources<'r>,
+        onboarding: Option<Onboarding>,
+    ) -> Result<SettingsWindow<'r>, ENguyenError> {
+        let picker = launcher.picker.clone();
+
+        let icon_data = include_bytes!("../logo/icon.png");
+
+        let ldim = LogicalSize::from((WIDTH, HEIGHT));
+        let surface = winit::WindowBuilder::new()
+            .with_dimensions(ldim)
+            .with_resizable(false)
+            .with_window_icon(Icon::from_bytes(icon_data).ok())
+            .with_title("E-Nguyen Settings")
+            .build_vk_surface(events_loop, picker.instance.clone())
+            .unwrap();
+
+        let mut swap_win = SwapWindow::new(&picker, &surface, PresentMode::Fifo, false)?;
+        let (framer, frame_state): (SettingsFramer, SettingsState) =
+            SettingsFramer::new(&mut swap_win, resources)?;
+
+        let capabilities = Capabilities::of(&swap_win.device);
+        Ok(SettingsWindow {
+            launcher,
+            swap_win,
+            framer,
+            frame_state,
+            mt: MouseTracker::new(),
+            kt: KeyTracker::new(),
+            onboarding,
+            loop_test: LoopTest::Untested,
+            tested: None,
+            capabilities,
+            done: false,
+        })
+    }
+
+    pub fn id(&self) -> winit::WindowId {
+        self.swap_win.surface.window().id()
+    }
+
+    pub fn handle(&mut self, ev: &winit::Event, shared: &mut SharedState) {
+        match &ev {
+            winit::Event::WindowEvent { event: winit::WindowEvent::CloseRequested, .. } => {
+                self.done = true
+            },
+            winit::Event::WindowEvent { event: winit::WindowEvent::Resized(_), .. } => {
+                self.frame_state.recreate_swapchain = true;
+            },
+            winit::Event::WindowEvent {
+                event: winit::WindowEvent::HiDpiFactorChanged(factor),
+                ..
+            } => {
+                // entered an output with another scale.  Text follows on the next frame.
+                debug!("Scale factor changed to {}", factor);
+                self.frame_state.recreate_swapchain = true;
+            },
+            _ => {},
+        }
+
+        if let Some(pe) = input::process(&ev) {
+            if let Some(ue) = self.kt.update(&pe) {
+                match &ue {
+                    UserEvent::KeyPress { character: c } if self.onboarding.is_some() => {
+                        if *c == '\u{1b}' {
+                            self.done = true;
+                        }
+                        let finished = match &mut self.onboarding {
+                            Some(onboarding) => {
+                                onboarding.key(*c);
+                                onboarding.finished()
+                            },
+                            None => false,
+                        };
+                        if finished {
+                            if let Some(onboarding) = self.onboarding.take() {
+                                shared.update(|config| onboarding.apply(config));
+                                self.launcher.finish_onboarding();
+                                self.done = true;
+                            }
+                        }
+                    },
+                    UserEvent::KeyPress { character } => match hotkey(*character) {
+                        Some(Hotkey::Close) => self.done = true,
+                        Some(Hotkey::Visualizer) => self.launcher.launch_mez(),
+                        Some(Hotkey::Fullscreen) => {
+                            // applied to a running visualizer on its next frame
+                            shared.update(|config| {
+                                config.start_in_fullscreen = !config.start_in_fullscreen
+                            });
+                        },
+                        Some(Hotkey::Palette) => {
+                            let palette = shared.params.get().palette.next();
+                            shared.params.apply(ParamChange::Palette(palette));
+                        },
+                        Some(Hotkey::Louder) => {
+                            let gain = shared.params.get().gain * GAIN_STEP;
+                            shared.params.apply(ParamChange::Gain(gain));
+                        },
+                        Some(Hotkey::Quieter) => {
+                            let gain = shared.params.get().gain / GAIN_STEP;
+                            shared.params.apply(ParamChange::Gain(gain));
+                        },
+                        Some(Hotkey::ResetScene) => shared.reset_scene(),
+                        Some(Hotkey::Calibrate) => {
+                            // measured while the source is quiet
+                            self.launcher.calibrate(shared.config.clone());
+                        },
+                        Some(Hotkey::LoopTest) if self.loop_test != LoopTest::Listening => {
+                            self.test_loop(shared);
+                        },
+                        _ => {},
+                    },
+                    _ => {},
+                }
             }
-            Err(_e) => {
-                frame_state = SettingsState {
-                    recreate_swapchain: true,
-                    previous_frame: Box::new(vulkano::sync::now(swap_win.device.clone())),
-                };
+            if let Some(_ue) = self.mt.update(&pe) {
+                // info!("Mousetracker: {:#?}", ue);
             }
         }
+    }
 
-        events_loop.poll_events(|ev| {
-            match &ev {
-                winit::Event::WindowEvent { event: winit::WindowEvent::CloseRequested, .. } => {
-                    done = true
+    /// Plays the preview tone and listens for it on the chosen source in the background
+    fn test_loop(&mut self, shared: &SharedState) {
+        let choice = shared.config.source_choice();
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let heard = PaStream::select(&choice).and_then(|mut s| audio::loop_test(&mut s));
+            let result = match heard {
+                Ok(heard) if heard >= audio::TONE_HEARD => LoopTest::Passed,
+                Ok(heard) => {
+                    warn!("The preview tone was heard at {:.3} of its level", heard);
+                    LoopTest::Failed
+                },
+                Err(e) => {
+                    warn!("Loop test failed: {}", e);
+                    LoopTest::Failed
+                },
+            };
+            // the window may have closed
+            let _ = tx.send(result);
+        });
+        self.loop_test = LoopTest::Listening;
+        self.tested = Some(rx);
+    }
+
+    pub fn command(&mut self, command: WindowCommand) {
+        match command {
+            WindowCommand::Focus => self.swap_win.surface.window().show(),
+            WindowCommand::Close => self.done = true,
+            WindowCommand::ToggleFullscreen => {},
+        }
+    }
+
+    /// Closes the window once its last frame is done
+    pub fn close(mut self) {
+        self.frame_state.previous_frame.cleanup_finished();
+        ewin::wait_idle(&self.swap_win.device);
+    }
+
+    /// Draws one frame.  Recoverable errors retry on the next one, fatal ones are returned.
+    pub fn render(&mut self, shared: &SharedState) -> Result<(), FrameError> {
+        self.framer.body_text = match &mut self.onboarding {
+            Some(onboarding) => {
+                onboarding.poll();
+                onboarding.lines()
+            },
+            None => {
+                if let Some(result) = self.tested.as_ref().and_then(|rx| rx.try_recv().ok()) {
+                    self.loop_test = result;
+                    self.tested = None;
                 }
-                winit::Event::WindowEvent { event: winit::WindowEvent::Resized(_), .. } => {
-                    frame_state.recreate_swapchain = true;
+                let params = shared.params.get();
+                let mut lines = current_lines(&shared.config, &params, &self.capabilities);
+                lines.push(self.loop_test.line().to_owned());
+                lines
+            },
+        };
+        let device = self.swap_win.device.clone();
+        let frame_state =
+            std::mem::replace(&mut self.frame_state, SettingsState::fresh(&device, false));
+        let result = self.framer.render_one(&mut self.swap_win, frame_state);
+        match result {
+            Ok(new_state) => {
+                self.frame_state = new_state;
+                Ok(())
+            },
+            Err(fe) => {
+                self.frame_state = SettingsState::fresh(&device, true);
+                if fe.is_recoverable() {
+                    debug!("Frame skipped.  Recreating the swapchain: {}", fe);
+                    Ok(())
+                } else {
+                    Err(fe)
                 }
-                _ => {}
-            }
+            },
+        }
+    }
+}
 
-            if let Some(pe) = input::process(&ev) {
-                if let Some(ue) = kt.update(&pe) {
-                    match &ue {
-                        UserEvent::KeyPress { character: c } => {
-                            match &c {
-                                '\u{1b}' => {
-                                    // escape key
-                                    done = true;
-                                }
-                                'm' => {
-                                    launcher.launch_mez();
-                                }
-                                _ => {}
-                            }
+/// What a key pressed in the settings window does, outside of onboarding
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Hotkey {
+    Close,
+    Visualizer,
+    Fullscreen,
+    Palette,
+    Louder,
+    Quieter,
+    ResetScene,
+    Calibrate,
+    LoopTest,
+}
+
+fn hotkey(character: char) -> Option<Hotkey> {
+    match character {
+        '\u{1b}' => Some(Hotkey::Close),
+        'm' => Some(Hotkey::Visualizer),
+        'f' => Some(Hotkey::Fullscreen),
+        'p' => Some(Hotkey::Palette),
+        '+' | '=' => Some(Hotkey::Louder),
+        '-' => Some(Hotkey::Quieter),
+        'r' => Some(Hotkey::ResetScene),
+        'n' => Some(Hotkey::Calibrate),
+        't' => Some(Hotkey::LoopTest),
+        _ => None,
+    }
+}
+
+/// Where the logo goes in a window `window` logical pixels large, one logical pixel per
+/// pixel of the `logo` image on any monitor
+fn logo_rect(window: [f32; 2], logo: [u32; 2]) -> [f32; 4] {
+    let [width, height] = [logo[0] as f32, logo[1] as f32];
+    pixel_rect(window, (window[0] - width) / 2.0, LOGO_TOP, width, height)
+}
+
+/// Whether a tone played to the default output is heard on the chosen source
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum LoopTest {
+    Untested,
+    Listening,
+    Passed,
+    Failed,
+}
+
+impl LoopTest {
+    fn line(self) -> &'static str {
+        match self {
+            LoopTest::Untested => "t to test audio",
+            LoopTest::Listening => "loop test: listening...",
+            LoopTest::Passed => "loop test: passed",
+            LoopTest::Failed => "loop test: failed",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Step {
+    Gpu,
+    Audio,
+    Done,
+}
+
+/// First run: choose a GPU, then an audio source, before the visualizer opens.  Audio
+/// sources are listened to in the background so the user can see which one is playing.
+pub struct Onboarding {
+    step: Step,
+    /// UUID and name of each GPU that can draw
+    gpus: Vec<(String, String)>,
+    sources: Vec<String>,
+    /// Peak level of each source, once it's been listened to
+    levels: Vec<Option<f32>>,
+    heard: Option<Receiver<(usize, f32)>>,
+    gpu: Option<usize>,
+    source: Option<usize>,
+}
+
+impl Onboarding {
+    /// Lists devices and starts listening to each audio source
+    pub fn detect(picker: &GpuPicker) -> Onboarding {
+        let gpus = PhysicalDevice::enumerate(&picker.instance)
+            .filter(|pd| GpuPicker::has_graphics(pd))
+            .map(|pd| (ewin::format_uuid(pd.uuid()), pd.name()))
+            .collect();
+        let sources: Vec<String> = match audio::list_sources() {
+            Ok(sources) => sources.iter().map(|s| s.name().to_owned()).collect(),
+            Err(e) => {
+                warn!("No audio sources to choose from: {}", e);
+                Vec::new()
+            },
+        };
+
+        let (tx, rx) = mpsc::channel();
+        let names = sources.clone();
+        thread::spawn(move || {
+            for (i, name) in names.iter().enumerate() {
+                let level = PaStream::with_source(name)
+                    .and_then(|mut stream| audio::peak_level(&mut stream, LISTEN));
+                match level {
+                    // onboarding finished before every source was heard
+                    Ok(level) => {
+                        if tx.send((i, level)).is_err() {
+                            break;
                         }
-                        _ => {}
-                    }
-                }
-                if let Some(_ue) = mt.update(&pe) {
-                    // info!("Mousetracker: {:#?}", ue);
+                    },
+                    Err(e) => warn!("Could not listen to {}: {}", name, e),
                 }
             }
         });
-        if done {
-            break;
+        Onboarding::new(gpus, sources, Some(rx))
+    }
+
+    fn new(
+        gpus: Vec<(String, String)>,
+        sources: Vec<String>,
+        heard: Option<Receiver<(usize, f32)>>,
+    ) -> Onboarding {
+        let levels = vec![None; sources.len()];
+        let mut onboarding =
+            Onboarding { step: Step::Gpu, gpus, sources, levels, heard, gpu: None, source: None };
+        // no need to ask about a lone GPU
+        if onboarding.gpus.len() <= 1 {
+            onboarding.gpu = if onboarding.gpus.is_empty() { None } else { Some(0) };
+            onboarding.next_step();
+        }
+        onboarding
+    }
+
+    fn next_step(&mut self) {
+        self.step = match self.step {
+            Step::Gpu if !self.sources.is_empty() => Step::Audio,
+            _ => Step::Done,
+        };
+    }
+
+    /// Number keys choose.  Enter picks the loudest source heard so far.
+    pub fn key(&mut self, key: char) {
+        let chosen = key.to_digit(10).and_then(|d| (d as usize).checked_sub(1));
+        match self.step {
+            Step::Gpu => {
+                if let Some(i) = chosen.filter(|i| *i < self.gpus.len()) {
+                    self.gpu = Some(i);
+                    self.next_step();
+                }
+            },
+            Step::Audio => {
+                let chosen = match key {
+                    '\r' | '\n' => self.loudest(),
+                    _ => chosen.filter(|i| *i < self.sources.len()),
+                };
+                if let Some(i) = chosen {
+                    self.source = Some(i);
+                    self.next_step();
+                }
+            },
+            Step::Done => {},
+        }
+    }
+
+    /// Collect levels heard since the last call
+    pub fn poll(&mut self) {
+        if let Some(heard) = &self.heard {
+            for (i, level) in heard.try_iter() {
+                if let Some(slot) = self.levels.get_mut(i) {
+                    *slot = Some(level);
+                }
+            }
         }
     }
-    Ok(())
+
+    pub fn finished(&self) -> bool {
+        self.step == Step::Done
+    }
+
+    /// Write the choices into `config`
+    pub fn apply(&self, config: &mut ENguyenConfig) {
+        if let Some((uuid, name)) = self.gpu.and_then(|i| self.gpus.get(i)) {
+            config.physical_device_uuid = Some(uuid.clone());
+            config.physical_device_name = Some(name.clone());
+        }
+        if let Some(source) = self.source.and_then(|i| self.sources.get(i)) {
+            config.audio.source = Some(source.clone());
+        }
+    }
+
+    fn loudest(&self) -> Option<usize> {
+        let heard = self.levels.iter().enumerate().filter_map(|(i, l)| l.map(|l| (i, l)));
+        heard
+            .filter(|(_i, level)| *level > HEARD_LEVEL)
+            .fold(None, |loudest: Option<(usize, f32)>, (i, level)| match loudest {
+                Some((_, max)) if max >= level => loudest,
+                _ => Some((i, level)),
+            })
+            .map(|(i, _level)| i)
+    }
+
+    /// What to show in the window
+    pub fn lines(&self) -> Vec<String> {
+        match self.step {
+            Step::Gpu => {
+                let mut lines = vec!["choose a gpu".to_owned()];
+                lines.extend(numbered(self.gpus.iter().map(|(_uuid, name)| name)));
+                lines
+            },
+            Step::Audio => {
+                let mut lines = vec!["choose audio".to_owned()];
+                lines.extend(numbered(self.sources.iter()));
+                match self.loudest() {
+                    Some(i) => {
+                        lines.push("we hear audio from:".to_owned());
+                        lines.push(short(&self.sources[i]));
+                        lines.push("enter to use it".to_owned());
+                    },
+                    None if self.levels.iter().any(|l| l.is_none()) => {
+                        lines.push("listening...".to_owned());
+                    },
+                    None => lines.push("we hear nothing.  play something".to_owned()),
+                }
+                lines
+            },
+            Step::Done => Vec::new(),
+        }
+    }
+}
+
+/// The settings in effect, shown when not onboarding.  Those `capabilities` rule out are
+/// marked unsupported.
+fn current_lines(
+    config: &ENguyenConfig,
+    params: &Parameters,
+    capabilities: &Capabilities,
+) -> Vec<String> {
+    let gpu = config.physical_device_name.as_deref().unwrap_or("any");
+    let fps = if params.fps_cap == 0 { "vsync".to_owned() } else { params.fps_cap.to_string() };
+    let present = format!("{:?}", config.video.present_mode).to_lowercase();
+    let scene = params.scene;
+    let unsupported = if capabilities.supports(scene) { "" } else { UNSUPPORTED };
+    vec![
+        "gpu".to_owned(),
+        short(gpu),
+        "audio".to_owned(),
+        short(&config.source_choice().to_string()),
+        format!("present {}", present),
+        format!("fps {}", fps),
+        format!("gain {:.2}", params.gain),
+        format!("palette {}", params.palette),
+        format!("scene {}{}", scene, unsupported),
+        format!("fullscreen {}", if config.start_in_fullscreen { "on" } else { "off" }),
+        format!("noise floor {}", if params.noise_floor == [0.0; 4] { "off" } else { "on" }),
+    ]
+}
+
+/// One line per choice, keyed by the number that picks it
+fn numbered<'n, I: Iterator<Item = &'n String>>(names: I) -> Vec<String> {
+    names.enumerate().take(9).map(|(i, name)| format!("{} {}", i + 1, short(name))).collect()
+}
+
+fn short(name: &str) -> String {
+    if name.chars().count() <= NAME_CHARS {
+        name.to_owned()
+    } else {
+        let tail: String = name.chars().skip(name.chars().count() + 2 - NAME_CHARS).collect();
+        format!("..{}", tail)
+    }
 }
 
 pub struct SettingsResources<'s> {
@@ -134,86 +561,30 @@ pub struct SettingsResources<'s> {
 }
 
 impl<'s> SettingsResources<'s> {
-    fn new() -> Result<SettingsResources<'s>, VulkanoError> {
-        match Font::from_bytes(include_bytes!("../font/MajorMonoDisplay-Regular.ttf") as &[u8]) {
-            Ok(font) => Ok(SettingsResources { font }),
-            Err(err) => Err(VulkanoError::from("Font loading failed")),
-        }
+    pub fn new() -> Result<SettingsResources<'s>, ENguyenError> {
+        Ok(SettingsResources { font: ui_font()? })
     }
 }
 
 pub struct SettingsFramer<'f> {
     framebuffers: Vec<Arc<FramebufferAbstract + Send + Sync>>,
     render_pass: Arc<RenderPassAbstract + Send + Sync>,
-    pipeline: Arc<GraphicsPipelineAbstract + Send + Sync>,
     background_pipe: Arc<GraphicsPipelineAbstract + Send + Sync>,
-    vertex_buffer: Arc<CpuAccessibleBuffer<[XyUvVertex]>>,
-    background_rect: Arc<CpuAccessibleBuffer<[XyVertex]>>,
-    set: Arc<dyn DescriptorSet + Send + Sync>,
-    title: Vec<Section>,
-    glyph_brush: GlyphBrush<'f>,
+    background_rect: Arc<ImmutableBuffer<[XyVertex]>>,
+    logo: ImageLayer,
+    text: TextRenderer<'f>,
+    /// Lines drawn under the title
+    body_text: Vec<String>,
 }
 
-impl<'f, 'r: 'f> Framer<'f, 'r, SettingsFramer<'f>, SettingsState, SettingsResources<'r>>
-    for SettingsFramer<'f>
-{
+impl<'f> SettingsFramer<'f> {
     fn new(
         swap_win: &mut SwapWindow,
-        resources: &'f SettingsResources<'r>,
-    ) -> Result<(SettingsFramer<'f>, SettingsState), VulkanoError> {
-        let background_rect = {
-            CpuAccessibleBuffer::from_iter(
-                swap_win.device.clone(),
-                BufferUsage::all(),
-                [
-                    XyVertex { position: [-1.0, -1.0] },
-                    XyVertex { position: [1.0, -1.0] },
-                    XyVertex { position: [-1.0, 1.0] },
-                    XyVertex { position: [1.0, 1.0] },
-                ]
-                .iter()
-                .cloned(),
-            )?
-        };
-
-        let vertex_buffer: Arc<CpuAccessibleBuffer<[XyUvVertex]>> = {
-            // TODO move this resize-dependent logic to allow both swapchain and
-            // Framers to adjust to the new window dimensions
-            let window = swap_win.surface.window();
-            let dimensions = if let Some(dimensions) = window.get_inner_size() {
-                let dimensions: (u32, u32) =
-                    dimensions.to_physical(window.get_hidpi_factor()).into();
-                [dimensions.0, dimensions.1]
-            } else {
-                [WIDTH, HEIGHT]
-            };
-
-            let dims = [dimensions[0] as f32, dimensions[1] as f32];
-
-            let top_pad: f32 = 24.0;
-            let left_pad: f32 = (dims[0] - (LOGO_WIDTH as f32)) / 2.0;
-            let x0: f32 = -1.0 + left_pad / dims[0] * 2.0;
-            let x1: f32 = -x0;
-            let y0: f32 = -1.0 + top_pad / (dims[1]) * 2.0;
-            let y1: f32 = y0 + (LOGO_HEIGHT as f32) / dims[1] * 2.0;
-
-            CpuAccessibleBuffer::from_iter(
-                swap_win.device.clone(),
-                BufferUsage::all(),
-                [
-                    XyUvVertex { position: [x0, y0], uv: [0.0, 0.0] },
-                    XyUvVertex { position: [x1, y0], uv: [1.0, 0.0] },
-                    XyUvVertex { position: [x0, y1], uv: [0.0, 1.0] },
-                    XyUvVertex { position: [x1, y1], uv: [1.0, 1.0] },
-                ]
-                .iter()
-                .cloned(),
-            )
-            .unwrap()
-        };
-
-        let vs = uv_image_vsm::Shader::load(swap_win.device.clone()).unwrap();
-        let fs = uv_image_fsm::Shader::load(swap_win.device.clone()).unwrap();
+        resources: &SettingsResources<'f>,
+    ) -> Result<(SettingsFramer<'f>, SettingsState), ENguyenError> {
+        let corners = [[-1.0, -1.0], [1.0, -1.0], [-1.0, 1.0], [1.0, 1.0]];
+        let corners = corners.iter().map(|&position| XyVertex { position });
+        let (background_rect, rect_upload) = static_vertices(&swap_win.transfer_queue, corners)?;
 
         let render_pass = Arc::new(
             vulkano::single_pass_renderpass!(swap_win.device.clone(),
@@ -234,108 +605,33 @@ impl<'f, 'r: 'f> Framer<'f, 'r, SettingsFramer<'f>, SettingsState, SettingsResou
             .unwrap(),
         );
 
-        let pipeline: Arc<GraphicsPipelineAbstract + Send + Sync> = Arc::new(
-            GraphicsPipeline::start()
-                .blend_collective(AttachmentBlend::alpha_blending())
-                .vertex_input_single_buffer::<XyUvVertex>()
-                .vertex_shader(vs.main_entry_point(), ())
-                .triangle_strip()
-                .viewports_dynamic_scissors_irrelevant(1)
-                .fragment_shader(fs.main_entry_point(), ())
-                .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
-                .build(swap_win.device.clone())
-                .unwrap(),
-        );
         let framebuffers = swap_win.size_dependent_setup(render_pass.clone())?;
 
-        let vs_grad = diag_grad_vsm::Shader::load(swap_win.device.clone()).unwrap();
-        let fs_grad = diag_grad_fsm::Shader::load(swap_win.device.clone()).unwrap();
-
-        let background_pipe = Arc::new(
-            GraphicsPipeline::start()
-                .vertex_input_single_buffer::<XyVertex>()
-                .vertex_shader(vs_grad.main_entry_point(), ())
-                .triangle_strip()
-                .viewports_dynamic_scissors_irrelevant(1)
-                .fragment_shader(fs_grad.main_entry_point(), ())
-                .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
-                .build(swap_win.device.clone())
-                .unwrap(),
-        );
+        let pipelines = PipelineRegistry::new(swap_win.device.clone(), render_pass.clone());
+        let background_pipe = gradient_pipeline(&pipelines)?;
 
-        // texture kept alive by descriptor set
-        let (texture, tex_future) = {
-            let image = image::load_from_memory_with_format(
-                include_bytes!("../logo/eye_of_nguyen_settings_logo.png"),
-                ImageFormat::PNG,
-            )
-            .unwrap()
-            .to_rgba();
-            let image_data = image.into_raw().clone();
-
-            ImmutableImage::from_iter(
-                image_data.iter().cloned(),
-                Dimensions::Dim2d { width: LOGO_WIDTH, height: LOGO_HEIGHT },
-                Format::R8G8B8A8Srgb,
-                swap_win.window_queue.clone(),
-            )
-            .unwrap()
-        };
-
-        let texture_future = Box::new(tex_future);
-
-        // lives in descriptor set
-        let sampler = Sampler::new(
-            swap_win.device.clone(),
-            Filter::Linear,
-            Filter::Linear,
-            MipmapMode::Nearest,
-            SamplerAddressMode::Repeat,
-            SamplerAddressMode::Repeat,
-            SamplerAddressMode::Repeat,
-            0.0,
-            1.0,
-            0.0,
-            0.0,
+        let logo = image::load_from_memory_with_format(
+            include_bytes!("../logo/eye_of_nguyen_settings_logo.png"),
+            ImageFormat::PNG,
         )
-        .unwrap();
-
-        let set = Arc::new(
-            PersistentDescriptorSet::start(pipeline.clone(), 0)
-                .add_sampled_image(texture.clone(), sampler.clone())
-                .unwrap()
-                .build()
-                .unwrap(),
-        );
+        .map_err(|e| ENguyenError::render("Settings logo failed to decode").caused_by(e))?
+        .to_rgba();
+        let (logo, texture_future) = ImageLayer::new(&swap_win.transfer_queue, &pipelines, logo)?;
 
         let subpass =
             Subpass::from(render_pass.clone() as Arc<RenderPassAbstract + Send + Sync>, 0)
-                .ok_or("Subpass is None")?;
-        let mut glyph_brush = GlyphBrush::new(&swap_win.device, subpass.clone()).unwrap();
-
-        let title = vec![
-            (glyph_brush.queue_glyphs(
-                resources.font.layout("E-NGUYEN", Scale::uniform(72.0), point(56.0, 256.0)),
-                0,
-                [1.0, 1.0, 1.0, 1.0],
-            )),
-        ];
-
-        let copy_future =
-            glyph_brush.cache_sections(&swap_win.window_queue, title.iter()).unwrap().unwrap();
-
-        let texture_future: Box<dyn GpuFuture> = Box::new(texture_future.join(copy_future));
+                .ok_or_else(|| ENguyenError::render("Subpass is None"))?;
+        let text = TextRenderer::new(&swap_win.device, subpass, resources.font.clone())?;
+        let texture_future: Box<dyn GpuFuture> = Box::new(texture_future.join(rect_upload));
 
         let settings_framer = SettingsFramer {
             render_pass,
-            pipeline,
-            set,
             framebuffers,
-            vertex_buffer,
             background_rect,
             background_pipe,
-            title,
-            glyph_brush,
+            logo,
+            text,
+            body_text: Vec::new(),
         };
         let frame_state =
             SettingsState { previous_frame: texture_future, recreate_swapchain: false };
@@ -346,8 +642,7 @@ impl<'f, 'r: 'f> Framer<'f, 'r, SettingsFramer<'f>, SettingsState, SettingsResou
         &mut self,
         swap_win: &mut SwapWindow,
         mut frame_state: SettingsState,
-        resources: &SettingsResources,
-    ) -> Result<SettingsState, VulkanoError> {
+    ) -> Result<SettingsState, FrameError> {
         // TODO memory swaps = lifetime impedence
         let mut previous_frame = Box::new(sync::now(swap_win.device.clone())) as Box<GpuFuture>;
         std::mem::swap(&mut previous_frame, &mut frame_state.previous_frame);
@@ -357,6 +652,17 @@ impl<'f, 'r: 'f> Framer<'f, 'r, SettingsFramer<'f>, SettingsState, SettingsResou
             self.framebuffers = swap_win.recreate_swapchain(self.render_pass.clone())?;
         }
 
+        let mut text = vec![TextBlock::new("E-NGUYEN", [56.0, 256.0], 72.0, TITLE_COLOR)];
+        for (i, line) in self.body_text.iter().enumerate() {
+            let at = [24.0, 312.0 + 26.0 * i as f32];
+            let color = if line.ends_with(UNSUPPORTED) { UNSUPPORTED_COLOR } else { BODY_COLOR };
+            text.push(TextBlock::new(line.clone(), at, 20.0, color));
+        }
+        let hidpi = swap_win.hidpi_factor();
+        if let Some(upload) = self.text.show(&swap_win.window_queue, &text, hidpi)? {
+            previous_frame = Box::new(previous_frame.join(upload));
+        }
+
         let (image_num, acquire_future) = swap_win.future_image()?;
 
         let clear_values = vec![[0.0, 0.0, 0.0, 1.0].into()];
@@ -365,9 +671,9 @@ impl<'f, 'r: 'f> Framer<'f, 'r, SettingsFramer<'f>, SettingsState, SettingsResou
             swap_win.device.clone(),
             swap_win.window_queue.family(),
         )
-        .unwrap()
+        .map_err(|e| ENguyenError::render("Command buffer allocation failed").caused_by(e))?
         .begin_render_pass(self.framebuffers[image_num].clone(), false, clear_values)
-        .unwrap()
+        .map_err(|e| ENguyenError::render("Render pass could not begin").caused_by(e))?
         .draw(
             self.background_pipe.clone(),
             &swap_win.dynamic_state,
@@ -375,55 +681,32 @@ impl<'f, 'r: 'f> Framer<'f, 'r, SettingsFramer<'f>, SettingsState, SettingsResou
             (),
             (),
         )
-        .unwrap()
-        .draw(
-            self.pipeline.clone(),
-            &swap_win.dynamic_state,
-            vec![self.vertex_buffer.clone()],
-            self.set.clone(),
-            (),
-        )
-        .unwrap();
-        let command_buffer = self
-            .glyph_brush
-            .draw(
-                command_buffer,
-                &self.title,
-                &swap_win.dynamic_state,
-                [
-                    [1.0, 0.0, 0.0, 0.0],
-                    [0.0, 1.0, 0.0, 0.0],
-                    [0.0, 0.0, 1.0, 0.0],
-                    [0.0, 0.0, 0.0, 1.0],
-                ],
-                swap_win.f_dimensions().unwrap(),
-            )
-            .unwrap()
+        .map_err(|e| ENguyenError::render("Background draw failed").caused_by(e))?;
+        let (dimensions, logical) = match (swap_win.f_dimensions(), swap_win.logical_dimensions()) {
+            (Some(dimensions), Some(logical)) => (dimensions, logical),
+            _ => return Err(FrameError::recoverable(ENguyenError::render("No window dimensions"))),
+        };
+        let rect = logo_rect(logical, self.logo.dimensions);
+        let command_buffer = self.logo.draw(command_buffer, &swap_win.dynamic_state, rect, 1.0)?;
+        let command_buffer = self.text.draw(command_buffer, &swap_win.dynamic_state, dimensions)?;
+        let command_buffer = command_buffer
             .end_render_pass()
-            .unwrap()
+            .map_err(|e| ENguyenError::render("Render pass could not end").caused_by(e))?
             .build()
-            .unwrap();
+            .map_err(|e| ENguyenError::render("Command buffer build failed").caused_by(e))?;
 
         let new_frame = previous_frame
             .join(acquire_future)
             .then_execute(swap_win.window_queue.clone(), command_buffer)
-            .unwrap()
+            .map_err(|e| ENguyenError::render("Frame submit failed").caused_by(e))?
             .then_swapchain_present(
                 swap_win.window_queue.clone(),
                 swap_win.swapchain.clone(),
                 image_num,
             )
-            .then_signal_fence_and_flush();
-
-        match new_frame {
-            Ok(frame) => {
-                frame_state.previous_frame = Box::new(frame);
-                Ok(frame_state)
-            }
-            // TODO research which of these are recoverable
-            Err(e @ FlushError::OutOfDate) => Err((Box::new(e) as Box<Error>).into()),
-            Err(e) => Err((Box::new(e) as Box<Error>).into()),
-        }
+            .then_signal_fence_and_flush()?;
+        frame_state.previous_frame = Box::new(new_frame);
+        Ok(frame_state)
     }
 }
 
@@ -432,4 +715,115 @@ struct SettingsState {
     pub recreate_swapchain: bool,
 }
 
-impl FrameState for SettingsState {}
+impl SettingsState {
+    fn fresh(device: &Arc<Device>, recreate_swapchain: bool) -> SettingsState {
+        let previous_frame = Box::new(sync::now(device.clone()));
+        SettingsState { previous_frame, recreate_swapchain }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::{typed, ProcessedEvent, Simulation};
+    use crate::params::Scene;
+
+    fn sources() -> Vec<String> {
+        vec!["alsa_output.analog-stereo.monitor".to_owned(), "alsa_input.mic".to_owned()]
+    }
+
+    #[test]
+    fn onboarding_steps() {
+        let gpus =
+            vec![("a".to_owned(), "Card A".to_owned()), ("b".to_owned(), "Card B".to_owned())];
+        let (tx, rx) = mpsc::channel();
+        let mut onboarding = Onboarding::new(gpus, sources(), Some(rx));
+        assert_eq!(onboarding.lines()[0], "choose a gpu");
+        onboarding.key('7');
+        onboarding.key('2');
+        assert_eq!(onboarding.step, Step::Audio);
+        assert!(onboarding.lines().contains(&"listening...".to_owned()));
+
+        // enter does nothing until something is heard
+        onboarding.key('\r');
+        assert_eq!(onboarding.step, Step::Audio);
+        tx.send((0, 0.0)).unwrap();
+        tx.send((1, 0.3)).unwrap();
+        onboarding.poll();
+        assert!(onboarding.lines().contains(&"we hear audio from:".to_owned()));
+        onboarding.key('\r');
+        assert!(onboarding.finished());
+
+        let mut config = ENguyenConfig::default();
+        onboarding.apply(&mut config);
+        assert_eq!(config.physical_device_uuid, Some("b".to_owned()));
+        assert_eq!(config.physical_device_name, Some("Card B".to_owned()));
+        assert_eq!(config.audio.source, Some("alsa_input.mic".to_owned()));
+    }
+
+    #[test]
+    fn current_settings_are_listed() {
+        let mut config = ENguyenConfig::default();
+        config.physical_device_name = Some("Card B".to_owned());
+        config.audio.source = Some("alsa_input.mic".to_owned());
+        let params = Parameters { fps_cap: 30, scene: Scene::Goniometer, ..Parameters::default() };
+        let lines = current_lines(&config, &params, &Capabilities::default());
+        assert_eq!(lines[1], "Card B");
+        assert_eq!(lines[3], "alsa_input.mic");
+        assert!(lines.contains(&"present fifo".to_owned()));
+        assert!(lines.contains(&"fps 30".to_owned()));
+        assert!(lines.contains(&"fullscreen off".to_owned()));
+        assert!(lines.contains(&"noise floor off".to_owned()));
+        assert!(lines.contains(&"scene goniometer (unsupported)".to_owned()));
+    }
+
+    #[test]
+    fn onboarding_skips_lone_choices() {
+        let gpus = vec![("a".to_owned(), "Card A".to_owned())];
+        let onboarding = Onboarding::new(gpus.clone(), sources(), None);
+        assert_eq!(onboarding.step, Step::Audio);
+        assert_eq!(onboarding.gpu, Some(0));
+
+        let onboarding = Onboarding::new(gpus, Vec::new(), None);
+        assert!(onboarding.finished());
+    }
+
+    #[test]
+    fn scripted_hotkeys() {
+        let mut script = typed("m=x");
+        // held modifiers leave the key to the window manager
+        script.push(ProcessedEvent::KeyDown { scancode: 40, no_mods: true });
+        script.push(ProcessedEvent::KeyChar { character: 'p' });
+        script.push(ProcessedEvent::KeyUp { scancode: 40, no_mods: false });
+        script.extend(typed("\u{1b}"));
+        let keys: Vec<Option<Hotkey>> = Simulation::new()
+            .run(&script)
+            .iter()
+            .map(|ue| match ue {
+                UserEvent::KeyPress { character } => hotkey(*character),
+                _ => panic!("only keys were pressed"),
+            })
+            .collect();
+        let expected =
+            vec![Some(Hotkey::Visualizer), Some(Hotkey::Louder), None, Some(Hotkey::Close)];
+        assert_eq!(keys, expected);
+    }
+
+    #[test]
+    fn logo_keeps_its_logical_size() {
+        // centered a fixed distance from the top, a fifth of a 500 pixel wide window
+        let rect = logo_rect([500.0, 400.0], [100, 40]);
+        let expected = pixel_rect([500.0, 400.0], 200.0, LOGO_TOP, 100.0, 40.0);
+        assert_eq!(rect, expected);
+        assert!((rect[2] - rect[0] - 0.4).abs() < 1e-6);
+    }
+
+    #[test]
+    fn long_names_are_shortened() {
+        let name = "alsa_output.pci-0000_00_1f.3.analog-stereo.monitor";
+        let shortened = short(name);
+        assert_eq!(shortened.chars().count(), NAME_CHARS);
+        assert!(shortened.ends_with("stereo.monitor"));
+        assert_eq!(short("mic"), "mic");
+    }
+}
@@ -0,0 +1,147 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! With `--dev`, compute pipelines are rebuilt from the GLSL in the checkout's `shaders/`
+//! whenever it's edited, so tuning a shader doesn't take a cargo rebuild.  The pipeline layout
+//! still comes from the shader compiled in, so bindings and push constants can't change
+//! without one.
+
+use crate::errors::ENguyenError;
+
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use std::ffi::CStr;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use vulkano::descriptor::pipeline_layout::PipelineLayoutDescNames;
+use vulkano::device::Device;
+use vulkano::pipeline::shader::ShaderModule;
+use vulkano::pipeline::{ComputePipeline, ComputePipelineAbstract};
+
+static SHADER_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/shaders");
+/// Editors write a file in several steps
+static RELOAD_DEBOUNCE: Duration = Duration::from_millis(200);
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Reload shaders in every analysis pipeline started from now on
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Notices edits under `shaders/`.  Stops watching when dropped.
+pub struct ShaderWatch {
+    _watcher: RecommendedWatcher,
+    edited: Arc<AtomicBool>,
+}
+
+impl ShaderWatch {
+    /// Starts out edited, so the first check loads what's on disk
+    pub fn start() -> Result<ShaderWatch, ENguyenError> {
+        let (tx, rx) = mpsc::channel();
+        let mut watcher: RecommendedWatcher = Watcher::new(tx, RELOAD_DEBOUNCE)?;
+        watcher.watch(SHADER_DIR, RecursiveMode::Recursive)?;
+        let edited = Arc::new(AtomicBool::new(true));
+        let flag = edited.clone();
+        thread::spawn(move || {
+            // ends when the watcher is dropped along with its sender
+            for event in rx.iter() {
+                match event {
+                    DebouncedEvent::Create(_)
+                    | DebouncedEvent::Write(_)
+                    | DebouncedEvent::Rename(_, _) => flag.store(true, Ordering::Relaxed),
+                    _ => {},
+                }
+            }
+        });
+        Ok(ShaderWatch { _watcher: watcher, edited })
+    }
+
+    /// True if a shader changed since the last call
+    pub fn edited(&self) -> bool {
+        self.edited.swap(false, Ordering::Relaxed)
+    }
+}
+
+/// SPIR-V of the compute shader `name` in `shaders/`.  Includes are relative to `shaders/`,
+/// as the build script expands them.
+pub fn compile_compute(name: &str) -> Result<Vec<u8>, ENguyenError> {
+    let dir = Path::new(SHADER_DIR);
+    let path = dir.join(name);
+    let source = fs::read_to_string(&path).map_err(|e| {
+        ENguyenError::compute(format!("Could not read {}", path.display())).caused_by(e)
+    })?;
+    let mut compiler =
+        shaderc::Compiler::new().ok_or_else(|| ENguyenError::compute("No shader compiler"))?;
+    let mut options = shaderc::CompileOptions::new()
+        .ok_or_else(|| ENguyenError::compute("No shader compiler options"))?;
+    options.set_include_callback(|requested, _, _, _| {
+        let include = dir.join(requested);
+        match fs::read_to_string(&include) {
+            Ok(content) => {
+                let resolved_name = include.display().to_string();
+                Ok(shaderc::ResolvedInclude { resolved_name, content })
+            },
+            Err(e) => Err(format!("{}: {}", include.display(), e)),
+        }
+    });
+    let kind = shaderc::ShaderKind::Compute;
+    let compiled = compiler
+        .compile_into_spirv(&source, kind, name, "main", Some(&options))
+        .map_err(|e| ENguyenError::compute(format!("{} failed to compile", name)).caused_by(e))?;
+    Ok(compiled.as_binary_u8().to_vec())
+}
+
+/// A pipeline of the compute shader `name` in `shaders/`, laid out as `layout`, the `Layout`
+/// of the same shader compiled in
+pub fn compute_pipeline<L>(
+    device: &Arc<Device>,
+    name: &str,
+    layout: L,
+) -> Result<Arc<dyn ComputePipelineAbstract + Send + Sync>, ENguyenError>
+where
+    L: PipelineLayoutDescNames + Clone + Send + Sync + 'static,
+{
+    let spirv = compile_compute(name)?;
+    // the SPIR-V is shaderc's own output, and the layout must match it as documented above
+    let module = unsafe { ShaderModule::new(device.clone(), &spirv) }
+        .map_err(|e| ENguyenError::compute(format!("{} failed to load", name)).caused_by(e))?;
+    let main = CStr::from_bytes_with_nul(b"main\0").unwrap();
+    let entry_point = unsafe { module.compute_entry_point::<(), L>(main, layout) };
+    let pipeline = ComputePipeline::new(device.clone(), &entry_point, &()).map_err(|e| {
+        ENguyenError::compute(format!("{} pipeline creation failed", name)).caused_by(e)
+    })?;
+    Ok(Arc::new(pipeline))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shaders_compile_from_disk() {
+        let spirv = compile_compute("channel_combine.comp").unwrap();
+        // SPIR-V magic number, little endian
+        assert_eq!(&spirv[..4], &[0x03, 0x02, 0x23, 0x07]);
+        assert!(compile_compute("missing.comp").is_err());
+    }
+}
@@ -0,0 +1,167 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! What the visualizer is doing, shown where it can be seen while the window isn't focused.
+//! The title names the scene, track, recording and pauses, and without audio the window asks for
+//! attention.  Taskbars following Unity's launcher API also badge the minutes recorded.
+
+use crate::metadata::NowPlaying;
+use crate::params::Scene;
+
+use dbus::arg::{RefArg, Variant};
+use dbus::{BusType, Connection, Message};
+use log::debug;
+use std::collections::HashMap;
+use winit::os::unix::WindowExt;
+use winit::Window;
+
+static APP_NAME: &str = "E-Nguyen";
+/// The desktop entry taskbars know the visualizer by
+static APP_URI: &str = "application://e-nguyen.desktop";
+static LAUNCHER_PATH: &str = "/com/canonical/unity/launcherentry/e_nguyen";
+static LAUNCHER_INTERFACE: &str = "com.canonical.Unity.LauncherEntry";
+
+/// What the title and taskbar show
+#[derive(Clone, Debug, PartialEq)]
+pub struct WindowStatus {
+    pub scene: Scene,
+    pub track: Option<NowPlaying>,
+    /// Whole minutes recorded, while recording
+    pub recording: Option<u64>,
+    /// New frames aren't presented
+    pub frozen: bool,
+    /// Analysis is paused by the user, so no audio is expected
+    pub paused: bool,
+    /// Analysis stopped or no longer hears the source
+    pub audio_lost: bool,
+}
+
+impl WindowStatus {
+    pub fn title(&self) -> String {
+        let mut parts = vec![APP_NAME.to_owned()];
+        if self.audio_lost {
+            parts.push("no audio".to_owned());
+        }
+        if self.paused {
+            parts.push("paused".to_owned());
+        }
+        if self.frozen {
+            parts.push("frozen".to_owned());
+        }
+        parts.push(self.scene.to_string());
+        if let Some(track) = &self.track {
+            parts.push(match track.artist().as_str() {
+                "" => track.title.clone(),
+                artist => format!("{} by {}", track.title, artist),
+            });
+        }
+        if let Some(minutes) = self.recording {
+            parts.push(format!("recording {} min", minutes));
+        }
+        parts.join(" - ")
+    }
+
+    /// The launcher entry properties for this status
+    fn launcher_properties(&self) -> HashMap<&'static str, Variant<Box<dyn RefArg>>> {
+        let mut properties: HashMap<&'static str, Variant<Box<dyn RefArg>>> = HashMap::new();
+        let minutes = self.recording.unwrap_or(0) as i64;
+        properties.insert("count", Variant(Box::new(minutes)));
+        properties.insert("count-visible", Variant(Box::new(self.recording.is_some())));
+        properties.insert("urgent", Variant(Box::new(self.audio_lost)));
+        properties
+    }
+}
+
+/// Shows each new status on the window and its taskbar entry
+pub struct StatusDisplay {
+    shown: Option<WindowStatus>,
+    /// For taskbar entries.  None without a session bus.
+    bus: Option<Connection>,
+}
+
+impl StatusDisplay {
+    pub fn new() -> StatusDisplay {
+        let bus = Connection::get_private(BusType::Session)
+            .map_err(|e| debug!("No session bus for the taskbar entry: {}", e))
+            .ok();
+        StatusDisplay { shown: None, bus }
+    }
+
+    /// Updates `window` and the taskbar if `status` has changed
+    pub fn show(&mut self, window: &Window, status: WindowStatus) {
+        if self.shown.as_ref() == Some(&status) {
+            return;
+        }
+        window.set_title(&status.title());
+        // X11 only.  Wayland has no urgency without a protocol winit doesn't speak.
+        window.set_urgent(status.audio_lost);
+        self.update_launcher(&status);
+        self.shown = Some(status);
+    }
+
+    fn update_launcher(&self, status: &WindowStatus) {
+        let bus = match &self.bus {
+            Some(bus) => bus,
+            None => return,
+        };
+        let sent = Message::new_signal(LAUNCHER_PATH, LAUNCHER_INTERFACE, "Update")
+            .map(|signal| signal.append2(APP_URI, status.launcher_properties()))
+            .map_err(|e| debug!("Launcher signal rejected: {}", e))
+            .and_then(|signal| bus.send(signal).map_err(|_| debug!("Launcher signal not sent")));
+        if sent.is_err() {
+            debug!("The taskbar entry isn't updated");
+        }
+    }
+}
+
+impl Drop for StatusDisplay {
+    /// Clears the badge and urgency the window leaves behind
+    fn drop(&mut self) {
+        if let Some(mut status) = self.shown.take() {
+            status.recording = None;
+            status.audio_lost = false;
+            self.update_launcher(&status);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn titles_follow_the_status() {
+        let mut status = WindowStatus {
+            scene: Scene::Scroll,
+            track: None,
+            recording: None,
+            frozen: false,
+            paused: false,
+            audio_lost: false,
+        };
+        assert_eq!(status.title(), "E-Nguyen - scroll");
+        let artists = vec!["Nguyen".to_owned()];
+        status.track = Some(NowPlaying { title: "Spectra".to_owned(), artists });
+        status.recording = Some(3);
+        assert_eq!(status.title(), "E-Nguyen - scroll - Spectra by Nguyen - recording 3 min");
+        status.audio_lost = true;
+        status.track = Some(NowPlaying { title: "Spectra".to_owned(), artists: Vec::new() });
+        assert!(status.title().starts_with("E-Nguyen - no audio - scroll - Spectra - "));
+        status.audio_lost = false;
+        status.frozen = true;
+        status.paused = true;
+        assert!(status.title().starts_with("E-Nguyen - paused - frozen - scroll"));
+    }
+}
@@ -0,0 +1,198 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! The studio scene.  The spectrogram scrolls in the lower half of the window while an
+//! oscilloscope and level meters fill the upper half, each drawn in its own viewport of the
+//! one render pass.
+
+use crate::errors::ENguyenError;
+use crate::features::{dbfs, AudioFeatures};
+use crate::geometry::quad;
+use crate::rendering::{SolidPainter, XyVertex};
+use crate::scenes::{Scene, SceneFrame, Spectrogram};
+
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DynamicState};
+use vulkano::pipeline::viewport::Viewport;
+
+/// Share of the upper half's width the meters take
+static METER_WIDTH: f32 = 0.15;
+/// Level at the bottom of the meters
+static METER_FLOOR: f32 = -60.0;
+/// Peaks above this are drawn as clipping
+static CLIP_DBFS: f32 = -1.0;
+/// Space between and around the meter bars, in viewport coordinates
+static METER_GAP: f32 = 0.15;
+static PEAK_HEIGHT: f32 = 0.02;
+/// Keeps full scale waveforms off the edges of their viewport
+static SCOPE_HEIGHT: f32 = 0.9;
+static SCOPE_COLORS: [[f32; 4]; 2] = [[0.0, 0.906, 0.702, 1.0], [0.4, 0.6, 1.0, 0.8]];
+static METER_COLOR: [f32; 4] = [0.0, 0.906, 0.702, 1.0];
+static PEAK_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+static CLIP_COLOR: [f32; 4] = [1.0, 0.25, 0.25, 1.0];
+
+/// Where each part of the scene is drawn
+pub struct StudioLayout {
+    pub spectrum: DynamicState,
+    pub scope: DynamicState,
+    pub meters: DynamicState,
+}
+
+impl StudioLayout {
+    /// Splits the window's viewport in `full`.  None before the swapchain has a size.
+    pub fn of(full: &DynamicState) -> Option<StudioLayout> {
+        let viewport = full.viewports.as_ref()?.first()?;
+        let [x, y] = viewport.origin;
+        let [width, height] = viewport.dimensions;
+        let half = height / 2.0;
+        let scope_width = width * (1.0 - METER_WIDTH);
+        Some(StudioLayout {
+            spectrum: viewed([x, y + half], [width, half]),
+            scope: viewed([x, y], [scope_width, half]),
+            meters: viewed([x + scope_width, y], [width - scope_width, half]),
+        })
+    }
+}
+
+/// The spectrogram under the scope and meters
+pub struct StudioScene {
+    spectrogram: Spectrogram,
+}
+
+impl StudioScene {
+    pub fn new(spectrogram: Spectrogram) -> StudioScene {
+        StudioScene { spectrogram }
+    }
+}
+
+impl Scene for StudioScene {
+    fn render(
+        &mut self,
+        mut cbb: AutoCommandBufferBuilder,
+        frame: &SceneFrame,
+    ) -> Result<AutoCommandBufferBuilder, ENguyenError> {
+        let layout = match StudioLayout::of(frame.state) {
+            Some(layout) => layout,
+            None => return Ok(cbb),
+        };
+        cbb = self.spectrogram.draw(cbb, &layout.spectrum, frame)?;
+        draw(frame.painter, cbb, &layout, frame.features)
+    }
+}
+
+/// A viewport of `dimensions` at `origin`, in pixels
+pub fn viewed(origin: [f32; 2], dimensions: [f32; 2]) -> DynamicState {
+    let viewport = Viewport { origin, dimensions, depth_range: 0.0..1.0 };
+    DynamicState { line_width: None, viewports: Some(vec![viewport]), scissors: None }
+}
+
+/// Records the scope and meters.  The render pass must have begun.
+pub fn draw(
+    painter: &SolidPainter,
+    mut cbb: AutoCommandBufferBuilder,
+    layout: &StudioLayout,
+    features: &AudioFeatures,
+) -> Result<AutoCommandBufferBuilder, ENguyenError> {
+    if features.waveform.len() > 1 {
+        for (channel, color) in SCOPE_COLORS.iter().enumerate() {
+            let strip = scope_strip(&features.waveform, channel);
+            cbb = painter.lines(cbb, &layout.scope, strip, *color)?;
+        }
+    }
+
+    cbb = painter.triangles(cbb, &layout.meters, meter_bars(features.rms), METER_COLOR)?;
+    let clipped = features.peak.iter().any(|peak| dbfs(*peak, METER_FLOOR) > CLIP_DBFS);
+    let peak_color = if clipped { CLIP_COLOR } else { PEAK_COLOR };
+    painter.triangles(cbb, &layout.meters, meter_ticks(features.peak), peak_color)
+}
+
+/// One channel of the waveform across the whole viewport, positive samples up
+fn scope_strip(waveform: &[[f32; 2]], channel: usize) -> Vec<XyVertex> {
+    let span = (waveform.len().max(2) - 1) as f32;
+    waveform
+        .iter()
+        .enumerate()
+        .map(|(i, frame)| XyVertex {
+            position: [-1.0 + 2.0 * i as f32 / span, -frame[channel] * SCOPE_HEIGHT],
+        })
+        .collect()
+}
+
+/// Height of `level` on the meter scale, from 0.0 at the floor to 1.0 at full scale
+fn meter_fraction(level: f32) -> f32 {
+    (dbfs(level, METER_FLOOR) - METER_FLOOR) / -METER_FLOOR
+}
+
+/// A bar per channel rising from the bottom of the viewport
+fn meter_bars(levels: [f32; 2]) -> Vec<XyVertex> {
+    let mut vertices = Vec::new();
+    for (channel, level) in levels.iter().enumerate() {
+        let (left, right) = meter_column(channel);
+        let top = 1.0 - 2.0 * meter_fraction(*level);
+        vertices.extend_from_slice(&quad(left, top, right, 1.0));
+    }
+    vertices
+}
+
+/// A thin mark per channel at the peak level
+fn meter_ticks(peaks: [f32; 2]) -> Vec<XyVertex> {
+    let mut vertices = Vec::new();
+    for (channel, peak) in peaks.iter().enumerate() {
+        let (left, right) = meter_column(channel);
+        let top = 1.0 - 2.0 * meter_fraction(*peak);
+        vertices.extend_from_slice(&quad(left, top, right, top + PEAK_HEIGHT));
+    }
+    vertices
+}
+
+/// Left and right edges of a channel's meter
+fn meter_column(channel: usize) -> (f32, f32) {
+    let left = -1.0 + channel as f32;
+    (left + METER_GAP, left + 1.0 - METER_GAP)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn viewports_split() {
+        let full = viewed([0.0, 0.0], [1000.0, 600.0]);
+        let layout = StudioLayout::of(&full).unwrap();
+        let viewport = |state: &DynamicState| {
+            let viewport = &state.viewports.as_ref().unwrap()[0];
+            (viewport.origin, viewport.dimensions)
+        };
+        assert_eq!(viewport(&layout.spectrum), ([0.0, 300.0], [1000.0, 300.0]));
+        assert_eq!(viewport(&layout.scope), ([0.0, 0.0], [850.0, 300.0]));
+        assert_eq!(viewport(&layout.meters), ([850.0, 0.0], [150.0, 300.0]));
+        let no_viewport = DynamicState { line_width: None, viewports: None, scissors: None };
+        assert!(StudioLayout::of(&no_viewport).is_none());
+    }
+
+    #[test]
+    fn meters_and_scope() {
+        let bars = meter_bars([1.0, 0.0]);
+        assert_eq!(bars.len(), 12);
+        // full scale reaches the top, silence stays at the bottom
+        assert_eq!(bars[0].position, [-1.0 + METER_GAP, -1.0]);
+        assert_eq!(bars[6].position, [METER_GAP, 1.0]);
+        assert!(meter_fraction(0.001).abs() < 1e-6);
+        assert!((meter_fraction(0.5) - 0.8997).abs() < 1e-3);
+
+        let strip = scope_strip(&[[1.0, 0.0], [0.0, 0.0], [-1.0, 0.0]], 0);
+        assert_eq!(strip[0].position, [-1.0, -SCOPE_HEIGHT]);
+        assert_eq!(strip[2].position, [1.0, SCOPE_HEIGHT]);
+    }
+}
@@ -0,0 +1,227 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! Track changes heard in the audio alone, for sources without track metadata such as a
+//! record player on a line-in.  A new track starts when sound returns after a silent gap, or
+//! when the sound's balance across bands and pitch classes turns into something else and
+//! stays that way.
+
+use crate::bench::millis;
+use crate::config::{ENguyenConfig, TrackAction};
+use crate::features::AudioFeatures;
+use crate::kiosk::next_scene;
+use crate::params::{ParamChange, Parameters};
+
+use std::time::Instant;
+
+/// Below this level in every band is silence
+static SILENT_LEVEL: f32 = 0.02;
+/// Silence at least this long is a gap between tracks
+static GAP_SECONDS: f32 = 1.5;
+/// Tracks shorter than this aren't, so quiet passages and breakdowns don't split them
+static MIN_TRACK_SECONDS: f32 = 30.0;
+/// How long the recent and the track's sound are averaged over
+static RECENT_SECONDS: f32 = 1.0;
+static TRACK_SECONDS: f32 = 15.0;
+/// Cosine distance between the recent and track sound that's a different track
+static CHANGE_DISTANCE: f32 = 0.2;
+/// A change must last this long to count, so fills and drops don't
+static CHANGE_SECONDS: f32 = 3.0;
+/// Bands then pitch classes
+const SOUND_LEN: usize = 16;
+
+/// Follows the sound from window to window and tells when it's a new track
+pub struct TrackDetector {
+    /// When the current track started, or listening did
+    started: Option<Instant>,
+    /// When the silence now playing began
+    silent_since: Option<Instant>,
+    /// The silence lasted long enough to be a gap
+    gap: bool,
+    /// When the sound last strayed from the track's
+    changed_since: Option<Instant>,
+    recent: [f32; SOUND_LEN],
+    track: [f32; SOUND_LEN],
+    previous: Option<Instant>,
+}
+
+impl TrackDetector {
+    pub fn new() -> TrackDetector {
+        TrackDetector {
+            started: None,
+            silent_since: None,
+            gap: false,
+            changed_since: None,
+            recent: [0.0; SOUND_LEN],
+            track: [0.0; SOUND_LEN],
+            previous: None,
+        }
+    }
+
+    /// Follow a window with `features`, captured `at`.  True if a new track started in it.
+    pub fn hear(&mut self, features: &AudioFeatures, at: Instant) -> bool {
+        let started = *self.started.get_or_insert(at);
+        let elapsed = self.previous.map_or(0.0, |previous| seconds_between(previous, at));
+        self.previous = Some(at);
+        let long_enough = seconds_between(started, at) >= MIN_TRACK_SECONDS;
+
+        if features.bands.iter().all(|band| *band < SILENT_LEVEL) {
+            let since = *self.silent_since.get_or_insert(at);
+            self.gap = self.gap || seconds_between(since, at) >= GAP_SECONDS;
+            // the sound averages only follow music
+            self.changed_since = None;
+            return false;
+        }
+        self.silent_since = None;
+        if std::mem::replace(&mut self.gap, false) && long_enough {
+            return self.new_track(features, at);
+        }
+
+        let sound = sound_of(features);
+        approach(&mut self.recent, &sound, elapsed / RECENT_SECONDS);
+        approach(&mut self.track, &sound, elapsed / TRACK_SECONDS);
+        if distance(&self.recent, &self.track) < CHANGE_DISTANCE {
+            self.changed_since = None;
+            return false;
+        }
+        let since = *self.changed_since.get_or_insert(at);
+        if seconds_between(since, at) >= CHANGE_SECONDS && long_enough {
+            return self.new_track(features, at);
+        }
+        false
+    }
+
+    /// The track's sound starts over from `features`
+    fn new_track(&mut self, features: &AudioFeatures, at: Instant) -> bool {
+        self.started = Some(at);
+        self.changed_since = None;
+        self.recent = sound_of(features);
+        self.track = self.recent;
+        true
+    }
+}
+
+impl Default for TrackDetector {
+    fn default() -> TrackDetector {
+        TrackDetector::new()
+    }
+}
+
+/// The change `config` asks for when a new track starts, from `params`
+pub fn on_change(config: &ENguyenConfig, params: &Parameters) -> Option<ParamChange> {
+    match config.tracks.on_change {
+        TrackAction::Nothing => None,
+        TrackAction::Scene => {
+            Some(ParamChange::Scene(next_scene(&config.kiosk.scenes, params.scene)))
+        },
+        TrackAction::Palette => Some(ParamChange::Palette(params.palette.next())),
+    }
+}
+
+/// Band levels and pitch classes side by side
+fn sound_of(features: &AudioFeatures) -> [f32; SOUND_LEN] {
+    let mut sound = [0.0; SOUND_LEN];
+    sound[..4].copy_from_slice(&features.bands);
+    sound[4..].copy_from_slice(&features.chroma);
+    sound
+}
+
+/// Moves `average` toward `sound` by `rate`, the share of its time constant that passed
+fn approach(average: &mut [f32; SOUND_LEN], sound: &[f32; SOUND_LEN], rate: f32) {
+    let weight = 1.0 - (-rate).exp();
+    for (averaged, now) in average.iter_mut().zip(sound.iter()) {
+        *averaged += (now - *averaged) * weight;
+    }
+}
+
+/// 0.0 for sounds in the same balance up to 1.0 for ones with nothing in common
+fn distance(a: &[f32; SOUND_LEN], b: &[f32; SOUND_LEN]) -> f32 {
+    let dot: f32 = a.iter().zip(b.iter()).map(|(a, b)| a * b).sum();
+    let norm = |v: &[f32; SOUND_LEN]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms <= std::f32::EPSILON {
+        0.0
+    } else {
+        1.0 - dot / norms
+    }
+}
+
+fn seconds_between(earlier: Instant, later: Instant) -> f32 {
+    millis(later.duration_since(earlier)) as f32 / 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Plays `seconds` of `sound` in windows 1/60s apart, from `from` seconds after `start`.
+    /// When each new track was heard.
+    fn play(
+        detector: &mut TrackDetector,
+        start: Instant,
+        from: f32,
+        seconds: f32,
+        sound: &AudioFeatures,
+    ) -> Vec<f32> {
+        let windows = (seconds * 60.0) as u64;
+        let first = (from * 60.0) as u64;
+        let at = |window: u64| start + Duration::from_millis(window * 1000 / 60);
+        (first..first + windows)
+            .filter(|window| detector.hear(sound, at(*window)))
+            .map(|window| window as f32 / 60.0)
+            .collect()
+    }
+
+    #[test]
+    fn new_tracks_change_what_is_configured() {
+        use crate::params::Scene;
+        let mut config = ENguyenConfig::default();
+        let params = Parameters { scene: Scene::Piano, ..Parameters::default() };
+        assert_eq!(on_change(&config, &params), None);
+        config.tracks.on_change = TrackAction::Palette;
+        let palette = params.palette.next();
+        assert_eq!(on_change(&config, &params), Some(ParamChange::Palette(palette)));
+        config.tracks.on_change = TrackAction::Scene;
+        assert_eq!(on_change(&config, &params), Some(ParamChange::Scene(Scene::Tunnel)));
+        config.kiosk.scenes = vec![Scene::Piano, Scene::Scroll];
+        assert_eq!(on_change(&config, &params), Some(ParamChange::Scene(Scene::Scroll)));
+    }
+
+    #[test]
+    fn gaps_and_new_sounds_are_new_tracks() {
+        let mut chroma = [0.0; 12];
+        chroma[0] = 1.0;
+        let bass = AudioFeatures { bands: [0.8, 0.7, 0.2, 0.1], chroma, ..Default::default() };
+        chroma = [0.0; 12];
+        chroma[7] = 1.0;
+        let strings = AudioFeatures { bands: [0.0, 0.1, 0.6, 0.5], chroma, ..Default::default() };
+        let silence = AudioFeatures::default();
+        let (start, mut detector) = (Instant::now(), TrackDetector::new());
+
+        assert!(play(&mut detector, start, 0.0, 40.0, &bass).is_empty());
+        // a short breath isn't a gap
+        assert!(play(&mut detector, start, 40.0, 0.5, &silence).is_empty());
+        assert!(play(&mut detector, start, 40.5, 10.0, &bass).is_empty());
+        assert!(play(&mut detector, start, 50.5, 2.0, &silence).is_empty());
+        assert_eq!(play(&mut detector, start, 52.5, 40.0, &bass), vec![52.5]);
+
+        // a new sound has to last, and the track before it has to have
+        let changed = play(&mut detector, start, 92.5, 10.0, &strings);
+        assert_eq!(changed.len(), 1);
+        assert!(changed[0] > 95.5 && changed[0] < 97.0, "{:?}", changed);
+        assert!(play(&mut detector, start, 102.5, 10.0, &bass).is_empty());
+    }
+}
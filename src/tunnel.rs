@@ -0,0 +1,150 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! The tunnel scene.  Flies down a tunnel of rings that speeds up with the sub-bass and
+//! widens on every beat.
+
+use crate::bench::millis;
+use crate::errors::ENguyenError;
+use crate::features::AudioFeatures;
+use crate::rendering::{tunnel_fsm, uv_image_vsm, PipelineRegistry, XyUvVertex};
+use crate::scenes::{Scene, SceneFrame};
+
+use std::sync::Arc;
+use std::time::Duration;
+use vulkano::buffer::ImmutableBuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::pipeline::{GraphicsPipeline, GraphicsPipelineAbstract};
+
+/// Rings passed per second in silence
+static BASE_SPEED: f32 = 0.6;
+/// Rings per second added at full scale sub-bass
+static BASS_SPEED: f32 = 3.0;
+static BASE_RADIUS: f32 = 0.35;
+/// Shares of the radius added at full scale sub-bass and on the beat
+static BASS_PULSE: f32 = 0.3;
+static BEAT_PULSE: f32 = 0.25;
+/// Turns of the ribs per unit of depth
+static TWIST: f32 = 0.05;
+/// Multiplied by the palette's weights
+static WALL_COLOR: [f32; 3] = [0.5, 0.6, 0.7];
+
+/// How far down the tunnel the view is and how wide the tunnel is there
+#[derive(Clone, Debug, PartialEq)]
+struct Motion {
+    /// Rings passed, wrapped to one
+    travel: f32,
+    radius: f32,
+}
+
+impl Default for Motion {
+    fn default() -> Motion {
+        Motion { travel: 0.0, radius: BASE_RADIUS }
+    }
+}
+
+impl Motion {
+    fn update(&mut self, features: &AudioFeatures, elapsed: Duration) {
+        let seconds = millis(elapsed) as f32 / 1000.0;
+        let sub_bass = features.bands[0];
+        self.travel = (self.travel + (BASE_SPEED + BASS_SPEED * sub_bass) * seconds).fract();
+        // jumps out on the beat and eases back in over it
+        let kick = (1.0 - features.beat.phase).powi(4);
+        self.radius = BASE_RADIUS * (1.0 + BASS_PULSE * sub_bass + BEAT_PULSE * kick);
+    }
+}
+
+pub struct Tunnel {
+    pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    /// A triangle strip covering the window
+    rect: Arc<ImmutableBuffer<[XyUvVertex]>>,
+    motion: Motion,
+}
+
+impl Tunnel {
+    pub fn new(
+        pipelines: &PipelineRegistry,
+        rect: Arc<ImmutableBuffer<[XyUvVertex]>>,
+    ) -> Result<Tunnel, ENguyenError> {
+        let pipeline = pipelines.get("tunnel", |device, subpass| {
+            let vs = uv_image_vsm::Shader::load(device.clone())
+                .map_err(|e| ENguyenError::render("Tunnel shader failed to load").caused_by(e))?;
+            let fs = tunnel_fsm::Shader::load(device.clone())
+                .map_err(|e| ENguyenError::render("Tunnel shader failed to load").caused_by(e))?;
+            let pipeline = GraphicsPipeline::start()
+                .triangle_strip()
+                .vertex_input_single_buffer::<XyUvVertex>()
+                .vertex_shader(vs.main_entry_point(), ())
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(fs.main_entry_point(), ())
+                .render_pass(subpass)
+                .build(device.clone())
+                .map_err(|e| ENguyenError::render("Tunnel pipeline failed").caused_by(e))?;
+            Ok(Arc::new(pipeline))
+        })?;
+        Ok(Tunnel { pipeline, rect, motion: Motion::default() })
+    }
+}
+
+impl Scene for Tunnel {
+    fn update(&mut self, features: &AudioFeatures, elapsed: Duration) {
+        self.motion.update(features, elapsed);
+    }
+
+    fn render(
+        &mut self,
+        cbb: AutoCommandBufferBuilder,
+        frame: &SceneFrame,
+    ) -> Result<AutoCommandBufferBuilder, ENguyenError> {
+        let [r, g, b] = frame.palette.weights();
+        let push_constants = tunnel_fsm::ty::PushConstant {
+            color: [WALL_COLOR[0] * r, WALL_COLOR[1] * g, WALL_COLOR[2] * b, 1.0],
+            travel: self.motion.travel,
+            radius: self.motion.radius,
+            twist: TWIST,
+        };
+        let rect = vec![self.rect.clone()];
+        cbb.draw(self.pipeline.clone(), frame.state, rect, frame.uniforms.clone(), push_constants)
+            .map_err(|e| ENguyenError::render("Tunnel draw failed").caused_by(e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beat::Beat;
+
+    #[test]
+    fn bass_drives_the_tunnel() {
+        let beat = Beat { phase: 0.5, ..Beat::default() };
+        let quiet = AudioFeatures { beat, ..AudioFeatures::default() };
+        let mut motion = Motion::default();
+        motion.update(&quiet, Duration::from_millis(500));
+        assert!((motion.travel - BASE_SPEED * 0.5).abs() < 1e-6);
+        let resting = motion.radius;
+
+        let mut loud = quiet.clone();
+        loud.bands[0] = 1.0;
+        motion.update(&loud, Duration::from_millis(500));
+        // 0.3 + 1.8 rings wraps to 0.1
+        assert!((motion.travel - 0.1).abs() < 1e-5, "{}", motion.travel);
+        assert!(motion.radius > resting);
+
+        loud.beat.phase = 0.0;
+        let widest = BASE_RADIUS * (1.0 + BASS_PULSE + BEAT_PULSE);
+        motion.update(&loud, Duration::from_millis(0));
+        assert!((motion.radius - widest).abs() < 1e-6);
+    }
+}
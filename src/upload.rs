@@ -0,0 +1,88 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! Uploads of data the GPU only reads, such as static geometry and images.  Data is staged in
+//! host visible memory and copied to device local memory by the queue given, usually a
+//! `SwapWindow`'s transfer queue.  That is a dedicated DMA queue on GPUs that have one.
+
+use crate::errors::ENguyenError;
+
+use image::RgbaImage;
+use std::sync::Arc;
+use vulkano::buffer::{BufferUsage, ImmutableBuffer};
+use vulkano::device::Queue;
+use vulkano::format::Format;
+use vulkano::image::{Dimensions, ImmutableImage};
+use vulkano::sync::GpuFuture;
+
+/// A buffer of `data` with `usage`.  Use it once the returned future completes.
+pub fn buffer<T, I>(
+    queue: &Arc<Queue>,
+    usage: BufferUsage,
+    data: I,
+) -> Result<(Arc<ImmutableBuffer<[T]>>, Box<dyn GpuFuture>), ENguyenError>
+where
+    T: Send + Sync + 'static,
+    I: ExactSizeIterator<Item = T>,
+{
+    let (buffer, uploaded) = ImmutableBuffer::from_iter(data, usage, queue.clone())
+        .map_err(|e| ENguyenError::render("Buffer upload failed").caused_by(e))?;
+    Ok((buffer, signalled(uploaded)?))
+}
+
+/// A sampled sRGB texture of `image`.  Use it once the returned future completes.
+pub fn texture(
+    queue: &Arc<Queue>,
+    image: RgbaImage,
+) -> Result<(Arc<ImmutableImage<Format>>, Box<dyn GpuFuture>), ENguyenError> {
+    let (width, height) = image.dimensions();
+    let (texture, uploaded) = ImmutableImage::from_iter(
+        image.into_raw().into_iter(),
+        Dimensions::Dim2d { width, height },
+        Format::R8G8B8A8Srgb,
+        queue.clone(),
+    )
+    .map_err(|e| ENguyenError::render("Image upload failed").caused_by(e))?;
+    Ok((texture, signalled(uploaded)?))
+}
+
+/// Submits `uploaded` with a semaphore, so work on other queues can wait for the copy
+fn signalled<F: GpuFuture + 'static>(uploaded: F) -> Result<Box<dyn GpuFuture>, ENguyenError> {
+    let signalled = uploaded
+        .then_signal_semaphore_and_flush()
+        .map_err(|e| ENguyenError::render("Upload submit failed").caused_by(e))?;
+    Ok(Box::new(signalled))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ewin::GpuPicker;
+    use vulkano::buffer::TypedBufferAccess;
+
+    #[test]
+    fn uploads_complete() {
+        let picker = GpuPicker::new(false).unwrap();
+        let (_device, queues) = picker.windowless_device().unwrap();
+        let queue = queues.transfer;
+        let data = [1.0f32, 2.0, 3.0];
+        let (vertices, uploaded) =
+            buffer(&queue, BufferUsage::vertex_buffer(), data.iter().cloned()).unwrap();
+        let (image, textured) = texture(&queue, RgbaImage::new(4, 2)).unwrap();
+        uploaded.join(textured).then_signal_fence_and_flush().unwrap().wait(None).unwrap();
+        assert_eq!(vertices.len(), 3);
+        assert_eq!(image.dimensions().width_height(), [4, 2]);
+    }
+}
@@ -0,0 +1,189 @@
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the Lesser GNU General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Lesser GNU General Public License for more details.
+
+// You should have received a copy of the Lesser GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Copyright 2019 E-Nguyen Developers.
+
+//! Feature records served to browsers and other WebSocket clients as JSON text frames.  Each
+//! client is written to from its own thread through a short queue, so a slow client only
+//! misses frames and never holds up analysis.
+
+use crate::errors::ENguyenError;
+use crate::export::{FeatureRecord, FeatureSink};
+
+use log::{debug, warn};
+use std::io::ErrorKind;
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tungstenite::Message;
+
+/// Frames queued for a client before it misses some
+static CLIENT_QUEUE: usize = 8;
+/// How often the listener checks for new clients and being dropped
+static ACCEPT_POLL: Duration = Duration::from_millis(100);
+/// How long a client may take over its handshake or over taking a frame before it's dropped
+static CLIENT_TIMEOUT: Duration = Duration::from_secs(2);
+
+type Clients = Arc<Mutex<Vec<SyncSender<String>>>>;
+
+/// Listens for clients until dropped
+pub struct WebSocketSink {
+    clients: Clients,
+    /// Seconds between frames sent
+    interval: f64,
+    /// `FeatureRecord::time` of the last frame sent
+    last_sent: Option<f64>,
+    stop: Arc<AtomicBool>,
+}
+
+impl WebSocketSink {
+    /// Serve on `address`, `host:port`, sending at most `rate` frames a second
+    pub fn listen(address: &str, rate: f32) -> Result<WebSocketSink, ENguyenError> {
+        let listener = TcpListener::bind(address).map_err(|e| {
+            ENguyenError::config(format!("Could not listen on {}", address)).caused_by(e)
+        })?;
+        listener.set_nonblocking(true)?;
+        let clients: Clients = Arc::new(Mutex::new(Vec::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (accepting, stopped) = (clients.clone(), stop.clone());
+        thread::spawn(move || accept(listener, accepting, stopped));
+        let interval = 1.0 / f64::from(rate.max(0.1));
+        Ok(WebSocketSink { clients, interval, last_sent: None, stop })
+    }
+}
+
+/// Hands each new connection its own thread until `stop` is set
+fn accept(listener: TcpListener, clients: Clients, stop: Arc<AtomicBool>) {
+    while !stop.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, peer)) => {
+                debug!("WebSocket client {} connected", peer);
+                let (tx, rx) = sync_channel(CLIENT_QUEUE);
+                clients.lock().unwrap().push(tx);
+                thread::spawn(move || serve(stream, rx));
+            },
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(ACCEPT_POLL),
+            Err(e) => {
+                warn!("WebSocket clients can't connect: {}", e);
+                return;
+            },
+        }
+    }
+}
+
+/// Writes frames to one client until it leaves or the sink is dropped
+fn serve(stream: TcpStream, frames: Receiver<String>) {
+    let blocking = stream
+        .set_nonblocking(false)
+        .and_then(|_| stream.set_read_timeout(Some(CLIENT_TIMEOUT)))
+        .and_then(|_| stream.set_write_timeout(Some(CLIENT_TIMEOUT)));
+    if let Err(e) = blocking {
+        warn!("WebSocket client can't time out: {}", e);
+        return;
+    }
+    let mut socket = match tungstenite::accept(stream) {
+        Ok(socket) => socket,
+        Err(e) => {
+            debug!("WebSocket handshake failed: {}", e);
+            return;
+        },
+    };
+    for frame in frames.iter() {
+        if let Err(e) = socket.write_message(Message::Text(frame)) {
+            debug!("WebSocket client left: {}", e);
+            return;
+        }
+    }
+    let _ = socket.close(None);
+}
+
+impl FeatureSink for WebSocketSink {
+    fn send(&mut self, record: &FeatureRecord) -> Result<(), ENguyenError> {
+        if let Some(last) = self.last_sent {
+            if record.time - last < self.interval {
+                return Ok(());
+            }
+        }
+        self.last_sent = Some(record.time);
+        let mut clients = self.clients.lock().unwrap();
+        if clients.is_empty() {
+            return Ok(());
+        }
+        let frame = record.json()?;
+        // a full queue misses this frame, a closed one is a client that left
+        clients.retain(|client| match client.try_send(frame.clone()) {
+            Ok(()) | Err(TrySendError::Full(_)) => true,
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+        Ok(())
+    }
+}
+
+impl Drop for WebSocketSink {
+    /// Frees the port for the next analysis and lets clients go
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.clients.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::features::AudioFeatures;
+
+    #[test]
+    fn frames_reach_clients() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        let mut sink = WebSocketSink::listen(&address, 10.0).unwrap();
+        let url = format!("ws://{}/", address);
+        let (mut client, _) = tungstenite::connect(url.as_str()).unwrap();
+        while sink.clients.lock().unwrap().is_empty() {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let features = AudioFeatures { spectrum: vec![0.5, 1.0], ..AudioFeatures::default() };
+        // only every tenth of a second is sent
+        for time in &[0.0, 0.05, 0.1] {
+            sink.send(&FeatureRecord::new(&features, *time)).unwrap();
+        }
+        let times: Vec<f64> = (0..2)
+            .map(|_| {
+                let frame = client.read_message().unwrap().into_text().unwrap();
+                let record: serde_json::Value = serde_json::from_str(&frame).unwrap();
+                assert_eq!(record["spectrum"][1], 1.0);
+                record["time"].as_f64().unwrap()
+            })
+            .collect();
+        assert_eq!(times, vec![0.0, 0.1]);
+    }
+
+    #[test]
+    fn silent_clients_time_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _silent = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let (_frames, rx) = sync_channel(CLIENT_QUEUE);
+        let (done, finished) = sync_channel(1);
+        thread::spawn(move || {
+            serve(stream, rx);
+            done.send(()).unwrap();
+        });
+        finished.recv_timeout(CLIENT_TIMEOUT * 2).unwrap();
+    }
+}